@@ -5,9 +5,22 @@ extern crate alloc;
 extern crate failure;
 
 use alloc::boxed::Box;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
 use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::cmp::min;
+use core::iter::from_fn;
+use core::ops::{Bound, Deref, DerefMut, Range, RangeBounds};
 use failure::{Error, Fail};
 
+// The longest instruction decoded, as many bytes as get_next_instruction_bytes returns
+const MAX_INSTRUCTION_SIZE: usize = 3;
+// Everything the 16 bits address bus can reach
+const ADDRESS_SPACE_SIZE: usize = 0x10000;
+
 #[macro_export]
 macro_rules! single {
     ($num:expr) => {
@@ -36,6 +49,7 @@ macro_rules! bi_conditional {
     };
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Cycles {
     Single(u8),
     OneCondition {
@@ -49,14 +63,215 @@ pub enum Cycles {
     },
 }
 
-pub trait InputDevice {
+impl Cycles {
+    // What the instruction takes when every condition goes the expensive way
+    pub fn worst_case(&self) -> u8 {
+        match self {
+            Cycles::Single(cycles) => *cycles,
+            Cycles::OneCondition { not_met, met } => *not_met.max(met),
+            Cycles::TwoConditions {
+                not_met,
+                first_met,
+                second_met,
+            } => *not_met.max(first_met).max(second_met),
+        }
+    }
+}
+
+// What a hook wants done with the instruction that's about to run
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HookAction {
+    Continue,
+    Pause,
+    Skip,
+}
+
+// How far a run with a budget got
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RunStats {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub pc: u16,
+}
+
+// The addresses in the range, as u32 so a range can reach the end of the 64KB address space
+pub fn address_range<R: RangeBounds<u16>>(range: &R) -> Range<u32> {
+    let start = match range.start_bound() {
+        Bound::Included(start) => u32::from(*start),
+        Bound::Excluded(start) => u32::from(*start) + 1,
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) => u32::from(*end) + 1,
+        Bound::Excluded(end) => u32::from(*end),
+        Bound::Unbounded => 0x10000,
+    };
+    start..end
+}
+
+// Decodes the bytes as a program loaded at zero. Instructions cut by the end of the bytes, and
+// those without a size, are printed as a byte of data
+pub fn disassemble<I>(bytes: &[u8]) -> Vec<(u16, String)>
+where
+    I: Instruction + for<'a> From<&'a [u8]> + ToString,
+{
+    disassemble_with(bytes, |instruction: &I| instruction.to_string())
+}
+
+// Like disassemble, printing the instructions with format
+pub fn disassemble_with<I, F>(bytes: &[u8], mut format: F) -> Vec<(u16, String)>
+where
+    I: Instruction + for<'a> From<&'a [u8]>,
+    F: FnMut(&I) -> String,
+{
+    let addresses = 0..min(bytes.len(), ADDRESS_SPACE_SIZE) as u32;
+    decode_instructions(
+        addresses,
+        |address| bytes[usize::from(address)],
+        |window| Some(I::from(window)),
+    )
+    .map(|(pc, instruction)| match instruction {
+        Some(instruction) => (pc, format(&instruction)),
+        None => (pc, format!("DB {:02x}", bytes[usize::from(pc)])),
+    })
+    .collect()
+}
+
+// Decodes the addresses an instruction at a time, handing decode as many bytes as the longest
+// instruction, zeros past the end. What decode doesn't know, instructions without a size and
+// those cut by the end are None, and after them the walk moves on a single byte
+pub fn decode_instructions<I, F, D>(
+    addresses: Range<u32>,
+    read: F,
+    decode: D,
+) -> impl Iterator<Item = (u16, Option<I>)>
+where
+    I: Instruction,
+    F: Fn(u16) -> u8,
+    D: Fn(&[u8]) -> Option<I>,
+{
+    let mut pc = addresses.start;
+    from_fn(move || {
+        if pc >= addresses.end {
+            return None;
+        }
+        let address = pc as u16;
+        let available = min((addresses.end - pc) as usize, MAX_INSTRUCTION_SIZE);
+        let mut window = [0; MAX_INSTRUCTION_SIZE];
+        for (offset, byte) in window.iter_mut().enumerate().take(available) {
+            *byte = read(address.wrapping_add(offset as u16));
+        }
+        let decoded =
+            decode(&window).and_then(|instruction| Some((instruction.size().ok()?, instruction)));
+        match decoded {
+            Some((size, instruction)) if size > 0 && usize::from(size) <= available => {
+                pc += u32::from(size);
+                Some((address, Some(instruction)))
+            }
+            _ => {
+                pc += 1;
+                Some((address, None))
+            }
+        }
+    })
+}
+
+// Devices go wherever their cpu goes, so they have to be able to move to another thread
+pub trait InputDevice: Send {
     fn read(&mut self) -> u8;
 }
 
-pub trait OutputDevice {
+pub trait OutputDevice: Send {
     fn write(&mut self, byte: u8);
 }
 
+// What the cpus see through their address bus. Behind it there can be plain RAM, or mirrors,
+// banks and devices mapped wherever the machine puts them
+pub trait Memory {
+    fn set(&mut self, index: u16, new_value: u8);
+    fn get(&self, index: u16) -> u8;
+    // The bytes behind it, without counting mirrors
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    // Both cpus keep words little endian. The high byte wraps around the address space
+    fn get_word(&self, index: u16) -> u16 {
+        u16::from(self.get(index.wrapping_add(1))) << 8 | u16::from(self.get(index))
+    }
+    fn set_word(&mut self, index: u16, new_value: u16) {
+        self.set(index, new_value as u8);
+        self.set(index.wrapping_add(1), (new_value >> 8) as u8);
+    }
+}
+
+impl Memory for [u8; ADDRESS_SPACE_SIZE] {
+    fn set(&mut self, index: u16, new_value: u8) {
+        self[index as usize] = new_value;
+    }
+    fn get(&self, index: u16) -> u8 {
+        self[index as usize]
+    }
+    fn len(&self) -> usize {
+        ADDRESS_SPACE_SIZE
+    }
+}
+
+impl<T: Memory> Memory for Rc<RefCell<T>> {
+    fn set(&mut self, index: u16, new_value: u8) {
+        self.borrow_mut().set(index, new_value);
+    }
+    fn get(&self, index: u16) -> u8 {
+        self.borrow().get(index)
+    }
+    fn len(&self) -> usize {
+        self.borrow().len()
+    }
+}
+
+// Bytes from address zero up. Past them reads give 0 and writes are lost. It derefs to the bytes,
+// for the hosts that want them all at once
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlainMemory {
+    bytes: Vec<u8>,
+}
+
+impl PlainMemory {
+    pub fn new(size: usize) -> PlainMemory {
+        PlainMemory {
+            bytes: vec![0; size.min(ADDRESS_SPACE_SIZE)],
+        }
+    }
+}
+
+impl Memory for PlainMemory {
+    fn set(&mut self, index: u16, new_value: u8) {
+        if let Some(byte) = self.bytes.get_mut(index as usize) {
+            *byte = new_value;
+        }
+    }
+    fn get(&self, index: u16) -> u8 {
+        self.bytes.get(index as usize).cloned().unwrap_or(0)
+    }
+    fn len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+impl Deref for PlainMemory {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl DerefMut for PlainMemory {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
 pub trait Instruction {
     fn size(&self) -> Result<u8, Error>;
     fn get_cycles(&self) -> Result<Cycles, Error>;
@@ -64,11 +279,12 @@ pub trait Instruction {
 
 pub trait Cpu<I, F>
 where
-    I: Instruction + From<Vec<u8>>,
+    I: Instruction + for<'a> From<&'a [u8]>,
     F: Fail,
 {
     fn execute(&mut self) -> Result<u8, Error> {
-        let instruction = I::from(self.get_next_instruction_bytes());
+        let bytes = self.get_next_instruction_bytes();
+        let instruction = I::from(&bytes[..]);
         if !self.can_run(&instruction) {
             return Ok(0);
         }
@@ -78,6 +294,82 @@ where
         Ok(cycles)
     }
 
+    // The hook sees the pc and the next instruction before it runs. Continue executes it,
+    // Skip moves the pc past it without executing it and Pause leaves the cpu untouched.
+    fn execute_with_hook(
+        &mut self,
+        hook: &mut dyn FnMut(u16, &I) -> HookAction,
+    ) -> Result<u8, Error> {
+        let bytes = self.get_next_instruction_bytes();
+        let instruction = self.decode_instruction(&bytes[..]);
+        match hook(self.get_pc(), &instruction) {
+            HookAction::Continue => self.execute(),
+            HookAction::Pause => Ok(0),
+            HookAction::Skip => {
+                self.increase_pc(instruction.size()?);
+                Ok(0)
+            }
+        }
+    }
+
+    // Runs until the pc is at the address or the cpu is done, returning the cycles spent
+    fn run_until(&mut self, pc: u16) -> Result<u64, Error> {
+        let mut cycles = 0;
+        while !self.is_done() && self.get_pc() != pc {
+            let previous_pc = self.get_pc();
+            let spent = self.execute()?;
+            // A stopped cpu doesn't move, and waiting on it would never end
+            if spent == 0 && self.get_pc() == previous_pc {
+                break;
+            }
+            cycles += u64::from(spent);
+        }
+        Ok(cycles)
+    }
+
+    // Runs whole instructions while the worst case of the next one still fits in the budget, so
+    // frontends can keep in step with a frame clock. Returns the cycles actually spent, which never
+    // go past the budget. Unlike run_until_cycles, an instruction that doesn't fit is left for later
+    fn run_for_cycles(&mut self, budget: u32) -> Result<u32, Error> {
+        let mut cycles = 0;
+        while !self.is_done() {
+            let bytes = self.get_next_instruction_bytes();
+            let instruction = self.decode_instruction(&bytes[..]);
+            if cycles + u32::from(instruction.get_cycles()?.worst_case()) > budget {
+                break;
+            }
+            let previous_pc = self.get_pc();
+            let spent = self.execute()?;
+            if spent == 0 && self.get_pc() == previous_pc {
+                break;
+            }
+            cycles += u32::from(spent);
+        }
+        Ok(cycles)
+    }
+
+    // Runs until the cycles reach the budget, the cpu is done or it stops, as it does on a HLT.
+    // Unlike run_for_cycles, the last instruction can go past the budget by up to its own cycles,
+    // so any budget makes progress. Calling it again picks up from there
+    fn run_until_cycles(&mut self, budget: u64) -> Result<RunStats, Error> {
+        let mut stats = RunStats::default();
+        while !self.is_done() && stats.cycles < budget {
+            let previous_pc = self.get_pc();
+            let spent = self.execute()?;
+            if spent == 0 && self.get_pc() == previous_pc {
+                break;
+            }
+            stats.instructions += 1;
+            stats.cycles += u64::from(spent);
+        }
+        stats.pc = self.get_pc();
+        Ok(stats)
+    }
+
+    fn decode_instruction(&self, bytes: &[u8]) -> I {
+        I::from(bytes)
+    }
+
     fn get_cycles_for_instruction(&mut self, instruction: &I) -> Result<u8, Error> {
         let cycles = instruction.get_cycles()?;
         match cycles {
@@ -95,10 +387,12 @@ where
 
     fn execute_instruction(&mut self, instruction: &I) -> Result<(), Error>;
     fn get_pc(&self) -> u16;
-    fn get_next_instruction_bytes(&self) -> Vec<u8>;
+    fn get_next_instruction_bytes(&self) -> [u8; 3];
     fn can_run(&self, instruction: &I) -> bool;
     fn is_done(&self) -> bool;
     fn increase_pc(&mut self, steps: u8);
+    // Back to the power-on registers and PC, leaving the memory as it is
+    fn reset(&mut self);
     fn get_cycles_from_one_condition(
         &self,
         instruction: &I,
@@ -118,3 +412,157 @@ pub trait WithPorts {
     fn add_input_device(&mut self, id: u8, device: Box<dyn InputDevice>);
     fn add_output_device(&mut self, id: u8, device: Box<dyn OutputDevice>);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Cpu, Cycles, Instruction, Memory, PlainMemory, RunStats};
+    use alloc::vec::Vec;
+    use failure::{Error, Fail};
+
+    #[derive(Debug, Fail)]
+    #[fail(display = "Stub error")]
+    struct StubError;
+
+    // The opcode is the number of cycles, except for 0xff which is a branch that isn't taken
+    struct StubInstruction(u8);
+
+    impl From<&[u8]> for StubInstruction {
+        fn from(bytes: &[u8]) -> StubInstruction {
+            StubInstruction(bytes[0])
+        }
+    }
+
+    impl Instruction for StubInstruction {
+        fn size(&self) -> Result<u8, Error> {
+            Ok(1)
+        }
+
+        fn get_cycles(&self) -> Result<Cycles, Error> {
+            Ok(match self.0 {
+                0xff => conditional!(5, 11),
+                cycles => single!(cycles),
+            })
+        }
+    }
+
+    struct StubCpu {
+        memory: Vec<u8>,
+        pc: u16,
+    }
+
+    impl Cpu<StubInstruction, StubError> for StubCpu {
+        fn execute_instruction(&mut self, _instruction: &StubInstruction) -> Result<(), Error> {
+            Ok(())
+        }
+
+        fn get_pc(&self) -> u16 {
+            self.pc
+        }
+
+        fn get_next_instruction_bytes(&self) -> [u8; 3] {
+            [self.memory[self.pc as usize], 0, 0]
+        }
+
+        fn can_run(&self, _instruction: &StubInstruction) -> bool {
+            true
+        }
+
+        fn is_done(&self) -> bool {
+            self.pc as usize >= self.memory.len()
+        }
+
+        fn increase_pc(&mut self, steps: u8) {
+            self.pc += u16::from(steps);
+        }
+
+        fn reset(&mut self) {
+            self.pc = 0;
+        }
+
+        fn get_cycles_from_one_condition(
+            &self,
+            _instruction: &StubInstruction,
+            not_met: u8,
+            _met: u8,
+        ) -> Result<u8, Error> {
+            Ok(not_met)
+        }
+
+        fn get_cycles_from_two_conditions(
+            &self,
+            _instruction: &StubInstruction,
+            not_met: u8,
+            _first_met: u8,
+            _second_met: u8,
+        ) -> Result<u8, Error> {
+            Ok(not_met)
+        }
+    }
+
+    fn create_cpu(memory: &[u8]) -> StubCpu {
+        StubCpu {
+            memory: memory.to_vec(),
+            pc: 0,
+        }
+    }
+
+    #[test]
+    fn it_should_run_for_at_most_the_budget() {
+        let mut cpu = create_cpu(&[4, 7, 10, 4]);
+        assert_eq!(cpu.run_for_cycles(15).unwrap(), 11);
+        assert_eq!(cpu.pc, 2);
+        assert_eq!(cpu.run_for_cycles(14).unwrap(), 14);
+        assert!(cpu.is_done());
+        assert_eq!(cpu.run_for_cycles(100).unwrap(), 0);
+    }
+
+    #[test]
+    fn it_should_stop_at_or_just_past_the_budget() {
+        let mut cpu = create_cpu(&[4, 7, 10, 4]);
+        let stats = cpu.run_until_cycles(11).unwrap();
+        assert_eq!(
+            stats,
+            RunStats {
+                instructions: 2,
+                cycles: 11,
+                pc: 2
+            }
+        );
+        let stats = cpu.run_until_cycles(1).unwrap();
+        assert_eq!((stats.instructions, stats.cycles, stats.pc), (1, 10, 3));
+        let stats = cpu.run_until_cycles(100).unwrap();
+        assert_eq!((stats.instructions, stats.cycles, stats.pc), (1, 4, 4));
+        assert_eq!(cpu.run_until_cycles(100).unwrap().instructions, 0);
+    }
+
+    #[test]
+    fn it_should_leave_room_for_the_worst_case_of_conditional_instructions() {
+        let mut cpu = create_cpu(&[4, 0xff, 4]);
+        assert_eq!(cpu.run_for_cycles(14).unwrap(), 4);
+        assert_eq!(cpu.pc, 1);
+        assert_eq!(cpu.run_for_cycles(11).unwrap(), 9);
+        assert!(cpu.is_done());
+    }
+
+    #[test]
+    fn it_should_keep_words_little_endian() {
+        let mut memory = PlainMemory::new(0x10000);
+        memory.set_word(0x1234, 0xbeef);
+        assert_eq!((memory.get(0x1234), memory.get(0x1235)), (0xef, 0xbe));
+        assert_eq!(memory.get_word(0x1234), 0xbeef);
+        memory.set_word(0xffff, 0x1234);
+        assert_eq!((memory.get(0xffff), memory.get(0x0000)), (0x34, 0x12));
+        assert_eq!(memory.get_word(0xffff), 0x1234);
+    }
+
+    #[test]
+    fn it_should_read_zero_past_a_plain_memory() {
+        let mut memory = PlainMemory::new(0x100);
+        memory.set(0x80, 0x42);
+        memory.set(0x100, 0x42);
+        assert_eq!(memory.len(), 0x100);
+        assert_eq!(memory.get(0x80), 0x42);
+        assert_eq!(memory.get(0x100), 0);
+        assert_eq!(memory[0x80], 0x42);
+    }
+}
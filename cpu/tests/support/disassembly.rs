@@ -0,0 +1,40 @@
+// Golden disassembly helpers shared by the tests of the cpu crates. Include them with
+// #[path = "../../cpu/tests/support/disassembly.rs"]
+use cpu::Instruction;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+
+// Every opcode once, with operand bytes that tell the low and high byte apart
+pub fn listing<I, F>(decode: F) -> String
+where
+    I: Instruction + Display,
+    F: Fn(&[u8]) -> I,
+{
+    (0..=0xffu8)
+        .map(|opcode| {
+            let bytes = [opcode, 0x34, 0x12];
+            let instruction = decode(&bytes);
+            let size = instruction.size().unwrap() as usize;
+            let hex: Vec<String> = bytes[..size].iter().map(|b| format!("{:02x}", b)).collect();
+            format!("{:<9} {}\n", hex.join(" "), instruction)
+        })
+        .collect()
+}
+
+// Set UPDATE_SNAPSHOTS to rewrite the golden file after an intended change
+pub fn assert_matches_golden(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, actual).unwrap();
+    }
+    let expected = fs::read_to_string(&path)
+        .unwrap_or_else(|_| panic!("{} is missing, run with UPDATE_SNAPSHOTS=1", path.display()));
+    for (line, (expected, actual)) in expected.lines().zip(actual.lines()).enumerate() {
+        assert_eq!(actual, expected, "{}:{} differs", name, line + 1);
+    }
+    assert_eq!(actual.lines().count(), expected.lines().count());
+}
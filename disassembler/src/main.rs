@@ -1,18 +1,21 @@
 extern crate cpu;
 #[macro_use]
 extern crate failure;
+extern crate intel8080_assembler;
 extern crate intel8080cpu;
 extern crate mos6502cpu;
 extern crate smoked;
 
-use cpu::Instruction;
+use cpu::{decode_instructions, disassemble_with};
 use failure::Error;
-use intel8080cpu::Intel8080Instruction;
-use mos6502cpu::Mos6502Instruction;
-use smoked::instruction::{Instruction as SmokedInstruction};
-use std::cmp::min;
+use intel8080_assembler::{read_map_with_radix, Radix, SymbolTable};
+use intel8080cpu::{Intel8080Cpu, Intel8080Instruction};
+use mos6502cpu::{Mos6502Instruction, OPCODE_TABLE as MOS6502_OPCODE_TABLE};
+use smoked::instruction::Instruction as SmokedInstruction;
+use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::env::args;
-use std::fs::File;
+use std::fs::{read_to_string, File};
 use std::io::Read;
 
 #[derive(Debug, Fail)]
@@ -23,69 +26,264 @@ enum DisassemblerError {
 
 // This is an arbitrarily chosen number. We either need RFC 2000 or something else that I dunno yet
 const ROM_MEMORY_LIMIT: usize = 0x10000;
+// An opcode, an operand and a location, eight bytes each but the opcode
+const SMOKED_MAX_INSTRUCTION_SIZE: usize = 17;
 
-const USAGE: &str = "Usage: disassembler [cpu] [file]
+const USAGE: &str = "Usage: disassembler [cpu] [file] [--symbols map file] [--radix hex|dec|oct]
+       disassembler [cpu] [file] [--asm]
 
 Disassemble a binary file for an old cpu. So far, supports only:
 
 - mos6502
 - intel8080
-- smoked";
-type InstructionsResult = Result<Vec<(u16, Box<dyn ToString>)>, Error>;
+- smoked
 
-fn get_instructions_for_cpu(cpu: &str, bytes: [u8; ROM_MEMORY_LIMIT]) -> InstructionsResult {
+With --symbols, it reads a map file written by the intel 8080 assembler and prints the names of
+the symbols instead of their addresses. --radix has to match the one the map was written with, hex
+by default.
+
+With --asm, intel 8080 programs are printed as a source that the intel 8080 assembler turns back
+into the same binary.";
+type InstructionsResult = Result<Vec<(u16, String)>, Error>;
+
+trait SymbolizedInstruction: ToString {
+    fn to_string_with_symbols(&self, _symbols: &SymbolTable) -> String {
+        self.to_string()
+    }
+}
+
+impl SymbolizedInstruction for Intel8080Instruction {
+    fn to_string_with_symbols(&self, symbols: &SymbolTable) -> String {
+        Intel8080Instruction::to_string_with_symbols(self, |address| symbols.name(address))
+    }
+}
+
+fn get_instructions_for_cpu(
+    cpu: &str,
+    bytes: [u8; ROM_MEMORY_LIMIT],
+    symbols: &SymbolTable,
+) -> InstructionsResult {
     match cpu {
-        "mos6502" => get_instructions::<Mos6502Instruction>(bytes),
-        "intel8080" => get_instructions::<Intel8080Instruction>(bytes),
-        "smoked" => get_instructions::<SmokedInstruction>(bytes),
+        "mos6502" => Ok(get_mos6502_instructions(&bytes)),
+        "intel8080" => Ok(symbolize::<Intel8080Instruction>(&bytes, symbols)),
+        "smoked" => Ok(get_smoked_instructions(&bytes)),
         _ => Err(Error::from(DisassemblerError::InvalidCpu {
             name: String::from(cpu),
         })),
     }
 }
 
-fn get_instructions<I: 'static + Instruction + ToString + From<Vec<u8>>>(
-    bytes: [u8; ROM_MEMORY_LIMIT],
-) -> InstructionsResult {
-    let mut result: Vec<(u16, Box<dyn ToString>)> = Vec::with_capacity(bytes.len());
-    let mut pass = 0;
-    let mut pc: usize = 0;
-    for index in 0..bytes.len() {
-        if pass == 0 {
-            let i = I::from(bytes[index..min(index + 3, bytes.len())].to_vec());
-            let instruction_size = i.size()?;
-            pass = instruction_size - 1;
-            result.push((pc as u16, Box::new(i)));
-            pc += instruction_size as usize;
-        } else {
-            pass -= 1;
+fn symbolize<I>(bytes: &[u8], symbols: &SymbolTable) -> Vec<(u16, String)>
+where
+    I: SymbolizedInstruction + cpu::Instruction + for<'a> From<&'a [u8]>,
+{
+    disassemble_with(bytes, |instruction: &I| {
+        instruction.to_string_with_symbols(symbols)
+    })
+}
+
+// The opcode table says how many bytes the instruction takes, the decoder only gets those
+fn decode_mos6502(window: &[u8]) -> Option<Mos6502Instruction> {
+    let required_bytes = usize::from(MOS6502_OPCODE_TABLE[usize::from(window[0])].size);
+    Mos6502Instruction::try_from_bytes(&window[..required_bytes]).ok()
+}
+
+// Unknown opcodes and instructions cut by the end of the file are printed as data
+fn get_mos6502_instructions(bytes: &[u8]) -> Vec<(u16, String)> {
+    decode_instructions(
+        0..bytes.len() as u32,
+        |address| bytes[usize::from(address)],
+        decode_mos6502,
+    )
+    .map(|(pc, instruction)| match instruction {
+        Some(instruction) => (pc, instruction.to_string()),
+        None => (pc, format!(".byte ${:02x}", bytes[usize::from(pc)])),
+    })
+    .collect()
+}
+
+// Smoked operands take eight bytes, so its instructions don't fit the walkers of the 8 bit cpus
+fn get_smoked_instructions(bytes: &[u8]) -> Vec<(u16, String)> {
+    let mut result = Vec::new();
+    let mut pc = 0;
+    while pc + SMOKED_MAX_INSTRUCTION_SIZE <= bytes.len() {
+        let instruction = match SmokedInstruction::try_from(&bytes[pc..]) {
+            Ok(instruction) => instruction,
+            Err(_) => break,
+        };
+        result.push((pc as u16, instruction.to_string()));
+        pc += instruction.size();
+    }
+    result
+}
+
+// Labels every jump and call target that starts an instruction, and prints the rest as data
+fn get_intel8080_assembly(bytes: &[u8]) -> String {
+    let mut source = String::from("ORG 0000H\n");
+    if bytes.is_empty() {
+        return source;
+    }
+    let cpu = Intel8080Cpu::with_memory_size(bytes, bytes.len());
+    let instructions: Vec<(u16, Option<Intel8080Instruction>)> = cpu
+        .iter_instructions(..=(bytes.len() - 1) as u16)
+        .map(|(pc, instruction)| match instruction {
+            // Undocumented opcodes decode as a NOP, that would assemble into a different byte
+            Ok(Intel8080Instruction::Noop) if bytes[pc as usize] != 0 => (pc, None),
+            Ok(instruction) => (pc, Some(instruction)),
+            Err(_) => (pc, None),
+        })
+        .collect();
+    let starts: HashSet<u16> = instructions.iter().map(|(pc, _)| *pc).collect();
+    let labels: HashSet<u16> = instructions
+        .iter()
+        .filter_map(|(_, instruction)| instruction.as_ref().and_then(|i| i.branch_target()))
+        .filter(|target| starts.contains(target))
+        .collect();
+    for (pc, instruction) in &instructions {
+        if labels.contains(pc) {
+            source.push_str(&format!("{}:\n", intel8080_label(*pc)));
         }
+        let line = match instruction {
+            Some(instruction) => {
+                to_assembler_syntax(&instruction.to_string_with_symbols(|address| {
+                    Some(intel8080_label(address)).filter(|_| labels.contains(&address))
+                }))
+            }
+            None => format!("DB 0{:02X}H", bytes[*pc as usize]),
+        };
+        source.push_str(&format!("    {}\n", line));
     }
-    Ok(result)
+    source
+}
+
+fn intel8080_label(address: u16) -> String {
+    format!("L_{:04X}", address)
 }
 
-fn read_file(file_name: &str) -> std::io::Result<[u8; ROM_MEMORY_LIMIT]> {
-    let mut f = File::open(file_name)?;
+// Numbers print as $04c3 and immediates as #$3f, the assembler reads them as 04C3H and 03FH
+fn to_assembler_syntax(instruction: &str) -> String {
+    let mut result = String::with_capacity(instruction.len());
+    let mut chars = instruction.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => {}
+            '$' => {
+                let mut digits = String::new();
+                while let Some(digit) = chars.peek().cloned().filter(char::is_ascii_hexdigit) {
+                    digits.push(digit.to_ascii_uppercase());
+                    chars.next();
+                }
+                result.push_str(&format!("0{}H", digits));
+            }
+            ',' => result.push_str(", "),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+// Binaries smaller than the whole address space are padded with zeros
+fn read_file(file_name: &str) -> std::io::Result<([u8; ROM_MEMORY_LIMIT], usize)> {
+    let mut bytes = Vec::new();
+    File::open(file_name)?
+        .take(ROM_MEMORY_LIMIT as u64)
+        .read_to_end(&mut bytes)?;
     let mut memory = [0; ROM_MEMORY_LIMIT];
-    f.read_exact(&mut memory)?;
-    Ok(memory)
+    memory[..bytes.len()].copy_from_slice(&bytes);
+    Ok((memory, bytes.len()))
 }
 
-fn disassemble(cpu: &str, memory: [u8; ROM_MEMORY_LIMIT]) -> Result<(), Error> {
-    let instructions = get_instructions_for_cpu(cpu, memory)?;
+fn disassemble(
+    cpu: &str,
+    memory: [u8; ROM_MEMORY_LIMIT],
+    symbols: &SymbolTable,
+) -> Result<(), Error> {
+    let instructions = get_instructions_for_cpu(cpu, memory, symbols)?;
     for (pc, instruction) in &instructions {
-        println!("{:04x} {}", pc, instruction.to_string());
+        if let Some(label) = symbols.label(*pc) {
+            println!("{}:", label);
+        }
+        println!("{:04x} {}", pc, instruction);
     }
     Ok(())
 }
 
 fn main() {
     let args: Vec<String> = args().collect();
-    if args.len() != 3 {
-        panic!(USAGE);
-    }
+    let (symbols_file, radix, asm) = match &args[..] {
+        [_, _, _] => (None, "hex", false),
+        [_, _, _, flag] if flag == "--asm" => (None, "hex", true),
+        [_, _, _, flag, map] if flag == "--symbols" => (Some(map), "hex", false),
+        [_, _, _, flag, map, radix_flag, radix]
+            if flag == "--symbols" && radix_flag == "--radix" =>
+        {
+            (Some(map), radix.as_str(), false)
+        }
+        _ => panic!("{}", USAGE),
+    };
+    let radix: Radix = radix.parse().unwrap_or_else(|_| panic!("{}", USAGE));
 
-    let memory = read_file(&args[2]).unwrap();
+    let (memory, size) = read_file(&args[2]).unwrap();
     let cpu = &args[1];
-    disassemble(cpu, memory).unwrap();
+    if asm {
+        if cpu != "intel8080" {
+            panic!("{}", USAGE);
+        }
+        print!("{}", get_intel8080_assembly(&memory[..size]));
+        return;
+    }
+    let symbols = match symbols_file {
+        Some(map) => read_map_with_radix(&read_to_string(map).unwrap(), radix).unwrap(),
+        None => Vec::new(),
+    };
+    disassemble(cpu, memory, &SymbolTable::new(&symbols)).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use get_intel8080_assembly;
+    use get_mos6502_instructions;
+    use intel8080_assembler::{Assembler, Lexer, Parser};
+
+    fn assemble(source: &str) -> Vec<u8> {
+        let tokens = Lexer::new(source.as_bytes()).scan_tokens().unwrap();
+        let statements = Parser::new(tokens).parse_statements().unwrap();
+        Assembler::new().assemble(statements).unwrap().to_vec()
+    }
+
+    #[test]
+    fn it_should_label_jumps_and_calls() {
+        let rom = [0xc3, 0x06, 0x00, 0xcd, 0x06, 0x00, 0x76, 0xc3, 0x08, 0x00];
+        assert_eq!(
+            get_intel8080_assembly(&rom),
+            "ORG 0000H\n    JMP L_0006\n    CALL L_0006\nL_0006:\n    HLT\n    JMP 00008H\n"
+        );
+    }
+
+    #[test]
+    fn it_should_assemble_back_into_the_same_binary() {
+        let mut rom: Vec<u8> = (0..=0xff)
+            .flat_map(|opcode| vec![opcode, 0x10, 0x00])
+            .collect();
+        rom.extend_from_slice(&[0xc3, 0x10]);
+        let source = get_intel8080_assembly(&rom);
+        assert!(source.ends_with("    DB 0C3H\n    DB 010H\n"));
+        let assembled = assemble(&source);
+        assert_eq!(assembled[..rom.len()], rom[..]);
+        assert!(assembled[rom.len()..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn it_should_print_unknown_6502_opcodes_as_data() {
+        assert_eq!(
+            get_mos6502_instructions(&[0xa9, 0x01, 0x02, 0xea, 0xad, 0x34]),
+            vec![
+                (0, String::from("LDA #$01")),
+                (2, String::from(".byte $02")),
+                (3, String::from("NOP")),
+                (4, String::from(".byte $ad")),
+                (5, String::from(".byte $34")),
+            ]
+        );
+    }
 }
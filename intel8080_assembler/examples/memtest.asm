@@ -0,0 +1,64 @@
+; Memory test for CP/M. Fills a page of memory with a few patterns, reads every one back and
+; prints PASS or FAIL through the BDOS.
+;
+; Labels followed by DB or DW are constants, they don't take any room in the program
+BDOS            DW 5            ; CP/M programs CALL here to reach the operating system
+PRINT_STRING    DB 9            ; BDOS function that prints from DE up to a '$'
+WARM_BOOT       DW 0            ; Jumping here hands the machine back to CP/M
+STACK           DW 0F000H
+BUFFER          DW 2000H        ; The page under test
+PATTERN_COUNT   DB 4
+
+        ORG 100H                ; CP/M loads programs at 100H
+START:
+        LXI SP, STACK
+        LXI D, PATTERNS
+        MVI C, PATTERN_COUNT
+NEXT_PATTERN:
+        LDAX D                  ; A = the pattern DE points to
+        CALL FILL
+        CALL CHECK
+        JNZ FAILED
+        INX D
+        DCR C
+        JNZ NEXT_PATTERN
+        LXI D, PASS_MESSAGE
+        JMP PRINT
+FAILED:
+        LXI D, FAIL_MESSAGE
+PRINT:
+        MVI C, PRINT_STRING
+        CALL BDOS
+        JMP WARM_BOOT
+
+; Writes A to every byte of the buffer. B starts at 0, so DCR wraps it around and the loop runs
+; 256 times
+FILL:
+        LXI H, BUFFER
+        MVI B, 0
+FILL_LOOP:
+        MOV M, A
+        INX H
+        DCR B
+        JNZ FILL_LOOP
+        RET
+
+; Compares every byte of the buffer with A. Returns with Z clear at the first one that differs,
+; and with Z set once B wraps back to 0
+CHECK:
+        LXI H, BUFFER
+        MVI B, 0
+CHECK_LOOP:
+        CMP M
+        RNZ
+        INX H
+        DCR B
+        JNZ CHECK_LOOP
+        RET
+
+PATTERNS:
+        DB 55H, 0AAH, 0, 0FFH
+PASS_MESSAGE:
+        DB 'PASS$'
+FAIL_MESSAGE:
+        DB 'FAIL$'
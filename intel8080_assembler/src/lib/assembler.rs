@@ -5,6 +5,7 @@ use super::*;
 use failure::Error;
 use intel8080cpu::{Location, RegisterType};
 use std::collections::HashMap;
+use symbols::{Symbol, SymbolKind};
 
 const ROM_MEMORY_LIMIT: usize = 65536;
 
@@ -16,19 +17,40 @@ enum StageOneValue {
     Word(u8),
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum AssertionCheck {
+    Flag(FlagExpression, bool),
+    Location(Location, u16),
+    Memory(u16, u8),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assertion {
+    pub check: AssertionCheck,
+    pub line: usize,
+}
+
 pub struct Assembler {
+    assertions: Vec<(AssertionExpression, usize)>,
+    line: usize,
+    listing: Vec<ListingEntry>,
     pc: u16,
-    stage_one_room: Vec<StageOneValue>,
+    stage_one_room: Vec<(StageOneValue, usize)>,
     room: [u8; ROM_MEMORY_LIMIT],
+    symbols: Vec<Symbol>,
     two_words: HashMap<LabelExpression, u16>,
 }
 
 impl Default for Assembler {
     fn default() -> Assembler {
         Assembler {
+            assertions: Vec::new(),
+            line: 0,
+            listing: Vec::new(),
             pc: 0,
             room: [0; ROM_MEMORY_LIMIT],
             stage_one_room: Vec::with_capacity(ROM_MEMORY_LIMIT),
+            symbols: Vec::new(),
             two_words: HashMap::new(),
         }
     }
@@ -45,42 +67,130 @@ impl Assembler {
         Ok(self.room)
     }
 
+    pub fn assemble_with_assertions(
+        mut self,
+        statements: Vec<Statement>,
+    ) -> Result<([u8; ROM_MEMORY_LIMIT], Vec<Assertion>), Error> {
+        self.stage_one(statements)?;
+        self.stage_two()?;
+        let mut assertions = Vec::with_capacity(self.assertions.len());
+        for (expression, line) in self.assertions.clone() {
+            self.line = line;
+            let check = match expression {
+                AssertionExpression::Flag(flag, expected) => AssertionCheck::Flag(flag, expected),
+                AssertionExpression::Location(location, expected) => {
+                    AssertionCheck::Location(location, self.operation_to_u16(expected)?)
+                }
+                AssertionExpression::Memory(address, expected) => AssertionCheck::Memory(
+                    self.operation_to_u16(address)?,
+                    self.operation_to_u8(expected)?,
+                ),
+            };
+            assertions.push(Assertion { check, line });
+        }
+        Ok((self.room, assertions))
+    }
+
+    // Symbols come sorted by address and name, so map files are stable between runs
+    pub fn assemble_with_symbols(
+        mut self,
+        statements: Vec<Statement>,
+    ) -> Result<([u8; ROM_MEMORY_LIMIT], Vec<Symbol>), Error> {
+        self.stage_one(statements)?;
+        self.stage_two()?;
+        let mut symbols = self.symbols;
+        symbols.sort();
+        Ok((self.room, symbols))
+    }
+
+    pub fn assemble_with_listing(
+        mut self,
+        statements: Vec<Statement>,
+    ) -> Result<([u8; ROM_MEMORY_LIMIT], Vec<ListingEntry>), Error> {
+        self.stage_one(statements)?;
+        self.stage_two()?;
+        Ok((self.room, self.listing))
+    }
+
+    // Walks the statements to give every label its address, operands are resolved on stage two
     fn stage_one(&mut self, statements: Vec<Statement>) -> Result<(), Error> {
         for expression in statements {
             match expression {
-                Statement::InstructionExprStmt(instruction) => {
+                Statement::AssertStatement(assertion, line) => {
+                    self.assertions.push((assertion, line));
+                }
+                Statement::DataStatement(bytes, line) => {
+                    self.line = line;
+                    for byte in bytes {
+                        self.stage_one_room
+                            .push((StageOneValue::ByteOperation(byte), line));
+                        self.pc = self.pc.wrapping_add(1);
+                    }
+                }
+                Statement::InstructionExprStmt(instruction, line) => {
+                    self.line = line;
                     self.add_instruction(instruction)?;
                 }
-                Statement::LabelDefinitionStatement(label) => {
-                    self.two_words.insert(label, self.pc);
+                Statement::LabelDefinitionStatement(label, line) => {
+                    self.line = line;
+                    self.add_symbol(label, self.pc, SymbolKind::Label)?;
                 }
                 Statement::OrgStatement(tw) => {
                     self.pc = tw;
-                    self.stage_one_room.push(StageOneValue::OrgStatement(tw));
+                    self.stage_one_room
+                        .push((StageOneValue::OrgStatement(tw), self.line));
                 }
-                Statement::TwoWordDefinitionStatement(label, value) => {
+                Statement::TwoWordDefinitionStatement(label, value, line) => {
+                    self.line = line;
                     let value = self.operation_to_u16(value)?;
-                    self.two_words.insert(label, value);
+                    self.add_symbol(label, value, SymbolKind::Equ)?;
                 }
-                Statement::WordDefinitionStatement(label, value) => {
+                Statement::WordDefinitionStatement(label, value, line) => {
+                    self.line = line;
                     let value = u16::from(self.operation_to_u8(value)?);
-                    self.two_words.insert(label, value);
+                    self.add_symbol(label, value, SymbolKind::Equ)?;
                 }
             };
         }
         Ok(())
     }
 
+    fn add_symbol(
+        &mut self,
+        label: LabelExpression,
+        address: u16,
+        kind: SymbolKind,
+    ) -> Result<(), Error> {
+        if self.two_words.contains_key(&label) {
+            return Err(Error::from(AssemblerError::DuplicateLabel {
+                label,
+                line: self.line,
+            }));
+        }
+        self.symbols.push(Symbol {
+            address,
+            name: label.0.clone(),
+            kind,
+        });
+        self.two_words.insert(label, address);
+        Ok(())
+    }
+
     fn stage_two(&mut self) -> Result<(), Error> {
-        let iter = self.stage_one_room.iter();
+        let stage_one_room = std::mem::take(&mut self.stage_one_room);
         self.pc = 0;
-        for v in iter {
+        for (v, line) in stage_one_room.iter() {
+            self.line = *line;
+            let address = self.pc;
             match v {
                 StageOneValue::ByteOperation(op) => {
                     self.room[self.pc as usize] = self.operation_to_u8(op.clone())?;
                     self.pc = self.pc.wrapping_add(1);
                 }
-                StageOneValue::OrgStatement(address) => self.pc = *address,
+                StageOneValue::OrgStatement(address) => {
+                    self.pc = *address;
+                    continue;
+                }
                 StageOneValue::TwoByteOperation(op) => {
                     let tw = self.operation_to_u16(op.clone())?;
                     self.room[self.pc as usize] = (tw & 0x00ff) as u8;
@@ -93,10 +203,35 @@ impl Assembler {
                     self.pc = self.pc.wrapping_add(1);
                 }
             }
+            self.list(*line, address);
         }
+        self.stage_one_room = stage_one_room;
         Ok(())
     }
 
+    // Adds the bytes written since address to the line's entry, or starts a new one
+    fn list(&mut self, line: usize, address: u16) {
+        let length = self.pc.wrapping_sub(address);
+        if length == 0 {
+            return;
+        }
+        let room = &self.room;
+        let bytes = (0..length).map(|offset| room[address.wrapping_add(offset) as usize]);
+        match self.listing.last_mut() {
+            Some(ref mut entry)
+                if entry.line == line
+                    && entry.address.wrapping_add(entry.bytes.len() as u16) == address =>
+            {
+                entry.bytes.extend(bytes)
+            }
+            _ => self.listing.push(ListingEntry {
+                line,
+                address,
+                bytes: bytes.collect(),
+            }),
+        }
+    }
+
     fn operation_to_u8(&self, operation: OperationExpression) -> Result<u8, Error> {
         Ok(self.operation_to_u16(operation)? as u8)
     }
@@ -143,11 +278,12 @@ impl Assembler {
         match operand {
             TwoWordExpression::Char(char_value) => Ok(char_value as u16),
             TwoWordExpression::Dollar => Ok(self.pc - 1),
-            TwoWordExpression::Label(l) => self
-                .two_words
-                .get(&l)
-                .copied()
-                .ok_or_else(|| Error::from(AssemblerError::LabelNotFound { label: l })),
+            TwoWordExpression::Label(l) => self.two_words.get(&l).copied().ok_or_else(|| {
+                Error::from(AssemblerError::LabelNotFound {
+                    label: l,
+                    line: self.line,
+                })
+            }),
             TwoWordExpression::Literal(v) => Ok(v),
         }
     }
@@ -159,7 +295,7 @@ impl Assembler {
                 StageOneValue::ByteOperation(_) | StageOneValue::Word(_) => 1,
                 _ => 2,
             };
-            self.stage_one_room.push(v);
+            self.stage_one_room.push((v, self.line));
             self.pc = self.pc.wrapping_add(steps);
         }
         Ok(())
@@ -1184,6 +1320,7 @@ impl Assembler {
                 Some(InstructionArgument::DataStore(d)),
                 Some(InstructionArgument::DataStore(s)),
             ) => self.add_mov_instruction(&mut res, s, d),
+            Instruction(InstructionCode::Hlt, _, _) => res.push(StageOneValue::Word(0x76)),
             Instruction(
                 InstructionCode::Add,
                 Some(InstructionArgument::DataStore(location)),
@@ -1247,7 +1384,7 @@ impl Assembler {
             Instruction(InstructionCode::Adi, Some(InstructionArgument::Word(v)), _) => {
                 self.add_simple_word_instruction(0xc6, &mut res, v)?
             }
-            Instruction(InstructionCode::Rst, Some(InstructionArgument::TwoWord(v)), _) => {
+            Instruction(InstructionCode::Rst, Some(InstructionArgument::Word(v)), _) => {
                 self.add_rst_instruction(&mut res, v)?
             }
             Instruction(InstructionCode::Rz, _, _) => res.push(StageOneValue::Word(0xc8)),
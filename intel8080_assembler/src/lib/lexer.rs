@@ -41,6 +41,8 @@ impl<R: Read> Lexer<R> {
             '(' => Ok(Some(AssemblerTokenType::LeftParen)),
             ')' => Ok(Some(AssemblerTokenType::RightParen)),
             ':' => Ok(Some(AssemblerTokenType::Colon)),
+            // Separates statements written on the same line
+            '!' => Ok(Some(AssemblerTokenType::Bang)),
             ';' => {
                 self.consume(|c| c != '\n')?;
                 Ok(None)
@@ -51,6 +53,10 @@ impl<R: Read> Lexer<R> {
             '$' => Ok(Some(AssemblerTokenType::Dollar)),
             '*' => Ok(Some(AssemblerTokenType::Mult)),
             '/' => Ok(Some(AssemblerTokenType::Div)),
+            '=' if self.check(|c| c == '=') => {
+                self.source.next();
+                Ok(Some(AssemblerTokenType::EqualEqual))
+            }
             _ => Err(Error::from(AssemblerError::UnexpectedCharacter {
                 c: input,
                 line: self.line,
@@ -65,10 +71,14 @@ impl<R: Read> Lexer<R> {
         Ok(())
     }
 
+    // A single character is a number, longer ones are strings that only DB takes
     #[inline]
     fn scan_char(&mut self) -> Result<Option<AssemblerTokenType>, Error> {
         let rest = self.consume(|c| c != '\'')?;
         self.source.next();
+        if rest.chars().count() > 1 {
+            return Ok(Some(AssemblerTokenType::Str(rest)));
+        }
         let value = char::from_str(&rest)?;
         Ok(Some(AssemblerTokenType::Char(value)))
     }
@@ -78,15 +88,15 @@ impl<R: Read> Lexer<R> {
         &mut self,
         first_char: char,
     ) -> Result<Option<AssemblerTokenType>, Error> {
-        let rest = self.consume(|c| c.is_alphabetic() || c == '_')?;
+        let rest = self.consume(|c| c.is_alphanumeric() || c == '_')?;
         let literal = format!("{}{}", first_char, rest);
         Ok(match literal.as_str() {
-            "A" | "B" | "C" | "D" | "E" | "H" | "L" | "M" | "PSW" | "SP" => {
-                Some(AssemblerTokenType::DataStore(Location::from(&literal)?))
-            }
             "AND" => Some(AssemblerTokenType::And),
+            "ASSERT" => Some(AssemblerTokenType::Assert),
             "DB" => Some(AssemblerTokenType::Db),
             "DW" => Some(AssemblerTokenType::Dw),
+            "ENDM" => Some(AssemblerTokenType::Endm),
+            "MACRO" => Some(AssemblerTokenType::Macro),
             "ORG" => Some(AssemblerTokenType::Org),
             "MOD" => Some(AssemblerTokenType::Mod),
             "NOT" => Some(AssemblerTokenType::Not),
@@ -172,7 +182,14 @@ impl<R: Read> Lexer<R> {
             "EI" => Some(AssemblerTokenType::InstructionCode(InstructionCode::Ei)),
             "CM" => Some(AssemblerTokenType::InstructionCode(InstructionCode::Cm)),
             "CPI" => Some(AssemblerTokenType::InstructionCode(InstructionCode::Cpi)),
-            _ => Some(AssemblerTokenType::LabelToken(LabelExpression(literal))),
+            _ => match Location::from_str(&literal) {
+                // Only the names in 8080 mnemonics, in upper case. The pair forms BC, DE and HL
+                // are still labels, the parser reads them as registers in operand position
+                Ok(location) if literal == location.to_string() => {
+                    Some(AssemblerTokenType::DataStore(location))
+                }
+                _ => Some(AssemblerTokenType::LabelToken(LabelExpression(literal))),
+            },
         })
     }
 
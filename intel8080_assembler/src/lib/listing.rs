@@ -0,0 +1,54 @@
+use super::Radix;
+
+// Bytes a source line put in memory, starting at address
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListingEntry {
+    pub line: usize,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+}
+
+// No instruction takes more than three bytes, longer data goes on the lines below its source
+const BYTES_PER_LINE: usize = 3;
+
+// Every source line, after the address and the bytes it assembled to. Lines that don't emit
+// anything keep those columns empty
+pub fn write_listing(source: &str, entries: &[ListingEntry], radix: Radix) -> String {
+    let address_width = radix.address(0).len();
+    let bytes_width = (radix.byte(0).len() + 1) * BYTES_PER_LINE;
+    let mut listing = String::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut wrote_text = false;
+        for entry in entries.iter().filter(|entry| entry.line == line) {
+            for (chunk_index, chunk) in entry.bytes.chunks(BYTES_PER_LINE).enumerate() {
+                let address = entry
+                    .address
+                    .wrapping_add((chunk_index * BYTES_PER_LINE) as u16);
+                let bytes: Vec<String> = chunk.iter().map(|byte| radix.byte(*byte)).collect();
+                let text = if wrote_text { "" } else { text };
+                wrote_text = true;
+                let row = format!(
+                    "{} {:width$} {}",
+                    radix.address(address),
+                    bytes.join(" "),
+                    text,
+                    width = bytes_width - 1
+                );
+                listing.push_str(row.trim_end());
+                listing.push('\n');
+            }
+        }
+        if !wrote_text {
+            let row = format!(
+                "{:width$}{}",
+                "",
+                text,
+                width = address_width + bytes_width + 1
+            );
+            listing.push_str(row.trim_end());
+            listing.push('\n');
+        }
+    }
+    listing
+}
@@ -0,0 +1,60 @@
+use super::{AssemblerToken, AssemblerTokenType, LabelExpression};
+use std::collections::HashSet;
+
+// Deep enough for any sane nesting, shallow enough to stop a macro that invokes itself
+pub(crate) const MAX_MACRO_DEPTH: usize = 16;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Macro {
+    pub(crate) name: LabelExpression,
+    // Labels or registers, a register parameter replaces that register everywhere in the body
+    pub(crate) parameters: Vec<AssemblerTokenType>,
+    pub(crate) body: Vec<AssemblerToken>,
+}
+
+impl Macro {
+    // The body with every parameter replaced by the tokens of its argument, in the line of the
+    // parameter. Labels defined in the body get the expansion as suffix, so using the macro twice
+    // doesn't declare them twice. The lexer doesn't take dots in labels, so no label in the source
+    // can be named like them
+    pub(crate) fn expand(
+        &self,
+        arguments: &[Vec<AssemblerToken>],
+        expansion: usize,
+    ) -> Vec<AssemblerToken> {
+        let locals = self.local_labels();
+        let mut tokens = Vec::with_capacity(self.body.len());
+        for token in self.body.iter() {
+            if let Some(index) = self.parameters.iter().position(|p| *p == token.token_type) {
+                tokens.extend(arguments[index].iter().map(|argument| AssemblerToken {
+                    token_type: argument.token_type.clone(),
+                    line: token.line,
+                }));
+                continue;
+            }
+            match token.token_type {
+                AssemblerTokenType::LabelToken(ref label) if locals.contains(label) => {
+                    let local = format!("{}.{}.{}", label.0, self.name.0, expansion);
+                    tokens.push(AssemblerToken {
+                        token_type: AssemblerTokenType::LabelToken(LabelExpression(local)),
+                        line: token.line,
+                    });
+                }
+                _ => tokens.push(token.clone()),
+            }
+        }
+        tokens
+    }
+
+    fn local_labels(&self) -> HashSet<&LabelExpression> {
+        self.body
+            .windows(2)
+            .filter_map(|pair| match (&pair[0].token_type, &pair[1].token_type) {
+                (AssemblerTokenType::LabelToken(label), AssemblerTokenType::Colon)
+                | (AssemblerTokenType::LabelToken(label), AssemblerTokenType::Db)
+                | (AssemblerTokenType::LabelToken(label), AssemblerTokenType::Dw) => Some(label),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -3,6 +3,7 @@ extern crate failure;
 extern crate intel8080cpu;
 
 use intel8080cpu::Location;
+use std::fmt;
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct LabelExpression(String);
@@ -11,22 +12,16 @@ pub struct LabelExpression(String);
 pub enum AssemblerError {
     #[fail(display = "Unexpected character: {} at line {}", c, line)]
     UnexpectedCharacter { c: char, line: usize },
-    #[fail(display = "Expecting {:?}, got {:?} ar line {}", expected, got, line)]
+    #[fail(display = "Expecting {}, got {} ar line {}", expected, got, line)]
     ExpectingToken {
-        expected: AssemblerTokenType,
-        got: Option<AssemblerTokenType>,
-        line: usize,
-    },
-    #[fail(display = "Expecting number, got {:?} at line {}", got, line)]
-    ExpectingNumber {
-        got: Option<AssemblerTokenType>,
-        line: usize,
-    },
-    #[fail(display = "Expecting number, got {:?} at line {}", got, line)]
-    ExpectingOperation {
-        got: Option<AssemblerTokenType>,
+        expected: SourceToken,
+        got: SourceToken,
         line: usize,
     },
+    #[fail(display = "Expecting number, got {} at line {}", got, line)]
+    ExpectingNumber { got: SourceToken, line: usize },
+    #[fail(display = "Expecting number, got {} at line {}", got, line)]
+    ExpectingOperation { got: SourceToken, line: usize },
     #[fail(display = "Expecting single character at line {}", line)]
     ExpectingCharacter { line: usize },
     #[fail(display = "Expecting single quote at line {}", line)]
@@ -41,8 +36,55 @@ pub enum AssemblerError {
     UndefinedError { line: usize },
     #[fail(display = "Unexpected end of expression at line {}", line)]
     UnexpectedEndOfExpression { line: usize },
-    #[fail(display = "Label {:?} wasn't declared", label)]
-    LabelNotFound { label: LabelExpression },
+    #[fail(display = "Label {:?} wasn't declared, used at line {}", label, line)]
+    LabelNotFound { label: LabelExpression, line: usize },
+    #[fail(display = "Label {:?} is declared again at line {}", label, line)]
+    DuplicateLabel { label: LabelExpression, line: usize },
+    #[fail(display = "Invalid assertion at line {}", line)]
+    InvalidAssertion { line: usize },
+    #[fail(display = "Invalid symbol map entry at line {}", line)]
+    InvalidMapEntry { line: usize },
+    #[fail(display = "Unknown radix {}, expecting hex, dec or oct", radix)]
+    InvalidRadix { radix: String },
+    #[fail(display = "Invalid macro parameter at line {}", line)]
+    InvalidMacroParameter { line: usize },
+    #[fail(display = "Macro {:?} declared at line {} has no ENDM", name, line)]
+    UnterminatedMacro { name: LabelExpression, line: usize },
+    #[fail(display = "Macro {:?} declares another macro at line {}", name, line)]
+    NestedMacro { name: LabelExpression, line: usize },
+    #[fail(
+        display = "Macro {:?} takes {} arguments, got {} at line {}",
+        name, expected, got, line
+    )]
+    MacroArgumentCount {
+        name: LabelExpression,
+        expected: usize,
+        got: usize,
+        line: usize,
+    },
+    #[fail(display = "Macro {:?} expands too deeply at line {}", name, line)]
+    MacroRecursion { name: LabelExpression, line: usize },
+    // The error keeps the line inside the macro, the invocation is the one in the source
+    #[fail(display = "{} in macro {:?} invoked at line {}", error, name, line)]
+    InMacro {
+        name: LabelExpression,
+        line: usize,
+        error: Box<AssemblerError>,
+    },
+}
+
+// A token in a diagnostic, with the radix its numbers are written in. None is the end of the
+// source
+#[derive(Debug)]
+pub struct SourceToken(pub Option<AssemblerTokenType>, pub Radix);
+
+impl fmt::Display for SourceToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(ref token) => write!(f, "{}", token.to_string_with_radix(self.1)),
+            None => write!(f, "end of file"),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -130,6 +172,8 @@ pub enum InstructionCode {
 #[derive(Clone, Debug, PartialEq)]
 pub enum AssemblerTokenType {
     And,
+    Assert,
+    Bang,
     Char(char),
     Colon,
     Comma,
@@ -138,9 +182,12 @@ pub enum AssemblerTokenType {
     Div,
     Dollar,
     Dw,
+    Endm,
+    EqualEqual,
     InstructionCode(InstructionCode),
     LabelToken(LabelExpression),
     LeftParen,
+    Macro,
     Minus,
     Mod,
     Mult,
@@ -151,10 +198,49 @@ pub enum AssemblerTokenType {
     RightParen,
     Shl,
     Shr,
+    Str(String),
     TwoWord(u16),
     Xor,
 }
 
+impl AssemblerTokenType {
+    // The token as it'd be written in the source
+    pub fn to_string_with_radix(&self, radix: Radix) -> String {
+        match self {
+            AssemblerTokenType::And => String::from("AND"),
+            AssemblerTokenType::Assert => String::from("ASSERT"),
+            AssemblerTokenType::Bang => String::from("!"),
+            AssemblerTokenType::Char(c) => format!("'{}'", c),
+            AssemblerTokenType::Colon => String::from(":"),
+            AssemblerTokenType::Comma => String::from(","),
+            AssemblerTokenType::DataStore(location) => location.to_string(),
+            AssemblerTokenType::Db => String::from("DB"),
+            AssemblerTokenType::Div => String::from("/"),
+            AssemblerTokenType::Dollar => String::from("$"),
+            AssemblerTokenType::Dw => String::from("DW"),
+            AssemblerTokenType::Endm => String::from("ENDM"),
+            AssemblerTokenType::EqualEqual => String::from("=="),
+            AssemblerTokenType::InstructionCode(code) => format!("{:?}", code).to_uppercase(),
+            AssemblerTokenType::LabelToken(label) => label.0.clone(),
+            AssemblerTokenType::LeftParen => String::from("("),
+            AssemblerTokenType::Macro => String::from("MACRO"),
+            AssemblerTokenType::Minus => String::from("-"),
+            AssemblerTokenType::Mod => String::from("MOD"),
+            AssemblerTokenType::Mult => String::from("*"),
+            AssemblerTokenType::Not => String::from("NOT"),
+            AssemblerTokenType::Or => String::from("OR"),
+            AssemblerTokenType::Org => String::from("ORG"),
+            AssemblerTokenType::Plus => String::from("+"),
+            AssemblerTokenType::RightParen => String::from(")"),
+            AssemblerTokenType::Shl => String::from("SHL"),
+            AssemblerTokenType::Shr => String::from("SHR"),
+            AssemblerTokenType::Str(s) => format!("'{}'", s),
+            AssemblerTokenType::TwoWord(value) => radix.number(*value),
+            AssemblerTokenType::Xor => String::from("XOR"),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct AssemblerToken {
     pub token_type: AssemblerTokenType,
@@ -218,6 +304,22 @@ impl From<u16> for InstructionArgument {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum FlagExpression {
+    AuxiliaryCarry,
+    Carry,
+    Parity,
+    Sign,
+    Zero,
+}
+
+#[derive(Clone, Debug)]
+pub enum AssertionExpression {
+    Flag(FlagExpression, bool),
+    Location(Location, OperationExpression),
+    Memory(OperationExpression, OperationExpression),
+}
+
 #[derive(Debug)]
 pub struct Instruction(
     InstructionCode,
@@ -226,16 +328,51 @@ pub struct Instruction(
 );
 
 pub enum Statement {
-    WordDefinitionStatement(LabelExpression, OperationExpression),
-    InstructionExprStmt(Instruction),
-    LabelDefinitionStatement(LabelExpression),
+    AssertStatement(AssertionExpression, usize),
+    DataStatement(Vec<OperationExpression>, usize),
+    WordDefinitionStatement(LabelExpression, OperationExpression, usize),
+    InstructionExprStmt(Instruction, usize),
+    LabelDefinitionStatement(LabelExpression, usize),
     OrgStatement(u16),
-    TwoWordDefinitionStatement(LabelExpression, OperationExpression),
+    TwoWordDefinitionStatement(LabelExpression, OperationExpression, usize),
+}
+
+impl Statement {
+    pub(crate) fn at_line(self, line: usize) -> Statement {
+        match self {
+            Statement::AssertStatement(assertion, _) => Statement::AssertStatement(assertion, line),
+            Statement::DataStatement(bytes, _) => Statement::DataStatement(bytes, line),
+            Statement::WordDefinitionStatement(label, value, _) => {
+                Statement::WordDefinitionStatement(label, value, line)
+            }
+            Statement::InstructionExprStmt(instruction, _) => {
+                Statement::InstructionExprStmt(instruction, line)
+            }
+            Statement::LabelDefinitionStatement(label, _) => {
+                Statement::LabelDefinitionStatement(label, line)
+            }
+            Statement::OrgStatement(address) => Statement::OrgStatement(address),
+            Statement::TwoWordDefinitionStatement(label, value, _) => {
+                Statement::TwoWordDefinitionStatement(label, value, line)
+            }
+        }
+    }
 }
 
 mod assembler;
 mod lexer;
+mod listing;
+mod macros;
 mod parser;
-pub use assembler::Assembler;
+mod radix;
+mod symbols;
+mod test_runner;
+pub use assembler::{Assembler, Assertion, AssertionCheck};
 pub use lexer::Lexer;
+pub use listing::{write_listing, ListingEntry};
 pub use parser::Parser;
+pub use radix::Radix;
+pub use symbols::{
+    read_map, read_map_with_radix, write_map, write_map_with_radix, Symbol, SymbolKind, SymbolTable,
+};
+pub use test_runner::{run_test, AssertionResult, TestReport};
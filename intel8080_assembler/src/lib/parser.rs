@@ -1,15 +1,23 @@
 extern crate failure;
 extern crate intel8080cpu;
 
+use super::macros::{Macro, MAX_MACRO_DEPTH};
 use super::*;
 use failure::Error;
 use intel8080cpu::{Location, RegisterType};
+use std::collections::HashMap;
 use std::iter::{IntoIterator, Peekable};
+use std::mem;
+use std::str::FromStr;
 use std::vec::IntoIter;
 
 pub struct Parser {
     source: Peekable<IntoIter<AssemblerToken>>,
     expressions: Vec<Statement>,
+    macros: HashMap<LabelExpression, Macro>,
+    expansions: usize,
+    depth: usize,
+    radix: Radix,
 }
 
 impl Parser {
@@ -17,17 +25,47 @@ impl Parser {
         Parser {
             source: source.into_iter().peekable(),
             expressions: Vec::new(),
+            macros: HashMap::new(),
+            expansions: 0,
+            depth: 0,
+            radix: Radix::default(),
         }
     }
 
+    // The radix of the numbers in the diagnostics
+    pub fn with_radix(mut self, radix: Radix) -> Parser {
+        self.radix = radix;
+        self
+    }
+
     pub fn parse_statements(mut self) -> Result<Vec<Statement>, Error> {
+        self.parse_source()?;
+        Ok(self.expressions)
+    }
+
+    fn parse_source(&mut self) -> Result<(), Error> {
         while let Some(input) = self.source.next() {
-            self.parse_statement(&input)?;
+            // Statements don't need a separator, so the ones between them can be skipped
+            if input.token_type != AssemblerTokenType::Bang {
+                self.parse_statement(&input)?;
+            }
         }
-        Ok(self.expressions)
+        Ok(())
     }
 
     fn parse_statement(&mut self, input: &AssemblerToken) -> Result<(), Error> {
+        if let AssemblerTokenType::LabelToken(ref label) = input.token_type {
+            match self.source.peek().map(|t| &t.token_type) {
+                Some(AssemblerTokenType::Macro) => {
+                    return self.parse_macro_definition(label, input.line)
+                }
+                Some(AssemblerTokenType::Colon) => {}
+                _ if self.macros.contains_key(label) => {
+                    return self.expand_macro(label, input.line)
+                }
+                _ => {}
+            }
+        }
         let next = self.source.peek().map(|a| (*a).clone());
         let expression = match (input, next) {
             (
@@ -50,13 +88,13 @@ impl Parser {
                 },
                 ref got,
             ) => Err(Error::from(AssemblerError::ExpectingNumber {
-                got: got.clone().map(|v| v.token_type),
+                got: SourceToken(got.clone().map(|v| v.token_type), self.radix),
                 line: *line,
             })),
             (
                 AssemblerToken {
                     token_type: AssemblerTokenType::LabelToken(ref label),
-                    ..
+                    line,
                 },
                 Some(AssemblerToken {
                     token_type: AssemblerTokenType::Colon,
@@ -64,7 +102,7 @@ impl Parser {
                 }),
             ) => {
                 self.source.next();
-                Ok(Statement::LabelDefinitionStatement(label.clone()))
+                Ok(Statement::LabelDefinitionStatement(label.clone(), *line))
             }
             (
                 AssemblerToken {
@@ -87,17 +125,33 @@ impl Parser {
                     line,
                 }),
             ) => self.parse_word_definition(label, line),
+            (
+                AssemblerToken {
+                    token_type: AssemblerTokenType::Assert,
+                    line,
+                },
+                _,
+            ) => self.parse_assertion(*line),
+            (
+                AssemblerToken {
+                    token_type: AssemblerTokenType::Db,
+                    line,
+                },
+                _,
+            ) => self.parse_data(*line),
             (
                 AssemblerToken {
                     token_type: AssemblerTokenType::InstructionCode(instruction),
                     line,
                 },
                 ref next,
-            ) => self.parse_instruction(
-                instruction,
-                &next.clone().map(|t| t.token_type),
-                line.clone(),
-            ),
+            ) => {
+                let operand = next
+                    .clone()
+                    .map(|t| register_pair_operand(instruction, t.token_type));
+                self.parse_instruction(instruction, &operand, *line)
+                    .map(|instruction| Statement::InstructionExprStmt(instruction, *line))
+            }
             (t, _) => Err(Error::from(AssemblerError::UndefinedError { line: t.line })),
         }?;
         self.expressions.push(expression);
@@ -111,7 +165,7 @@ impl Parser {
     ) -> Result<Statement, Error> {
         self.source.next();
         let op = self.parse_operation(line)?;
-        Ok(Statement::WordDefinitionStatement(label.clone(), op))
+        Ok(Statement::WordDefinitionStatement(label.clone(), op, line))
     }
 
     fn parse_two_word_definition(
@@ -121,7 +175,82 @@ impl Parser {
     ) -> Result<Statement, Error> {
         self.source.next();
         let op = self.parse_operation(line)?;
-        Ok(Statement::TwoWordDefinitionStatement(label.clone(), op))
+        Ok(Statement::TwoWordDefinitionStatement(
+            label.clone(),
+            op,
+            line,
+        ))
+    }
+
+    // Without a label, DB stores its comma separated bytes right where it is. Strings store a
+    // byte per character
+    fn parse_data(&mut self, line: usize) -> Result<Statement, Error> {
+        let mut bytes = Vec::new();
+        self.parse_data_item(&mut bytes, line)?;
+        while let Some(AssemblerTokenType::Comma) = self.source.peek().map(|t| t.token_type.clone())
+        {
+            self.source.next();
+            self.parse_data_item(&mut bytes, line)?;
+        }
+        Ok(Statement::DataStatement(bytes, line))
+    }
+
+    fn parse_data_item(
+        &mut self,
+        bytes: &mut Vec<OperationExpression>,
+        line: usize,
+    ) -> Result<(), Error> {
+        if let Some(AssemblerTokenType::Str(text)) =
+            self.source.peek().map(|t| t.token_type.clone())
+        {
+            self.source.next();
+            bytes.extend(
+                text.chars()
+                    .map(|c| OperationExpression::Operand(TwoWordExpression::Char(c))),
+            );
+        } else {
+            bytes.push(self.parse_operation(line)?);
+        }
+        Ok(())
+    }
+
+    fn parse_assertion(&mut self, line: usize) -> Result<Statement, Error> {
+        let next = self.source.next().map(|t| t.token_type);
+        let expression = match next {
+            Some(AssemblerTokenType::DataStore(location)) => {
+                self.consume(AssemblerTokenType::EqualEqual, line)?;
+                let expected = self.parse_operation(line)?;
+                Ok(AssertionExpression::Location(location, expected))
+            }
+            Some(AssemblerTokenType::LabelToken(ref label)) if label.0 == "MEM" => {
+                let address = self.parse_operation(line)?;
+                self.consume(AssemblerTokenType::EqualEqual, line)?;
+                let expected = self.parse_operation(line)?;
+                Ok(AssertionExpression::Memory(address, expected))
+            }
+            Some(AssemblerTokenType::Not) => match self.source.next().map(|t| t.token_type) {
+                Some(AssemblerTokenType::LabelToken(ref label)) => {
+                    Ok(AssertionExpression::Flag(self.parse_flag(label, line)?, false))
+                }
+                _ => Err(Error::from(AssemblerError::InvalidAssertion { line })),
+            },
+            Some(AssemblerTokenType::LabelToken(ref label)) => {
+                Ok(AssertionExpression::Flag(self.parse_flag(label, line)?, true))
+            }
+            _ => Err(Error::from(AssemblerError::InvalidAssertion { line })),
+        }?;
+        Ok(Statement::AssertStatement(expression, line))
+    }
+
+    fn parse_flag(&self, label: &LabelExpression, line: usize) -> Result<FlagExpression, Error> {
+        match label.0.as_str() {
+            "AUXCARRY" => Ok(FlagExpression::AuxiliaryCarry),
+            "CARRY" => Ok(FlagExpression::Carry),
+            "PARITY" => Ok(FlagExpression::Parity),
+            "SIGN" => Ok(FlagExpression::Sign),
+            "ZERO" => Ok(FlagExpression::Zero),
+            _ => Err(Error::from(AssemblerError::InvalidAssertion { line })),
+        }
     }
 
     fn parse_operation(&mut self, line: usize) -> Result<OperationExpression, Error> {
@@ -277,7 +406,10 @@ impl Parser {
             Some(AssemblerTokenType::Dollar) => Ok(TwoWordExpression::Dollar),
             Some(AssemblerTokenType::TwoWord(value)) => Ok(TwoWordExpression::Literal(value)),
             Some(AssemblerTokenType::LabelToken(label)) => Ok(TwoWordExpression::Label(label)),
-            got => Err(Error::from(AssemblerError::ExpectingNumber { got, line })),
+            got => Err(Error::from(AssemblerError::ExpectingNumber {
+                got: SourceToken(got, self.radix),
+                line,
+            })),
         }?;
         self.source.next();
         Ok(res)
@@ -288,7 +420,7 @@ impl Parser {
         instruction: &InstructionCode,
         next: &Option<AssemblerTokenType>,
         line: usize,
-    ) -> Result<Statement, Error> {
+    ) -> Result<Instruction, Error> {
         match (instruction, next) {
             (
                 InstructionCode::Adc,
@@ -490,16 +622,8 @@ impl Parser {
             }
             (InstructionCode::Cc, _) => self.parse_two_word_instruction(InstructionCode::Cc, line),
             (InstructionCode::Cm, _) => self.parse_two_word_instruction(InstructionCode::Cm, line),
-            (InstructionCode::Cma, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Cma,
-                None,
-                None,
-            ))),
-            (InstructionCode::Cmc, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Cmc,
-                None,
-                None,
-            ))),
+            (InstructionCode::Cma, _) => Ok(Instruction(InstructionCode::Cma, None, None)),
+            (InstructionCode::Cmc, _) => Ok(Instruction(InstructionCode::Cmc, None, None)),
             (
                 InstructionCode::Cmp,
                 &Some(AssemblerTokenType::DataStore(
@@ -579,11 +703,7 @@ impl Parser {
                 self.parse_two_word_instruction(InstructionCode::Cpo, line)
             }
             (InstructionCode::Cz, _) => self.parse_two_word_instruction(InstructionCode::Cz, line),
-            (InstructionCode::Daa, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Daa,
-                None,
-                None,
-            ))),
+            (InstructionCode::Daa, _) => Ok(Instruction(InstructionCode::Daa, None, None)),
             (
                 InstructionCode::Dad,
                 &Some(AssemblerTokenType::DataStore(
@@ -722,21 +842,9 @@ impl Parser {
                     line,
                 }))
             }
-            (InstructionCode::Di, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Di,
-                None,
-                None,
-            ))),
-            (InstructionCode::Ei, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Ei,
-                None,
-                None,
-            ))),
-            (InstructionCode::Hlt, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Hlt,
-                None,
-                None,
-            ))),
+            (InstructionCode::Di, _) => Ok(Instruction(InstructionCode::Di, None, None)),
+            (InstructionCode::Ei, _) => Ok(Instruction(InstructionCode::Ei, None, None)),
+            (InstructionCode::Hlt, _) => Ok(Instruction(InstructionCode::Hlt, None, None)),
             (InstructionCode::In, _) => self.parse_word_instruction(InstructionCode::In, line),
             (
                 InstructionCode::Inr,
@@ -920,11 +1028,11 @@ impl Parser {
                 self.source.next();
                 self.consume(AssemblerTokenType::Comma, line)?;
                 let op = self.parse_operation(line)?;
-                Ok(Statement::InstructionExprStmt(Instruction(
+                Ok(Instruction(
                     InstructionCode::Lxi,
                     Some(InstructionArgument::DataStore(l)),
                     Some(InstructionArgument::TwoWord(op)),
-                )))
+                ))
             }
             (InstructionCode::Lxi, _) => {
                 Err(Error::from(AssemblerError::InvalidInstructionArgument {
@@ -1028,11 +1136,11 @@ impl Parser {
                     ))
                     | Some(AssemblerTokenType::DataStore(s @ Location::Memory)) => {
                         self.source.next();
-                        Ok(Statement::InstructionExprStmt(Instruction(
+                        Ok(Instruction(
                             InstructionCode::Mov,
                             Some(InstructionArgument::DataStore(d)),
                             Some(InstructionArgument::DataStore(s)),
-                        )))
+                        ))
                     }
                     _ => Err(Error::from(AssemblerError::InvalidInstructionArgument {
                         line,
@@ -1104,22 +1212,18 @@ impl Parser {
                 self.source.next();
                 self.consume(AssemblerTokenType::Comma, line)?;
                 let op = self.parse_operation(line)?;
-                Ok(Statement::InstructionExprStmt(Instruction(
+                Ok(Instruction(
                     InstructionCode::Mvi,
                     Some(InstructionArgument::DataStore(s)),
                     Some(InstructionArgument::from(op)),
-                )))
+                ))
             }
             (InstructionCode::Mvi, _) => {
                 Err(Error::from(AssemblerError::InvalidInstructionArgument {
                     line,
                 }))
             }
-            (InstructionCode::Noop, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Noop,
-                None,
-                None,
-            ))),
+            (InstructionCode::Noop, _) => Ok(Instruction(InstructionCode::Noop, None, None)),
             (
                 InstructionCode::Ora,
                 &Some(AssemblerTokenType::DataStore(
@@ -1186,11 +1290,7 @@ impl Parser {
             }
             (InstructionCode::Ori, _) => self.parse_word_instruction(InstructionCode::Ori, line),
             (InstructionCode::Out, _) => self.parse_word_instruction(InstructionCode::Out, line),
-            (InstructionCode::Pchl, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Pchl,
-                None,
-                None,
-            ))),
+            (InstructionCode::Pchl, _) => Ok(Instruction(InstructionCode::Pchl, None, None)),
             (
                 InstructionCode::Pop,
                 &Some(AssemblerTokenType::DataStore(
@@ -1265,72 +1365,20 @@ impl Parser {
                     line,
                 }))
             }
-            (InstructionCode::Ral, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Ral,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rar, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rar,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rc, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rc,
-                None,
-                None,
-            ))),
-            (InstructionCode::Ret, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Ret,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rlc, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rlc,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rm, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rm,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rnc, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rnc,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rnz, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rnz,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rp, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rp,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rpe, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rpe,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rpo, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rpo,
-                None,
-                None,
-            ))),
-            (InstructionCode::Rrc, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rrc,
-                None,
-                None,
-            ))),
+            (InstructionCode::Ral, _) => Ok(Instruction(InstructionCode::Ral, None, None)),
+            (InstructionCode::Rar, _) => Ok(Instruction(InstructionCode::Rar, None, None)),
+            (InstructionCode::Rc, _) => Ok(Instruction(InstructionCode::Rc, None, None)),
+            (InstructionCode::Ret, _) => Ok(Instruction(InstructionCode::Ret, None, None)),
+            (InstructionCode::Rlc, _) => Ok(Instruction(InstructionCode::Rlc, None, None)),
+            (InstructionCode::Rm, _) => Ok(Instruction(InstructionCode::Rm, None, None)),
+            (InstructionCode::Rnc, _) => Ok(Instruction(InstructionCode::Rnc, None, None)),
+            (InstructionCode::Rnz, _) => Ok(Instruction(InstructionCode::Rnz, None, None)),
+            (InstructionCode::Rp, _) => Ok(Instruction(InstructionCode::Rp, None, None)),
+            (InstructionCode::Rpe, _) => Ok(Instruction(InstructionCode::Rpe, None, None)),
+            (InstructionCode::Rpo, _) => Ok(Instruction(InstructionCode::Rpo, None, None)),
+            (InstructionCode::Rrc, _) => Ok(Instruction(InstructionCode::Rrc, None, None)),
             (InstructionCode::Rst, _) => self.parse_word_instruction(InstructionCode::Rst, line),
-            (InstructionCode::Rz, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Rz,
-                None,
-                None,
-            ))),
+            (InstructionCode::Rz, _) => Ok(Instruction(InstructionCode::Rz, None, None)),
             (
                 InstructionCode::Sbb,
                 &Some(AssemblerTokenType::DataStore(
@@ -1399,11 +1447,7 @@ impl Parser {
             (InstructionCode::Shld, _) => {
                 self.parse_two_word_instruction(InstructionCode::Shld, line)
             }
-            (InstructionCode::Sphl, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Sphl,
-                None,
-                None,
-            ))),
+            (InstructionCode::Sphl, _) => Ok(Instruction(InstructionCode::Sphl, None, None)),
             (InstructionCode::Sta, _) => {
                 self.parse_two_word_instruction(InstructionCode::Sta, line)
             }
@@ -1428,11 +1472,7 @@ impl Parser {
                     line,
                 }))
             }
-            (InstructionCode::Stc, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Stc,
-                None,
-                None,
-            ))),
+            (InstructionCode::Stc, _) => Ok(Instruction(InstructionCode::Stc, None, None)),
             (
                 InstructionCode::Sub,
                 &Some(AssemblerTokenType::DataStore(
@@ -1498,11 +1538,7 @@ impl Parser {
                 }))
             }
             (InstructionCode::Sui, _) => self.parse_word_instruction(InstructionCode::Sui, line),
-            (InstructionCode::Xchg, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Xchg,
-                None,
-                None,
-            ))),
+            (InstructionCode::Xchg, _) => Ok(Instruction(InstructionCode::Xchg, None, None)),
             (
                 InstructionCode::Xra,
                 &Some(AssemblerTokenType::DataStore(
@@ -1568,11 +1604,7 @@ impl Parser {
                 }))
             }
             (InstructionCode::Xri, _) => self.parse_word_instruction(InstructionCode::Xri, line),
-            (InstructionCode::Xthl, _) => Ok(Statement::InstructionExprStmt(Instruction(
-                InstructionCode::Xthl,
-                None,
-                None,
-            ))),
+            (InstructionCode::Xthl, _) => Ok(Instruction(InstructionCode::Xthl, None, None)),
         }
     }
 
@@ -1581,14 +1613,14 @@ impl Parser {
             Some(AssemblerToken { ref token_type, .. }) if token_type == &token => Ok(()),
             Some(AssemblerToken { token_type, line }) => {
                 Err(Error::from(AssemblerError::ExpectingToken {
-                    expected: token,
-                    got: Some(token_type),
+                    expected: SourceToken(Some(token), self.radix),
+                    got: SourceToken(Some(token_type), self.radix),
                     line,
                 }))
             }
             None => Err(Error::from(AssemblerError::ExpectingToken {
-                expected: token,
-                got: None,
+                expected: SourceToken(Some(token), self.radix),
+                got: SourceToken(None, self.radix),
                 line,
             })),
         }
@@ -1599,13 +1631,13 @@ impl Parser {
         &mut self,
         l: Location,
         i: InstructionCode,
-    ) -> Result<Statement, Error> {
+    ) -> Result<Instruction, Error> {
         self.source.next();
-        Ok(Statement::InstructionExprStmt(Instruction(
+        Ok(Instruction(
             i,
             Some(InstructionArgument::DataStore(l)),
             None,
-        )))
+        ))
     }
 
     #[inline]
@@ -1613,13 +1645,13 @@ impl Parser {
         &mut self,
         i: InstructionCode,
         line: usize,
-    ) -> Result<Statement, Error> {
+    ) -> Result<Instruction, Error> {
         let op = self.parse_operation(line)?;
-        Ok(Statement::InstructionExprStmt(Instruction(
+        Ok(Instruction(
             i.clone(),
             Some(InstructionArgument::Word(op)),
             None,
-        )))
+        ))
     }
 
     #[inline]
@@ -1627,12 +1659,169 @@ impl Parser {
         &mut self,
         i: InstructionCode,
         line: usize,
-    ) -> Result<Statement, Error> {
+    ) -> Result<Instruction, Error> {
         let op = self.parse_operation(line)?;
-        Ok(Statement::InstructionExprStmt(Instruction(
+        Ok(Instruction(
             i.clone(),
             Some(InstructionArgument::TwoWord(op)),
             None,
-        )))
+        ))
+    }
+
+    // NAME MACRO P1, P2 with the parameters on the same line, and the body up to ENDM
+    fn parse_macro_definition(&mut self, name: &LabelExpression, line: usize) -> Result<(), Error> {
+        self.source.next();
+        let mut parameters = Vec::new();
+        while self.source.peek().map(|t| t.line) == Some(line) {
+            match self.source.next().map(|t| t.token_type) {
+                Some(parameter @ AssemblerTokenType::LabelToken(_))
+                | Some(parameter @ AssemblerTokenType::DataStore(_)) => parameters.push(parameter),
+                _ => return Err(Error::from(AssemblerError::InvalidMacroParameter { line })),
+            }
+            if self.source.peek().map(|t| (t.line, &t.token_type))
+                == Some((line, &AssemblerTokenType::Comma))
+            {
+                self.source.next();
+            }
+        }
+        let mut body = Vec::new();
+        loop {
+            match self.source.next() {
+                Some(AssemblerToken {
+                    token_type: AssemblerTokenType::Endm,
+                    ..
+                }) => break,
+                Some(AssemblerToken {
+                    token_type: AssemblerTokenType::Macro,
+                    line: nested,
+                }) => {
+                    return Err(Error::from(AssemblerError::NestedMacro {
+                        name: name.clone(),
+                        line: nested,
+                    }))
+                }
+                Some(token) => body.push(token),
+                None => {
+                    return Err(Error::from(AssemblerError::UnterminatedMacro {
+                        name: name.clone(),
+                        line,
+                    }))
+                }
+            }
+        }
+        self.macros.insert(
+            name.clone(),
+            Macro {
+                name: name.clone(),
+                parameters,
+                body,
+            },
+        );
+        Ok(())
+    }
+
+    // The expansion is parsed on its own, with the lines inside the macro so errors can point
+    // at both. Its statements take the line of the invocation, which is what listings show
+    fn expand_macro(&mut self, name: &LabelExpression, line: usize) -> Result<(), Error> {
+        let arguments = self.parse_macro_arguments(line);
+        let definition = self.macros[name].clone();
+        if arguments.len() != definition.parameters.len() {
+            return Err(Error::from(AssemblerError::MacroArgumentCount {
+                name: name.clone(),
+                expected: definition.parameters.len(),
+                got: arguments.len(),
+                line,
+            }));
+        }
+        if self.depth == MAX_MACRO_DEPTH {
+            return Err(Error::from(AssemblerError::MacroRecursion {
+                name: name.clone(),
+                line,
+            }));
+        }
+        self.expansions += 1;
+        let mut parser = Parser {
+            source: definition
+                .expand(&arguments, self.expansions)
+                .into_iter()
+                .peekable(),
+            expressions: Vec::new(),
+            macros: mem::take(&mut self.macros),
+            expansions: self.expansions,
+            depth: self.depth + 1,
+            radix: self.radix,
+        };
+        let result = parser.parse_source();
+        self.macros = parser.macros;
+        self.expansions = parser.expansions;
+        match result.map_err(|error| error.downcast::<AssemblerError>()) {
+            Ok(()) => {
+                let statements = parser.expressions.into_iter();
+                self.expressions
+                    .extend(statements.map(|statement| statement.at_line(line)));
+                Ok(())
+            }
+            // Only the innermost invocation is worth reporting when it never ends
+            Err(Ok(error @ AssemblerError::MacroRecursion { .. })) => Err(Error::from(error)),
+            Err(Ok(error)) => Err(Error::from(AssemblerError::InMacro {
+                name: name.clone(),
+                line,
+                error: Box::new(error),
+            })),
+            Err(Err(error)) => Err(error),
+        }
+    }
+
+    // Comma separated token lists up to the end of the line, commas between parentheses are
+    // part of the argument
+    fn parse_macro_arguments(&mut self, line: usize) -> Vec<Vec<AssemblerToken>> {
+        let mut arguments = Vec::new();
+        let mut argument = Vec::new();
+        let mut nesting = 0;
+        while let Some(token) = self.source.peek().cloned() {
+            if token.line != line || token.token_type == AssemblerTokenType::Bang {
+                break;
+            }
+            self.source.next();
+            match token.token_type {
+                AssemblerTokenType::Comma if nesting == 0 => {
+                    arguments.push(mem::take(&mut argument));
+                    continue;
+                }
+                AssemblerTokenType::LeftParen => nesting += 1,
+                AssemblerTokenType::RightParen => nesting -= 1,
+                _ => {}
+            }
+            argument.push(token);
+        }
+        if !argument.is_empty() || !arguments.is_empty() {
+            arguments.push(argument);
+        }
+        arguments
+    }
+}
+
+// BC, DE and HL lex as labels, so they can still name one. Where an instruction takes a register
+// pair they can only be the pair
+fn register_pair_operand(
+    instruction: &InstructionCode,
+    operand: AssemblerTokenType,
+) -> AssemblerTokenType {
+    match (instruction, operand) {
+        (
+            InstructionCode::Lxi
+            | InstructionCode::Stax
+            | InstructionCode::Inx
+            | InstructionCode::Dad
+            | InstructionCode::Ldax
+            | InstructionCode::Dcx
+            | InstructionCode::Pop
+            | InstructionCode::Push,
+            AssemblerTokenType::LabelToken(ref label),
+        ) if ["BC", "DE", "HL"].contains(&label.0.as_str()) => {
+            // Safe because every pair name parses
+            AssemblerTokenType::DataStore(Location::from_str(&label.0).unwrap())
+        }
+        (_, operand) => operand,
     }
 }
@@ -0,0 +1,71 @@
+use super::AssemblerError;
+use failure::Error;
+use std::str::FromStr;
+
+// How every number the assembler writes looks, in listings, maps and diagnostics
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Radix {
+    #[default]
+    Hexadecimal,
+    Decimal,
+    Octal,
+}
+
+impl FromStr for Radix {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Radix, Error> {
+        match s {
+            "hex" => Ok(Radix::Hexadecimal),
+            "dec" => Ok(Radix::Decimal),
+            "oct" => Ok(Radix::Octal),
+            _ => Err(Error::from(AssemblerError::InvalidRadix {
+                radix: String::from(s),
+            })),
+        }
+    }
+}
+
+impl Radix {
+    // Padded to the widest address, so listing and map columns line up
+    pub fn address(self, value: u16) -> String {
+        match self {
+            Radix::Hexadecimal => format!("{:04X}", value),
+            Radix::Decimal => format!("{:05}", value),
+            Radix::Octal => format!("{:06o}", value),
+        }
+    }
+
+    pub fn byte(self, value: u8) -> String {
+        match self {
+            Radix::Hexadecimal => format!("{:02X}", value),
+            Radix::Decimal => format!("{:03}", value),
+            Radix::Octal => format!("{:03o}", value),
+        }
+    }
+
+    // Written like a literal in the source, so 0FFH reads back as 0FFH
+    pub fn number(self, value: u16) -> String {
+        match self {
+            Radix::Hexadecimal => {
+                let digits = format!("{:X}H", value);
+                if digits.starts_with(char::is_numeric) {
+                    digits
+                } else {
+                    format!("0{}", digits)
+                }
+            }
+            Radix::Decimal => format!("{}", value),
+            Radix::Octal => format!("{:o}Q", value),
+        }
+    }
+
+    pub fn parse_address(self, text: &str) -> Option<u16> {
+        let radix = match self {
+            Radix::Hexadecimal => 16,
+            Radix::Decimal => 10,
+            Radix::Octal => 8,
+        };
+        u16::from_str_radix(text, radix).ok()
+    }
+}
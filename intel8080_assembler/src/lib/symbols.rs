@@ -0,0 +1,111 @@
+use super::{AssemblerError, Radix};
+use failure::Error;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum SymbolKind {
+    Label,
+    Equ,
+}
+
+impl fmt::Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolKind::Label => write!(f, "label"),
+            SymbolKind::Equ => write!(f, "equ"),
+        }
+    }
+}
+
+impl FromStr for SymbolKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<SymbolKind, ()> {
+        match s {
+            "label" => Ok(SymbolKind::Label),
+            "equ" => Ok(SymbolKind::Equ),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Symbol {
+    pub address: u16,
+    pub name: String,
+    pub kind: SymbolKind,
+}
+
+// One "address name kind" line per symbol, with the address in hexadecimal
+pub fn write_map(symbols: &[Symbol]) -> String {
+    write_map_with_radix(symbols, Radix::default())
+}
+
+pub fn write_map_with_radix(symbols: &[Symbol], radix: Radix) -> String {
+    let mut sorted = symbols.to_vec();
+    sorted.sort();
+    sorted
+        .iter()
+        .map(|s| format!("{} {} {}\n", radix.address(s.address), s.name, s.kind))
+        .collect()
+}
+
+pub fn read_map(source: &str) -> Result<Vec<Symbol>, Error> {
+    read_map_with_radix(source, Radix::default())
+}
+
+pub fn read_map_with_radix(source: &str, radix: Radix) -> Result<Vec<Symbol>, Error> {
+    let mut symbols = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = || Error::from(AssemblerError::InvalidMapEntry { line: index + 1 });
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 {
+            return Err(invalid());
+        }
+        symbols.push(Symbol {
+            address: radix.parse_address(fields[0]).ok_or_else(invalid)?,
+            name: String::from(fields[1]),
+            kind: fields[2].parse().map_err(|_| invalid())?,
+        });
+    }
+    Ok(symbols)
+}
+
+pub struct SymbolTable {
+    names: BTreeMap<u16, Vec<String>>,
+    labels: BTreeMap<u16, Vec<String>>,
+}
+
+impl SymbolTable {
+    pub fn new(symbols: &[Symbol]) -> SymbolTable {
+        let mut names: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+        let mut labels: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+        let mut sorted = symbols.to_vec();
+        sorted.sort();
+        for symbol in sorted {
+            if symbol.kind == SymbolKind::Label {
+                labels
+                    .entry(symbol.address)
+                    .or_default()
+                    .push(symbol.name.clone());
+            }
+            names.entry(symbol.address).or_default().push(symbol.name);
+        }
+        SymbolTable { names, labels }
+    }
+
+    // Every symbol at the address, so collisions show up as name1/name2
+    pub fn name(&self, address: u16) -> Option<String> {
+        self.names.get(&address).map(|names| names.join("/"))
+    }
+
+    // Only labels mark the start of a line, equ values are rarely code addresses
+    pub fn label(&self, address: u16) -> Option<String> {
+        self.labels.get(&address).map(|names| names.join("/"))
+    }
+}
@@ -0,0 +1,123 @@
+extern crate failure;
+extern crate intel8080cpu;
+
+use super::*;
+use failure::Error;
+use intel8080cpu::{
+    Cpu, Intel8080Cpu, Location, RegisterType, TerminationCondition, ROM_MEMORY_LIMIT,
+};
+use std::fmt;
+
+const CYCLES_LIMIT: u64 = 10_000_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AssertionResult {
+    pub assertion: Assertion,
+    pub actual: u16,
+    pub passed: bool,
+}
+
+impl fmt::Display for AssertionResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = if self.passed { "PASS" } else { "FAIL" };
+        let description = match &self.assertion.check {
+            AssertionCheck::Flag(flag, expected) => format!(
+                "{}{:?} (got {})",
+                if *expected { "" } else { "NOT " },
+                flag,
+                self.actual != 0
+            ),
+            AssertionCheck::Location(location, expected) => {
+                format!("{} == {:#x} (got {:#x})", location, expected, self.actual)
+            }
+            AssertionCheck::Memory(address, expected) => format!(
+                "MEM({:#x}) == {:#x} (got {:#x})",
+                address, expected, self.actual
+            ),
+        };
+        write!(
+            f,
+            "[line {}] {}: {}",
+            self.assertion.line, status, description
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TestReport {
+    pub cycles: u64,
+    pub halted: bool,
+    pub results: Vec<AssertionResult>,
+}
+
+impl TestReport {
+    pub fn passed(&self) -> bool {
+        self.halted && self.results.iter().all(|r| r.passed)
+    }
+}
+
+pub fn run_test(source: &str) -> Result<TestReport, Error> {
+    let tokens = Lexer::new(source.as_bytes()).scan_tokens()?;
+    let statements = Parser::new(tokens).parse_statements()?;
+    let (memory, assertions) = Assembler::new().assemble_with_assertions(statements)?;
+    let mut rom = [0; ROM_MEMORY_LIMIT];
+    rom.copy_from_slice(&memory[..ROM_MEMORY_LIMIT]);
+    let mut cpu = Intel8080Cpu::new(rom);
+    cpu.memory.copy_from_slice(&memory);
+    cpu.set_termination_condition(TerminationCondition::Any(vec![
+        TerminationCondition::PcPasses(ROM_MEMORY_LIMIT as u16),
+        TerminationCondition::OnHlt,
+    ]));
+    let mut cycles = 0;
+    while !cpu.is_done() && cycles < CYCLES_LIMIT {
+        cycles += u64::from(cpu.execute()?);
+    }
+    let mut results = Vec::with_capacity(assertions.len());
+    for assertion in assertions {
+        let (actual, passed) = evaluate_assertion(&cpu, &assertion)?;
+        results.push(AssertionResult {
+            assertion,
+            actual,
+            passed,
+        });
+    }
+    Ok(TestReport {
+        cycles,
+        halted: cpu.is_stopped(),
+        results,
+    })
+}
+
+fn evaluate_assertion(cpu: &Intel8080Cpu, assertion: &Assertion) -> Result<(u16, bool), Error> {
+    match &assertion.check {
+        AssertionCheck::Flag(flag, expected) => {
+            let flags = cpu.get_flags();
+            let actual = match flag {
+                FlagExpression::AuxiliaryCarry => flags.auxiliary_carry,
+                FlagExpression::Carry => flags.carry,
+                FlagExpression::Parity => flags.parity,
+                FlagExpression::Sign => flags.sign,
+                FlagExpression::Zero => flags.zero,
+            };
+            Ok((u16::from(actual), actual == *expected))
+        }
+        AssertionCheck::Location(location, expected) => {
+            let actual = match location {
+                Location::Register {
+                    register: RegisterType::Sp,
+                } => cpu.get_sp(),
+                Location::Register { register } => u16::from(cpu.get_register_value(*register)?),
+                Location::Memory => {
+                    let h = cpu.get_register_value(RegisterType::H)?;
+                    let l = cpu.get_register_value(RegisterType::L)?;
+                    u16::from(cpu.memory[(usize::from(h) << 8) | usize::from(l)])
+                }
+            };
+            Ok((actual, actual == *expected))
+        }
+        AssertionCheck::Memory(address, expected) => {
+            let actual = cpu.memory[*address as usize];
+            Ok((u16::from(actual), actual == *expected))
+        }
+    }
+}
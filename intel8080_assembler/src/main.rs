@@ -1,28 +1,102 @@
 extern crate intel8080_assembler;
 
-use intel8080_assembler::{Assembler, Lexer, Parser};
+use intel8080_assembler::{
+    run_test, write_listing, write_map_with_radix, Assembler, Lexer, Parser, Radix,
+};
 use std::env::args;
-use std::fs::File;
+use std::fs::{read_to_string, File};
 use std::io::Write;
+use std::process::exit;
 
-const USAGE: &str = "Usage: intel8080_assembler [input file] [output file]
+const USAGE: &str = "Usage: intel8080_assembler [input file] [output file] [--map map file]
+                           [--listing listing file] [--radix hex|dec|oct]
+       intel8080_assembler test [input file]
 
-Assemble an intel 8080 asm file.";
+Assemble an intel 8080 asm file, or assemble it, run it and check its ASSERT directives.
+
+With --map, it also writes the address of every label and constant to [map file], which the
+disassembler can read with --symbols. With --listing, it writes every source line next to its
+address and the bytes it assembled to. --radix picks how both write numbers, hex by default.";
+
+#[derive(Default)]
+struct Options {
+    map: Option<String>,
+    listing: Option<String>,
+    radix: Radix,
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(1)
+}
+
+fn parse_options(args: &[String]) -> Options {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(option) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match option.as_str() {
+            "--map" => options.map = Some(value.clone()),
+            "--listing" => options.listing = Some(value.clone()),
+            "--radix" => options.radix = value.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+    options
+}
+
+fn test(file: &str) {
+    let source = read_to_string(file).unwrap();
+    let report = run_test(&source).unwrap();
+    for result in report.results.iter() {
+        println!("{}", result);
+    }
+    if !report.halted {
+        println!("Program didn't halt after {} cycles", report.cycles);
+    }
+    if !report.passed() {
+        exit(1);
+    }
+}
 
 fn main() {
     let args: Vec<String> = args().collect();
-    if args.len() != 3 {
-        panic!(USAGE);
+    if args.len() < 3 {
+        usage();
+    }
+    if args[1] == "test" {
+        if args.len() != 3 {
+            usage();
+        }
+        test(&args[2]);
+        return;
     }
+    let options = parse_options(&args[3..]);
 
-    let f = File::open(&args[1]).unwrap();
-    let lexer = Lexer::new(f);
-    let tokens = lexer.scan_tokens().unwrap();
-    let parser = Parser::new(tokens);
-    let statements = parser.parse_statements().unwrap();
-    let assembler = Assembler::new();
-    let output = assembler.assemble(statements).unwrap();
+    let source = read_to_string(&args[1]).unwrap();
+    let tokens = Lexer::new(source.as_bytes()).scan_tokens().unwrap();
+    let radix = options.radix;
+    let parse = || {
+        Parser::new(tokens.clone())
+            .with_radix(radix)
+            .parse_statements()
+            .unwrap()
+    };
+    let (output, symbols) = Assembler::new().assemble_with_symbols(parse()).unwrap();
 
     let mut output_file = File::create(&args[2]).unwrap();
     output_file.write_all(&output).unwrap();
+    if let Some(map) = options.map {
+        let mut map_file = File::create(map).unwrap();
+        map_file
+            .write_all(write_map_with_radix(&symbols, radix).as_bytes())
+            .unwrap();
+    }
+    if let Some(listing) = options.listing {
+        let (_, entries) = Assembler::new().assemble_with_listing(parse()).unwrap();
+        let mut listing_file = File::create(listing).unwrap();
+        listing_file
+            .write_all(write_listing(&source, &entries, radix).as_bytes())
+            .unwrap();
+    }
 }
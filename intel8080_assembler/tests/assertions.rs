@@ -0,0 +1,37 @@
+extern crate intel8080_assembler;
+
+use intel8080_assembler::run_test;
+use std::fs::{read_dir, read_to_string};
+
+#[test]
+fn it_should_pass_every_assertion_file() {
+    for entry in read_dir("tests/assertions").unwrap() {
+        let path = entry.unwrap().path();
+        let report = run_test(&read_to_string(&path).unwrap()).unwrap();
+        let failures: Vec<String> = report
+            .results
+            .iter()
+            .filter(|r| !r.passed)
+            .map(|r| r.to_string())
+            .collect();
+        assert!(report.halted, "{:?} didn't halt", path);
+        assert!(failures.is_empty(), "{:?}: {:?}", path, failures);
+    }
+}
+
+#[test]
+fn it_should_report_failing_assertions_with_line_numbers() {
+    let report = run_test("MVI A, 1\nHLT\nASSERT A == 2\nASSERT A == 1\n").unwrap();
+    assert!(!report.passed());
+    assert_eq!(report.results.len(), 2);
+    assert!(!report.results[0].passed);
+    assert_eq!(report.results[0].assertion.line, 3);
+    assert_eq!(report.results[0].actual, 1);
+    assert!(report.results[1].passed);
+}
+
+#[test]
+fn it_should_not_emit_bytes_for_assertions() {
+    let report = run_test("ASSERT MEM(0) == 76H\nHLT\n").unwrap();
+    assert!(report.passed());
+}
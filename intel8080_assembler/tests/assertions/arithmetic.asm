@@ -0,0 +1,18 @@
+; Checks ADD, SUB and memory stores against the final CPU state
+        ORG 0
+        MVI A, 40H
+        MVI B, 2
+        ADD B
+        STA 2400H
+        LXI H, 2401H
+        MVI M, 7
+        MVI C, 0FFH
+        INR C
+        HLT
+
+        ASSERT A == 42H
+        ASSERT B == 2
+        ASSERT MEM(2400H) == 42H
+        ASSERT M == 7
+        ASSERT ZERO
+        ASSERT NOT CARRY
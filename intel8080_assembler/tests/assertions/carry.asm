@@ -0,0 +1,9 @@
+; Checks the carry flag after an overflowing addition
+        ORG 0
+        MVI A, 0F0H
+        ADI 20H
+        HLT
+
+        ASSERT A == 10H
+        ASSERT CARRY
+        ASSERT NOT ZERO
@@ -0,0 +1,103 @@
+extern crate failure;
+extern crate intel8080_assembler;
+
+use failure::Error;
+use intel8080_assembler::{Assembler, AssemblerError, Lexer, Parser};
+
+fn assemble(source: &str) -> Result<[u8; 65536], Error> {
+    let tokens = Lexer::new(source.as_bytes()).scan_tokens()?;
+    let statements = Parser::new(tokens).parse_statements()?;
+    Assembler::new().assemble(statements)
+}
+
+fn assembler_error(source: &str) -> AssemblerError {
+    assemble(source)
+        .expect_err("The source shouldn't assemble")
+        .downcast::<AssemblerError>()
+        .unwrap()
+}
+
+#[test]
+fn it_should_resolve_forward_jumps() {
+    let rom = assemble("JMP END\nNOP\nEND:\nHLT\n").unwrap();
+    assert_eq!(rom[..5], [0xc3, 0x04, 0x00, 0x00, 0x76]);
+}
+
+#[test]
+fn it_should_resolve_labels_after_an_org() {
+    let rom = assemble("CALL PRINT\nHLT\nORG 100H\nPRINT:\nRET\n").unwrap();
+    assert_eq!(rom[..4], [0xcd, 0x00, 0x01, 0x76]);
+    assert_eq!(rom[0x100], 0xc9);
+}
+
+#[test]
+fn it_should_report_where_an_undefined_label_is_used() {
+    match assembler_error("NOP\nEND:\nJMP START\nHLT\n") {
+        AssemblerError::LabelNotFound { label, line } => {
+            assert_eq!(format!("{:?}", label), "LabelExpression(\"START\")");
+            assert_eq!(line, 3);
+        }
+        error => panic!("Unexpected {:?}", error),
+    }
+}
+
+#[test]
+fn it_should_reject_duplicate_labels() {
+    match assembler_error("START:\nNOP\nSTART:\nHLT\n") {
+        AssemblerError::DuplicateLabel { line, .. } => assert_eq!(line, 3),
+        error => panic!("Unexpected {:?}", error),
+    }
+    match assembler_error("SIZE DB 2\nSIZE:\nHLT\n") {
+        AssemblerError::DuplicateLabel { line, .. } => assert_eq!(line, 2),
+        error => panic!("Unexpected {:?}", error),
+    }
+}
+
+#[test]
+fn it_should_store_bytes_with_an_unlabeled_db() {
+    let rom = assemble("DB 1, 0FFH\nL_0002:\nRST 1\nJMP L_0002\n").unwrap();
+    assert_eq!(rom[..6], [0x01, 0xff, 0xcf, 0xc3, 0x02, 0x00]);
+}
+
+#[test]
+fn it_should_define_a_label_on_the_same_line_as_an_instruction() {
+    let rom = assemble("MVI B, 2\nLOOP: DCR B\nJNZ LOOP\nHLT\n").unwrap();
+    assert_eq!(rom[..7], [0x06, 0x02, 0x05, 0xc2, 0x02, 0x00, 0x76]);
+}
+
+#[test]
+fn it_should_define_labels_alone_or_before_data() {
+    let rom = assemble("JMP START\nSIZE: DB 2, 3\nSTART:\nLDA SIZE\nHLT\n").unwrap();
+    assert_eq!(
+        rom[..9],
+        [0xc3, 0x05, 0x00, 0x02, 0x03, 0x3a, 0x03, 0x00, 0x76]
+    );
+}
+
+#[test]
+fn it_should_define_several_labels_for_the_same_instruction() {
+    let rom = assemble("JMP SECOND\nFIRST:\nSECOND: JMP FIRST\n").unwrap();
+    assert_eq!(rom[..6], [0xc3, 0x03, 0x00, 0xc3, 0x03, 0x00]);
+}
+
+#[test]
+fn it_should_separate_statements_with_a_bang() {
+    let rom = assemble("LOOP: MVI A, 1 ! ADD A ! JMP LOOP ! HLT\n").unwrap();
+    assert_eq!(rom[..7], [0x3e, 0x01, 0x87, 0xc3, 0x00, 0x00, 0x76]);
+}
+
+#[test]
+fn it_should_store_a_byte_per_character_of_a_string() {
+    let rom = assemble("MSG: DB 'OK$', 0DH, 'A'\nMVI A, 'B'\n").unwrap();
+    assert_eq!(rom[..7], [b'O', b'K', b'$', 0x0d, b'A', 0x3e, b'B']);
+    assert!(assemble("MVI A, 'AB'\n").is_err());
+}
+
+#[test]
+fn it_should_read_pair_names_as_labels_unless_a_register_pair_is_expected() {
+    let rom = assemble("HL: NOP\nJMP HL\nLXI HL, HL\nPUSH BC\nDAD DE\n").unwrap();
+    assert_eq!(
+        rom[..9],
+        [0x00, 0xc3, 0x00, 0x00, 0x21, 0x00, 0x00, 0xc5, 0x19]
+    );
+}
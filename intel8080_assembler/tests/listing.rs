@@ -0,0 +1,154 @@
+extern crate failure;
+extern crate intel8080_assembler;
+
+use intel8080_assembler::{
+    read_map_with_radix, write_listing, write_map_with_radix, Assembler, AssemblerError, Lexer,
+    ListingEntry, Parser, Radix, Symbol,
+};
+
+const PROGRAM: &str = "START:\nMVI A, 0FFH\nJMP START\nORG 10H\nDATA:\nDB 1, 2, 3, 4\n";
+
+fn listing(source: &str) -> Vec<ListingEntry> {
+    let tokens = Lexer::new(source.as_bytes()).scan_tokens().unwrap();
+    let statements = Parser::new(tokens).parse_statements().unwrap();
+    Assembler::new()
+        .assemble_with_listing(statements)
+        .unwrap()
+        .1
+}
+
+fn symbols(source: &str) -> Vec<Symbol> {
+    let tokens = Lexer::new(source.as_bytes()).scan_tokens().unwrap();
+    let statements = Parser::new(tokens).parse_statements().unwrap();
+    Assembler::new()
+        .assemble_with_symbols(statements)
+        .unwrap()
+        .1
+}
+
+fn parse_error(source: &str) -> AssemblerError {
+    parse_error_with_radix(source, Radix::default())
+}
+
+fn parse_error_with_radix(source: &str, radix: Radix) -> AssemblerError {
+    let tokens = Lexer::new(source.as_bytes()).scan_tokens().unwrap();
+    match Parser::new(tokens).with_radix(radix).parse_statements() {
+        Ok(_) => panic!("The source shouldn't parse"),
+        Err(error) => error.downcast::<AssemblerError>().unwrap(),
+    }
+}
+
+#[test]
+fn it_should_list_every_line_with_its_bytes() {
+    assert_eq!(
+        listing(PROGRAM),
+        vec![
+            ListingEntry {
+                line: 2,
+                address: 0,
+                bytes: vec![0x3e, 0xff],
+            },
+            ListingEntry {
+                line: 3,
+                address: 2,
+                bytes: vec![0xc3, 0x00, 0x00],
+            },
+            ListingEntry {
+                line: 6,
+                address: 0x10,
+                bytes: vec![1, 2, 3, 4],
+            },
+        ]
+    );
+}
+
+#[test]
+fn it_should_write_the_listing_in_hexadecimal() {
+    assert_eq!(
+        write_listing(PROGRAM, &listing(PROGRAM), Radix::Hexadecimal),
+        "              START:\n\
+         0000 3E FF    MVI A, 0FFH\n\
+         0002 C3 00 00 JMP START\n\
+         \x20             ORG 10H\n\
+         \x20             DATA:\n\
+         0010 01 02 03 DB 1, 2, 3, 4\n\
+         0013 04\n"
+    );
+}
+
+#[test]
+fn it_should_write_the_listing_in_decimal() {
+    assert_eq!(
+        write_listing(PROGRAM, &listing(PROGRAM), Radix::Decimal),
+        "                  START:\n\
+         00000 062 255     MVI A, 0FFH\n\
+         00002 195 000 000 JMP START\n\
+         \x20                 ORG 10H\n\
+         \x20                 DATA:\n\
+         00016 001 002 003 DB 1, 2, 3, 4\n\
+         00019 004\n"
+    );
+}
+
+#[test]
+fn it_should_write_the_listing_in_octal() {
+    assert_eq!(
+        write_listing(PROGRAM, &listing(PROGRAM), Radix::Octal),
+        "                   START:\n\
+         000000 076 377     MVI A, 0FFH\n\
+         000002 303 000 000 JMP START\n\
+         \x20                  ORG 10H\n\
+         \x20                  DATA:\n\
+         000020 001 002 003 DB 1, 2, 3, 4\n\
+         000023 004\n"
+    );
+}
+
+#[test]
+fn it_should_write_and_read_maps_in_any_radix() {
+    let symbols = symbols(PROGRAM);
+    let map = write_map_with_radix(&symbols, Radix::Octal);
+    assert_eq!(map, "000000 START label\n000020 DATA label\n");
+    assert_eq!(read_map_with_radix(&map, Radix::Octal).unwrap(), symbols);
+    let map = write_map_with_radix(&symbols, Radix::Decimal);
+    assert_eq!(map, "00000 START label\n00016 DATA label\n");
+    assert_eq!(read_map_with_radix(&map, Radix::Decimal).unwrap(), symbols);
+}
+
+#[test]
+fn it_should_format_numbers_like_the_source() {
+    assert_eq!(Radix::Hexadecimal.number(0xff), "0FFH");
+    assert_eq!(Radix::Hexadecimal.number(0x10), "10H");
+    assert_eq!(Radix::Decimal.number(0xff), "255");
+    assert_eq!(Radix::Octal.number(0xff), "377Q");
+    assert_eq!("oct".parse::<Radix>().unwrap(), Radix::Octal);
+    assert!("bin".parse::<Radix>().is_err());
+}
+
+#[test]
+fn it_should_show_numbers_in_errors_as_written() {
+    assert_eq!(
+        parse_error("ORG 10H\nSTA A, 0FFH\n").to_string(),
+        "Expecting number, got A at line 2"
+    );
+    assert_eq!(
+        parse_error("ORG ORG 0FFH\n").to_string(),
+        "Expecting number, got ORG at line 1"
+    );
+    assert_eq!(
+        parse_error("MOV A 0FFH\n").to_string(),
+        "Expecting ,, got 0FFH ar line 1"
+    );
+}
+
+#[test]
+fn it_should_show_numbers_in_errors_in_the_chosen_radix() {
+    assert_eq!(
+        parse_error_with_radix("MOV A 0FFH\n", Radix::Decimal).to_string(),
+        "Expecting ,, got 255 ar line 1"
+    );
+    assert_eq!(
+        parse_error_with_radix("MOV A 0FFH\n", Radix::Octal).to_string(),
+        "Expecting ,, got 377Q ar line 1"
+    );
+}
@@ -0,0 +1,141 @@
+extern crate failure;
+extern crate intel8080_assembler;
+
+use failure::Error;
+use intel8080_assembler::{Assembler, AssemblerError, Lexer, Parser, Symbol};
+
+fn assemble(source: &str) -> Result<([u8; 65536], Vec<Symbol>), Error> {
+    let tokens = Lexer::new(source.as_bytes()).scan_tokens()?;
+    let statements = Parser::new(tokens).parse_statements()?;
+    Assembler::new().assemble_with_symbols(statements)
+}
+
+fn assembler_error(source: &str) -> AssemblerError {
+    assemble(source)
+        .expect_err("The source shouldn't assemble")
+        .downcast::<AssemblerError>()
+        .unwrap()
+}
+
+const DELAY: &str = "DELAY MACRO count
+MVI B, count
+LOOP: DCR B
+JNZ LOOP
+ENDM
+";
+
+#[test]
+fn it_should_expand_a_macro_every_time_it_is_used() {
+    let source = format!("{}DELAY 2\nDELAY 3\nHLT\n", DELAY);
+    let (rom, symbols) = assemble(&source).unwrap();
+    assert_eq!(
+        rom[..13],
+        [
+            0x06, 0x02, 0x05, 0xc2, 0x02, 0x00, // First expansion
+            0x06, 0x03, 0x05, 0xc2, 0x08, 0x00, // Second expansion
+            0x76,
+        ]
+    );
+    let labels: Vec<(&str, u16)> = symbols
+        .iter()
+        .map(|symbol| (symbol.name.as_str(), symbol.address))
+        .collect();
+    assert_eq!(labels, [("LOOP.DELAY.1", 0x02), ("LOOP.DELAY.2", 0x08)]);
+}
+
+#[test]
+fn it_should_keep_the_labels_of_a_macro_apart_from_the_source_ones() {
+    let source = format!("{}DELAY 2\nJMP LOOP_DELAY_1\nLOOP_DELAY_1: HLT\n", DELAY);
+    let (rom, symbols) = assemble(&source).unwrap();
+    assert_eq!(
+        rom[..10],
+        [0x06, 0x02, 0x05, 0xc2, 0x02, 0x00, 0xc3, 0x09, 0x00, 0x76]
+    );
+    assert_eq!(symbols.len(), 2);
+}
+
+#[test]
+fn it_should_substitute_expressions_and_registers_as_arguments() {
+    let source = "LOAD MACRO reg, value\nMVI reg, value\nENDM\nLOAD C, (1 + 2) * 4\nHLT\n";
+    let (rom, _) = assemble(source).unwrap();
+    assert_eq!(rom[..3], [0x0e, 0x0c, 0x76]);
+}
+
+#[test]
+fn it_should_take_registers_as_parameter_names() {
+    let source = "MOVE MACRO A, B\nMOV A, B\nENDM\nMOVE C, D\nHLT\n";
+    let (rom, _) = assemble(source).unwrap();
+    assert_eq!(rom[..2], [0x4a, 0x76]);
+}
+
+#[test]
+fn it_should_expand_macros_used_inside_macros() {
+    let source = format!(
+        "{}TWICE MACRO count\nDELAY count\nDELAY count + 1\nENDM\nTWICE 4\nHLT\n",
+        DELAY
+    );
+    let (rom, _) = assemble(&source).unwrap();
+    assert_eq!(
+        rom[..13],
+        [0x06, 0x04, 0x05, 0xc2, 0x02, 0x00, 0x06, 0x05, 0x05, 0xc2, 0x08, 0x00, 0x76]
+    );
+}
+
+#[test]
+fn it_should_stop_macros_that_expand_forever() {
+    match assembler_error("FOREVER MACRO\nNOP\nFOREVER\nENDM\nFOREVER\n") {
+        AssemblerError::MacroRecursion { line, .. } => assert_eq!(line, 3),
+        error => panic!("Unexpected {:?}", error),
+    }
+}
+
+#[test]
+fn it_should_report_the_invocation_and_the_macro_line_of_an_error() {
+    let source = format!("{}NOP\nDELAY\n", DELAY);
+    match assembler_error(&source) {
+        AssemblerError::MacroArgumentCount {
+            expected,
+            got,
+            line,
+            ..
+        } => assert_eq!((expected, got, line), (1, 0, 7)),
+        error => panic!("Unexpected {:?}", error),
+    }
+    let error = assembler_error("BAD MACRO\nNOP\nADD 1\nENDM\nNOP\nBAD\n");
+    assert_eq!(
+        error.to_string(),
+        "Invalid argument for instruction at line 3 in macro LabelExpression(\"BAD\") invoked \
+         at line 6"
+    );
+    match error {
+        AssemblerError::InMacro { line, error, .. } => {
+            assert_eq!(line, 6);
+            match *error {
+                AssemblerError::InvalidInstructionArgument { line } => assert_eq!(line, 3),
+                error => panic!("Unexpected {:?}", error),
+            }
+        }
+        error => panic!("Unexpected {:?}", error),
+    }
+}
+
+#[test]
+fn it_should_reject_a_macro_without_endm() {
+    match assembler_error("EMPTY MACRO\nNOP\n") {
+        AssemblerError::UnterminatedMacro { line, .. } => assert_eq!(line, 1),
+        error => panic!("Unexpected {:?}", error),
+    }
+}
+
+#[test]
+fn it_should_reject_a_macro_declared_inside_another() {
+    let error = assembler_error("OUTER MACRO\nINNER MACRO\nNOP\nENDM\nENDM\n");
+    assert_eq!(
+        error.to_string(),
+        "Macro LabelExpression(\"OUTER\") declares another macro at line 2"
+    );
+    match error {
+        AssemblerError::NestedMacro { line, .. } => assert_eq!(line, 2),
+        error => panic!("Unexpected {:?}", error),
+    }
+}
@@ -0,0 +1,67 @@
+extern crate intel8080_assembler;
+extern crate intel8080cpu;
+
+use intel8080_assembler::{Assembler, Lexer, Parser};
+use intel8080cpu::{disassemble, Cpu, Intel8080Cpu, Intel8080Instruction, Printer};
+use std::fs::read_to_string;
+use std::path::Path;
+
+const CP_M_START: u16 = 0x100;
+const STEPS_LIMIT: usize = 100_000;
+
+struct Screen {
+    output: String,
+}
+
+impl Printer for Screen {
+    fn print(&mut self, bytes: &[u8]) {
+        self.output.push_str(&String::from_utf8_lossy(bytes));
+    }
+}
+
+// The whole address space, and where the last byte the program emitted is
+fn assemble_example(name: &str) -> ([u8; 65536], usize) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("examples")
+        .join(name);
+    let source = read_to_string(&path).unwrap();
+    let tokens = Lexer::new(source.as_bytes()).scan_tokens().unwrap();
+    let statements = Parser::new(tokens).parse_statements().unwrap();
+    let (memory, listing) = Assembler::new().assemble_with_listing(statements).unwrap();
+    let end = listing
+        .iter()
+        .map(|entry| entry.address as usize + entry.bytes.len())
+        .max()
+        .unwrap();
+    (memory, end)
+}
+
+#[test]
+fn it_should_assemble_run_and_disassemble_the_memory_test() {
+    let (memory, end) = assemble_example("memtest.asm");
+    let program = &memory[CP_M_START as usize..end];
+
+    let screen = &mut Screen {
+        output: String::new(),
+    };
+    {
+        let mut cpu = Intel8080Cpu::with_program(program, CP_M_START);
+        cpu.set_cp_m_compatibility(true);
+        cpu.add_listener(screen);
+        let mut steps = 0;
+        while !cpu.is_done() && steps < STEPS_LIMIT {
+            cpu.execute().unwrap();
+            steps += 1;
+        }
+        assert!(cpu.is_done());
+    }
+    assert_eq!(screen.output, "PASS");
+
+    let disassembly: Vec<String> = disassemble::<Intel8080Instruction>(program)
+        .iter()
+        .map(|(address, instruction)| format!("{:04x} {}", address + CP_M_START, instruction))
+        .collect();
+    assert_eq!(disassembly[0], "0100 LXI SP,#$f000");
+    assert!(disassembly.contains(&String::from("0120 MVI C,#$09")));
+    assert!(disassembly.contains(&String::from("0122 CALL $0005")));
+}
@@ -0,0 +1,55 @@
+extern crate intel8080_assembler;
+extern crate intel8080cpu;
+
+use intel8080_assembler::{
+    read_map, write_map, Assembler, Lexer, Parser, Symbol, SymbolKind, SymbolTable,
+};
+use intel8080cpu::Intel8080Instruction;
+
+fn assemble(source: &str) -> ([u8; 65536], Vec<Symbol>) {
+    let tokens = Lexer::new(source.as_bytes()).scan_tokens().unwrap();
+    let statements = Parser::new(tokens).parse_statements().unwrap();
+    Assembler::new().assemble_with_symbols(statements).unwrap()
+}
+
+fn disassemble_at(rom: &[u8], address: usize, symbols: &SymbolTable) -> String {
+    let instruction = Intel8080Instruction::from(&rom[address..address + 3]);
+    instruction.to_string_with_symbols(|address| symbols.name(address))
+}
+
+#[test]
+fn it_should_write_a_sorted_map() {
+    let (_, symbols) = assemble("SIZE DB 2\nCALL PRINT\nHLT\nPRINT:\nMVI A, 1\nRET\n");
+    let map = write_map(&symbols);
+    assert_eq!(map, "0002 SIZE equ\n0004 PRINT label\n");
+    assert_eq!(read_map(&map).unwrap(), symbols);
+    assert_eq!(symbols[1].kind, SymbolKind::Label);
+}
+
+#[test]
+fn it_should_show_the_subroutine_name_when_disassembling_a_call() {
+    let (rom, symbols) = assemble("CALL PRINT\nHLT\nPRINT:\nMVI A, 1\nRET\n");
+    let map = write_map(&symbols);
+    let symbols = SymbolTable::new(&read_map(&map).unwrap());
+    assert_eq!(disassemble_at(&rom, 0, &symbols), "CALL PRINT");
+    assert_eq!(symbols.label(4), Some(String::from("PRINT")));
+    assert_eq!(symbols.label(0), None);
+    assert_eq!(
+        disassemble_at(&rom, 0, &SymbolTable::new(&[])),
+        "CALL $0004"
+    );
+}
+
+#[test]
+fn it_should_join_the_names_of_symbols_at_the_same_address() {
+    let (rom, symbols) = assemble("JMP SHOW\nPRINT:\nSHOW:\nHLT\n");
+    let symbols = SymbolTable::new(&symbols);
+    assert_eq!(disassemble_at(&rom, 0, &symbols), "JMP PRINT/SHOW");
+}
+
+#[test]
+fn it_should_reject_malformed_map_entries() {
+    assert!(read_map("0004 PRINT\n").is_err());
+    assert!(read_map("zzzz PRINT label\n").is_err());
+    assert!(read_map("0004 PRINT public\n").is_err());
+}
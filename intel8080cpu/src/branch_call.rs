@@ -1,9 +1,10 @@
 use alloc::vec::Vec;
+use super::cpu::Memory;
 use super::CpuError;
 use helpers::{two_bytes_to_word, word_to_address};
-use intel8080cpu::{Intel8080Cpu, RegisterType, State};
+use intel8080cpu::{EmulationListener, Intel8080Cpu, RegisterType, State, TerminationReason};
 
-impl<'a> Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
     pub(crate) fn execute_rst(&mut self, value: u8) {
         if self.interruptions_enabled {
             let low_byte = (value & 0x07) << 3;
@@ -19,6 +20,7 @@ impl<'a> Intel8080Cpu<'a> {
             self.handle_cp_m_print()?;
         } else if self.cp_m_compatibility && address == 0 {
             self.state = State::Halted;
+            self.notify_listeners(|l| l.on_terminate(TerminationReason::WarmBoot));
         } else {
             self.perform_call(high_byte, low_byte);
         }
@@ -81,16 +83,15 @@ impl<'a> Intel8080Cpu<'a> {
 
     #[inline]
     fn push_program_counter_to_stack(&mut self) {
-        let sp = self.get_current_sp_value() as usize;
         let address = word_to_address(self.pc);
-        self.memory[sp - 1] = address[1];
-        self.memory[sp - 2] = address[0];
-        self.save_to_sp((sp - 2) as u16);
+        self.push_to_stack(address[1], address[0]);
     }
 
     #[inline]
     fn handle_cp_m_print(&mut self) -> Result<(), CpuError> {
         let c_value = self.get_current_single_register_value(RegisterType::C)?;
+        let de_value = self.get_current_de_value();
+        self.notify_listeners(|l| l.on_bdos_call(c_value, de_value));
         if c_value == 9 {
             self.print_de_to_screen();
         } else if c_value == 2 {
@@ -102,27 +103,23 @@ impl<'a> Intel8080Cpu<'a> {
     #[inline]
     fn print_e_value_to_screen(&mut self) -> Result<(), CpuError> {
         let e_value = self.get_current_single_register_value(RegisterType::E)?;
-        self.print_message(&[b'E', b' ', e_value]);
+        self.print_message(&[e_value]);
         Ok(())
     }
 
     #[inline]
     fn print_de_to_screen(&mut self) {
-        let mut address = (self.get_current_de_value() + 3) as usize; // Skip prefix
-        let mut bytes: Vec<u8> = Vec::new();
-        while (self.memory[address] as char) != '$' {
-            bytes.push(self.memory[address]);
-            address += 1;
-        }
+        let address = self.get_current_de_value();
+        let bytes: Vec<u8> = (address..=0xffff)
+            .map(|address| self.read_memory(address))
+            .take_while(|byte| *byte != b'$')
+            .collect();
         self.print_message(bytes.as_ref());
     }
 
     #[inline]
     fn print_message(&mut self, bytes: &[u8]) {
-        match self.printer {
-            Some(ref mut screen) => screen.print(bytes),
-            _ => panic!("Screen not configured while in CP/M compatibility mode."),
-        }
+        self.notify_listeners(|l| l.on_output(bytes));
     }
 }
 
@@ -130,7 +127,12 @@ impl<'a> Intel8080Cpu<'a> {
 mod tests {
     use super::super::cpu::Cpu;
     use instruction::Intel8080Instruction;
-    use intel8080cpu::{Intel8080Cpu, Printer, RegisterType, State, ROM_MEMORY_LIMIT};
+    use intel8080cpu::{
+        EmulationListener, Intel8080Cpu, Printer, RegisterType, State, TerminationReason,
+        ROM_MEMORY_LIMIT,
+    };
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     #[test]
     fn it_should_execute_call() {
@@ -147,6 +149,25 @@ mod tests {
         assert_eq!(cpu.memory[1], 0x2c);
     }
 
+    #[test]
+    fn it_should_wrap_call_around_the_bottom_of_memory() {
+        for &(sp, high_address, low_address) in
+            [(0x0000, 0xffff, 0xfffe), (0x0001, 0x0000, 0xffff)].iter()
+        {
+            let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+            cpu.save_to_sp(sp);
+            cpu.pc = 0x2c03;
+            cpu.execute_instruction(&Intel8080Instruction::Call {
+                address: [0x00, 0x3c],
+            })
+            .unwrap();
+            assert_eq!(cpu.pc, 0x3c00);
+            assert_eq!(cpu.get_current_sp_value(), sp.wrapping_sub(2));
+            assert_eq!(cpu.memory[high_address], 0x2c);
+            assert_eq!(cpu.memory[low_address], 0x03);
+        }
+    }
+
     #[test]
     fn it_should_print_when_executing_call_to_5_while_in_cp_m_compatibility_mode() {
         struct FakePrinter {
@@ -165,10 +186,10 @@ mod tests {
             cpu.pc = 0x2c03;
             cpu.save_to_single_register(9, RegisterType::C).unwrap();
             cpu.save_to_single_register(0, RegisterType::D).unwrap();
-            cpu.save_to_single_register(0, RegisterType::E).unwrap();
-            cpu.memory[3] = '4' as u8;
-            cpu.memory[4] = '2' as u8;
-            cpu.memory[5] = '$' as u8;
+            cpu.save_to_single_register(3, RegisterType::E).unwrap();
+            cpu.memory[3] = b'4';
+            cpu.memory[4] = b'2';
+            cpu.memory[5] = b'$';
             cpu.execute_instruction(&Intel8080Instruction::Call {
                 address: [0x05, 0x00],
             })
@@ -178,6 +199,131 @@ mod tests {
         assert_eq!(screen.res, "42");
     }
 
+    #[test]
+    fn it_should_print_to_a_printer_that_cant_leave_the_thread() {
+        struct SharedPrinter {
+            res: Rc<RefCell<String>>,
+        }
+        impl Printer for SharedPrinter {
+            fn print(&mut self, bytes: &[u8]) {
+                self.res
+                    .borrow_mut()
+                    .push_str(&String::from_utf8_lossy(bytes));
+            }
+        }
+        let res = Rc::new(RefCell::new(String::new()));
+        let screen = &mut SharedPrinter { res: res.clone() };
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..7].copy_from_slice(&[
+            0x0e, 0x02, // MVI C, 2
+            0x1e, b'!', // MVI E, '!'
+            0xcd, 0x05, 0x00, // CALL 5
+        ]);
+        let mut cpu = Intel8080Cpu::new_cp_m_compatible(rom, screen);
+        for _ in 0..3 {
+            cpu.execute().unwrap();
+        }
+        assert_eq!(*res.borrow(), "!");
+    }
+
+    #[test]
+    fn it_should_notify_listeners_while_running_a_cp_m_program() {
+        #[derive(Debug, PartialEq)]
+        enum Event {
+            Bdos(u8, u16),
+            Output(Vec<u8>),
+            Terminate(TerminationReason),
+            IllegalOpcode(u16, u8),
+        }
+        struct RecordingListener {
+            events: Vec<Event>,
+        }
+        impl EmulationListener for RecordingListener {
+            fn on_bdos_call(&mut self, function: u8, de: u16) {
+                self.events.push(Event::Bdos(function, de));
+            }
+            fn on_output(&mut self, bytes: &[u8]) {
+                self.events.push(Event::Output(bytes.to_vec()));
+            }
+            fn on_terminate(&mut self, reason: TerminationReason) {
+                self.events.push(Event::Terminate(reason));
+            }
+            fn on_illegal_opcode(&mut self, pc: u16, opcode: u8) {
+                self.events.push(Event::IllegalOpcode(pc, opcode));
+            }
+        }
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        let program = [
+            0x0e, 0x09, // MVI C, 9
+            0x11, 0x20, 0x00, // LXI D, 0020H
+            0xcd, 0x05, 0x00, // CALL 5
+            0x0e, 0x02, // MVI C, 2
+            0x1e, b'X', // MVI E, 'X'
+            0xcd, 0x05, 0x00, // CALL 5
+            0x08, // Undocumented NOP
+            0xcd, 0x00, 0x00, // CALL 0
+        ];
+        rom[..program.len()].copy_from_slice(&program);
+        rom[0x20..0x23].copy_from_slice(b"OK$");
+        let first = &mut RecordingListener { events: vec![] };
+        let second = &mut RecordingListener { events: vec![] };
+        {
+            let mut cpu = Intel8080Cpu::new_cp_m_compatible(rom, first);
+            cpu.add_listener(second);
+            while !cpu.is_done() {
+                cpu.execute().unwrap();
+            }
+        }
+        let expected = vec![
+            Event::Bdos(9, 0x20),
+            Event::Output(b"OK".to_vec()),
+            Event::Bdos(2, u16::from(b'X')),
+            Event::Output(vec![b'X']),
+            Event::IllegalOpcode(0x0f, 0x08),
+            Event::Terminate(TerminationReason::WarmBoot),
+        ];
+        assert_eq!(first.events, expected);
+        assert_eq!(second.events, expected);
+    }
+
+    #[test]
+    fn it_should_print_from_a_cp_m_program_loaded_at_0x100() {
+        struct FakePrinter {
+            res: String,
+        }
+        impl Printer for FakePrinter {
+            fn print(&mut self, bytes: &[u8]) {
+                self.res.push_str(&String::from_utf8_lossy(bytes));
+            }
+        }
+        let program = [
+            0x31, 0x00, 0xf0, // LXI SP, F000H
+            0x11, 0x19, 0x01, // LXI D, MSG
+            0xcd, 0x13, 0x01, // CALL PRINT
+            0x0e, 0x02, // MVI C, 2
+            0x1e, b'!', // MVI E, '!'
+            0xcd, 0x05, 0x00, // CALL 5
+            0xcd, 0x00, 0x00, // CALL 0
+            0x0e, 0x09, // PRINT: MVI C, 9
+            0xcd, 0x05, 0x00, // CALL 5
+            0xc9, // RET
+            b'H', b'E', b'L', b'L', b'O', b'$', // MSG: DB 'HELLO$'
+        ];
+        let screen = &mut (FakePrinter {
+            res: "".to_string(),
+        });
+        {
+            let mut cpu = Intel8080Cpu::with_program(&program, 0x100);
+            cpu.set_cp_m_compatibility(true);
+            cpu.add_listener(screen);
+            while !cpu.is_done() {
+                cpu.execute().unwrap();
+            }
+            assert_eq!(cpu.get_current_sp_value(), 0xf000);
+        }
+        assert_eq!(screen.res, "HELLO!");
+    }
+
     #[test]
     fn it_should_execute_cc_if_carry_is_set() {
         let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
@@ -477,4 +623,36 @@ mod tests {
         assert_eq!(cpu.memory[0], 0x03);
         assert_eq!(cpu.memory[1], 0x2c);
     }
+
+    #[test]
+    fn it_should_return_after_the_call_and_restore_the_stack_pointer() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..12].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP, 2400H
+            0xcd, 0x0a, 0x00, // CALL 000AH
+            0x3e, 0x42, // MVI A, 42H
+            0x76, // HLT
+            0x00, // NOP
+            0x04, // INR B
+            0xc9, // RET
+        ]);
+        let mut cpu = Intel8080Cpu::new(rom);
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x0a);
+        assert_eq!(cpu.get_current_sp_value(), 0x23fe);
+        assert_eq!(cpu.memory[0x23fe], 0x06);
+        assert_eq!(cpu.memory[0x23ff], 0x00);
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x06);
+        assert_eq!(cpu.get_current_sp_value(), 0x2400);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x42);
+        assert_eq!(
+            cpu.get_current_single_register_value(RegisterType::B)
+                .unwrap(),
+            1
+        );
+    }
 }
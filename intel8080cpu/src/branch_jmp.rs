@@ -1,7 +1,8 @@
+use super::cpu::Memory;
 use helpers::two_bytes_to_word;
-use intel8080cpu::{Intel8080Cpu, State};
+use intel8080cpu::{EmulationListener, Intel8080Cpu, State};
 
-impl<'a> Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
     pub(crate) fn execute_pchl(&mut self) {
         let new_pc = self.get_current_hl_value();
         self.pc = new_pc;
@@ -264,4 +265,30 @@ mod tests {
         .unwrap();
         assert_eq!(cpu.pc, 0);
     }
+
+    fn run_countdown(start: u8) -> Intel8080Cpu<'static> {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..6].copy_from_slice(&[
+            0x3e, start, // MVI A, start
+            0x3d, // DCR A
+            0xc2, 0x00, 0x10, // JNZ 1000H
+        ]);
+        let mut cpu = Intel8080Cpu::new(rom);
+        for _ in 0..3 {
+            cpu.execute().unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn it_should_take_jnz_from_rom_when_the_result_isnt_zero() {
+        let cpu = run_countdown(2);
+        assert_eq!(cpu.pc, 0x1000);
+    }
+
+    #[test]
+    fn it_should_skip_jnz_from_rom_when_the_result_is_zero() {
+        let cpu = run_countdown(1);
+        assert_eq!(cpu.pc, 0x06);
+    }
 }
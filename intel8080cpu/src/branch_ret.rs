@@ -1,6 +1,7 @@
-use intel8080cpu::Intel8080Cpu;
+use super::cpu::Memory;
+use intel8080cpu::{EmulationListener, Intel8080Cpu};
 
-impl<'a> Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
     pub(crate) fn execute_rc(&mut self) {
         if self.flags.carry {
             self.perform_ret();
@@ -55,11 +56,8 @@ impl<'a> Intel8080Cpu<'a> {
 
     #[inline]
     fn perform_ret(&mut self) {
-        let sp = self.get_current_sp_value() as usize;
-        let high_byte = self.memory[sp + 1];
-        let low_byte = self.memory[sp];
+        let (high_byte, low_byte) = self.pop_from_stack();
         self.perform_jump(high_byte, low_byte);
-        self.save_to_sp((sp + 2) as u16);
     }
 }
 
@@ -288,4 +286,15 @@ mod tests {
         assert_eq!(cpu.pc, 0x2442);
         assert_eq!(cpu.get_current_sp_value(), 0);
     }
+
+    #[test]
+    fn it_should_wrap_ret_around_the_top_of_memory() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_sp(0xffff);
+        cpu.memory[0xffff] = 0x03;
+        cpu.memory[0x0000] = 0x2c;
+        cpu.execute_instruction(&Intel8080Instruction::Ret).unwrap();
+        assert_eq!(cpu.pc, 0x2c03);
+        assert_eq!(cpu.get_current_sp_value(), 0x0001);
+    }
 }
@@ -1,21 +1,37 @@
 use alloc::boxed::Box;
-use alloc::vec::Vec;
-use super::cpu::{Cpu, InputDevice, OutputDevice, WithPorts};
+use core::ops::RangeBounds;
+use super::cpu::{
+    address_range, decode_instructions, Cpu, InputDevice, Instruction, Memory, OutputDevice,
+    WithPorts,
+};
 use super::failure::Error;
 use super::CpuError;
-use instruction::Intel8080Instruction;
-use intel8080cpu::{Intel8080Cpu, Location, State, ROM_MEMORY_LIMIT};
+use instruction::{is_undocumented_opcode, Intel8080Instruction, Intel8080InstructionError};
+use intel8080cpu::{EmulationListener, Flags, Intel8080Cpu, Location, RegisterSet, State};
 
-#[inline]
-fn min(f: usize, s: usize) -> usize {
-    if f < s {
-        f
-    } else {
-        s
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Cpu<Intel8080Instruction, CpuError>
+    for Intel8080Cpu<'a, M, L>
+{
+    fn execute(&mut self) -> Result<u8, Error> {
+        let bytes = self.get_next_instruction_bytes();
+        if is_undocumented_opcode(bytes[0]) {
+            let pc = self.pc;
+            self.notify_listeners(|l| l.on_illegal_opcode(pc, bytes[0]));
+        }
+        let instruction = Intel8080Instruction::from(&bytes[..]);
+        if !self.can_run(&instruction) {
+            return Ok(0);
+        }
+        if self.tracer.is_some() {
+            self.trace(&instruction);
+        }
+        self.interruption_delay = false;
+        self.increase_pc(instruction.size()?);
+        self.execute_instruction(&instruction)?;
+        let cycles = self.get_cycles_for_instruction(&instruction)?;
+        Ok(cycles)
     }
-}
 
-impl<'a> Cpu<Intel8080Instruction, CpuError> for Intel8080Cpu<'a> {
     fn execute_instruction(&mut self, instruction: &Intel8080Instruction) -> Result<(), Error> {
         if !self.can_run(&instruction) {
             return Ok(());
@@ -169,10 +185,12 @@ impl<'a> Cpu<Intel8080Instruction, CpuError> for Intel8080Cpu<'a> {
     }
 
     #[inline]
-    fn get_next_instruction_bytes(&self) -> Vec<u8> {
-        let from = self.pc as usize;
-        let to = min(from + 3, self.memory.len());
-        self.memory[from..to].to_vec()
+    fn get_next_instruction_bytes(&self) -> [u8; 3] {
+        [
+            self.read_memory(self.pc),
+            self.read_memory(self.pc.wrapping_add(1)),
+            self.read_memory(self.pc.wrapping_add(2)),
+        ]
     }
 
     #[inline]
@@ -186,11 +204,21 @@ impl<'a> Cpu<Intel8080Instruction, CpuError> for Intel8080Cpu<'a> {
     }
 
     fn is_done(&self) -> bool {
-        self.pc >= ROM_MEMORY_LIMIT as u16 || self.state == State::Halted
+        self.termination.is_met(self.pc, self.state)
     }
 
     fn increase_pc(&mut self, steps: u8) {
-        self.pc += u16::from(steps);
+        self.pc = self.pc.wrapping_add(u16::from(steps));
+    }
+
+    fn reset(&mut self) {
+        self.registers = RegisterSet::new();
+        self.flags = Flags::new();
+        self.pc = self.initial_pc;
+        self.interruptions_enabled = true;
+        self.interruption_delay = false;
+        self.state = State::Running;
+        self.prev_state = State::Running;
     }
 
     fn get_cycles_from_one_condition(
@@ -247,7 +275,7 @@ impl<'a> Cpu<Intel8080Instruction, CpuError> for Intel8080Cpu<'a> {
     }
 }
 
-impl<'a> WithPorts for Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> WithPorts for Intel8080Cpu<'a, M, L> {
     fn add_input_device(&mut self, id: u8, device: Box<dyn InputDevice>) {
         self.inputs[id as usize] = Some(device);
     }
@@ -257,9 +285,28 @@ impl<'a> WithPorts for Intel8080Cpu<'a> {
     }
 }
 
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
+    // Decodes the range an instruction at a time. An instruction that doesn't fit in what's left
+    // of the range is an error, and after an error the walk moves on a single byte
+    pub fn iter_instructions<R: RangeBounds<u16>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (u16, Result<Intel8080Instruction, Intel8080InstructionError>)> + '_
+    {
+        let memory = &self.memory;
+        decode_instructions(
+            address_range(&range),
+            move |address| memory.get(address),
+            |bytes| Some(Intel8080Instruction::from(bytes)),
+        )
+        .map(|(address, instruction)| (address, instruction.ok_or(Intel8080InstructionError {})))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::super::cpu::Cpu;
+    use super::super::cpu::{disassemble, Cpu, HookAction};
+    use instruction::Intel8080Instruction;
     use intel8080cpu::{Intel8080Cpu, State, ROM_MEMORY_LIMIT};
 
     #[test]
@@ -281,4 +328,121 @@ mod tests {
         cpu.execute().unwrap();
         assert_eq!(cpu.pc, 0x00);
     }
+
+    fn counting_cpu<'a>() -> Intel8080Cpu<'a> {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..6].copy_from_slice(&[
+            0x3c, // INR A
+            0x3c, // INR A
+            0x3c, // INR A
+            0xc3, 0x00, 0x00, // JMP 0000H
+        ]);
+        Intel8080Cpu::new(rom)
+    }
+
+    #[test]
+    fn it_should_let_the_hook_decide_what_to_do_with_the_instruction() {
+        let mut cpu = counting_cpu();
+        let mut seen = vec![];
+        let mut actions = vec![HookAction::Pause, HookAction::Skip, HookAction::Continue];
+        for _ in 0..3 {
+            cpu.execute_with_hook(&mut |pc, instruction| {
+                seen.push((pc, instruction.to_string()));
+                actions.remove(0)
+            })
+            .unwrap();
+        }
+        let inr = String::from("INR A");
+        assert_eq!(seen, vec![(0, inr.clone()), (0, inr.clone()), (1, inr)]);
+        assert_eq!(cpu.pc, 2);
+        assert_eq!(cpu.get_current_a_value().unwrap(), 1);
+    }
+
+    #[test]
+    fn it_should_run_until_the_pc_reaches_the_address() {
+        let mut cpu = counting_cpu();
+        assert_eq!(cpu.run_until(3).unwrap(), 15);
+        assert_eq!(cpu.pc, 3);
+        assert_eq!(cpu.run_until(3).unwrap(), 0);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.run_until(2).unwrap(), 10);
+        assert_eq!(cpu.get_current_a_value().unwrap(), 5);
+        // A stopped cpu never gets there
+        cpu.state = State::Stopped;
+        assert_eq!(cpu.run_until(0).unwrap(), 0);
+        assert_eq!(cpu.pc, 2);
+    }
+
+    #[test]
+    fn it_should_run_a_budget_of_cycles_until_it_halts() {
+        let mut cpu = counting_cpu();
+        // INR A takes 5 cycles and JMP 10
+        let stats = cpu.run_until_cycles(12).unwrap();
+        assert_eq!((stats.instructions, stats.cycles, stats.pc), (3, 15, 3));
+        let stats = cpu.run_until_cycles(20).unwrap();
+        assert_eq!((stats.instructions, stats.cycles, stats.pc), (3, 20, 2));
+        assert_eq!(cpu.get_current_a_value().unwrap(), 5);
+        cpu.memory[2] = 0x76;
+        let stats = cpu.run_until_cycles(1000).unwrap();
+        assert_eq!((stats.instructions, stats.cycles, stats.pc), (1, 7, 3));
+        assert_eq!(cpu.run_until_cycles(1000).unwrap().instructions, 0);
+    }
+
+    #[test]
+    fn it_should_report_instructions_cut_by_the_end_of_the_range() {
+        let cpu = counting_cpu();
+        let instructions: Vec<(u16, String)> = cpu
+            .iter_instructions(1..4)
+            .map(|(pc, instruction)| (pc, instruction.map(|i| i.to_string()).unwrap_or_default()))
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![
+                (1, String::from("INR A")),
+                (2, String::from("INR A")),
+                (3, String::new()),
+            ]
+        );
+        assert_eq!(cpu.iter_instructions(3..=5).count(), 1);
+    }
+
+    #[test]
+    fn it_should_walk_data_one_instruction_at_a_time() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        // LXI H, 2121H twice and a lonely 21H
+        rom[0x100..0x107].copy_from_slice(&[0x21; 7]);
+        let cpu = Intel8080Cpu::new(rom);
+        let instructions: Vec<(u16, bool)> = cpu
+            .iter_instructions(0x100..0x107)
+            .map(|(pc, instruction)| (pc, instruction.is_ok()))
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![(0x100, true), (0x103, true), (0x106, false)]
+        );
+        assert_eq!(cpu.iter_instructions(0xfffe..).count(), 2);
+    }
+
+    #[test]
+    fn it_should_disassemble_a_program() {
+        let program = [
+            0x3e, 0x3f, // MVI A, 3FH
+            0x21, 0x00, 0x24, // LXI H, 2400H
+            0x77, // MOV M, A
+            0xc3, 0x02, 0x00, // JMP 0002H
+            0xcd, 0x10, // CALL without its high byte, and 10H decodes alone as a NOP
+        ];
+        let instructions = disassemble::<Intel8080Instruction>(&program);
+        assert_eq!(
+            instructions,
+            vec![
+                (0, String::from("MVI A,#$3f")),
+                (2, String::from("LXI H,#$2400")),
+                (5, String::from("MOV M,A")),
+                (6, String::from("JMP $0002")),
+                (9, String::from("DB cd")),
+                (10, String::from("NOP")),
+            ]
+        );
+    }
 }
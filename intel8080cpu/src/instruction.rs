@@ -1,8 +1,9 @@
+use alloc::fmt;
 use alloc::format;
 use alloc::string::{String, ToString};
-use alloc::vec::Vec;
 use super::cpu::{Cycles, Instruction};
 use super::failure::Error;
+use helpers::two_bytes_to_word;
 use intel8080cpu::{Address, Location, RegisterType};
 
 #[derive(Debug, Fail)]
@@ -407,9 +408,17 @@ impl Instruction for Intel8080Instruction {
     }
 }
 
-impl From<Vec<u8>> for Intel8080Instruction {
+#[inline]
+pub(crate) const fn is_undocumented_opcode(opcode: u8) -> bool {
+    matches!(
+        opcode,
+        0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 | 0xcb | 0xd9 | 0xdd | 0xed | 0xfd
+    )
+}
+
+impl From<&[u8]> for Intel8080Instruction {
     #[inline]
-    fn from(bytes: Vec<u8>) -> Intel8080Instruction {
+    fn from(bytes: &[u8]) -> Intel8080Instruction {
         match bytes[0] {
             0x00 => Intel8080Instruction::Noop,
             0x01 => Intel8080Instruction::Lxi {
@@ -1519,31 +1528,81 @@ impl From<Vec<u8>> for Intel8080Instruction {
     }
 }
 
-impl ToString for Intel8080Instruction {
-    fn to_string(&self) -> String {
+impl Intel8080Instruction {
+    pub fn address_operand(&self) -> Option<u16> {
+        match self {
+            Intel8080Instruction::Shld { address }
+            | Intel8080Instruction::Lhld { address }
+            | Intel8080Instruction::Sta { address }
+            | Intel8080Instruction::Lda { address }
+            | Intel8080Instruction::Jnz { address }
+            | Intel8080Instruction::Jmp { address }
+            | Intel8080Instruction::Cnz { address }
+            | Intel8080Instruction::Jz { address }
+            | Intel8080Instruction::Cz { address }
+            | Intel8080Instruction::Call { address }
+            | Intel8080Instruction::Jnc { address }
+            | Intel8080Instruction::Cnc { address }
+            | Intel8080Instruction::Jc { address }
+            | Intel8080Instruction::Cc { address }
+            | Intel8080Instruction::Jpo { address }
+            | Intel8080Instruction::Cpo { address }
+            | Intel8080Instruction::Jpe { address }
+            | Intel8080Instruction::Cpe { address }
+            | Intel8080Instruction::Jp { address }
+            | Intel8080Instruction::Cp { address }
+            | Intel8080Instruction::Jm { address }
+            | Intel8080Instruction::Cm { address } => {
+                Some(two_bytes_to_word(address[1], address[0]))
+            }
+            _ => None,
+        }
+    }
+
+    // Where a jump or a call goes, unlike the address operand of loads and stores
+    pub fn branch_target(&self) -> Option<u16> {
         match self {
+            Intel8080Instruction::Shld { .. }
+            | Intel8080Instruction::Lhld { .. }
+            | Intel8080Instruction::Sta { .. }
+            | Intel8080Instruction::Lda { .. } => None,
+            _ => self.address_operand(),
+        }
+    }
+
+    // Prints the name of the address operand instead of the raw address, when it has one
+    pub fn to_string_with_symbols<F: Fn(u16) -> Option<String>>(&self, symbol: F) -> String {
+        let text = self.to_string();
+        match self
+            .address_operand()
+            .and_then(|address| symbol(address).map(|name| (address, name)))
+        {
+            Some((address, name)) => text.replace(&format!("${:04x}", address), &name),
+            None => text,
+        }
+    }
+}
+
+impl fmt::Display for Intel8080Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
             Intel8080Instruction::Noop => String::from("NOP"),
             Intel8080Instruction::Lxi {
                 register,
                 low_byte,
                 high_byte,
-            } => format!(
-                "LXI {},#${:02x}{:02x}",
-                register.to_string(),
-                high_byte,
-                low_byte
-            ),
-            Intel8080Instruction::Stax { register } => format!("STAX {}", register.to_string()),
-            Intel8080Instruction::Inx { register } => format!("INX {}", register.to_string()),
-            Intel8080Instruction::Inr { source } => format!("INR {}", source.to_string()),
-            Intel8080Instruction::Dcr { source } => format!("DCR {}", source.to_string()),
+            } => format!("LXI {},#${:02x}{:02x}", register, high_byte, low_byte),
+            Intel8080Instruction::Stax { register } => format!("STAX {}", register),
+            Intel8080Instruction::Inx { register } => format!("INX {}", register),
+            Intel8080Instruction::Inr { source } => format!("INR {}", source),
+            Intel8080Instruction::Dcr { source } => format!("DCR {}", source),
             Intel8080Instruction::Mvi { source, byte } => {
-                format!("MVI {},#${:02x}", source.to_string(), byte)
+                format!("MVI {},#${:02x}", source, byte)
             }
             Intel8080Instruction::Rlc => String::from("RLC"),
-            Intel8080Instruction::Dad { register } => format!("DAD {}", register.to_string()),
-            Intel8080Instruction::Ldax { register } => format!("LDAX {}", register.to_string()),
-            Intel8080Instruction::Dcx { register } => format!("DCX {}", register.to_string()),
+            Intel8080Instruction::Dad { register } => format!("DAD {}", register),
+            Intel8080Instruction::Ldax { register } => format!("LDAX {}", register),
+            Intel8080Instruction::Dcx { register } => format!("DCX {}", register),
             Intel8080Instruction::Rrc => String::from("RRC"),
             Intel8080Instruction::Ral => String::from("RAL"),
             Intel8080Instruction::Rar => String::from("RAR"),
@@ -1564,19 +1623,19 @@ impl ToString for Intel8080Instruction {
             Intel8080Instruction::Stc => String::from("STC"),
             Intel8080Instruction::Cmc => String::from("CMC"),
             Intel8080Instruction::Mov { destiny, source } => {
-                format!("MOV {},{}", destiny.to_string(), source.to_string())
+                format!("MOV {},{}", destiny, source)
             }
             Intel8080Instruction::Hlt => "HLT".to_string(),
-            Intel8080Instruction::Add { source } => format!("ADD {}", source.to_string()),
-            Intel8080Instruction::Adc { source } => format!("ADC {}", source.to_string()),
-            Intel8080Instruction::Sub { source } => format!("SUB {}", source.to_string()),
-            Intel8080Instruction::Sbb { source } => format!("SBB {}", source.to_string()),
-            Intel8080Instruction::Ana { source } => format!("ANA {}", source.to_string()),
-            Intel8080Instruction::Xra { source } => format!("XRA {}", source.to_string()),
-            Intel8080Instruction::Ora { source } => format!("ORA {}", source.to_string()),
-            Intel8080Instruction::Cmp { source } => format!("CMP {}", source.to_string()),
+            Intel8080Instruction::Add { source } => format!("ADD {}", source),
+            Intel8080Instruction::Adc { source } => format!("ADC {}", source),
+            Intel8080Instruction::Sub { source } => format!("SUB {}", source),
+            Intel8080Instruction::Sbb { source } => format!("SBB {}", source),
+            Intel8080Instruction::Ana { source } => format!("ANA {}", source),
+            Intel8080Instruction::Xra { source } => format!("XRA {}", source),
+            Intel8080Instruction::Ora { source } => format!("ORA {}", source),
+            Intel8080Instruction::Cmp { source } => format!("CMP {}", source),
             Intel8080Instruction::Rnz => String::from("RNZ"),
-            Intel8080Instruction::Pop { register } => format!("POP {}", register.to_string()),
+            Intel8080Instruction::Pop { register } => format!("POP {}", register),
             Intel8080Instruction::Jnz { address } => {
                 format!("JNZ ${:02x}{:02x}", address[1], address[0])
             }
@@ -1586,7 +1645,7 @@ impl ToString for Intel8080Instruction {
             Intel8080Instruction::Cnz { address } => {
                 format!("CNZ ${:02x}{:02x}", address[1], address[0])
             }
-            Intel8080Instruction::Push { register } => format!("PUSH {}", register.to_string()),
+            Intel8080Instruction::Push { register } => format!("PUSH {}", register),
             Intel8080Instruction::Adi { byte } => format!("ADI #${:02x}", byte),
             Intel8080Instruction::Rst { byte } => format!("RST {}", byte),
             Intel8080Instruction::Rz => String::from("RZ"),
@@ -1633,7 +1692,7 @@ impl ToString for Intel8080Instruction {
             Intel8080Instruction::Jpe { address } => {
                 format!("JPE ${:02x}{:02x}", address[1], address[0])
             }
-            Intel8080Instruction::Xchg => String::from("RNC"),
+            Intel8080Instruction::Xchg => String::from("XCHG"),
             Intel8080Instruction::Cpe { address } => {
                 format!("CPE ${:02x}{:02x}", address[1], address[0])
             }
@@ -1657,6 +1716,7 @@ impl ToString for Intel8080Instruction {
                 format!("CM ${:02x}{:02x}", address[1], address[0])
             }
             Intel8080Instruction::Cpi { byte } => format!("CPI #${:02x}", byte),
-        }
+        };
+        write!(f, "{}", s)
     }
 }
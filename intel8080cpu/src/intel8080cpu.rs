@@ -1,12 +1,16 @@
 use alloc::boxed::Box;
 use alloc::fmt;
-use alloc::string::{String, ToString};
+use alloc::string::String;
 use alloc::vec::Vec;
-use super::cpu::{InputDevice, OutputDevice};
+use alloc::str::FromStr;
+use super::cpu::{InputDevice, Memory, OutputDevice, PlainMemory};
 use super::CpuError;
-use helpers::two_bytes_to_word;
+use helpers::{two_bytes_to_word, word_to_address};
+use trace::TraceEntry;
 
 pub const ROM_MEMORY_LIMIT: usize = 8192;
+// Everything the 16 bits address bus can reach
+pub const MEMORY_SIZE: usize = ROM_MEMORY_LIMIT * 8;
 pub(crate) const MAX_INPUT_OUTPUT_DEVICES: usize = 0x100;
 pub const HERTZ: i64 = 2_000_000;
 
@@ -23,27 +27,70 @@ pub enum RegisterType {
     Psw,
 }
 
+const REGISTERS: [RegisterType; 9] = [
+    RegisterType::A,
+    RegisterType::B,
+    RegisterType::C,
+    RegisterType::D,
+    RegisterType::E,
+    RegisterType::H,
+    RegisterType::L,
+    RegisterType::Sp,
+    RegisterType::Psw,
+];
+
+impl RegisterType {
+    pub fn all() -> impl Iterator<Item = RegisterType> {
+        REGISTERS.iter().cloned()
+    }
+}
+
 impl fmt::Display for RegisterType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let s = match self {
-            RegisterType::A => String::from("A"),
-            RegisterType::B => String::from("B"),
-            RegisterType::C => String::from("C"),
-            RegisterType::D => String::from("D"),
-            RegisterType::E => String::from("E"),
-            RegisterType::H => String::from("H"),
-            RegisterType::L => String::from("L"),
-            RegisterType::Sp => String::from("SP"),
-            RegisterType::Psw => String::from("PSW"),
+            RegisterType::A => "A",
+            RegisterType::B => "B",
+            RegisterType::C => "C",
+            RegisterType::D => "D",
+            RegisterType::E => "E",
+            RegisterType::H => "H",
+            RegisterType::L => "L",
+            RegisterType::Sp => "SP",
+            RegisterType::Psw => "PSW",
         };
         write!(f, "{}", s)
     }
 }
 
+// Pair names parse to the register that names the pair in 8080 mnemonics
+impl FromStr for RegisterType {
+    type Err = LocationParsingError;
+
+    fn from_str(register: &str) -> Result<Self, Self::Err> {
+        match register.to_uppercase().as_str() {
+            "A" => Ok(RegisterType::A),
+            "B" | "BC" => Ok(RegisterType::B),
+            "C" => Ok(RegisterType::C),
+            "D" | "DE" => Ok(RegisterType::D),
+            "E" => Ok(RegisterType::E),
+            "H" | "HL" => Ok(RegisterType::H),
+            "L" => Ok(RegisterType::L),
+            "SP" => Ok(RegisterType::Sp),
+            "PSW" => Ok(RegisterType::Psw),
+            _ => Err(LocationParsingError {
+                register: String::from(register),
+            }),
+        }
+    }
+}
+
 pub type Address = [u8; 2];
 
 #[derive(Debug, Fail)]
-#[fail(display = "{} isn't a valid register.", register)]
+#[fail(
+    display = "{} isn't a valid register. Valid names are A, B, C, D, E, H, L, M, SP, PSW, BC, DE and HL.",
+    register
+)]
 pub struct LocationParsingError {
     register: String,
 }
@@ -54,63 +101,44 @@ pub enum Location {
     Memory,
 }
 
-impl ToString for Location {
-    fn to_string(&self) -> String {
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Location::Register { register } => register.to_string(),
-            Location::Memory => String::from("M"),
+            Location::Register { register } => write!(f, "{}", register),
+            Location::Memory => write!(f, "M"),
+        }
+    }
+}
+
+impl FromStr for Location {
+    type Err = LocationParsingError;
+
+    fn from_str(location: &str) -> Result<Self, Self::Err> {
+        if location.eq_ignore_ascii_case("M") {
+            Ok(Location::Memory)
+        } else {
+            let register = RegisterType::from_str(location)?;
+            Ok(Location::Register { register })
         }
     }
 }
 
 impl Location {
     pub fn from(location: &str) -> Result<Self, LocationParsingError> {
-        match location {
-            "A" => Ok(Location::Register {
-                register: RegisterType::A,
-            }),
-            "B" => Ok(Location::Register {
-                register: RegisterType::B,
-            }),
-            "C" => Ok(Location::Register {
-                register: RegisterType::C,
-            }),
-            "D" => Ok(Location::Register {
-                register: RegisterType::D,
-            }),
-            "E" => Ok(Location::Register {
-                register: RegisterType::E,
-            }),
-            "H" => Ok(Location::Register {
-                register: RegisterType::H,
-            }),
-            "L" => Ok(Location::Register {
-                register: RegisterType::L,
-            }),
-            "M" => Ok(Location::Memory),
-            "SP" => Ok(Location::Register {
-                register: RegisterType::Sp,
-            }),
-            "PSW" => Ok(Location::Register {
-                register: RegisterType::Psw,
-            }),
-            _ => Err(LocationParsingError {
-                register: String::from(location),
-            }),
-        }
+        Location::from_str(location)
     }
 }
 
 #[derive(Debug)]
 pub(crate) struct RegisterSet {
-    a: u8,
-    b: u8,
-    c: u8,
-    d: u8,
-    e: u8,
-    h: u8,
-    l: u8,
-    sp: u16,
+    pub(crate) a: u8,
+    pub(crate) b: u8,
+    pub(crate) c: u8,
+    pub(crate) d: u8,
+    pub(crate) e: u8,
+    pub(crate) h: u8,
+    pub(crate) l: u8,
+    pub(crate) sp: u16,
 }
 
 impl RegisterSet {
@@ -136,21 +164,81 @@ pub(crate) enum State {
     Halted,
 }
 
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TerminationReason {
+    Halt,
+    WarmBoot,
+}
+
+// When is_done starts reporting the program as finished
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TerminationCondition {
+    Never,
+    PcReaches(u16),
+    PcPasses(u16),
+    // HLT stops the CPU until an interruption, so it isn't done unless asked to
+    OnHlt,
+    // Warm boot of a CP/M program, a JMP 0 or CALL 0 with CP/M compatibility on
+    JumpToZero,
+    Any(Vec<TerminationCondition>),
+}
+
+impl TerminationCondition {
+    pub(crate) fn is_met(&self, pc: u16, state: State) -> bool {
+        match self {
+            TerminationCondition::Never => false,
+            TerminationCondition::PcReaches(address) => pc == *address,
+            TerminationCondition::PcPasses(address) => pc >= *address,
+            TerminationCondition::OnHlt => state == State::Stopped,
+            TerminationCondition::JumpToZero => state == State::Halted,
+            TerminationCondition::Any(conditions) => conditions.iter().any(|c| c.is_met(pc, state)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Diagnostic {
+    StackWrapped { sp: u16 },
+}
+
+pub trait EmulationListener {
+    fn on_bdos_call(&mut self, _function: u8, _de: u16) {}
+    fn on_output(&mut self, _bytes: &[u8]) {}
+    fn on_terminate(&mut self, _reason: TerminationReason) {}
+    fn on_illegal_opcode(&mut self, _pc: u16, _opcode: u8) {}
+    fn on_diagnostic(&mut self, _diagnostic: Diagnostic) {}
+}
+
 pub trait Printer {
     fn print(&mut self, bytes: &[u8]);
 }
 
+impl<P: Printer> EmulationListener for P {
+    fn on_output(&mut self, bytes: &[u8]) {
+        self.print(bytes);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flag {
+    Sign,
+    Zero,
+    AuxiliaryCarry,
+    Parity,
+    Carry,
+}
+
 #[derive(Debug)]
-pub(crate) struct Flags {
-    pub(crate) sign: bool,
-    pub(crate) zero: bool,
-    pub(crate) parity: bool,
-    pub(crate) carry: bool,
-    pub(crate) auxiliary_carry: bool,
+pub struct Flags {
+    pub sign: bool,
+    pub zero: bool,
+    pub parity: bool,
+    pub carry: bool,
+    pub auxiliary_carry: bool,
 }
 
 impl Flags {
-    fn new() -> Flags {
+    pub(crate) fn new() -> Flags {
         Flags {
             sign: true,
             zero: true,
@@ -159,57 +247,155 @@ impl Flags {
             auxiliary_carry: true,
         }
     }
+
+    // The PSW layout: S Z 0 AC 0 P 1 CY, from the most significant bit
+    pub fn to_byte(&self) -> u8 {
+        (self.sign as u8) << 7
+            | (self.zero as u8) << 6
+            | (self.auxiliary_carry as u8) << 4
+            | (self.parity as u8) << 2
+            | 0x02
+            | self.carry as u8
+    }
+
+    pub fn from_byte(byte: u8) -> Flags {
+        Flags {
+            sign: byte & 0x80 == 0x80,
+            zero: byte & 0x40 == 0x40,
+            parity: byte & 0x04 == 0x04,
+            carry: byte & 0x01 == 0x01,
+            auxiliary_carry: byte & 0x10 == 0x10,
+        }
+    }
 }
 
-pub struct Intel8080Cpu<'a> {
+// Generic over what's behind the address bus, plain memory unless a machine maps its own, and over
+// the listeners, which a machine handing the cpu to another thread can require to be Send
+pub struct Intel8080Cpu<'a, M = PlainMemory, L: ?Sized = dyn EmulationListener + 'a> {
     pub(crate) registers: RegisterSet,
     pub(crate) pc: u16,
-    pub memory: [u8; ROM_MEMORY_LIMIT * 8],
+    // Where reset sends the PC back to
+    pub(crate) initial_pc: u16,
+    pub memory: M,
     pub(crate) cp_m_compatibility: bool,
+    pub(crate) strict: bool,
+    pub(crate) termination: TerminationCondition,
     pub(crate) flags: Flags,
     pub interruptions_enabled: bool,
+    // EI only lets interruptions in after the instruction that follows it
+    pub(crate) interruption_delay: bool,
     pub(crate) state: State,
     pub(crate) prev_state: State,
     pub(crate) inputs: Vec<Option<Box<dyn InputDevice>>>,
     pub(crate) outputs: Vec<Option<Box<dyn OutputDevice>>>,
-    pub(crate) printer: Option<&'a mut dyn Printer>,
+    pub(crate) listeners: Vec<&'a mut L>,
+    pub(crate) write_log: Option<Vec<(u16, u8)>>,
+    pub(crate) tracer: Option<Box<dyn FnMut(TraceEntry) + Send + 'a>>,
 }
 
 impl<'a> Intel8080Cpu<'a> {
     pub fn new_cp_m_compatible(
         rom_memory: [u8; ROM_MEMORY_LIMIT],
-        screen: &mut dyn Printer,
-    ) -> Intel8080Cpu {
+        screen: &'a mut dyn EmulationListener,
+    ) -> Intel8080Cpu<'a> {
         let mut cpu = Intel8080Cpu::new(rom_memory);
         cpu.cp_m_compatibility = true;
-        cpu.printer = Some(screen);
+        cpu.add_listener(screen);
+        cpu
+    }
+
+    // Loads the program at the offset and starts running it from there, CP/M binaries go at 0x100
+    pub fn with_program<'b>(program: &[u8], offset: u16) -> Intel8080Cpu<'b> {
+        let mut cpu = Intel8080Cpu::with_memory_size(&[], MEMORY_SIZE);
+        let start = offset as usize;
+        let length = program.len().min(cpu.memory.len() - start);
+        cpu.memory[start..start + length].copy_from_slice(&program[..length]);
+        cpu.pc = offset;
+        cpu.initial_pc = offset;
+        cpu.termination = TerminationCondition::JumpToZero;
         cpu
     }
 
     pub fn new<'b>(rom_memory: [u8; ROM_MEMORY_LIMIT]) -> Intel8080Cpu<'b> {
-        let registers = RegisterSet::new();
-        let mut memory = [0; ROM_MEMORY_LIMIT * 8];
-        for i in 0..(ROM_MEMORY_LIMIT * 8) {
-            let value = if i < rom_memory.len() {
-                rom_memory[i]
-            } else {
-                0
-            };
-            memory[i] = value;
+        Intel8080Cpu::with_memory_size(&rom_memory, MEMORY_SIZE)
+    }
+
+    // Programs finish when they run past the ROM or warm boot. Without a ROM the code is written
+    // later, so only warm booting finishes it. Addresses past the memory read as 0 and ignore
+    // writes, and the address space caps it at 64KB
+    pub fn with_memory_size<'b>(rom_memory: &[u8], total: usize) -> Intel8080Cpu<'b> {
+        let mut memory = PlainMemory::new(total);
+        let rom_size = rom_memory.len().min(memory.len());
+        memory[..rom_size].copy_from_slice(&rom_memory[..rom_size]);
+        let mut cpu = Intel8080Cpu::with_memory(memory);
+        if rom_size > 0 && rom_size < MEMORY_SIZE {
+            cpu.termination = TerminationCondition::Any(alloc::vec![
+                TerminationCondition::PcPasses(rom_size as u16),
+                TerminationCondition::JumpToZero,
+            ]);
         }
+        cpu
+    }
+}
 
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
+    // Runs whatever the memory maps from address 0 until it warm boots
+    pub fn with_memory(memory: M) -> Intel8080Cpu<'a, M, L> {
         Intel8080Cpu {
-            registers,
+            registers: RegisterSet::new(),
             pc: 0,
+            initial_pc: 0,
             memory,
             flags: Flags::new(),
             interruptions_enabled: true,
+            interruption_delay: false,
             state: State::Running,
             prev_state: State::Running,
-            inputs: Intel8080Cpu::make_inputs_vector(),
-            outputs: Intel8080Cpu::make_outputs_vector(),
+            inputs: Self::make_inputs_vector(),
+            outputs: Self::make_outputs_vector(),
             cp_m_compatibility: false,
-            printer: None,
+            strict: false,
+            termination: TerminationCondition::JumpToZero,
+            listeners: Vec::new(),
+            write_log: None,
+            tracer: None,
+        }
+    }
+
+    pub fn set_cp_m_compatibility(&mut self, enabled: bool) {
+        self.cp_m_compatibility = enabled;
+    }
+
+    pub fn set_termination_condition(&mut self, condition: TerminationCondition) {
+        self.termination = condition;
+    }
+
+    // In strict mode, suspicious but well defined behaviour is reported to the listeners
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    // While enabled, every memory write is kept until drained, in the order it happened
+    pub fn log_writes(&mut self, enabled: bool) {
+        self.write_log = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn drain_writes<F: FnMut(u16, u8)>(&mut self, mut f: F) {
+        if let Some(ref mut writes) = self.write_log {
+            for (address, value) in writes.drain(..) {
+                f(address, value);
+            }
+        }
+    }
+
+    pub fn add_listener(&mut self, listener: &'a mut L) {
+        self.listeners.push(listener);
+    }
+
+    #[inline]
+    pub(crate) fn notify_listeners<F: FnMut(&mut L)>(&mut self, mut notify: F) {
+        for listener in self.listeners.iter_mut() {
+            notify(&mut **listener);
         }
     }
 
@@ -243,12 +429,94 @@ impl<'a> Intel8080Cpu<'a> {
     }
 
     pub fn is_hard_stopped(&self) -> bool {
-        match self.state {
-            State::HardStop => true,
-            _ => false,
+        matches!(self.state, State::HardStop)
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.state == State::Stopped
+    }
+
+    pub fn get_flags(&self) -> &Flags {
+        &self.flags
+    }
+
+    pub fn get_register_value(&self, register: RegisterType) -> Result<u8, CpuError> {
+        self.get_current_single_register_value(register)
+    }
+
+    pub fn set_register_value(
+        &mut self,
+        register: RegisterType,
+        value: u8,
+    ) -> Result<(), CpuError> {
+        self.save_to_single_register(value, register)
+    }
+
+    // Like in LXI or PUSH, B, D and H stand for the BC, DE and HL pairs
+    pub fn get_register_pair_value(&self, register: RegisterType) -> Result<u16, CpuError> {
+        match register {
+            RegisterType::B => Ok(self.get_current_bc_value()),
+            RegisterType::D => Ok(self.get_current_de_value()),
+            RegisterType::H => Ok(self.get_current_hl_value()),
+            RegisterType::Sp => Ok(self.get_current_sp_value()),
+            RegisterType::Psw => Ok(two_bytes_to_word(self.registers.a, self.flags.to_byte())),
+            _ => Err(CpuError::InvalidRegisterArgument { register }),
+        }
+    }
+
+    pub fn set_register_pair_value(
+        &mut self,
+        register: RegisterType,
+        value: u16,
+    ) -> Result<(), CpuError> {
+        let [low_byte, high_byte] = word_to_address(value);
+        match register {
+            RegisterType::B => {
+                self.registers.b = high_byte;
+                self.registers.c = low_byte;
+            }
+            RegisterType::D => {
+                self.registers.d = high_byte;
+                self.registers.e = low_byte;
+            }
+            RegisterType::H => {
+                self.registers.h = high_byte;
+                self.registers.l = low_byte;
+            }
+            RegisterType::Sp => self.save_to_sp(value),
+            RegisterType::Psw => {
+                self.registers.a = high_byte;
+                self.flags = Flags::from_byte(low_byte);
+            }
+            _ => return Err(CpuError::InvalidRegisterArgument { register }),
+        }
+        Ok(())
+    }
+
+    pub fn get_flag(&self, flag: Flag) -> bool {
+        match flag {
+            Flag::Sign => self.flags.sign,
+            Flag::Zero => self.flags.zero,
+            Flag::AuxiliaryCarry => self.flags.auxiliary_carry,
+            Flag::Parity => self.flags.parity,
+            Flag::Carry => self.flags.carry,
         }
     }
 
+    pub fn set_flag(&mut self, flag: Flag, value: bool) {
+        match flag {
+            Flag::Sign => self.flags.sign = value,
+            Flag::Zero => self.flags.zero = value,
+            Flag::AuxiliaryCarry => self.flags.auxiliary_carry = value,
+            Flag::Parity => self.flags.parity = value,
+            Flag::Carry => self.flags.carry = value,
+        }
+    }
+
+    pub fn get_sp(&self) -> u16 {
+        self.get_current_sp_value()
+    }
+
     pub fn toggle_hard_stop(&mut self) {
         match self.state {
             State::HardStop => {
@@ -261,7 +529,9 @@ impl<'a> Intel8080Cpu<'a> {
         }
     }
 
+    // is_multiple_of would need Rust 1.87
     #[inline]
+    #[allow(clippy::manual_is_multiple_of)]
     pub(crate) fn update_flags(&mut self, answer: u16, with_carry: bool) {
         self.flags.zero = answer.trailing_zeros() >= 8;
         self.flags.sign = (answer & 0x80) != 0;
@@ -292,16 +562,29 @@ impl<'a> Intel8080Cpu<'a> {
         two_bytes_to_word(high_value, low_value)
     }
 
+    #[inline]
+    pub fn read_memory(&self, address: u16) -> u8 {
+        self.memory.get(address)
+    }
+
+    #[inline]
+    pub fn write_memory(&mut self, address: u16, value: u8) {
+        self.memory.set(address, value);
+        if let Some(ref mut writes) = self.write_log {
+            writes.push((address, value));
+        }
+    }
+
     #[inline]
     pub(crate) fn get_value_in_memory_at_hl(&self) -> u8 {
         let source_value_address: u16 = self.get_current_hl_value();
-        self.memory[source_value_address as usize]
+        self.read_memory(source_value_address)
     }
 
     #[inline]
     pub(crate) fn set_value_in_memory_at_hl(&mut self, value: u8) {
         let source_value_address: u16 = self.get_current_hl_value();
-        self.memory[source_value_address as usize] = value;
+        self.write_memory(source_value_address, value);
     }
 
     #[inline]
@@ -388,3 +671,321 @@ impl<'a> Intel8080Cpu<'a> {
 
     pub(crate) fn execute_noop(&self) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        Flag, Intel8080Cpu, Location, RegisterType, State, TerminationCondition, ROM_MEMORY_LIMIT,
+    };
+    use cpu::{Cpu, Memory};
+    use std::str::FromStr;
+
+    // A kilobyte that repeats over the whole address space
+    struct MirroredMemory {
+        bytes: [u8; 0x400],
+    }
+
+    impl Memory for MirroredMemory {
+        fn set(&mut self, index: u16, new_value: u8) {
+            self.bytes[index as usize % 0x400] = new_value;
+        }
+        fn get(&self, index: u16) -> u8 {
+            self.bytes[index as usize % 0x400]
+        }
+        fn len(&self) -> usize {
+            0x400
+        }
+    }
+
+    #[test]
+    fn it_should_run_on_any_memory() {
+        let mut memory = MirroredMemory { bytes: [0; 0x400] };
+        memory.bytes[..10].copy_from_slice(&[
+            0x3e, 0x42, // MVI A, 42H
+            0x32, 0x00, 0x05, // STA 0500H
+            0x3e, 0x00, // MVI A, 0
+            0x3a, 0x00, 0x0d, // LDA 0D00H
+        ]);
+        let mut cpu: Intel8080Cpu<_> = Intel8080Cpu::with_memory(memory);
+        for _ in 0..4 {
+            cpu.execute().unwrap();
+        }
+        assert_eq!(cpu.memory.bytes[0x100], 0x42);
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x42);
+        assert_eq!(cpu.read_memory(0xfd00), 0x42);
+    }
+
+    #[test]
+    fn it_should_parse_every_register_name() {
+        let names = [
+            ("a", RegisterType::A),
+            ("B", RegisterType::B),
+            ("bc", RegisterType::B),
+            ("c", RegisterType::C),
+            ("D", RegisterType::D),
+            ("De", RegisterType::D),
+            ("e", RegisterType::E),
+            ("h", RegisterType::H),
+            ("HL", RegisterType::H),
+            ("l", RegisterType::L),
+            ("sp", RegisterType::Sp),
+            ("Psw", RegisterType::Psw),
+        ];
+        for (name, register) in names.iter() {
+            assert_eq!(RegisterType::from_str(name).unwrap(), *register);
+            assert_eq!(
+                Location::from_str(name).unwrap(),
+                Location::Register {
+                    register: *register
+                }
+            );
+        }
+        assert_eq!(Location::from_str("m").unwrap(), Location::Memory);
+        assert_eq!(Location::from_str("M").unwrap(), Location::Memory);
+    }
+
+    #[test]
+    fn it_should_fail_to_parse_invalid_names() {
+        for name in ["", "x", "m", "af", "spp", "h l"].iter() {
+            assert!(RegisterType::from_str(name).is_err());
+        }
+        assert!(Location::from_str("ix").is_err());
+        let error = Location::from_str("q").unwrap_err().to_string();
+        assert!(error.starts_with("q isn't a valid register."));
+        assert!(error.contains("PSW"));
+    }
+
+    #[test]
+    fn it_should_round_trip_through_display() {
+        for register in RegisterType::all() {
+            assert_eq!(
+                RegisterType::from_str(&register.to_string()).unwrap(),
+                register
+            );
+            let location = Location::Register { register };
+            assert_eq!(Location::from_str(&location.to_string()).unwrap(), location);
+        }
+        assert_eq!(
+            Location::from_str(&Location::Memory.to_string()).unwrap(),
+            Location::Memory
+        );
+        assert_eq!(RegisterType::all().count(), 9);
+    }
+
+    #[test]
+    fn it_should_log_memory_writes_only_when_enabled() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..8].copy_from_slice(&[
+            0x3e, 0x42, // MVI A, 42H
+            0x32, 0x00, 0x24, // STA 2400H
+            0x32, 0x01, 0x24, // STA 2401H
+        ]);
+        let mut cpu = Intel8080Cpu::new(rom);
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        cpu.log_writes(true);
+        cpu.execute().unwrap();
+        let mut writes = vec![];
+        cpu.drain_writes(|address, value| writes.push((address, value)));
+        assert_eq!(writes, vec![(0x2401, 0x42)]);
+        cpu.drain_writes(|_, _| panic!("Writes should be drained"));
+    }
+
+    #[test]
+    fn it_should_set_parity_from_the_number_of_set_bits() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.update_flags(0x03, false);
+        assert!(cpu.flags.parity);
+        cpu.update_flags(0x07, false);
+        assert!(!cpu.flags.parity);
+        cpu.update_flags(0xff, false);
+        assert!(cpu.flags.parity);
+        // Only the low byte counts
+        cpu.update_flags(0x101, true);
+        assert!(!cpu.flags.parity);
+    }
+
+    #[test]
+    fn it_should_branch_on_the_parity_of_an_addition() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..7].copy_from_slice(&[
+            0x3e, 0x01, // MVI A, 01H
+            0xc6, 0x02, // ADI 02H
+            0xea, 0x00, 0x10, // JPE 1000H
+        ]);
+        let mut cpu = Intel8080Cpu::new(rom);
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x1000);
+    }
+    #[test]
+    fn it_should_run_a_cp_m_program_loaded_at_0x100_across_the_address_space() {
+        let mut program = vec![0; 0x3000];
+        program[..13].copy_from_slice(&[
+            0x31, 0x00, 0xf0, // LXI SP, F000H
+            0x3e, 0x42, // MVI A, 42H
+            0x32, 0x00, 0x80, // STA 8000H
+            0xcd, 0x00, 0x30, // CALL 3000H
+            0xcd, 0x00, // CALL 0 (its last byte is already zero)
+        ]);
+        program[0x2f00..0x2f05].copy_from_slice(&[
+            0x32, 0x00, 0xc0, // STA C000H
+            0x3c, // INR A
+            0xc9, // RET
+        ]);
+        let mut cpu = Intel8080Cpu::with_program(&program, 0x100);
+        cpu.set_cp_m_compatibility(true);
+        assert_eq!(cpu.pc, 0x100);
+        assert_eq!(cpu.memory[0x3000], 0x32);
+        let mut steps = 0;
+        while !cpu.is_done() {
+            cpu.execute().unwrap();
+            steps += 1;
+        }
+        assert_eq!(steps, 8);
+        assert_eq!(cpu.state, State::Halted);
+        assert_eq!(cpu.memory[0x8000], 0x42);
+        assert_eq!(cpu.memory[0xc000], 0x42);
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x43);
+        assert_eq!(cpu.get_current_sp_value(), 0xf000);
+    }
+
+    #[test]
+    fn it_should_stop_at_the_configured_termination_condition() {
+        let program = [
+            0x3e, 0x01, // MVI A, 1
+            0xc3, 0x08, 0x00, // JMP 0008H
+            0x00, 0x00, 0x00, // NOP
+            0x76, // HLT
+        ];
+        let run = |condition: TerminationCondition| {
+            let mut cpu = Intel8080Cpu::with_program(&program, 0);
+            cpu.set_termination_condition(condition);
+            let mut steps = 0;
+            while !cpu.is_done() && steps < 10 {
+                cpu.execute().unwrap();
+                steps += 1;
+            }
+            (cpu.pc, steps)
+        };
+        assert_eq!(run(TerminationCondition::Never), (9, 10));
+        assert_eq!(run(TerminationCondition::PcReaches(5)), (9, 10));
+        assert_eq!(run(TerminationCondition::PcReaches(8)), (8, 2));
+        assert_eq!(run(TerminationCondition::PcPasses(5)), (8, 2));
+        assert_eq!(run(TerminationCondition::OnHlt), (9, 3));
+        assert_eq!(
+            run(TerminationCondition::Any(vec![
+                TerminationCondition::PcReaches(5),
+                TerminationCondition::OnHlt,
+            ])),
+            (9, 3)
+        );
+        let mut cpu = Intel8080Cpu::with_program(&[0x00, 0xc3, 0x00, 0x00], 0x100);
+        cpu.set_cp_m_compatibility(true);
+        cpu.set_termination_condition(TerminationCondition::JumpToZero);
+        cpu.execute().unwrap();
+        assert!(!cpu.is_done());
+        cpu.execute().unwrap();
+        assert!(cpu.is_done());
+    }
+
+    #[test]
+    fn it_should_reset_to_the_initial_state() {
+        let program = [
+            0x3e, 0x42, // MVI A, 42H
+            0x31, 0x00, 0x24, // LXI SP, 2400H
+            0x3f, // CMC
+            0xf3, // DI
+            0x76, // HLT
+        ];
+        let mut cpu = Intel8080Cpu::with_program(&program, 0x100);
+        for _ in 0..5 {
+            cpu.execute().unwrap();
+        }
+        assert!(cpu.is_stopped() && !cpu.flags.carry && !cpu.interruptions_enabled);
+        cpu.reset();
+        assert_eq!(cpu.pc, 0x100);
+        assert_eq!(cpu.registers.a, 0);
+        assert_eq!(cpu.registers.sp, 0xffff);
+        assert!(cpu.flags.carry);
+        assert!(cpu.interruptions_enabled);
+        assert!(!cpu.is_stopped());
+        assert_eq!(cpu.memory[0x100..0x108], program);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn it_should_run_programs_bigger_than_the_default_rom() {
+        let mut program = vec![0; 0x4000];
+        program[..3].copy_from_slice(&[0xc3, 0x00, 0x28]); // JMP 2800H
+        program[0x2800..0x2808].copy_from_slice(&[
+            0x3e, 0x42, // MVI A, 42H
+            0x32, 0x00, 0x4c, // STA 4C00H
+            0x32, 0x00, 0x60, // STA 6000H
+        ]);
+        let mut cpu = Intel8080Cpu::with_memory_size(&program, 0x5000);
+        assert_eq!(cpu.memory.len(), 0x5000);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.pc, 0x2800);
+        assert!(!cpu.is_done());
+        while !cpu.is_done() {
+            cpu.execute().unwrap();
+        }
+        assert_eq!(cpu.pc, 0x4000);
+        assert_eq!(cpu.memory[0x4c00], 0x42);
+        // Past the memory, writes are lost and reads give 0
+        assert_eq!(cpu.memory.len(), 0x5000);
+        assert_eq!(cpu.read_memory(0x6000), 0);
+    }
+
+    #[test]
+    fn it_should_run_code_written_after_starting_without_a_rom() {
+        let mut cpu = Intel8080Cpu::with_memory_size(&[], 0x100);
+        assert!(!cpu.is_done());
+        cpu.write_memory(0, 0x3e); // MVI A, 42H
+        cpu.write_memory(1, 0x42);
+        cpu.write_memory(2, 0x3c); // INR A
+        cpu.execute().unwrap();
+        assert!(!cpu.is_done());
+        cpu.execute().unwrap();
+        assert_eq!(cpu.registers.a, 0x43);
+        assert!(!cpu.is_done());
+    }
+
+    #[test]
+    fn it_should_read_the_register_pairs_from_the_single_registers() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.set_register_value(RegisterType::B, 0x12).unwrap();
+        cpu.set_register_value(RegisterType::C, 0x34).unwrap();
+        assert_eq!(
+            cpu.get_register_pair_value(RegisterType::B).unwrap(),
+            0x1234
+        );
+        cpu.set_register_pair_value(RegisterType::H, 0xabcd)
+            .unwrap();
+        assert_eq!(cpu.get_register_value(RegisterType::H).unwrap(), 0xab);
+        assert_eq!(cpu.get_register_value(RegisterType::L).unwrap(), 0xcd);
+        cpu.set_register_pair_value(RegisterType::Sp, 0x2400)
+            .unwrap();
+        assert_eq!(cpu.get_sp(), 0x2400);
+        assert!(cpu.get_register_pair_value(RegisterType::C).is_err());
+        assert!(cpu.set_register_value(RegisterType::Sp, 0).is_err());
+    }
+
+    #[test]
+    fn it_should_flip_single_flags() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.set_register_pair_value(RegisterType::Psw, 0x4200)
+            .unwrap();
+        assert!(!cpu.get_flag(Flag::Carry));
+        cpu.set_flag(Flag::Carry, true);
+        assert!(cpu.get_flag(Flag::Carry));
+        assert!(!cpu.get_flag(Flag::Zero));
+        assert_eq!(
+            cpu.get_register_pair_value(RegisterType::Psw).unwrap(),
+            0x4203
+        );
+    }
+}
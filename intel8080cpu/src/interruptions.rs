@@ -1,8 +1,20 @@
-use intel8080cpu::{Intel8080Cpu, State};
+use super::cpu::Memory;
+use super::CpuError;
+use intel8080cpu::{EmulationListener, Intel8080Cpu, State, TerminationReason};
+
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
+    // Hardware interruption, as if the device put RST value on the data bus
+    pub fn interrupt(&mut self, value: u8) -> Result<bool, CpuError> {
+        if !self.interruptions_enabled || self.interruption_delay {
+            return Ok(false);
+        }
+        self.execute_rst(value);
+        Ok(true)
+    }
 
-impl<'a> Intel8080Cpu<'a> {
     pub(crate) fn execute_ei(&mut self) {
         self.interruptions_enabled = true;
+        self.interruption_delay = true;
     }
 
     pub(crate) fn execute_di(&mut self) {
@@ -11,6 +23,7 @@ impl<'a> Intel8080Cpu<'a> {
 
     pub(crate) fn execute_hlt(&mut self) {
         self.state = State::Stopped;
+        self.notify_listeners(|l| l.on_terminate(TerminationReason::Halt));
     }
 }
 
@@ -52,6 +65,65 @@ mod tests {
         assert!(!cpu.interruptions_enabled);
     }
 
+    fn interruptible_cpu<'a>(program: &[u8]) -> Intel8080Cpu<'a> {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..program.len()].copy_from_slice(program);
+        let mut cpu = Intel8080Cpu::new(rom);
+        cpu.save_to_sp(0x2400);
+        cpu
+    }
+
+    #[test]
+    fn it_should_accept_an_interruption_when_enabled() {
+        let mut cpu = interruptible_cpu(&[0x00, 0x00]);
+        cpu.execute().unwrap();
+        assert!(cpu.interrupt(2).unwrap());
+        assert_eq!(cpu.pc, 0x10);
+        assert_eq!(cpu.get_current_sp_value(), 0x23fe);
+        assert_eq!(cpu.memory[0x23fe], 0x01);
+        assert_eq!(cpu.memory[0x23ff], 0x00);
+        assert!(!cpu.interruptions_enabled);
+    }
+
+    #[test]
+    fn it_should_ignore_an_interruption_when_disabled() {
+        // DI, NOP
+        let mut cpu = interruptible_cpu(&[0xf3, 0x00]);
+        cpu.execute().unwrap();
+        assert!(!cpu.interrupt(1).unwrap());
+        assert_eq!(cpu.pc, 1);
+        assert_eq!(cpu.get_current_sp_value(), 0x2400);
+    }
+
+    #[test]
+    fn it_should_wait_an_instruction_after_ei_before_accepting_interruptions() {
+        // DI, EI, NOP, NOP
+        let mut cpu = interruptible_cpu(&[0xf3, 0xfb, 0x00, 0x00]);
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        assert!(cpu.interruptions_enabled);
+        assert!(!cpu.interrupt(1).unwrap());
+        assert_eq!(cpu.pc, 2);
+        cpu.execute().unwrap();
+        assert!(cpu.interrupt(1).unwrap());
+        assert_eq!(cpu.pc, 0x08);
+        assert_eq!(cpu.memory[0x23fe], 0x03);
+    }
+
+    #[test]
+    fn it_should_leave_hlt_on_an_interruption_after_ei() {
+        // EI, HLT
+        let mut cpu = interruptible_cpu(&[0xfb, 0x76]);
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.state, State::Stopped);
+        assert_eq!(cpu.execute().unwrap(), 0);
+        assert!(cpu.interrupt(1).unwrap());
+        assert_eq!(cpu.state, State::Running);
+        assert_eq!(cpu.pc, 0x08);
+        assert_eq!(cpu.memory[0x23fe], 0x02);
+    }
+
     #[test]
     fn it_should_execute_hlt() {
         let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
@@ -1,7 +1,8 @@
+use super::cpu::Memory;
 use super::CpuError;
-use intel8080cpu::Intel8080Cpu;
+use intel8080cpu::{EmulationListener, Intel8080Cpu};
 
-impl<'a> Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
     pub(crate) fn execute_in(&mut self, id: u8) -> Result<(), CpuError> {
         let val = match self.inputs.get_mut(id as usize) {
             Some(Some(device)) => Ok(device.read()),
@@ -28,6 +29,7 @@ mod tests {
     use instruction::Intel8080Instruction;
     use intel8080cpu::{Intel8080Cpu, ROM_MEMORY_LIMIT};
     use std::boxed::Box;
+    use CpuError;
 
     #[test]
     fn it_should_execute_in() {
@@ -61,4 +63,19 @@ mod tests {
         cpu.execute_instruction(&Intel8080Instruction::Out { byte: 0 })
             .unwrap();
     }
+
+    #[test]
+    fn it_should_fail_to_use_ports_without_a_device() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_a(42).unwrap();
+        match cpu.execute_in(0x10) {
+            Err(CpuError::InputDeviceNotConfigured { id: 0x10 }) => {}
+            result => panic!("Unexpected {:?}", result),
+        }
+        assert_eq!(cpu.get_current_a_value().unwrap(), 42);
+        match cpu.execute_out(0xff) {
+            Err(CpuError::OutputDeviceNotConfigured { id: 0xff }) => {}
+            result => panic!("Unexpected {:?}", result),
+        }
+    }
 }
@@ -1,6 +1,9 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 extern crate alloc;
+// no_std brings core in, tests build against std and have to ask for it
+#[cfg(test)]
+extern crate core;
 #[macro_use]
 extern crate cpu;
 #[macro_use]
@@ -18,8 +21,11 @@ mod io;
 mod logical;
 mod math;
 mod mov;
+mod opcode_table;
+mod save_state;
 mod stack;
 mod state;
+mod trace;
 
 #[derive(Debug, Fail)]
 pub enum CpuError {
@@ -38,8 +44,17 @@ pub enum CpuError {
     VirtualRegister { register: RegisterType },
     #[fail(display = "The instruction doesn't support that kind of cycle calculation.")]
     InvalidCyclesCalculation,
+    #[fail(display = "The save state is corrupted or truncated.")]
+    InvalidSaveState,
+    #[fail(display = "Unsupported save state version: {}", version)]
+    UnsupportedSaveStateVersion { version: u8 },
 }
 
-pub use cpu::{Cpu, InputDevice, Instruction, OutputDevice, WithPorts};
+pub use cpu::{
+    disassemble, Cpu, HookAction, InputDevice, Instruction, Memory, OutputDevice, PlainMemory,
+    RunStats, WithPorts,
+};
 pub use instruction::{Intel8080Instruction, Intel8080InstructionError};
 pub use intel8080cpu::*;
+pub use opcode_table::{OpcodeInfo, OperandKind, OPCODE_TABLE};
+pub use trace::{format_trace, TraceEntry};
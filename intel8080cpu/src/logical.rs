@@ -1,7 +1,8 @@
+use super::cpu::Memory;
 use super::CpuError;
-use intel8080cpu::{Intel8080Cpu, RegisterType};
+use intel8080cpu::{EmulationListener, Intel8080Cpu, RegisterType};
 
-impl<'a> Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
     pub(crate) fn execute_ana_by_register(
         &mut self,
         register_type: RegisterType,
@@ -283,6 +284,34 @@ mod tests {
         assert!(!cpu.flags.carry);
     }
 
+    #[test]
+    fn it_should_move_the_high_bit_to_carry_and_bit_zero_on_rlc() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_a(0x80).unwrap();
+        cpu.flags.carry = false;
+        cpu.flags.zero = false;
+        cpu.flags.sign = true;
+        cpu.execute_instruction(&Intel8080Instruction::Rlc).unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x01);
+        assert!(cpu.flags.carry);
+        assert!(!cpu.flags.zero);
+        assert!(cpu.flags.sign);
+    }
+
+    #[test]
+    fn it_should_rotate_the_carry_into_the_high_bit_on_rar() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_a(0x01).unwrap();
+        cpu.flags.carry = true;
+        cpu.flags.zero = true;
+        cpu.flags.sign = false;
+        cpu.execute_instruction(&Intel8080Instruction::Rar).unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x80);
+        assert!(cpu.flags.carry);
+        assert!(cpu.flags.zero);
+        assert!(!cpu.flags.sign);
+    }
+
     #[test]
     fn it_should_execute_xri() {
         let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
@@ -1,12 +1,11 @@
-use alloc::string::ToString;
+use super::cpu::Memory;
 use super::CpuError;
-use intel8080cpu::{Intel8080Cpu, RegisterType};
+use intel8080cpu::{EmulationListener, Intel8080Cpu, RegisterType};
 
-impl<'a> Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
     pub(crate) fn execute_aci(&mut self, byte: u8) -> Result<(), CpuError> {
-        let carry_as_u16 = self.flags.carry as u16;
-        let destiny_value = (u16::from(self.get_current_a_value()?) + carry_as_u16) & 0xff;
-        let new_value = self.perform_add_with_carry(u16::from(byte), destiny_value);
+        let destiny_value = u16::from(self.get_current_a_value()?);
+        let new_value = self.perform_adc(destiny_value, u16::from(byte));
         self.save_to_a(new_value)
     }
 
@@ -22,18 +21,14 @@ impl<'a> Intel8080Cpu<'a> {
     ) -> Result<(), CpuError> {
         let destiny_value = u16::from(self.get_current_a_value()?);
         let source_value = u16::from(self.get_current_single_register_value(register_type)?);
-        let carry_as_u16 = self.flags.carry as u16;
-        let new_value = self.perform_add_with_carry(source_value, destiny_value);
-        let new_value = self.perform_add_with_carry(carry_as_u16, u16::from(new_value));
+        let new_value = self.perform_adc(destiny_value, source_value);
         self.save_to_a(new_value)
     }
 
     pub(crate) fn execute_adc_by_memory(&mut self) -> Result<(), CpuError> {
         let destiny_value = u16::from(self.get_current_a_value()?);
         let source_value = u16::from(self.get_value_in_memory_at_hl());
-        let carry_as_u16 = self.flags.carry as u16;
-        let new_value = self.perform_add_with_carry(source_value, destiny_value);
-        let new_value = self.perform_add_with_carry(carry_as_u16, u16::from(new_value));
+        let new_value = self.perform_adc(destiny_value, source_value);
         self.save_to_a(new_value)
     }
 
@@ -81,21 +76,17 @@ impl<'a> Intel8080Cpu<'a> {
     }
 
     pub(crate) fn execute_daa(&mut self) -> Result<(), CpuError> {
-        let destiny_value = u16::from(self.get_current_a_value()?);
-        let mut least_significant = destiny_value & 0x0f;
-        let mut result = destiny_value;
-        if least_significant > 9 || self.flags.auxiliary_carry {
-            result += 6;
-            self.flags.auxiliary_carry = (least_significant + 6) > 0x0f;
-            least_significant = result & 0x0f;
+        let mut result = u16::from(self.get_current_a_value()?);
+        if (result & 0x0f) > 9 || self.flags.auxiliary_carry {
+            self.flags.auxiliary_carry = (result & 0x0f) + 0x06 > 0x0f;
+            result += 0x06;
         }
-        let mut most_significant = (result & 0xf0) >> 4;
-        if most_significant > 9 || self.flags.carry {
-            most_significant += 6;
-            self.flags.carry = most_significant > 0x0f;
-            most_significant &= 0x0f;
+        // The high nibble is checked after the low correction, which may have bumped it
+        if (result >> 4) > 9 || self.flags.carry {
+            result += 0x60;
+            // It never clears a carry that was already set
+            self.flags.carry = true;
         }
-        result = (most_significant << 4) | least_significant;
         self.update_flags(result, false);
         self.save_to_a(result as u8)
     }
@@ -160,25 +151,21 @@ impl<'a> Intel8080Cpu<'a> {
         register_type: RegisterType,
     ) -> Result<(), CpuError> {
         let destiny_value = u16::from(self.get_current_a_value()?);
-        let carry = self.flags.carry as u8;
-        let source_value =
-            u16::from(self.get_current_single_register_value(register_type)? + carry);
-        let new_value = self.perform_sub_with_carry(destiny_value, source_value);
+        let source_value = u16::from(self.get_current_single_register_value(register_type)?);
+        let new_value = self.perform_sbb(destiny_value, source_value);
         self.save_to_a(new_value)
     }
 
     pub(crate) fn execute_sbb_by_memory(&mut self) -> Result<(), CpuError> {
         let destiny_value = u16::from(self.get_current_a_value()?);
-        let carry = u8::from(self.flags.carry);
-        let source_value = u16::from(self.get_value_in_memory_at_hl() + carry);
-        let new_value = self.perform_sub_with_carry(destiny_value, source_value);
+        let source_value = u16::from(self.get_value_in_memory_at_hl());
+        let new_value = self.perform_sbb(destiny_value, source_value);
         self.save_to_a(new_value)
     }
 
     pub(crate) fn execute_sbi(&mut self, byte: u8) -> Result<(), CpuError> {
         let destiny_value = u16::from(self.get_current_a_value()?);
-        let add = u16::from(byte) + u16::from(self.flags.carry);
-        let new_value = self.perform_sub_with_carry(destiny_value, add);
+        let new_value = self.perform_sbb(destiny_value, u16::from(byte));
         self.save_to_a(new_value)
     }
 
@@ -205,14 +192,20 @@ impl<'a> Intel8080Cpu<'a> {
         self.save_to_a(new_value)
     }
 
+    #[inline]
+    fn perform_adc(&mut self, destiny: u16, source: u16) -> u8 {
+        let carry = u16::from(self.flags.carry);
+        self.perform_add(destiny, source, carry, true)
+    }
+
     #[inline]
     fn perform_add_with_carry(&mut self, destiny: u16, source: u16) -> u8 {
-        self.perform_add(destiny, source, true)
+        self.perform_add(destiny, source, 0, true)
     }
 
     #[inline]
     fn perform_add_without_carry(&mut self, destiny: u16, source: u16) -> u8 {
-        self.perform_add(destiny, source, false)
+        self.perform_add(destiny, source, 0, false)
     }
 
     #[inline]
@@ -226,7 +219,7 @@ impl<'a> Intel8080Cpu<'a> {
             RegisterType::D => u32::from(self.get_current_de_value()),
             RegisterType::H => u32::from(self.get_current_hl_value()),
             RegisterType::Sp => u32::from(self.get_current_sp_value()),
-            _ => panic!("{} is not a valid INX argument!", register_type.to_string()),
+            _ => panic!("{} is not a valid INX argument!", register_type),
         };
         let result = if inc {
             destiny_value.wrapping_add(1)
@@ -256,44 +249,44 @@ impl<'a> Intel8080Cpu<'a> {
         }
     }
 
+    // The carry goes in with the low nibble, so it can be the one carrying out of it
     #[inline]
-    fn perform_add(&mut self, destiny: u16, source: u16, with_carry: bool) -> u8 {
-        let answer: u16 = source + destiny;
+    fn perform_add(&mut self, destiny: u16, source: u16, carry: u16, with_carry: bool) -> u8 {
+        let answer: u16 = destiny + source + carry;
         self.update_flags(answer, with_carry);
-        self.update_auxiliary_carry(destiny, source);
+        self.flags.auxiliary_carry = (destiny & 0x0f) + (source & 0x0f) + carry > 0x0f;
         (answer & 0xff) as u8
     }
 
+    #[inline]
+    fn perform_sbb(&mut self, destiny: u16, source: u16) -> u8 {
+        let borrow = u16::from(self.flags.carry);
+        self.perform_sub(destiny, source, borrow, true)
+    }
+
     #[inline]
     fn perform_sub_with_carry(&mut self, destiny: u16, source: u16) -> u8 {
-        self.perform_sub(destiny, source, true)
+        self.perform_sub(destiny, source, 0, true)
     }
 
     #[inline]
     fn perform_sub_without_carry(&mut self, destiny: u16, source: u16) -> u8 {
-        self.perform_sub(destiny, source, false)
+        self.perform_sub(destiny, source, 0, false)
     }
 
+    // Like the 8080, it adds the complement of the source and the borrow. The carry flag is set
+    // when nothing carries out, and the auxiliary carry when the low nibble does
     #[inline]
-    fn perform_sub(&mut self, destiny: u16, source: u16, with_carry: bool) -> u8 {
-        let answer = destiny + (!source & 0xff) + 1;
+    fn perform_sub(&mut self, destiny: u16, source: u16, borrow: u16, with_carry: bool) -> u8 {
+        let not_borrow = 1 - borrow;
+        let answer = destiny + (!source & 0xff) + not_borrow;
         self.update_flags(answer, false);
         if with_carry {
             self.flags.carry = answer <= 0xff;
         }
-        self.update_auxiliary_carry_with_sub(destiny, source);
+        self.flags.auxiliary_carry = (destiny & 0x0f) + (!source & 0x0f) + not_borrow > 0x0f;
         (answer & 0xff) as u8
     }
-
-    #[inline]
-    fn update_auxiliary_carry_with_sub(&mut self, destiny: u16, source: u16) {
-        self.flags.auxiliary_carry = (destiny & 0x0f) + (!source & 0x0f) + 1 > 0x0f;
-    }
-
-    #[inline]
-    fn update_auxiliary_carry(&mut self, destiny: u16, source: u16) {
-        self.flags.auxiliary_carry = (destiny & 0x0f) + (source & 0x0f) > 0x0f;
-    }
 }
 
 #[cfg(test)]
@@ -480,6 +473,28 @@ mod tests {
         assert!(!cpu.flags.zero);
     }
 
+    #[test]
+    fn it_should_set_only_zero_when_comparing_equal_values() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_a(0x3c).unwrap();
+        cpu.save_to_single_register(0x3c, RegisterType::B).unwrap();
+        cpu.execute_instruction(&Intel8080Instruction::Cmp {
+            source: Location::Register {
+                register: RegisterType::B,
+            },
+        })
+        .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x3c);
+        assert!(cpu.flags.zero);
+        assert!(!cpu.flags.carry);
+        assert!(!cpu.flags.sign);
+        cpu.execute_instruction(&Intel8080Instruction::Cpi { byte: 0x3d })
+            .unwrap();
+        assert!(!cpu.flags.zero);
+        assert!(cpu.flags.carry);
+        assert!(cpu.flags.sign);
+    }
+
     #[test]
     fn it_should_execute_daa_without_carries_nor_change() {
         let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
@@ -503,7 +518,7 @@ mod tests {
         cpu.flags.carry = true;
         cpu.execute_instruction(&Intel8080Instruction::Daa).unwrap();
         assert_eq!(cpu.get_current_a_value().unwrap(), 0x76);
-        assert!(!cpu.flags.carry);
+        assert!(cpu.flags.carry);
         assert!(!cpu.flags.sign);
         assert!(!cpu.flags.parity);
         assert!(!cpu.flags.auxiliary_carry);
@@ -525,6 +540,39 @@ mod tests {
         assert!(!cpu.flags.zero);
     }
 
+    #[test]
+    fn it_should_execute_daa_when_the_low_correction_carries_into_the_high_nibble() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_a(0x9a).unwrap();
+        cpu.flags.auxiliary_carry = false;
+        cpu.flags.carry = false;
+        cpu.execute_instruction(&Intel8080Instruction::Daa).unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x00);
+        assert!(cpu.flags.carry);
+        assert!(cpu.flags.auxiliary_carry);
+        assert!(cpu.flags.zero);
+        assert!(cpu.flags.parity);
+        assert!(!cpu.flags.sign);
+    }
+
+    #[test]
+    fn it_should_execute_daa_with_an_invalid_low_nibble_and_auxiliary_carry() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_a(0x0f).unwrap();
+        cpu.flags.auxiliary_carry = true;
+        cpu.flags.carry = false;
+        cpu.execute_instruction(&Intel8080Instruction::Daa).unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x15);
+        assert!(cpu.flags.auxiliary_carry);
+        assert!(!cpu.flags.carry);
+        cpu.save_to_a(0x9f).unwrap();
+        cpu.flags.auxiliary_carry = true;
+        cpu.execute_instruction(&Intel8080Instruction::Daa).unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x05);
+        assert!(cpu.flags.auxiliary_carry);
+        assert!(cpu.flags.carry);
+    }
+
     #[test]
     fn it_should_execute_dad() {
         let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
@@ -544,6 +592,23 @@ mod tests {
         assert!(cpu.flags.zero);
     }
 
+    #[test]
+    fn it_should_only_set_carry_when_dad_overflows() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_single_register(0xff, RegisterType::H).unwrap();
+        cpu.save_to_single_register(0xff, RegisterType::L).unwrap();
+        cpu.registers.sp = 0x0002;
+        cpu.flags.carry = false;
+        cpu.flags.zero = false;
+        cpu.execute_instruction(&Intel8080Instruction::Dad {
+            register: RegisterType::Sp,
+        })
+        .unwrap();
+        assert_eq!(cpu.get_current_hl_value(), 0x0001);
+        assert!(cpu.flags.carry);
+        assert!(!cpu.flags.zero);
+    }
+
     #[test]
     fn it_should_execute_dcr_by_register() {
         let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
@@ -636,6 +701,61 @@ mod tests {
         assert!(!cpu.flags.zero);
     }
 
+    #[test]
+    fn it_should_keep_the_carry_on_inr_and_dcr() {
+        let inr = Intel8080Instruction::Inr {
+            source: Location::Register {
+                register: RegisterType::B,
+            },
+        };
+        let dcr = Intel8080Instruction::Dcr {
+            source: Location::Register {
+                register: RegisterType::B,
+            },
+        };
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_single_register(0x01, RegisterType::B).unwrap();
+        cpu.flags.carry = true;
+        cpu.execute_instruction(&dcr).unwrap();
+        assert_eq!(
+            cpu.get_current_single_register_value(RegisterType::B)
+                .unwrap(),
+            0x00
+        );
+        assert!(cpu.flags.carry);
+        assert!(cpu.flags.zero);
+        assert!(cpu.flags.parity);
+        cpu.execute_instruction(&inr).unwrap();
+        assert_eq!(
+            cpu.get_current_single_register_value(RegisterType::B)
+                .unwrap(),
+            0x01
+        );
+        assert!(cpu.flags.carry);
+        assert!(!cpu.flags.zero);
+        assert!(!cpu.flags.parity);
+        // Wrapping around doesn't set the carry either
+        cpu.flags.carry = false;
+        cpu.execute_instruction(&dcr).unwrap();
+        cpu.execute_instruction(&dcr).unwrap();
+        assert_eq!(
+            cpu.get_current_single_register_value(RegisterType::B)
+                .unwrap(),
+            0xff
+        );
+        assert!(!cpu.flags.carry);
+        assert!(cpu.flags.sign);
+        cpu.execute_instruction(&inr).unwrap();
+        assert_eq!(
+            cpu.get_current_single_register_value(RegisterType::B)
+                .unwrap(),
+            0x00
+        );
+        assert!(!cpu.flags.carry);
+        assert!(cpu.flags.zero);
+        assert!(cpu.flags.auxiliary_carry);
+    }
+
     #[test]
     fn it_should_execute_inr_by_memory() {
         let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
@@ -671,6 +791,22 @@ mod tests {
         assert!(cpu.flags.zero);
     }
 
+    #[test]
+    fn it_should_wrap_inx_without_touching_flags() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_single_register(0xff, RegisterType::H).unwrap();
+        cpu.save_to_single_register(0xff, RegisterType::L).unwrap();
+        cpu.flags.carry = false;
+        cpu.flags.zero = false;
+        cpu.execute_instruction(&Intel8080Instruction::Inx {
+            register: RegisterType::H,
+        })
+        .unwrap();
+        assert_eq!(cpu.get_current_hl_value(), 0x0000);
+        assert!(!cpu.flags.carry);
+        assert!(!cpu.flags.zero);
+    }
+
     #[test]
     fn it_should_execute_sbb_by_register() {
         let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
@@ -691,6 +827,118 @@ mod tests {
         assert!(!cpu.flags.zero);
     }
 
+    #[test]
+    fn it_should_borrow_when_subtracting_0xff_with_carry() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_a(0x00).unwrap();
+        cpu.save_to_single_register(0xff, RegisterType::B).unwrap();
+        cpu.flags.carry = true;
+        cpu.execute_instruction(&Intel8080Instruction::Sbb {
+            source: Location::Register {
+                register: RegisterType::B,
+            },
+        })
+        .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x00);
+        assert!(cpu.flags.carry);
+        assert!(!cpu.flags.sign);
+        assert!(cpu.flags.parity);
+        assert!(cpu.flags.zero);
+
+        cpu.save_to_single_register(0x0, RegisterType::H).unwrap();
+        cpu.save_to_single_register(0x0, RegisterType::L).unwrap();
+        cpu.memory[0] = 0xff;
+        cpu.save_to_a(0x01).unwrap();
+        cpu.flags.carry = true;
+        cpu.execute_instruction(&Intel8080Instruction::Sbb {
+            source: Location::Memory,
+        })
+        .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x01);
+        assert!(cpu.flags.carry);
+        assert!(!cpu.flags.zero);
+    }
+
+    #[test]
+    fn it_should_carry_into_the_high_nibble_with_the_carry_of_adc_and_aci() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_a(0x0f).unwrap();
+        cpu.save_to_single_register(0x01, RegisterType::B).unwrap();
+        cpu.flags.carry = true;
+        cpu.execute_instruction(&Intel8080Instruction::Adc {
+            source: Location::Register {
+                register: RegisterType::B,
+            },
+        })
+        .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x11);
+        assert!(cpu.flags.auxiliary_carry);
+        assert!(!cpu.flags.carry);
+
+        cpu.save_to_a(0x08).unwrap();
+        cpu.flags.carry = true;
+        cpu.execute_instruction(&Intel8080Instruction::Aci { byte: 0x07 })
+            .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x10);
+        assert!(cpu.flags.auxiliary_carry);
+
+        cpu.save_to_a(0xff).unwrap();
+        cpu.flags.carry = true;
+        cpu.execute_instruction(&Intel8080Instruction::Aci { byte: 0x00 })
+            .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x00);
+        assert!(cpu.flags.auxiliary_carry);
+        assert!(cpu.flags.carry);
+        assert!(cpu.flags.zero);
+    }
+
+    #[test]
+    fn it_should_set_the_auxiliary_carry_of_subtractions_like_the_8080() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_a(0x13).unwrap();
+        cpu.save_to_single_register(0x01, RegisterType::B).unwrap();
+        cpu.execute_instruction(&Intel8080Instruction::Sub {
+            source: Location::Register {
+                register: RegisterType::B,
+            },
+        })
+        .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x12);
+        assert!(cpu.flags.auxiliary_carry);
+
+        cpu.save_to_a(0x10).unwrap();
+        cpu.execute_instruction(&Intel8080Instruction::Sub {
+            source: Location::Register {
+                register: RegisterType::B,
+            },
+        })
+        .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x0f);
+        assert!(!cpu.flags.auxiliary_carry);
+
+        // The borrow counts with the low nibble, 0x10 - 0x0f - 1 doesn't carry out of it
+        cpu.save_to_a(0x10).unwrap();
+        cpu.save_to_single_register(0x0f, RegisterType::B).unwrap();
+        cpu.flags.carry = true;
+        cpu.execute_instruction(&Intel8080Instruction::Sbb {
+            source: Location::Register {
+                register: RegisterType::B,
+            },
+        })
+        .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x00);
+        assert!(!cpu.flags.auxiliary_carry);
+        assert!(!cpu.flags.carry);
+
+        cpu.save_to_a(0x05).unwrap();
+        cpu.flags.carry = true;
+        cpu.execute_instruction(&Intel8080Instruction::Sbi { byte: 0x02 })
+            .unwrap();
+        assert_eq!(cpu.get_current_a_value().unwrap(), 0x02);
+        assert!(cpu.flags.auxiliary_carry);
+        assert!(!cpu.flags.carry);
+    }
+
     #[test]
     fn it_should_execute_sbb_by_memory() {
         let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
@@ -1,12 +1,12 @@
-use alloc::string::ToString;
+use super::cpu::Memory;
 use super::CpuError;
 use helpers::two_bytes_to_word;
-use intel8080cpu::{Intel8080Cpu, Location, RegisterType};
+use intel8080cpu::{EmulationListener, Intel8080Cpu, Location, RegisterType};
 
-impl<'a> Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
     pub(crate) fn execute_lda(&mut self, high_byte: u8, low_byte: u8) -> Result<(), CpuError> {
-        let source_address = two_bytes_to_word(high_byte, low_byte) as usize;
-        let value = self.memory[source_address];
+        let source_address = two_bytes_to_word(high_byte, low_byte);
+        let value = self.read_memory(source_address);
         self.save_to_a(value)
     }
 
@@ -16,17 +16,17 @@ impl<'a> Intel8080Cpu<'a> {
             RegisterType::D => self.get_current_de_value(),
             _ => panic!(
                 "Register {} is not a valid input of LDAX",
-                register.to_string()
+                register
             ),
-        } as usize;
-        let value = self.memory[source_address];
+        };
+        let value = self.read_memory(source_address);
         self.save_to_a(value)
     }
 
     pub(crate) fn execute_lhld(&mut self, high_byte: u8, low_byte: u8) -> Result<(), CpuError> {
-        let destiny_address = two_bytes_to_word(high_byte, low_byte) as usize;
-        let l_value = self.memory[destiny_address];
-        let h_value = self.memory[destiny_address + 1];
+        let destiny_address = two_bytes_to_word(high_byte, low_byte);
+        let l_value = self.read_memory(destiny_address);
+        let h_value = self.read_memory(destiny_address.wrapping_add(1));
         self.save_to_single_register(h_value, RegisterType::H)?;
         self.save_to_single_register(l_value, RegisterType::L)
     }
@@ -83,15 +83,15 @@ impl<'a> Intel8080Cpu<'a> {
     #[inline]
     pub(crate) fn execute_mvi_to_memory(&mut self, byte: u8) {
         let address = self.get_current_hl_value();
-        self.memory[address as usize] = byte;
+        self.write_memory(address, byte);
     }
 
     pub(crate) fn execute_shld(&mut self, high_byte: u8, low_byte: u8) -> Result<(), CpuError> {
         let h_value = self.get_current_single_register_value(RegisterType::H)?;
         let l_value = self.get_current_single_register_value(RegisterType::L)?;
-        let destiny_address = two_bytes_to_word(high_byte, low_byte) as usize;
-        self.memory[destiny_address] = l_value;
-        self.memory[destiny_address + 1] = h_value;
+        let destiny_address = two_bytes_to_word(high_byte, low_byte);
+        self.write_memory(destiny_address, l_value);
+        self.write_memory(destiny_address.wrapping_add(1), h_value);
         Ok(())
     }
 
@@ -103,7 +103,7 @@ impl<'a> Intel8080Cpu<'a> {
     pub(crate) fn execute_sta(&mut self, high_byte: u8, low_byte: u8) -> Result<(), CpuError> {
         let value = self.get_current_a_value()?;
         let destiny_address = two_bytes_to_word(high_byte, low_byte);
-        self.memory[destiny_address as usize] = value;
+        self.write_memory(destiny_address, value);
         Ok(())
     }
 
@@ -114,10 +114,10 @@ impl<'a> Intel8080Cpu<'a> {
             RegisterType::D => self.get_current_de_value(),
             _ => panic!(
                 "Register {} is not a valid input of STAX",
-                register.to_string()
+                register
             ),
-        } as usize;
-        self.memory[destiny_address] = value;
+        };
+        self.write_memory(destiny_address, value);
         Ok(())
     }
 
@@ -133,13 +133,16 @@ impl<'a> Intel8080Cpu<'a> {
     }
 
     pub(crate) fn execute_xthl(&mut self) -> Result<(), CpuError> {
-        let sp = self.get_current_sp_value() as usize;
-        let first_byte = self.memory[sp + 1];
-        let second_byte = self.memory[sp];
+        let sp = self.get_current_sp_value();
+        if sp == 0xffff {
+            self.report_stack_wrap(sp);
+        }
+        let first_byte = self.read_memory(sp.wrapping_add(1));
+        let second_byte = self.read_memory(sp);
         let h_value = self.get_current_single_register_value(RegisterType::H)?;
         let l_value = self.get_current_single_register_value(RegisterType::L)?;
-        self.memory[sp + 1] = h_value;
-        self.memory[sp] = l_value;
+        self.write_memory(sp.wrapping_add(1), h_value);
+        self.write_memory(sp, l_value);
         self.save_to_single_register(first_byte, RegisterType::H)?;
         self.save_to_single_register(second_byte, RegisterType::L)
     }
@@ -189,7 +192,7 @@ mod tests {
             }
             _ => panic!(
                 "Register {} is not a valid argument to ldax.",
-                register.to_string()
+                register
             ),
         };
         cpu
@@ -209,7 +212,7 @@ mod tests {
             }
             _ => panic!(
                 "Register {} is not a valid argument to stax.",
-                register.to_string()
+                register
             ),
         };
         cpu
@@ -490,4 +493,20 @@ mod tests {
             .unwrap();
         assert_eq!(cpu.get_current_hl_value(), 0x2442);
     }
+
+    #[test]
+    fn it_should_wrap_xthl_around_the_top_of_memory() {
+        let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        cpu.save_to_sp(0xffff);
+        cpu.memory[0xffff] = 0x42;
+        cpu.memory[0x0000] = 0x24;
+        cpu.save_to_single_register(0x0b, RegisterType::H).unwrap();
+        cpu.save_to_single_register(0x3c, RegisterType::L).unwrap();
+        cpu.execute_instruction(&Intel8080Instruction::Xthl)
+            .unwrap();
+        assert_eq!(cpu.get_current_hl_value(), 0x2442);
+        assert_eq!(cpu.memory[0xffff], 0x3c);
+        assert_eq!(cpu.memory[0x0000], 0x0b);
+        assert_eq!(cpu.get_current_sp_value(), 0xffff);
+    }
 }
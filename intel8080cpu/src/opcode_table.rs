@@ -0,0 +1,156 @@
+use super::cpu::Cycles;
+use instruction::is_undocumented_opcode;
+
+// What follows the opcode byte
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OperandKind {
+    None,
+    // An immediate byte or a port
+    Byte,
+    // An address or an immediate word, low byte first
+    Word,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    pub size: u8,
+    pub cycles: Cycles,
+    pub kind: OperandKind,
+}
+
+// What the decoder makes of every opcode, for tools that want it as data. Undocumented opcodes
+// are NOPs, like the decoder reads them
+pub static OPCODE_TABLE: [OpcodeInfo; 256] = opcode_table();
+
+// Indexed by the three bits in the middle of the opcode
+const ROTATES: [&str; 8] = ["RLC", "RRC", "RAL", "RAR", "DAA", "CMA", "STC", "CMC"];
+const ALU: [&str; 8] = ["ADD", "ADC", "SUB", "SBB", "ANA", "XRA", "ORA", "CMP"];
+const ALU_IMMEDIATES: [&str; 8] = ["ADI", "ACI", "SUI", "SBI", "ANI", "XRI", "ORI", "CPI"];
+const RETURNS: [&str; 8] = ["RNZ", "RZ", "RNC", "RC", "RPO", "RPE", "RP", "RM"];
+const JUMPS: [&str; 8] = ["JNZ", "JZ", "JNC", "JC", "JPO", "JPE", "JP", "JM"];
+const CALLS: [&str; 8] = ["CNZ", "CZ", "CNC", "CC", "CPO", "CPE", "CP", "CM"];
+// The register fields of an opcode pick the memory at HL with this value
+const MEMORY: u8 = 6;
+
+const fn opcode_table() -> [OpcodeInfo; 256] {
+    let mut table = [opcode_info(0); 256];
+    let mut opcode = 0;
+    while opcode < 256 {
+        table[opcode] = opcode_info(opcode as u8);
+        opcode += 1;
+    }
+    table
+}
+
+const fn info(mnemonic: &'static str, kind: OperandKind, cycles: Cycles) -> OpcodeInfo {
+    let size = match kind {
+        OperandKind::None => 1,
+        OperandKind::Byte => 2,
+        OperandKind::Word => 3,
+    };
+    OpcodeInfo {
+        mnemonic,
+        size,
+        cycles,
+        kind,
+    }
+}
+
+// Memory operands take longer than register ones
+const fn by_location(memory: bool, register: u8, in_memory: u8) -> Cycles {
+    single!(if memory { in_memory } else { register })
+}
+
+const fn opcode_info(opcode: u8) -> OpcodeInfo {
+    let middle = (opcode >> 3) & 0x07;
+    let memory_destiny = middle == MEMORY;
+    let memory_source = opcode & 0x07 == MEMORY;
+    let name = middle as usize;
+    if is_undocumented_opcode(opcode) {
+        return info("NOP", OperandKind::None, single!(4));
+    }
+    match opcode {
+        0x76 => info("HLT", OperandKind::None, single!(7)),
+        0x40..=0x7f => info(
+            "MOV",
+            OperandKind::None,
+            by_location(memory_source || memory_destiny, 5, 7),
+        ),
+        0x80..=0xbf => info(
+            ALU[name],
+            OperandKind::None,
+            by_location(memory_source, 4, 7),
+        ),
+        0x00..=0x3f => match opcode & 0x0f {
+            0x01 => info("LXI", OperandKind::Word, single!(10)),
+            0x02 => match opcode {
+                0x22 => info("SHLD", OperandKind::Word, single!(16)),
+                0x32 => info("STA", OperandKind::Word, single!(13)),
+                _ => info("STAX", OperandKind::None, single!(7)),
+            },
+            0x03 => info("INX", OperandKind::None, single!(5)),
+            0x04 | 0x0c => info("INR", OperandKind::None, by_location(memory_destiny, 5, 10)),
+            0x05 | 0x0d => info("DCR", OperandKind::None, by_location(memory_destiny, 5, 10)),
+            0x06 | 0x0e => info("MVI", OperandKind::Byte, by_location(memory_destiny, 7, 10)),
+            0x07 | 0x0f => info(ROTATES[name], OperandKind::None, single!(4)),
+            0x09 => info("DAD", OperandKind::None, single!(10)),
+            0x0a => match opcode {
+                0x2a => info("LHLD", OperandKind::Word, single!(16)),
+                0x3a => info("LDA", OperandKind::Word, single!(13)),
+                _ => info("LDAX", OperandKind::None, single!(7)),
+            },
+            0x0b => info("DCX", OperandKind::None, single!(5)),
+            _ => info("NOP", OperandKind::None, single!(4)),
+        },
+        _ => match opcode & 0x07 {
+            0x00 => info(RETURNS[name], OperandKind::None, conditional!(5, 11)),
+            0x01 => match opcode {
+                0xc9 => info("RET", OperandKind::None, single!(10)),
+                0xe9 => info("PCHL", OperandKind::None, single!(5)),
+                0xf9 => info("SPHL", OperandKind::None, single!(5)),
+                _ => info("POP", OperandKind::None, single!(10)),
+            },
+            0x02 => info(JUMPS[name], OperandKind::Word, single!(10)),
+            0x03 => match opcode {
+                0xc3 => info("JMP", OperandKind::Word, single!(10)),
+                0xd3 => info("OUT", OperandKind::Byte, single!(10)),
+                0xdb => info("IN", OperandKind::Byte, single!(10)),
+                0xe3 => info("XTHL", OperandKind::None, single!(18)),
+                0xeb => info("XCHG", OperandKind::None, single!(4)),
+                0xf3 => info("DI", OperandKind::None, single!(4)),
+                _ => info("EI", OperandKind::None, single!(4)),
+            },
+            0x04 => info(CALLS[name], OperandKind::Word, conditional!(11, 17)),
+            0x05 => match opcode {
+                0xcd => info("CALL", OperandKind::Word, single!(17)),
+                _ => info("PUSH", OperandKind::None, single!(11)),
+            },
+            0x06 => info(ALU_IMMEDIATES[name], OperandKind::Byte, single!(7)),
+            _ => info("RST", OperandKind::None, single!(11)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cpu::Instruction;
+    use instruction::Intel8080Instruction;
+    use opcode_table::OPCODE_TABLE;
+
+    #[test]
+    fn it_should_agree_with_the_decoder() {
+        for (opcode, info) in OPCODE_TABLE.iter().enumerate() {
+            let instruction = Intel8080Instruction::from(&[opcode as u8, 0x34, 0x12][..]);
+            let text = instruction.to_string();
+            assert_eq!(
+                text.split(' ').next().unwrap(),
+                info.mnemonic,
+                "opcode {:02x}",
+                opcode
+            );
+            assert_eq!(instruction.size().unwrap(), info.size, "{}", text);
+            assert_eq!(instruction.get_cycles().unwrap(), info.cycles, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use alloc::vec::Vec;
+use super::cpu::Memory;
+use helpers::{two_bytes_to_word, word_to_address};
+use intel8080cpu::{EmulationListener, Flags, Intel8080Cpu, State};
+use super::CpuError;
+
+// 2 stores the flags in the documented layout, the interruption delay and memory of any size
+const SAVE_STATE_VERSION: u8 = 2;
+// Version, A to L, SP, PC, flags, interruptions, state and previous state
+const HEADER_SIZE: usize = 1 + 7 + 2 + 2 + 1 + 1 + 1 + 1;
+
+impl State {
+    fn to_byte(self) -> u8 {
+        match self {
+            State::Running => 0,
+            State::Stopped => 1,
+            State::HardStop => 2,
+            State::Halted => 3,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<State, CpuError> {
+        match byte {
+            0 => Ok(State::Running),
+            1 => Ok(State::Stopped),
+            2 => Ok(State::HardStop),
+            3 => Ok(State::Halted),
+            _ => Err(CpuError::InvalidSaveState),
+        }
+    }
+}
+
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
+    // Devices, listeners and configuration aren't part of the snapshot
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(HEADER_SIZE + self.memory.len());
+        state.push(SAVE_STATE_VERSION);
+        state.extend_from_slice(&[
+            self.registers.a,
+            self.registers.b,
+            self.registers.c,
+            self.registers.d,
+            self.registers.e,
+            self.registers.h,
+            self.registers.l,
+        ]);
+        state.extend_from_slice(&word_to_address(self.registers.sp));
+        state.extend_from_slice(&word_to_address(self.pc));
+        state.push(self.flags.to_byte());
+        state.push(self.interruptions_enabled as u8 | (self.interruption_delay as u8) << 1);
+        state.push(self.state.to_byte());
+        state.push(self.prev_state.to_byte());
+        state.extend((0..self.memory.len()).map(|address| self.memory.get(address as u16)));
+        state
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), CpuError> {
+        if state.first() != Some(&SAVE_STATE_VERSION) {
+            return Err(CpuError::UnsupportedSaveStateVersion {
+                version: state.first().cloned().unwrap_or(0),
+            });
+        }
+        // The memory in the snapshot has to be as big as this cpu's
+        if state.len() != HEADER_SIZE + self.memory.len() {
+            return Err(CpuError::InvalidSaveState);
+        }
+        // Validate everything before touching the cpu, so a bad snapshot leaves it untouched
+        let cpu_state = State::from_byte(state[14])?;
+        let prev_state = State::from_byte(state[15])?;
+        self.registers.a = state[1];
+        self.registers.b = state[2];
+        self.registers.c = state[3];
+        self.registers.d = state[4];
+        self.registers.e = state[5];
+        self.registers.h = state[6];
+        self.registers.l = state[7];
+        self.registers.sp = two_bytes_to_word(state[9], state[8]);
+        self.pc = two_bytes_to_word(state[11], state[10]);
+        self.flags = Flags::from_byte(state[12]);
+        self.interruptions_enabled = state[13] & 0x01 == 0x01;
+        self.interruption_delay = state[13] & 0x02 == 0x02;
+        self.state = cpu_state;
+        self.prev_state = prev_state;
+        for (address, byte) in state[HEADER_SIZE..].iter().enumerate() {
+            self.memory.set(address as u16, *byte);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cpu::{Cpu, OutputDevice, WithPorts};
+    use super::HEADER_SIZE;
+    use intel8080cpu::{Intel8080Cpu, MEMORY_SIZE, ROM_MEMORY_LIMIT};
+    use std::boxed::Box;
+    use std::string::String;
+    use std::sync::{Arc, Mutex};
+    use std::vec::Vec;
+    use CpuError;
+
+    struct RecordingOutputDevice {
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl OutputDevice for RecordingOutputDevice {
+        fn write(&mut self, new_value: u8) {
+            self.written.lock().unwrap().push(new_value);
+        }
+    }
+
+    // Writes an increasing counter to memory and to the device on port 1
+    fn counting_cpu<'a>() -> (Intel8080Cpu<'a>, Arc<Mutex<Vec<u8>>>) {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..15].copy_from_slice(&[
+            0x3e, 0x00, // MVI A, 00H
+            0x21, 0x00, 0x20, // LXI H, 2000H
+            0x3c, // INR A
+            0x77, // MOV M, A
+            0x23, // INX H
+            0xd3, 0x01, // OUT 1
+            0xc6, 0x03, // ADI 03H
+            0xc3, 0x05, 0x00, // JMP 0005H
+        ]);
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let mut cpu = Intel8080Cpu::new(rom);
+        cpu.add_output_device(
+            1,
+            Box::new(RecordingOutputDevice {
+                written: written.clone(),
+            }),
+        );
+        (cpu, written)
+    }
+
+    fn run(cpu: &mut Intel8080Cpu, instructions: usize) -> Vec<String> {
+        (0..instructions)
+            .map(|_| {
+                cpu.execute().unwrap();
+                cpu.get_debug_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_should_behave_the_same_after_restoring_a_state() {
+        let (mut cpu, written) = counting_cpu();
+        run(&mut cpu, 50);
+        let state = cpu.save_state();
+        assert_eq!(state.len(), HEADER_SIZE + MEMORY_SIZE);
+        let written_before = written.lock().unwrap().len();
+        let expected = run(&mut cpu, 100);
+        let expected_memory = cpu.memory.to_vec();
+        let expected_output = written.lock().unwrap()[written_before..].to_vec();
+
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.save_state(), state);
+        let written_after = written.lock().unwrap().len();
+        assert_eq!(run(&mut cpu, 100), expected);
+        assert_eq!(cpu.memory.to_vec(), expected_memory);
+        // The output device survives the restore and sees the same writes again
+        assert_eq!(written.lock().unwrap()[written_after..].to_vec(), expected_output);
+    }
+
+    #[test]
+    fn it_should_replace_the_memory_and_registers_of_another_cpu() {
+        let (mut cpu, _) = counting_cpu();
+        run(&mut cpu, 20);
+        cpu.interruptions_enabled = false;
+        let state = cpu.save_state();
+        let mut other = Intel8080Cpu::new([0xff; ROM_MEMORY_LIMIT]);
+        other.load_state(&state).unwrap();
+        assert_eq!(other.get_debug_string(), cpu.get_debug_string());
+        assert_eq!(other.memory.to_vec(), cpu.memory.to_vec());
+        assert!(!other.interruptions_enabled);
+        assert_eq!(other.save_state(), state);
+    }
+
+    #[test]
+    fn it_should_reject_invalid_states() {
+        let (mut cpu, _) = counting_cpu();
+        run(&mut cpu, 10);
+        let mut state = cpu.save_state();
+        let debug_string = cpu.get_debug_string();
+        match cpu.load_state(&state[..100]) {
+            Err(CpuError::InvalidSaveState) => {}
+            _ => panic!("A truncated state should be rejected"),
+        }
+        state[14] = 0x42;
+        match cpu.load_state(&state) {
+            Err(CpuError::InvalidSaveState) => {}
+            _ => panic!("An unknown cpu state should be rejected"),
+        }
+        state[0] = 3;
+        match cpu.load_state(&state) {
+            Err(CpuError::UnsupportedSaveStateVersion { version: 3 }) => {}
+            _ => panic!("A newer version should be rejected"),
+        }
+        assert!(cpu.load_state(&[]).is_err());
+        assert_eq!(cpu.get_debug_string(), debug_string);
+    }
+
+    #[test]
+    fn it_should_reject_states_of_the_first_version() {
+        let (mut cpu, _) = counting_cpu();
+        run(&mut cpu, 10);
+        let mut state = cpu.save_state();
+        assert_eq!(state[0], 2);
+        let debug_string = cpu.get_debug_string();
+        state[0] = 1;
+        match cpu.load_state(&state) {
+            Err(CpuError::UnsupportedSaveStateVersion { version: 1 }) => {}
+            _ => panic!("A state with the old flags layout should be rejected"),
+        }
+        assert_eq!(cpu.get_debug_string(), debug_string);
+    }
+
+    #[test]
+    fn it_should_only_restore_states_of_the_same_memory_size() {
+        let cpu = Intel8080Cpu::with_memory_size(&[0x3c, 0x3c], 0x4000);
+        let state = cpu.save_state();
+        assert_eq!(state.len(), HEADER_SIZE + 0x4000);
+        let mut other = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        match other.load_state(&state) {
+            Err(CpuError::InvalidSaveState) => {}
+            _ => panic!("A state with less memory should be rejected"),
+        }
+        let mut same_size = Intel8080Cpu::with_memory_size(&[], 0x4000);
+        same_size.load_state(&state).unwrap();
+        assert_eq!(same_size.memory, cpu.memory);
+    }
+}
@@ -1,9 +1,9 @@
+use super::cpu::Memory;
 use super::CpuError;
-use intel8080cpu::{Intel8080Cpu, RegisterType};
+use intel8080cpu::{Diagnostic, EmulationListener, Flags, Intel8080Cpu, RegisterType};
 
-impl<'a> Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
     pub(crate) fn execute_push(&mut self, register: RegisterType) -> Result<(), CpuError> {
-        let sp = self.get_current_sp_value() as usize;
         let (first_byte, second_byte) = match register {
             RegisterType::B => Ok((
                 self.get_current_single_register_value(RegisterType::B)?,
@@ -17,20 +17,15 @@ impl<'a> Intel8080Cpu<'a> {
                 self.get_current_single_register_value(RegisterType::H)?,
                 self.get_current_single_register_value(RegisterType::L)?,
             )),
-            RegisterType::Psw => Ok((self.get_current_a_value()?, self.get_current_flags_byte())),
+            RegisterType::Psw => Ok((self.get_current_a_value()?, self.flags.to_byte())),
             _ => Err(CpuError::InvalidRegisterArgument { register }),
         }?;
-        self.memory[sp - 1] = first_byte;
-        self.memory[sp - 2] = second_byte;
-        self.save_to_sp((sp - 2) as u16);
+        self.push_to_stack(first_byte, second_byte);
         Ok(())
     }
 
     pub(crate) fn execute_pop(&mut self, register: RegisterType) -> Result<(), CpuError> {
-        let sp = self.get_current_sp_value() as usize;
-        let first_byte = self.memory[sp + 1];
-        let second_byte = self.memory[sp];
-        self.save_to_sp((sp + 2) as u16);
+        let (first_byte, second_byte) = self.pop_from_stack();
         match register {
             RegisterType::B => {
                 self.save_to_single_register(first_byte, RegisterType::B)?;
@@ -45,7 +40,7 @@ impl<'a> Intel8080Cpu<'a> {
                 self.save_to_single_register(second_byte, RegisterType::L)
             }
             RegisterType::Psw => {
-                self.set_flags_byte(second_byte);
+                self.flags = Flags::from_byte(second_byte);
                 self.save_to_a(first_byte)
             }
             _ => Err(CpuError::InvalidRegisterArgument { register }),
@@ -53,21 +48,32 @@ impl<'a> Intel8080Cpu<'a> {
     }
 
     #[inline]
-    fn get_current_flags_byte(&self) -> u8 {
-        (self.flags.zero as u8)
-            | (self.flags.sign as u8) << 1
-            | (self.flags.parity as u8) << 2
-            | (self.flags.carry as u8) << 3
-            | (self.flags.auxiliary_carry as u8) << 4
+    pub(crate) fn push_to_stack(&mut self, high_byte: u8, low_byte: u8) {
+        let sp = self.get_current_sp_value();
+        if sp < 2 {
+            self.report_stack_wrap(sp);
+        }
+        self.write_memory(sp.wrapping_sub(1), high_byte);
+        self.write_memory(sp.wrapping_sub(2), low_byte);
+        self.save_to_sp(sp.wrapping_sub(2));
     }
 
     #[inline]
-    fn set_flags_byte(&mut self, byte: u8) {
-        self.flags.zero = (byte & 0x01) == 0x01;
-        self.flags.sign = (byte & 0x02) == 0x02;
-        self.flags.parity = (byte & 0x04) == 0x04;
-        self.flags.carry = (byte & 0x08) == 0x08;
-        self.flags.auxiliary_carry = (byte & 0x10) == 0x10;
+    pub(crate) fn pop_from_stack(&mut self) -> (u8, u8) {
+        let sp = self.get_current_sp_value();
+        if sp > 0xfffd {
+            self.report_stack_wrap(sp);
+        }
+        let high_byte = self.read_memory(sp.wrapping_add(1));
+        let low_byte = self.read_memory(sp);
+        self.save_to_sp(sp.wrapping_add(2));
+        (high_byte, low_byte)
+    }
+
+    pub(crate) fn report_stack_wrap(&mut self, sp: u16) {
+        if self.strict {
+            self.notify_listeners(|l| l.on_diagnostic(Diagnostic::StackWrapped { sp }));
+        }
     }
 }
 
@@ -75,7 +81,9 @@ impl<'a> Intel8080Cpu<'a> {
 mod tests {
     use super::super::cpu::Cpu;
     use instruction::Intel8080Instruction;
-    use intel8080cpu::{Intel8080Cpu, RegisterType, ROM_MEMORY_LIMIT};
+    use intel8080cpu::{
+        Diagnostic, EmulationListener, Flags, Intel8080Cpu, RegisterType, ROM_MEMORY_LIMIT,
+    };
 
     fn get_pop_ready_cpu<'a>() -> Intel8080Cpu<'a> {
         let mut memory = [0; ROM_MEMORY_LIMIT];
@@ -111,7 +119,7 @@ mod tests {
             }
             _ => panic!(
                 "Register {} is not an argument for PUSH.",
-                register.to_string()
+                register
             ),
         }
         cpu.save_to_sp(0x3A2C);
@@ -154,6 +162,7 @@ mod tests {
     #[test]
     fn it_should_pop_from_stack_to_a_and_flags() {
         let mut cpu = get_pop_ready_cpu();
+        cpu.memory[0x1239] = 0x57;
         cpu.execute_instruction(&Intel8080Instruction::Pop {
             register: RegisterType::Psw,
         })
@@ -211,7 +220,114 @@ mod tests {
         })
         .unwrap();
         assert_eq!(cpu.memory[0x3a2b], 0x8f);
-        assert_eq!(cpu.memory[0x3a2a], 0x1d);
+        assert_eq!(cpu.memory[0x3a2a], 0x57);
         assert_eq!(cpu.get_current_sp_value(), 0x3A2A);
     }
+
+    #[test]
+    fn it_should_keep_the_fixed_bits_of_the_flags_byte() {
+        let cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+        assert_eq!(cpu.flags.to_byte(), 0xd7);
+        assert_eq!(Flags::from_byte(0x00).to_byte(), 0x02);
+        assert_eq!(Flags::from_byte(0xff).to_byte(), 0xd7);
+    }
+
+    #[test]
+    fn it_should_restore_every_flag_after_push_and_pop_psw() {
+        for byte in 0..0x20u8 {
+            let mut cpu = get_push_ready_cpu(RegisterType::Psw);
+            cpu.flags.sign = byte & 0x01 == 0x01;
+            cpu.flags.zero = byte & 0x02 == 0x02;
+            cpu.flags.parity = byte & 0x04 == 0x04;
+            cpu.flags.carry = byte & 0x08 == 0x08;
+            cpu.flags.auxiliary_carry = byte & 0x10 == 0x10;
+            let expected = cpu.flags.to_byte();
+            cpu.execute_instruction(&Intel8080Instruction::Push {
+                register: RegisterType::Psw,
+            })
+            .unwrap();
+            cpu.flags = Flags::from_byte(!expected);
+            cpu.save_to_a(0).unwrap();
+            cpu.execute_instruction(&Intel8080Instruction::Pop {
+                register: RegisterType::Psw,
+            })
+            .unwrap();
+            assert_eq!(cpu.flags.to_byte(), expected);
+            assert_eq!(cpu.get_current_a_value().unwrap(), 0x8f);
+            assert_eq!(cpu.get_current_sp_value(), 0x3A2C);
+        }
+    }
+
+    #[test]
+    fn it_should_wrap_push_around_the_bottom_of_memory() {
+        for &(sp, high_address, low_address) in
+            [(0x0000, 0xffff, 0xfffe), (0x0001, 0x0000, 0xffff), (0xffff, 0xfffe, 0xfffd)].iter()
+        {
+            let mut cpu = get_push_ready_cpu(RegisterType::B);
+            cpu.save_to_sp(sp);
+            cpu.execute_instruction(&Intel8080Instruction::Push {
+                register: RegisterType::B,
+            })
+            .unwrap();
+            assert_eq!(cpu.memory[high_address], 0x8f);
+            assert_eq!(cpu.memory[low_address], 0x9d);
+            assert_eq!(cpu.get_current_sp_value(), sp.wrapping_sub(2));
+        }
+    }
+
+    #[test]
+    fn it_should_wrap_pop_around_the_top_of_memory() {
+        for &(sp, high_address, low_address) in
+            [(0x0000, 0x0001, 0x0000), (0x0001, 0x0002, 0x0001), (0xffff, 0x0000, 0xffff)].iter()
+        {
+            let mut cpu = Intel8080Cpu::new([0; ROM_MEMORY_LIMIT]);
+            cpu.memory[high_address] = 0x93;
+            cpu.memory[low_address] = 0x3d;
+            cpu.save_to_sp(sp);
+            cpu.execute_instruction(&Intel8080Instruction::Pop {
+                register: RegisterType::D,
+            })
+            .unwrap();
+            assert_eq!(cpu.get_current_de_value(), 0x933d);
+            assert_eq!(cpu.get_current_sp_value(), sp.wrapping_add(2));
+        }
+    }
+
+    #[test]
+    fn it_should_report_stack_wraps_in_strict_mode() {
+        struct DiagnosticListener {
+            diagnostics: Vec<Diagnostic>,
+        }
+        impl EmulationListener for DiagnosticListener {
+            fn on_diagnostic(&mut self, diagnostic: Diagnostic) {
+                self.diagnostics.push(diagnostic);
+            }
+        }
+        let mut listener = DiagnosticListener {
+            diagnostics: Vec::new(),
+        };
+        {
+            let mut cpu = get_push_ready_cpu(RegisterType::B);
+            cpu.add_listener(&mut listener);
+            cpu.save_to_sp(0x0001);
+            cpu.execute_instruction(&Intel8080Instruction::Push {
+                register: RegisterType::B,
+            })
+            .unwrap();
+            cpu.set_strict(true);
+            cpu.execute_instruction(&Intel8080Instruction::Pop {
+                register: RegisterType::B,
+            })
+            .unwrap();
+            cpu.execute_instruction(&Intel8080Instruction::Pop {
+                register: RegisterType::B,
+            })
+            .unwrap();
+        }
+        assert_eq!(
+            listener.diagnostics,
+            vec![Diagnostic::StackWrapped { sp: 0xffff }]
+        );
+    }
 }
+
@@ -1,7 +1,8 @@
+use super::cpu::Memory;
 use super::CpuError;
-use intel8080cpu::Intel8080Cpu;
+use intel8080cpu::{EmulationListener, Intel8080Cpu};
 
-impl<'a> Intel8080Cpu<'a> {
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
     #[inline]
     pub(crate) fn execute_cma(&mut self) -> Result<(), CpuError> {
         let destiny_value = self.get_current_a_value()?;
@@ -0,0 +1,122 @@
+use alloc::boxed::Box;
+use alloc::fmt;
+use alloc::string::{String, ToString};
+use super::cpu::Memory;
+use helpers::two_bytes_to_word;
+use instruction::Intel8080Instruction;
+use intel8080cpu::{EmulationListener, Flags, Intel8080Cpu};
+
+// The machine as an instruction found it, before running it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub instruction: String,
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub sign: bool,
+    pub zero: bool,
+    pub auxiliary_carry: bool,
+    pub parity: bool,
+    pub carry: bool,
+}
+
+impl TraceEntry {
+    pub fn psw(&self) -> u16 {
+        let flags = Flags {
+            sign: self.sign,
+            zero: self.zero,
+            parity: self.parity,
+            carry: self.carry,
+            auxiliary_carry: self.auxiliary_carry,
+        };
+        two_bytes_to_word(self.a, flags.to_byte())
+    }
+}
+
+// The layout most 8080 emulators log in, so traces can be diffed line by line
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PC={:04X} AF={:04X} BC={:04X} DE={:04X} HL={:04X} SP={:04X}",
+            self.pc,
+            self.psw(),
+            two_bytes_to_word(self.b, self.c),
+            two_bytes_to_word(self.d, self.e),
+            two_bytes_to_word(self.h, self.l),
+            self.sp
+        )
+    }
+}
+
+pub fn format_trace(entries: &[TraceEntry]) -> String {
+    let mut dump = String::new();
+    for entry in entries {
+        dump.push_str(&alloc::format!("{}\n", entry));
+    }
+    dump
+}
+
+impl<'a, M: Memory, L: EmulationListener + ?Sized> Intel8080Cpu<'a, M, L> {
+    // Every executed instruction goes to the tracer, None stops tracing
+    pub fn set_trace(&mut self, tracer: Option<Box<dyn FnMut(TraceEntry) + Send + 'a>>) {
+        self.tracer = tracer;
+    }
+
+    pub(crate) fn trace(&mut self, instruction: &Intel8080Instruction) {
+        let entry = TraceEntry {
+            pc: self.pc,
+            instruction: instruction.to_string(),
+            a: self.registers.a,
+            b: self.registers.b,
+            c: self.registers.c,
+            d: self.registers.d,
+            e: self.registers.e,
+            h: self.registers.h,
+            l: self.registers.l,
+            sp: self.registers.sp,
+            sign: self.flags.sign,
+            zero: self.flags.zero,
+            auxiliary_carry: self.flags.auxiliary_carry,
+            parity: self.flags.parity,
+            carry: self.flags.carry,
+        };
+        if let Some(ref mut tracer) = self.tracer {
+            tracer(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cpu::Cpu;
+    use intel8080cpu::Intel8080Cpu;
+    use std::sync::mpsc::channel;
+    use std::vec::Vec;
+    use trace::{format_trace, TraceEntry};
+
+    #[test]
+    fn it_should_trace_the_state_before_every_instruction() {
+        let (sender, receiver) = channel();
+        let mut cpu = Intel8080Cpu::with_program(&[0x3e, 0x42, 0x01, 0x34, 0x12], 0x100);
+        cpu.set_trace(Some(Box::new(move |entry| sender.send(entry).unwrap())));
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        cpu.set_trace(None);
+        cpu.execute().unwrap();
+        let entries: Vec<TraceEntry> = receiver.try_iter().collect();
+        assert_eq!(entries[0].instruction, "MVI A,#$42");
+        assert_eq!(entries[1].instruction, "LXI B,#$1234");
+        assert_eq!(
+            format_trace(&entries),
+            "PC=0100 AF=00D7 BC=0000 DE=0000 HL=0000 SP=FFFF\n\
+             PC=0102 AF=42D7 BC=0000 DE=0000 HL=0000 SP=FFFF\n"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+extern crate intel8080cpu;
+
+use intel8080cpu::{Cpu, Intel8080Cpu, ROM_MEMORY_LIMIT};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn it_should_not_allocate_while_executing_instructions() {
+    let mut rom = [0; ROM_MEMORY_LIMIT];
+    // INR A; LXI H, 0x2000; MOV M, A; JMP 0x0000
+    rom[..8].copy_from_slice(&[0x3c, 0x21, 0x00, 0x20, 0x77, 0xc3, 0x00, 0x00]);
+    let mut cpu = Intel8080Cpu::new(rom);
+    for _ in 0..10 {
+        cpu.execute().unwrap();
+    }
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    for _ in 0..1000 {
+        cpu.execute().unwrap();
+    }
+    assert_eq!(ALLOCATIONS.load(Ordering::SeqCst), before);
+}
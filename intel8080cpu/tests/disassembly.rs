@@ -0,0 +1,14 @@
+extern crate cpu;
+extern crate intel8080cpu;
+
+#[path = "../../cpu/tests/support/disassembly.rs"]
+mod support;
+
+use intel8080cpu::Intel8080Instruction;
+use support::{assert_matches_golden, listing};
+
+#[test]
+fn it_should_match_the_golden_disassembly() {
+    let listing = listing(|bytes| Intel8080Instruction::from(bytes));
+    assert_matches_golden("disassembly.txt", &listing);
+}
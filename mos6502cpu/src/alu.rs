@@ -12,6 +12,7 @@ impl Mos6502Cpu {
             AddressingMode::ZeroPageIndexedX { .. } => Ok(()),
             AddressingMode::IndexedIndirect { .. } => Ok(()),
             AddressingMode::IndirectIndexed { .. } => Ok(()),
+            AddressingMode::ZeroPageIndirect { .. } => Ok(()),
             AddressingMode::Absolute { .. } => Ok(()),
             AddressingMode::AbsoluteIndexedX { .. } => Ok(()),
             AddressingMode::AbsoluteIndexedY { .. } => Ok(()),
@@ -1,5 +1,5 @@
 use bit_utils::{two_bytes_to_word, two_complement, word_to_two_bytes};
-use instruction::AddressingMode;
+use instruction::{AddressingMode, Mos6502InstructionCode};
 use mos6502cpu::{ProcessorStatus, INTERRUPT_HANDLERS_START};
 use {CpuError, CpuResult, Mos6502Cpu};
 
@@ -52,12 +52,16 @@ impl Mos6502Cpu {
         Ok(())
     }
 
+    pub(crate) fn execute_bra(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
+        let offset = self.get_branch_offset(addressing_mode)?;
+        self.update_pc_from_offset(offset);
+        Ok(())
+    }
+
     pub(crate) fn execute_brk(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         if let AddressingMode::Implicit = addressing_mode {
-            if !self.registers.p.interrupt_disable {
-                self.registers.p.break_flag = true;
-                self.execute_interruption(2);
-            }
+            self.registers.p.break_flag = true;
+            self.execute_interruption(2);
             Ok(())
         } else {
             Err(CpuError::InvalidAddressingMode)
@@ -106,7 +110,10 @@ impl Mos6502Cpu {
 
     pub(crate) fn execute_nmi(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         if let AddressingMode::Implicit = addressing_mode {
-            self.execute_interruption(0);
+            // It comes between instructions and returns to the next one
+            let return_address = self.registers.pc;
+            let p_byte = self.registers.p.to_byte() & !0x10;
+            self.execute_interruption_returning_to(return_address, p_byte, 0);
             Ok(())
         } else {
             Err(CpuError::InvalidAddressingMode)
@@ -147,8 +154,14 @@ impl Mos6502Cpu {
 
     #[inline]
     fn execute_interruption(&mut self, index: u16) {
+        let return_address = self.registers.pc + 1;
         let p_byte = self.registers.p.to_byte();
-        let (low_byte, high_byte) = word_to_two_bytes(self.registers.pc + 1);
+        self.execute_interruption_returning_to(return_address, p_byte, index);
+    }
+
+    #[inline]
+    fn execute_interruption_returning_to(&mut self, return_address: u16, p_byte: u8, index: u16) {
+        let (low_byte, high_byte) = word_to_two_bytes(return_address);
         self.push(high_byte);
         self.push(low_byte);
         self.push(p_byte);
@@ -161,6 +174,22 @@ impl Mos6502Cpu {
         self.registers.p.interrupt_disable = true;
     }
 
+    // Whether the branch goes to its target with the flags as they are now
+    pub(crate) fn is_branch_taken(&self, instruction: &Mos6502InstructionCode) -> bool {
+        match instruction {
+            Mos6502InstructionCode::Bcc => !self.registers.p.carry,
+            Mos6502InstructionCode::Bcs => self.registers.p.carry,
+            Mos6502InstructionCode::Beq => self.registers.p.zero,
+            Mos6502InstructionCode::Bmi => self.registers.p.negative,
+            Mos6502InstructionCode::Bne => !self.registers.p.zero,
+            Mos6502InstructionCode::Bpl => !self.registers.p.negative,
+            Mos6502InstructionCode::Bra => true,
+            Mos6502InstructionCode::Bvc => !self.registers.p.overflow,
+            Mos6502InstructionCode::Bvs => self.registers.p.overflow,
+            _ => false,
+        }
+    }
+
     #[inline]
     fn get_branch_offset(&self, addressing_mode: &AddressingMode) -> Result<u8, CpuError> {
         match addressing_mode {
@@ -571,8 +600,8 @@ mod tests {
         .unwrap();
         assert_eq!(cpu.registers.s, 0);
         assert_eq!(cpu.memory.get(0x103), 0x42);
-        assert_eq!(cpu.memory.get(0x102), 0x25);
-        assert_eq!(cpu.memory.get(0x101), 0x30);
+        assert_eq!(cpu.memory.get(0x102), 0x24);
+        assert_eq!(cpu.memory.get(0x101), 0x20);
     }
 
     #[test]
@@ -665,4 +694,17 @@ mod tests {
         assert_eq!(cpu.registers.pc, 0x4200);
         assert_eq!(cpu.registers.s, 0xff);
     }
+
+    #[test]
+    fn it_should_always_branch_on_bra() {
+        let m = [0; AVAILABLE_MEMORY];
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.registers.pc = 0x10;
+        cpu.execute_instruction(&Mos6502Instruction {
+            instruction: Mos6502InstructionCode::Bra,
+            addressing_mode: AddressingMode::Relative { byte: 0xf0 },
+        })
+        .unwrap();
+        assert_eq!(cpu.registers.pc, 0);
+    }
 }
@@ -5,11 +5,16 @@ impl Mos6502Cpu {
     pub(crate) fn execute_bit(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_bit_address(addressing_mode)?;
         let value = self.get_value_from_addressing_mode(addressing_mode)?;
+        self.bit_logic(value);
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn bit_logic(&mut self, value: u8) {
         let answer = value & self.registers.a;
         self.update_zero_flag(answer);
         self.registers.p.overflow = value & 0x40 > 0;
         self.registers.p.negative = value & 0x80 > 0;
-        Ok(())
     }
 
     pub(crate) fn execute_clc(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
@@ -75,6 +80,37 @@ impl Mos6502Cpu {
         }
     }
 
+    pub(crate) fn execute_trb(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
+        self.check_bit_address(addressing_mode)?;
+        let address = self.get_address_from_addressing_mode(addressing_mode)?;
+        let value = self.memory.get(address);
+        let answer = self.trb_logic(value);
+        self.memory.set(address, answer);
+        Ok(())
+    }
+
+    // Like BIT, the zero flag reflects A & M before the memory is modified
+    #[inline]
+    pub(crate) fn trb_logic(&mut self, value: u8) -> u8 {
+        self.update_zero_flag(value & self.registers.a);
+        value & !self.registers.a
+    }
+
+    pub(crate) fn execute_tsb(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
+        self.check_bit_address(addressing_mode)?;
+        let address = self.get_address_from_addressing_mode(addressing_mode)?;
+        let value = self.memory.get(address);
+        let answer = self.tsb_logic(value);
+        self.memory.set(address, answer);
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn tsb_logic(&mut self, value: u8) -> u8 {
+        self.update_zero_flag(value & self.registers.a);
+        value | self.registers.a
+    }
+
     #[inline]
     fn check_bit_address(&self, addressing_mode: &AddressingMode) -> CpuResult {
         match addressing_mode {
@@ -237,4 +273,26 @@ mod tests {
         .unwrap();
         assert!(cpu.registers.p.interrupt_disable);
     }
+
+    #[test]
+    fn it_should_reset_and_set_bits_on_trb_and_tsb() {
+        let m = [0; AVAILABLE_MEMORY];
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.registers.a = 0x0f;
+        cpu.memory.set(0x42, 0x3c);
+        cpu.execute_instruction(&Mos6502Instruction {
+            instruction: Mos6502InstructionCode::Trb,
+            addressing_mode: AddressingMode::ZeroPage { byte: 0x42 },
+        })
+        .unwrap();
+        assert_eq!(cpu.memory.get(0x42), 0x30);
+        assert!(!cpu.registers.p.zero);
+        cpu.execute_instruction(&Mos6502Instruction {
+            instruction: Mos6502InstructionCode::Tsb,
+            addressing_mode: AddressingMode::ZeroPage { byte: 0x42 },
+        })
+        .unwrap();
+        assert_eq!(cpu.memory.get(0x42), 0x3f);
+        assert!(cpu.registers.p.zero);
+    }
 }
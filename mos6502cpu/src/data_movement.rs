@@ -41,6 +41,7 @@ impl Mos6502Cpu {
 
     pub(crate) fn execute_sta(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_store_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         let address = self.get_address_from_addressing_mode(addressing_mode)?;
         self.memory.set(address, self.registers.a);
         Ok(())
@@ -72,6 +73,20 @@ impl Mos6502Cpu {
         }
     }
 
+    pub(crate) fn execute_stz(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
+        match addressing_mode {
+            AddressingMode::ZeroPage { .. }
+            | AddressingMode::ZeroPageIndexedX { .. }
+            | AddressingMode::Absolute { .. }
+            | AddressingMode::AbsoluteIndexedX { .. } => {
+                let address = self.get_address_from_addressing_mode(addressing_mode)?;
+                self.memory.set(address, 0);
+                Ok(())
+            }
+            _ => Err(CpuError::InvalidAddressingMode),
+        }
+    }
+
     pub(crate) fn execute_tax(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         if let AddressingMode::Implicit = addressing_mode {
             self.execute_tax_unchecked();
@@ -186,6 +201,7 @@ impl Mos6502Cpu {
             AddressingMode::AbsoluteIndexedY { .. } => Ok(()),
             AddressingMode::IndexedIndirect { .. } => Ok(()),
             AddressingMode::IndirectIndexed { .. } => Ok(()),
+            AddressingMode::ZeroPageIndirect { .. } => Ok(()),
             _ => Err(CpuError::InvalidAddressingMode),
         }
     }
@@ -670,4 +686,21 @@ mod tests {
         assert!(!cpu.registers.p.zero);
         assert!(cpu.registers.p.negative);
     }
+
+    #[test]
+    fn it_should_store_zero_on_stz() {
+        let m = [0; AVAILABLE_MEMORY];
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.registers.x = 2;
+        cpu.memory.set(0x2444, 0x42);
+        cpu.execute_instruction(&Mos6502Instruction {
+            instruction: Mos6502InstructionCode::Stz,
+            addressing_mode: AddressingMode::AbsoluteIndexedX {
+                high_byte: 0x24,
+                low_byte: 0x42,
+            },
+        })
+        .unwrap();
+        assert_eq!(cpu.memory.get(0x2444), 0);
+    }
 }
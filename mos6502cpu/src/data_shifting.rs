@@ -5,22 +5,29 @@ use {CpuError, CpuResult, Mos6502Cpu};
 impl Mos6502Cpu {
     pub(crate) fn execute_asl(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_shifting_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_asl_unchecked(addressing_mode)
     }
 
     #[inline]
     pub(crate) fn execute_asl_unchecked(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         let value = self.get_value_from_addressing_mode(addressing_mode)?;
-        let future_carry = value & 0x80 > 0;
+        let answer = self.asl_logic(value);
+        self.set_value_to_addressing_mode(addressing_mode, answer)
+    }
+
+    #[inline]
+    pub(crate) fn asl_logic(&mut self, value: u8) -> u8 {
         let answer = value << 1;
         self.update_zero_flag(answer);
         self.update_negative_flag(answer);
-        self.registers.p.carry = future_carry;
-        self.set_value_to_addressing_mode(addressing_mode, answer)
+        self.registers.p.carry = value & 0x80 > 0;
+        answer
     }
 
     pub(crate) fn execute_dec(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_memory_data_shifting_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_dec_unchecked(addressing_mode)
     }
 
@@ -55,6 +62,7 @@ impl Mos6502Cpu {
 
     pub(crate) fn execute_inc(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_memory_data_shifting_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_inc_unchecked(addressing_mode)
     }
 
@@ -90,53 +98,74 @@ impl Mos6502Cpu {
 
     pub(crate) fn execute_lsr(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_shifting_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_lsr_unchecked(addressing_mode)
     }
 
     #[inline]
     pub(crate) fn execute_lsr_unchecked(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         let value = self.get_value_from_addressing_mode(addressing_mode)?;
+        let answer = self.lsr_logic(value);
+        self.set_value_to_addressing_mode(addressing_mode, answer)
+    }
+
+    #[inline]
+    pub(crate) fn lsr_logic(&mut self, value: u8) -> u8 {
         let answer = value >> 1;
         self.update_zero_flag(answer);
         self.registers.p.carry = value & 0x01 > 0;
         self.registers.p.negative = false;
-        self.set_value_to_addressing_mode(addressing_mode, answer)
+        answer
     }
 
     pub(crate) fn execute_rol(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_shifting_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_rol_unchecked(addressing_mode)
     }
 
     #[inline]
     pub(crate) fn execute_rol_unchecked(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
-        let carry_mask = self.registers.p.carry as u8;
         let value = self.get_value_from_addressing_mode(addressing_mode)?;
+        let answer = self.rol_logic(value);
+        self.set_value_to_addressing_mode(addressing_mode, answer)
+    }
+
+    #[inline]
+    pub(crate) fn rol_logic(&mut self, value: u8) -> u8 {
+        let carry_mask = self.registers.p.carry as u8;
         let answer = (value << 1) | carry_mask;
         self.update_zero_flag(answer);
         self.update_negative_flag(answer);
         self.registers.p.carry = value & 0x80 > 0;
-        self.set_value_to_addressing_mode(addressing_mode, answer)
+        answer
     }
 
     pub(crate) fn execute_ror(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_shifting_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_ror_unchecked(addressing_mode)
     }
 
     #[inline]
     pub(crate) fn execute_ror_unchecked(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
-        let carry_mask = (self.registers.p.carry as u8) << 7;
         let value = self.get_value_from_addressing_mode(addressing_mode)?;
+        let answer = self.ror_logic(value);
+        self.set_value_to_addressing_mode(addressing_mode, answer)
+    }
+
+    #[inline]
+    pub(crate) fn ror_logic(&mut self, value: u8) -> u8 {
+        let carry_mask = (self.registers.p.carry as u8) << 7;
         let answer = (value >> 1) | carry_mask;
         self.update_zero_flag(answer);
         self.update_negative_flag(answer);
         self.registers.p.carry = value & 0x01 > 0;
-        self.set_value_to_addressing_mode(addressing_mode, answer)
+        answer
     }
 
     #[inline]
-    fn increment(&mut self, value: u8) -> u8 {
+    pub(crate) fn increment(&mut self, value: u8) -> u8 {
         let answer = value.wrapping_add(1);
         self.update_zero_flag(answer);
         self.update_negative_flag(answer);
@@ -144,7 +173,7 @@ impl Mos6502Cpu {
     }
 
     #[inline]
-    fn decrement(&mut self, value: u8) -> u8 {
+    pub(crate) fn decrement(&mut self, value: u8) -> u8 {
         let answer = value.wrapping_add(ONE_TWO_COMPLEMENT);
         self.update_zero_flag(answer);
         self.update_negative_flag(answer);
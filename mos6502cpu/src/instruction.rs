@@ -1,5 +1,8 @@
 use super::cpu::{Cycles, Instruction};
 use super::failure::Error;
+use mos6502cpu::Variant;
+use stats::AddressingModeKind;
+use std::cmp::min;
 use std::fmt;
 
 #[derive(Debug, Fail)]
@@ -20,6 +23,15 @@ pub enum Mos6502InstructionError {
     NoCycles {
         instruction_code: Mos6502InstructionCode,
     },
+    #[fail(
+        display = "The instruction at {:04x} doesn't fit in the range",
+        address
+    )]
+    Truncated { address: u16 },
+    #[fail(display = "Unknown opcode {:02x}", opcode)]
+    UnknownOpcode { opcode: u8 },
+    #[fail(display = "There are no bytes to decode")]
+    MissingOpcode,
 }
 
 #[derive(Clone, Debug)]
@@ -37,6 +49,7 @@ pub enum AddressingMode {
     AbsoluteIndexedY { high_byte: u8, low_byte: u8 },
     IndexedIndirect { byte: u8 },
     IndirectIndexed { byte: u8 },
+    ZeroPageIndirect { byte: u8 },
 }
 
 impl fmt::Display for AddressingMode {
@@ -44,7 +57,7 @@ impl fmt::Display for AddressingMode {
         let s = match self {
             AddressingMode::Implicit => String::from(""),
             AddressingMode::Accumulator => String::from("A"),
-            AddressingMode::Immediate { byte } => format!("#{:02x}", byte),
+            AddressingMode::Immediate { byte } => format!("#${:02x}", byte),
             AddressingMode::ZeroPage { byte } => format!("${:02x}", byte),
             AddressingMode::Absolute {
                 high_byte,
@@ -67,12 +80,46 @@ impl fmt::Display for AddressingMode {
             } => format!("${:02x}{:02x},y", high_byte, low_byte),
             AddressingMode::IndexedIndirect { byte } => format!("(${:02x},x)", byte),
             AddressingMode::IndirectIndexed { byte } => format!("(${:02x}),y", byte),
+            AddressingMode::ZeroPageIndirect { byte } => format!("(${:02x})", byte),
         };
         write!(f, "{}", s)
     }
 }
 
-#[derive(Clone, Debug)]
+impl AddressingMode {
+    // What follows the opcode, low byte first
+    fn operand_bytes(&self) -> Vec<u8> {
+        match *self {
+            AddressingMode::Implicit | AddressingMode::Accumulator => Vec::new(),
+            AddressingMode::Immediate { byte }
+            | AddressingMode::ZeroPage { byte }
+            | AddressingMode::Relative { byte }
+            | AddressingMode::ZeroPageIndexedX { byte }
+            | AddressingMode::ZeroPageIndexedY { byte }
+            | AddressingMode::IndexedIndirect { byte }
+            | AddressingMode::IndirectIndexed { byte }
+            | AddressingMode::ZeroPageIndirect { byte } => vec![byte],
+            AddressingMode::Absolute {
+                high_byte,
+                low_byte,
+            }
+            | AddressingMode::Indirect {
+                high_byte,
+                low_byte,
+            }
+            | AddressingMode::AbsoluteIndexedX {
+                high_byte,
+                low_byte,
+            }
+            | AddressingMode::AbsoluteIndexedY {
+                high_byte,
+                low_byte,
+            } => vec![low_byte, high_byte],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Mos6502InstructionCode {
     Adc,
     Ahx,
@@ -89,6 +136,7 @@ pub enum Mos6502InstructionCode {
     Bmi,
     Bne,
     Bpl,
+    Bra,
     Brk,
     Bvc,
     Bvs,
@@ -122,8 +170,12 @@ pub enum Mos6502InstructionCode {
     Ora,
     Pha,
     Php,
+    Phx,
+    Phy,
     Pla,
     Plp,
+    Plx,
+    Ply,
     Rla,
     Rol,
     Ror,
@@ -143,9 +195,12 @@ pub enum Mos6502InstructionCode {
     Sta,
     Stx,
     Sty,
+    Stz,
     Tas,
     Tax,
     Tay,
+    Trb,
+    Tsb,
     Tsx,
     Txa,
     Txs,
@@ -171,6 +226,7 @@ impl fmt::Display for Mos6502InstructionCode {
             Mos6502InstructionCode::Bmi => String::from("BMI"),
             Mos6502InstructionCode::Bne => String::from("BNE"),
             Mos6502InstructionCode::Bpl => String::from("BPL"),
+            Mos6502InstructionCode::Bra => String::from("BRA"),
             Mos6502InstructionCode::Brk => String::from("BRK"),
             Mos6502InstructionCode::Bvc => String::from("BVC"),
             Mos6502InstructionCode::Bvs => String::from("BVS"),
@@ -204,8 +260,12 @@ impl fmt::Display for Mos6502InstructionCode {
             Mos6502InstructionCode::Ora => String::from("ORA"),
             Mos6502InstructionCode::Pha => String::from("PHA"),
             Mos6502InstructionCode::Php => String::from("PHP"),
+            Mos6502InstructionCode::Phx => String::from("PHX"),
+            Mos6502InstructionCode::Phy => String::from("PHY"),
             Mos6502InstructionCode::Pla => String::from("PLA"),
             Mos6502InstructionCode::Plp => String::from("PLP"),
+            Mos6502InstructionCode::Plx => String::from("PLX"),
+            Mos6502InstructionCode::Ply => String::from("PLY"),
             Mos6502InstructionCode::Rla => String::from("RLA"),
             Mos6502InstructionCode::Rol => String::from("ROL"),
             Mos6502InstructionCode::Ror => String::from("ROR"),
@@ -225,9 +285,12 @@ impl fmt::Display for Mos6502InstructionCode {
             Mos6502InstructionCode::Sta => String::from("STA"),
             Mos6502InstructionCode::Stx => String::from("STX"),
             Mos6502InstructionCode::Sty => String::from("STY"),
+            Mos6502InstructionCode::Stz => String::from("STZ"),
             Mos6502InstructionCode::Tas => String::from("TAS"),
             Mos6502InstructionCode::Tax => String::from("TAX"),
             Mos6502InstructionCode::Tay => String::from("TAY"),
+            Mos6502InstructionCode::Trb => String::from("TRB"),
+            Mos6502InstructionCode::Tsb => String::from("TSB"),
             Mos6502InstructionCode::Tsx => String::from("TSX"),
             Mos6502InstructionCode::Txa => String::from("TXA"),
             Mos6502InstructionCode::Txs => String::from("TXS"),
@@ -260,6 +323,7 @@ impl Mos6502Instruction {
             AddressingMode::ZeroPageIndexedX { .. } => Ok(2),
             AddressingMode::IndexedIndirect { .. } => Ok(2),
             AddressingMode::IndirectIndexed { .. } => Ok(2),
+            AddressingMode::ZeroPageIndirect { .. } => Ok(2),
             AddressingMode::Absolute { .. } => Ok(3),
             AddressingMode::AbsoluteIndexedX { .. } => Ok(3),
             AddressingMode::AbsoluteIndexedY { .. } => Ok(3),
@@ -273,6 +337,7 @@ impl Mos6502Instruction {
             AddressingMode::ZeroPageIndexedX { .. } => Ok(2),
             AddressingMode::IndexedIndirect { .. } => Ok(2),
             AddressingMode::IndirectIndexed { .. } => Ok(2),
+            AddressingMode::ZeroPageIndirect { .. } => Ok(2),
             AddressingMode::Absolute { .. } => Ok(3),
             AddressingMode::AbsoluteIndexedX { .. } => Ok(3),
             AddressingMode::AbsoluteIndexedY { .. } => Ok(3),
@@ -298,6 +363,7 @@ impl Mos6502Instruction {
             AddressingMode::ZeroPageIndexedX { .. } => Ok(single!(4)),
             AddressingMode::IndexedIndirect { .. } => Ok(single!(6)),
             AddressingMode::IndirectIndexed { .. } => Ok(conditional!(5, 6)),
+            AddressingMode::ZeroPageIndirect { .. } => Ok(single!(5)),
             AddressingMode::Absolute { .. } => Ok(single!(4)),
             AddressingMode::AbsoluteIndexedX { .. } => Ok(conditional!(4, 5)),
             AddressingMode::AbsoluteIndexedY { .. } => Ok(conditional!(4, 5)),
@@ -311,6 +377,7 @@ impl Mos6502Instruction {
             AddressingMode::ZeroPageIndexedX { .. } => Ok(single!(4)),
             AddressingMode::IndexedIndirect { .. } => Ok(single!(6)),
             AddressingMode::IndirectIndexed { .. } => Ok(single!(6)),
+            AddressingMode::ZeroPageIndirect { .. } => Ok(single!(5)),
             AddressingMode::Absolute { .. } => Ok(single!(4)),
             AddressingMode::AbsoluteIndexedX { .. } => Ok(single!(5)),
             AddressingMode::AbsoluteIndexedY { .. } => Ok(single!(5)),
@@ -342,6 +409,58 @@ impl Mos6502Instruction {
         }
     }
 
+    fn test_bits_size(&self) -> Result<u8, Error> {
+        match self.addressing_mode {
+            AddressingMode::ZeroPage { .. } => Ok(2),
+            AddressingMode::Absolute { .. } => Ok(3),
+            _ => Err(self.invalid_addressing_mode()),
+        }
+    }
+
+    fn test_bits_cycles(&self) -> Result<Cycles, Error> {
+        match self.addressing_mode {
+            AddressingMode::ZeroPage { .. } => Ok(single!(5)),
+            AddressingMode::Absolute { .. } => Ok(single!(6)),
+            _ => Err(self.invalid_addressing_mode()),
+        }
+    }
+
+    // The 65C02 reuses some of the NMOS unofficial opcodes for its new instructions
+    pub fn decode(bytes: &[u8], variant: Variant) -> Mos6502Instruction {
+        match variant {
+            Variant::Cmos65C02 => {
+                decode_cmos(bytes).unwrap_or_else(|| Mos6502Instruction::from(bytes))
+            }
+            _ => Mos6502Instruction::from(bytes),
+        }
+    }
+
+    // The bytes that decode back to this instruction. Official opcodes win over the unofficial
+    // ones that do the same, and the interrupt pseudo instructions have no bytes at all
+    pub fn encode(&self) -> Vec<u8> {
+        let kind = AddressingModeKind::from(&self.addressing_mode);
+        let is_self = |candidate: &Mos6502Instruction| {
+            candidate.instruction == self.instruction
+                && AddressingModeKind::from(&candidate.addressing_mode) == kind
+        };
+        let nmos = |opcode: &u8| is_self(&Mos6502Instruction::from(&[*opcode, 0, 0][..]));
+        let opcode = (0..=0xff)
+            .filter(|opcode| !is_unofficial_opcode(*opcode, Variant::Nmos))
+            .find(nmos)
+            .or_else(|| {
+                (0..=0xff).find(|opcode| decode_cmos(&[*opcode, 0, 0]).is_some_and(|i| is_self(&i)))
+            })
+            .or_else(|| (0..=0xff).find(nmos));
+        match opcode {
+            Some(opcode) => {
+                let mut bytes = vec![opcode];
+                bytes.extend(self.addressing_mode.operand_bytes());
+                bytes
+            }
+            None => Vec::new(),
+        }
+    }
+
     #[inline]
     fn invalid_addressing_mode(&self) -> Error {
         Error::from(Mos6502InstructionError::InvalidAddressingMode {
@@ -377,6 +496,7 @@ impl Instruction for Mos6502Instruction {
             Mos6502InstructionCode::Bmi => Ok(2),
             Mos6502InstructionCode::Bne => Ok(2),
             Mos6502InstructionCode::Bpl => Ok(2),
+            Mos6502InstructionCode::Bra => Ok(2),
             Mos6502InstructionCode::Brk => Ok(1),
             Mos6502InstructionCode::Bvc => Ok(2),
             Mos6502InstructionCode::Bvs => Ok(2),
@@ -459,12 +579,24 @@ impl Instruction for Mos6502Instruction {
             Mos6502InstructionCode::Nmi => Err(Error::from(Mos6502InstructionError::NoSize {
                 instruction_code: Mos6502InstructionCode::Nmi,
             })),
-            Mos6502InstructionCode::Nop => Ok(1),
+            Mos6502InstructionCode::Nop => match self.addressing_mode {
+                AddressingMode::Implicit => Ok(1),
+                AddressingMode::Immediate { .. } => Ok(2),
+                AddressingMode::ZeroPage { .. } => Ok(2),
+                AddressingMode::ZeroPageIndexedX { .. } => Ok(2),
+                AddressingMode::Absolute { .. } => Ok(3),
+                AddressingMode::AbsoluteIndexedX { .. } => Ok(3),
+                _ => Err(self.invalid_addressing_mode()),
+            },
             Mos6502InstructionCode::Ora => self.alu_size(),
             Mos6502InstructionCode::Pha => Ok(1),
             Mos6502InstructionCode::Php => Ok(1),
+            Mos6502InstructionCode::Phx => Ok(1),
+            Mos6502InstructionCode::Phy => Ok(1),
             Mos6502InstructionCode::Pla => Ok(1),
             Mos6502InstructionCode::Plp => Ok(1),
+            Mos6502InstructionCode::Plx => Ok(1),
+            Mos6502InstructionCode::Ply => Ok(1),
             Mos6502InstructionCode::Rla => self.alu_accumulator_size(),
             Mos6502InstructionCode::Rol => self.data_movement_size(),
             Mos6502InstructionCode::Ror => self.data_movement_size(),
@@ -502,9 +634,18 @@ impl Instruction for Mos6502Instruction {
                 AddressingMode::Absolute { .. } => Ok(3),
                 _ => Err(self.invalid_addressing_mode()),
             },
+            Mos6502InstructionCode::Stz => match self.addressing_mode {
+                AddressingMode::ZeroPage { .. } => Ok(2),
+                AddressingMode::ZeroPageIndexedX { .. } => Ok(2),
+                AddressingMode::Absolute { .. } => Ok(3),
+                AddressingMode::AbsoluteIndexedX { .. } => Ok(3),
+                _ => Err(self.invalid_addressing_mode()),
+            },
             Mos6502InstructionCode::Tas => Ok(3),
             Mos6502InstructionCode::Tax => Ok(1),
             Mos6502InstructionCode::Tay => Ok(1),
+            Mos6502InstructionCode::Trb => self.test_bits_size(),
+            Mos6502InstructionCode::Tsb => self.test_bits_size(),
             Mos6502InstructionCode::Tsx => Ok(1),
             Mos6502InstructionCode::Txa => Ok(1),
             Mos6502InstructionCode::Txs => Ok(1),
@@ -523,20 +664,21 @@ impl Instruction for Mos6502Instruction {
             Mos6502InstructionCode::Arr => Ok(single!(2)),
             Mos6502InstructionCode::Asl => self.data_movement_cycles(),
             Mos6502InstructionCode::Axs => Ok(single!(2)),
-            Mos6502InstructionCode::Bcc => Ok(bi_conditional!(2, 3, 5)),
-            Mos6502InstructionCode::Bcs => Ok(bi_conditional!(2, 3, 5)),
-            Mos6502InstructionCode::Beq => Ok(bi_conditional!(2, 3, 5)),
+            Mos6502InstructionCode::Bcc => Ok(bi_conditional!(2, 3, 4)),
+            Mos6502InstructionCode::Bcs => Ok(bi_conditional!(2, 3, 4)),
+            Mos6502InstructionCode::Beq => Ok(bi_conditional!(2, 3, 4)),
             Mos6502InstructionCode::Bit => match self.addressing_mode {
                 AddressingMode::ZeroPage { .. } => Ok(single!(3)),
                 AddressingMode::Absolute { .. } => Ok(single!(4)),
                 _ => Err(self.invalid_addressing_mode()),
             },
-            Mos6502InstructionCode::Bmi => Ok(bi_conditional!(2, 3, 5)),
-            Mos6502InstructionCode::Bne => Ok(bi_conditional!(2, 3, 5)),
-            Mos6502InstructionCode::Bpl => Ok(bi_conditional!(2, 3, 5)),
+            Mos6502InstructionCode::Bmi => Ok(bi_conditional!(2, 3, 4)),
+            Mos6502InstructionCode::Bne => Ok(bi_conditional!(2, 3, 4)),
+            Mos6502InstructionCode::Bpl => Ok(bi_conditional!(2, 3, 4)),
+            Mos6502InstructionCode::Bra => Ok(conditional!(3, 4)),
             Mos6502InstructionCode::Brk => Ok(single!(7)),
-            Mos6502InstructionCode::Bvc => Ok(bi_conditional!(2, 3, 5)),
-            Mos6502InstructionCode::Bvs => Ok(bi_conditional!(2, 3, 5)),
+            Mos6502InstructionCode::Bvc => Ok(bi_conditional!(2, 3, 4)),
+            Mos6502InstructionCode::Bvs => Ok(bi_conditional!(2, 3, 4)),
             Mos6502InstructionCode::Clc => Ok(single!(2)),
             Mos6502InstructionCode::Cld => Ok(single!(2)),
             Mos6502InstructionCode::Cli => Ok(single!(2)),
@@ -624,8 +766,12 @@ impl Instruction for Mos6502Instruction {
             Mos6502InstructionCode::Ora => self.alu_cycles(),
             Mos6502InstructionCode::Pha => Ok(single!(3)),
             Mos6502InstructionCode::Php => Ok(single!(3)),
+            Mos6502InstructionCode::Phx => Ok(single!(3)),
+            Mos6502InstructionCode::Phy => Ok(single!(3)),
             Mos6502InstructionCode::Pla => Ok(single!(4)),
             Mos6502InstructionCode::Plp => Ok(single!(4)),
+            Mos6502InstructionCode::Plx => Ok(single!(4)),
+            Mos6502InstructionCode::Ply => Ok(single!(4)),
             Mos6502InstructionCode::Rla => self.unofficial_alu_accumulator_cycles(),
             Mos6502InstructionCode::Rol => self.data_movement_cycles(),
             Mos6502InstructionCode::Ror => self.data_movement_cycles(),
@@ -661,9 +807,18 @@ impl Instruction for Mos6502Instruction {
                 AddressingMode::Absolute { .. } => Ok(single!(4)),
                 _ => Err(self.invalid_addressing_mode()),
             },
+            Mos6502InstructionCode::Stz => match self.addressing_mode {
+                AddressingMode::ZeroPage { .. } => Ok(single!(3)),
+                AddressingMode::ZeroPageIndexedX { .. } => Ok(single!(4)),
+                AddressingMode::Absolute { .. } => Ok(single!(4)),
+                AddressingMode::AbsoluteIndexedX { .. } => Ok(single!(5)),
+                _ => Err(self.invalid_addressing_mode()),
+            },
             Mos6502InstructionCode::Tas => Ok(single!(5)),
             Mos6502InstructionCode::Tax => Ok(single!(2)),
             Mos6502InstructionCode::Tay => Ok(single!(2)),
+            Mos6502InstructionCode::Trb => self.test_bits_cycles(),
+            Mos6502InstructionCode::Tsb => self.test_bits_cycles(),
             Mos6502InstructionCode::Tsx => Ok(single!(2)),
             Mos6502InstructionCode::Txa => Ok(single!(2)),
             Mos6502InstructionCode::Txs => Ok(single!(2)),
@@ -673,10 +828,38 @@ impl Instruction for Mos6502Instruction {
     }
 }
 
-impl From<Vec<u8>> for Mos6502Instruction {
+// Opcodes the NMOS decoder doesn't know run as an implicit NOP, the jams included
+impl From<&[u8]> for Mos6502Instruction {
     #[inline]
-    fn from(bytes: Vec<u8>) -> Mos6502Instruction {
-        match bytes[0] {
+    fn from(bytes: &[u8]) -> Mos6502Instruction {
+        Mos6502Instruction::decode_nmos(bytes).unwrap_or(Mos6502Instruction {
+            instruction: Mos6502InstructionCode::Nop,
+            addressing_mode: AddressingMode::Implicit,
+        })
+    }
+}
+
+impl Mos6502Instruction {
+    // For tools that would rather know, the disassembler prints unknown opcodes as data. An
+    // instruction whose operand doesn't fit in the bytes is Truncated, at address 0 of them
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Mos6502Instruction, Mos6502InstructionError> {
+        let mut window = [0; 3];
+        let available = min(bytes.len(), window.len());
+        window[..available].copy_from_slice(&bytes[..available]);
+        let instruction = match bytes.first() {
+            None => Err(Mos6502InstructionError::MissingOpcode),
+            Some(opcode) => Mos6502Instruction::decode_nmos(&window)
+                .ok_or(Mos6502InstructionError::UnknownOpcode { opcode: *opcode }),
+        }?;
+        if instruction.addressing_mode.operand_bytes().len() >= available {
+            return Err(Mos6502InstructionError::Truncated { address: 0 });
+        }
+        Ok(instruction)
+    }
+
+    #[inline]
+    fn decode_nmos(bytes: &[u8]) -> Option<Mos6502Instruction> {
+        Some(match bytes[0] {
             0x00 => Mos6502Instruction {
                 instruction: Mos6502InstructionCode::Brk,
                 addressing_mode: AddressingMode::Implicit,
@@ -1473,6 +1656,10 @@ impl From<Vec<u8>> for Mos6502Instruction {
                 instruction: Mos6502InstructionCode::Sbc,
                 addressing_mode: AddressingMode::Immediate { byte: bytes[1] },
             },
+            0xEA => Mos6502Instruction {
+                instruction: Mos6502InstructionCode::Nop,
+                addressing_mode: AddressingMode::Implicit,
+            },
             0xEB => Mos6502Instruction {
                 instruction: Mos6502InstructionCode::Sbc,
                 addressing_mode: AddressingMode::Immediate { byte: bytes[1] },
@@ -1554,16 +1741,225 @@ impl From<Vec<u8>> for Mos6502Instruction {
                     high_byte: bytes[2],
                 },
             },
-            _ => Mos6502Instruction {
-                instruction: Mos6502InstructionCode::Nop,
-                addressing_mode: AddressingMode::Implicit,
+            _ => return None,
+        })
+    }
+}
+
+fn decode_cmos(bytes: &[u8]) -> Option<Mos6502Instruction> {
+    let (instruction, addressing_mode) = match bytes[0] {
+        0x04 => (
+            Mos6502InstructionCode::Tsb,
+            AddressingMode::ZeroPage { byte: bytes[1] },
+        ),
+        0x0C => (
+            Mos6502InstructionCode::Tsb,
+            AddressingMode::Absolute {
+                low_byte: bytes[1],
+                high_byte: bytes[2],
+            },
+        ),
+        0x12 => (
+            Mos6502InstructionCode::Ora,
+            AddressingMode::ZeroPageIndirect { byte: bytes[1] },
+        ),
+        0x14 => (
+            Mos6502InstructionCode::Trb,
+            AddressingMode::ZeroPage { byte: bytes[1] },
+        ),
+        0x1C => (
+            Mos6502InstructionCode::Trb,
+            AddressingMode::Absolute {
+                low_byte: bytes[1],
+                high_byte: bytes[2],
+            },
+        ),
+        0x32 => (
+            Mos6502InstructionCode::And,
+            AddressingMode::ZeroPageIndirect { byte: bytes[1] },
+        ),
+        0x52 => (
+            Mos6502InstructionCode::Eor,
+            AddressingMode::ZeroPageIndirect { byte: bytes[1] },
+        ),
+        0x5A => (Mos6502InstructionCode::Phy, AddressingMode::Implicit),
+        0x64 => (
+            Mos6502InstructionCode::Stz,
+            AddressingMode::ZeroPage { byte: bytes[1] },
+        ),
+        0x72 => (
+            Mos6502InstructionCode::Adc,
+            AddressingMode::ZeroPageIndirect { byte: bytes[1] },
+        ),
+        0x74 => (
+            Mos6502InstructionCode::Stz,
+            AddressingMode::ZeroPageIndexedX { byte: bytes[1] },
+        ),
+        0x7A => (Mos6502InstructionCode::Ply, AddressingMode::Implicit),
+        0x80 => (
+            Mos6502InstructionCode::Bra,
+            AddressingMode::Relative { byte: bytes[1] },
+        ),
+        0x92 => (
+            Mos6502InstructionCode::Sta,
+            AddressingMode::ZeroPageIndirect { byte: bytes[1] },
+        ),
+        0x9C => (
+            Mos6502InstructionCode::Stz,
+            AddressingMode::Absolute {
+                low_byte: bytes[1],
+                high_byte: bytes[2],
             },
+        ),
+        0x9E => (
+            Mos6502InstructionCode::Stz,
+            AddressingMode::AbsoluteIndexedX {
+                low_byte: bytes[1],
+                high_byte: bytes[2],
+            },
+        ),
+        0xB2 => (
+            Mos6502InstructionCode::Lda,
+            AddressingMode::ZeroPageIndirect { byte: bytes[1] },
+        ),
+        0xD2 => (
+            Mos6502InstructionCode::Cmp,
+            AddressingMode::ZeroPageIndirect { byte: bytes[1] },
+        ),
+        0xDA => (Mos6502InstructionCode::Phx, AddressingMode::Implicit),
+        0xF2 => (
+            Mos6502InstructionCode::Sbc,
+            AddressingMode::ZeroPageIndirect { byte: bytes[1] },
+        ),
+        0xFA => (Mos6502InstructionCode::Plx, AddressingMode::Implicit),
+        _ => return None,
+    };
+    Some(Mos6502Instruction::new(instruction, addressing_mode))
+}
+
+pub(crate) fn is_unofficial_opcode(opcode: u8, variant: Variant) -> bool {
+    if variant == Variant::Cmos65C02 && decode_cmos(&[opcode, 0, 0]).is_some() {
+        return false;
+    }
+    let instruction = Mos6502Instruction::from(&[opcode, 0, 0][..]);
+    match instruction.instruction {
+        Mos6502InstructionCode::Ahx
+        | Mos6502InstructionCode::Alr
+        | Mos6502InstructionCode::Anc
+        | Mos6502InstructionCode::Arr
+        | Mos6502InstructionCode::Axs
+        | Mos6502InstructionCode::Dcp
+        | Mos6502InstructionCode::Isc
+        | Mos6502InstructionCode::Las
+        | Mos6502InstructionCode::Lax
+        | Mos6502InstructionCode::Rla
+        | Mos6502InstructionCode::Rra
+        | Mos6502InstructionCode::Sax
+        | Mos6502InstructionCode::Shx
+        | Mos6502InstructionCode::Shy
+        | Mos6502InstructionCode::Slo
+        | Mos6502InstructionCode::Sre
+        | Mos6502InstructionCode::Tas
+        | Mos6502InstructionCode::Xaa => true,
+        Mos6502InstructionCode::Nop => opcode != 0xea,
+        Mos6502InstructionCode::Sbc => opcode == 0xeb,
+        _ => false,
+    }
+}
+
+impl fmt::Display for Mos6502Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.addressing_mode {
+            AddressingMode::Implicit => write!(f, "{}", self.instruction),
+            _ => write!(f, "{} {}", self.instruction, self.addressing_mode),
         }
     }
 }
 
-impl ToString for Mos6502Instruction {
-    fn to_string(&self) -> String {
-        format!("{} {}", self.instruction, self.addressing_mode)
+#[cfg(test)]
+mod tests {
+    use super::{decode_cmos, is_unofficial_opcode};
+    use cpu::Instruction;
+    use {Mos6502Instruction, Mos6502InstructionError, Variant};
+
+    #[test]
+    fn it_should_encode_every_documented_opcode_back_to_its_bytes() {
+        for opcode in 0..=0xffu8 {
+            let bytes = [opcode, 0x34, 0x12];
+            let variant = if decode_cmos(&bytes).is_some() {
+                Variant::Cmos65C02
+            } else if !is_unofficial_opcode(opcode, Variant::Nmos) {
+                Variant::Nmos
+            } else {
+                continue;
+            };
+            let instruction = Mos6502Instruction::decode(&bytes, variant);
+            let size = instruction.size().unwrap() as usize;
+            assert_eq!(
+                instruction.encode(),
+                bytes[..size].to_vec(),
+                "{}",
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_prefer_the_official_opcode_when_encoding() {
+        let unofficial_sbc = Mos6502Instruction::from(&[0xeb, 0x01, 0x00][..]);
+        assert_eq!(unofficial_sbc.encode(), vec![0xe9, 0x01]);
+        let unofficial_nop = Mos6502Instruction::from(&[0x1a, 0x00, 0x00][..]);
+        assert_eq!(unofficial_nop.encode(), vec![0xea]);
+        let nop_zero_page = Mos6502Instruction::from(&[0x04, 0x10, 0x00][..]);
+        assert_eq!(nop_zero_page.encode(), vec![0x04, 0x10]);
+    }
+
+    #[test]
+    fn it_should_display_the_mnemonic_and_the_operand() {
+        let display = |bytes: &[u8]| Mos6502Instruction::from(bytes).to_string();
+        assert_eq!(display(&[0xe8, 0x00, 0x00]), "INX");
+        assert_eq!(display(&[0x0a, 0x00, 0x00]), "ASL A");
+        assert_eq!(display(&[0x09, 0x34, 0x00]), "ORA #$34");
+        assert_eq!(display(&[0xbd, 0x34, 0x12]), "LDA $1234,x");
+        assert_eq!(display(&[0x6c, 0x34, 0x12]), "JMP ($1234)");
+        assert_eq!(display(&[0xb1, 0x20, 0x00]), "LDA ($20),y");
+    }
+
+    #[test]
+    fn it_should_report_unknown_opcodes() {
+        match Mos6502Instruction::try_from_bytes(&[0x02]) {
+            Err(Mos6502InstructionError::UnknownOpcode { opcode: 0x02 }) => {}
+            Err(error) => panic!("Unexpected {}", error),
+            Ok(instruction) => panic!("Decoded {}", instruction),
+        }
+        assert_eq!(
+            Mos6502Instruction::from(&[0x02, 0x00, 0x00][..]).to_string(),
+            "NOP"
+        );
+        match Mos6502Instruction::try_from_bytes(&[]) {
+            Err(Mos6502InstructionError::MissingOpcode) => {}
+            Err(error) => panic!("Unexpected {}", error),
+            Ok(instruction) => panic!("Decoded {}", instruction),
+        }
+    }
+
+    #[test]
+    fn it_should_decode_known_opcodes_from_short_slices() {
+        let nop = Mos6502Instruction::try_from_bytes(&[0xea]).unwrap();
+        assert_eq!(nop.to_string(), "NOP");
+        let lda = Mos6502Instruction::try_from_bytes(&[0xad, 0x34, 0x12, 0xff]).unwrap();
+        assert_eq!(lda.to_string(), "LDA $1234");
+        assert_eq!(lda.size().unwrap(), 3);
+    }
+
+    #[test]
+    fn it_should_report_instructions_cut_short() {
+        for bytes in [&[0xad, 0x34][..], &[0xad][..], &[0xa9][..]].iter() {
+            match Mos6502Instruction::try_from_bytes(bytes) {
+                Err(Mos6502InstructionError::Truncated { address: 0 }) => {}
+                Err(error) => panic!("Unexpected {}", error),
+                Ok(instruction) => panic!("Decoded {}", instruction),
+            }
+        }
     }
 }
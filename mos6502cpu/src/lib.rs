@@ -13,13 +13,23 @@ mod instruction;
 mod logical;
 mod math;
 mod mos6502cpu;
+mod opcode_table;
+mod ram_init;
 mod stack;
+mod stats;
+mod tick;
+mod trace;
 mod undocumented;
 
 pub type CpuResult = Result<(), CpuError>;
 
-pub use cpu::{Cpu, Instruction};
+pub use cpu::{Cpu, HookAction, Instruction, Memory, RunStats};
 pub use instruction::{
     AddressingMode, Mos6502Instruction, Mos6502InstructionCode, Mos6502InstructionError,
 };
-pub use mos6502cpu::{CpuError, Memory, Mos6502Cpu, AVAILABLE_MEMORY};
+pub use mos6502cpu::{CpuError, Mos6502Cpu, Variant, AVAILABLE_MEMORY, TRIGGERED_IRQ};
+pub use opcode_table::{OpcodeInfo, OPCODE_TABLE};
+pub use ram_init::RamInitPattern;
+pub use stats::{instruction_stats_to_csv, AddressingModeKind, InstructionStats};
+pub use tick::TickResult;
+pub use trace::{format_trace, TraceEntry, TRACE_LENGTH};
@@ -2,16 +2,17 @@ extern crate failure;
 extern crate mos6502cpu;
 
 use failure::Error;
-use mos6502cpu::{Cpu, Mos6502Cpu, AVAILABLE_MEMORY};
+use mos6502cpu::{format_trace, instruction_stats_to_csv, Cpu, Mos6502Cpu, AVAILABLE_MEMORY};
 use std::env::args;
 use std::fs::File;
 use std::io::Read;
 
-const USAGE: &str = "Usage: mos6502cpu [file] [starting address]
+const USAGE: &str = "Usage: mos6502cpu [file] [starting address] [--stats]
 
 Runs [file], a MOS 6502 compatible binary file, in the emulator.
 
-It starts at [starting address].";
+It starts at [starting address]. With --stats, it prints how many times each instruction was
+executed when it finishes.";
 
 fn read_file(file_name: &str) -> std::io::Result<[u8; AVAILABLE_MEMORY]> {
     let mut f = File::open(file_name)?;
@@ -20,21 +21,35 @@ fn read_file(file_name: &str) -> std::io::Result<[u8; AVAILABLE_MEMORY]> {
     Ok(memory)
 }
 
-fn test(memory: [u8; AVAILABLE_MEMORY], starting_address: u16) -> Result<(), Error> {
+fn test(memory: [u8; AVAILABLE_MEMORY], starting_address: u16, stats: bool) -> Result<(), Error> {
     let mut cpu = Mos6502Cpu::new(Box::new(memory));
     cpu.set_pc(starting_address);
+    if stats {
+        cpu.enable_stats();
+    }
+    let result = run(&mut cpu);
+    if stats {
+        print!("{}", instruction_stats_to_csv(&cpu.instruction_stats()));
+    }
+    result
+}
+
+fn run(cpu: &mut Mos6502Cpu) -> Result<(), Error> {
     while !cpu.is_done() {
-        cpu.execute()?;
+        if let Err(e) = cpu.execute() {
+            eprint!("Recent instructions:\n{}", format_trace(&cpu.recent_trace()));
+            return Err(e);
+        }
     }
     Ok(())
 }
 
 fn main() {
     let args: Vec<String> = args().collect();
-    if args.len() != 3 {
-        panic!(USAGE);
+    if args.len() < 3 || args.len() > 4 || (args.len() == 4 && args[3] != "--stats") {
+        panic!("{}", USAGE);
     }
     let memory = read_file(&args[1]).unwrap();
     let starting_address = args[2].parse::<u16>().unwrap();
-    test(memory, starting_address).unwrap();
+    test(memory, starting_address, args.len() == 4).unwrap();
 }
@@ -103,12 +103,12 @@ impl Mos6502Cpu {
         let mut al = (a & 0x0f)
             .wrapping_sub(value & 0x0f)
             .wrapping_sub(!self.registers.p.carry as u8);
-        if (al & 0x10) > 0 {
+        // The borrow has to be taken before the adjustment, which can clear the bit for invalid BCD
+        let borrow = (al & 0x10) > 0;
+        if borrow {
             al -= 6
         };
-        let mut ah = (a >> 4)
-            .wrapping_sub(value >> 4)
-            .wrapping_sub(((al & 0x10) > 0) as u8);
+        let mut ah = (a >> 4).wrapping_sub(value >> 4).wrapping_sub(borrow as u8);
         if (ah & 0x10) > 0 {
             ah -= 6
         };
@@ -324,6 +324,51 @@ mod tests {
         assert!(!cpu.registers.p.negative);
     }
 
+    // Results of an NMOS 6502, from http://www.6502.org/tutorials/decimal_mode.html
+    #[test]
+    fn it_should_add_and_subtract_in_decimal_mode() {
+        use instruction::Mos6502InstructionCode::{Adc, Sbc};
+        // (instruction, a, operand, carry, result, carry, zero, negative, overflow)
+        let vectors = [
+            (Adc, 0x09, 0x01, false, 0x10, false, false, false, false),
+            (Adc, 0x12, 0x34, false, 0x46, false, false, false, false),
+            (Adc, 0x15, 0x26, false, 0x41, false, false, false, false),
+            (Adc, 0x58, 0x46, true, 0x05, true, false, true, true),
+            (Adc, 0x81, 0x92, false, 0x73, true, false, false, true),
+            // Zero comes from the binary sum, 0x9a, and negative from before the last adjustment
+            (Adc, 0x99, 0x01, false, 0x00, true, false, true, false),
+            (Adc, 0x0f, 0x0f, true, 0x15, false, false, false, false),
+            (Sbc, 0x46, 0x12, true, 0x34, true, false, false, false),
+            (Sbc, 0x40, 0x13, true, 0x27, true, false, false, false),
+            (Sbc, 0x32, 0x02, false, 0x29, true, false, false, false),
+            (Sbc, 0x12, 0x21, true, 0x91, false, false, true, false),
+            (Sbc, 0x01, 0x01, true, 0x00, true, true, false, false),
+            (Sbc, 0x80, 0x01, true, 0x79, true, false, false, true),
+            (Sbc, 0x00, 0x0f, false, 0x9a, false, false, true, false),
+        ];
+        for (instruction, a, byte, carry, result, c, z, n, v) in vectors.iter().cloned() {
+            let case = format!("{:?} {:02x} {:02x} {}", instruction, a, byte, carry);
+            let m = [0; AVAILABLE_MEMORY];
+            let mut cpu = Mos6502Cpu::new(Box::new(m));
+            cpu.registers.a = a;
+            cpu.registers.p.carry = carry;
+            cpu.registers.p.decimal = true;
+            cpu.execute_instruction(&Mos6502Instruction {
+                instruction,
+                addressing_mode: AddressingMode::Immediate { byte },
+            })
+            .unwrap();
+            let flags = (
+                cpu.registers.p.carry,
+                cpu.registers.p.zero,
+                cpu.registers.p.negative,
+                cpu.registers.p.overflow,
+            );
+            assert_eq!(cpu.registers.a, result, "{}", case);
+            assert_eq!(flags, (c, z, n, v), "{}", case);
+        }
+    }
+
     #[test]
     fn it_should_set_zero_and_carry_on_cmp_on_same_values_but_not_negative() {
         let m = [0; AVAILABLE_MEMORY];
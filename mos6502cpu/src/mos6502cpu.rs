@@ -1,14 +1,19 @@
 use super::instruction::{AddressingMode, Mos6502InstructionCode};
 use bit_utils::two_bytes_to_word;
-use cpu::{Cpu, Cycles, Instruction};
+use cpu::{address_range, decode_instructions, Cpu, Cycles, Instruction, Memory};
 use failure::Error;
-use std::cell::RefCell;
+use instruction::Mos6502InstructionError;
+use stats::StatsCollector;
 use std::cmp::min;
-use std::rc::Rc;
+use std::ops::RangeBounds;
+use tick::{Latches, MicroState, TickResult};
+use trace::TraceBuffer;
 use {CpuResult, Mos6502Instruction};
 
 pub const AVAILABLE_MEMORY: usize = 0x10000;
 pub(crate) const INTERRUPT_HANDLERS_START: usize = 0xFFFA;
+// The IRQ source trigger_irq asserts, the other sources shouldn't use it
+pub const TRIGGERED_IRQ: u8 = 0x80;
 
 #[derive(Debug, Fail)]
 pub enum CpuError {
@@ -20,6 +25,8 @@ pub enum CpuError {
     InvalidAddressingMode,
     #[fail(display = "The instruction doesn't support that kind of cycle calculation.")]
     InvalidCyclesCalculation,
+    #[fail(display = "Unofficial opcode {:#04x} at {:#06x} while in strict mode.", opcode, pc)]
+    UnofficialOpcode { opcode: u8, pc: u16 },
 }
 
 pub(crate) struct ProcessorStatus {
@@ -91,37 +98,13 @@ impl RegisterSet {
     }
 }
 
-pub trait Memory {
-    fn set(&mut self, index: u16, new_value: u8);
-    fn get(&self, index: u16) -> u8;
-    fn len(&self) -> usize;
-    fn is_empty(&self) -> bool {
-        self.len() == 0
-    }
-}
-
-impl Memory for [u8; AVAILABLE_MEMORY] {
-    fn set(&mut self, index: u16, new_value: u8) {
-        self[index as usize] = new_value;
-    }
-    fn get(&self, index: u16) -> u8 {
-        self[index as usize]
-    }
-    fn len(&self) -> usize {
-        AVAILABLE_MEMORY
-    }
-}
-
-impl<T: Memory> Memory for Rc<RefCell<T>> {
-    fn set(&mut self, index: u16, new_value: u8) {
-        self.borrow_mut().set(index, new_value);
-    }
-    fn get(&self, index: u16) -> u8 {
-        self.borrow().get(index)
-    }
-    fn len(&self) -> usize {
-        self.borrow().len()
-    }
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Variant {
+    Nmos,
+    // The NES CPU, a 6502 without decimal mode
+    Ricoh2A03,
+    // Adds new instructions on top of some of the NMOS unofficial opcodes
+    Cmos65C02,
 }
 
 pub struct Mos6502Cpu {
@@ -129,25 +112,95 @@ pub struct Mos6502Cpu {
     pub(crate) registers: RegisterSet,
     pub(crate) page_crossed: bool,
     pub(crate) decimal_enabled: bool,
+    pub(crate) variant: Variant,
+    pub(crate) micro_state: MicroState,
+    pub(crate) latches: Latches,
+    pub(crate) irq_sources: u8,
+    pub(crate) nmi_line: bool,
+    pub(crate) nmi_pending: bool,
+    pub(crate) stats: Option<Box<StatsCollector>>,
+    pub(crate) strict: bool,
+    pub(crate) trace: TraceBuffer,
 }
 
 impl Mos6502Cpu {
     pub fn new(memory: Box<dyn Memory>) -> Mos6502Cpu {
-        Mos6502Cpu {
-            decimal_enabled: true,
-            memory,
-            registers: RegisterSet::new(),
-            page_crossed: false,
-        }
+        Mos6502Cpu::with_variant(memory, Variant::Nmos)
     }
 
     pub fn without_decimal(memory: Box<dyn Memory>) -> Mos6502Cpu {
+        Mos6502Cpu::with_variant(memory, Variant::Ricoh2A03)
+    }
+
+    pub fn with_variant(memory: Box<dyn Memory>, variant: Variant) -> Mos6502Cpu {
         Mos6502Cpu {
-            decimal_enabled: false,
+            decimal_enabled: variant != Variant::Ricoh2A03,
+            variant,
             memory,
             registers: RegisterSet::new(),
             page_crossed: false,
+            micro_state: MicroState::Fetch,
+            latches: Latches::new(),
+            irq_sources: 0,
+            nmi_line: false,
+            nmi_pending: false,
+            stats: None,
+            strict: false,
+            trace: TraceBuffer::new(),
+        }
+    }
+
+    #[inline]
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    // In strict mode, unofficial opcodes are reported as errors instead of executed
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    #[inline]
+    pub fn set_irq(&mut self, source: u8, asserted: bool) {
+        if asserted {
+            self.irq_sources |= source;
+        } else {
+            self.irq_sources &= !source;
+        }
+    }
+
+    #[inline]
+    pub fn is_irq_asserted(&self) -> bool {
+        self.irq_sources != 0
+    }
+
+    // Unlike IRQ, NMI goes off when the line gets asserted. Holding it doesn't trigger it again,
+    // it has to be released first
+    #[inline]
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    // A pulse on the NMI line, taken after the current instruction. While something else holds
+    // the line there's no edge, so nothing happens
+    pub fn trigger_nmi(&mut self) {
+        if !self.nmi_line {
+            self.set_nmi(true);
+            self.set_nmi(false);
+        }
+    }
+
+    // Asserts TRIGGERED_IRQ until the CPU takes the interrupt, after the current instruction.
+    // Returns whether it will, with interrupts disabled it does nothing
+    pub fn trigger_irq(&mut self) -> bool {
+        if self.registers.p.interrupt_disable {
+            return false;
         }
+        self.set_irq(TRIGGERED_IRQ, true);
+        true
     }
 
     #[inline]
@@ -209,18 +262,26 @@ impl Mos6502Cpu {
                 Ok(two_bytes_to_word(high_byte, low_byte))
             }
             AddressingMode::IndirectIndexed { byte } => {
-                let (low_byte, high_byte) = (
-                    self.memory.get(u16::from(*byte)),
-                    self.memory.get(u16::from(*byte) + 1),
-                );
-                Ok(two_bytes_to_word(high_byte, low_byte) + u16::from(self.registers.y))
+                Ok(self.get_zero_page_pointer(*byte) + u16::from(self.registers.y))
             }
+            AddressingMode::ZeroPageIndirect { byte } => Ok(self.get_zero_page_pointer(*byte)),
             _ => Err(CpuError::InvalidAddressingMode),
         }
     }
 
+    // The pointer wraps around the zero page, a pointer at $FF takes its high byte from $00
+    #[inline]
+    fn get_zero_page_pointer(&self, byte: u8) -> u16 {
+        let (low_byte, high_byte) = (
+            self.memory.get(u16::from(byte)),
+            self.memory.get(u16::from(byte.wrapping_add(1))),
+        );
+        two_bytes_to_word(high_byte, low_byte)
+    }
+
+    // Indexed reads record whether the index moved them to another page, which costs them a cycle
     pub(crate) fn get_value_from_addressing_mode(
-        &self,
+        &mut self,
         addressing_mode: &AddressingMode,
     ) -> Result<u8, CpuError> {
         match addressing_mode {
@@ -236,16 +297,34 @@ impl Mos6502Cpu {
             AddressingMode::Absolute { .. } => Ok(self
                 .memory
                 .get(self.get_address_from_addressing_mode(addressing_mode)?)),
-            AddressingMode::AbsoluteIndexedX { .. } => Ok(self
-                .memory
-                .get(self.get_address_from_addressing_mode(addressing_mode)?)),
-            AddressingMode::AbsoluteIndexedY { .. } => Ok(self
-                .memory
-                .get(self.get_address_from_addressing_mode(addressing_mode)?)),
+            AddressingMode::AbsoluteIndexedX {
+                high_byte,
+                low_byte,
+            } => {
+                let x = u16::from(self.registers.x);
+                let address = two_bytes_to_word(*high_byte, *low_byte);
+                self.update_page_crossed_status(address, address + x);
+                Ok(self.memory.get(address + x))
+            }
+            AddressingMode::AbsoluteIndexedY {
+                high_byte,
+                low_byte,
+            } => {
+                let y = u16::from(self.registers.y);
+                let address = two_bytes_to_word(*high_byte, *low_byte);
+                self.update_page_crossed_status(address, address + y);
+                Ok(self.memory.get(address + y))
+            }
             AddressingMode::IndexedIndirect { .. } => Ok(self
                 .memory
                 .get(self.get_address_from_addressing_mode(addressing_mode)?)),
-            AddressingMode::IndirectIndexed { .. } => Ok(self
+            AddressingMode::IndirectIndexed { byte } => {
+                let indirect_address = self.get_zero_page_pointer(*byte);
+                let direct_address = indirect_address + u16::from(self.registers.y);
+                self.update_page_crossed_status(indirect_address, direct_address);
+                Ok(self.memory.get(direct_address))
+            }
+            AddressingMode::ZeroPageIndirect { .. } => Ok(self
                 .memory
                 .get(self.get_address_from_addressing_mode(addressing_mode)?)),
             _ => Err(CpuError::InvalidAddressingMode),
@@ -317,23 +396,47 @@ impl Mos6502Cpu {
             }
             AddressingMode::IndirectIndexed { byte } => {
                 let y = u16::from(self.registers.y);
-                let (low_byte, high_byte) = (
-                    self.memory.get(u16::from(*byte)),
-                    self.memory.get(u16::from(*byte) + 1),
-                );
-                let indirect_address = two_bytes_to_word(high_byte, low_byte);
+                let indirect_address = self.get_zero_page_pointer(*byte);
                 let direct_address = indirect_address + y;
                 self.update_page_crossed_status(indirect_address, direct_address);
                 self.memory.set(direct_address, new_value);
                 Ok(())
             }
+            AddressingMode::ZeroPageIndirect { .. } => {
+                let address = self.get_address_from_addressing_mode(addressing_mode)?;
+                self.memory.set(address, new_value);
+                Ok(())
+            }
             _ => Err(CpuError::InvalidAddressingMode),
         }
     }
 
     #[inline]
     pub(crate) fn update_page_crossed_status(&mut self, original: u16, new: u16) {
-        self.page_crossed = (original & 0xff00) == (new & 0xff00);
+        self.page_crossed = (original & 0xff00) != (new & 0xff00);
+    }
+
+    // Stores and read-modify-writes through an index always spend a cycle fixing the high byte,
+    // and the NMOS parts read from the address before the fix during it. Without a page crossing
+    // that's the final address, so it just gets read one more time. The 65C02 reads the last
+    // instruction byte instead, which nothing can see
+    pub(crate) fn dummy_read_before_indexed_write(&self, addressing_mode: &AddressingMode) {
+        if self.variant == Variant::Cmos65C02 {
+            return;
+        }
+        let (address, index) = match addressing_mode {
+            AddressingMode::AbsoluteIndexedX {
+                high_byte,
+                low_byte,
+            } => (two_bytes_to_word(*high_byte, *low_byte), self.registers.x),
+            AddressingMode::AbsoluteIndexedY {
+                high_byte,
+                low_byte,
+            } => (two_bytes_to_word(*high_byte, *low_byte), self.registers.y),
+            _ => return,
+        };
+        let unfixed = (address & 0xff00) | (address as u8).wrapping_add(index) as u16;
+        self.memory.get(unfixed);
     }
 
     #[inline]
@@ -350,6 +453,14 @@ impl Mos6502Cpu {
 }
 
 impl Cpu<Mos6502Instruction, CpuError> for Mos6502Cpu {
+    fn execute(&mut self) -> Result<u8, Error> {
+        loop {
+            if let TickResult::InstructionBoundary { cycles } = self.tick()? {
+                return Ok(cycles);
+            }
+        }
+    }
+
     fn get_cycles_for_instruction(
         &mut self,
         instruction: &Mos6502Instruction,
@@ -391,6 +502,7 @@ impl Cpu<Mos6502Instruction, CpuError> for Mos6502Cpu {
             Mos6502InstructionCode::Bmi => self.execute_bmi(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Bne => self.execute_bne(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Bpl => self.execute_bpl(&instruction.addressing_mode)?,
+            Mos6502InstructionCode::Bra => self.execute_bra(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Brk => self.execute_brk(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Bvc => self.execute_bvc(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Bvs => self.execute_bvs(&instruction.addressing_mode)?,
@@ -424,8 +536,12 @@ impl Cpu<Mos6502Instruction, CpuError> for Mos6502Cpu {
             Mos6502InstructionCode::Ora => self.execute_ora(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Pha => self.execute_pha(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Php => self.execute_php(&instruction.addressing_mode)?,
+            Mos6502InstructionCode::Phx => self.execute_phx(&instruction.addressing_mode)?,
+            Mos6502InstructionCode::Phy => self.execute_phy(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Pla => self.execute_pla(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Plp => self.execute_plp(&instruction.addressing_mode)?,
+            Mos6502InstructionCode::Plx => self.execute_plx(&instruction.addressing_mode)?,
+            Mos6502InstructionCode::Ply => self.execute_ply(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Rla => self.execute_rla(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Rol => self.execute_rol(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Ror => self.execute_ror(&instruction.addressing_mode)?,
@@ -445,9 +561,12 @@ impl Cpu<Mos6502Instruction, CpuError> for Mos6502Cpu {
             Mos6502InstructionCode::Sta => self.execute_sta(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Stx => self.execute_stx(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Sty => self.execute_sty(&instruction.addressing_mode)?,
+            Mos6502InstructionCode::Stz => self.execute_stz(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Tas => self.execute_tas(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Tax => self.execute_tax(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Tay => self.execute_tay(&instruction.addressing_mode)?,
+            Mos6502InstructionCode::Trb => self.execute_trb(&instruction.addressing_mode)?,
+            Mos6502InstructionCode::Tsb => self.execute_tsb(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Tsx => self.execute_tsx(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Txa => self.execute_txa(&instruction.addressing_mode)?,
             Mos6502InstructionCode::Txs => self.execute_txs(&instruction.addressing_mode)?,
@@ -461,16 +580,20 @@ impl Cpu<Mos6502Instruction, CpuError> for Mos6502Cpu {
         self.registers.pc
     }
 
-    fn get_next_instruction_bytes(&self) -> Vec<u8> {
-        let mut res = Vec::with_capacity(3);
+    fn get_next_instruction_bytes(&self) -> [u8; 3] {
+        let mut res = [0; 3];
         let from = self.registers.pc as usize;
         let to = min(from + 3, self.memory.len());
         for i in from..to {
-            res.push(self.memory.get(i as u16));
+            res[i - from] = self.memory.get(i as u16);
         }
         res
     }
 
+    fn decode_instruction(&self, bytes: &[u8]) -> Mos6502Instruction {
+        Mos6502Instruction::decode(bytes, self.variant)
+    }
+
     fn can_run(&self, _: &Mos6502Instruction) -> bool {
         true
     }
@@ -483,6 +606,17 @@ impl Cpu<Mos6502Instruction, CpuError> for Mos6502Cpu {
         self.registers.pc += u16::from(steps)
     }
 
+    // Takes the cycles of an interrupt, only reading the stack, and the PC comes from the reset
+    // vector at the end. It disables interrupts too, an NMI on its way is lost
+    fn reset(&mut self) {
+        self.registers = RegisterSet::new();
+        self.registers.p.interrupt_disable = true;
+        self.page_crossed = false;
+        self.nmi_pending = false;
+        self.latches = Latches::new();
+        self.micro_state = MicroState::Reset;
+    }
+
     fn get_cycles_from_one_condition(
         &self,
         instruction: &Mos6502Instruction,
@@ -501,6 +635,7 @@ impl Cpu<Mos6502Instruction, CpuError> for Mos6502Cpu {
         match instruction.instruction {
             Mos6502InstructionCode::Adc => page_crossed_condition!(),
             Mos6502InstructionCode::And => page_crossed_condition!(),
+            Mos6502InstructionCode::Bra => page_crossed_condition!(),
             Mos6502InstructionCode::Cmp => page_crossed_condition!(),
             Mos6502InstructionCode::Eor => page_crossed_condition!(),
             Mos6502InstructionCode::Lax => page_crossed_condition!(),
@@ -521,37 +656,56 @@ impl Cpu<Mos6502Instruction, CpuError> for Mos6502Cpu {
         first_met: u8,
         second_met: u8,
     ) -> Result<u8, Error> {
-        macro_rules! bicondition {
-            ($condition:expr) => {
-                if $condition {
-                    if self.page_crossed {
-                        Ok(second_met)
-                    } else {
-                        Ok(first_met)
-                    }
-                } else {
+        match instruction.instruction {
+            Mos6502InstructionCode::Bcc
+            | Mos6502InstructionCode::Bcs
+            | Mos6502InstructionCode::Beq
+            | Mos6502InstructionCode::Bmi
+            | Mos6502InstructionCode::Bne
+            | Mos6502InstructionCode::Bpl
+            | Mos6502InstructionCode::Bvc
+            | Mos6502InstructionCode::Bvs => {
+                if !self.is_branch_taken(&instruction.instruction) {
                     Ok(not_met)
+                } else if self.page_crossed {
+                    Ok(second_met)
+                } else {
+                    Ok(first_met)
                 }
-            };
-        }
-        match instruction.instruction {
-            Mos6502InstructionCode::Bcc => bicondition!(!self.registers.p.carry),
-            Mos6502InstructionCode::Bcs => bicondition!(self.registers.p.carry),
-            Mos6502InstructionCode::Beq => bicondition!(self.registers.p.zero),
-            Mos6502InstructionCode::Bmi => bicondition!(self.registers.p.negative),
-            Mos6502InstructionCode::Bne => bicondition!(!self.registers.p.zero),
-            Mos6502InstructionCode::Bpl => bicondition!(!self.registers.p.negative),
-            Mos6502InstructionCode::Bvc => bicondition!(!self.registers.p.overflow),
-            Mos6502InstructionCode::Bvs => bicondition!(self.registers.p.overflow),
+            }
             _ => Err(Error::from(CpuError::InvalidCyclesCalculation)),
         }
     }
 }
 
+impl Mos6502Cpu {
+    // Decodes the range an instruction at a time. An instruction that doesn't fit in what's left
+    // of the range is an error, and after an error the walk moves on a single byte
+    pub fn iter_instructions<R: RangeBounds<u16>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (u16, Result<Mos6502Instruction, Mos6502InstructionError>)> + '_ {
+        decode_instructions(
+            address_range(&range),
+            move |address| self.memory.get(address),
+            move |bytes| Some(self.decode_instruction(bytes)),
+        )
+        .map(|(address, instruction)| {
+            (address, instruction.ok_or(Mos6502InstructionError::Truncated { address }))
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use instruction::AddressingMode;
-    use mos6502cpu::{Mos6502Cpu, AVAILABLE_MEMORY};
+    use cpu::{Cpu, HookAction};
+    use instruction::{AddressingMode, Mos6502InstructionCode, Mos6502InstructionError};
+    use mos6502cpu::{Mos6502Cpu, Variant, AVAILABLE_MEMORY};
+    use Memory;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use tick::TickResult;
+    use Mos6502Instruction;
 
     #[test]
     fn it_should_get_value_from_addressing_mode_for_accumulator() {
@@ -568,7 +722,7 @@ mod tests {
     #[test]
     fn it_should_get_value_from_addressing_mode_for_immediate() {
         let m = [0; AVAILABLE_MEMORY];
-        let cpu = Mos6502Cpu::new(Box::new(m));
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
         assert_eq!(
             cpu.get_value_from_addressing_mode(&AddressingMode::Immediate { byte: 0x42 })
                 .unwrap(),
@@ -903,4 +1057,359 @@ mod tests {
             .unwrap();
         assert_eq!(address, 0x4028);
     }
+
+    #[test]
+    fn it_should_show_the_hook_the_instruction_of_the_variant() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        // STZ $10; INX; JMP $0000
+        for (i, byte) in [0x64, 0x10, 0xe8, 0x4c, 0x00, 0x00].iter().enumerate() {
+            m.set(i as u16, *byte);
+        }
+        let mut cpu = Mos6502Cpu::with_variant(Box::new(m), Variant::Cmos65C02);
+        let mut seen = vec![];
+        cpu.execute_with_hook(&mut |pc, instruction| {
+            seen.push((pc, instruction.to_string()));
+            HookAction::Skip
+        })
+        .unwrap();
+        assert_eq!(seen, vec![(0, String::from("STZ $10"))]);
+        assert_eq!(cpu.registers.pc, 2);
+        assert_eq!(cpu.run_until(3).unwrap(), 2);
+        assert_eq!(cpu.registers.x, 1);
+        assert_eq!(cpu.run_until(2).unwrap(), 6);
+        assert_eq!(cpu.registers.x, 1);
+    }
+
+    #[test]
+    fn it_should_report_instructions_cut_by_the_end_of_the_range() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        // INX; LDA $E8E8
+        for (i, byte) in [0xe8, 0xad, 0xe8, 0xe8].iter().enumerate() {
+            m.set(0x200 + i as u16, *byte);
+        }
+        let cpu = Mos6502Cpu::new(Box::new(m));
+        let instructions: Vec<(u16, String)> = cpu
+            .iter_instructions(0x200..0x203)
+            .map(|(pc, instruction)| match instruction {
+                Ok(instruction) => (pc, instruction.instruction.to_string()),
+                Err(Mos6502InstructionError::Truncated { address }) => (address, String::new()),
+                Err(e) => panic!("Unexpected error {}", e),
+            })
+            .collect();
+        assert_eq!(
+            instructions,
+            vec![
+                (0x200, String::from("INX")),
+                (0x201, String::new()),
+                (0x202, String::from("INX")),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_should_walk_data_one_instruction_at_a_time() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        for address in 0x300..0x380 {
+            m.set(address, 0x0d);
+        }
+        let cpu = Mos6502Cpu::new(Box::new(m));
+        // ORA $0D0D every three bytes, until there's no room for another one
+        let instructions: Vec<(u16, bool)> = cpu
+            .iter_instructions(0x300..0x380)
+            .map(|(pc, instruction)| (pc, instruction.is_ok()))
+            .collect();
+        assert_eq!(instructions.len(), 44);
+        assert_eq!(instructions[41], (0x37b, true));
+        assert_eq!(instructions[42..], [(0x37e, false), (0x37f, false)]);
+        assert_eq!(cpu.iter_instructions(0xfffe..).count(), 2);
+    }
+
+    #[test]
+    fn it_should_reset_to_the_reset_vector() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m[0x8000..0x8005].copy_from_slice(&[
+            0xa9, 0x42, // LDA #$42
+            0xa2, 0x24, // LDX #$24
+            0x78, // SEI
+        ]);
+        m[0xfffc..0xfffe].copy_from_slice(&[0x00, 0x80]);
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.reset();
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x8000);
+        for _ in 0..3 {
+            cpu.execute().unwrap();
+        }
+        assert_eq!(cpu.registers.pc, 0x8005);
+        cpu.reset();
+        assert_eq!((cpu.registers.a, cpu.registers.x, cpu.registers.s), (0, 0, 0xff));
+        assert!(cpu.registers.p.interrupt_disable);
+        assert_eq!(cpu.memory.get(0x8000), 0xa9);
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x8000);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn it_should_take_a_cycle_more_when_indexing_crosses_a_page() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m[0..3].copy_from_slice(&[0xbd, 0xf0, 0x12]); // LDA $12F0,X
+        m[3..5].copy_from_slice(&[0xb1, 0x20]); // LDA ($20),Y
+        m[0x20..0x22].copy_from_slice(&[0xf0, 0x12]);
+        m[0x1300] = 0x42;
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        for (index, cycles, value) in [(0x0f, 4, 0x00), (0x10, 5, 0x42)].iter() {
+            cpu.registers.pc = 0;
+            cpu.registers.x = *index;
+            assert_eq!(cpu.execute().unwrap(), *cycles);
+            assert_eq!(cpu.registers.a, *value);
+            cpu.registers.y = *index;
+            assert_eq!(cpu.execute().unwrap(), cycles + 1);
+            assert_eq!(cpu.registers.a, *value);
+        }
+    }
+
+    #[test]
+    fn it_should_wrap_an_indirect_indexed_pointer_around_the_zero_page() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m[0..2].copy_from_slice(&[0xb1, 0xff]); // LDA ($FF),Y
+        m[2..4].copy_from_slice(&[0x91, 0xff]); // STA ($FF),Y
+        m[0xff] = 0xf0;
+        m[0x00] = 0xb1; // Also the first opcode
+        m[0x100] = 0x12; // Where the pointer would end without the wrap
+        m[0xb200] = 0x42;
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.registers.y = 0x10;
+        assert_eq!(cpu.execute().unwrap(), 6);
+        assert_eq!(cpu.registers.a, 0x42);
+        cpu.registers.a = 0x24;
+        cpu.registers.y = 0x11;
+        cpu.execute().unwrap();
+        assert_eq!(cpu.memory.get(0xb201), 0x24);
+        assert_eq!(cpu.memory.get(0x1301), 0x00);
+    }
+
+    #[test]
+    fn it_should_take_a_cycle_more_when_a_branch_crosses_a_page() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m[0x10f0..0x10f2].copy_from_slice(&[0xd0, 0x02]); // BNE +2
+        m[0x10fc..0x10fe].copy_from_slice(&[0xd0, 0x02]);
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.registers.p.zero = false;
+        cpu.registers.pc = 0x10f0;
+        assert_eq!(cpu.execute().unwrap(), 3);
+        assert_eq!(cpu.registers.pc, 0x10f4);
+        cpu.registers.pc = 0x10fc;
+        assert_eq!(cpu.execute().unwrap(), 4);
+        assert_eq!(cpu.registers.pc, 0x1100);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Access {
+        Read(u16),
+        Write(u16),
+    }
+
+    struct RecordingMemory {
+        bytes: [u8; AVAILABLE_MEMORY],
+        accesses: RefCell<Vec<Access>>,
+    }
+
+    impl Memory for RecordingMemory {
+        fn set(&mut self, index: u16, new_value: u8) {
+            self.accesses.borrow_mut().push(Access::Write(index));
+            self.bytes[index as usize] = new_value;
+        }
+
+        fn get(&self, index: u16) -> u8 {
+            self.accesses.borrow_mut().push(Access::Read(index));
+            self.bytes[index as usize]
+        }
+
+        fn len(&self) -> usize {
+            AVAILABLE_MEMORY
+        }
+    }
+
+    fn recorded(
+        variant: Variant,
+        instruction: Mos6502InstructionCode,
+        x: u8,
+    ) -> (Vec<Access>, Rc<RefCell<RecordingMemory>>) {
+        let memory = Rc::new(RefCell::new(RecordingMemory {
+            bytes: [0; AVAILABLE_MEMORY],
+            accesses: RefCell::new(Vec::new()),
+        }));
+        let mut cpu = Mos6502Cpu::with_variant(Box::new(memory.clone()), variant);
+        cpu.registers.a = 0x42;
+        cpu.registers.x = x;
+        cpu.execute_instruction(&Mos6502Instruction {
+            instruction,
+            addressing_mode: AddressingMode::AbsoluteIndexedX {
+                high_byte: 0x20,
+                low_byte: 0xff,
+            },
+        })
+        .unwrap();
+        let accesses = memory.borrow().accesses.replace(Vec::new());
+        (accesses, memory)
+    }
+
+    #[test]
+    fn it_should_read_the_unfixed_address_before_an_indexed_store() {
+        let (accesses, memory) = recorded(Variant::Nmos, Mos6502InstructionCode::Sta, 1);
+        assert_eq!(accesses, vec![Access::Read(0x2000), Access::Write(0x2100)]);
+        assert_eq!(memory.borrow().bytes[0x2100], 0x42);
+        let (accesses, _) = recorded(Variant::Ricoh2A03, Mos6502InstructionCode::Sta, 1);
+        assert_eq!(accesses, vec![Access::Read(0x2000), Access::Write(0x2100)]);
+    }
+
+    #[test]
+    fn it_should_read_the_final_address_before_an_indexed_store_in_the_same_page() {
+        let (accesses, _) = recorded(Variant::Nmos, Mos6502InstructionCode::Sta, 0);
+        assert_eq!(accesses, vec![Access::Read(0x20ff), Access::Write(0x20ff)]);
+    }
+
+    #[test]
+    fn it_should_read_the_unfixed_address_before_an_indexed_read_modify_write() {
+        let (accesses, _) = recorded(Variant::Nmos, Mos6502InstructionCode::Inc, 1);
+        assert_eq!(
+            accesses,
+            vec![
+                Access::Read(0x2000),
+                Access::Read(0x2100),
+                Access::Write(0x2100)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_should_not_read_before_an_indexed_store_on_the_65c02() {
+        let (accesses, _) = recorded(Variant::Cmos65C02, Mos6502InstructionCode::Sta, 1);
+        assert_eq!(accesses, vec![Access::Write(0x2100)]);
+    }
+
+    fn recording_cpu(bytes: [u8; AVAILABLE_MEMORY]) -> (Mos6502Cpu, Rc<RefCell<RecordingMemory>>) {
+        let memory = Rc::new(RefCell::new(RecordingMemory {
+            bytes,
+            accesses: RefCell::new(Vec::new()),
+        }));
+        (Mos6502Cpu::new(Box::new(memory.clone())), memory)
+    }
+
+    // The accesses of every tick until the end of the instruction
+    fn accesses_by_tick(
+        cpu: &mut Mos6502Cpu,
+        memory: &Rc<RefCell<RecordingMemory>>,
+    ) -> (Vec<Vec<Access>>, TickResult) {
+        let mut ticks = vec![];
+        loop {
+            let result = cpu.tick().unwrap();
+            ticks.push(memory.borrow().accesses.replace(Vec::new()));
+            if result != TickResult::Cycle {
+                return (ticks, result);
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_access_the_bus_once_a_tick_for_an_indexed_read_modify_write() {
+        let mut bytes = [0; AVAILABLE_MEMORY];
+        bytes[0x600..0x603].copy_from_slice(&[0xfe, 0xff, 0x20]); // INC $20FF,X
+        bytes[0x2100] = 0x41;
+        let (mut cpu, memory) = recording_cpu(bytes);
+        cpu.set_pc(0x600);
+        cpu.registers.x = 1;
+        let mut ticks = vec![];
+        for _ in 0..6 {
+            assert_eq!(cpu.tick().unwrap(), TickResult::Cycle);
+            ticks.push(memory.borrow().accesses.replace(Vec::new()));
+        }
+        // The first write puts back what was read, the modified value comes a cycle later
+        assert_eq!(memory.borrow().bytes[0x2100], 0x41);
+        assert_eq!(
+            cpu.tick().unwrap(),
+            TickResult::InstructionBoundary { cycles: 7 }
+        );
+        ticks.push(memory.borrow().accesses.replace(Vec::new()));
+        assert_eq!(
+            ticks,
+            vec![
+                vec![Access::Read(0x600)],
+                vec![Access::Read(0x601)],
+                vec![Access::Read(0x602)],
+                vec![Access::Read(0x2000)],
+                vec![Access::Read(0x2100)],
+                vec![Access::Write(0x2100)],
+                vec![Access::Write(0x2100)],
+            ]
+        );
+        assert_eq!(memory.borrow().bytes[0x2100], 0x42);
+        assert_eq!(cpu.registers.pc, 0x603);
+    }
+
+    #[test]
+    fn it_should_access_the_bus_once_a_tick_for_a_taken_branch() {
+        let mut bytes = [0; AVAILABLE_MEMORY];
+        bytes[0x10f0..0x10f2].copy_from_slice(&[0xd0, 0x02]); // BNE +2
+        bytes[0x10fc..0x10fe].copy_from_slice(&[0xd0, 0x02]);
+        let (mut cpu, memory) = recording_cpu(bytes);
+        cpu.registers.p.zero = false;
+        cpu.set_pc(0x10f0);
+        let (ticks, result) = accesses_by_tick(&mut cpu, &memory);
+        assert_eq!(
+            ticks,
+            vec![
+                vec![Access::Read(0x10f0)],
+                vec![Access::Read(0x10f1)],
+                vec![Access::Read(0x10f2)],
+            ]
+        );
+        assert_eq!(result, TickResult::InstructionBoundary { cycles: 3 });
+        assert_eq!(cpu.registers.pc, 0x10f4);
+        // To another page, the PC has the wrong high byte for a cycle
+        cpu.set_pc(0x10fc);
+        let (ticks, result) = accesses_by_tick(&mut cpu, &memory);
+        assert_eq!(
+            ticks,
+            vec![
+                vec![Access::Read(0x10fc)],
+                vec![Access::Read(0x10fd)],
+                vec![Access::Read(0x10fe)],
+                vec![Access::Read(0x1000)],
+            ]
+        );
+        assert_eq!(result, TickResult::InstructionBoundary { cycles: 4 });
+        assert_eq!(cpu.registers.pc, 0x1100);
+    }
+
+    #[test]
+    fn it_should_let_the_memory_see_the_writes_of_a_program() {
+        let mut bytes = [0; AVAILABLE_MEMORY];
+        bytes[0x600..0x605].copy_from_slice(&[
+            0xa9, 0x80, // LDA #$80
+            0x8d, 0x00, 0x20, // STA $2000
+        ]);
+        let memory = Rc::new(RefCell::new(RecordingMemory {
+            bytes,
+            accesses: RefCell::new(Vec::new()),
+        }));
+        let mut cpu = Mos6502Cpu::without_decimal(Box::new(memory.clone()));
+        cpu.set_pc(0x600);
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        let writes: Vec<Access> = memory
+            .borrow()
+            .accesses
+            .replace(Vec::new())
+            .into_iter()
+            .filter(|access| match access {
+                Access::Write(_) => true,
+                Access::Read(_) => false,
+            })
+            .collect();
+        assert_eq!(writes, vec![Access::Write(0x2000)]);
+        assert_eq!(memory.borrow().bytes[0x2000], 0x80);
+    }
 }
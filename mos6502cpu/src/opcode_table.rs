@@ -0,0 +1,316 @@
+use super::cpu::Cycles;
+use stats::AddressingModeKind;
+use stats::AddressingModeKind::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpcodeInfo {
+    pub mnemonic: &'static str,
+    pub size: u8,
+    pub cycles: Cycles,
+    pub kind: AddressingModeKind,
+}
+
+const fn info(mnemonic: &'static str, kind: AddressingModeKind, cycles: Cycles) -> OpcodeInfo {
+    let size = match kind {
+        Implicit | Accumulator => 1,
+        Absolute | AbsoluteIndexedX | AbsoluteIndexedY | Indirect => 3,
+        _ => 2,
+    };
+    OpcodeInfo {
+        mnemonic,
+        size,
+        cycles,
+        kind,
+    }
+}
+
+// What the NMOS decoder makes of every opcode, for tools that want it as data. Opcodes that jam
+// the CPU are implicit NOPs, like the decoder reads them
+pub static OPCODE_TABLE: [OpcodeInfo; 256] = [
+    info("BRK", Implicit, single!(7)),                 // 00
+    info("ORA", IndexedIndirect, single!(6)),          // 01
+    info("NOP", Implicit, single!(2)),                 // 02
+    info("NOP", Implicit, single!(2)),                 // 03
+    info("NOP", ZeroPage, single!(3)),                 // 04
+    info("ORA", ZeroPage, single!(3)),                 // 05
+    info("ASL", ZeroPage, single!(5)),                 // 06
+    info("NOP", Implicit, single!(2)),                 // 07
+    info("PHP", Implicit, single!(3)),                 // 08
+    info("ORA", Immediate, single!(2)),                // 09
+    info("ASL", Accumulator, single!(2)),              // 0a
+    info("NOP", Implicit, single!(2)),                 // 0b
+    info("NOP", Absolute, single!(4)),                 // 0c
+    info("ORA", Absolute, single!(4)),                 // 0d
+    info("ASL", Absolute, single!(6)),                 // 0e
+    info("NOP", Implicit, single!(2)),                 // 0f
+    info("BPL", Relative, bi_conditional!(2, 3, 4)),   // 10
+    info("ORA", IndirectIndexed, conditional!(5, 6)),  // 11
+    info("NOP", Implicit, single!(2)),                 // 12
+    info("NOP", Implicit, single!(2)),                 // 13
+    info("NOP", ZeroPageIndexedX, single!(4)),         // 14
+    info("ORA", ZeroPageIndexedX, single!(4)),         // 15
+    info("ASL", ZeroPageIndexedX, single!(6)),         // 16
+    info("NOP", Implicit, single!(2)),                 // 17
+    info("CLC", Implicit, single!(2)),                 // 18
+    info("ORA", AbsoluteIndexedY, conditional!(4, 5)), // 19
+    info("NOP", Implicit, single!(2)),                 // 1a
+    info("NOP", Implicit, single!(2)),                 // 1b
+    info("NOP", AbsoluteIndexedX, conditional!(4, 5)), // 1c
+    info("ORA", AbsoluteIndexedX, conditional!(4, 5)), // 1d
+    info("ASL", AbsoluteIndexedX, single!(7)),         // 1e
+    info("NOP", Implicit, single!(2)),                 // 1f
+    info("JSR", Absolute, single!(6)),                 // 20
+    info("AND", IndexedIndirect, single!(6)),          // 21
+    info("NOP", Implicit, single!(2)),                 // 22
+    info("NOP", Implicit, single!(2)),                 // 23
+    info("BIT", ZeroPage, single!(3)),                 // 24
+    info("AND", ZeroPage, single!(3)),                 // 25
+    info("ROL", ZeroPage, single!(5)),                 // 26
+    info("NOP", Implicit, single!(2)),                 // 27
+    info("PLP", Implicit, single!(4)),                 // 28
+    info("AND", Immediate, single!(2)),                // 29
+    info("ROL", Accumulator, single!(2)),              // 2a
+    info("NOP", Implicit, single!(2)),                 // 2b
+    info("BIT", Absolute, single!(4)),                 // 2c
+    info("AND", Absolute, single!(4)),                 // 2d
+    info("ROL", Absolute, single!(6)),                 // 2e
+    info("NOP", Implicit, single!(2)),                 // 2f
+    info("BMI", Relative, bi_conditional!(2, 3, 4)),   // 30
+    info("AND", IndirectIndexed, conditional!(5, 6)),  // 31
+    info("NOP", Implicit, single!(2)),                 // 32
+    info("NOP", Implicit, single!(2)),                 // 33
+    info("NOP", ZeroPageIndexedX, single!(4)),         // 34
+    info("AND", ZeroPageIndexedX, single!(4)),         // 35
+    info("ROL", ZeroPageIndexedX, single!(6)),         // 36
+    info("NOP", Implicit, single!(2)),                 // 37
+    info("SEC", Implicit, single!(2)),                 // 38
+    info("AND", AbsoluteIndexedY, conditional!(4, 5)), // 39
+    info("NOP", Implicit, single!(2)),                 // 3a
+    info("NOP", Implicit, single!(2)),                 // 3b
+    info("NOP", AbsoluteIndexedX, conditional!(4, 5)), // 3c
+    info("AND", AbsoluteIndexedX, conditional!(4, 5)), // 3d
+    info("ROL", AbsoluteIndexedX, single!(7)),         // 3e
+    info("NOP", Implicit, single!(2)),                 // 3f
+    info("RTI", Implicit, single!(6)),                 // 40
+    info("EOR", IndexedIndirect, single!(6)),          // 41
+    info("NOP", Implicit, single!(2)),                 // 42
+    info("NOP", Implicit, single!(2)),                 // 43
+    info("NOP", ZeroPage, single!(3)),                 // 44
+    info("EOR", ZeroPage, single!(3)),                 // 45
+    info("LSR", ZeroPage, single!(5)),                 // 46
+    info("NOP", Implicit, single!(2)),                 // 47
+    info("PHA", Implicit, single!(3)),                 // 48
+    info("EOR", Immediate, single!(2)),                // 49
+    info("LSR", Accumulator, single!(2)),              // 4a
+    info("NOP", Implicit, single!(2)),                 // 4b
+    info("JMP", Absolute, single!(3)),                 // 4c
+    info("EOR", Absolute, single!(4)),                 // 4d
+    info("LSR", Absolute, single!(6)),                 // 4e
+    info("NOP", Implicit, single!(2)),                 // 4f
+    info("BVC", Relative, bi_conditional!(2, 3, 4)),   // 50
+    info("EOR", IndirectIndexed, conditional!(5, 6)),  // 51
+    info("NOP", Implicit, single!(2)),                 // 52
+    info("NOP", Implicit, single!(2)),                 // 53
+    info("NOP", ZeroPageIndexedX, single!(4)),         // 54
+    info("EOR", ZeroPageIndexedX, single!(4)),         // 55
+    info("LSR", ZeroPageIndexedX, single!(6)),         // 56
+    info("NOP", Implicit, single!(2)),                 // 57
+    info("CLI", Implicit, single!(2)),                 // 58
+    info("EOR", AbsoluteIndexedY, conditional!(4, 5)), // 59
+    info("NOP", Implicit, single!(2)),                 // 5a
+    info("NOP", Implicit, single!(2)),                 // 5b
+    info("NOP", AbsoluteIndexedX, conditional!(4, 5)), // 5c
+    info("EOR", AbsoluteIndexedX, conditional!(4, 5)), // 5d
+    info("LSR", AbsoluteIndexedX, single!(7)),         // 5e
+    info("NOP", Implicit, single!(2)),                 // 5f
+    info("RTS", Implicit, single!(6)),                 // 60
+    info("ADC", IndexedIndirect, single!(6)),          // 61
+    info("NOP", Implicit, single!(2)),                 // 62
+    info("NOP", Implicit, single!(2)),                 // 63
+    info("NOP", ZeroPage, single!(3)),                 // 64
+    info("ADC", ZeroPage, single!(3)),                 // 65
+    info("ROR", ZeroPage, single!(5)),                 // 66
+    info("NOP", Implicit, single!(2)),                 // 67
+    info("PLA", Implicit, single!(4)),                 // 68
+    info("ADC", Immediate, single!(2)),                // 69
+    info("ROR", Accumulator, single!(2)),              // 6a
+    info("NOP", Implicit, single!(2)),                 // 6b
+    info("JMP", Indirect, single!(5)),                 // 6c
+    info("ADC", Absolute, single!(4)),                 // 6d
+    info("ROR", Absolute, single!(6)),                 // 6e
+    info("NOP", Implicit, single!(2)),                 // 6f
+    info("BVS", Relative, bi_conditional!(2, 3, 4)),   // 70
+    info("ADC", IndirectIndexed, conditional!(5, 6)),  // 71
+    info("NOP", Implicit, single!(2)),                 // 72
+    info("NOP", Implicit, single!(2)),                 // 73
+    info("NOP", ZeroPageIndexedX, single!(4)),         // 74
+    info("ADC", ZeroPageIndexedX, single!(4)),         // 75
+    info("ROR", ZeroPageIndexedX, single!(6)),         // 76
+    info("NOP", Implicit, single!(2)),                 // 77
+    info("SEI", Implicit, single!(2)),                 // 78
+    info("ADC", AbsoluteIndexedY, conditional!(4, 5)), // 79
+    info("NOP", Implicit, single!(2)),                 // 7a
+    info("NOP", Implicit, single!(2)),                 // 7b
+    info("NOP", AbsoluteIndexedX, conditional!(4, 5)), // 7c
+    info("ADC", AbsoluteIndexedX, conditional!(4, 5)), // 7d
+    info("ROR", AbsoluteIndexedX, single!(7)),         // 7e
+    info("NOP", Implicit, single!(2)),                 // 7f
+    info("NOP", Immediate, single!(2)),                // 80
+    info("STA", IndexedIndirect, single!(6)),          // 81
+    info("NOP", Immediate, single!(2)),                // 82
+    info("NOP", Implicit, single!(2)),                 // 83
+    info("STY", ZeroPage, single!(3)),                 // 84
+    info("STA", ZeroPage, single!(3)),                 // 85
+    info("STX", ZeroPage, single!(3)),                 // 86
+    info("NOP", Implicit, single!(2)),                 // 87
+    info("DEY", Implicit, single!(2)),                 // 88
+    info("NOP", Immediate, single!(2)),                // 89
+    info("TXA", Implicit, single!(2)),                 // 8a
+    info("NOP", Implicit, single!(2)),                 // 8b
+    info("STY", Absolute, single!(4)),                 // 8c
+    info("STA", Absolute, single!(4)),                 // 8d
+    info("STX", Absolute, single!(4)),                 // 8e
+    info("NOP", Implicit, single!(2)),                 // 8f
+    info("BCC", Relative, bi_conditional!(2, 3, 4)),   // 90
+    info("STA", IndirectIndexed, single!(6)),          // 91
+    info("NOP", Implicit, single!(2)),                 // 92
+    info("NOP", Implicit, single!(2)),                 // 93
+    info("STY", ZeroPageIndexedX, single!(4)),         // 94
+    info("STA", ZeroPageIndexedX, single!(4)),         // 95
+    info("STX", ZeroPageIndexedY, single!(4)),         // 96
+    info("NOP", Implicit, single!(2)),                 // 97
+    info("TYA", Implicit, single!(2)),                 // 98
+    info("STA", AbsoluteIndexedY, single!(5)),         // 99
+    info("TXS", Implicit, single!(2)),                 // 9a
+    info("NOP", Implicit, single!(2)),                 // 9b
+    info("NOP", Implicit, single!(2)),                 // 9c
+    info("STA", AbsoluteIndexedX, single!(5)),         // 9d
+    info("NOP", Implicit, single!(2)),                 // 9e
+    info("NOP", Implicit, single!(2)),                 // 9f
+    info("LDY", Immediate, single!(2)),                // a0
+    info("LDA", IndexedIndirect, single!(6)),          // a1
+    info("LDX", Immediate, single!(2)),                // a2
+    info("NOP", Implicit, single!(2)),                 // a3
+    info("LDY", ZeroPage, single!(3)),                 // a4
+    info("LDA", ZeroPage, single!(3)),                 // a5
+    info("LDX", ZeroPage, single!(3)),                 // a6
+    info("NOP", Implicit, single!(2)),                 // a7
+    info("TAY", Implicit, single!(2)),                 // a8
+    info("LDA", Immediate, single!(2)),                // a9
+    info("TAX", Implicit, single!(2)),                 // aa
+    info("NOP", Implicit, single!(2)),                 // ab
+    info("LDY", Absolute, single!(4)),                 // ac
+    info("LDA", Absolute, single!(4)),                 // ad
+    info("LDX", Absolute, single!(4)),                 // ae
+    info("NOP", Implicit, single!(2)),                 // af
+    info("BCS", Relative, bi_conditional!(2, 3, 4)),   // b0
+    info("LDA", IndirectIndexed, conditional!(5, 6)),  // b1
+    info("NOP", Implicit, single!(2)),                 // b2
+    info("NOP", Implicit, single!(2)),                 // b3
+    info("LDY", ZeroPageIndexedX, single!(4)),         // b4
+    info("LDA", ZeroPageIndexedX, single!(4)),         // b5
+    info("LDX", ZeroPageIndexedY, single!(4)),         // b6
+    info("NOP", Implicit, single!(2)),                 // b7
+    info("CLV", Implicit, single!(2)),                 // b8
+    info("LDA", AbsoluteIndexedY, conditional!(4, 5)), // b9
+    info("TSX", Implicit, single!(2)),                 // ba
+    info("NOP", Implicit, single!(2)),                 // bb
+    info("LDY", AbsoluteIndexedX, conditional!(4, 5)), // bc
+    info("LDA", AbsoluteIndexedX, conditional!(4, 5)), // bd
+    info("LDX", AbsoluteIndexedY, conditional!(4, 5)), // be
+    info("NOP", Implicit, single!(2)),                 // bf
+    info("CPY", Immediate, single!(2)),                // c0
+    info("CMP", IndexedIndirect, single!(6)),          // c1
+    info("NOP", Immediate, single!(2)),                // c2
+    info("NOP", Implicit, single!(2)),                 // c3
+    info("CPY", ZeroPage, single!(3)),                 // c4
+    info("CMP", ZeroPage, single!(3)),                 // c5
+    info("DEC", ZeroPage, single!(5)),                 // c6
+    info("NOP", Implicit, single!(2)),                 // c7
+    info("INY", Implicit, single!(2)),                 // c8
+    info("CMP", Immediate, single!(2)),                // c9
+    info("DEX", Implicit, single!(2)),                 // ca
+    info("NOP", Implicit, single!(2)),                 // cb
+    info("CPY", Absolute, single!(4)),                 // cc
+    info("CMP", Absolute, single!(4)),                 // cd
+    info("DEC", Absolute, single!(6)),                 // ce
+    info("NOP", Implicit, single!(2)),                 // cf
+    info("BNE", Relative, bi_conditional!(2, 3, 4)),   // d0
+    info("CMP", IndirectIndexed, conditional!(5, 6)),  // d1
+    info("NOP", Implicit, single!(2)),                 // d2
+    info("NOP", Implicit, single!(2)),                 // d3
+    info("NOP", ZeroPageIndexedX, single!(4)),         // d4
+    info("CMP", ZeroPageIndexedX, single!(4)),         // d5
+    info("DEC", ZeroPageIndexedX, single!(6)),         // d6
+    info("NOP", Implicit, single!(2)),                 // d7
+    info("CLD", Implicit, single!(2)),                 // d8
+    info("CMP", AbsoluteIndexedY, conditional!(4, 5)), // d9
+    info("NOP", Implicit, single!(2)),                 // da
+    info("NOP", Implicit, single!(2)),                 // db
+    info("NOP", AbsoluteIndexedX, conditional!(4, 5)), // dc
+    info("CMP", AbsoluteIndexedX, conditional!(4, 5)), // dd
+    info("DEC", AbsoluteIndexedX, single!(7)),         // de
+    info("NOP", Implicit, single!(2)),                 // df
+    info("CPX", Immediate, single!(2)),                // e0
+    info("SBC", IndexedIndirect, single!(6)),          // e1
+    info("NOP", Immediate, single!(2)),                // e2
+    info("NOP", Implicit, single!(2)),                 // e3
+    info("CPX", ZeroPage, single!(3)),                 // e4
+    info("SBC", ZeroPage, single!(3)),                 // e5
+    info("INC", ZeroPage, single!(5)),                 // e6
+    info("NOP", Implicit, single!(2)),                 // e7
+    info("INX", Implicit, single!(2)),                 // e8
+    info("SBC", Immediate, single!(2)),                // e9
+    info("NOP", Implicit, single!(2)),                 // ea
+    info("SBC", Immediate, single!(2)),                // eb
+    info("CPX", Absolute, single!(4)),                 // ec
+    info("SBC", Absolute, single!(4)),                 // ed
+    info("INC", Absolute, single!(6)),                 // ee
+    info("NOP", Implicit, single!(2)),                 // ef
+    info("BEQ", Relative, bi_conditional!(2, 3, 4)),   // f0
+    info("SBC", IndirectIndexed, conditional!(5, 6)),  // f1
+    info("NOP", Implicit, single!(2)),                 // f2
+    info("NOP", Implicit, single!(2)),                 // f3
+    info("NOP", ZeroPageIndexedX, single!(4)),         // f4
+    info("SBC", ZeroPageIndexedX, single!(4)),         // f5
+    info("INC", ZeroPageIndexedX, single!(6)),         // f6
+    info("NOP", Implicit, single!(2)),                 // f7
+    info("SED", Implicit, single!(2)),                 // f8
+    info("SBC", AbsoluteIndexedY, conditional!(4, 5)), // f9
+    info("NOP", Implicit, single!(2)),                 // fa
+    info("NOP", Implicit, single!(2)),                 // fb
+    info("NOP", AbsoluteIndexedX, conditional!(4, 5)), // fc
+    info("SBC", AbsoluteIndexedX, conditional!(4, 5)), // fd
+    info("INC", AbsoluteIndexedX, single!(7)),         // fe
+    info("NOP", Implicit, single!(2)),                 // ff
+];
+
+#[cfg(test)]
+mod tests {
+    use super::super::cpu::Instruction;
+    use opcode_table::OPCODE_TABLE;
+    use stats::AddressingModeKind;
+    use Mos6502Instruction;
+
+    #[test]
+    fn it_should_agree_with_the_decoder() {
+        for (opcode, info) in OPCODE_TABLE.iter().enumerate() {
+            let instruction = Mos6502Instruction::from(&[opcode as u8, 0x34, 0x12][..]);
+            let text = instruction.to_string();
+            assert_eq!(
+                instruction.instruction.to_string(),
+                info.mnemonic,
+                "opcode {:02x}",
+                opcode
+            );
+            assert_eq!(
+                AddressingModeKind::from(&instruction.addressing_mode),
+                info.kind,
+                "{}",
+                text
+            );
+            assert_eq!(instruction.size().unwrap(), info.size, "{}", text);
+            assert_eq!(instruction.get_cycles().unwrap(), info.cycles, "{}", text);
+        }
+    }
+}
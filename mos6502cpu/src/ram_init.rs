@@ -0,0 +1,103 @@
+use cpu::address_range;
+use std::ops::RangeBounds;
+use Mos6502Cpu;
+
+// What RAM holds at power on, real systems don't boot with it zeroed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RamInitPattern {
+    #[default]
+    AllZeros,
+    AllOnes,
+    // Blocks of four 0x00 and four 0xff, like the DRAM of many consoles
+    Alternating,
+    Seeded(u64),
+}
+
+impl RamInitPattern {
+    pub fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamInitPattern::AllZeros => fill_with(ram, 0),
+            RamInitPattern::AllOnes => fill_with(ram, 0xff),
+            RamInitPattern::Alternating => {
+                for (i, b) in ram.iter_mut().enumerate() {
+                    *b = if i & 0x04 > 0 { 0xff } else { 0x00 };
+                }
+            }
+            RamInitPattern::Seeded(seed) => fill_with_seed(ram, seed),
+        }
+    }
+}
+
+fn fill_with(ram: &mut [u8], byte: u8) {
+    for b in ram.iter_mut() {
+        *b = byte;
+    }
+}
+
+// splitmix64, so the same seed gives the same RAM on every platform
+fn fill_with_seed(ram: &mut [u8], seed: u64) {
+    let mut state = seed;
+    for chunk in ram.chunks_mut(8) {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        let len = chunk.len();
+        chunk.copy_from_slice(&z.to_le_bytes()[..len]);
+    }
+}
+
+impl Mos6502Cpu {
+    // Only the range is written, so the ROM and the registers mapped in memory stay as they are
+    pub fn initialize_ram<R: RangeBounds<u16>>(&mut self, pattern: RamInitPattern, range: R) {
+        let addresses = address_range(&range);
+        let mut ram = vec![0; (addresses.end - addresses.start) as usize];
+        pattern.fill(&mut ram);
+        for (address, byte) in addresses.zip(ram) {
+            self.memory.set(address as u16, byte);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ram_init::RamInitPattern;
+    use {Memory, Mos6502Cpu, AVAILABLE_MEMORY};
+
+    fn first_bytes(pattern: RamInitPattern) -> [u8; 10] {
+        let mut ram = [0x42; 10];
+        pattern.fill(&mut ram);
+        ram
+    }
+
+    #[test]
+    fn it_should_fill_ram_with_each_pattern() {
+        assert_eq!(first_bytes(RamInitPattern::AllZeros), [0; 10]);
+        assert_eq!(first_bytes(RamInitPattern::AllOnes), [0xff; 10]);
+        assert_eq!(
+            first_bytes(RamInitPattern::Alternating),
+            [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0, 0]
+        );
+        assert_eq!(
+            first_bytes(RamInitPattern::Seeded(42)),
+            first_bytes(RamInitPattern::Seeded(42))
+        );
+        assert_ne!(
+            first_bytes(RamInitPattern::Seeded(42)),
+            first_bytes(RamInitPattern::Seeded(24))
+        );
+    }
+
+    #[test]
+    fn it_should_only_initialize_the_given_range() {
+        let mut memory = [0; AVAILABLE_MEMORY];
+        memory.set(0x8000, 0xea);
+        let mut cpu = Mos6502Cpu::new(Box::new(memory));
+        cpu.initialize_ram(RamInitPattern::AllOnes, ..0x800);
+        assert_eq!(cpu.memory.get(0x0000), 0xff);
+        assert_eq!(cpu.memory.get(0x07ff), 0xff);
+        assert_eq!(cpu.memory.get(0x0800), 0x00);
+        assert_eq!(cpu.memory.get(0x8000), 0xea);
+    }
+}
@@ -23,6 +23,26 @@ impl Mos6502Cpu {
         }
     }
 
+    pub(crate) fn execute_phx(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
+        if let AddressingMode::Implicit = addressing_mode {
+            let x = self.registers.x;
+            self.push(x);
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode)
+        }
+    }
+
+    pub(crate) fn execute_phy(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
+        if let AddressingMode::Implicit = addressing_mode {
+            let y = self.registers.y;
+            self.push(y);
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode)
+        }
+    }
+
     pub(crate) fn execute_pla(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         if let AddressingMode::Implicit = addressing_mode {
             let new_a = self.pull();
@@ -44,6 +64,30 @@ impl Mos6502Cpu {
             Err(CpuError::InvalidAddressingMode)
         }
     }
+
+    pub(crate) fn execute_plx(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
+        if let AddressingMode::Implicit = addressing_mode {
+            let new_x = self.pull();
+            self.registers.x = new_x;
+            self.update_negative_flag(new_x);
+            self.update_zero_flag(new_x);
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode)
+        }
+    }
+
+    pub(crate) fn execute_ply(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
+        if let AddressingMode::Implicit = addressing_mode {
+            let new_y = self.pull();
+            self.registers.y = new_y;
+            self.update_negative_flag(new_y);
+            self.update_zero_flag(new_y);
+            Ok(())
+        } else {
+            Err(CpuError::InvalidAddressingMode)
+        }
+    }
 }
 
 #[cfg(test)]
@@ -159,4 +203,27 @@ mod tests {
         assert!(!cpu.registers.p.overflow);
         assert!(cpu.registers.p.break_flag);
     }
+
+    #[test]
+    fn it_should_push_and_pull_index_registers() {
+        let m = [0; AVAILABLE_MEMORY];
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.registers.s = 0xff;
+        cpu.registers.x = 0x80;
+        cpu.execute_instruction(&Mos6502Instruction {
+            instruction: Mos6502InstructionCode::Phx,
+            addressing_mode: AddressingMode::Implicit,
+        })
+        .unwrap();
+        assert_eq!(cpu.memory.get(0x1ff), 0x80);
+        cpu.execute_instruction(&Mos6502Instruction {
+            instruction: Mos6502InstructionCode::Ply,
+            addressing_mode: AddressingMode::Implicit,
+        })
+        .unwrap();
+        assert_eq!(cpu.registers.y, 0x80);
+        assert_eq!(cpu.registers.s, 0xff);
+        assert!(cpu.registers.p.negative);
+        assert!(!cpu.registers.p.zero);
+    }
 }
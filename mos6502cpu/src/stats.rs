@@ -0,0 +1,209 @@
+use std::fmt;
+use {
+    AddressingMode, Mos6502Cpu, Mos6502Instruction, Mos6502InstructionCode, Variant,
+    AVAILABLE_MEMORY,
+};
+
+const OPCODES: usize = 0x100;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressingModeKind {
+    Implicit,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    Absolute,
+    Relative,
+    Indirect,
+    ZeroPageIndexedX,
+    ZeroPageIndexedY,
+    AbsoluteIndexedX,
+    AbsoluteIndexedY,
+    IndexedIndirect,
+    IndirectIndexed,
+    ZeroPageIndirect,
+}
+
+impl From<&AddressingMode> for AddressingModeKind {
+    fn from(addressing_mode: &AddressingMode) -> AddressingModeKind {
+        match addressing_mode {
+            AddressingMode::Implicit => AddressingModeKind::Implicit,
+            AddressingMode::Accumulator => AddressingModeKind::Accumulator,
+            AddressingMode::Immediate { .. } => AddressingModeKind::Immediate,
+            AddressingMode::ZeroPage { .. } => AddressingModeKind::ZeroPage,
+            AddressingMode::Absolute { .. } => AddressingModeKind::Absolute,
+            AddressingMode::Relative { .. } => AddressingModeKind::Relative,
+            AddressingMode::Indirect { .. } => AddressingModeKind::Indirect,
+            AddressingMode::ZeroPageIndexedX { .. } => AddressingModeKind::ZeroPageIndexedX,
+            AddressingMode::ZeroPageIndexedY { .. } => AddressingModeKind::ZeroPageIndexedY,
+            AddressingMode::AbsoluteIndexedX { .. } => AddressingModeKind::AbsoluteIndexedX,
+            AddressingMode::AbsoluteIndexedY { .. } => AddressingModeKind::AbsoluteIndexedY,
+            AddressingMode::IndexedIndirect { .. } => AddressingModeKind::IndexedIndirect,
+            AddressingMode::IndirectIndexed { .. } => AddressingModeKind::IndirectIndexed,
+            AddressingMode::ZeroPageIndirect { .. } => AddressingModeKind::ZeroPageIndirect,
+        }
+    }
+}
+
+impl fmt::Display for AddressingModeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InstructionStats {
+    pub instruction: Mos6502InstructionCode,
+    pub addressing_mode: AddressingModeKind,
+    pub static_count: u64,
+    pub dynamic_count: u64,
+}
+
+pub(crate) struct StatsCollector {
+    dynamic_counts: [u64; OPCODES],
+    static_counts: [u64; OPCODES],
+    seen_addresses: Vec<u64>,
+}
+
+impl StatsCollector {
+    fn new() -> StatsCollector {
+        StatsCollector {
+            dynamic_counts: [0; OPCODES],
+            static_counts: [0; OPCODES],
+            seen_addresses: vec![0; AVAILABLE_MEMORY / 64],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, address: u16, opcode: u8) {
+        self.dynamic_counts[opcode as usize] += 1;
+        let (word, bit) = (address as usize / 64, address % 64);
+        if (self.seen_addresses[word] & (1 << bit)) == 0 {
+            self.seen_addresses[word] |= 1 << bit;
+            self.static_counts[opcode as usize] += 1;
+        }
+    }
+
+    fn export(&self, variant: Variant) -> Vec<InstructionStats> {
+        let mut result: Vec<InstructionStats> = Vec::new();
+        for opcode in 0..OPCODES {
+            if self.dynamic_counts[opcode] == 0 {
+                continue;
+            }
+            let decoded = Mos6502Instruction::decode(&[opcode as u8, 0, 0], variant);
+            let addressing_mode = AddressingModeKind::from(&decoded.addressing_mode);
+            let existing = result.iter_mut().find(|s| {
+                s.instruction == decoded.instruction && s.addressing_mode == addressing_mode
+            });
+            match existing {
+                Some(stats) => {
+                    stats.static_count += self.static_counts[opcode];
+                    stats.dynamic_count += self.dynamic_counts[opcode];
+                }
+                None => result.push(InstructionStats {
+                    instruction: decoded.instruction,
+                    addressing_mode,
+                    static_count: self.static_counts[opcode],
+                    dynamic_count: self.dynamic_counts[opcode],
+                }),
+            }
+        }
+        result.sort_by_key(|s| std::cmp::Reverse(s.dynamic_count));
+        result
+    }
+}
+
+pub fn instruction_stats_to_csv(stats: &[InstructionStats]) -> String {
+    let mut csv = String::from("instruction,addressing_mode,static_count,dynamic_count\n");
+    for s in stats {
+        csv.push_str(&format!(
+            "{},{},{},{}\n",
+            s.instruction, s.addressing_mode, s.static_count, s.dynamic_count
+        ));
+    }
+    csv
+}
+
+impl Mos6502Cpu {
+    pub fn enable_stats(&mut self) {
+        if self.stats.is_none() {
+            self.stats = Some(Box::new(StatsCollector::new()));
+        }
+    }
+
+    pub fn instruction_stats(&self) -> Vec<InstructionStats> {
+        match self.stats {
+            Some(ref stats) => stats.export(self.variant),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use stats::{instruction_stats_to_csv, AddressingModeKind, InstructionStats};
+    use {Memory, Mos6502Cpu, Mos6502InstructionCode, AVAILABLE_MEMORY};
+
+    fn run_loop(stats: bool) -> Mos6502Cpu {
+        let mut m = [0; AVAILABLE_MEMORY];
+        let program = [
+            0xa2, 0x05, // LDX #$05
+            0xca, // DEX
+            0xd0, 0xfd, // BNE -3
+            0xa9, 0x01, // LDA #$01
+        ];
+        for (i, byte) in program.iter().enumerate() {
+            m.set(i as u16, *byte);
+        }
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        if stats {
+            cpu.enable_stats();
+        }
+        while cpu.registers.pc < program.len() as u16 {
+            cpu.execute().unwrap();
+        }
+        cpu
+    }
+
+    #[test]
+    fn it_should_count_static_and_dynamic_executions() {
+        let stats = run_loop(true).instruction_stats();
+        assert_eq!(stats.len(), 4);
+        assert_eq!(
+            stats[0],
+            InstructionStats {
+                instruction: Mos6502InstructionCode::Dex,
+                addressing_mode: AddressingModeKind::Implicit,
+                static_count: 1,
+                dynamic_count: 5,
+            }
+        );
+        assert_eq!(
+            stats[1],
+            InstructionStats {
+                instruction: Mos6502InstructionCode::Bne,
+                addressing_mode: AddressingModeKind::Relative,
+                static_count: 1,
+                dynamic_count: 5,
+            }
+        );
+        assert_eq!(stats[2].instruction, Mos6502InstructionCode::Ldx);
+        assert_eq!(stats[2].dynamic_count, 1);
+        assert_eq!(stats[3].addressing_mode, AddressingModeKind::Immediate);
+    }
+
+    #[test]
+    fn it_shouldnt_collect_stats_unless_enabled() {
+        assert!(run_loop(false).instruction_stats().is_empty());
+    }
+
+    #[test]
+    fn it_should_export_stats_as_csv() {
+        let csv = instruction_stats_to_csv(&run_loop(true).instruction_stats()[..1]);
+        assert_eq!(
+            csv,
+            "instruction,addressing_mode,static_count,dynamic_count\nDEX,Implicit,1,5\n"
+        );
+    }
+}
@@ -0,0 +1,965 @@
+use bit_utils::{two_bytes_to_word, word_to_two_bytes};
+use cpu::Cpu;
+use failure::Error;
+use instruction::{is_unofficial_opcode, AddressingMode, Mos6502InstructionCode};
+use mos6502cpu::{ProcessorStatus, INTERRUPT_HANDLERS_START, TRIGGERED_IRQ};
+use stats::AddressingModeKind;
+use {CpuError, Mos6502Cpu, Mos6502Instruction, Variant};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TickResult {
+    Cycle,
+    InstructionBoundary { cycles: u8 },
+}
+
+// What the next tick does. Every state is a cycle with a single read or write on the bus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MicroState {
+    Fetch,
+    Reset,
+    Implied,
+    Immediate,
+    ZeroPage,
+    ZeroPageIndexed,
+    AddZeroPageIndex,
+    AbsoluteLow,
+    AbsoluteHigh,
+    IndirectLow,
+    IndirectHigh,
+    Pointer,
+    AddPointerIndex,
+    PointerLow,
+    PointerHigh,
+    ReadUnfixed,
+    FixHighByte,
+    Operand,
+    DummyWrite,
+    WriteResult,
+    BranchOffset,
+    BranchTaken,
+    BranchFixPage,
+    JsrLow,
+    JsrHigh,
+    DummyReadPc,
+    StackDummyRead,
+    Push,
+    Pull,
+    PullStatus,
+    PullPcl,
+    PullPch,
+    RtsIncrement,
+    BreakPadding,
+    PushPch,
+    PushPcl,
+    PushStatus,
+    VectorLow,
+    VectorHigh,
+}
+
+// What an instruction does with its operand, which decides the cycles once the address is known
+enum Access {
+    Read,
+    Write,
+    ReadModifyWrite,
+}
+
+fn access(instruction: &Mos6502InstructionCode) -> Access {
+    match instruction {
+        Mos6502InstructionCode::Ahx
+        | Mos6502InstructionCode::Sax
+        | Mos6502InstructionCode::Shx
+        | Mos6502InstructionCode::Shy
+        | Mos6502InstructionCode::Sta
+        | Mos6502InstructionCode::Stx
+        | Mos6502InstructionCode::Sty
+        | Mos6502InstructionCode::Stz
+        | Mos6502InstructionCode::Tas => Access::Write,
+        Mos6502InstructionCode::Asl
+        | Mos6502InstructionCode::Dcp
+        | Mos6502InstructionCode::Dec
+        | Mos6502InstructionCode::Inc
+        | Mos6502InstructionCode::Isc
+        | Mos6502InstructionCode::Lsr
+        | Mos6502InstructionCode::Rla
+        | Mos6502InstructionCode::Rol
+        | Mos6502InstructionCode::Ror
+        | Mos6502InstructionCode::Rra
+        | Mos6502InstructionCode::Slo
+        | Mos6502InstructionCode::Sre
+        | Mos6502InstructionCode::Trb
+        | Mos6502InstructionCode::Tsb => Access::ReadModifyWrite,
+        _ => Access::Read,
+    }
+}
+
+// What an instruction keeps from one cycle to the next. Interrupts and the reset run as the
+// pseudo instructions with their names
+pub(crate) struct Latches {
+    instruction: Mos6502InstructionCode,
+    kind: AddressingModeKind,
+    fetched: usize,
+    pointer: u8,
+    address: u16,
+    value: u8,
+    page_crossed: bool,
+    cycles: u8,
+}
+
+impl Latches {
+    pub(crate) fn new() -> Latches {
+        Latches {
+            instruction: Mos6502InstructionCode::Nop,
+            kind: AddressingModeKind::Implicit,
+            fetched: 0,
+            pointer: 0,
+            address: 0,
+            value: 0,
+            page_crossed: false,
+            cycles: 0,
+        }
+    }
+
+    #[inline]
+    fn is_interrupt(&self) -> bool {
+        matches!(
+            self.instruction,
+            Mos6502InstructionCode::Irq | Mos6502InstructionCode::Nmi | Mos6502InstructionCode::Rst
+        )
+    }
+}
+
+impl Mos6502Cpu {
+    pub fn tick(&mut self) -> Result<TickResult, Error> {
+        if self.micro_state == MicroState::Fetch {
+            self.latches.cycles = 0;
+        }
+        self.latches.cycles += 1;
+        self.micro_state = self.step(self.micro_state)?;
+        if self.micro_state != MicroState::Fetch {
+            return Ok(TickResult::Cycle);
+        }
+        if !self.latches.is_interrupt() {
+            self.trace.complete(&self.registers);
+        }
+        Ok(TickResult::InstructionBoundary {
+            cycles: self.latches.cycles,
+        })
+    }
+
+    fn step(&mut self, state: MicroState) -> Result<MicroState, Error> {
+        let next = match state {
+            MicroState::Fetch => return self.fetch(),
+            MicroState::Reset => self.begin_interrupt(Mos6502InstructionCode::Rst),
+            // Even without an operand the byte after the opcode is read
+            MicroState::Implied => {
+                self.read_next_byte();
+                let addressing_mode = match self.latches.kind {
+                    AddressingModeKind::Accumulator => AddressingMode::Accumulator,
+                    _ => AddressingMode::Implicit,
+                };
+                self.execute_latched(addressing_mode)?;
+                MicroState::Fetch
+            }
+            MicroState::Immediate => {
+                let byte = self.fetch_operand();
+                self.execute_latched(AddressingMode::Immediate { byte })?;
+                MicroState::Fetch
+            }
+            MicroState::ZeroPage => {
+                self.latches.address = u16::from(self.fetch_operand());
+                MicroState::Operand
+            }
+            MicroState::ZeroPageIndexed => {
+                self.latches.pointer = self.fetch_operand();
+                MicroState::AddZeroPageIndex
+            }
+            // The base address is read while the index is added, the sum stays in the zero page
+            MicroState::AddZeroPageIndex => {
+                let base = self.latches.pointer;
+                self.memory.get(u16::from(base));
+                self.latches.address = u16::from(base.wrapping_add(self.index()));
+                MicroState::Operand
+            }
+            MicroState::AbsoluteLow => {
+                self.latches.address = u16::from(self.fetch_operand());
+                MicroState::AbsoluteHigh
+            }
+            MicroState::AbsoluteHigh => {
+                let high_byte = self.fetch_operand();
+                self.latches.address = two_bytes_to_word(high_byte, self.latches.address as u8);
+                match self.latches.kind {
+                    AddressingModeKind::Indirect => MicroState::IndirectLow,
+                    AddressingModeKind::AbsoluteIndexedX | AddressingModeKind::AbsoluteIndexedY => {
+                        self.add_index()
+                    }
+                    _ if self.latches.instruction == Mos6502InstructionCode::Jmp => {
+                        self.registers.pc = self.latches.address;
+                        MicroState::Fetch
+                    }
+                    _ => MicroState::Operand,
+                }
+            }
+            MicroState::IndirectLow => {
+                self.latches.value = self.memory.get(self.latches.address);
+                MicroState::IndirectHigh
+            }
+            MicroState::IndirectHigh => {
+                let high_byte = self.memory.get(self.latches.address.wrapping_add(1));
+                self.registers.pc = two_bytes_to_word(high_byte, self.latches.value);
+                MicroState::Fetch
+            }
+            MicroState::Pointer => {
+                self.latches.pointer = self.fetch_operand();
+                match self.latches.kind {
+                    AddressingModeKind::IndexedIndirect => MicroState::AddPointerIndex,
+                    _ => MicroState::PointerLow,
+                }
+            }
+            MicroState::AddPointerIndex => {
+                self.memory.get(u16::from(self.latches.pointer));
+                self.latches.pointer = self.latches.pointer.wrapping_add(self.registers.x);
+                MicroState::PointerLow
+            }
+            MicroState::PointerLow => {
+                self.latches.address = u16::from(self.memory.get(u16::from(self.latches.pointer)));
+                MicroState::PointerHigh
+            }
+            // The pointer wraps around the zero page
+            MicroState::PointerHigh => {
+                let pointer = self.latches.pointer.wrapping_add(1);
+                let high_byte = self.memory.get(u16::from(pointer));
+                self.latches.address = two_bytes_to_word(high_byte, self.latches.address as u8);
+                match self.latches.kind {
+                    AddressingModeKind::IndirectIndexed => self.add_index(),
+                    _ => MicroState::Operand,
+                }
+            }
+            // Without a page crossing the read is done. With one, what came back is from the
+            // wrong page and the read is repeated once the high byte is fixed
+            MicroState::ReadUnfixed => {
+                if self.latches.page_crossed {
+                    self.read_while_fixing_high_byte();
+                    MicroState::Operand
+                } else {
+                    let value = self.memory.get(self.latches.address);
+                    self.operate(value)?;
+                    MicroState::Fetch
+                }
+            }
+            // Stores and read-modify-writes can't take back a write, so they always spend a
+            // cycle fixing the high byte
+            MicroState::FixHighByte => {
+                self.read_while_fixing_high_byte();
+                MicroState::Operand
+            }
+            MicroState::Operand => {
+                let address = self.latches.address;
+                match access(&self.latches.instruction) {
+                    Access::Read => {
+                        let value = self.memory.get(address);
+                        self.operate(value)?;
+                        MicroState::Fetch
+                    }
+                    Access::Write => {
+                        let value = self.store_value()?;
+                        self.memory.set(address, value);
+                        MicroState::Fetch
+                    }
+                    Access::ReadModifyWrite => {
+                        self.latches.value = self.memory.get(address);
+                        MicroState::DummyWrite
+                    }
+                }
+            }
+            // The NMOS parts write the value back while they modify it, the 65C02 reads it again
+            MicroState::DummyWrite => {
+                let (address, value) = (self.latches.address, self.latches.value);
+                if self.variant == Variant::Cmos65C02 {
+                    self.memory.get(address);
+                } else {
+                    self.memory.set(address, value);
+                }
+                self.latches.value = self.modify(value)?;
+                MicroState::WriteResult
+            }
+            MicroState::WriteResult => {
+                self.memory.set(self.latches.address, self.latches.value);
+                MicroState::Fetch
+            }
+            MicroState::BranchOffset => {
+                let offset = self.fetch_operand();
+                if self.is_branch_taken(&self.latches.instruction) {
+                    self.latches.address = self.registers.pc.wrapping_add(offset as i8 as u16);
+                    MicroState::BranchTaken
+                } else {
+                    MicroState::Fetch
+                }
+            }
+            // The next opcode is read while the offset is added to the low byte of the PC. The
+            // high byte takes another cycle, reading from the page it hasn't left yet
+            MicroState::BranchTaken => {
+                let (pc, target) = (self.registers.pc, self.latches.address);
+                self.memory.get(pc);
+                self.registers.pc = (pc & 0xff00) | (target & 0x00ff);
+                if (pc & 0xff00) == (target & 0xff00) {
+                    MicroState::Fetch
+                } else {
+                    MicroState::BranchFixPage
+                }
+            }
+            MicroState::BranchFixPage => {
+                self.memory.get(self.registers.pc);
+                self.registers.pc = self.latches.address;
+                MicroState::Fetch
+            }
+            MicroState::JsrLow => {
+                self.latches.address = u16::from(self.fetch_operand());
+                MicroState::StackDummyRead
+            }
+            MicroState::JsrHigh => {
+                let high_byte = self.read_next_byte();
+                self.registers.pc = two_bytes_to_word(high_byte, self.latches.address as u8);
+                MicroState::Fetch
+            }
+            MicroState::DummyReadPc => {
+                self.read_next_byte();
+                match self.latches.instruction {
+                    Mos6502InstructionCode::Pha
+                    | Mos6502InstructionCode::Php
+                    | Mos6502InstructionCode::Phx
+                    | Mos6502InstructionCode::Phy => MicroState::Push,
+                    Mos6502InstructionCode::Irq
+                    | Mos6502InstructionCode::Nmi
+                    | Mos6502InstructionCode::Rst => MicroState::PushPch,
+                    _ => MicroState::StackDummyRead,
+                }
+            }
+            MicroState::StackDummyRead => {
+                self.memory.get(0x100 + u16::from(self.registers.s));
+                match self.latches.instruction {
+                    Mos6502InstructionCode::Jsr => MicroState::PushPch,
+                    Mos6502InstructionCode::Rti => MicroState::PullStatus,
+                    Mos6502InstructionCode::Rts => MicroState::PullPcl,
+                    _ => MicroState::Pull,
+                }
+            }
+            // A push or a pull is all the instruction does with the bus
+            MicroState::Push | MicroState::Pull => {
+                self.execute_latched(AddressingMode::Implicit)?;
+                MicroState::Fetch
+            }
+            MicroState::PullStatus => {
+                let status = self.pull();
+                self.registers.p = ProcessorStatus::from_byte(status);
+                MicroState::PullPcl
+            }
+            MicroState::PullPcl => {
+                self.latches.address = u16::from(self.pull());
+                MicroState::PullPch
+            }
+            MicroState::PullPch => {
+                let high_byte = self.pull();
+                self.registers.pc = two_bytes_to_word(high_byte, self.latches.address as u8);
+                match self.latches.instruction {
+                    Mos6502InstructionCode::Rts => MicroState::RtsIncrement,
+                    _ => MicroState::Fetch,
+                }
+            }
+            // JSR pushed the address of its last byte
+            MicroState::RtsIncrement => {
+                self.memory.get(self.registers.pc);
+                self.registers.pc = self.registers.pc.wrapping_add(1);
+                MicroState::Fetch
+            }
+            MicroState::BreakPadding => {
+                self.fetch_operand();
+                MicroState::PushPch
+            }
+            MicroState::PushPch => {
+                let (_, high_byte) = word_to_two_bytes(self.registers.pc);
+                self.push_or_read(high_byte, 0);
+                MicroState::PushPcl
+            }
+            MicroState::PushPcl => {
+                let (low_byte, _) = word_to_two_bytes(self.registers.pc);
+                self.push_or_read(low_byte, 1);
+                match self.latches.instruction {
+                    Mos6502InstructionCode::Jsr => MicroState::JsrHigh,
+                    _ => MicroState::PushStatus,
+                }
+            }
+            // Only BRK pushes the status with B set
+            MicroState::PushStatus => {
+                let status = match self.latches.instruction {
+                    Mos6502InstructionCode::Brk => self.registers.p.to_byte(),
+                    _ => self.registers.p.to_byte() & !0x10,
+                };
+                self.push_or_read(status, 2);
+                MicroState::VectorLow
+            }
+            MicroState::VectorLow => {
+                self.latches.value = self.memory.get(self.vector());
+                self.registers.p.interrupt_disable = true;
+                MicroState::VectorHigh
+            }
+            MicroState::VectorHigh => {
+                let high_byte = self.memory.get(self.vector() + 1);
+                self.registers.pc = two_bytes_to_word(high_byte, self.latches.value);
+                MicroState::Fetch
+            }
+        };
+        Ok(next)
+    }
+
+    // Interrupts go in place of the next instruction, NMI first
+    fn fetch(&mut self) -> Result<MicroState, Error> {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            return Ok(self.begin_interrupt(Mos6502InstructionCode::Nmi));
+        }
+        if self.is_irq_asserted() && !self.registers.p.interrupt_disable {
+            self.set_irq(TRIGGERED_IRQ, false);
+            return Ok(self.begin_interrupt(Mos6502InstructionCode::Irq));
+        }
+        let pc = self.registers.pc;
+        let opcode = self.memory.get(pc);
+        if let Some(ref mut stats) = self.stats {
+            stats.record(pc, opcode);
+        }
+        self.trace.begin(pc, [opcode, 0, 0], &self.registers);
+        if self.strict && is_unofficial_opcode(opcode, self.variant) {
+            return Err(Error::from(CpuError::UnofficialOpcode { opcode, pc }));
+        }
+        self.registers.pc = pc.wrapping_add(1);
+        let instruction = Mos6502Instruction::decode(&[opcode, 0, 0], self.variant);
+        self.latches.kind = AddressingModeKind::from(&instruction.addressing_mode);
+        self.latches.fetched = 1;
+        let next = match instruction.instruction {
+            Mos6502InstructionCode::Brk => MicroState::BreakPadding,
+            Mos6502InstructionCode::Jsr => MicroState::JsrLow,
+            Mos6502InstructionCode::Pha
+            | Mos6502InstructionCode::Php
+            | Mos6502InstructionCode::Phx
+            | Mos6502InstructionCode::Phy
+            | Mos6502InstructionCode::Pla
+            | Mos6502InstructionCode::Plp
+            | Mos6502InstructionCode::Plx
+            | Mos6502InstructionCode::Ply
+            | Mos6502InstructionCode::Rti
+            | Mos6502InstructionCode::Rts => MicroState::DummyReadPc,
+            _ => match self.latches.kind {
+                AddressingModeKind::Implicit | AddressingModeKind::Accumulator => {
+                    MicroState::Implied
+                }
+                AddressingModeKind::Immediate => MicroState::Immediate,
+                AddressingModeKind::Relative => MicroState::BranchOffset,
+                AddressingModeKind::ZeroPage => MicroState::ZeroPage,
+                AddressingModeKind::ZeroPageIndexedX | AddressingModeKind::ZeroPageIndexedY => {
+                    MicroState::ZeroPageIndexed
+                }
+                AddressingModeKind::Absolute
+                | AddressingModeKind::AbsoluteIndexedX
+                | AddressingModeKind::AbsoluteIndexedY
+                | AddressingModeKind::Indirect => MicroState::AbsoluteLow,
+                AddressingModeKind::IndexedIndirect
+                | AddressingModeKind::IndirectIndexed
+                | AddressingModeKind::ZeroPageIndirect => MicroState::Pointer,
+            },
+        };
+        self.latches.instruction = instruction.instruction;
+        Ok(next)
+    }
+
+    // The opcode read is thrown away and the PC stays where it was, to return to it
+    fn begin_interrupt(&mut self, interrupt: Mos6502InstructionCode) -> MicroState {
+        self.memory.get(self.registers.pc);
+        self.latches.instruction = interrupt;
+        MicroState::DummyReadPc
+    }
+
+    fn fetch_operand(&mut self) -> u8 {
+        let byte = self.read_next_byte();
+        self.registers.pc = self.registers.pc.wrapping_add(1);
+        byte
+    }
+
+    #[inline]
+    fn read_next_byte(&mut self) -> u8 {
+        let byte = self.memory.get(self.registers.pc);
+        if self.latches.fetched < 3 && !self.latches.is_interrupt() {
+            self.trace.fill(self.latches.fetched, byte);
+            self.latches.fetched += 1;
+        }
+        byte
+    }
+
+    #[inline]
+    fn index(&self) -> u8 {
+        match self.latches.kind {
+            AddressingModeKind::ZeroPageIndexedY
+            | AddressingModeKind::AbsoluteIndexedY
+            | AddressingModeKind::IndirectIndexed => self.registers.y,
+            _ => self.registers.x,
+        }
+    }
+
+    // The index goes into the low byte first. Reads hope it didn't carry into the high byte
+    fn add_index(&mut self) -> MicroState {
+        let base = self.latches.address;
+        let address = base.wrapping_add(u16::from(self.index()));
+        self.latches.page_crossed = (base & 0xff00) != (address & 0xff00);
+        self.latches.address = address;
+        match access(&self.latches.instruction) {
+            Access::Read => MicroState::ReadUnfixed,
+            _ => MicroState::FixHighByte,
+        }
+    }
+
+    // The NMOS parts read from the address before the fix, the 65C02 reads the last
+    // instruction byte instead
+    fn read_while_fixing_high_byte(&mut self) {
+        let address = match self.variant {
+            Variant::Cmos65C02 => self.registers.pc.wrapping_sub(1),
+            _ if self.latches.page_crossed => self.latches.address.wrapping_sub(0x100),
+            _ => self.latches.address,
+        };
+        self.memory.get(address);
+    }
+
+    fn execute_latched(&mut self, addressing_mode: AddressingMode) -> Result<(), Error> {
+        let instruction =
+            Mos6502Instruction::new(self.latches.instruction.clone(), addressing_mode);
+        self.execute_instruction(&instruction)
+    }
+
+    // The operand was read on a cycle of its own, the instruction gets it as an immediate
+    fn operate(&mut self, value: u8) -> Result<(), Error> {
+        match self.latches.instruction {
+            Mos6502InstructionCode::Bit => self.bit_logic(value),
+            Mos6502InstructionCode::Las => self.las_logic(value),
+            _ => self.execute_latched(AddressingMode::Immediate { byte: value })?,
+        }
+        Ok(())
+    }
+
+    // The unofficial read-modify-writes go on with the modified value, like an immediate
+    fn modify(&mut self, value: u8) -> Result<u8, Error> {
+        let answer = match self.latches.instruction {
+            Mos6502InstructionCode::Asl | Mos6502InstructionCode::Slo => self.asl_logic(value),
+            Mos6502InstructionCode::Lsr | Mos6502InstructionCode::Sre => self.lsr_logic(value),
+            Mos6502InstructionCode::Rol | Mos6502InstructionCode::Rla => self.rol_logic(value),
+            Mos6502InstructionCode::Ror | Mos6502InstructionCode::Rra => self.ror_logic(value),
+            Mos6502InstructionCode::Inc | Mos6502InstructionCode::Isc => self.increment(value),
+            Mos6502InstructionCode::Dec | Mos6502InstructionCode::Dcp => self.decrement(value),
+            Mos6502InstructionCode::Tsb => self.tsb_logic(value),
+            Mos6502InstructionCode::Trb => self.trb_logic(value),
+            _ => return Err(Error::from(CpuError::InvalidAddressingMode)),
+        };
+        let modified = AddressingMode::Immediate { byte: answer };
+        match self.latches.instruction {
+            Mos6502InstructionCode::Slo => self.execute_ora_unchecked(&modified)?,
+            Mos6502InstructionCode::Sre => self.execute_eor_unchecked(&modified)?,
+            Mos6502InstructionCode::Rla => self.execute_and_unchecked(&modified)?,
+            Mos6502InstructionCode::Rra => self.execute_adc_unchecked(&modified)?,
+            Mos6502InstructionCode::Isc => self.execute_sbc_unchecked(&modified)?,
+            Mos6502InstructionCode::Dcp => self.execute_cmp_unchecked(&modified)?,
+            _ => {}
+        }
+        Ok(answer)
+    }
+
+    // The unstable stores mix in the high byte of the address they write to
+    fn store_value(&mut self) -> Result<u8, Error> {
+        let (a, x, y) = (self.registers.a, self.registers.x, self.registers.y);
+        let high_byte = (self.latches.address >> 8) as u8;
+        Ok(match self.latches.instruction {
+            Mos6502InstructionCode::Sta => a,
+            Mos6502InstructionCode::Stx => x,
+            Mos6502InstructionCode::Sty => y,
+            Mos6502InstructionCode::Stz => 0,
+            Mos6502InstructionCode::Sax => a & x,
+            Mos6502InstructionCode::Shx => x & high_byte,
+            Mos6502InstructionCode::Shy => y & high_byte,
+            Mos6502InstructionCode::Ahx => a & x & high_byte,
+            Mos6502InstructionCode::Tas => {
+                self.registers.p = ProcessorStatus::from_byte(a & x);
+                a & x & high_byte
+            }
+            _ => return Err(Error::from(CpuError::InvalidAddressingMode)),
+        })
+    }
+
+    // The reset goes through the cycles of an interrupt, reading the stack instead of writing it
+    fn push_or_read(&mut self, value: u8, offset: u8) {
+        if self.latches.instruction == Mos6502InstructionCode::Rst {
+            let s = self.registers.s.wrapping_sub(offset);
+            self.memory.get(0x100 + u16::from(s));
+        } else {
+            self.push(value);
+        }
+    }
+
+    #[inline]
+    fn vector(&self) -> u16 {
+        let index = match self.latches.instruction {
+            Mos6502InstructionCode::Nmi => 0,
+            Mos6502InstructionCode::Rst => 1,
+            _ => 2,
+        };
+        INTERRUPT_HANDLERS_START as u16 + index * 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::{Cpu, Instruction};
+    use instruction::Mos6502InstructionCode;
+    use tick::TickResult;
+    use {Memory, Mos6502Cpu, Mos6502Instruction, Variant, AVAILABLE_MEMORY};
+
+    fn count_ticks(cpu: &mut Mos6502Cpu) -> u8 {
+        let mut ticks = 1;
+        while cpu.tick().unwrap() == TickResult::Cycle {
+            ticks += 1;
+        }
+        ticks
+    }
+
+    #[test]
+    fn it_should_take_as_many_ticks_as_cycles_for_lda_immediate() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m.set(0, 0xa9);
+        m.set(1, 0x42);
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        assert_eq!(cpu.tick().unwrap(), TickResult::Cycle);
+        assert_eq!((cpu.registers.a, cpu.registers.pc), (0, 1));
+        assert_eq!(
+            cpu.tick().unwrap(),
+            TickResult::InstructionBoundary { cycles: 2 }
+        );
+        assert_eq!((cpu.registers.a, cpu.registers.pc), (0x42, 2));
+    }
+
+    #[test]
+    fn it_should_take_as_many_ticks_as_cycles_for_jsr() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m.set(0, 0x20);
+        m.set(1, 0x00);
+        m.set(2, 0x10);
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        assert_eq!(count_ticks(&mut cpu), 6);
+        assert_eq!(cpu.registers.pc, 0x1000);
+    }
+
+    #[test]
+    fn it_should_take_as_many_ticks_as_cycles_for_taken_branch() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m.set(0, 0xd0);
+        m.set(1, 0x02);
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.registers.p.zero = false;
+        let mut expected = Mos6502Cpu::new(Box::new(m));
+        expected.registers.p.zero = false;
+        let bytes = expected.get_next_instruction_bytes();
+        let instruction = Mos6502Instruction::from(&bytes[..]);
+        expected.increase_pc(2);
+        expected.execute_instruction(&instruction).unwrap();
+        let cycles = expected.get_cycles_for_instruction(&instruction).unwrap();
+        assert_eq!(count_ticks(&mut cpu), cycles);
+        assert_eq!(cpu.registers.pc, expected.registers.pc);
+    }
+
+    // A tick at a time, every opcode has to end up where running it at once does
+    #[test]
+    fn it_should_take_the_cycles_of_every_opcode_a_tick_each() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m[0x10..0x12].copy_from_slice(&[0x00, 0x04]);
+        m[0x310] = 0x81;
+        m[0x400] = 0x7f;
+        for variant in [Variant::Nmos, Variant::Ricoh2A03, Variant::Cmos65C02].iter() {
+            for opcode in 0..=0xff {
+                m[0x200..0x203].copy_from_slice(&[opcode, 0x10, 0x03]);
+                let mut expected = Mos6502Cpu::with_variant(Box::new(m), *variant);
+                expected.registers.pc = 0x200;
+                let instruction = expected.decode_instruction(&m[0x200..0x203]);
+                expected.increase_pc(instruction.size().unwrap());
+                expected.execute_instruction(&instruction).unwrap();
+                let cycles = expected.get_cycles_for_instruction(&instruction).unwrap();
+                let mut cpu = Mos6502Cpu::with_variant(Box::new(m), *variant);
+                cpu.registers.pc = 0x200;
+                assert_eq!(count_ticks(&mut cpu), cycles, "{}", instruction);
+                let registers = |cpu: &Mos6502Cpu| {
+                    let r = &cpu.registers;
+                    (r.pc, r.a, r.x, r.y, r.s, r.p.to_byte())
+                };
+                assert_eq!(registers(&cpu), registers(&expected), "{}", instruction);
+                for address in 0..0x500 {
+                    assert_eq!(
+                        cpu.memory.get(address),
+                        expected.memory.get(address),
+                        "{} at {:04x}",
+                        instruction,
+                        address
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_service_the_irq_line_while_any_source_holds_it() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m.set(0x10, 0x58);
+        m.set(0x11, 0x58);
+        m.set(0x12, 0xea);
+        m.set(0xfffe, 0x00);
+        m.set(0xffff, 0x10);
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.registers.s = 0xff;
+        cpu.registers.pc = 0x10;
+        cpu.set_irq(0x01, true);
+        cpu.set_irq(0x02, true);
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x1000);
+        assert!(cpu.registers.p.interrupt_disable);
+        assert_eq!(cpu.memory.get(0x1ff), 0x00);
+        assert_eq!(cpu.memory.get(0x1fe), 0x10);
+        assert_eq!(cpu.memory.get(0x1fd) & 0x10, 0);
+        cpu.registers.pc = 0x10;
+        cpu.set_irq(0x01, false);
+        assert!(cpu.is_irq_asserted());
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.registers.pc, 0x11);
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x1000);
+        cpu.registers.pc = 0x11;
+        cpu.set_irq(0x02, false);
+        assert!(!cpu.is_irq_asserted());
+        cpu.execute().unwrap();
+        assert!(!cpu.registers.p.interrupt_disable);
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.registers.pc, 0x13);
+    }
+
+    fn interrupt_program() -> [u8; AVAILABLE_MEMORY] {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m[0x200..0x202].copy_from_slice(&[0xa9, 0x42]); // LDA #$42
+        m[0x300] = 0x40; // RTI
+        m[0x400] = 0x40;
+        m[0xfffa..0xfffc].copy_from_slice(&[0x00, 0x03]);
+        m[0xfffc..0xfffe].copy_from_slice(&[0x00, 0x02]);
+        m[0xfffe..].copy_from_slice(&[0x00, 0x04]);
+        m
+    }
+
+    // Reset takes as long as an interrupt, those cycles go before anything else happens
+    fn reset_cpu() -> Mos6502Cpu {
+        let mut cpu = Mos6502Cpu::new(Box::new(interrupt_program()));
+        cpu.reset();
+        assert_eq!(count_ticks(&mut cpu), 7);
+        cpu
+    }
+
+    #[test]
+    fn it_should_take_seven_cycles_to_reset_with_interrupts_disabled() {
+        let mut cpu = Mos6502Cpu::new(Box::new(interrupt_program()));
+        cpu.reset();
+        assert!(cpu.registers.p.interrupt_disable);
+        assert_eq!(cpu.registers.s, 0xff);
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x200);
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.registers.a, 0x42);
+    }
+
+    #[test]
+    fn it_should_service_the_nmi_line_on_its_rising_edge() {
+        let mut cpu = reset_cpu();
+        cpu.registers.p.carry = true;
+        cpu.set_nmi(true);
+        // Interrupts are still disabled after the reset, NMI doesn't care
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x300);
+        assert_eq!(cpu.registers.s, 0xfc);
+        assert_eq!(cpu.memory.get(0x1ff), 0x02);
+        assert_eq!(cpu.memory.get(0x1fe), 0x00);
+        assert_eq!(cpu.memory.get(0x1fd), 0x25);
+        assert_eq!(cpu.execute().unwrap(), 6);
+        assert_eq!(cpu.registers.pc, 0x200);
+        // The line is still held, that's not an edge
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.registers.pc, 0x202);
+        cpu.registers.pc = 0x200;
+        cpu.set_nmi(false);
+        cpu.set_nmi(true);
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x300);
+        assert_eq!(cpu.memory.get(0x1ff), 0x02);
+        assert_eq!(cpu.memory.get(0x1fe), 0x00);
+    }
+
+    #[test]
+    fn it_should_service_the_nmi_line_before_the_irq_line() {
+        let mut cpu = reset_cpu();
+        cpu.registers.p.interrupt_disable = false;
+        cpu.set_irq(0x01, true);
+        cpu.set_nmi(true);
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x300);
+        // The NMI handler runs with interrupts disabled, RTI enables them again for the IRQ
+        assert_eq!(cpu.execute().unwrap(), 6);
+        assert_eq!(cpu.registers.pc, 0x200);
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x400);
+        assert_eq!(cpu.memory.get(0x1ff), 0x02);
+        assert_eq!(cpu.memory.get(0x1fe), 0x00);
+    }
+
+    #[test]
+    fn it_should_forget_a_pending_nmi_on_reset() {
+        let mut cpu = reset_cpu();
+        cpu.set_nmi(true);
+        cpu.reset();
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.registers.pc, 0x202);
+    }
+
+    #[test]
+    fn it_should_jump_through_the_irq_vector_unless_interrupts_are_disabled() {
+        let mut cpu = reset_cpu();
+        cpu.set_irq(0x01, true);
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.registers.pc, 0x202);
+        cpu.registers.pc = 0x200;
+        cpu.registers.p.interrupt_disable = false;
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x400);
+        assert!(cpu.registers.p.interrupt_disable);
+        assert_eq!(cpu.memory.get(0x1fd) & 0x30, 0x20);
+        cpu.set_irq(0x01, false);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.registers.pc, 0x200);
+        assert!(!cpu.registers.p.interrupt_disable);
+    }
+
+    #[test]
+    fn it_should_trigger_an_nmi_even_with_interrupts_disabled() {
+        let mut cpu = reset_cpu();
+        cpu.trigger_nmi();
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x300);
+        assert_eq!(cpu.execute().unwrap(), 6);
+        // A pulse, so it doesn't go off again
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.registers.pc, 0x202);
+    }
+
+    #[test]
+    fn it_should_trigger_an_irq_once_unless_interrupts_are_disabled() {
+        let mut cpu = reset_cpu();
+        assert!(!cpu.trigger_irq());
+        assert!(!cpu.is_irq_asserted());
+        cpu.registers.p.interrupt_disable = false;
+        assert!(cpu.trigger_irq());
+        assert_eq!(cpu.execute().unwrap(), 7);
+        assert_eq!(cpu.registers.pc, 0x400);
+        assert!(!cpu.is_irq_asserted());
+        assert_eq!(cpu.execute().unwrap(), 6);
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.registers.pc, 0x202);
+    }
+
+    #[test]
+    fn it_should_service_an_interrupt_after_the_instruction_it_interrupts() {
+        let mut cpu = reset_cpu();
+        assert_eq!(cpu.tick().unwrap(), TickResult::Cycle);
+        cpu.set_nmi(true);
+        assert_eq!(
+            cpu.tick().unwrap(),
+            TickResult::InstructionBoundary { cycles: 2 }
+        );
+        assert_eq!(cpu.registers.a, 0x42);
+        assert_eq!(count_ticks(&mut cpu), 7);
+        assert_eq!(cpu.registers.pc, 0x300);
+        assert_eq!(cpu.memory.get(0x1fe), 0x02);
+    }
+
+    #[test]
+    fn it_should_execute_through_ticks() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m.set(0, 0xa9);
+        m.set(1, 0x42);
+        m.set(2, 0xaa);
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.execute().unwrap(), 2);
+        assert_eq!(cpu.registers.x, 0x42);
+        assert_eq!(cpu.registers.pc, 3);
+    }
+
+    fn cmos_program() -> [u8; AVAILABLE_MEMORY] {
+        let mut m = [0; AVAILABLE_MEMORY];
+        let program = [
+            0xa2, 0x12, // LDX #$12
+            0xda, // PHX
+            0x7a, // PLY
+            0xa9, 0x34, // LDA #$34
+            0x92, 0x10, // STA ($10)
+            0x64, 0x20, // STZ $20
+            0x04, 0x21, // TSB $21
+            0xb2, 0x10, // LDA ($10)
+            0x80, 0x02, // BRA +2
+        ];
+        for (i, byte) in program.iter().enumerate() {
+            m.set(i as u16, *byte);
+        }
+        m.set(0x10, 0x00);
+        m.set(0x11, 0x20);
+        m.set(0x20, 0xff);
+        m.set(0x21, 0x01);
+        m
+    }
+
+    #[test]
+    fn it_should_run_65c02_instructions_under_the_cmos_variant() {
+        let mut cpu = Mos6502Cpu::with_variant(Box::new(cmos_program()), Variant::Cmos65C02);
+        cpu.set_strict(true);
+        let mut cycles = vec![];
+        for _ in 0..8 {
+            cycles.push(cpu.execute().unwrap());
+        }
+        assert_eq!(cycles, vec![2, 3, 4, 2, 5, 3, 5, 5]);
+        assert_eq!(cpu.registers.y, 0x12);
+        assert_eq!(cpu.registers.s, 0xff);
+        assert_eq!(cpu.memory.get(0x2000), 0x34);
+        assert_eq!(cpu.memory.get(0x20), 0);
+        assert_eq!(cpu.memory.get(0x21), 0x35);
+        assert_eq!(cpu.registers.a, 0x34);
+        cpu.execute().unwrap();
+        assert_eq!(cpu.registers.pc, 0x12);
+    }
+
+    #[test]
+    fn it_should_keep_the_nmos_opcodes_outside_the_cmos_variant() {
+        let mut cpu = Mos6502Cpu::new(Box::new(cmos_program()));
+        for _ in 0..3 {
+            cpu.execute().unwrap();
+        }
+        assert_eq!(cpu.registers.y, 0);
+        assert_eq!(cpu.registers.s, 0xff);
+        assert_eq!(cpu.registers.pc, 4);
+        cpu.registers.pc = 8;
+        cpu.execute().unwrap();
+        assert_eq!(cpu.memory.get(0x20), 0xff);
+        for opcode in [0x04, 0x12, 0x5a, 0x80, 0x92, 0xda].iter() {
+            let bytes = [*opcode, 0x02, 0x00];
+            for variant in [Variant::Nmos, Variant::Ricoh2A03].iter() {
+                let instruction = Mos6502Instruction::decode(&bytes, *variant);
+                assert_eq!(instruction.instruction, Mos6502InstructionCode::Nop);
+            }
+            let instruction = Mos6502Instruction::decode(&bytes, Variant::Cmos65C02);
+            assert_ne!(instruction.instruction, Mos6502InstructionCode::Nop);
+        }
+    }
+}
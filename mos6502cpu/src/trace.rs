@@ -0,0 +1,156 @@
+use mos6502cpu::RegisterSet;
+use std::fmt;
+use Mos6502Cpu;
+
+pub const TRACE_LENGTH: usize = 64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub bytes: [u8; 3],
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub s: u8,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}  {:02x} {:02x} {:02x}  A:{:02x} X:{:02x} Y:{:02x} P:{:02x} S:{:02x}",
+            self.pc,
+            self.bytes[0],
+            self.bytes[1],
+            self.bytes[2],
+            self.a,
+            self.x,
+            self.y,
+            self.p,
+            self.s
+        )
+    }
+}
+
+// Fixed size ring buffer, so tracing doesn't allocate on every instruction
+pub(crate) struct TraceBuffer {
+    entries: [TraceEntry; TRACE_LENGTH],
+    cursor: usize,
+    len: usize,
+}
+
+impl TraceBuffer {
+    pub(crate) fn new() -> TraceBuffer {
+        TraceBuffer {
+            entries: [TraceEntry::default(); TRACE_LENGTH],
+            cursor: 0,
+            len: 0,
+        }
+    }
+
+    // Registers are updated again once the instruction finishes executing
+    #[inline]
+    pub(crate) fn begin(&mut self, pc: u16, bytes: [u8; 3], registers: &RegisterSet) {
+        self.entries[self.cursor] = TraceEntry {
+            pc,
+            bytes,
+            ..TraceEntry::default()
+        };
+        self.cursor = (self.cursor + 1) % TRACE_LENGTH;
+        if self.len < TRACE_LENGTH {
+            self.len += 1;
+        }
+        self.complete(registers);
+    }
+
+    // The operand bytes are read on the cycles after the opcode
+    #[inline]
+    pub(crate) fn fill(&mut self, index: usize, byte: u8) {
+        let last = (self.cursor + TRACE_LENGTH - 1) % TRACE_LENGTH;
+        self.entries[last].bytes[index] = byte;
+    }
+
+    #[inline]
+    pub(crate) fn complete(&mut self, registers: &RegisterSet) {
+        let last = (self.cursor + TRACE_LENGTH - 1) % TRACE_LENGTH;
+        let entry = &mut self.entries[last];
+        entry.a = registers.a;
+        entry.x = registers.x;
+        entry.y = registers.y;
+        entry.p = registers.p.to_byte();
+        entry.s = registers.s;
+    }
+
+    fn entries(&self) -> Vec<TraceEntry> {
+        let start = (self.cursor + TRACE_LENGTH - self.len) % TRACE_LENGTH;
+        (0..self.len)
+            .map(|i| self.entries[(start + i) % TRACE_LENGTH])
+            .collect()
+    }
+}
+
+pub fn format_trace(entries: &[TraceEntry]) -> String {
+    let mut dump = String::new();
+    for entry in entries {
+        dump.push_str(&format!("{}\n", entry));
+    }
+    dump
+}
+
+impl Mos6502Cpu {
+    // Oldest first, ending with the last instruction fetched, even if it failed
+    pub fn recent_trace(&self) -> Vec<TraceEntry> {
+        self.trace.entries()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::Cpu;
+    use trace::{format_trace, TRACE_LENGTH};
+    use {CpuError, Memory, Mos6502Cpu, AVAILABLE_MEMORY};
+
+    #[test]
+    fn it_should_keep_the_last_instructions_before_an_error() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        for i in 0..99 {
+            m.set(i, 0xe8); // INX
+        }
+        m.set(99, 0x02); // Unofficial opcode
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.set_strict(true);
+        for _ in 0..99 {
+            cpu.execute().unwrap();
+        }
+        let error = cpu.execute().unwrap_err();
+        match error.downcast::<CpuError>().unwrap() {
+            CpuError::UnofficialOpcode { opcode: 0x02, pc: 99 } => {}
+            e => panic!("Unexpected error {}", e),
+        }
+        let trace = cpu.recent_trace();
+        assert_eq!(trace.len(), TRACE_LENGTH);
+        for (i, entry) in trace.iter().enumerate() {
+            assert_eq!(entry.pc, 36 + i as u16);
+        }
+        assert_eq!(trace[0].bytes[0], 0xe8);
+        assert_eq!(trace[0].x, 37);
+        assert_eq!(trace[62].x, 99);
+        assert_eq!(trace[63].bytes[0], 0x02);
+        assert!(format_trace(&trace[62..])
+            .starts_with("0062  e8 02 00  A:00 X:63 Y:00 P:30 S:ff\n0063  02 00 00  A:00 X:63"));
+    }
+
+    #[test]
+    fn it_should_trace_only_the_instructions_executed() {
+        let mut m = [0; AVAILABLE_MEMORY];
+        m.set(0, 0xa9); // LDA #$42
+        m.set(1, 0x42);
+        let mut cpu = Mos6502Cpu::new(Box::new(m));
+        cpu.execute().unwrap();
+        let trace = cpu.recent_trace();
+        assert_eq!(trace.len(), 1);
+        assert_eq!(trace[0].bytes, [0xa9, 0x42, 0x00]);
+        assert_eq!(trace[0].a, 0x42);
+    }
+}
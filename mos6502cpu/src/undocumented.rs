@@ -59,12 +59,14 @@ impl Mos6502Cpu {
 
     pub(crate) fn execute_dcp(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_store_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_dec_unchecked(addressing_mode)?;
         self.execute_cmp_unchecked(addressing_mode)
     }
 
     pub(crate) fn execute_isc(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_store_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_inc_unchecked(addressing_mode)?;
         self.execute_sbc_unchecked(addressing_mode)
     }
@@ -72,16 +74,21 @@ impl Mos6502Cpu {
     pub(crate) fn execute_las(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         if let AddressingMode::AbsoluteIndexedY { .. } = addressing_mode {
             let value = self.get_value_from_addressing_mode(addressing_mode)?;
-            let answer = value & self.registers.p.to_byte();
-            self.registers.x = answer;
-            self.registers.a = answer;
-            self.registers.p = ProcessorStatus::from_byte(answer);
+            self.las_logic(value);
             Ok(())
         } else {
             Err(CpuError::InvalidAddressingMode)
         }
     }
 
+    #[inline]
+    pub(crate) fn las_logic(&mut self, value: u8) {
+        let answer = value & self.registers.p.to_byte();
+        self.registers.x = answer;
+        self.registers.a = answer;
+        self.registers.p = ProcessorStatus::from_byte(answer);
+    }
+
     pub(crate) fn execute_lax(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         match addressing_mode {
             AddressingMode::Immediate { .. } => {
@@ -104,12 +111,14 @@ impl Mos6502Cpu {
 
     pub(crate) fn execute_rla(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_store_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_rol_unchecked(addressing_mode)?;
         self.execute_and_unchecked(addressing_mode)
     }
 
     pub(crate) fn execute_rra(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_store_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_ror_unchecked(addressing_mode)?;
         self.execute_adc_unchecked(addressing_mode)
     }
@@ -151,12 +160,14 @@ impl Mos6502Cpu {
 
     pub(crate) fn execute_slo(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_store_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_asl_unchecked(addressing_mode)?;
         self.execute_ora_unchecked(addressing_mode)
     }
 
     pub(crate) fn execute_sre(&mut self, addressing_mode: &AddressingMode) -> CpuResult {
         self.check_data_store_address(addressing_mode)?;
+        self.dummy_read_before_indexed_write(addressing_mode);
         self.execute_lsr_unchecked(addressing_mode)?;
         self.execute_eor_unchecked(addressing_mode)
     }
@@ -0,0 +1,41 @@
+extern crate mos6502cpu;
+
+use mos6502cpu::{Cpu, Memory, Mos6502Cpu, AVAILABLE_MEMORY};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn it_should_not_allocate_while_executing_instructions() {
+    let mut m = [0; AVAILABLE_MEMORY];
+    // LDA #0x42; STA 0x0200; INX; JMP 0x0000
+    for (i, byte) in [0xa9, 0x42, 0x8d, 0x00, 0x02, 0xe8, 0x4c, 0x00, 0x00].iter().enumerate() {
+        m.set(i as u16, *byte);
+    }
+    let mut cpu = Mos6502Cpu::new(Box::new(m));
+    for _ in 0..10 {
+        cpu.execute().unwrap();
+    }
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    for _ in 0..1000 {
+        cpu.execute().unwrap();
+    }
+    assert_eq!(ALLOCATIONS.load(Ordering::SeqCst), before);
+}
@@ -0,0 +1,20 @@
+extern crate cpu;
+extern crate mos6502cpu;
+
+#[path = "../../cpu/tests/support/disassembly.rs"]
+mod support;
+
+use mos6502cpu::{Mos6502Instruction, Variant};
+use support::{assert_matches_golden, listing};
+
+#[test]
+fn it_should_match_the_golden_nmos_disassembly() {
+    let listing = listing(|bytes| Mos6502Instruction::decode(bytes, Variant::Nmos));
+    assert_matches_golden("disassembly_nmos.txt", &listing);
+}
+
+#[test]
+fn it_should_match_the_golden_65c02_disassembly() {
+    let listing = listing(|bytes| Mos6502Instruction::decode(bytes, Variant::Cmos65C02));
+    assert_matches_golden("disassembly_65c02.txt", &listing);
+}
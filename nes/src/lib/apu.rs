@@ -0,0 +1,374 @@
+use mos6502cpu::Memory;
+use nes::InputOutputDevice;
+use ram::Ram;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub(crate) const DMC_REGISTERS: [u16; 4] = [0x4010, 0x4011, 0x4012, 0x4013];
+pub(crate) const STATUS_REGISTER: u16 = 0x4015;
+// NTSC periods of the DMC timer, in CPU cycles
+const DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+// Hardware steals between one and four cycles depending on what the CPU is doing, this takes the
+// usual case of a fetch landing on a read cycle
+const DMC_FETCH_CYCLES: u16 = 4;
+const DMC_ACTIVE: u8 = 0x10;
+const DMC_INTERRUPT: u8 = 0x80;
+
+// The standard nonlinear approximation of the APU mixer, with every output in its DAC range
+pub(crate) fn mix(pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+    let pulses = f32::from(pulse1) + f32::from(pulse2);
+    let pulse_out = if pulses == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulses + 100.0)
+    };
+    let tnd = f32::from(triangle) / 8227.0 + f32::from(noise) / 12241.0 + f32::from(dmc) / 22638.0;
+    let tnd_out = if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    };
+    pulse_out + tnd_out
+}
+
+/**
+ * Delta modulation channel, plays 1-bit deltas read straight from the CPU address space.
+ * See https://www.nesdev.org/wiki/APU_DMC
+ */
+pub(crate) struct Dmc {
+    irq_enabled: bool,
+    loop_flag: bool,
+    rate: u16,
+    timer: u16,
+    output_level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq_pending: bool,
+}
+
+impl Dmc {
+    pub(crate) fn new() -> Dmc {
+        Dmc {
+            irq_enabled: false,
+            loop_flag: false,
+            rate: DMC_RATES[0],
+            timer: DMC_RATES[0],
+            output_level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            current_address: 0xc000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq_pending: false,
+        }
+    }
+
+    pub(crate) fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x4010 => {
+                self.irq_enabled = (value & 0x80) > 0;
+                self.loop_flag = (value & 0x40) > 0;
+                self.rate = DMC_RATES[usize::from(value & 0x0f)];
+                if !self.irq_enabled {
+                    self.irq_pending = false;
+                }
+            }
+            0x4011 => self.output_level = value & 0x7f,
+            0x4012 => self.sample_address = 0xc000 + u16::from(value) * 64,
+            0x4013 => self.sample_length = u16::from(value) * 16 + 1,
+            _ => {}
+        }
+    }
+
+    // Writing $4015 acknowledges the interrupt, and starts or stops the sample
+    pub(crate) fn set_enabled(&mut self, enabled: bool) {
+        self.irq_pending = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    #[inline]
+    pub(crate) fn is_irq_asserted(&self) -> bool {
+        self.irq_pending
+    }
+
+    #[inline]
+    pub(crate) fn output(&self) -> u8 {
+        self.output_level
+    }
+
+    // Runs a CPU cycle, returning the cycles the CPU stays stalled by a sample fetch
+    pub(crate) fn clock(&mut self, memory: &dyn Memory) -> u16 {
+        let stall = self.fetch_sample(memory);
+        self.timer -= 1;
+        if self.timer == 0 {
+            self.timer = self.rate;
+            self.clock_output();
+        }
+        stall
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn fetch_sample(&mut self, memory: &dyn Memory) -> u16 {
+        if self.sample_buffer.is_some() || self.bytes_remaining == 0 {
+            return 0;
+        }
+        self.sample_buffer = Some(memory.get(self.current_address));
+        // The address wraps to $8000, not to zero
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+        DMC_FETCH_CYCLES
+    }
+
+    // Moves the level two steps up or down per bit, staying in 0..=127
+    fn clock_output(&mut self) {
+        if !self.silence {
+            if (self.shift_register & 0x01) > 0 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift_register = byte;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+/**
+ * Audio processing unit. Only the delta modulation channel is emulated for now, the other
+ * channels are silent in the mix and in $4015.
+ * See page 31 of https://nesdev.com/NESDoc.pdf
+ */
+pub(crate) struct Apu {
+    ram: Rc<RefCell<Ram>>,
+    pub(crate) dmc: Dmc,
+}
+
+impl Apu {
+    pub(crate) fn new(ram: &Rc<RefCell<Ram>>) -> Apu {
+        Apu {
+            ram: ram.clone(),
+            dmc: Dmc::new(),
+        }
+    }
+
+    // The RAM can't be borrowed while the CPU runs, so this goes between instructions. The cycles
+    // a fetch stalls also clock the channel
+    pub(crate) fn clock(&mut self, cycles: u16) -> u16 {
+        let ram = self.ram.borrow();
+        let mut stalled = 0;
+        let mut left = cycles;
+        while left > 0 {
+            let stall = self.dmc.clock(&*ram);
+            stalled += stall;
+            left += stall;
+            left -= 1;
+        }
+        stalled
+    }
+
+    #[inline]
+    pub(crate) fn is_irq_asserted(&self) -> bool {
+        self.dmc.is_irq_asserted()
+    }
+
+    pub(crate) fn output(&self) -> f32 {
+        mix(0, 0, 0, 0, self.dmc.output())
+    }
+
+    fn read_status(&self) -> u8 {
+        let active = if self.dmc.is_active() { DMC_ACTIVE } else { 0 };
+        let interrupt = if self.dmc.is_irq_asserted() {
+            DMC_INTERRUPT
+        } else {
+            0
+        };
+        active | interrupt
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        if address == STATUS_REGISTER {
+            self.dmc.set_enabled((value & DMC_ACTIVE) > 0);
+        } else {
+            self.dmc.write_register(address, value);
+        }
+    }
+}
+
+pub(crate) struct ApuConnector {
+    apu: Rc<RefCell<Apu>>,
+    address: u16,
+}
+
+impl ApuConnector {
+    pub(crate) fn new(apu: &Rc<RefCell<Apu>>, address: u16) -> ApuConnector {
+        ApuConnector {
+            apu: apu.clone(),
+            address,
+        }
+    }
+}
+
+impl InputOutputDevice for ApuConnector {
+    // Only $4015 can be read, the channel registers are write only
+    #[inline]
+    fn read(&self) -> u8 {
+        if self.address == STATUS_REGISTER {
+            self.apu.borrow().read_status()
+        } else {
+            0
+        }
+    }
+    #[inline]
+    fn write(&mut self, value: u8) -> u8 {
+        self.apu.borrow_mut().write_register(self.address, value);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use apu::{mix, Apu, ApuConnector, Dmc, DMC_RATES};
+    use nes::InputOutputDevice;
+    use ram::{Ram, ROM_SIZE};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn create_ram() -> Ram {
+        let mut rom = [0; ROM_SIZE];
+        rom[0x4000] = 0xff;
+        Ram::new(rom)
+    }
+
+    #[test]
+    fn it_should_clock_the_output_at_the_selected_rate() {
+        for (index, rate) in DMC_RATES.iter().enumerate() {
+            let ram = create_ram();
+            let mut dmc = Dmc::new();
+            dmc.write_register(0x4010, index as u8);
+            dmc.write_register(0x4011, 0x40);
+            dmc.timer = dmc.rate;
+            dmc.silence = false;
+            dmc.shift_register = 0x01;
+            for _ in 1..*rate {
+                dmc.clock(&ram);
+            }
+            assert_eq!(dmc.output(), 0x40);
+            dmc.clock(&ram);
+            assert_eq!(dmc.output(), 0x42);
+        }
+    }
+
+    #[test]
+    fn it_should_play_the_fetched_bytes_and_stall_the_cpu() {
+        let ram = Rc::new(RefCell::new(create_ram()));
+        let apu = Rc::new(RefCell::new(Apu::new(&ram)));
+        let mut rate = ApuConnector::new(&apu, 0x4010);
+        let mut status = ApuConnector::new(&apu, 0x4015);
+        rate.write(0x0f);
+        status.write(0x10);
+        assert_eq!(status.read(), 0x10);
+        assert_eq!(apu.borrow_mut().clock(1), 4);
+        assert_eq!(status.read(), 0x00);
+        // The timer runs out at the power up rate first, then the eight bits of silence end and
+        // the eight ones of the sample play
+        apu.borrow_mut().clock(428 - 5 + 54 * 7 - 1);
+        assert_eq!(apu.borrow().dmc.output(), 0);
+        apu.borrow_mut().clock(1 + 54 * 8);
+        assert_eq!(apu.borrow().dmc.output(), 16);
+        assert!(apu.borrow().output() > mix(0, 0, 0, 0, 14));
+    }
+
+    #[test]
+    fn it_should_restart_the_sample_when_looping() {
+        let ram = create_ram();
+        let mut dmc = Dmc::new();
+        dmc.write_register(0x4010, 0xc0);
+        dmc.set_enabled(true);
+        for _ in 0..3 {
+            dmc.sample_buffer = None;
+            assert_eq!(dmc.fetch_sample(&ram), 4);
+            assert!(dmc.is_active());
+            assert_eq!(dmc.current_address, 0xc000);
+        }
+        assert!(!dmc.is_irq_asserted());
+        dmc.write_register(0x4010, 0x80);
+        dmc.sample_buffer = None;
+        dmc.fetch_sample(&ram);
+        assert!(!dmc.is_active());
+        assert_eq!(dmc.current_address, 0xc001);
+        dmc.sample_buffer = None;
+        assert_eq!(dmc.fetch_sample(&ram), 0);
+    }
+
+    #[test]
+    fn it_should_assert_the_irq_when_the_last_byte_is_fetched() {
+        let ram = create_ram();
+        let mut dmc = Dmc::new();
+        dmc.write_register(0x4013, 0x01);
+        dmc.set_enabled(true);
+        for _ in 0..16 {
+            dmc.sample_buffer = None;
+            dmc.fetch_sample(&ram);
+            assert!(!dmc.is_irq_asserted());
+        }
+        dmc.sample_buffer = None;
+        dmc.fetch_sample(&ram);
+        assert!(!dmc.is_irq_asserted());
+
+        dmc.write_register(0x4010, 0x80);
+        dmc.set_enabled(true);
+        for _ in 0..16 {
+            dmc.sample_buffer = None;
+            dmc.fetch_sample(&ram);
+            assert!(!dmc.is_irq_asserted());
+        }
+        dmc.sample_buffer = None;
+        dmc.fetch_sample(&ram);
+        assert!(dmc.is_irq_asserted());
+        dmc.set_enabled(false);
+        assert!(!dmc.is_irq_asserted());
+    }
+}
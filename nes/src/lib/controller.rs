@@ -0,0 +1,316 @@
+use nes::InputOutputDevice;
+use ppu::{Framebuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub const BUTTON_A: u8 = 0x01;
+pub const BUTTON_B: u8 = 0x02;
+pub const BUTTON_SELECT: u8 = 0x04;
+pub const BUTTON_START: u8 = 0x08;
+pub const BUTTON_UP: u8 = 0x10;
+pub const BUTTON_DOWN: u8 = 0x20;
+pub const BUTTON_LEFT: u8 = 0x40;
+pub const BUTTON_RIGHT: u8 = 0x80;
+
+pub(crate) const CONTROLLERS: usize = 2;
+
+const ZAPPER_LIGHT_OFF: u8 = 0x08;
+const ZAPPER_TRIGGER: u8 = 0x10;
+// Pixels this far from where the gun aims still reach its photodiode
+const ZAPPER_RADIUS: usize = 2;
+// How long the photodiode keeps seeing a pixel after the beam drew it, about a millisecond
+const ZAPPER_LIGHT_SCANLINES: usize = 16;
+// The two upper rows of the palette are the light colors
+const LIGHT_COLOR_LEVEL: u8 = 2;
+
+// Columns $D to $F of the palette are blacks, only $2D and $3D are grays
+fn is_light(color: u8) -> bool {
+    let level = (color >> 4) & 0x03;
+    match color & 0x0f {
+        0x0e | 0x0f => false,
+        0x0d => level == 3,
+        _ => level >= LIGHT_COLOR_LEVEL,
+    }
+}
+
+/**
+ * Light gun, which reports its trigger on bit 4 and on bit 3 whether it doesn't see light.
+ * See https://wiki.nesdev.com/w/index.php/Zapper
+ */
+#[derive(Default)]
+pub(crate) struct Zapper {
+    x: usize,
+    y: usize,
+    trigger: bool,
+    light_sensed: bool,
+}
+
+impl Zapper {
+    // Where the gun aims, in framebuffer pixels. Off screen it never sees light
+    pub(crate) fn set_position(&mut self, x: usize, y: usize) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub(crate) fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    // Called once the scanline is drawn. Only light pixels drawn in the last few scanlines count,
+    // the rows below the scanline still have the previous frame
+    pub(crate) fn sense_light(&mut self, framebuffer: &Framebuffer, scanline: usize) {
+        let recent = scanline.saturating_sub(ZAPPER_LIGHT_SCANLINES - 1);
+        let first_row = self.y.saturating_sub(ZAPPER_RADIUS).max(recent);
+        let last_row = (self.y + ZAPPER_RADIUS)
+            .min(SCREEN_HEIGHT - 1)
+            .min(scanline);
+        let first_column = self.x.saturating_sub(ZAPPER_RADIUS);
+        let last_column = (self.x + ZAPPER_RADIUS).min(SCREEN_WIDTH - 1);
+        self.light_sensed = false;
+        for row in first_row..=last_row {
+            for column in first_column..=last_column {
+                let distance = (row as isize - self.y as isize).pow(2)
+                    + (column as isize - self.x as isize).pow(2);
+                if distance <= (ZAPPER_RADIUS as isize).pow(2)
+                    && is_light(framebuffer[row * SCREEN_WIDTH + column])
+                {
+                    self.light_sensed = true;
+                }
+            }
+        }
+    }
+
+    fn read(&self) -> u8 {
+        let light = if self.light_sensed {
+            0
+        } else {
+            ZAPPER_LIGHT_OFF
+        };
+        let trigger = if self.trigger { ZAPPER_TRIGGER } else { 0 };
+        light | trigger
+    }
+}
+
+/**
+ * Standard controllers, read a button at a time from $4016 and $4017, or zappers in their place.
+ * See page 46 of https://nesdev.com/NESDoc.pdf
+ */
+pub(crate) struct Controllers {
+    buttons: [u8; CONTROLLERS],
+    shift_registers: [u8; CONTROLLERS],
+    strobe: bool,
+    zappers: [Option<Zapper>; CONTROLLERS],
+}
+
+impl Controllers {
+    pub(crate) fn new() -> Controllers {
+        Controllers {
+            buttons: [0; CONTROLLERS],
+            shift_registers: [0; CONTROLLERS],
+            strobe: false,
+            zappers: [None, None],
+        }
+    }
+
+    pub(crate) fn connect_zapper(&mut self, port: usize) {
+        self.zappers[port] = Some(Zapper::default());
+    }
+
+    #[inline]
+    pub(crate) fn zapper_mut(&mut self, port: usize) -> Option<&mut Zapper> {
+        self.zappers[port].as_mut()
+    }
+
+    pub(crate) fn sense_light(&mut self, framebuffer: &Framebuffer, scanline: usize) {
+        for zapper in self.zappers.iter_mut().flatten() {
+            zapper.sense_light(framebuffer, scanline);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn buttons(&self, port: usize) -> u8 {
+        self.buttons[port]
+    }
+
+    #[inline]
+    pub(crate) fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.buttons[port] = buttons;
+    }
+
+    #[inline]
+    pub(crate) fn set_button(&mut self, port: usize, button: u8, pressed: bool) {
+        if pressed {
+            self.buttons[port] |= button;
+        } else {
+            self.buttons[port] &= !button;
+        }
+    }
+
+    // While the strobe is high the buttons are reloaded, they are latched when it goes low
+    fn write_strobe(&mut self, value: u8) {
+        if self.strobe || (value & 0x01) > 0 {
+            self.shift_registers = self.buttons;
+        }
+        self.strobe = (value & 0x01) > 0;
+    }
+
+    // After the eight buttons, official controllers report ones
+    fn read(&mut self, port: usize) -> u8 {
+        if let Some(ref zapper) = self.zappers[port] {
+            return zapper.read();
+        }
+        if self.strobe {
+            self.shift_registers[port] = self.buttons[port];
+        }
+        let bit = self.shift_registers[port] & 0x01;
+        self.shift_registers[port] = (self.shift_registers[port] >> 1) | 0x80;
+        bit
+    }
+}
+
+pub(crate) struct ControllerConnector {
+    controllers: Rc<RefCell<Controllers>>,
+    port: usize,
+}
+
+impl ControllerConnector {
+    pub(crate) fn new(controllers: &Rc<RefCell<Controllers>>, port: usize) -> ControllerConnector {
+        ControllerConnector {
+            controllers: controllers.clone(),
+            port,
+        }
+    }
+}
+
+impl InputOutputDevice for ControllerConnector {
+    #[inline]
+    fn read(&self) -> u8 {
+        self.controllers.borrow_mut().read(self.port)
+    }
+    #[inline]
+    fn write(&mut self, value: u8) -> u8 {
+        // Writes to $4017 go to the APU frame counter, only $4016 strobes the controllers
+        if self.port == 0 {
+            self.controllers.borrow_mut().write_strobe(value);
+        }
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use controller::{
+        ControllerConnector, Controllers, BUTTON_A, BUTTON_RIGHT, BUTTON_START, BUTTON_UP,
+    };
+    use nes::InputOutputDevice;
+    use ppu::{Framebuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const BLACK: u8 = 0x0f;
+    const WHITE: u8 = 0x30;
+
+    // A 16x16 square of the color at (100, 60) on black, like the boxes Duck Hunt flashes
+    fn create_framebuffer(color: u8) -> Box<Framebuffer> {
+        let mut framebuffer = Box::new([BLACK; SCREEN_WIDTH * SCREEN_HEIGHT]);
+        for y in 60..76 {
+            for x in 100..116 {
+                framebuffer[y * SCREEN_WIDTH + x] = color;
+            }
+        }
+        framebuffer
+    }
+
+    // The zapper on the second port, read after the scanline is drawn
+    fn read_zapper(framebuffer: &Framebuffer, x: usize, y: usize, scanline: usize) -> u8 {
+        let controllers = Rc::new(RefCell::new(Controllers::new()));
+        controllers.borrow_mut().connect_zapper(1);
+        let zapper = ControllerConnector::new(&controllers, 1);
+        controllers
+            .borrow_mut()
+            .zapper_mut(1)
+            .unwrap()
+            .set_position(x, y);
+        controllers.borrow_mut().sense_light(framebuffer, scanline);
+        zapper.read()
+    }
+
+    #[test]
+    fn it_should_shift_out_the_buttons_latched_by_the_strobe() {
+        let controllers = Rc::new(RefCell::new(Controllers::new()));
+        let mut first = ControllerConnector::new(&controllers, 0);
+        let mut second = ControllerConnector::new(&controllers, 1);
+        controllers
+            .borrow_mut()
+            .set_buttons(0, BUTTON_A | BUTTON_START | BUTTON_RIGHT);
+        controllers.borrow_mut().set_buttons(1, BUTTON_UP);
+        first.write(1);
+        assert_eq!(first.read(), 1);
+        assert_eq!(first.read(), 1);
+        first.write(0);
+        second.write(1);
+        controllers.borrow_mut().set_buttons(0, 0);
+        let first_bits: Vec<u8> = (0..10).map(|_| first.read()).collect();
+        let second_bits: Vec<u8> = (0..8).map(|_| second.read()).collect();
+        assert_eq!(first_bits, vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+        assert_eq!(second_bits, vec![0, 0, 0, 0, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn it_should_only_see_light_shortly_after_it_is_drawn() {
+        let framebuffer = create_framebuffer(WHITE);
+        // Bit 3 is set while the zapper doesn't see light
+        assert_eq!(read_zapper(&framebuffer, 108, 68, 59), 0x08);
+        assert_eq!(read_zapper(&framebuffer, 108, 68, 65), 0x08);
+        assert_eq!(read_zapper(&framebuffer, 108, 68, 66), 0x00);
+        assert_eq!(read_zapper(&framebuffer, 108, 68, 80), 0x00);
+        assert_eq!(read_zapper(&framebuffer, 108, 68, 90), 0x08);
+        assert_eq!(read_zapper(&framebuffer, 108, 68, 200), 0x08);
+    }
+
+    #[test]
+    fn it_should_only_see_light_close_to_where_it_aims() {
+        let framebuffer = create_framebuffer(WHITE);
+        assert_eq!(read_zapper(&framebuffer, 50, 68, 70), 0x08);
+        assert_eq!(read_zapper(&framebuffer, 98, 68, 70), 0x00);
+        assert_eq!(read_zapper(&framebuffer, 97, 68, 70), 0x08);
+        // The corner is further than the radius in a diagonal
+        assert_eq!(read_zapper(&framebuffer, 98, 58, 70), 0x08);
+        assert_eq!(read_zapper(&framebuffer, 99, 59, 70), 0x00);
+        assert_eq!(read_zapper(&framebuffer, 300, 300, 239), 0x08);
+        let mut framebuffer = create_framebuffer(BLACK);
+        framebuffer[SCREEN_WIDTH * SCREEN_HEIGHT - 1] = WHITE;
+        assert_eq!(read_zapper(&framebuffer, 255, 239, 239), 0x00);
+    }
+
+    #[test]
+    fn it_should_only_see_light_colors() {
+        for color in [0x20, 0x21, 0x3d, 0x37].iter() {
+            assert_eq!(read_zapper(&create_framebuffer(*color), 108, 68, 70), 0x00);
+        }
+        for color in [0x00, 0x16, 0x2d, 0x2e, 0x3f].iter() {
+            assert_eq!(read_zapper(&create_framebuffer(*color), 108, 68, 70), 0x08);
+        }
+    }
+
+    #[test]
+    fn it_should_report_the_trigger_of_a_zapper_in_place_of_the_buttons() {
+        let controllers = Rc::new(RefCell::new(Controllers::new()));
+        let mut first = ControllerConnector::new(&controllers, 0);
+        let zapper = ControllerConnector::new(&controllers, 1);
+        controllers.borrow_mut().connect_zapper(1);
+        controllers.borrow_mut().set_buttons(0, BUTTON_A);
+        controllers.borrow_mut().set_buttons(1, BUTTON_A);
+        first.write(1);
+        first.write(0);
+        assert_eq!(zapper.read(), 0x08);
+        controllers
+            .borrow_mut()
+            .zapper_mut(1)
+            .unwrap()
+            .set_trigger(true);
+        assert_eq!(zapper.read(), 0x18);
+        assert_eq!(zapper.read(), 0x18);
+        assert_eq!(first.read(), 1);
+    }
+}
@@ -0,0 +1,204 @@
+use failure::Error;
+use mapper::Mirroring;
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const PRG_ROM_UNIT: usize = 0x4000;
+const CHR_ROM_UNIT: usize = 0x2000;
+const MAGIC: &[u8; 4] = b"NES\x1a";
+
+#[derive(Debug, Fail)]
+pub enum InesError {
+    #[fail(display = "Not an iNES file, it doesn't start with NES\\x1A")]
+    InvalidMagic,
+    #[fail(
+        display = "The iNES file has {} bytes, its header needs {}",
+        size, expected
+    )]
+    Truncated { size: usize, expected: usize },
+    #[fail(display = "The iNES file has no PRG ROM")]
+    NoPrgRom,
+}
+
+/**
+ * A cartridge dumped with its 16 bytes iNES header.
+ * See https://wiki.nesdev.com/w/index.php/INES
+ */
+pub struct InesRom {
+    prg_rom: Vec<u8>,
+    chr_rom: Vec<u8>,
+    trainer: Option<Vec<u8>>,
+    mapper: u8,
+    mirroring: Mirroring,
+    four_screen: bool,
+    battery: bool,
+}
+
+impl InesRom {
+    pub fn new(bytes: &[u8]) -> Result<InesRom, Error> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::from(InesError::Truncated {
+                size: bytes.len(),
+                expected: HEADER_SIZE,
+            }));
+        }
+        if bytes[..4] != MAGIC[..] {
+            return Err(Error::from(InesError::InvalidMagic));
+        }
+        let (flags6, flags7) = (bytes[6], bytes[7]);
+        let has_trainer = (flags6 & 0x04) > 0;
+        let prg_start = HEADER_SIZE + if has_trainer { TRAINER_SIZE } else { 0 };
+        let chr_start = prg_start + bytes[4] as usize * PRG_ROM_UNIT;
+        let chr_end = chr_start + bytes[5] as usize * CHR_ROM_UNIT;
+        if bytes.len() < chr_end {
+            return Err(Error::from(InesError::Truncated {
+                size: bytes.len(),
+                expected: chr_end,
+            }));
+        }
+        if chr_start == prg_start {
+            return Err(Error::from(InesError::NoPrgRom));
+        }
+        // Old dumping tools wrote their name over bytes 7 to 15, so then flags 7 can't be trusted
+        let mapper_high = if bytes[12..HEADER_SIZE].iter().all(|b| *b == 0) {
+            flags7 & 0xf0
+        } else {
+            0
+        };
+        Ok(InesRom {
+            prg_rom: bytes[prg_start..chr_start].to_vec(),
+            chr_rom: bytes[chr_start..chr_end].to_vec(),
+            trainer: if has_trainer {
+                Some(bytes[HEADER_SIZE..prg_start].to_vec())
+            } else {
+                None
+            },
+            mapper: mapper_high | (flags6 >> 4),
+            mirroring: if (flags6 & 0x01) > 0 {
+                Mirroring::Vertical
+            } else {
+                Mirroring::Horizontal
+            },
+            four_screen: (flags6 & 0x08) > 0,
+            battery: (flags6 & 0x02) > 0,
+        })
+    }
+
+    #[inline]
+    pub fn prg_rom(&self) -> &[u8] {
+        &self.prg_rom
+    }
+
+    // Empty when the cartridge has CHR RAM instead
+    #[inline]
+    pub fn chr_rom(&self) -> &[u8] {
+        &self.chr_rom
+    }
+
+    // Loaded at $7000 before the game starts
+    #[inline]
+    pub fn trainer(&self) -> Option<&[u8]> {
+        self.trainer.as_deref()
+    }
+
+    #[inline]
+    pub fn mapper(&self) -> u8 {
+        self.mapper
+    }
+
+    #[inline]
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    #[inline]
+    pub fn has_four_screen_vram(&self) -> bool {
+        self.four_screen
+    }
+
+    #[inline]
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn create_ines_file(prg_rom: &[u8], chr_rom: &[u8], flags6: u8, flags7: u8) -> Vec<u8> {
+    let mut file = MAGIC.to_vec();
+    file.push((prg_rom.len() / PRG_ROM_UNIT) as u8);
+    file.push((chr_rom.len() / CHR_ROM_UNIT) as u8);
+    file.push(flags6);
+    file.push(flags7);
+    file.resize(HEADER_SIZE, 0);
+    if (flags6 & 0x04) > 0 {
+        file.extend((0..TRAINER_SIZE).map(|i| i as u8));
+    }
+    file.extend_from_slice(prg_rom);
+    file.extend_from_slice(chr_rom);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use ines::{create_ines_file, InesError, InesRom};
+    use mapper::Mirroring;
+
+    fn ines_error(bytes: &[u8]) -> InesError {
+        match InesRom::new(bytes) {
+            Ok(_) => panic!("The file shouldn't be valid"),
+            Err(error) => error.downcast::<InesError>().unwrap(),
+        }
+    }
+
+    #[test]
+    fn it_should_parse_the_header() {
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[0] = 0x42;
+        let chr_rom = vec![0x24; 0x2000];
+        let rom = InesRom::new(&create_ines_file(&prg_rom, &chr_rom, 0x47, 0x10)).unwrap();
+        assert_eq!(rom.mapper(), 0x14);
+        assert_eq!(rom.mirroring(), Mirroring::Vertical);
+        assert!(rom.has_battery());
+        assert!(!rom.has_four_screen_vram());
+        assert_eq!(rom.trainer().unwrap()[..3], [0, 1, 2]);
+        assert_eq!(rom.prg_rom(), &prg_rom[..]);
+        assert_eq!(rom.chr_rom(), &chr_rom[..]);
+    }
+
+    #[test]
+    fn it_should_ignore_flags_7_when_a_tool_wrote_over_the_header() {
+        let mut file = create_ines_file(&[0; 0x4000], &[], 0x48, 0x20);
+        file[7..16].copy_from_slice(b"DiskDude!");
+        let rom = InesRom::new(&file).unwrap();
+        assert_eq!(rom.mapper(), 4);
+        assert_eq!(rom.mirroring(), Mirroring::Horizontal);
+        assert!(rom.has_four_screen_vram());
+        assert!(rom.chr_rom().is_empty());
+    }
+
+    #[test]
+    fn it_should_reject_invalid_files() {
+        match ines_error(b"NES\x1a\x01") {
+            InesError::Truncated { size: 5, .. } => {}
+            error => panic!("Unexpected {:?}", error),
+        }
+        let mut file = create_ines_file(&[0; 0x4000], &[0; 0x2000], 0, 0);
+        file[3] = 0;
+        match ines_error(&file) {
+            InesError::InvalidMagic => {}
+            error => panic!("Unexpected {:?}", error),
+        }
+        file[3] = 0x1a;
+        file.truncate(0x4010);
+        match ines_error(&file) {
+            InesError::Truncated {
+                expected: 0x6010, ..
+            } => {}
+            error => panic!("Unexpected {:?}", error),
+        }
+        match ines_error(&create_ines_file(&[], &[0; 0x2000], 0, 0)) {
+            InesError::NoPrgRom => {}
+            error => panic!("Unexpected {:?}", error),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use failure::Error;
+use mapper::{Mapper, MapperError, Mirroring};
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x400;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+/**
+ * See https://wiki.nesdev.com/w/index.php/MMC3
+ */
+pub struct Mmc3 {
+    prg_rom: Vec<u8>,
+    prg_ram: [u8; 0x2000],
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    bank_select: u8,
+    bank_registers: [u8; 8],
+    mirroring: Mirroring,
+    prg_ram_enabled: bool,
+    prg_ram_write_protected: bool,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3 {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Result<Mmc3, Error> {
+        if prg_rom.is_empty() || !prg_rom.len().is_multiple_of(PRG_BANK_SIZE) {
+            return Err(Error::from(MapperError::InvalidPrgRomSize {
+                size: prg_rom.len(),
+                bank_size: PRG_BANK_SIZE,
+            }));
+        }
+        if !chr_rom.len().is_multiple_of(CHR_BANK_SIZE) {
+            return Err(Error::from(MapperError::InvalidChrRomSize {
+                size: chr_rom.len(),
+                bank_size: CHR_BANK_SIZE,
+            }));
+        }
+        let chr_is_ram = chr_rom.is_empty();
+        let chr = if chr_is_ram {
+            vec![0; CHR_RAM_SIZE]
+        } else {
+            chr_rom
+        };
+        Ok(Mmc3 {
+            prg_rom,
+            prg_ram: [0; 0x2000],
+            chr,
+            chr_is_ram,
+            bank_select: 0,
+            bank_registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: Mirroring::Vertical,
+            prg_ram_enabled: true,
+            prg_ram_write_protected: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        })
+    }
+
+    #[inline]
+    fn prg_banks(&self) -> usize {
+        self.prg_rom.len() / PRG_BANK_SIZE
+    }
+
+    #[inline]
+    fn chr_banks(&self) -> usize {
+        self.chr.len() / CHR_BANK_SIZE
+    }
+
+    #[inline]
+    fn is_prg_mode_swapped(&self) -> bool {
+        (self.bank_select & 0x40) > 0
+    }
+
+    #[inline]
+    fn is_chr_mode_inverted(&self) -> bool {
+        (self.bank_select & 0x80) > 0
+    }
+
+    fn get_prg_bank(&self, address: u16) -> usize {
+        let second_last = self.prg_banks() - 2;
+        let r6 = usize::from(self.bank_registers[6] & 0x3f);
+        let r7 = usize::from(self.bank_registers[7] & 0x3f);
+        let bank = match (address >> 13) & 0x03 {
+            0 if self.is_prg_mode_swapped() => second_last,
+            0 => r6,
+            1 => r7,
+            2 if self.is_prg_mode_swapped() => r6,
+            2 => second_last,
+            _ => self.prg_banks() - 1,
+        };
+        bank % self.prg_banks()
+    }
+
+    fn get_chr_bank(&self, address: u16) -> usize {
+        let address = if self.is_chr_mode_inverted() {
+            address ^ 0x1000
+        } else {
+            address
+        };
+        let bank = match address >> 10 {
+            0 => self.bank_registers[0] & 0xfe,
+            1 => self.bank_registers[0] | 0x01,
+            2 => self.bank_registers[1] & 0xfe,
+            3 => self.bank_registers[1] | 0x01,
+            slot => self.bank_registers[slot as usize - 2],
+        };
+        usize::from(bank) % self.chr_banks()
+    }
+
+    #[inline]
+    fn get_prg_rom_index(&self, address: u16) -> usize {
+        self.get_prg_bank(address) * PRG_BANK_SIZE + (address as usize & (PRG_BANK_SIZE - 1))
+    }
+
+    #[inline]
+    fn get_chr_index(&self, address: u16) -> usize {
+        let address = address & 0x1fff;
+        self.get_chr_bank(address) * CHR_BANK_SIZE + (address as usize & (CHR_BANK_SIZE - 1))
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        let is_even = (address & 0x01) == 0;
+        match (address & 0xe000, is_even) {
+            (0x8000, true) => self.bank_select = value,
+            (0x8000, false) => self.bank_registers[(self.bank_select & 0x07) as usize] = value,
+            (0xa000, true) => {
+                self.mirroring = if (value & 0x01) > 0 {
+                    Mirroring::Horizontal
+                } else {
+                    Mirroring::Vertical
+                }
+            }
+            (0xa000, false) => {
+                self.prg_ram_enabled = (value & 0x80) > 0;
+                self.prg_ram_write_protected = (value & 0x40) > 0;
+            }
+            (0xc000, true) => self.irq_latch = value,
+            (0xc000, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xe000, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        } else if address < 0x8000 {
+            if self.prg_ram_enabled {
+                self.prg_ram[address as usize - 0x6000]
+            } else {
+                0
+            }
+        } else {
+            self.prg_rom[self.get_prg_rom_index(address)]
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            self.write_register(address, value);
+        } else if address >= 0x6000 && self.prg_ram_enabled && !self.prg_ram_write_protected {
+            self.prg_ram[address as usize - 0x6000] = value;
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.get_chr_index(address)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        if self.chr_is_ram {
+            let index = self.get_chr_index(address);
+            self.chr[index] = value;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn clock_scanline(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn is_irq_asserted(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mapper::{Mapper, Mirroring, Mmc3};
+
+    fn banked_rom(banks: usize, bank_size: usize) -> Vec<u8> {
+        let mut rom = Vec::with_capacity(banks * bank_size);
+        for bank in 0..banks {
+            for _ in 0..bank_size {
+                rom.push(bank as u8);
+            }
+        }
+        rom
+    }
+
+    fn create_mmc3() -> Mmc3 {
+        Mmc3::new(banked_rom(16, 0x2000), banked_rom(32, 0x400)).unwrap()
+    }
+
+    #[test]
+    fn it_should_reject_invalid_prg_rom_sizes() {
+        assert!(Mmc3::new(vec![], vec![]).is_err());
+        assert!(Mmc3::new(vec![0; 0x2001], vec![]).is_err());
+    }
+
+    #[test]
+    fn it_should_map_prg_banks_in_mode_0() {
+        let mut mapper = create_mmc3();
+        mapper.write_prg(0x8000, 0x06);
+        mapper.write_prg(0x8001, 0x03);
+        mapper.write_prg(0x8000, 0x07);
+        mapper.write_prg(0x8001, 0x05);
+        assert_eq!(mapper.read_prg(0x8000), 3);
+        assert_eq!(mapper.read_prg(0xa000), 5);
+        assert_eq!(mapper.read_prg(0xc000), 14);
+        assert_eq!(mapper.read_prg(0xe000), 15);
+        assert_eq!(mapper.read_prg(0xffff), 15);
+    }
+
+    #[test]
+    fn it_should_map_prg_banks_in_mode_1() {
+        let mut mapper = create_mmc3();
+        mapper.write_prg(0x8000, 0x46);
+        mapper.write_prg(0x8001, 0x03);
+        mapper.write_prg(0x8000, 0x47);
+        mapper.write_prg(0x8001, 0x05);
+        assert_eq!(mapper.read_prg(0x8000), 14);
+        assert_eq!(mapper.read_prg(0xa000), 5);
+        assert_eq!(mapper.read_prg(0xc000), 3);
+        assert_eq!(mapper.read_prg(0xe000), 15);
+    }
+
+    #[test]
+    fn it_should_wrap_prg_banks_around_the_rom_size() {
+        let mut mapper = create_mmc3();
+        mapper.write_prg(0x8000, 0x06);
+        mapper.write_prg(0x8001, 0x13);
+        assert_eq!(mapper.read_prg(0x8000), 3);
+    }
+
+    #[test]
+    fn it_should_map_chr_banks_in_both_modes() {
+        let mut mapper = create_mmc3();
+        for register in 0..6 {
+            mapper.write_prg(0x8000, register);
+            mapper.write_prg(0x8001, 10 + register * 2);
+        }
+        assert_eq!(mapper.read_chr(0x0000), 10);
+        assert_eq!(mapper.read_chr(0x0400), 11);
+        assert_eq!(mapper.read_chr(0x0800), 12);
+        assert_eq!(mapper.read_chr(0x0c00), 13);
+        assert_eq!(mapper.read_chr(0x1000), 14);
+        assert_eq!(mapper.read_chr(0x1400), 16);
+        assert_eq!(mapper.read_chr(0x1800), 18);
+        assert_eq!(mapper.read_chr(0x1c00), 20);
+        mapper.write_prg(0x8000, 0x80);
+        assert_eq!(mapper.read_chr(0x0000), 14);
+        assert_eq!(mapper.read_chr(0x0c00), 20);
+        assert_eq!(mapper.read_chr(0x1000), 10);
+        assert_eq!(mapper.read_chr(0x1c00), 13);
+    }
+
+    #[test]
+    fn it_should_control_mirroring_and_prg_ram() {
+        let mut mapper = create_mmc3();
+        mapper.write_prg(0xa000, 0x01);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.write_prg(0xa000, 0x00);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.write_prg(0xa001, 0x80);
+        mapper.write_prg(0x6000, 0x42);
+        assert_eq!(mapper.read_prg(0x6000), 0x42);
+        mapper.write_prg(0xa001, 0xc0);
+        mapper.write_prg(0x6000, 0x24);
+        assert_eq!(mapper.read_prg(0x6000), 0x42);
+        mapper.write_prg(0xa001, 0x00);
+        assert_eq!(mapper.read_prg(0x6000), 0);
+    }
+
+    #[test]
+    fn it_should_reload_the_irq_counter_and_assert_when_it_hits_zero() {
+        let mut mapper = create_mmc3();
+        mapper.write_prg(0xc000, 2);
+        mapper.write_prg(0xc001, 0);
+        mapper.write_prg(0xe001, 0);
+        mapper.clock_scanline();
+        assert!(!mapper.is_irq_asserted());
+        mapper.clock_scanline();
+        assert!(!mapper.is_irq_asserted());
+        mapper.clock_scanline();
+        assert!(mapper.is_irq_asserted());
+        mapper.write_prg(0xe000, 0);
+        assert!(!mapper.is_irq_asserted());
+        mapper.write_prg(0xe001, 0);
+        mapper.clock_scanline();
+        assert!(!mapper.is_irq_asserted());
+        mapper.clock_scanline();
+        mapper.clock_scanline();
+        assert!(mapper.is_irq_asserted());
+    }
+
+    #[test]
+    fn it_shouldnt_assert_irq_while_disabled() {
+        let mut mapper = create_mmc3();
+        mapper.write_prg(0xc000, 0);
+        mapper.write_prg(0xc001, 0);
+        mapper.clock_scanline();
+        assert!(!mapper.is_irq_asserted());
+        mapper.write_prg(0xe001, 0);
+        mapper.clock_scanline();
+        assert!(mapper.is_irq_asserted());
+    }
+}
@@ -0,0 +1,29 @@
+mod mmc3;
+
+pub use self::mmc3::Mmc3;
+
+#[derive(Debug, Fail)]
+pub enum MapperError {
+    #[fail(display = "PRG ROM size must be a non zero multiple of {}: {}", bank_size, size)]
+    InvalidPrgRomSize { size: usize, bank_size: usize },
+    #[fail(display = "CHR ROM size must be a multiple of {}: {}", bank_size, size)]
+    InvalidChrRomSize { size: usize, bank_size: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+}
+
+pub trait Mapper {
+    fn read_prg(&self, address: u16) -> u8;
+    fn write_prg(&mut self, address: u16, value: u8);
+    fn read_chr(&self, address: u16) -> u8;
+    fn write_chr(&mut self, address: u16, value: u8);
+    fn mirroring(&self) -> Mirroring;
+    fn clock_scanline(&mut self) {}
+    fn is_irq_asserted(&self) -> bool {
+        false
+    }
+}
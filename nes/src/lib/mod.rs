@@ -1,9 +1,24 @@
+#[macro_use]
 extern crate failure;
 extern crate mos6502cpu;
 
+mod apu;
+mod controller;
+mod ines;
+mod mapper;
+mod movie;
 mod nes;
 mod ppu;
 mod ram;
 
-pub use nes::Nes;
+pub use controller::{
+    BUTTON_A, BUTTON_B, BUTTON_DOWN, BUTTON_LEFT, BUTTON_RIGHT, BUTTON_SELECT, BUTTON_START,
+    BUTTON_UP,
+};
+pub use ines::{InesError, InesRom};
+pub use mapper::{Mapper, MapperError, Mirroring, Mmc3};
+pub use mos6502cpu::RamInitPattern;
+pub use movie::MovieError;
+pub use nes::{Nes, NesError};
+pub use ppu::{Framebuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
 pub use ram::ROM_SIZE;
@@ -0,0 +1,313 @@
+use controller::CONTROLLERS;
+use failure::Error;
+use nes::Nes;
+use std::fs::{read_to_string, File};
+use std::hash::Hasher;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const MOVIE_VERSION: &str = "1";
+const START_POWER_UP: &str = "power-up";
+// From the most significant bit, like the buttons byte of the controllers
+const BUTTON_NAMES: &[u8; 8] = b"RLDUTSBA";
+
+#[derive(Debug, Fail)]
+pub enum MovieError {
+    #[fail(display = "Unsupported movie version {}", version)]
+    UnsupportedVersion { version: String },
+    #[fail(display = "The movie has no {} header", key)]
+    MissingHeader { key: &'static str },
+    #[fail(display = "Unsupported start state {}", start)]
+    UnsupportedStart { start: String },
+    #[fail(
+        display = "The movie was recorded on ROM {:016x}, not on {:016x}",
+        expected, actual
+    )]
+    RomMismatch { expected: u64, actual: u64 },
+    #[fail(display = "Invalid movie line {}", line)]
+    InvalidLine { line: usize },
+    #[fail(display = "Frame {} diverged from the movie", frame)]
+    Desync { frame: usize },
+}
+
+// FNV-1a, so hashes written in a movie don't change across platforms or releases
+pub(crate) struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub(crate) fn new() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MovieFrame {
+    buttons: [u8; CONTROLLERS],
+    hash: Option<u64>,
+}
+
+pub(crate) enum Movie {
+    Recording(BufWriter<File>),
+    Playing {
+        frames: Vec<MovieFrame>,
+        next: usize,
+    },
+}
+
+fn format_buttons(buttons: u8) -> String {
+    BUTTON_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            if buttons & (0x80 >> i) > 0 {
+                *name as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+fn parse_buttons(field: &str) -> Option<u8> {
+    if field.len() != BUTTON_NAMES.len() {
+        return None;
+    }
+    field.bytes().zip(BUTTON_NAMES.iter()).enumerate().try_fold(
+        0,
+        |buttons, (i, (c, name))| match c {
+            b'.' => Some(buttons),
+            _ if c == *name => Some(buttons | (0x80 >> i)),
+            _ => None,
+        },
+    )
+}
+
+// One "|0|RLDUTSBA|RLDUTSBA|hash" line per frame, the hash is optional
+fn format_frame(frame: &MovieFrame) -> String {
+    let hash = frame
+        .hash
+        .map(|h| format!("{:016x}", h))
+        .unwrap_or_default();
+    format!(
+        "|0|{}|{}|{}\n",
+        format_buttons(frame.buttons[0]),
+        format_buttons(frame.buttons[1]),
+        hash
+    )
+}
+
+fn parse_frame(line: &str) -> Option<MovieFrame> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() != 5 || !fields[0].is_empty() || fields[1] != "0" {
+        return None;
+    }
+    let hash = if fields[4].is_empty() {
+        None
+    } else {
+        Some(u64::from_str_radix(fields[4], 16).ok()?)
+    };
+    Some(MovieFrame {
+        buttons: [parse_buttons(fields[2])?, parse_buttons(fields[3])?],
+        hash,
+    })
+}
+
+fn format_header(rom_hash: u64) -> String {
+    format!(
+        "version {}\nromHash {:016x}\nstart {}\n",
+        MOVIE_VERSION, rom_hash, START_POWER_UP
+    )
+}
+
+fn parse_movie(source: &str, rom_hash: u64) -> Result<Vec<MovieFrame>, MovieError> {
+    let mut version = None;
+    let mut movie_rom_hash = None;
+    let mut start = None;
+    let mut frames = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let invalid = || MovieError::InvalidLine { line: index + 1 };
+        if line.starts_with('|') {
+            frames.push(parse_frame(line).ok_or_else(invalid)?);
+            continue;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.splitn(2, ' ');
+        let (key, value) = (fields.next(), fields.next().ok_or_else(invalid)?);
+        match key {
+            Some("version") => version = Some(value),
+            Some("romHash") => {
+                movie_rom_hash = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?)
+            }
+            Some("start") => start = Some(value),
+            // Like FM2, headers this emulator doesn't know about are skipped
+            _ => {}
+        }
+    }
+    match version {
+        Some(MOVIE_VERSION) => {}
+        Some(version) => {
+            return Err(MovieError::UnsupportedVersion {
+                version: String::from(version),
+            })
+        }
+        None => return Err(MovieError::MissingHeader { key: "version" }),
+    }
+    match start {
+        Some(START_POWER_UP) => {}
+        Some(start) => {
+            return Err(MovieError::UnsupportedStart {
+                start: String::from(start),
+            })
+        }
+        None => return Err(MovieError::MissingHeader { key: "start" }),
+    }
+    let expected = movie_rom_hash.ok_or(MovieError::MissingHeader { key: "romHash" })?;
+    if expected != rom_hash {
+        return Err(MovieError::RomMismatch {
+            expected,
+            actual: rom_hash,
+        });
+    }
+    Ok(frames)
+}
+
+impl Nes {
+    // Every frame from now on is written to the movie, with the buttons the frontend set
+    pub fn record_movie<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(format_header(self.rom_hash()).as_bytes())?;
+        self.stop_movie()?;
+        self.movie = Some(Movie::Recording(writer));
+        Ok(())
+    }
+
+    // The next frames take their buttons from the movie, until it runs out
+    pub fn play_movie<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        let frames = parse_movie(&read_to_string(path)?, self.rom_hash())?;
+        self.stop_movie()?;
+        if !frames.is_empty() {
+            self.movie = Some(Movie::Playing { frames, next: 0 });
+        }
+        Ok(())
+    }
+
+    pub fn stop_movie(&mut self) -> Result<(), Error> {
+        if let Some(Movie::Recording(mut writer)) = self.movie.take() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn is_playing_movie(&self) -> bool {
+        matches!(self.movie, Some(Movie::Playing { .. }))
+    }
+
+    pub(crate) fn start_movie_frame(&mut self) {
+        let buttons = match self.movie {
+            Some(Movie::Playing { ref frames, next }) => frames[next].buttons,
+            _ => return,
+        };
+        for (port, buttons) in buttons.iter().enumerate() {
+            self.set_buttons(port, *buttons);
+        }
+    }
+
+    pub(crate) fn finish_movie_frame(&mut self) -> Result<(), Error> {
+        let hash = self.frame_hash();
+        let buttons = [self.buttons(0), self.buttons(1)];
+        let finished = match self.movie {
+            Some(Movie::Recording(ref mut writer)) => {
+                let frame = MovieFrame {
+                    buttons,
+                    hash: Some(hash),
+                };
+                writer.write_all(format_frame(&frame).as_bytes())?;
+                false
+            }
+            Some(Movie::Playing {
+                ref frames,
+                ref mut next,
+            }) => {
+                if let Some(expected) = frames[*next].hash {
+                    if expected != hash {
+                        return Err(Error::from(MovieError::Desync { frame: *next }));
+                    }
+                }
+                *next += 1;
+                *next == frames.len()
+            }
+            None => false,
+        };
+        if finished {
+            self.movie = None;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use controller::{BUTTON_A, BUTTON_DOWN, BUTTON_RIGHT, BUTTON_SELECT};
+    use movie::{format_frame, format_header, parse_movie, MovieError, MovieFrame};
+
+    #[test]
+    fn it_should_read_back_the_movies_it_writes() {
+        let frames = vec![
+            MovieFrame {
+                buttons: [BUTTON_A | BUTTON_RIGHT, 0],
+                hash: Some(0x0123_4567_89ab_cdef),
+            },
+            MovieFrame {
+                buttons: [0, BUTTON_SELECT | BUTTON_DOWN],
+                hash: None,
+            },
+        ];
+        let mut movie = format_header(0x42);
+        for frame in frames.iter() {
+            movie.push_str(&format_frame(frame));
+        }
+        assert_eq!(
+            movie,
+            "version 1\nromHash 0000000000000042\nstart power-up\n\
+             |0|R......A|........|0123456789abcdef\n|0|........|..D..S..|\n"
+        );
+        assert_eq!(parse_movie(&movie, 0x42).unwrap(), frames);
+    }
+
+    #[test]
+    fn it_should_reject_broken_movies() {
+        let header = "version 1\nromHash 42\nstart power-up\n";
+        match parse_movie(header, 0x24) {
+            Err(MovieError::RomMismatch { expected, actual }) => {
+                assert_eq!((expected, actual), (0x42, 0x24))
+            }
+            result => panic!("Unexpected {:?}", result),
+        }
+        match parse_movie(&format!("{}|0|A.......|........|\n", header), 0x42) {
+            Err(MovieError::InvalidLine { line: 4 }) => {}
+            result => panic!("Unexpected {:?}", result),
+        }
+        match parse_movie("version 1\nromHash 42\nstart reset\n", 0x42) {
+            Err(MovieError::UnsupportedStart { .. }) => {}
+            result => panic!("Unexpected {:?}", result),
+        }
+        match parse_movie("romHash 42\nstart power-up\n", 0x42) {
+            Err(MovieError::MissingHeader { key: "version" }) => {}
+            result => panic!("Unexpected {:?}", result),
+        }
+    }
+}
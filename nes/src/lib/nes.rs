@@ -1,10 +1,59 @@
 use super::failure::Error;
-use mos6502cpu::{AddressingMode, Cpu, Mos6502Cpu, Mos6502Instruction, Mos6502InstructionCode};
-use ppu::Ppu;
+use apu::{Apu, ApuConnector, DMC_REGISTERS, STATUS_REGISTER};
+use controller::{ControllerConnector, Controllers};
+use ines::InesRom;
+use mapper::{Mapper, Mmc3};
+use mos6502cpu::{
+    AddressingMode, Cpu, Memory, Mos6502Cpu, Mos6502Instruction, Mos6502InstructionCode,
+    RamInitPattern,
+};
+use movie::{Fnv1a, Movie};
+use ppu::{Framebuffer, Ppu, SCREEN_HEIGHT};
 use ram::{Ram, ROM_SIZE};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::hash::Hasher;
 use std::rc::Rc;
 
+const MAPPER_IRQ: u8 = 0x01;
+const DMC_IRQ: u8 = 0x02;
+const CONTROLLER_REGISTERS: usize = 0x16 + 0x8;
+const INTERNAL_RAM_SIZE: u16 = 0x800;
+// NTSC timing, the CPU runs a cycle every three PPU dots
+const DOTS_PER_SCANLINE: i64 = 341;
+const DOTS_PER_CPU_CYCLE: i64 = 3;
+const SCANLINES_PER_FRAME: u16 = 262;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+const OAM_DMA_CYCLES: u16 = 513;
+const TRAINER_ADDRESS: u16 = 0x7000;
+const NROM: u8 = 0;
+const MMC3: u8 = 4;
+
+#[derive(Debug, Fail)]
+pub enum NesError {
+    #[fail(display = "Mapper {} isn't supported", mapper)]
+    UnsupportedMapper { mapper: u8 },
+    #[fail(
+        display = "NROM cartridges have 16KB or 32KB of PRG ROM, not {} bytes",
+        size
+    )]
+    InvalidNromPrgRomSize { size: usize },
+}
+
+// NROM-128 mirrors its 16KB at $C000
+fn nrom_prg_rom(prg_rom: &[u8]) -> Result<[u8; ROM_SIZE], NesError> {
+    if prg_rom.len() != ROM_SIZE && prg_rom.len() != ROM_SIZE / 2 {
+        return Err(NesError::InvalidNromPrgRomSize {
+            size: prg_rom.len(),
+        });
+    }
+    let mut rom = [0; ROM_SIZE];
+    for bank in rom.chunks_mut(prg_rom.len()) {
+        bank.copy_from_slice(prg_rom);
+    }
+    Ok(rom)
+}
+
 pub(crate) trait InputOutputDevice {
     fn read(&self) -> u8;
     fn write(&mut self, value: u8) -> u8;
@@ -14,14 +63,86 @@ pub struct Nes {
     cpu: Mos6502Cpu,
     pub ram: Rc<RefCell<Ram>>,
     ppu: Ppu,
+    apu: Rc<RefCell<Apu>>,
+    controllers: Rc<RefCell<Controllers>>,
+    nmi_requested: Rc<Cell<bool>>,
+    rom_hash: u64,
+    dots: i64,
+    // CPU cycles since power up, their parity decides how long an OAM DMA takes
+    cycles: u64,
+    pub(crate) movie: Option<Movie>,
 }
 
 impl Nes {
-    pub fn new(rom: [u8; ROM_SIZE]) -> Nes {
-        let ram = Rc::new(RefCell::new(Ram::new(rom)));
-        let cpu = Mos6502Cpu::without_decimal(Box::new(ram.clone()));
-        let ppu = Ppu::new(ram.clone());
-        Nes { cpu, ppu, ram }
+    pub fn new(rom: InesRom) -> Result<Nes, Error> {
+        let nes = match rom.mapper() {
+            NROM => {
+                let mut nes = Nes::from_ram(Ram::new(nrom_prg_rom(rom.prg_rom())?));
+                nes.ppu.load_chr_rom(rom.chr_rom());
+                nes
+            }
+            MMC3 => Nes::with_mapper(Box::new(Mmc3::new(
+                rom.prg_rom().to_vec(),
+                rom.chr_rom().to_vec(),
+            )?)),
+            mapper => return Err(Error::from(NesError::UnsupportedMapper { mapper })),
+        };
+        if let Some(trainer) = rom.trainer() {
+            let mut ram = nes.ram.borrow_mut();
+            for (address, byte) in (TRAINER_ADDRESS..).zip(trainer.iter()) {
+                ram.set(address, *byte);
+            }
+        }
+        Ok(nes)
+    }
+
+    pub fn with_mapper(mapper: Box<dyn Mapper>) -> Nes {
+        Nes::from_ram(Ram::with_mapper(mapper))
+    }
+
+    fn from_ram(ram: Ram) -> Nes {
+        let ram = Rc::new(RefCell::new(ram));
+        let mut cpu = Mos6502Cpu::without_decimal(Box::new(ram.clone()));
+        cpu.initialize_ram(RamInitPattern::Alternating, ..INTERNAL_RAM_SIZE);
+        let mut ppu = Ppu::new(ram.clone());
+        let nmi_requested = Rc::new(Cell::new(false));
+        let requested = nmi_requested.clone();
+        ppu.set_nmi_callback(Box::new(move || requested.set(true)));
+        let controllers = Rc::new(RefCell::new(Controllers::new()));
+        let apu = Rc::new(RefCell::new(Apu::new(&ram)));
+        let rom_hash = {
+            let mut ram = ram.borrow_mut();
+            for address in DMC_REGISTERS.iter().chain([STATUS_REGISTER].iter()) {
+                ram.io_registers[usize::from(address - 0x4000) + 0x8].device =
+                    Some(Box::new(ApuConnector::new(&apu, *address)));
+            }
+            for port in 0..2 {
+                ram.io_registers[CONTROLLER_REGISTERS + port].device =
+                    Some(Box::new(ControllerConnector::new(&controllers, port)));
+            }
+            let mut hasher = Fnv1a::new();
+            for address in 0x8000..=0xffff {
+                hasher.write_u8(ram.get(address));
+            }
+            hasher.finish()
+        };
+        Nes {
+            cpu,
+            ppu,
+            ram,
+            apu,
+            controllers,
+            nmi_requested,
+            rom_hash,
+            dots: 0,
+            cycles: 0,
+            movie: None,
+        }
+    }
+
+    // Boots use the alternating pattern, this refills the internal RAM before powering up
+    pub fn initialize_ram(&mut self, pattern: RamInitPattern) {
+        self.cpu.initialize_ram(pattern, ..INTERNAL_RAM_SIZE);
     }
 
     pub fn power_up(&mut self) -> Result<(), Error> {
@@ -30,4 +151,506 @@ impl Nes {
             AddressingMode::Implicit,
         ))
     }
+
+    // Like pressing the reset button, the internal RAM and the PPU keep what they had
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+    }
+
+    // Runs frame after frame until something goes wrong
+    pub fn run(&mut self) -> Result<(), Error> {
+        loop {
+            self.run_frame()?;
+        }
+    }
+
+    // Includes the cycles the CPU stays stalled by an OAM DMA the instruction started and by the
+    // DMC fetching samples
+    pub fn execute(&mut self) -> Result<u16, Error> {
+        self.update_mapper_irq();
+        let mut cycles = u16::from(self.cpu.execute()?);
+        self.cycles += u64::from(cycles);
+        if self.ppu.run_oam_dma() {
+            // One more cycle to align the DMA reads when it starts on an odd cycle
+            let stall = OAM_DMA_CYCLES + (self.cycles % 2) as u16;
+            self.cycles += u64::from(stall);
+            cycles += stall;
+        }
+        let stall = self.apu.borrow_mut().clock(cycles);
+        self.cycles += u64::from(stall);
+        cycles += stall;
+        let asserted = self.apu.borrow().is_irq_asserted();
+        self.cpu.set_irq(DMC_IRQ, asserted);
+        Ok(cycles)
+    }
+
+    // What the mixer outputs right now, between 0 and 1. Frontends sample it at their own rate
+    pub fn audio_sample(&self) -> f32 {
+        self.apu.borrow().output()
+    }
+
+    // Runs the CPU a scanline at a time, with a movie feeding or recording the controllers
+    pub fn run_frame(&mut self) -> Result<(), Error> {
+        self.start_movie_frame();
+        for scanline in 0..SCANLINES_PER_FRAME {
+            if scanline == VBLANK_SCANLINE {
+                self.ppu.start_vblank();
+                // The CPU takes it once the instruction it's in the middle of is done
+                self.cpu.set_nmi(self.nmi_requested.replace(false));
+            } else if scanline == PRE_RENDER_SCANLINE {
+                self.ppu.end_vblank();
+                self.cpu.set_nmi(false);
+            }
+            // What a scanline overran is taken from the next one
+            self.dots += DOTS_PER_SCANLINE;
+            while self.dots > 0 {
+                self.dots -= i64::from(self.execute()?) * DOTS_PER_CPU_CYCLE;
+            }
+            if usize::from(scanline) < SCREEN_HEIGHT {
+                self.ppu.render_scanline(usize::from(scanline));
+            }
+            self.controllers
+                .borrow_mut()
+                .sense_light(self.ppu.framebuffer(), usize::from(scanline));
+            self.on_scanline();
+        }
+        self.finish_movie_frame()
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        self.ppu.framebuffer()
+    }
+
+    // Draws the whole frame at once from what the PPU has now, like when the emulation is paused
+    pub fn render_frame(&mut self) {
+        self.ppu.render_frame();
+    }
+
+    pub fn set_buttons(&mut self, controller: usize, buttons: u8) {
+        self.controllers
+            .borrow_mut()
+            .set_buttons(controller, buttons);
+    }
+
+    // Presses or releases one of the BUTTON_* buttons, leaving the others as they are
+    pub fn set_button(&mut self, controller: usize, button: u8, pressed: bool) {
+        self.controllers
+            .borrow_mut()
+            .set_button(controller, button, pressed);
+    }
+
+    pub fn buttons(&self, controller: usize) -> u8 {
+        self.controllers.borrow().buttons(controller)
+    }
+
+    // Plugs a zapper instead of a controller, Duck Hunt and most light gun games expect it on the
+    // second port
+    pub fn connect_zapper(&mut self, controller: usize) {
+        self.controllers.borrow_mut().connect_zapper(controller);
+    }
+
+    // Does nothing without a zapper connected to the port
+    pub fn set_zapper_position(&mut self, controller: usize, x: usize, y: usize) {
+        if let Some(zapper) = self.controllers.borrow_mut().zapper_mut(controller) {
+            zapper.set_position(x, y);
+        }
+    }
+
+    pub fn set_zapper_trigger(&mut self, controller: usize, pulled: bool) {
+        if let Some(zapper) = self.controllers.borrow_mut().zapper_mut(controller) {
+            zapper.set_trigger(pulled);
+        }
+    }
+
+    // Hash of the PRG ROM the cartridge boots with
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    pub fn frame_hash(&self) -> u64 {
+        let mut hasher = Fnv1a::new();
+        self.ppu.hash_frame(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn on_scanline(&mut self) {
+        if self.ppu.is_rendering_enabled() {
+            if let Some(ref mut mapper) = self.ram.borrow_mut().mapper {
+                mapper.clock_scanline();
+            }
+        }
+        self.update_mapper_irq();
+    }
+
+    fn update_mapper_irq(&mut self) {
+        let asserted = match self.ram.borrow().mapper {
+            Some(ref mapper) => mapper.is_irq_asserted(),
+            None => false,
+        };
+        self.cpu.set_irq(MAPPER_IRQ, asserted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use controller::{BUTTON_A, BUTTON_LEFT, BUTTON_START, BUTTON_UP};
+    use ines::{create_ines_file, InesRom};
+    use mapper::Mmc3;
+    use mos6502cpu::{Memory, RamInitPattern};
+    use movie::MovieError;
+    use nes::{Nes, NesError};
+    use ram::ROM_SIZE;
+    use std::env::temp_dir;
+    use std::fs::{read_to_string, remove_file, write};
+    use std::path::PathBuf;
+
+    const FRAMES: usize = 60;
+
+    // Reads the first controller once a frame and writes the buttons to the next name table byte
+    fn create_input_rom() -> [u8; ROM_SIZE] {
+        let mut rom = [0; ROM_SIZE];
+        rom[..0x33].copy_from_slice(&[
+            0xa9, 0x01, // LDA #$01
+            0x8d, 0x16, 0x40, // STA $4016
+            0xa9, 0x00, // LDA #$00
+            0x8d, 0x16, 0x40, // STA $4016
+            0xa2, 0x08, // LDX #$08
+            0xad, 0x16, 0x40, // LDA $4016
+            0x4a, // LSR A
+            0x26, 0x00, // ROL $00
+            0xca, // DEX
+            0xd0, 0xf7, // BNE $800C
+            0xa9, 0x20, // LDA #$20
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa5, 0x01, // LDA $01
+            0x8d, 0x06, 0x20, // STA $2006
+            0xa5, 0x00, // LDA $00
+            0x8d, 0x07, 0x20, // STA $2007
+            0xe6, 0x01, // INC $01
+            0xad, 0x02, 0x20, // LDA $2002
+            0x10, 0xfb, // BPL $8026
+            0xad, 0x02, 0x20, // LDA $2002
+            0x30, 0xfb, // BMI $802B
+            0x4c, 0x00, 0x80, // JMP $8000
+        ]);
+        rom[0x7ffd] = 0x80;
+        rom
+    }
+
+    fn scripted_buttons(frame: usize) -> u8 {
+        match frame % 4 {
+            0 => BUTTON_A,
+            1 => BUTTON_A | BUTTON_LEFT,
+            2 => 0,
+            _ if frame % 8 == 3 => BUTTON_START | BUTTON_UP,
+            _ => BUTTON_UP,
+        }
+    }
+
+    fn movie_path(name: &str) -> PathBuf {
+        temp_dir().join(format!("nes_{}_{}.fm2", name, std::process::id()))
+    }
+
+    fn create_nes(prg_rom: &[u8], chr_rom: &[u8], flags6: u8) -> Nes {
+        let file = create_ines_file(prg_rom, chr_rom, flags6, 0);
+        Nes::new(InesRom::new(&file).unwrap()).unwrap()
+    }
+
+    fn powered_up(rom: [u8; ROM_SIZE]) -> Nes {
+        let mut nes = create_nes(&rom, &[], 0);
+        nes.power_up().unwrap();
+        nes
+    }
+
+    fn record(path: &PathBuf) -> u64 {
+        let mut nes = powered_up(create_input_rom());
+        nes.record_movie(path).unwrap();
+        for frame in 0..FRAMES {
+            nes.set_buttons(0, scripted_buttons(frame));
+            nes.run_frame().unwrap();
+        }
+        nes.stop_movie().unwrap();
+        nes.frame_hash()
+    }
+
+    #[test]
+    fn it_should_boot_with_the_alternating_ram_pattern() {
+        let mut nes = create_nes(&create_input_rom(), &[], 0);
+        let ram: Vec<u8> = (0..8).map(|a| nes.ram.borrow().get(a)).collect();
+        assert_eq!(ram, vec![0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(nes.ram.borrow().get(0x8000), 0xa9);
+        nes.initialize_ram(RamInitPattern::AllZeros);
+        assert_eq!(nes.ram.borrow().get(0x07fc), 0);
+    }
+
+    #[test]
+    fn it_should_load_nrom_cartridges() {
+        let mut prg_rom = [0; 0x4000];
+        prg_rom[0] = 0x42;
+        let mut chr_rom = [0; 0x2000];
+        chr_rom[0x10] = 0x24;
+        let nes = create_nes(&prg_rom, &chr_rom, 0x04);
+        let mut ram = nes.ram.borrow_mut();
+        assert_eq!(ram.get(0x8000), 0x42);
+        assert_eq!(ram.get(0xc000), 0x42);
+        assert_eq!(ram.get(0x7001), 0x01);
+        ram.set(0x2006, 0x00);
+        ram.set(0x2006, 0x10);
+        assert_eq!(ram.get(0x2007), 0x24);
+    }
+
+    #[test]
+    fn it_should_load_32kb_nrom_cartridges_without_mirroring() {
+        let mut prg_rom = [0; 0x8000];
+        prg_rom[0] = 0x42;
+        prg_rom[0x4000] = 0x24;
+        let nes = create_nes(&prg_rom, &[0; 0x2000], 0x01);
+        let ram = nes.ram.borrow();
+        assert_eq!(ram.get(0x8000), 0x42);
+        assert_eq!(ram.get(0xc000), 0x24);
+    }
+
+    #[test]
+    fn it_should_boot_an_nrom_cartridge_from_its_reset_vector() {
+        let mut prg_rom = [0; 0x4000];
+        prg_rom[..0x14].copy_from_slice(&[
+            0xa9, 0x42, // LDA #$42
+            0x8d, 0x00, 0x08, // STA $0800
+            0x8d, 0x00, 0xc0, // STA $C000
+            0xad, 0x00, 0xc0, // LDA $C000
+            0x8d, 0x01, 0x18, // STA $1801
+            0x8d, 0x15, 0x40, // STA $4015
+            0x4c, 0x11, 0xc0, // JMP $C011
+        ]);
+        prg_rom[0x3ffc..].copy_from_slice(&[0x00, 0xc0, 0x00, 0xc0]);
+        let mut nes = create_nes(&prg_rom, &[], 0);
+        nes.power_up().unwrap();
+        for _ in 0..7 {
+            nes.execute().unwrap();
+        }
+        let ram = nes.ram.borrow();
+        assert_eq!(ram.get(0x0000), 0x42);
+        assert_eq!(ram.get(0x0001), 0xa9);
+        assert_eq!(ram.get(0x8000), 0xa9);
+        assert_eq!(ram.get(0xc000), 0xa9);
+        // $4015 reads back the APU status, with the DMC left off by bit 4 of $A9
+        assert_eq!(ram.get(0x4015), 0x00);
+    }
+
+    #[test]
+    fn it_should_serialize_the_buttons_through_4016() {
+        let mut nes = create_nes(&[0; 0x4000], &[], 0);
+        nes.set_button(0, BUTTON_START, true);
+        nes.set_button(0, BUTTON_LEFT, true);
+        nes.set_button(0, BUTTON_LEFT, false);
+        assert_eq!(nes.buttons(0), BUTTON_START);
+        let mut ram = nes.ram.borrow_mut();
+        ram.set(0x4016, 1);
+        ram.set(0x4016, 0);
+        let bits: Vec<u8> = (0..8).map(|_| ram.get(0x4016) & 0x01).collect();
+        // A, B, Select, Start, Up, Down, Left and Right
+        assert_eq!(bits, vec![0, 0, 0, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn it_should_read_a_zapper_through_4017() {
+        let mut nes = create_nes(&[0; 0x4000], &[], 0);
+        nes.set_zapper_trigger(1, true);
+        assert_eq!(nes.ram.borrow().get(0x4017) & 0x18, 0x00);
+        nes.connect_zapper(1);
+        nes.set_zapper_position(1, 128, 120);
+        nes.set_zapper_trigger(1, true);
+        // Nothing drew light where it aims yet
+        assert_eq!(nes.ram.borrow().get(0x4017), 0x18);
+        nes.set_zapper_trigger(1, false);
+        assert_eq!(nes.ram.borrow().get(0x4017), 0x08);
+    }
+
+    #[test]
+    fn it_should_step_the_cpu_after_a_reset() {
+        let mut nes = create_nes(&create_input_rom(), &[], 0);
+        nes.set_buttons(0, BUTTON_A | BUTTON_UP);
+        nes.reset();
+        // The reset itself takes seven cycles
+        assert_eq!(nes.execute().unwrap(), 7);
+        let cycles: Vec<u16> = (0..6).map(|_| nes.execute().unwrap()).collect();
+        assert_eq!(cycles, vec![2, 4, 2, 4, 2, 4]);
+        // The rest of the eight reads of the controller
+        for _ in 0..8 * 5 - 1 {
+            nes.execute().unwrap();
+        }
+        // A is read first, so it ends up in the highest bit
+        assert_eq!(nes.ram.borrow().get(0x00), 0x88);
+    }
+
+    #[test]
+    fn it_should_run_the_nmi_handler_once_a_frame_when_enabled() {
+        let mut rom = [0; ROM_SIZE];
+        rom[..0x0b].copy_from_slice(&[
+            0xa9, 0x80, // LDA #$80
+            0x8d, 0x00, 0x20, // STA $2000
+            0x4c, 0x05, 0x80, // JMP $8005
+            0xe6, 0x00, // INC $00
+            0x40, // RTI
+        ]);
+        rom[0x7ffa..].copy_from_slice(&[0x08, 0x80, 0x00, 0x80, 0x00, 0x80]);
+        let mut nes = powered_up(rom);
+        nes.run_frame().unwrap();
+        nes.run_frame().unwrap();
+        assert_eq!(nes.ram.borrow().get(0x0000), 2);
+    }
+
+    #[test]
+    fn it_should_copy_a_page_to_the_sprite_memory_on_oam_dma() {
+        let mut rom = [0; ROM_SIZE];
+        rom[..0x0d].copy_from_slice(&[
+            0xa9, 0x04, // LDA #$04
+            0x8d, 0x03, 0x20, // STA $2003
+            0xa9, 0x02, // LDA #$02
+            0x8d, 0x14, 0x40, // STA $4014
+            0x8d, 0x14, 0x40, // STA $4014
+        ]);
+        rom[0x7ffc..0x7ffe].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = powered_up(rom);
+        for i in 0..0x100 {
+            nes.ram.borrow_mut().set(0x0200 + i, i as u8);
+        }
+        let cycles: Vec<u16> = (0..5).map(|_| nes.execute().unwrap()).collect();
+        // The first DMA starts after 12 cycles and the second one after 529
+        assert_eq!(cycles, vec![2, 4, 2, 4 + 513, 4 + 514]);
+        let mut ram = nes.ram.borrow_mut();
+        for (oam_address, expected) in [(0x04, 0x00), (0x03, 0xff), (0x44, 0x40)].iter() {
+            ram.set(0x2003, *oam_address);
+            assert_eq!(ram.get(0x2004), *expected);
+        }
+    }
+
+    #[test]
+    fn it_should_load_mmc3_cartridges() {
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[0x7fff] = 0x42;
+        let nes = create_nes(&prg_rom, &[], 0x40);
+        assert_eq!(nes.ram.borrow().get(0xffff), 0x42);
+    }
+
+    #[test]
+    fn it_should_reject_unsupported_cartridges() {
+        let file = create_ines_file(&[0; 0x4000], &[], 0x10, 0);
+        let error = Nes::new(InesRom::new(&file).unwrap()).err().unwrap();
+        match error.downcast_ref::<NesError>() {
+            Some(NesError::UnsupportedMapper { mapper: 1 }) => {}
+            _ => panic!("Unexpected {}", error),
+        }
+        assert_eq!(error.to_string(), "Mapper 1 isn't supported");
+        let file = create_ines_file(&[0; 0xc000], &[], 0, 0);
+        let error = Nes::new(InesRom::new(&file).unwrap()).err().unwrap();
+        match error.downcast_ref::<NesError>() {
+            Some(NesError::InvalidNromPrgRomSize { size: 0xc000 }) => {}
+            _ => panic!("Unexpected {}", error),
+        }
+    }
+
+    #[test]
+    fn it_should_play_back_a_recorded_movie_to_the_same_frame() {
+        let path = movie_path("playback");
+        let recorded = record(&path);
+        let mut idle = powered_up(create_input_rom());
+        for _ in 0..FRAMES {
+            idle.run_frame().unwrap();
+        }
+        assert_ne!(idle.frame_hash(), recorded);
+
+        let mut nes = powered_up(create_input_rom());
+        nes.play_movie(&path).unwrap();
+        for _ in 0..FRAMES {
+            assert!(nes.is_playing_movie());
+            nes.run_frame().unwrap();
+        }
+        assert!(!nes.is_playing_movie());
+        assert_eq!(nes.frame_hash(), recorded);
+        assert_eq!(nes.ram.borrow().get(0x01), FRAMES as u8);
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn it_should_refuse_a_movie_recorded_on_another_rom() {
+        let path = movie_path("other_rom");
+        record(&path);
+        let mut rom = create_input_rom();
+        rom[0x1000] = 0xff;
+        let mut nes = powered_up(rom);
+        let error = nes.play_movie(&path).unwrap_err();
+        match error.downcast_ref::<MovieError>() {
+            Some(MovieError::RomMismatch { .. }) => {}
+            _ => panic!("Unexpected {}", error),
+        }
+        assert!(!nes.is_playing_movie());
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn it_should_detect_when_playback_diverges() {
+        let path = movie_path("desync");
+        record(&path);
+        let movie = read_to_string(&path).unwrap();
+        // The buttons of frame 8 change, but not the hash that was recorded with them
+        let mut lines: Vec<&str> = movie.lines().collect();
+        assert!(lines[11].starts_with("|0|.......A|"));
+        let changed = lines[11].replacen("|0|.......A|", "|0|R.......|", 1);
+        lines[11] = &changed;
+        write(&path, lines.join("\n")).unwrap();
+        let mut nes = powered_up(create_input_rom());
+        nes.play_movie(&path).unwrap();
+        let error = (0..FRAMES).find_map(|_| nes.run_frame().err()).unwrap();
+        // The ROM reads the controller as the frame ends, so the change shows in the next one
+        match error.downcast_ref::<MovieError>() {
+            Some(MovieError::Desync { frame: 9 }) => {}
+            _ => panic!("Unexpected {}", error),
+        }
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn it_should_assert_the_cpu_irq_from_the_mapper_scanline_counter() {
+        let mut nes = Nes::with_mapper(Box::new(Mmc3::new(vec![0; 0x8000], vec![]).unwrap()));
+        {
+            let mut ram = nes.ram.borrow_mut();
+            ram.set(0xc000, 1);
+            ram.set(0xc001, 0);
+            ram.set(0xe001, 0);
+        }
+        nes.on_scanline();
+        nes.on_scanline();
+        assert!(!nes.cpu.is_irq_asserted());
+        nes.ram.borrow_mut().set(0x2001, 0x08);
+        nes.on_scanline();
+        assert!(!nes.cpu.is_irq_asserted());
+        nes.on_scanline();
+        assert!(nes.cpu.is_irq_asserted());
+        nes.ram.borrow_mut().set(0xe000, 0);
+        nes.execute().unwrap();
+        assert!(!nes.cpu.is_irq_asserted());
+    }
+
+    #[test]
+    fn it_should_assert_the_cpu_irq_when_a_dmc_sample_ends() {
+        let mut rom = [0; ROM_SIZE];
+        rom[..0x0d].copy_from_slice(&[
+            0xa9, 0x8f, // LDA #$8F
+            0x8d, 0x10, 0x40, // STA $4010
+            0xa9, 0x10, // LDA #$10
+            0x8d, 0x15, 0x40, // STA $4015
+            0x8d, 0x15, 0x40, // STA $4015
+        ]);
+        rom[0x7ffc..0x7ffe].copy_from_slice(&[0x00, 0x80]);
+        let mut nes = powered_up(rom);
+        let cycles: Vec<u16> = (0..4).map(|_| nes.execute().unwrap()).collect();
+        // The one byte sample is fetched right after the write that enables it
+        assert_eq!(cycles, vec![2, 4, 2, 4 + 4]);
+        assert!(nes.cpu.is_irq_asserted());
+        assert_eq!(nes.ram.borrow().get(0x4015), 0x80);
+        nes.execute().unwrap();
+        assert!(!nes.cpu.is_irq_asserted());
+        assert!(nes.audio_sample() == 0.0);
+    }
 }
@@ -7,8 +7,8 @@ pub(crate) struct AddressRegister {
 }
 
 /**
- * See page 34 asnd 35 of https://nesdev.com/NESDoc.pdf
- * This is for registers 2003, 2005 and 2006.
+ * See page 34 and 35 of https://nesdev.com/NESDoc.pdf
+ * This is for register 2003.
  */
 impl AddressRegister {
     pub(crate) fn new() -> AddressRegister {
@@ -6,10 +6,16 @@ mod register_2002;
 mod register_2004;
 mod register_2007;
 mod register_4014;
+mod scroll_register;
 mod video_ram;
 
 pub(crate) type SpriteMemory = [u8; 256];
 
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+// One palette index per pixel, row by row
+pub type Framebuffer = [u8; SCREEN_WIDTH * SCREEN_HEIGHT];
+
 pub(crate) enum SpriteMode {
     EightEight,
     EightSixteen,
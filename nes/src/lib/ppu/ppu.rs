@@ -5,12 +5,21 @@ use ppu::register_2002::{Register2002, Register2002Connector};
 use ppu::register_2004::{Register2004, Register2004Connector};
 use ppu::register_2007::{Register2007, Register2007Connector};
 use ppu::register_4014::{Register4014, Register4014Connector};
+use ppu::scroll_register::{Register2005Connector, Register2006Connector, ScrollRegister};
 use ppu::video_ram::VideoRam;
-use ppu::SpriteMemory;
+use ppu::{Framebuffer, SpriteMemory, SCREEN_HEIGHT, SCREEN_WIDTH};
 use ram::Ram;
 use std::cell::RefCell;
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
+const PATTERN_TABLES_SIZE: u16 = 0x2000;
+const NAME_TABLES_ADDRESS: u16 = 0x2000;
+const NAME_TABLE_SIZE: u16 = 0x400;
+const ATTRIBUTE_TABLE_OFFSET: u16 = 0x3c0;
+const PALETTES_ADDRESS: u16 = 0x3f00;
+const TILES_PER_ROW: u16 = 32;
+
 pub struct Ppu {
     ram: Rc<RefCell<Ram>>,
     register2000: Rc<RefCell<Register2000>>,
@@ -18,12 +27,13 @@ pub struct Ppu {
     register2002: Rc<RefCell<Register2002>>,
     register2003: Rc<RefCell<AddressRegister>>,
     register2004: Rc<RefCell<Register2004>>,
-    register2005: Rc<RefCell<AddressRegister>>,
-    register2006: Rc<RefCell<AddressRegister>>,
+    scroll: Rc<RefCell<ScrollRegister>>,
     register2007: Rc<RefCell<Register2007>>,
     register4014: Rc<RefCell<Register4014>>,
     sprite_memory: Rc<RefCell<SpriteMemory>>,
     video_ram: Rc<RefCell<VideoRam>>,
+    framebuffer: Box<Framebuffer>,
+    nmi_callback: Option<Box<dyn FnMut()>>,
 }
 
 impl Ppu {
@@ -38,13 +48,8 @@ impl Ppu {
             &register2003,
             &sprite_memory,
         )));
-        let register2005 = Rc::new(RefCell::new(AddressRegister::new()));
-        let register2006 = Rc::new(RefCell::new(AddressRegister::new()));
-        let register2007 = Rc::new(RefCell::new(Register2007::new(
-            &register2005,
-            &register2006,
-            &video_ram,
-        )));
+        let scroll = Rc::new(RefCell::new(ScrollRegister::new()));
+        let register2007 = Rc::new(RefCell::new(Register2007::new(&scroll, &video_ram)));
         let register4014 = Rc::new(RefCell::new(Register4014::new(&ram, &sprite_memory)));
         Ppu::set_connectors(
             &ram,
@@ -53,8 +58,7 @@ impl Ppu {
             &register2002,
             &register2003,
             &register2004,
-            &register2005,
-            &register2006,
+            &scroll,
             &register2007,
             &register4014,
         );
@@ -65,14 +69,117 @@ impl Ppu {
             register2002,
             register2003,
             register2004,
-            register2005,
-            register2006,
+            scroll,
             register2007,
             register4014,
             sprite_memory,
             video_ram,
+            framebuffer: Box::new([0; SCREEN_WIDTH * SCREEN_HEIGHT]),
+            nmi_callback: None,
+        }
+    }
+
+    // Called when vblank starts with NMIs enabled in $2000
+    pub fn set_nmi_callback(&mut self, callback: Box<dyn FnMut()>) {
+        self.nmi_callback = Some(callback);
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    pub fn render_frame(&mut self) {
+        for y in 0..SCREEN_HEIGHT {
+            self.render_scanline(y);
+        }
+    }
+
+    // Only the background for now, scrolled by the two writes to $2005
+    pub fn render_scanline(&mut self, y: usize) {
+        let video_ram = self.video_ram.borrow();
+        let backdrop = video_ram.get(PALETTES_ADDRESS);
+        let row = &mut self.framebuffer[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH];
+        if !self.register2001.borrow().is_background_shown() {
+            for pixel in row.iter_mut() {
+                *pixel = backdrop;
+            }
+            return;
         }
+        let register2000 = self.register2000.borrow();
+        let name_table = u16::from(register2000.get_name_table());
+        let pattern_table = u16::from(register2000.get_background_pattern_table()) * 0x1000;
+        let scroll = self.scroll.borrow();
+        let ram = self.ram.borrow();
+        // Scrolling past the bottom edge goes on into the name table below
+        let y = scroll.get_coarse_y() * 8 + scroll.get_fine_y() + y as u16;
+        let name_table = name_table ^ (((y / SCREEN_HEIGHT as u16) & 0x01) << 1);
+        let y = y % SCREEN_HEIGHT as u16;
+        for (x, pixel) in row.iter_mut().enumerate() {
+            // And past the right edge into the one next to it
+            let x = scroll.get_coarse_x() * 8 + scroll.get_fine_x() + x as u16;
+            let name_table = name_table ^ (x / SCREEN_WIDTH as u16);
+            let x = x % SCREEN_WIDTH as u16;
+            let base = NAME_TABLES_ADDRESS + name_table * NAME_TABLE_SIZE;
+            let tile = u16::from(video_ram.get(base + (y / 8) * TILES_PER_ROW + x / 8));
+            let pattern = pattern_table + tile * 16 + y % 8;
+            let (low, high) = match ram.mapper {
+                Some(ref mapper) => (mapper.read_chr(pattern), mapper.read_chr(pattern + 8)),
+                None => (video_ram.get(pattern), video_ram.get(pattern + 8)),
+            };
+            let bit = 7 - x % 8;
+            let color = ((low >> bit) & 0x01) | (((high >> bit) & 0x01) << 1);
+            *pixel = if color == 0 {
+                backdrop
+            } else {
+                // Each attribute byte covers 4x4 tiles, two bits for each 2x2 of them
+                let attribute =
+                    video_ram.get(base + ATTRIBUTE_TABLE_OFFSET + (y / 32) * 8 + x / 32);
+                let shift = ((y / 16) % 2) * 4 + ((x / 16) % 2) * 2;
+                let palette = u16::from((attribute >> shift) & 0x03);
+                video_ram.get(PALETTES_ADDRESS + palette * 4 + u16::from(color))
+            };
+        }
+    }
+
+    // Cartridges without a mapper have their CHR ROM where the pattern tables are
+    pub(crate) fn load_chr_rom(&mut self, chr_rom: &[u8]) {
+        let mut video_ram = self.video_ram.borrow_mut();
+        for (address, byte) in (0..PATTERN_TABLES_SIZE).zip(chr_rom.iter()) {
+            video_ram.set(address, *byte);
+        }
+    }
+
+    pub(crate) fn start_vblank(&mut self) {
+        self.register2002.borrow_mut().set_vblank_is_occurring();
+        if self.register2000.borrow().is_nmi_enabled() {
+            if let Some(ref mut callback) = self.nmi_callback {
+                callback();
+            }
+        }
+    }
+
+    pub(crate) fn end_vblank(&mut self) {
+        self.register2002.borrow_mut().set_vblank_stopped();
+    }
+
+    // Copies the page written to $4014 into the sprite memory, if there was a write since the
+    // last call. The CPU is stalled meanwhile, so the caller adds the cycles
+    pub(crate) fn run_oam_dma(&mut self) -> bool {
+        let oam_address = self.register2003.borrow().value;
+        self.register4014.borrow_mut().transfer(oam_address)
     }
+
+    // Everything the frame is drawn from, as sprites aren't in the framebuffer yet
+    pub(crate) fn hash_frame<H: Hasher>(&self, state: &mut H) {
+        self.video_ram.borrow().hash(state);
+        state.write(&*self.sprite_memory.borrow());
+    }
+
+    pub(crate) fn is_rendering_enabled(&self) -> bool {
+        let register2001 = self.register2001.borrow();
+        register2001.is_background_shown() || register2001.are_sprites_shown()
+    }
+
     #[inline]
     fn set_connectors(
         ram: &Rc<RefCell<Ram>>,
@@ -81,20 +188,122 @@ impl Ppu {
         register2002: &Rc<RefCell<Register2002>>,
         register2003: &Rc<RefCell<AddressRegister>>,
         register2004: &Rc<RefCell<Register2004>>,
-        register2005: &Rc<RefCell<AddressRegister>>,
-        register2006: &Rc<RefCell<AddressRegister>>,
+        scroll: &Rc<RefCell<ScrollRegister>>,
         register2007: &Rc<RefCell<Register2007>>,
         register4014: &Rc<RefCell<Register4014>>,
     ) {
         let mut m = ram.borrow_mut();
         m.io_registers[0].device = Some(Box::new(Register2000Connector::new(register2000)));
         m.io_registers[1].device = Some(Box::new(Register2001Connector::new(register2001)));
-        m.io_registers[2].device = Some(Box::new(Register2002Connector::new(register2002)));
+        m.io_registers[2].device = Some(Box::new(Register2002Connector::new(register2002, scroll)));
         m.io_registers[3].device = Some(Box::new(AddressRegisterConnector::new(register2003)));
         m.io_registers[4].device = Some(Box::new(Register2004Connector::new(register2004)));
-        m.io_registers[5].device = Some(Box::new(AddressRegisterConnector::new(register2005)));
-        m.io_registers[6].device = Some(Box::new(AddressRegisterConnector::new(register2006)));
+        m.io_registers[5].device = Some(Box::new(Register2005Connector::new(scroll)));
+        m.io_registers[6].device = Some(Box::new(Register2006Connector::new(scroll)));
         m.io_registers[7].device = Some(Box::new(Register2007Connector::new(register2007)));
         m.io_registers[28].device = Some(Box::new(Register4014Connector::new(register4014)));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mos6502cpu::Memory;
+    use ppu::Ppu;
+    use ram::{Ram, ROM_SIZE};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    const BACKDROP: u8 = 0x0f;
+
+    // Tile 1 has a first row of four pixels of color 1 and four of color 2
+    fn create_ppu() -> Ppu {
+        let ppu = Ppu::new(Rc::new(RefCell::new(Ram::new([0; ROM_SIZE]))));
+        {
+            let mut video_ram = ppu.video_ram.borrow_mut();
+            video_ram.set(0x0010, 0xf0);
+            video_ram.set(0x0018, 0x0f);
+            video_ram.set(0x2000, 0x01);
+            video_ram.set(0x2002, 0x01);
+            // The second 2x2 tiles block of the first name table uses palette 1
+            video_ram.set(0x23c0, 0x04);
+            video_ram.set(0x2400, 0x01);
+            video_ram.set(0x3f00, BACKDROP);
+            video_ram.set(0x3f01, 0x21);
+            video_ram.set(0x3f02, 0x22);
+            video_ram.set(0x3f05, 0x31);
+            video_ram.set(0x3f06, 0x32);
+        }
+        ppu.register2001.borrow_mut().value = 0x08;
+        ppu
+    }
+
+    #[test]
+    fn it_should_render_the_background() {
+        let mut ppu = create_ppu();
+        ppu.render_frame();
+        let row = &ppu.framebuffer()[..24];
+        assert_eq!(row[..8], [0x21, 0x21, 0x21, 0x21, 0x22, 0x22, 0x22, 0x22]);
+        assert_eq!(row[8..16], [BACKDROP; 8]);
+        assert_eq!(
+            row[16..24],
+            [0x31, 0x31, 0x31, 0x31, 0x32, 0x32, 0x32, 0x32]
+        );
+        assert_eq!(ppu.framebuffer()[256..264], [BACKDROP; 8]);
+    }
+
+    #[test]
+    fn it_should_scroll_horizontally_into_the_next_name_table() {
+        let mut ppu = create_ppu();
+        ppu.ram.borrow_mut().set(0x2005, 4);
+        ppu.render_scanline(0);
+        let row = &ppu.framebuffer()[..256];
+        assert_eq!(row[..4], [0x22; 4]);
+        assert_eq!(row[4..12], [BACKDROP; 8]);
+        assert_eq!(row[12..16], [0x31; 4]);
+        assert_eq!(row[252..], [0x21; 4]);
+    }
+
+    #[test]
+    fn it_should_take_the_second_write_to_2005_as_the_vertical_scroll() {
+        let mut ppu = create_ppu();
+        ppu.video_ram.borrow_mut().set(0x2020, 0x01);
+        {
+            let mut ram = ppu.ram.borrow_mut();
+            ram.set(0x2005, 0x10);
+            // Reading $2002 starts over with the horizontal scroll
+            ram.get(0x2002);
+            ram.set(0x2005, 0x04);
+            ram.set(0x2005, 0x08);
+        }
+        assert_eq!(ppu.scroll.borrow().x, 0x04);
+        assert_eq!(ppu.scroll.borrow().y, 0x08);
+        ppu.render_scanline(0);
+        let row = &ppu.framebuffer()[..256];
+        assert_eq!(row[..4], [0x22; 4]);
+        assert_eq!(row[4..], [BACKDROP; 252][..]);
+    }
+
+    #[test]
+    fn it_should_only_draw_the_backdrop_when_the_background_is_hidden() {
+        let mut ppu = create_ppu();
+        ppu.register2001.borrow_mut().value = 0;
+        ppu.render_scanline(0);
+        assert_eq!(ppu.framebuffer()[..8], [BACKDROP; 8]);
+    }
+
+    #[test]
+    fn it_should_call_back_on_vblank_when_nmis_are_enabled() {
+        let mut ppu = create_ppu();
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        ppu.set_nmi_callback(Box::new(move || counter.set(counter.get() + 1)));
+        ppu.start_vblank();
+        assert_eq!(calls.get(), 0);
+        assert_eq!(ppu.register2002.borrow().value() & 0x80, 0x80);
+        ppu.end_vblank();
+        assert_eq!(ppu.register2002.borrow().value() & 0x80, 0);
+        ppu.register2000.borrow_mut().value = 0x80;
+        ppu.start_vblank();
+        assert_eq!(calls.get(), 1);
+    }
+}
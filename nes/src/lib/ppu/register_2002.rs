@@ -1,4 +1,5 @@
 use nes::InputOutputDevice;
+use ppu::scroll_register::ScrollRegister;
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -45,12 +46,17 @@ impl Register2002 {
 
 pub(crate) struct Register2002Connector {
     register: Rc<RefCell<Register2002>>,
+    scroll: Rc<RefCell<ScrollRegister>>,
 }
 
 impl Register2002Connector {
-    pub(crate) fn new(register: &Rc<RefCell<Register2002>>) -> Register2002Connector {
+    pub(crate) fn new(
+        register: &Rc<RefCell<Register2002>>,
+        scroll: &Rc<RefCell<ScrollRegister>>,
+    ) -> Register2002Connector {
         Register2002Connector {
             register: register.clone(),
+            scroll: scroll.clone(),
         }
     }
 }
@@ -58,6 +64,7 @@ impl Register2002Connector {
 impl InputOutputDevice for Register2002Connector {
     #[inline]
     fn read(&self) -> u8 {
+        (*self.scroll.borrow_mut()).reset_toggle();
         (*self.register.borrow()).value()
     }
     #[inline]
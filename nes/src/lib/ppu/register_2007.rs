@@ -1,17 +1,11 @@
 use nes::InputOutputDevice;
-use ppu::address_register::AddressRegister;
+use ppu::scroll_register::ScrollRegister;
 use ppu::video_ram::VideoRam;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-#[inline]
-fn two_bytes_to_word(high_byte: u8, low_byte: u8) -> u16 {
-    u16::from(high_byte) << 8 | u16::from(low_byte)
-}
-
 pub(crate) struct Register2007 {
-    register2005: Rc<RefCell<AddressRegister>>,
-    register2006: Rc<RefCell<AddressRegister>>,
+    scroll: Rc<RefCell<ScrollRegister>>,
     video_ram: Rc<RefCell<VideoRam>>,
 }
 
@@ -20,21 +14,17 @@ pub(crate) struct Register2007 {
  */
 impl Register2007 {
     pub(crate) fn new(
-        register2005: &Rc<RefCell<AddressRegister>>,
-        register2006: &Rc<RefCell<AddressRegister>>,
+        scroll: &Rc<RefCell<ScrollRegister>>,
         video_ram: &Rc<RefCell<VideoRam>>,
     ) -> Register2007 {
         Register2007 {
-            register2005: register2005.clone(),
-            register2006: register2006.clone(),
+            scroll: scroll.clone(),
             video_ram: video_ram.clone(),
         }
     }
     #[inline]
     fn get_address(&self) -> u16 {
-        let lb = (*self.register2005.borrow()).value;
-        let hb = (*self.register2006.borrow()).value;
-        two_bytes_to_word(hb, lb)
+        self.scroll.borrow().address
     }
 }
 
@@ -9,6 +9,7 @@ pub(crate) struct Register4014 {
     ram: Rc<RefCell<Ram>>,
     pub(crate) sprite_memory: Rc<RefCell<SpriteMemory>>,
     value: u8,
+    pending: bool,
 }
 
 /**
@@ -23,8 +24,26 @@ impl Register4014 {
             ram: ram.clone(),
             sprite_memory: sprite_memory.clone(),
             value: 0,
+            pending: false,
         }
     }
+
+    // The RAM is borrowed by the CPU while it writes to $4014, so the copy waits until it's done.
+    // It starts at the current OAM address and wraps around the sprite memory
+    pub(crate) fn transfer(&mut self, oam_address: u8) -> bool {
+        if !self.pending {
+            return false;
+        }
+        self.pending = false;
+        let page = u16::from(self.value) * 0x100;
+        let ram = self.ram.borrow();
+        let mut sprite_memory = self.sprite_memory.borrow_mut();
+        for i in 0..=0xff {
+            sprite_memory[usize::from(oam_address.wrapping_add(i))] =
+                ram.get(page.wrapping_add(u16::from(i)));
+        }
+        true
+    }
 }
 
 pub(crate) struct Register4014Connector {
@@ -37,14 +56,6 @@ impl Register4014Connector {
             register: register.clone(),
         }
     }
-    fn save_to_sprite_memory(&mut self, starting_address: u16) {
-        let mut register = self.register.borrow_mut();
-        for i in 0..256 {
-            let ram_index = starting_address.wrapping_add(i as u16);
-            let value = register.ram.borrow().get(ram_index);
-            (*register.sprite_memory.borrow_mut())[i] = value;
-        }
-    }
 }
 
 impl InputOutputDevice for Register4014Connector {
@@ -54,12 +65,9 @@ impl InputOutputDevice for Register4014Connector {
     }
     #[inline]
     fn write(&mut self, value: u8) -> u8 {
-        (*self.register.borrow_mut()).value = value;
-        // TODO: This should keep the CPU busy for 512 cycles.
-        // I'm not really sure how to express that right now. Possible ideas:
-        // 1. Let the user pass a possible delay to the execute_instruction method.
-        // 2. Make the Memory trait somehow express the delays in reading to it.
-        self.save_to_sprite_memory(u16::from(value).wrapping_mul(0x100));
+        let mut register = self.register.borrow_mut();
+        register.value = value;
+        register.pending = true;
         value
     }
 }
@@ -0,0 +1,115 @@
+use nes::InputOutputDevice;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+pub(crate) struct ScrollRegister {
+    pub(crate) x: u8,
+    pub(crate) y: u8,
+    pub(crate) address: u16,
+    second_write: bool,
+}
+
+/**
+ * See page 34 and 35 of https://nesdev.com/NESDoc.pdf
+ * This is for registers 2005 and 2006. Both take two writes, the same toggle says which one
+ * comes next and reading 2002 resets it.
+ */
+impl ScrollRegister {
+    pub(crate) fn new() -> ScrollRegister {
+        ScrollRegister {
+            x: 0,
+            y: 0,
+            address: 0,
+            second_write: false,
+        }
+    }
+    #[inline]
+    pub(crate) fn write_scroll(&mut self, value: u8) {
+        if self.second_write {
+            self.y = value;
+        } else {
+            self.x = value;
+        }
+        self.second_write = !self.second_write;
+    }
+    // High byte first, the address space of the PPU is 14 bits wide
+    #[inline]
+    pub(crate) fn write_address(&mut self, value: u8) {
+        self.address = if self.second_write {
+            (self.address & 0xff00) | u16::from(value)
+        } else {
+            (u16::from(value & 0x3f) << 8) | (self.address & 0x00ff)
+        };
+        self.second_write = !self.second_write;
+    }
+    #[inline]
+    pub(crate) fn reset_toggle(&mut self) {
+        self.second_write = false;
+    }
+    #[inline]
+    pub(crate) fn get_coarse_x(&self) -> u16 {
+        u16::from(self.x >> 3)
+    }
+    #[inline]
+    pub(crate) fn get_fine_x(&self) -> u16 {
+        u16::from(self.x & 0x07)
+    }
+    #[inline]
+    pub(crate) fn get_coarse_y(&self) -> u16 {
+        u16::from(self.y >> 3)
+    }
+    #[inline]
+    pub(crate) fn get_fine_y(&self) -> u16 {
+        u16::from(self.y & 0x07)
+    }
+}
+
+pub(crate) struct Register2005Connector {
+    register: Rc<RefCell<ScrollRegister>>,
+}
+
+impl Register2005Connector {
+    pub(crate) fn new(register: &Rc<RefCell<ScrollRegister>>) -> Register2005Connector {
+        Register2005Connector {
+            register: register.clone(),
+        }
+    }
+}
+
+impl InputOutputDevice for Register2005Connector {
+    // Write only
+    #[inline]
+    fn read(&self) -> u8 {
+        0
+    }
+    #[inline]
+    fn write(&mut self, value: u8) -> u8 {
+        (*self.register.borrow_mut()).write_scroll(value);
+        value
+    }
+}
+
+pub(crate) struct Register2006Connector {
+    register: Rc<RefCell<ScrollRegister>>,
+}
+
+impl Register2006Connector {
+    pub(crate) fn new(register: &Rc<RefCell<ScrollRegister>>) -> Register2006Connector {
+        Register2006Connector {
+            register: register.clone(),
+        }
+    }
+}
+
+impl InputOutputDevice for Register2006Connector {
+    // Write only
+    #[inline]
+    fn read(&self) -> u8 {
+        0
+    }
+    #[inline]
+    fn write(&mut self, value: u8) -> u8 {
+        (*self.register.borrow_mut()).write_address(value);
+        value
+    }
+}
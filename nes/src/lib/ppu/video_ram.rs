@@ -1,3 +1,5 @@
+use std::hash::{Hash, Hasher};
+
 pub(crate) struct VideoRam {
     pattern_tables: [u8; 0x2000],
     name_tables: [u8; 0x1000],
@@ -70,6 +72,14 @@ impl VideoRam {
     }
 }
 
+impl Hash for VideoRam {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write(&self.pattern_tables);
+        state.write(&self.name_tables);
+        state.write(&self.palettes);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use ppu::video_ram::VideoRam;
@@ -1,5 +1,6 @@
 extern crate mos6502cpu;
 
+use mapper::Mapper;
 use mos6502cpu::{Memory, AVAILABLE_MEMORY};
 use nes::InputOutputDevice;
 
@@ -39,6 +40,7 @@ pub struct Ram {
     expansion_rom: [u8; 0x1E00],
     sram: [u8; 0x2000],
     rom: [u8; ROM_SIZE],
+    pub(crate) mapper: Option<Box<dyn Mapper>>,
 }
 
 impl Ram {
@@ -53,9 +55,16 @@ impl Ram {
             sram: [0; 0x2000],
             io_registers,
             rom,
+            mapper: None,
         }
     }
 
+    pub fn with_mapper(mapper: Box<dyn Mapper>) -> Ram {
+        let mut ram = Ram::new([0; ROM_SIZE]);
+        ram.mapper = Some(mapper);
+        ram
+    }
+
     fn set_in_ram(&mut self, index: u16, new_value: u8) {
         self.ram[index as usize % 0x800] = new_value;
     }
@@ -111,6 +120,8 @@ impl Memory for Ram {
             self.set_in_io(index, new_value);
         } else if index < 0x6000 {
             self.set_in_expansion_rom(index, new_value);
+        } else if let Some(ref mut mapper) = self.mapper {
+            mapper.write_prg(index, new_value);
         } else if index < 0x8000 {
             self.set_in_sram(index, new_value);
         }
@@ -122,6 +133,8 @@ impl Memory for Ram {
             self.get_from_io(index)
         } else if index < 0x6000 {
             self.get_from_expansion_rom(index)
+        } else if let Some(ref mapper) = self.mapper {
+            mapper.read_prg(index)
         } else if index < 0x8000 {
             self.get_from_sram(index)
         } else {
@@ -135,6 +148,7 @@ impl Memory for Ram {
 
 #[cfg(test)]
 mod tests {
+    use mapper::Mmc3;
     use mos6502cpu::Memory;
     use ram::{Ram, ROM_SIZE};
 
@@ -243,4 +257,15 @@ mod tests {
         let memory = Ram::new([0x42; ROM_SIZE]);
         assert_eq!(memory.get(0x8000), 0x42);
     }
+
+    #[test]
+    fn it_should_go_through_the_mapper() {
+        let mut prg_rom = vec![0; 0x8000];
+        prg_rom[0x6000] = 0x42;
+        let mut memory = Ram::with_mapper(Box::new(Mmc3::new(prg_rom, vec![]).unwrap()));
+        assert_eq!(memory.get(0xe000), 0x42);
+        memory.set(0x6000, 0x24);
+        assert_eq!(memory.get(0x6000), 0x24);
+        assert_eq!(memory.sram[0x0], 0x0);
+    }
 }
@@ -3,30 +3,137 @@ extern crate mos6502cpu;
 extern crate nes;
 
 use failure::Error;
-use nes::{Nes, ROM_SIZE};
+use nes::{InesRom, Nes};
 use std::env::args;
-use std::fs::File;
-use std::io::Read;
+use std::fs::read;
+use std::io::{stdin, BufRead};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
 
-const USAGE: &str = "Usage: nes [game file]";
+const USAGE: &str =
+    "Usage: nes [game file] [--record movie [--frames n] | --play movie | --zapper]";
+const DEFAULT_RECORDED_FRAMES: usize = 600;
+// Light gun games expect the zapper on the second port
+const ZAPPER_PORT: usize = 1;
 
-fn read_file(file_name: &str) -> std::io::Result<[u8; ROM_SIZE]> {
-    let mut f = File::open(file_name)?;
-    let mut memory = [0; ROM_SIZE];
-    f.read_exact(&mut memory)?;
-    Ok(memory)
+enum Mode {
+    Record { path: String, frames: usize },
+    Play { path: String },
+    Zapper,
 }
 
-fn start_game(game: &str) -> Result<(), Error> {
-    let rom = read_file(game)?;
-    let _nes = Nes::new(rom);
+// There's no window to take the mouse from, so the gun is driven from the standard input: "x y"
+// aims it at that pixel and "x y fire" also pulls the trigger until the next line
+fn parse_zapper_event(line: &str) -> Option<(usize, usize, bool)> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (x, y, pulled) = match words[..] {
+        [x, y] => (x, y, false),
+        [x, y, "fire"] => (x, y, true),
+        _ => return None,
+    };
+    Some((x.parse().ok()?, y.parse().ok()?, pulled))
+}
+
+fn read_zapper_events() -> Receiver<(usize, usize, bool)> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let input = stdin();
+        for line in input.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            match parse_zapper_event(&line) {
+                Some(event) => {
+                    if sender.send(event).is_err() {
+                        return;
+                    }
+                }
+                None => eprintln!("Invalid zapper event {:?}, expecting \"x y [fire]\"", line),
+            }
+        }
+    });
+    receiver
+}
+
+fn start_game(game: &str, mode: Option<Mode>) -> Result<(), Error> {
+    let rom = InesRom::new(&read(game)?)?;
+    let mut nes = Nes::new(rom)?;
+    match mode {
+        Some(Mode::Record { path, frames }) => {
+            nes.power_up()?;
+            nes.record_movie(&path)?;
+            for _ in 0..frames {
+                nes.run_frame()?;
+            }
+            nes.stop_movie()?;
+        }
+        Some(Mode::Play { path }) => {
+            nes.power_up()?;
+            nes.play_movie(&path)?;
+            while nes.is_playing_movie() {
+                nes.run_frame()?;
+            }
+            println!("Final frame hash {:016x}", nes.frame_hash());
+        }
+        Some(Mode::Zapper) => {
+            nes.power_up()?;
+            nes.connect_zapper(ZAPPER_PORT);
+            let events = read_zapper_events();
+            loop {
+                for (x, y, pulled) in events.try_iter() {
+                    nes.set_zapper_position(ZAPPER_PORT, x, y);
+                    nes.set_zapper_trigger(ZAPPER_PORT, pulled);
+                }
+                nes.run_frame()?;
+            }
+        }
+        None => {
+            nes.power_up()?;
+            nes.run()?;
+        }
+    }
     Ok(())
 }
 
+fn parse_mode(options: &[String]) -> Option<Mode> {
+    match options {
+        [] => None,
+        [flag, path] if flag == "--record" => Some(Mode::Record {
+            path: path.clone(),
+            frames: DEFAULT_RECORDED_FRAMES,
+        }),
+        [flag, path, frames_flag, frames] if flag == "--record" && frames_flag == "--frames" => {
+            Some(Mode::Record {
+                path: path.clone(),
+                frames: frames.parse().expect(USAGE),
+            })
+        }
+        [flag, path] if flag == "--play" => Some(Mode::Play { path: path.clone() }),
+        [flag] if flag == "--zapper" => Some(Mode::Zapper),
+        _ => panic!("{}", USAGE),
+    }
+}
+
 fn main() {
     let args: Vec<String> = args().collect();
-    if args.len() != 2 {
-        panic!(USAGE);
+    if args.len() < 2 {
+        panic!("{}", USAGE);
+    }
+    let mode = parse_mode(&args[2..]);
+    start_game(&args[1], mode).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use parse_zapper_event;
+
+    #[test]
+    fn it_should_parse_zapper_events() {
+        assert_eq!(parse_zapper_event("128 120"), Some((128, 120, false)));
+        assert_eq!(parse_zapper_event(" 3  4 fire "), Some((3, 4, true)));
+        assert_eq!(parse_zapper_event("3 fire"), None);
+        assert_eq!(parse_zapper_event("3 4 shoot"), None);
+        assert_eq!(parse_zapper_event("-3 4"), None);
     }
-    start_game(&args[1]).unwrap();
 }
@@ -0,0 +1,59 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use smoked::allocator::Allocator;
+use smoked::cpu::{CompoundValue, Location, Value, VM};
+use smoked::instruction::{Instruction, InstructionType};
+use smoked::memory::Memory;
+
+// Adds the counter to a global accumulator, so every iteration reads four globals
+fn sum_in_globals(iterations: i64) {
+    let rom = vec![
+        InstructionType::Constant(0),
+        InstructionType::SetGlobal(0),
+        InstructionType::Pop,
+        InstructionType::Constant(0),
+        InstructionType::SetGlobal(1),
+        InstructionType::Pop,
+        InstructionType::GetGlobal(0),
+        InstructionType::Constant(1),
+        InstructionType::Less,
+        InstructionType::JmpIfFalse(11),
+        InstructionType::GetGlobal(1),
+        InstructionType::GetGlobal(0),
+        InstructionType::Plus,
+        InstructionType::SetGlobal(1),
+        InstructionType::Pop,
+        InstructionType::GetGlobal(0),
+        InstructionType::Constant(2),
+        InstructionType::Plus,
+        InstructionType::SetGlobal(0),
+        InstructionType::Pop,
+        InstructionType::Loop(15),
+        InstructionType::GetGlobal(1),
+    ];
+    let constants = vec![
+        CompoundValue::SimpleValue(Value::Integer(0)),
+        CompoundValue::SimpleValue(Value::Integer(iterations)),
+        CompoundValue::SimpleValue(Value::Integer(1)),
+    ];
+    let rom = rom
+        .into_iter()
+        .map(|instruction_type| Instruction { instruction_type, location: 0 })
+        .collect();
+    let mut vm = VM::new(Allocator::new(16), vec![], vec![], Memory::new(16), vec![]);
+    let sum = vm
+        .append_and_run(rom, constants, vec![Location { address: 0, line: 0 }])
+        .unwrap();
+    assert_eq!(
+        sum,
+        Some(CompoundValue::SimpleValue(Value::Integer(iterations * (iterations - 1) / 2)))
+    );
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    c.bench_function("sum in globals 1000", |b| {
+        b.iter(|| sum_in_globals(black_box(1000)))
+    });
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+use failure::Error;
+use smoked::allocator::Allocator;
+use smoked::cpu::{CompoundValue, Location, Value, VM};
+use smoked::instruction::{Instruction, InstructionType};
+use smoked::memory::Memory;
+use smoked::scheduler::Scheduler;
+
+// Counts up to limit, giving the turn away after every step, and returns the count
+fn counter(limit: i64) -> VM {
+    let rom = vec![
+        InstructionType::Constant(0),
+        InstructionType::SetGlobal(0),
+        InstructionType::Pop,
+        InstructionType::GetGlobal(0),
+        InstructionType::Constant(2),
+        InstructionType::Less,
+        InstructionType::JmpIfFalse(7),
+        InstructionType::GetGlobal(0),
+        InstructionType::Constant(1),
+        InstructionType::Plus,
+        InstructionType::SetGlobal(0),
+        InstructionType::Pop,
+        InstructionType::Yield,
+        InstructionType::Loop(11),
+        InstructionType::GetGlobal(0),
+        InstructionType::Return,
+    ];
+    let constants = vec![
+        CompoundValue::SimpleValue(Value::Integer(0)),
+        CompoundValue::SimpleValue(Value::Integer(1)),
+        CompoundValue::SimpleValue(Value::Integer(limit)),
+    ];
+    let rom = rom
+        .into_iter()
+        .map(|instruction_type| Instruction {
+            instruction_type,
+            location: 0,
+        })
+        .collect();
+    let locations = vec![Location {
+        address: 0,
+        line: 0,
+    }];
+    let mut vm = VM::new(
+        Allocator::new(16),
+        constants,
+        locations,
+        Memory::new(16),
+        rom,
+    );
+    vm.start();
+    vm
+}
+
+// Runs three counters side by side, printing how far each got after every round
+fn main() -> Result<(), Error> {
+    let mut scheduler = Scheduler::new(vec![counter(3), counter(5), counter(8)]);
+    let mut round = 0;
+    while scheduler.round()? {
+        round += 1;
+        let states: Vec<&str> = scheduler
+            .vms()
+            .iter()
+            .map(|vm| if vm.is_done() { "done" } else { "running" })
+            .collect();
+        println!("Round {}: {}", round, states.join(", "));
+    }
+    for (index, vm) in scheduler.into_vms().iter().enumerate() {
+        println!("Program {} returned {:?}", index, vm.exit_value());
+    }
+    Ok(())
+}
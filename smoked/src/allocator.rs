@@ -15,6 +15,7 @@ pub enum AllocatorError {
     AddressAlreadyFreed { address: usize },
 }
 
+#[derive(Clone, Debug, PartialEq)]
 struct FreeChunks {
     free_chunks: Vec<(usize, usize)>,
 }
@@ -62,6 +63,7 @@ impl FreeChunks {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub struct Allocator {
     free_chunks: FreeChunks,
     allocated_spaces: HashMap<usize, usize>,
@@ -102,6 +104,13 @@ impl Allocator {
         self.allocated_spaces.get(&address).cloned()
     }
 
+    // Every address still allocated along with its size, in no particular order
+    pub fn allocations<'a>(&'a self) -> impl Iterator<Item = (usize, usize)> + 'a {
+        self.allocated_spaces
+            .iter()
+            .map(|(address, size)| (*address, *size))
+    }
+
     pub fn malloc_t<T, R: Iterator<Item = usize>>(
         &mut self,
         used_addresses: R,
@@ -114,27 +123,31 @@ impl Allocator {
         size: usize,
         used_addresses: R,
     ) -> Result<usize, AllocatorError> {
-        let free_memory = self.capacity - self.allocated_space;
-        if self.allocated_space > self.next_gc_pass || size > free_memory {
-            self.next_gc_pass += NEXT_GC_RATIO;
+        if self.should_collect(size) {
             self.collect_garbage(used_addresses)?;
         }
-        let free_memory = self.capacity - self.allocated_space;
-        if size > free_memory {
-            Err(AllocatorError::NotEnoughMemory { intended: size })
-        } else {
-            let space = self.free_chunks.find_suitable_chunk(size);
-            match space {
-                None => Err(AllocatorError::NotEnoughMemory { intended: size }),
-                Some((index, (from, to))) => {
-                    self.free_chunks.remove(index);
-                    if from + size < to {
-                        self.free_chunks.insert((from + size, to))?;
-                    }
-                    self.allocated_spaces.insert(from, size);
-                    self.allocated_space += size;
-                    Ok(from)
+        self.allocate(size)
+    }
+
+    // Either it's time for the periodic collection or there's no free chunk big enough, even if
+    // the free space adds up to size
+    pub fn should_collect(&self, size: usize) -> bool {
+        self.allocated_space > self.next_gc_pass
+            || self.free_chunks.find_suitable_chunk(size).is_none()
+    }
+
+    // Allocates without collecting garbage, for hosts that collect on their own
+    pub fn allocate(&mut self, size: usize) -> Result<usize, AllocatorError> {
+        match self.free_chunks.find_suitable_chunk(size) {
+            None => Err(AllocatorError::NotEnoughMemory { intended: size }),
+            Some((index, (from, to))) => {
+                self.free_chunks.remove(index);
+                if from + size < to {
+                    self.free_chunks.insert((from + size, to))?;
                 }
+                self.allocated_spaces.insert(from, size);
+                self.allocated_space += size;
+                Ok(from)
             }
         }
     }
@@ -163,10 +176,12 @@ impl Allocator {
         }
     }
 
-    fn collect_garbage<R: Iterator<Item = usize>>(
+    // Frees every allocation that isn't in used_addresses, which has to hold everything reachable
+    pub fn collect_garbage<R: Iterator<Item = usize>>(
         &mut self,
         used_addresses: R,
     ) -> Result<(), AllocatorError> {
+        self.next_gc_pass += NEXT_GC_RATIO;
         let in_use_set: HashSet<usize> = HashSet::from_iter(used_addresses);
         let reserved_set = HashSet::from_iter(self.allocated_spaces.keys().cloned());
         reserved_set
@@ -276,4 +291,19 @@ mod tests {
         allocator.next_gc_pass = 0;
         allocator.malloc(1, used_addresses.into_iter()).unwrap();
     }
+
+    #[test]
+    fn it_should_collect_garbage_when_the_free_space_is_fragmented() {
+        let mut allocator = Allocator::new(5);
+        let addresses: Vec<usize> = (0..5)
+            .map(|_| allocator.malloc(1, std::iter::empty()).unwrap())
+            .collect();
+        allocator.free(addresses[0]).unwrap();
+        allocator.free(addresses[2]).unwrap();
+        // Two bytes are free, but not next to each other until the second one is collected
+        let used_addresses = vec![addresses[3], addresses[4]];
+        let address = allocator.malloc(2, used_addresses.into_iter()).unwrap();
+        assert!(address < addresses[3]);
+        assert_eq!(allocator.allocated_space, 4);
+    }
 }
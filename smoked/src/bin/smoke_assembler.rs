@@ -2,10 +2,8 @@ use std::env::args;
 use std::fs::File;
 use std::io::prelude::*;
 use std::iter::Peekable;
-use std::mem::size_of;
 use std::str::FromStr;
-use smoked::cpu::{VALUE_SIZE, USIZE_SIZE};
-use smoked::serialize_type;
+use smoked::serde::{header, serialize_usize, ConstantDeclaration};
 
 const USAGE: &str = "Usage: smoke-assembler [input file] [output file]";
 #[derive(Debug)]
@@ -233,6 +231,7 @@ fn parse_constants<'a, I: Iterator<Item=Token<'a>>>(
     let mut memory = vec![];
     let mut bytes = vec![];
     let mut next_address = 0usize;
+    let mut declared = 0usize;
     if let Some(TokenType::Data) = lexems.peek().cloned().map(|t| t.token_type) {
         lexems.next();
         while let Some(TokenType::Constant(constant)) = lexems.peek().cloned().map(|t| t.token_type) {
@@ -241,11 +240,11 @@ fn parse_constants<'a, I: Iterator<Item=Token<'a>>>(
                 Constant::Nil => bytes.push(0),
                 Constant::Integer(i) => {
                     bytes.push(1);
-                    serialize_type!(bytes, i, i64);
+                    bytes.extend_from_slice(&i.to_le_bytes());
                 },
                 Constant::Float(f) => {
                     bytes.push(2);
-                    serialize_type!(bytes, f, f32);
+                    bytes.extend_from_slice(&f.to_le_bytes());
                 },
                 Constant::Bool(b) => {
                     bytes.push(3);
@@ -253,38 +252,37 @@ fn parse_constants<'a, I: Iterator<Item=Token<'a>>>(
                 }
                 Constant::String(s) => {
                     bytes.push(4);
-                    serialize_type!(bytes, next_address, usize);
+                    serialize_usize(&mut bytes, next_address);
                     memory.extend_from_slice(s.as_bytes());
                     next_address += s.len();
                 }
                 Constant::Function { ip, arity } => {
                     bytes.push(5);
-                    serialize_type!(bytes, ip, usize);
-                    serialize_type!(bytes, arity, usize);
+                    serialize_usize(&mut bytes, ip);
+                    serialize_usize(&mut bytes, arity);
+                    // Neither uplifts nor defaults
+                    bytes.push(0);
                     bytes.push(0);
                 }
+                // The memory only holds strings, arrays are made of the constants right before
+                // them and objects of the name and value pairs right before them
                 Constant::Array { capacity } => {
-                    bytes.push(6);
-                    let capacity = VALUE_SIZE * capacity;
-                    serialize_type!(bytes, capacity, usize);
-                    for _ in 0..capacity {
-                        memory.push(0)
-                    }
+                    let first = declared.checked_sub(capacity).expect("Not enough constants for the array");
+                    let declaration = ConstantDeclaration::Array((first..declared).collect());
+                    bytes.extend_from_slice(&Vec::<u8>::from(declaration));
                 }
                 Constant::Object { capacity } => {
-                    bytes.push(7);
-                    serialize_type!(memory, capacity, usize);
-                    let capacity = (USIZE_SIZE + VALUE_SIZE) * capacity;
-                    serialize_type!(bytes, capacity, usize);
-                    for _ in 0..capacity {
-                        memory.push(0)
-                    }
+                    let first = declared.checked_sub(capacity * 2).expect("Not enough constants for the object");
+                    let properties = (first..declared).step_by(2).map(|key| (key, key + 1)).collect();
+                    let declaration = ConstantDeclaration::Object(properties);
+                    bytes.extend_from_slice(&Vec::<u8>::from(declaration));
                 }
             }
+            declared += 1;
         }
     }
     bytes.push(4);
-    serialize_type!(bytes, next_address, usize);
+    serialize_usize(&mut bytes, next_address);
     memory.extend_from_slice(file_name.as_bytes());
     (memory, bytes)
 }
@@ -333,18 +331,31 @@ fn parse_instructions<'a, I: Iterator<Item=Token<'a>>>(lexems: &mut Peekable<I>)
                         "AND" => upcodes.push(32),
                         "OR" => upcodes.push(33),
                         "ABS" => upcodes.push(34),
+                        "CLOCK" => upcodes.push(51),
+                        "RANDOM" => upcodes.push(52),
+                        "CHECKED_PLUS" => upcodes.push(53),
+                        "CHECKED_MINUS" => upcodes.push(54),
+                        "CHECKED_MULT" => upcodes.push(55),
+                        "YIELD" => upcodes.push(56),
+                        "MOD" => upcodes.push(57),
+                        "BIT_AND" => upcodes.push(58),
+                        "BIT_OR" => upcodes.push(59),
+                        "BIT_XOR" => upcodes.push(60),
+                        "SHL" => upcodes.push(61),
+                        "SHR" => upcodes.push(62),
+                        "CALL_N" => upcodes.push(63),
                         "NOOP" => upcodes.push(255),
                         _ => panic!("Unexpected instruction {}", i),
                     };
                     if let Some(TokenType::Number(n)) = lexems.peek().cloned().map(|t| t.token_type) {
                         lexems.next();
-                        serialize_type!(upcodes, n, usize);
+                        serialize_usize(&mut upcodes, n);
                     }
                     if !instructions.last().map(|l| l == &token.location).unwrap_or(false) {
                         instructions.push(token.location);
                     }
                     let _location = instructions.len() - 1;
-                    serialize_type!(upcodes, _location, usize);
+                    serialize_usize(&mut upcodes, _location);
                 },
                 t => panic!("Unexpected token, {:?}, only instructions or tokens expected", t),
             }
@@ -371,15 +382,12 @@ fn main() {
     let mut lexems = lexer(&content).into_iter().peekable();
     let (memory, constants) = parse_constants(&mut lexems, &file_name);
     let (upcodes, locations) = parse_instructions(&mut lexems);
-    let mut output = vec![];
-    serialize_type!(output, constants.len(), usize);
-    serialize_type!(output, memory.len(), usize);
-    serialize_type!(output, locations.len(), usize);
+    let mut output = header(constants.len(), memory.len(), locations.len(), upcodes.len());
     output.extend_from_slice(&constants);
     output.extend_from_slice(&memory);
     for _line in locations {
-        serialize_type!(output, 0usize, usize);
-        serialize_type!(output, _line, usize);
+        serialize_usize(&mut output, 0);
+        serialize_usize(&mut output, _line);
     }
     output.extend_from_slice(&upcodes);
     output_file.write_all(&output).unwrap();
@@ -1,7 +1,7 @@
 use std::env::args;
 use std::fs::File;
 use std::io::prelude::*;
-use smoked::serde::from_bytes;
+use smoked::serde::{constant_table, from_bytes};
 use std::str::FromStr;
 
 const USAGE: &str = "Usage: smoked [-s] [-d] [input file]";
@@ -57,10 +57,13 @@ fn main() {
         .unwrap_or_else(|| Box::new(std::io::stdin()));
     let mut bytes = vec![];
     input_file.read_to_end(&mut bytes).unwrap();
-    let mut vm = from_bytes(bytes.as_ref(), conf.stack_size.clone());
+    let mut vm = from_bytes(bytes.as_ref(), conf.stack_size).unwrap();
     vm.debug = conf.show_instructions;
+    for warning in vm.type_warnings() {
+        eprintln!("Warning: {}", warning);
+    }
     if conf.debug {
-        eprintln!("Constants: {:?}", vm.constants);
+        eprint!("Constants:\n{}", constant_table(&bytes).unwrap());
         eprintln!("Instructions: {:?}", vm.rom);
         eprintln!("Locations: {:?}", vm.locations);
     }
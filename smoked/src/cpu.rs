@@ -1,18 +1,26 @@
 use std::borrow::BorrowMut;
 use crate::allocator::Allocator;
+use crate::host::Host;
 use crate::instruction::{Instruction, InstructionType};
 use crate::memory::Memory;
+use crate::serde::{
+    deserialize_usize, serialize_usize, SerdeError, DEFAULTS_VERSION, SERIALIZED_USIZE_SIZE,
+};
+#[cfg(feature = "profile-interp")]
+use crate::profile::{InstructionTimer, InterpProfile};
 use failure::Error;
 use failure::_core::fmt::Formatter;
 use sc::{syscall0, syscall1, syscall2, syscall3, syscall4, syscall5, syscall6};
 use std::cell::RefCell;
-use std::collections::{HashMap, BTreeSet};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
-use std::iter::FromIterator;
 
 pub(crate) const STACK_MAX: usize = 256;
+// Globals are a vector indexed by the operand, so a wild one can't make it allocate the world
+pub(crate) const GLOBALS_MAX: usize = 64 * 1024;
+pub const DEFAULT_ALLOCATION_LIMIT: usize = 16 * 1024 * 1024;
 pub const USIZE_SIZE: usize = std::mem::size_of::<usize>();
-const F32_SIZE: usize = std::mem::size_of::<f32>();
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -23,7 +31,8 @@ pub enum Value {
     Bool(bool),
     String(usize),
     Pointer(usize),
-    Function { ip: usize, arity: usize, uplifts: Option<usize> },
+    // defaults is an array with the values of the last parameters, for calls that leave them out
+    Function { ip: usize, arity: usize, uplifts: Option<usize>, defaults: Option<usize> },
     Array { capacity: usize, address: usize },
     Object { address: usize, tags: usize },
 }
@@ -34,73 +43,78 @@ pub enum CompoundValue {
     PartialFunction { function: Value, arguments: Vec<Value>, }
 }
 
-fn next_x_items<I: Iterator<Item=u8>>(iterator: &mut I, x: usize) -> Vec<u8> {
-    let mut result = vec![];
-    for _ in 0..x {
-        result.push(iterator.next().unwrap());
+fn next_byte<I: Iterator<Item=u8>>(iterator: &mut I) -> Result<u8, SerdeError> {
+    iterator.next().ok_or(SerdeError::UnexpectedEnd)
+}
+
+fn next_bytes<I: Iterator<Item=u8>, const N: usize>(iterator: &mut I) -> Result<[u8; N], SerdeError> {
+    let mut result = [0u8; N];
+    for byte in result.iter_mut() {
+        *byte = next_byte(iterator)?;
+    }
+    Ok(result)
+}
+
+fn next_usize<I: Iterator<Item=u8>>(iterator: &mut I) -> Result<usize, SerdeError> {
+    deserialize_usize(&next_bytes::<I, SERIALIZED_USIZE_SIZE>(iterator)?, 0)
+}
+
+// A flag byte, followed by the value when it isn't zero
+fn next_optional_usize<I: Iterator<Item=u8>>(iterator: &mut I) -> Result<Option<usize>, SerdeError> {
+    if next_byte(iterator)? == 0 {
+        Ok(None)
+    } else {
+        Ok(Some(next_usize(iterator)?))
     }
-    result
 }
 
-impl<I: Iterator<Item=u8>> From<&mut I> for Value {
-    fn from(bytes: &mut I) -> Self {
-        match bytes.next().unwrap() {
+fn serialize_optional_usize(bytes: &mut Vec<u8>, value: Option<usize>) {
+    if let Some(value) = value {
+        bytes.push(1);
+        serialize_usize(bytes, value);
+    } else {
+        bytes.push(0);
+    }
+}
+
+impl Value {
+    pub(crate) fn deserialize<I: Iterator<Item=u8>>(
+        bytes: &mut I,
+        version: u8,
+    ) -> Result<Value, SerdeError> {
+        Ok(match next_byte(bytes)? {
             0 => Value::Nil,
-            1 => {
-                let bytes = next_x_items(bytes, U64_SIZE);
-                let integer = *unsafe { (bytes.as_ptr() as *const i64).as_ref() }.unwrap();
-                Value::Integer(integer)
-            }
-            2 => {
-                let bytes = next_x_items(bytes, F32_SIZE);
-                let float = *unsafe { (bytes.as_ptr() as *const f32).as_ref() }.unwrap();
-                Value::Float(float)
-            }
+            1 => Value::Integer(i64::from_le_bytes(next_bytes(bytes)?)),
+            2 => Value::Float(f32::from_le_bytes(next_bytes(bytes)?)),
             3 => {
-                let bool = bytes.next().unwrap() != 0;
+                let bool = next_byte(bytes)? != 0;
                 Value::Bool(bool)
             }
-            4 => {
-                let bytes = next_x_items(bytes, USIZE_SIZE);
-                let address = * unsafe { (bytes.as_ptr() as *const usize).as_ref() }.unwrap();
-                Value::String(address)
-            }
+            4 => Value::String(next_usize(bytes)?),
             5 => {
-                let ip_bytes = next_x_items(bytes, USIZE_SIZE);
-                let ip = * unsafe { (ip_bytes.as_ptr() as *const usize).as_ref() }.unwrap();
-                let arity_bytes = next_x_items(bytes, USIZE_SIZE);
-                let arity = * unsafe { (arity_bytes.as_ptr() as *const usize).as_ref() }.unwrap();
-                let uplifts = if bytes.next().unwrap() == 0 {
+                let ip = next_usize(bytes)?;
+                let arity = next_usize(bytes)?;
+                let uplifts = next_optional_usize(bytes)?;
+                let defaults = if version < DEFAULTS_VERSION {
                     None
                 } else {
-                    let address_bytes = next_x_items(bytes, USIZE_SIZE);
-                    Some(
-                        * unsafe { (address_bytes.as_ptr() as *const usize).as_ref() }.unwrap()
-                    )
+                    next_optional_usize(bytes)?
                 };
-                Value::Function { arity, ip, uplifts }
+                Value::Function { arity, ip, uplifts, defaults }
             }
             6 => {
-                let capacity_bytes = next_x_items(bytes, USIZE_SIZE);
-                let capacity = * unsafe { (capacity_bytes.as_ptr() as *const usize).as_ref() }.unwrap();
-                let address_bytes = next_x_items(bytes, USIZE_SIZE);
-                let address = * unsafe { (address_bytes.as_ptr() as *const usize).as_ref() }.unwrap();
+                let capacity = next_usize(bytes)?;
+                let address = next_usize(bytes)?;
                 Value::Array { address, capacity }
             }
             7 => {
-                let address_bytes = next_x_items(bytes, USIZE_SIZE);
-                let address = * unsafe { (address_bytes.as_ptr() as *const usize).as_ref() }.unwrap();
-                let tags_bytes = next_x_items(bytes, USIZE_SIZE);
-                let tags = * unsafe { (tags_bytes.as_ptr() as *const usize).as_ref() }.unwrap();
+                let address = next_usize(bytes)?;
+                let tags = next_usize(bytes)?;
                 Value::Object { address, tags }
             }
-            8 => {
-                let address_bytes = next_x_items(bytes, USIZE_SIZE);
-                let address = * unsafe { (address_bytes.as_ptr() as *const usize).as_ref() }.unwrap();
-                Value::Pointer(address)
-            }
-            _ => unimplemented!()
-        }
+            8 => Value::Pointer(next_usize(bytes)?),
+            tag => return Err(SerdeError::UnknownValueTag { tag }),
+        })
     }
 }
 
@@ -123,41 +137,36 @@ impl Into<Vec<u8>> for Value {
             }
             Value::String(s) => {
                 ret.push(4);
-                ret.extend_from_slice(&s.to_le_bytes());
+                serialize_usize(&mut ret, s);
             }
-            Value::Function { ip, arity, uplifts } => {
+            Value::Function { ip, arity, uplifts, defaults } => {
                 ret.push(5);
-                ret.extend_from_slice(&ip.to_le_bytes());
-                ret.extend_from_slice(&arity.to_le_bytes());
-                if let Some(uplifts) = uplifts {
-                    ret.push(1);
-                    ret.extend_from_slice(&uplifts.to_le_bytes());
-                } else {
-                    ret.push(0);
-                }
+                serialize_usize(&mut ret, ip);
+                serialize_usize(&mut ret, arity);
+                serialize_optional_usize(&mut ret, uplifts);
+                serialize_optional_usize(&mut ret, defaults);
             }
             Value::Array { capacity, address } => {
                 ret.push(6);
-                ret.extend_from_slice(&capacity.to_le_bytes());
-                ret.extend_from_slice(&address.to_le_bytes())
+                serialize_usize(&mut ret, capacity);
+                serialize_usize(&mut ret, address)
             }
             Value::Object { address, tags } => {
                 ret.push(7);
-                ret.extend_from_slice(&address.to_le_bytes());
-                ret.extend_from_slice(&tags.to_le_bytes())
+                serialize_usize(&mut ret, address);
+                serialize_usize(&mut ret, tags)
             }
             Value::Pointer(address) => {
                 ret.push(8);
-                ret.extend_from_slice(&address.to_le_bytes())
+                serialize_usize(&mut ret, address)
             }
         }
         ret
     }
 }
 
-const U64_SIZE: usize = std::mem::size_of::<u64>();
 pub const VALUE_SIZE: usize = std::mem::size_of::<Value>();
-const COMPOUND_VALUE_SIZE: usize = std::mem::size_of::<CompoundValue>();
+pub(crate) const COMPOUND_VALUE_SIZE: usize = std::mem::size_of::<CompoundValue>();
 pub(crate) const NULL_VALUE: CompoundValue = CompoundValue::SimpleValue(Value::Nil);
 #[cfg(test)]
 const ZERO_VALUE: CompoundValue = CompoundValue::SimpleValue(Value::Integer(0));
@@ -187,6 +196,33 @@ impl Into<bool> for CompoundValue {
     }
 }
 
+fn relocate_function(value: Value, rom_offset: usize) -> Value {
+    match value {
+        Value::Function { ip, arity, uplifts, defaults } => {
+            Value::Function { ip: ip + rom_offset, arity, uplifts, defaults }
+        }
+        v => v,
+    }
+}
+
+fn relocate_constant(value: CompoundValue, rom_offset: usize) -> CompoundValue {
+    match value {
+        CompoundValue::SimpleValue(v) => CompoundValue::SimpleValue(relocate_function(v, rom_offset)),
+        CompoundValue::PartialFunction { function, arguments } => CompoundValue::PartialFunction {
+            function: relocate_function(function, rom_offset),
+            arguments,
+        },
+    }
+}
+
+// Why run_for gave the control back to the host
+#[derive(Clone, Debug, PartialEq)]
+pub enum RunStatus {
+    Finished(Option<CompoundValue>),
+    Yielded,
+    OutOfFuel,
+}
+
 #[derive(Debug, Fail, PartialEq)]
 pub enum VMErrorType {
     #[fail(display = "Trying to push to a full stack")]
@@ -194,13 +230,14 @@ pub enum VMErrorType {
     #[fail(display = "Trying to pop from an empty stack")]
     EmptyStack,
     #[fail(display = "Expected two numbers. Got {:?} and {:?}", 0, 1)]
-    ExpectedNumbers(CompoundValue, CompoundValue),
+    // Boxed so every VM result doesn't carry two compound values
+    ExpectedNumbers(Box<CompoundValue>, Box<CompoundValue>),
     #[fail(display = "Expected a number. Got {:?}", 0)]
     ExpectedNumber(CompoundValue),
     #[fail(display = "Expected String")]
     ExpectedString,
     #[fail(display = "Expected two Strings. Got {:?} and {:?}", 0, 1)]
-    ExpectedStrings(CompoundValue, CompoundValue),
+    ExpectedStrings(Box<CompoundValue>, Box<CompoundValue>),
     #[fail(display = "Expected a function. Got {:?}", 0)]
     ExpectedFunction(CompoundValue),
     #[fail(display = "Expected an array")]
@@ -217,6 +254,30 @@ pub enum VMErrorType {
     GlobalDoesntExist(usize),
     #[fail(display = "Property {} not in object", 0)]
     PropertyDoesntExist(String),
+    #[fail(display = "Trying to allocate {} bytes, over the limit of {}", requested, limit)]
+    AllocationTooLarge { requested: usize, limit: usize },
+    #[fail(display = "Expected a non negative capacity. Got {}", 0)]
+    NegativeCapacity(i64),
+    #[fail(display = "The program already finished")]
+    ProgramFinished,
+    #[fail(display = "Integer overflow")]
+    IntegerOverflow,
+    #[fail(display = "Division by zero")]
+    DivisionByZero,
+    #[fail(display = "Expected a non negative shift. Got {}", _0)]
+    NegativeShift(i64),
+    #[fail(display = "Local {} is outside of the stack", _0)]
+    InvalidLocal(usize),
+    #[fail(display = "Global {} is over the limit of {}", _0, _1)]
+    InvalidGlobal(usize, usize),
+    #[fail(display = "Jumping to instruction {}, outside of the program", target)]
+    InvalidJump { target: i128 },
+    #[fail(display = "Too many arguments for function call")]
+    TooManyArgumentsForFunction,
+    #[fail(display = "Syscalls take up to 6 arguments. Got {}", _0)]
+    TooManySyscallArguments(usize),
+    #[fail(display = "Pointer {} ends up pointing to itself", _0)]
+    PointerCycle(usize),
 }
 
 #[derive(Debug, Fail, PartialEq)]
@@ -232,6 +293,7 @@ impl Display for VMError {
     }
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) struct Frame {
     arity: usize,
     ip: usize,
@@ -248,10 +310,20 @@ pub struct VM {
     pub(crate) allocator: RefCell<Allocator>,
     pub(crate) memory: Memory,
     pub(crate) frames: Vec<Frame>,
-    pub(crate) globals: HashMap<usize, CompoundValue>,
+    // Indexed by the global number the compiler assigned, None until the first SetGlobal
+    pub(crate) globals: Vec<Option<CompoundValue>>,
     pub(crate) sp: usize,
     pub(crate) stack: [CompoundValue; STACK_MAX],
+    pub(crate) host: Host,
+    pub(crate) exit_value: Option<CompoundValue>,
+    // With leak tracking on, the location of the instruction behind every allocation
+    pub(crate) allocation_sites: Option<RefCell<HashMap<usize, Option<usize>>>>,
+    // Off while looking for leaks, so what the program drops stays allocated
+    pub(crate) garbage_collection: bool,
+    #[cfg(feature = "profile-interp")]
+    pub(crate) profile: InterpProfile,
     pub debug: bool,
+    pub allocation_limit: usize,
     pub constants: Vec<CompoundValue>,
     pub rom: Vec<Instruction>,
     pub locations: Vec<Location>,
@@ -268,10 +340,17 @@ impl VM {
         VM {
             allocator: RefCell::new(allocator),
             frames: vec![],
-            globals: HashMap::new(),
+            globals: Vec::new(),
             sp: 0,
             stack: [NULL_VALUE; STACK_MAX],
+            host: Host::new(),
+            exit_value: None,
+            allocation_sites: None,
+            garbage_collection: true,
+            #[cfg(feature = "profile-interp")]
+            profile: InterpProfile::new(),
             debug: false,
+            allocation_limit: DEFAULT_ALLOCATION_LIMIT,
             constants,
             locations,
             memory,
@@ -280,7 +359,7 @@ impl VM {
     }
 
     fn pop(&mut self) -> Result<CompoundValue, Error> {
-        if (self.sp - self.frames.last().unwrap().stack_offset) == 0 {
+        if self.sp <= self.stack_offset() {
             Err(self.create_error(VMErrorType::EmptyStack)?)?;
         }
         self.sp -= 1;
@@ -288,7 +367,7 @@ impl VM {
     }
 
     fn peek(&self) -> Result<CompoundValue, Error> {
-        if (self.sp - self.frames.last().unwrap().stack_offset) == 0 {
+        if self.sp <= self.stack_offset() {
             Err(self.create_error(VMErrorType::EmptyStack)?)?;
         }
         Ok(self.stack[self.sp - 1].clone())
@@ -307,17 +386,40 @@ impl VM {
         &self.stack[..self.sp]
     }
 
-    fn create_error(&self, error_type: VMErrorType) -> Result<VMError, Error> {
-        let location = self.rom[self.ip() - 1].location;
-        let file = self
+    // The location of the instruction before the ip, the one running
+    fn running_location(&self) -> Option<usize> {
+        self.ip()
+            .checked_sub(1)
+            .and_then(|ip| self.rom.get(ip))
+            .map(|instruction| instruction.location)
+    }
+
+    pub(crate) fn location_file(&self, location: &Location) -> Result<String, Error> {
+        Ok(self
             .memory
-            .get_string(
-                self.locations[location].address,
-                self.get_size(self.locations[location].address)?,
-            )?
-            .to_owned();
+            .get_string(location.address, self.get_size(location.address)?)?
+            .to_owned())
+    }
+
+    // Blames the instruction running. Without a location the error still gets out, just without a
+    // file and line
+    fn create_error(&self, error_type: VMErrorType) -> Result<VMError, Error> {
+        let location = self
+            .running_location()
+            .and_then(|index| self.locations.get(index));
+        let location = match location {
+            Some(location) => location,
+            None => {
+                return Ok(VMError {
+                    line: 0,
+                    error_type,
+                    file: String::new(),
+                })
+            }
+        };
+        let file = self.location_file(location)?;
         Ok(VMError {
-            line: self.locations[location].line,
+            line: location.line,
             error_type,
             file,
         })
@@ -343,9 +445,13 @@ impl VM {
         extra_arguments: Option<&[Value]>,
     ) -> Result<(), Error> {
         let arguments_length = extra_arguments.map_or(0, |args| args.len());
+        if arguments_length > arity {
+            Err(self.create_error(VMErrorType::TooManyArgumentsForFunction)?)?;
+        }
         if (self.sp + arguments_length) < arity {
             Err(self.create_error(VMErrorType::NotEnoughArgumentsForFunction)?)?;
         }
+        self.check_jump(ip as i128)?;
         self.new_frame(ip, arity - arguments_length);
         if let Some(arguments) = extra_arguments {
             for i in (arguments_length..arity).rev() {
@@ -384,17 +490,24 @@ impl VM {
         VM {
             constants: Vec::new(),
             debug: false,
+            allocation_limit: DEFAULT_ALLOCATION_LIMIT,
             frames: vec![Frame {
                 arity: 0,
                 ip: 1,
                 stack_offset: 0,
             }],
-            globals: HashMap::default(),
+            globals: Vec::new(),
             locations: vec![Location {
                 address: 0,
                 line: 0,
             }],
             stack: [ZERO_VALUE; STACK_MAX],
+            host: Host::new(),
+            exit_value: None,
+            allocation_sites: None,
+            garbage_collection: true,
+            #[cfg(feature = "profile-interp")]
+            profile: InterpProfile::new(),
             rom: vec![Instruction {
                 instruction_type: InstructionType::Noop,
                 location: 0,
@@ -410,15 +523,22 @@ impl VM {
             allocator: RefCell::new(Allocator::new(mem)),
             constants: Vec::new(),
             debug: false,
+            allocation_limit: DEFAULT_ALLOCATION_LIMIT,
             frames: vec![Frame {
                 arity: 0,
                 ip: 0,
                 stack_offset: 0,
             }],
-            globals: HashMap::default(),
+            globals: Vec::new(),
             locations: vec![],
             memory: Memory::new(mem),
             stack: [ZERO_VALUE; STACK_MAX],
+            host: Host::new(),
+            exit_value: None,
+            allocation_sites: None,
+            garbage_collection: true,
+            #[cfg(feature = "profile-interp")]
+            profile: InterpProfile::new(),
             rom: Vec::new(),
             sp,
         }
@@ -434,18 +554,25 @@ impl VM {
         VM {
             constants: Vec::new(),
             debug: false,
+            allocation_limit: DEFAULT_ALLOCATION_LIMIT,
             frames: vec![Frame {
                 arity: 0,
                 ip: 1,
                 stack_offset: 0,
             }],
-            globals: HashMap::default(),
+            globals: Vec::new(),
             locations: vec![Location { address, line: 0 }],
             rom: vec![Instruction {
                 instruction_type: InstructionType::Noop,
                 location: 0,
             }],
             stack: [ZERO_VALUE; STACK_MAX],
+            host: Host::new(),
+            exit_value: None,
+            allocation_sites: None,
+            garbage_collection: true,
+            #[cfg(feature = "profile-interp")]
+            profile: InterpProfile::new(),
             allocator,
             memory,
             sp,
@@ -542,15 +669,40 @@ macro_rules! logical_operation {
     };
 }
 
+// Integers wrap on overflow whatever the host was built with, the checked instructions fail instead
 macro_rules! math_operation {
-    ($self: ident, $op: tt, $location: expr) => {
+    ($self: ident, $op: tt, wrapping $integer_op: ident) => {
+        math_operation!($self, $op, |b: i64, a: i64| Ok(b.$integer_op(a)));
+    };
+    ($self: ident, $op: tt, checked $integer_op: ident) => {
+        math_operation!($self, $op, |b: i64, a: i64| b.$integer_op(a).ok_or(VMErrorType::IntegerOverflow));
+    };
+    ($self: ident, $op: tt, $integer_op: expr) => {
         match ($self.dereference_pop()?, $self.dereference_pop()?) {
-            (CompoundValue::SimpleValue(Value::Integer(a)), CompoundValue::SimpleValue(Value::Integer(b))) => $self.push(CompoundValue::SimpleValue(Value::Integer(b $op a))),
+            (CompoundValue::SimpleValue(Value::Integer(a)), CompoundValue::SimpleValue(Value::Integer(b))) => match ($integer_op)(b, a) {
+                Ok(result) => $self.push(CompoundValue::SimpleValue(Value::Integer(result))),
+                Err(error_type) => Err(Error::from($self.create_error(error_type)?)),
+            },
             (CompoundValue::SimpleValue(Value::Float(a)), CompoundValue::SimpleValue(Value::Integer(b))) => $self.push(CompoundValue::SimpleValue(Value::Float(b as f32 $op a))),
             (CompoundValue::SimpleValue(Value::Integer(a)), CompoundValue::SimpleValue(Value::Float(b))) => $self.push(CompoundValue::SimpleValue(Value::Float(b $op a as f32))),
             (CompoundValue::SimpleValue(Value::Float(a)), CompoundValue::SimpleValue(Value::Float(b))) => $self.push(CompoundValue::SimpleValue(Value::Float(b $op a))),
             (v1, v2) => {
-                Err(Error::from($self.create_error(VMErrorType::ExpectedNumbers(v1, v2))?))
+                Err(Error::from($self.create_error(VMErrorType::ExpectedNumbers(Box::new(v1), Box::new(v2)))?))
+            },
+        }?;
+    };
+}
+
+// Floats don't have bits to work with, so they fail like any other operand that isn't an integer
+macro_rules! integer_operation {
+    ($self: ident, $integer_op: expr) => {
+        match ($self.dereference_pop()?, $self.dereference_pop()?) {
+            (CompoundValue::SimpleValue(Value::Integer(a)), CompoundValue::SimpleValue(Value::Integer(b))) => match ($integer_op)(b, a) {
+                Ok(result) => $self.push(CompoundValue::SimpleValue(Value::Integer(result))),
+                Err(error_type) => Err(Error::from($self.create_error(error_type)?)),
+            },
+            (v1, v2) => {
+                Err(Error::from($self.create_error(VMErrorType::ExpectedNumbers(Box::new(v1), Box::new(v2)))?))
             },
         }?;
     };
@@ -558,9 +710,18 @@ macro_rules! math_operation {
 
 impl VM {
     pub fn execute(&mut self) -> Result<u8, Error> {
+        // There's no instruction left to blame, so the error has no location
+        if self.is_done() {
+            Err(VMErrorType::ProgramFinished)?;
+        }
         let ip = self.ip();
         self.increase_pc(1);
+        self.host.count_instruction();
+        #[cfg(feature = "profile-interp")]
+        let timer = InstructionTimer::start(&self.rom[ip].instruction_type);
         self.execute_instruction(self.rom[ip].clone())?;
+        #[cfg(feature = "profile-interp")]
+        self.profile.record(timer);
         Ok(0)
     }
 
@@ -569,20 +730,63 @@ impl VM {
             eprintln!("Instruction: {:?}\tStack: {:?}", instruction, self.stack());
         }
         match &instruction.instruction_type {
-            InstructionType::Noop => {}
+            // The host finds out by looking at the instruction, see run_for
+            InstructionType::Noop | InstructionType::Yield => {}
             InstructionType::Return => self.return_from_call()?,
             InstructionType::Constant(index) => self.constant(*index)?,
             InstructionType::Plus => {
-                math_operation!(self, +, instruction.location);
+                math_operation!(self, +, wrapping wrapping_add);
             }
             InstructionType::Minus => {
-                math_operation!(self, -, instruction.location);
+                math_operation!(self, -, wrapping wrapping_sub);
             }
             InstructionType::Mult => {
-                math_operation!(self, *, instruction.location);
+                math_operation!(self, *, wrapping wrapping_mul);
             }
             InstructionType::Div => {
-                math_operation!(self, /, instruction.location);
+                math_operation!(self, /, |b: i64, a: i64| match a {
+                    0 => Err(VMErrorType::DivisionByZero),
+                    a => Ok(b.wrapping_div(a)),
+                });
+            }
+            // The remainder takes the sign of the dividend, like in C
+            InstructionType::Mod => {
+                integer_operation!(self, |b: i64, a: i64| match a {
+                    0 => Err(VMErrorType::DivisionByZero),
+                    a => Ok(b.wrapping_rem(a)),
+                });
+            }
+            InstructionType::BitAnd => {
+                integer_operation!(self, |b: i64, a: i64| Ok(b & a));
+            }
+            InstructionType::BitOr => {
+                integer_operation!(self, |b: i64, a: i64| Ok(b | a));
+            }
+            InstructionType::BitXor => {
+                integer_operation!(self, |b: i64, a: i64| Ok(b ^ a));
+            }
+            // Shifting by 64 or more moves every bit out. Shr is arithmetic, so what's left is the sign
+            InstructionType::Shl => {
+                integer_operation!(self, |b: i64, a: i64| match a {
+                    a if a < 0 => Err(VMErrorType::NegativeShift(a)),
+                    a if a >= 64 => Ok(0),
+                    a => Ok(b << a),
+                });
+            }
+            InstructionType::Shr => {
+                integer_operation!(self, |b: i64, a: i64| match a {
+                    a if a < 0 => Err(VMErrorType::NegativeShift(a)),
+                    a => Ok(b >> a.min(63)),
+                });
+            }
+            InstructionType::CheckedPlus => {
+                math_operation!(self, +, checked checked_add);
+            }
+            InstructionType::CheckedMinus => {
+                math_operation!(self, -, checked checked_sub);
+            }
+            InstructionType::CheckedMult => {
+                math_operation!(self, *, checked checked_mul);
             }
             InstructionType::Nil => self.push(CompoundValue::SimpleValue(Value::Nil))?,
             InstructionType::True => self.push(CompoundValue::SimpleValue(Value::Bool(true)))?,
@@ -618,7 +822,7 @@ impl VM {
             InstructionType::Abs => {
                 let v = self.dereference_pop()?;
                 match v {
-                    CompoundValue::SimpleValue(Value::Integer(a)) => self.push(CompoundValue::SimpleValue(Value::Integer(a.abs())))?,
+                    CompoundValue::SimpleValue(Value::Integer(a)) => self.push(CompoundValue::SimpleValue(Value::Integer(a.wrapping_abs())))?,
                     CompoundValue::SimpleValue(Value::Float(a)) => self.push(CompoundValue::SimpleValue(Value::Float(a.abs())))?,
                     v => Err(self.create_error(VMErrorType::ExpectedNumber(v))?)?,
                 };
@@ -630,13 +834,10 @@ impl VM {
             InstructionType::GetLocal(g) => self.get_local(*g)?,
             InstructionType::SetLocal(g) => self.set_local(*g)?,
             InstructionType::JmpIfFalse(o) => self.jmp_if_false(*o)?,
-            InstructionType::Jmp(o) => {
-                self.add_to_ip(*o);
-            }
-            InstructionType::Loop(o) => {
-                self.frames.last_mut().unwrap().ip -= *o;
-            }
+            InstructionType::Jmp(o) => self.jump_forward(*o)?,
+            InstructionType::Loop(o) => self.loop_back(*o)?,
             InstructionType::Call => self.call()?,
+            InstructionType::CallN(arguments) => self.call_n(*arguments)?,
             InstructionType::ArrayAlloc => self.array_alloc()?,
             InstructionType::ArrayGet => self.array_get()?,
             InstructionType::ArraySet => self.array_set()?,
@@ -661,6 +862,8 @@ impl VM {
             InstructionType::ObjectMerge => self.object_merge()?,
             InstructionType::RemoveTag => self.remove_tag()?,
             InstructionType::Duplicate => self.duplicate()?,
+            InstructionType::Clock => self.clock()?,
+            InstructionType::Random => self.random()?,
         };
         Ok(())
     }
@@ -682,6 +885,95 @@ impl VM {
         Ok(())
     }
 
+    pub fn append_and_run(
+        &mut self,
+        rom_fragment: Vec<Instruction>,
+        constants_fragment: Vec<CompoundValue>,
+        locations_fragment: Vec<Location>,
+    ) -> Result<Option<CompoundValue>, Error> {
+        let rom_offset = self.rom.len();
+        let constants_offset = self.constants.len();
+        let locations_offset = self.locations.len();
+        self.constants.extend(
+            constants_fragment
+                .into_iter()
+                .map(|c| relocate_constant(c, rom_offset)),
+        );
+        self.locations.extend(locations_fragment);
+        self.rom.extend(rom_fragment.into_iter().map(|i| {
+            let instruction_type = match i.instruction_type {
+                InstructionType::Constant(index) => InstructionType::Constant(index + constants_offset),
+                instruction_type => instruction_type,
+            };
+            Instruction {
+                instruction_type,
+                location: i.location + locations_offset,
+            }
+        }));
+        let depth = self.frames.len();
+        let base_sp = self.sp;
+        self.exit_value = None;
+        self.new_frame(rom_offset, 0);
+        while self.frames.len() > depth && !self.is_done() {
+            if let Err(e) = self.execute() {
+                self.frames.truncate(depth);
+                self.sp = base_sp;
+                return Err(e);
+            }
+        }
+        self.frames.truncate(depth);
+        let result = if let Some(value) = self.exit_value.take() {
+            Some(value)
+        } else if self.sp > base_sp {
+            Some(self.stack[self.sp - 1].clone())
+        } else {
+            None
+        };
+        self.sp = base_sp;
+        Ok(result)
+    }
+
+    // Points the VM at the first instruction of its rom with an empty stack, like loading it from
+    // bytes does. For hosts that build it with new
+    pub fn start(&mut self) {
+        self.frames.clear();
+        self.sp = 0;
+        self.new_frame(0, 0);
+    }
+
+    // Runs until the program finishes, returning what the outermost frame returned. Yields don't
+    // stop it, there's nobody else to give the turn to
+    pub fn run(&mut self) -> Result<Option<CompoundValue>, Error> {
+        while !self.is_done() {
+            self.execute()?;
+        }
+        Ok(self.exit_value.clone())
+    }
+
+    // Runs at most fuel instructions, stopping early if the program yields or finishes. Calling it
+    // again picks up right after where it stopped
+    pub fn run_for(&mut self, fuel: usize) -> Result<RunStatus, Error> {
+        for _ in 0..fuel {
+            if self.is_done() {
+                break;
+            }
+            let yielding = self.rom[self.ip()].instruction_type == InstructionType::Yield;
+            self.execute()?;
+            if yielding && !self.is_done() {
+                return Ok(RunStatus::Yielded);
+            }
+        }
+        if self.is_done() {
+            Ok(RunStatus::Finished(self.exit_value.clone()))
+        } else {
+            Ok(RunStatus::OutOfFuel)
+        }
+    }
+
+    pub fn exit_value(&self) -> Option<&CompoundValue> {
+        self.exit_value.as_ref()
+    }
+
     #[inline]
     pub fn is_done(&self) -> bool {
         self.frames.is_empty() || self.ip() >= self.rom.len() as _
@@ -692,14 +984,50 @@ impl VM {
         self.add_to_ip(steps as _);
     }
 
+    // Zero once the last frame returned, there's nothing left to run then
     #[inline]
     fn ip(&self) -> usize {
-        self.frames.last().unwrap().ip
+        self.frames.last().map_or(0, |frame| frame.ip)
     }
 
+    // Where the locals of the current frame start
+    #[inline]
+    fn stack_offset(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.stack_offset)
+    }
+
+    // Stepping past the last instruction finishes the program
     #[inline]
     fn add_to_ip(&mut self, steps: usize) {
-        self.frames.last_mut().unwrap().ip += steps;
+        if let Some(frame) = self.frames.last_mut() {
+            frame.ip = frame.ip.saturating_add(steps);
+        }
+    }
+
+    // Wide enough that no offset overflows, the check below catches what's out of the rom
+    fn jump_forward(&mut self, offset: usize) -> Result<(), Error> {
+        self.jump_to(self.ip() as i128 + offset as i128)
+    }
+
+    fn loop_back(&mut self, offset: usize) -> Result<(), Error> {
+        self.jump_to(self.ip() as i128 - offset as i128)
+    }
+
+    // Landing right after the last instruction finishes the program, anything further is a bug
+    fn jump_to(&mut self, target: i128) -> Result<(), Error> {
+        self.check_jump(target)?;
+        if let Some(frame) = self.frames.last_mut() {
+            frame.ip = target as usize;
+        }
+        Ok(())
+    }
+
+    fn check_jump(&self, target: i128) -> Result<(), Error> {
+        if target < 0 || target > self.rom.len() as i128 {
+            Err(Error::from(self.create_error(VMErrorType::InvalidJump { target })?))
+        } else {
+            Ok(())
+        }
     }
 
     #[inline]
@@ -716,9 +1044,12 @@ impl VM {
     #[inline]
     fn return_from_call(&mut self) -> Result<(), Error> {
         let return_value = {
-            let previous_frame = self.frames.last().unwrap();
+            let previous_frame = match self.frames.last() {
+                Some(frame) => frame.clone(),
+                None => Err(VMErrorType::ProgramFinished)?,
+            };
             let pso = previous_frame.stack_offset;
-            let r = if self.sp - previous_frame.arity > previous_frame.stack_offset {
+            let r = if self.sp.saturating_sub(previous_frame.arity) > previous_frame.stack_offset {
                 Some(self.pop()?)
             } else {
                 None
@@ -726,10 +1057,12 @@ impl VM {
             self.sp = pso;
             r
         };
-        if let Some(return_value) = return_value {
+        self.frames.pop();
+        if self.frames.is_empty() {
+            self.exit_value = return_value;
+        } else if let Some(return_value) = return_value {
             self.push(return_value)?;
         }
-        self.frames.pop();
         Ok(())
     }
 
@@ -742,14 +1075,11 @@ impl VM {
                     string1.extend(string2);
                     string1
                 };
-                let address = self
-                    .allocator
-                    .borrow_mut()
-                    .malloc(result.len(), self.get_roots())?;
-                self.memory.copy_u8_vector(&result, address);
+                let address = self.malloc(result.len())?;
+                self.memory.copy_u8_vector(&result, address)?;
                 self.push(CompoundValue::SimpleValue(Value::String(address)))?;
             }
-            (v1, v2) => Err(self.create_error(VMErrorType::ExpectedStrings(v1, v2))?)?,
+            (v1, v2) => Err(self.create_error(VMErrorType::ExpectedStrings(Box::new(v1), Box::new(v2)))?)?,
         };
         Ok(())
     }
@@ -799,14 +1129,26 @@ impl VM {
                     self.pop_usize()?,
                 )
             },
-            _ => unreachable!(),
+            _ => Err(self.create_error(VMErrorType::TooManySyscallArguments(arguments))?)?,
         };
         self.push(CompoundValue::SimpleValue(Value::Integer(ret as _)))?;
         Ok(())
     }
 
+    #[inline]
+    pub(crate) fn global(&self, global: usize) -> Option<&CompoundValue> {
+        self.globals.get(global).and_then(Option::as_ref)
+    }
+
+    pub(crate) fn store_global(&mut self, global: usize, value: CompoundValue) {
+        if global >= self.globals.len() {
+            self.globals.resize(global + 1, None);
+        }
+        self.globals[global] = Some(value);
+    }
+
     fn get_global(&mut self, global: usize) -> Result<(), Error> {
-        match self.globals.get(&global).cloned() {
+        match self.global(global).cloned() {
             None => {
                 Err(self.create_error(VMErrorType::GlobalDoesntExist(global))?)?;
             }
@@ -817,38 +1159,53 @@ impl VM {
 
     fn set_global(&mut self, global: usize) -> Result<(), Error> {
         let value = self.dereference_pop()?;
-        if let Some(CompoundValue::SimpleValue(Value::Pointer(address))) = self.globals.get(&global) {
+        if let Some(CompoundValue::SimpleValue(Value::Pointer(address))) = self.global(global) {
             let address = *address;
-            self.memory.copy_t(&self.peek()?, address);
+            self.memory.copy_t(&self.peek()?, address)?;
             self.push(CompoundValue::SimpleValue(Value::Pointer(address)))?;
+        } else if global >= GLOBALS_MAX {
+            Err(self.create_error(VMErrorType::InvalidGlobal(global, GLOBALS_MAX))?)?;
         } else {
-            self.globals.insert(global, value.clone());
+            self.store_global(global, value.clone());
             self.push(value)?;
         }
         Ok(())
     }
 
+    // Where the local lives in the stack, if it fits
+    fn local_index(&self, local: usize) -> Result<usize, Error> {
+        match self.stack_offset().checked_add(local) {
+            Some(index) if index < STACK_MAX => Ok(index),
+            _ => Err(Error::from(self.create_error(VMErrorType::InvalidLocal(local))?)),
+        }
+    }
+
     fn get_local(&mut self, local: usize) -> Result<(), Error> {
-        self.push(self.stack()[self.frames.last().unwrap().stack_offset + local].clone())?;
+        let index = self.local_index(local)?;
+        match self.stack().get(index).cloned() {
+            Some(value) => self.push(value)?,
+            None => Err(self.create_error(VMErrorType::InvalidLocal(local))?)?,
+        }
         Ok(())
     }
 
     fn set_local(&mut self, local: usize) -> Result<(), Error> {
+        let index = self.local_index(local)?;
         let value = self.pop()?;
-        if self.sp - self.frames.last().unwrap().stack_offset == 0 {
-            self.sp += local+1;
+        if self.sp == self.stack_offset() {
+            self.sp = index + 1;
         }
-        if let CompoundValue::SimpleValue(Value::Pointer(address)) = self.stack[self.frames.last().unwrap().stack_offset + local] {
+        if let CompoundValue::SimpleValue(Value::Pointer(address)) = self.stack[index] {
             if let CompoundValue::SimpleValue(Value::Pointer(_)) = &value {
-                self.memory.copy_t(&self.dereference_pointer(value)?, address);
+                self.memory.copy_t(&self.dereference_pointer(value)?, address)?;
             } else {
-                self.memory.copy_t(&value, address);
+                self.memory.copy_t(&value, address)?;
             }
             self.push(CompoundValue::SimpleValue(Value::Pointer(address)))?;
         } else {
-            self.stack[self.frames.last().unwrap().stack_offset + local] = value.clone();
-            if self.frames.last().unwrap().stack_offset + local >= self.sp {
-                self.sp += (self.frames.last().unwrap().stack_offset + local + 1) - self.sp;
+            self.stack[index] = value.clone();
+            if index >= self.sp {
+                self.sp = index + 1;
             }
             self.push(value)?;
         }
@@ -856,53 +1213,59 @@ impl VM {
     }
 
     fn uplift(&mut self, local: usize) -> Result<(), Error> {
-        let value = self.stack[self.frames.last().unwrap().stack_offset + local].clone();
+        let index = self.local_index(local)?;
+        let value = self.stack[index].clone();
         if let CompoundValue::SimpleValue(Value::Pointer(_)) = value {
             self.push(value)?;
         } else {
-            let address = self.allocator.borrow_mut().malloc_t::<CompoundValue, _>(self.get_roots())?;
-            self.memory.copy_t(&value, address);
-            self.stack[self.frames.last().unwrap().stack_offset + local] = CompoundValue::SimpleValue(Value::Pointer(address));
+            let address = self.malloc(COMPOUND_VALUE_SIZE)?;
+            self.memory.copy_t(&value, address)?;
+            self.stack[index] = CompoundValue::SimpleValue(Value::Pointer(address));
             self.push(CompoundValue::SimpleValue(Value::Pointer(address)))?;
         }
         Ok(())
     }
 
     fn attach_array(&mut self, global: usize) -> Result<(), Error> {
-        let function = self.globals.get(&global).cloned();
-        if let None = function {
-            return Err(Error::from(self.create_error(VMErrorType::InvalidConstant(global))?));
-        }
-        if let Some(CompoundValue::SimpleValue(Value::Function { ip, arity, .. })) = function {
+        let function = match self.global(global).cloned() {
+            Some(function) => function,
+            None => return Err(Error::from(self.create_error(VMErrorType::InvalidConstant(global))?)),
+        };
+        if let CompoundValue::SimpleValue(Value::Function { ip, arity, defaults, .. }) = function {
             let address = if let CompoundValue::SimpleValue(Value::Array { address, .. }) = self.pop()? {
                 address
             } else {
                 return Err(Error::from(self.create_error(VMErrorType::ExpectedArray)?));
             };
-            let global_value = CompoundValue::SimpleValue(Value::Function { ip, arity, uplifts: Some(address) });
-            self.globals.insert(global, global_value.clone());
+            let global_value = CompoundValue::SimpleValue(Value::Function {
+                ip,
+                arity,
+                uplifts: Some(address),
+                defaults,
+            });
+            self.store_global(global, global_value.clone());
             self.push(global_value)?;
             Ok(())
         } else {
-            Err(Error::from(self.create_error(VMErrorType::ExpectedFunction(function.unwrap()))?))
+            Err(Error::from(self.create_error(VMErrorType::ExpectedFunction(function))?))
         }
     }
 
     fn jmp_if_false(&mut self, offset: usize) -> Result<(), Error> {
         let jmp_cond: bool = self.dereference_pop()?.into();
         if !jmp_cond {
-            self.add_to_ip(offset);
+            self.jump_forward(offset)?;
         }
         Ok(())
     }
 
     fn call(&mut self) -> Result<(), Error> {
         match self.dereference_pop()? {
-            CompoundValue::SimpleValue(Value::Function { ip, arity, uplifts }) => {
+            CompoundValue::SimpleValue(Value::Function { ip, arity, uplifts, .. }) => {
                 self.switch_context(ip, arity, uplifts, None)?;
             },
             CompoundValue::PartialFunction {
-                function: Value::Function { ip, arity, uplifts },
+                function: Value::Function { ip, arity, uplifts, .. },
                 arguments
             } => {
                 self.switch_context(ip, arity, uplifts, Some(&arguments))?;
@@ -917,13 +1280,53 @@ impl VM {
         Ok(())
     }
 
+    // A call that knows how many arguments were pushed, the ones missing at the end come from the
+    // defaults of the function
+    fn call_n(&mut self, pushed: usize) -> Result<(), Error> {
+        let function = self.dereference_pop()?;
+        let (arity, defaults, supplied) = match &function {
+            CompoundValue::SimpleValue(Value::Function { arity, defaults, .. }) => (*arity, *defaults, pushed),
+            CompoundValue::PartialFunction {
+                function: Value::Function { arity, defaults, .. },
+                arguments,
+            } => (*arity, *defaults, pushed + arguments.len()),
+            _ => (pushed, None, pushed),
+        };
+        if supplied > arity {
+            Err(self.create_error(VMErrorType::TooManyArgumentsForFunction)?)?;
+        }
+        self.push_defaults(arity, defaults, supplied)?;
+        self.push(function)?;
+        self.call()
+    }
+
+    fn push_defaults(&mut self, arity: usize, defaults: Option<usize>, supplied: usize) -> Result<(), Error> {
+        let (address, count) = match defaults {
+            Some(address) => (address, self.get_size(address)? / COMPOUND_VALUE_SIZE),
+            None => (0, 0),
+        };
+        let first_default = arity.saturating_sub(count);
+        if supplied < first_default {
+            Err(self.create_error(VMErrorType::NotEnoughArgumentsForFunction)?)?;
+        }
+        for parameter in supplied..arity {
+            let value = self
+                .memory
+                .get_t::<CompoundValue>(address + (parameter - first_default) * COMPOUND_VALUE_SIZE)?
+                .clone();
+            self.push(value)?;
+        }
+        Ok(())
+    }
+
     fn array_alloc(&mut self) -> Result<(), Error> {
         match self.dereference_pop()? {
+            CompoundValue::SimpleValue(Value::Integer(capacity)) if capacity < 0 => {
+                Err(self.create_error(VMErrorType::NegativeCapacity(capacity))?)?
+            }
             CompoundValue::SimpleValue(Value::Integer(capacity)) =>  {
-                let address = self
-                    .allocator
-                    .borrow_mut()
-                    .malloc(COMPOUND_VALUE_SIZE * capacity as usize, self.get_roots())?;
+                let size = self.allocation_size(capacity as usize, COMPOUND_VALUE_SIZE, 0)?;
+                let address = self.malloc(size)?;
                 self.push(CompoundValue::SimpleValue(Value::Array {
                     capacity: capacity as usize,
                     address,
@@ -942,10 +1345,8 @@ impl VM {
                 Err(self.create_error(VMErrorType::IndexOutOfRange)?)?
             }
             (CompoundValue::SimpleValue(Value::Array { address, .. }), CompoundValue::SimpleValue(Value::Integer(index))) => {
-                let v = self
-                    .memory
-                    .get_t::<CompoundValue>(address + index as usize * COMPOUND_VALUE_SIZE)?
-                    .clone();
+                let element = self.element_address(address, index as usize)?;
+                let v = self.memory.get_t::<CompoundValue>(element)?.clone();
                 self.push(v)?;
             }
             (CompoundValue::SimpleValue(Value::Array { .. }), v) => Err(self.create_error(VMErrorType::ExpectedNumber(v))?)?,
@@ -963,8 +1364,8 @@ impl VM {
             }
             (CompoundValue::SimpleValue(Value::Array { address, .. }), CompoundValue::SimpleValue(Value::Integer(index))) => {
                 let v = self.peek()?;
-                self.memory
-                    .copy_t::<CompoundValue>(&v, address + index as usize * COMPOUND_VALUE_SIZE);
+                let element = self.element_address(address, index as usize)?;
+                self.memory.copy_t::<CompoundValue>(&v, element)?;
             }
             (CompoundValue::SimpleValue(Value::Array { .. }), v) => Err(self.create_error(VMErrorType::ExpectedNumber(v))?)?,
             (_, _) => Err(self.create_error(VMErrorType::ExpectedArray)?)?,
@@ -972,6 +1373,17 @@ impl VM {
         Ok(())
     }
 
+    // An array value can come from a corrupted file, so its index can't be trusted to fit
+    fn element_address(&self, address: usize, index: usize) -> Result<usize, Error> {
+        match index
+            .checked_mul(COMPOUND_VALUE_SIZE)
+            .and_then(|offset| address.checked_add(offset))
+        {
+            Some(element) => Ok(element),
+            None => Err(Error::from(self.create_error(VMErrorType::IndexOutOfRange)?)),
+        }
+    }
+
     fn multi_array_set(&mut self) -> Result<(), Error> {
         match self.dereference_pop()? {
             CompoundValue::SimpleValue(Value::Array { address, capacity }) => {
@@ -980,7 +1392,7 @@ impl VM {
                     let v = self.pop()?;
                     vs.push(v);
                 }
-                self.memory.copy_t_slice(&vs, address);
+                self.memory.copy_t_slice(&vs, address)?;
                 self.push(CompoundValue::SimpleValue(Value::Array { address, capacity }))?;
             }
             _ => Err(self.create_error(VMErrorType::ExpectedArray)?)?,
@@ -991,9 +1403,14 @@ impl VM {
     fn repeated_array_set(&mut self) -> Result<(), Error> {
         match self.dereference_pop()? {
             CompoundValue::SimpleValue(Value::Array { address, capacity }) => {
+                // A capacity the allocation can't hold would only fill memory that isn't there
+                match capacity.checked_mul(COMPOUND_VALUE_SIZE) {
+                    Some(size) if size <= self.get_size(address)? => {}
+                    _ => Err(self.create_error(VMErrorType::IndexOutOfRange)?)?,
+                }
                 let v = self.pop()?;
                 let vs = vec![v].into_iter().cycle().take(capacity).collect::<Vec<CompoundValue>>();
-                self.memory.copy_t_slice(&vs, address);
+                self.memory.copy_t_slice(&vs, address)?;
                 self.push(CompoundValue::SimpleValue(Value::Array { address, capacity }))?;
             }
             _ => Err(self.create_error(VMErrorType::ExpectedArray)?)?,
@@ -1003,15 +1420,15 @@ impl VM {
 
     fn object_alloc(&mut self) -> Result<(), Error> {
         match self.dereference_pop()? {
+            CompoundValue::SimpleValue(Value::Integer(capacity)) if capacity < 0 => {
+                Err(self.create_error(VMErrorType::NegativeCapacity(capacity))?)?
+            }
             CompoundValue::SimpleValue(Value::Integer(capacity)) => {
-                let capacity = (VALUE_SIZE + USIZE_SIZE) * capacity as usize;
-                let size = capacity + USIZE_SIZE;
-                let address = self.allocator.borrow_mut().malloc(USIZE_SIZE, self.get_roots())?;
-                let props_address = self.allocator.borrow_mut().malloc(size, self.get_roots())?;
-                let tags = self.allocator.borrow_mut().malloc(USIZE_SIZE, self.get_roots())?;
-                self.memory.copy_t(&0usize, tags);
-                self.memory.copy_t(&0usize, props_address);
-                self.memory.copy_t(&props_address, address);
+                let size = self.allocation_size(capacity as usize, VALUE_SIZE + USIZE_SIZE, USIZE_SIZE)?;
+                let [address, props_address, tags] = self.malloc_all([USIZE_SIZE, size, USIZE_SIZE])?;
+                self.memory.copy_t(&0usize, tags)?;
+                self.memory.copy_t(&0usize, props_address)?;
+                self.memory.copy_t(&props_address, address)?;
                 self.push(CompoundValue::SimpleValue(Value::Object { address, tags }))?;
             }
             v => Err(self.create_error(VMErrorType::ExpectedNumber(v))?)?,
@@ -1028,14 +1445,9 @@ impl VM {
             CompoundValue::SimpleValue(Value::String(address)),
         ) = (self.dereference_pop()?, self.dereference_pop()?)
         {
-            let size = self
-                .allocator
-                .borrow()
-                .get_allocated_space(address)
-                .unwrap();
-            let property = self.memory.get_string(address, size)?;
+            let property = self.address_to_string(address)?;
             let bytes = self.get_properties(obj_address)?;
-            let i = match self.property_lookup(bytes, property) {
+            let i = match self.property_lookup(bytes, property)? {
                 Ok(i) => i,
                 Err(_) => {
                     Err(self.create_error(VMErrorType::PropertyDoesntExist(property.to_owned()))?)?
@@ -1071,31 +1483,29 @@ impl VM {
             let size = self.get_size(address)?;
             let property = self.memory.get_string(address, size)?;
             let bytes = self.get_properties(obj_prop_address)?;
-            let index = match self.property_lookup(bytes, property) {
+            let index = match self.property_lookup(bytes, property)? {
                 Ok(index) => index,
                 Err(index) => {
                     let object_length: usize = *self.memory.get_t(obj_address)?;
                     if capacity <= object_length {
                         self.allocator.borrow_mut().free(obj_address)?;
-                        obj_address = self.allocator.borrow_mut().malloc(
-                            USIZE_SIZE + capacity * 2 * (VALUE_SIZE + USIZE_SIZE),
-                            self.get_roots(),
-                        )?;
-                        self.memory.copy_t(&obj_address, obj_prop_address);
-                        self.memory.copy_t(&(object_length + 1), obj_address);
-                        self.memory.copy_t_slice(&bytes, obj_address + USIZE_SIZE);
+                        obj_address =
+                            self.malloc(USIZE_SIZE + capacity * 2 * (VALUE_SIZE + USIZE_SIZE))?;
+                        self.memory.copy_t(&obj_address, obj_prop_address)?;
+                        self.memory.copy_t(&(object_length + 1), obj_address)?;
+                        self.memory.copy_t_slice(bytes, obj_address + USIZE_SIZE)?;
                     }
                     for i in (index..bytes.len()).rev() {
                         self.memory.copy_t(
                             &bytes[i],
                             obj_address + USIZE_SIZE + (i + 1) * (VALUE_SIZE + USIZE_SIZE),
-                        );
+                        )?;
                     }
-                    self.memory.copy_t(&(object_length + 1), obj_address);
+                    self.memory.copy_t(&(object_length + 1), obj_address)?;
                     self.memory.copy_t(
                         &address,
                         obj_address + USIZE_SIZE + index * (VALUE_SIZE + USIZE_SIZE),
-                    );
+                    )?;
                     index
                 }
             };
@@ -1104,12 +1514,12 @@ impl VM {
                     self.memory.copy_t(
                         function,
                         obj_address + USIZE_SIZE * 2 + index * (VALUE_SIZE + USIZE_SIZE),
-                    ),
+                    )?,
                 CompoundValue::SimpleValue(value) =>
                     self.memory.copy_t(
                         value,
                         obj_address + USIZE_SIZE * 2 + index * (VALUE_SIZE + USIZE_SIZE),
-                    ),
+                    )?,
             }
             self.push(value)?;
             self.push(CompoundValue::SimpleValue(Value::Object {
@@ -1131,14 +1541,9 @@ impl VM {
             CompoundValue::SimpleValue(Value::String(address)),
         ) = (self.dereference_pop()?, self.dereference_pop()?)
         {
-            let size = self
-                .allocator
-                .borrow()
-                .get_allocated_space(address)
-                .unwrap();
-            let property = self.memory.get_string(address, size)?;
+            let property = self.address_to_string(address)?;
             let bytes = self.get_properties(obj_address)?;
-            let has_prop = self.property_lookup(bytes, property).is_ok();
+            let has_prop = self.property_lookup(bytes, property)?.is_ok();
             self.push(CompoundValue::SimpleValue(this))?;
             self.push(CompoundValue::SimpleValue(Value::Bool(has_prop)))?;
         } else {
@@ -1158,6 +1563,16 @@ impl VM {
         Ok(())
     }
 
+    fn clock(&mut self) -> Result<(), Error> {
+        let seconds = self.host.seconds() as f32;
+        self.push(CompoundValue::SimpleValue(Value::Float(seconds)))
+    }
+
+    fn random(&mut self) -> Result<(), Error> {
+        let value = self.host.next_random();
+        self.push(CompoundValue::SimpleValue(Value::Float(value)))
+    }
+
     fn duplicate(&mut self) -> Result<(), Error> {
         let last = self.peek()?;
         self.push(last)?;
@@ -1177,19 +1592,9 @@ impl VM {
         if let CompoundValue::SimpleValue(Value::String(address)) = v {
             self.push(CompoundValue::SimpleValue(Value::String(address)))?;
         } else {
-            let s = match v {
-                CompoundValue::SimpleValue(Value::Nil) => "nil".to_string(),
-                CompoundValue::SimpleValue(Value::Integer(i)) => i.to_string(),
-                CompoundValue::SimpleValue(Value::Bool(b)) => b.to_string(),
-                CompoundValue::SimpleValue(Value::Float(f)) => f.to_string(),
-                CompoundValue::SimpleValue(Value::Function { .. }) => "[function]".to_string(),
-                CompoundValue::SimpleValue(Value::Array { .. }) => "[array]".to_string(),
-                CompoundValue::SimpleValue(Value::Object { address, .. }) => format!("[object {}]", address),
-                CompoundValue::PartialFunction { .. } => "[partial function]".to_string(),
-                v => panic!("Cannot convert {:?} to string", v),
-            };
-            let a = self.allocator.borrow_mut().malloc(s.len(), self.get_roots())?;
-            self.memory.copy_u8_vector(s.as_bytes(), a);
+            let s = self.inspect(&v)?.to_string();
+            let a = self.malloc(s.len())?;
+            self.memory.copy_u8_vector(s.as_bytes(), a)?;
             self.push(CompoundValue::SimpleValue(Value::String(a)))?;
         }
         Ok(())
@@ -1202,7 +1607,8 @@ impl VM {
         ) = (self.dereference_pop()?, self.dereference_pop()?)
         {
             let tags = self.get_tags(tags)?;
-            match tags.binary_search(&string_address) {
+            let tag = self.address_to_string(string_address)?;
+            match self.tag_lookup(tags, tag)? {
                 Ok(_) => {
                     self.push(CompoundValue::SimpleValue(o))?;
                 },
@@ -1210,10 +1616,7 @@ impl VM {
                     let mut new_tags = tags[..index].to_vec();
                     new_tags.push(string_address);
                     new_tags.extend_from_slice(&tags[index..]);
-                    let new_tags_address = self.allocator
-                        .borrow_mut()
-                        .malloc(USIZE_SIZE * new_tags.len(), self.get_roots())?;
-                    self.memory.copy_t_slice(&new_tags, new_tags_address);
+                    let new_tags_address = self.allocate_tags(&new_tags)?;
                     self.push(CompoundValue::SimpleValue(
                         Value::Object { tags: new_tags_address, address }
                     ))?;
@@ -1231,7 +1634,8 @@ impl VM {
             CompoundValue::SimpleValue(Value::String(string_address)),
         ) = (self.dereference_pop()?, self.dereference_pop()?)
         {
-            match self.get_tags(tags)?.binary_search(&string_address) {
+            let tag = self.address_to_string(string_address)?;
+            match self.tag_lookup(self.get_tags(tags)?, tag)? {
                 Ok(_) => {
                     self.push(CompoundValue::SimpleValue(Value::Bool(true)))?;
                 },
@@ -1252,12 +1656,12 @@ impl VM {
         ) = (self.dereference_pop()?, self.dereference_pop()?)
         {
             let tags = self.get_tags(tags)?;
-            match tags.binary_search(&string_address) {
+            let tag = self.address_to_string(string_address)?;
+            match self.tag_lookup(tags, tag)? {
                 Ok(i) => {
-                    let length = tags.len() - 1;
-                    let new_tags = self.allocator.borrow_mut().malloc(length * USIZE_SIZE, self.get_roots())?;
-                    self.memory.copy_t_slice(&tags[0..i], new_tags);
-                    self.memory.copy_t_slice(&tags[i+1..], new_tags + i * USIZE_SIZE);
+                    let mut new_tags = tags[..i].to_vec();
+                    new_tags.extend_from_slice(&tags[i + 1..]);
+                    let new_tags = self.allocate_tags(&new_tags)?;
                     self.push(CompoundValue::SimpleValue(Value::Object {
                         address,
                         tags: new_tags
@@ -1283,14 +1687,14 @@ impl VM {
             let properties = self.merge_properties(first_properties, second_properties)?;
             let new_tags = self.merge_tags(first_tags, second_tags)?;
             let capacity = properties.len() * (VALUE_SIZE + USIZE_SIZE);
-            let props_address = self.allocator.borrow_mut().malloc(USIZE_SIZE + capacity, self.get_roots())?;
-            let address = self.allocator.borrow_mut().malloc(USIZE_SIZE, self.get_roots())?;
-            let tags_capacity = new_tags.len() * USIZE_SIZE;
-            let tags = self.allocator.borrow_mut().malloc(tags_capacity, self.get_roots())?;
-            self.memory.copy_t(&props_address, address);
-            self.memory.copy_t(&properties.len(), props_address);
-            self.memory.copy_t_slice(&properties, props_address + USIZE_SIZE);
-            self.memory.copy_t_slice(&new_tags, tags);
+            let tags_capacity = USIZE_SIZE + new_tags.len() * USIZE_SIZE;
+            let [props_address, address, tags] =
+                self.malloc_all([USIZE_SIZE + capacity, USIZE_SIZE, tags_capacity])?;
+            self.memory.copy_t(&props_address, address)?;
+            self.memory.copy_t(&properties.len(), props_address)?;
+            self.memory.copy_t_slice(&properties, props_address + USIZE_SIZE)?;
+            self.memory.copy_t(&new_tags.len(), tags)?;
+            self.memory.copy_t_slice(&new_tags, tags + USIZE_SIZE)?;
             self.push(CompoundValue::SimpleValue(Value::Object {
                 address,
                 tags,
@@ -1301,34 +1705,72 @@ impl VM {
         }
     }
 
-    fn get_properties(&self, obj_address: usize) -> Result<&[(usize, Value)], Error> {
+    pub(crate) fn get_properties(&self, obj_address: usize) -> Result<&[(usize, Value)], Error> {
         let props_address: usize = *self.memory.get_t(obj_address)?;
         let object_length: usize = *self.memory.get_t(props_address)?;
         Ok(self.memory.get_vector::<(usize, Value)>(
-            props_address + USIZE_SIZE,
-            object_length * (VALUE_SIZE + USIZE_SIZE),
+            props_address.saturating_add(USIZE_SIZE),
+            object_length.saturating_mul(VALUE_SIZE + USIZE_SIZE),
         )?)
     }
 
-    fn get_tags(&self, tags: usize) -> Result<&[usize], Error> {
-        let length = self.get_size(tags)?;
-        Ok(self.memory.get_vector::<usize>(tags, length)?)
+    // Like properties, tags start with how many there are, so a new object has none
+    pub(crate) fn get_tags(&self, tags: usize) -> Result<&[usize], Error> {
+        let length: usize = *self.memory.get_t(tags)?;
+        Ok(self.memory.get_vector::<usize>(
+            tags.saturating_add(USIZE_SIZE),
+            length.saturating_mul(USIZE_SIZE),
+        )?)
     }
 
-    fn property_lookup(&self, bytes: &[(usize, Value)], property: &str) -> Result<usize, usize> {
-        bytes.binary_search_by(|(curr_address, _)| {
-            let found_property = self.address_to_string(*curr_address).unwrap();
-            found_property.cmp(property)
-        })
+    fn allocate_tags(&self, tags: &[usize]) -> Result<usize, Error> {
+        let address = self.malloc(USIZE_SIZE + tags.len() * USIZE_SIZE)?;
+        self.memory.copy_t(&tags.len(), address)?;
+        self.memory.copy_t_slice(tags, address + USIZE_SIZE)?;
+        Ok(address)
+    }
+
+    fn property_lookup(
+        &self,
+        bytes: &[(usize, Value)],
+        property: &str,
+    ) -> Result<Result<usize, usize>, Error> {
+        self.string_lookup(bytes, |(address, _)| *address, property)
+    }
+
+    // Tags are kept sorted by their contents, so equal strings match wherever they live
+    fn tag_lookup(&self, tags: &[usize], tag: &str) -> Result<Result<usize, usize>, Error> {
+        self.string_lookup(tags, |address| *address, tag)
+    }
+
+    // A binary search by the contents of the strings the items point to. A string that can't be
+    // read ends the search with its error
+    fn string_lookup<T, F: Fn(&T) -> usize>(
+        &self,
+        items: &[T],
+        address_of: F,
+        target: &str,
+    ) -> Result<Result<usize, usize>, Error> {
+        let mut error = None;
+        let result = items.binary_search_by(|item| match self.address_to_string(address_of(item)) {
+            Ok(found) => found.cmp(target),
+            Err(e) => {
+                error.get_or_insert(e);
+                Ordering::Less
+            }
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(result),
+        }
     }
 
     fn create_object(&mut self, address: usize, tags: usize) -> Result<Value, Error> {
         let size = self.get_size(address)?;
-        let new_props_address = self.allocator.borrow_mut().malloc(size, self.get_roots())?;
+        let [new_props_address, new_address] = self.malloc_all([size, USIZE_SIZE])?;
         let object_bytes = self.memory.get_u8_vector(address, size)?;
-        self.memory.copy_u8_vector(object_bytes, new_props_address);
-        let new_address = self.allocator.borrow_mut().malloc(USIZE_SIZE, self.get_roots())?;
-        self.memory.copy_t(&new_props_address, new_address);
+        self.memory.copy_u8_vector(object_bytes, new_props_address)?;
+        self.memory.copy_t(&new_props_address, new_address)?;
         let this = Value::Object {
             address: new_address,
             tags,
@@ -1338,14 +1780,14 @@ impl VM {
 
     fn merge_tags(&self, first_tags: usize, second_tags: usize) -> Result<Vec<usize>, Error> {
         let first_tags = self.get_tags(first_tags)?;
-        let second_tags = self.get_tags(second_tags)?;
-        let first_tags_tree = BTreeSet::from_iter(first_tags.iter().cloned());
-        let second_tags_tree = BTreeSet::from_iter(second_tags.iter().cloned());
-        Ok(
-            first_tags_tree.union(&second_tags_tree)
-                .cloned()
-                .collect()
-        )
+        let mut merged = self.get_tags(second_tags)?.to_vec();
+        for tag_address in first_tags {
+            let tag = self.address_to_string(*tag_address)?;
+            if let Err(index) = self.tag_lookup(&merged, tag)? {
+                merged.insert(index, *tag_address);
+            }
+        }
+        Ok(merged)
     }
 
     fn merge_properties(
@@ -1395,11 +1837,69 @@ impl VM {
         Ok(merged_properties)
     }
 
-    fn address_to_string(&self, address: usize) -> Result<&str, Error> {
+    pub(crate) fn address_to_string(&self, address: usize) -> Result<&str, Error> {
         let found_length = self.get_size(address)?;
         Ok(self.memory.get_string(address, found_length)?)
     }
 
+    fn allocation_size(&self, capacity: usize, item_size: usize, header_size: usize) -> Result<usize, Error> {
+        match capacity.checked_mul(item_size).and_then(|size| size.checked_add(header_size)) {
+            Some(size) => Ok(size),
+            None => Err(Error::from(self.create_error(VMErrorType::AllocationTooLarge {
+                requested: usize::MAX,
+                limit: self.allocation_limit,
+            })?)),
+        }
+    }
+
+    fn malloc(&self, size: usize) -> Result<usize, Error> {
+        let [address] = self.malloc_all([size])?;
+        Ok(address)
+    }
+
+    // Collects garbage at most once for all the sizes, a collection between them would free the
+    // first ones, nothing points to them yet. The roots are found before borrowing the allocator,
+    // following uplifts needs the size of their arrays
+    fn malloc_all<const N: usize>(&self, sizes: [usize; N]) -> Result<[usize; N], Error> {
+        let mut total = 0usize;
+        for size in sizes.iter() {
+            if *size > self.allocation_limit {
+                Err(self.create_error(VMErrorType::AllocationTooLarge {
+                    requested: *size,
+                    limit: self.allocation_limit,
+                })?)?;
+            }
+            total = total.saturating_add(*size);
+        }
+        if self.garbage_collection && self.allocator.borrow().should_collect(total) {
+            self.collect_garbage()?;
+        }
+        let mut addresses = [0; N];
+        {
+            let mut allocator = self.allocator.borrow_mut();
+            for (address, size) in addresses.iter_mut().zip(sizes.iter()) {
+                *address = allocator.allocate(*size)?;
+            }
+        }
+        if let Some(ref sites) = self.allocation_sites {
+            let location = self.running_location();
+            sites
+                .borrow_mut()
+                .extend(addresses.iter().map(|address| (*address, location)));
+        }
+        Ok(addresses)
+    }
+
+    // Frees everything the program can't reach anymore, without waiting for the allocator to
+    // need the room
+    pub fn collect_garbage(&self) -> Result<(), Error> {
+        let roots: Vec<usize> = self.get_roots().collect();
+        self.allocator
+            .borrow_mut()
+            .collect_garbage(roots.into_iter())?;
+        Ok(())
+    }
+
     fn get_size(&self, address: usize) -> Result<usize, Error> {
         match self.allocator.borrow().get_allocated_space(address) {
             Some(ret) => Ok(ret),
@@ -1423,60 +1923,154 @@ impl VM {
         Ok(ret)
     }
 
-    fn get_roots<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+    // The whole stack, past its top too, an instruction can still be using what it just popped
+    pub(crate) fn get_roots<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
         self.stack
             .iter()
             .chain(self.constants.iter())
-            .chain(self.globals.values())
-            .filter_map(move |v| match v {
-                CompoundValue::SimpleValue(Value::String(address)) => Some(vec![*address]),
-                CompoundValue::SimpleValue(Value::Array { address, capacity }) => {
-                    Some(self.get_addresses_from_array(*address, *capacity))
-                }
-                CompoundValue::SimpleValue(Value::Object {address, tags }) =>
-                    Some(self.get_addresses_from_object(*address, *tags)),
-                _ => None,
-            })
-            .flatten()
+            .chain(self.globals.iter().flatten())
+            .chain(self.exit_value.iter())
+            .flat_map(move |v| self.get_addresses_from_compound_value(v))
     }
 
-    fn get_addresses_from_object(&self, address: usize, tags: usize) -> Vec<usize> {
-        let props_address: usize = *self.memory.get_t(address).unwrap();
-        let length: usize = *self.memory.get_t(props_address).unwrap();
-        let mut result = vec![address, props_address, tags];
-        let pairs = self.memory
-            .get_vector::<(usize, Value)>(props_address + USIZE_SIZE,length * (VALUE_SIZE + USIZE_SIZE))
-            .unwrap();
+    fn get_addresses_from_compound_value(&self, value: &CompoundValue) -> Vec<usize> {
+        match value {
+            CompoundValue::SimpleValue(value) => self.get_addresses_from_value(value),
+            CompoundValue::PartialFunction {
+                function,
+                arguments,
+            } => std::iter::once(function)
+                .chain(arguments.iter())
+                .flat_map(|value| self.get_addresses_from_value(value))
+                .collect(),
+        }
+    }
+
+    fn get_addresses_from_value(&self, value: &Value) -> Vec<usize> {
+        let mut result = vec![];
+        self.add_used_addresses_from_value(&mut result, &mut HashSet::new(), value);
+        result
+    }
+
+    // What can't be read isn't a root, a broken object can't keep anything alive. Objects and
+    // arrays can hold themselves, so the ones already seen aren't walked again
+    fn add_addresses_from_object(
+        &self,
+        result: &mut Vec<usize>,
+        seen: &mut HashSet<usize>,
+        address: usize,
+        tags: usize,
+    ) {
+        if !seen.insert(address) {
+            return;
+        }
+        result.push(address);
+        result.push(tags);
+        if let Ok(tag_addresses) = self.get_tags(tags) {
+            result.extend_from_slice(tag_addresses);
+        }
+        let props_address: usize = match self.memory.get_t(address) {
+            Ok(props_address) => *props_address,
+            Err(_) => return,
+        };
+        result.push(props_address);
+        let pairs = match self.get_properties(address) {
+            Ok(pairs) => pairs,
+            Err(_) => return,
+        };
         for (string, value) in pairs {
             result.push(*string);
-            self.add_used_addresses_from_value(&mut result, value);
+            self.add_used_addresses_from_value(result, seen, value);
         }
-        result
     }
 
-    fn get_addresses_from_array(&self, address: usize, capacity: usize) -> Vec<usize> {
-        let mut result = vec![address];
-        for _ in 0..capacity {
-            let v = self
+    fn add_addresses_from_array(
+        &self,
+        result: &mut Vec<usize>,
+        seen: &mut HashSet<usize>,
+        address: usize,
+        capacity: usize,
+    ) {
+        if !seen.insert(address) {
+            return;
+        }
+        result.push(address);
+        for index in 0..capacity {
+            let v = match self
                 .memory
-                .get_t::<Value>(address + capacity * std::mem::size_of::<Value>())
-                .unwrap();
-            self.add_used_addresses_from_value(&mut result, v);
+                .get_t::<CompoundValue>(address + index * COMPOUND_VALUE_SIZE)
+            {
+                Ok(v) => v,
+                Err(_) => break,
+            };
+            if let CompoundValue::SimpleValue(v) = v {
+                self.add_used_addresses_from_value(result, seen, v);
+            }
         }
-        result
     }
 
-    fn add_used_addresses_from_value(&self, result: &mut Vec<usize>, v: &Value) {
+    fn add_used_addresses_from_value(&self, result: &mut Vec<usize>, seen: &mut HashSet<usize>, v: &Value) {
         match v {
             Value::Array { address, capacity } => {
-                result.extend(self.get_addresses_from_array(*address, *capacity))
+                self.add_addresses_from_array(result, seen, *address, *capacity)
             }
             Value::String(a) => result.push(*a),
-            Value::Object { address, tags } => result.extend(self.get_addresses_from_object(*address, *tags)),
+            Value::Object { address, tags } => self.add_addresses_from_object(result, seen, *address, *tags),
+            Value::Pointer(address) => self.add_addresses_from_pointer(result, seen, *address),
+            // The arrays of pointers to the uplifted values and of default arguments, they don't
+            // keep their capacity
+            Value::Function { uplifts, defaults, .. } => {
+                for address in uplifts.iter().chain(defaults.iter()) {
+                    let capacity = self
+                        .allocator
+                        .borrow()
+                        .get_allocated_space(*address)
+                        .map_or(0, |size| size / COMPOUND_VALUE_SIZE);
+                    self.add_addresses_from_array(result, seen, *address, capacity)
+                }
+            }
             _ => {}
         }
     }
 
+    // The cell an uplifted local lives in, along with whatever it holds
+    fn add_addresses_from_pointer(&self, result: &mut Vec<usize>, seen: &mut HashSet<usize>, address: usize) {
+        if !seen.insert(address) {
+            return;
+        }
+        result.push(address);
+        if let Ok(CompoundValue::SimpleValue(value)) = self.memory.get_t::<CompoundValue>(address) {
+            self.add_used_addresses_from_value(result, seen, value);
+        }
+    }
+
+    pub(crate) fn build_array(&self, elements: &[CompoundValue]) -> Result<Value, Error> {
+        let address = self.malloc(elements.len() * COMPOUND_VALUE_SIZE)?;
+        self.memory.copy_t_slice(elements, address)?;
+        Ok(Value::Array {
+            capacity: elements.len(),
+            address,
+        })
+    }
+
+    pub(crate) fn build_object(&self, properties: &[(usize, Value)]) -> Result<Value, Error> {
+        let mut sorted: Vec<(usize, Value)> = Vec::with_capacity(properties.len());
+        for (key, value) in properties {
+            let property = self.address_to_string(*key)?;
+            match self.property_lookup(&sorted, property)? {
+                Ok(index) => sorted[index].1 = *value,
+                Err(index) => sorted.insert(index, (*key, *value)),
+            }
+        }
+        let props_size = USIZE_SIZE + sorted.len() * (VALUE_SIZE + USIZE_SIZE);
+        let [address, props_address, tags] = self.malloc_all([USIZE_SIZE, props_size, USIZE_SIZE])?;
+        self.memory.copy_t(&0usize, tags)?;
+        self.memory.copy_t(&sorted.len(), props_address)?;
+        self.memory.copy_t_slice(&sorted, props_address + USIZE_SIZE)?;
+        self.memory.copy_t(&props_address, address)?;
+        Ok(Value::Object { address, tags })
+    }
+
     pub(crate) fn new_frame(&mut self, ip: usize, arity: usize) {
         let new_frame = Frame {
             arity: 0,
@@ -1489,11 +2083,12 @@ impl VM {
 
 #[cfg(test)]
 mod cpu_tests {
-    use super::{Value, VM};
+    use super::{Location, RunStatus, Value, VM, VMError, VMErrorType, DEFAULT_ALLOCATION_LIMIT};
     use crate::allocator::Allocator;
     use crate::cpu::{USIZE_SIZE, VALUE_SIZE, CompoundValue, COMPOUND_VALUE_SIZE};
     use crate::instruction::{Instruction, InstructionType};
     use crate::memory::Memory;
+    use crate::serde::ConstantDeclaration;
     use failure::Error;
 
     fn create_instruction(instruction_type: InstructionType) -> Instruction {
@@ -1503,6 +2098,10 @@ mod cpu_tests {
         }
     }
 
+    fn noops(length: usize) -> Vec<Instruction> {
+        vec![create_instruction(InstructionType::Noop); length]
+    }
+
     #[test]
     fn test_constant() -> Result<(), Error> {
         let mut vm = VM::test_vm(0);
@@ -1688,6 +2287,114 @@ mod cpu_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_integer_arithmetic_wraps() -> Result<(), Error> {
+        let mut vm = VM::test_vm(2);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(i64::MAX));
+        vm.stack[1] = CompoundValue::SimpleValue(Value::Integer(1));
+        vm.execute_instruction(create_instruction(InstructionType::Plus))?;
+        assert_eq!(vm.stack[0], CompoundValue::SimpleValue(Value::Integer(i64::MIN)));
+        vm.stack[1] = CompoundValue::SimpleValue(Value::Integer(-1));
+        vm.sp = 2;
+        vm.execute_instruction(create_instruction(InstructionType::Div))?;
+        assert_eq!(vm.sp, 1);
+        assert_eq!(vm.stack[0], CompoundValue::SimpleValue(Value::Integer(i64::MIN)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_checked_integer_arithmetic() -> Result<(), Error> {
+        let mut vm = VM::test_vm(2);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(i64::MAX));
+        vm.stack[1] = CompoundValue::SimpleValue(Value::Integer(1));
+        let error = vm.execute_instruction(create_instruction(InstructionType::CheckedPlus))
+            .unwrap_err()
+            .downcast::<VMError>()
+            .unwrap();
+        assert_eq!(error.error_type, VMErrorType::IntegerOverflow);
+        assert_eq!(vm.sp, 0);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(i64::MAX));
+        vm.stack[1] = CompoundValue::SimpleValue(Value::Integer(-1));
+        vm.sp = 2;
+        vm.execute_instruction(create_instruction(InstructionType::CheckedPlus))?;
+        assert_eq!(vm.stack[0], CompoundValue::SimpleValue(Value::Integer(i64::MAX - 1)));
+        vm.stack[1] = CompoundValue::SimpleValue(Value::Float(0.5));
+        vm.sp = 2;
+        vm.execute_instruction(create_instruction(InstructionType::CheckedMult))?;
+        assert_eq!(vm.stack[0], CompoundValue::SimpleValue(Value::Float(i64::MAX as f32 * 0.5)));
+        Ok(())
+    }
+
+    // Runs b op a, giving back what it left on the stack or why it failed
+    fn integer_operation(instruction_type: InstructionType, b: Value, a: Value) -> Result<CompoundValue, VMErrorType> {
+        let mut vm = VM::test_vm(2);
+        vm.stack[0] = CompoundValue::SimpleValue(b);
+        vm.stack[1] = CompoundValue::SimpleValue(a);
+        match vm.execute_instruction(create_instruction(instruction_type)) {
+            Ok(()) => Ok(vm.stack[0].clone()),
+            Err(error) => Err(error.downcast::<VMError>().unwrap().error_type),
+        }
+    }
+
+    fn integer(value: i64) -> Result<CompoundValue, VMErrorType> {
+        Ok(CompoundValue::SimpleValue(Value::Integer(value)))
+    }
+
+    #[test]
+    fn test_mod_integer() {
+        let modulo = |b, a| integer_operation(InstructionType::Mod, Value::Integer(b), Value::Integer(a));
+        assert_eq!(modulo(7, 3), integer(1));
+        assert_eq!(modulo(-7, 3), integer(-1));
+        assert_eq!(modulo(7, -3), integer(1));
+        assert_eq!(modulo(i64::MIN, -1), integer(0));
+        assert_eq!(modulo(7, 0), Err(VMErrorType::DivisionByZero));
+        assert_eq!(
+            integer_operation(InstructionType::Div, Value::Integer(7), Value::Integer(0)),
+            Err(VMErrorType::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn test_bitwise_integer() {
+        let run = |instruction_type, b, a| integer_operation(instruction_type, Value::Integer(b), Value::Integer(a));
+        assert_eq!(run(InstructionType::BitAnd, 0b1100, 0b1010), integer(0b1000));
+        assert_eq!(run(InstructionType::BitOr, 0b1100, 0b1010), integer(0b1110));
+        assert_eq!(run(InstructionType::BitXor, 0b1100, 0b1010), integer(0b0110));
+        assert_eq!(run(InstructionType::BitAnd, -1, 0xff), integer(0xff));
+        assert_eq!(run(InstructionType::BitOr, -8, 7), integer(-1));
+        assert_eq!(run(InstructionType::BitXor, -8, -1), integer(7));
+    }
+
+    #[test]
+    fn test_shift_integer() {
+        let shl = |b, a| integer_operation(InstructionType::Shl, Value::Integer(b), Value::Integer(a));
+        let shr = |b, a| integer_operation(InstructionType::Shr, Value::Integer(b), Value::Integer(a));
+        assert_eq!(shl(1, 3), integer(8));
+        assert_eq!(shl(-1, 63), integer(i64::MIN));
+        assert_eq!(shl(1, 64), integer(0));
+        assert_eq!(shl(-1, 1000), integer(0));
+        assert_eq!(shr(8, 3), integer(1));
+        assert_eq!(shr(-8, 1), integer(-4));
+        assert_eq!(shr(8, 64), integer(0));
+        assert_eq!(shr(-8, 64), integer(-1));
+        assert_eq!(shr(i64::MIN, i64::MAX), integer(-1));
+        assert_eq!(shl(1, -1), Err(VMErrorType::NegativeShift(-1)));
+        assert_eq!(shr(1, -1), Err(VMErrorType::NegativeShift(-1)));
+    }
+
+    #[test]
+    fn test_integer_operations_reject_floats() {
+        for instruction_type in &[InstructionType::Mod, InstructionType::BitAnd, InstructionType::Shl] {
+            assert_eq!(
+                integer_operation(instruction_type.clone(), Value::Float(7.0), Value::Integer(2)),
+                Err(VMErrorType::ExpectedNumbers(
+                    Box::new(CompoundValue::SimpleValue(Value::Integer(2))),
+                    Box::new(CompoundValue::SimpleValue(Value::Float(7.0)))
+                ))
+            );
+        }
+    }
+
     #[test]
     fn test_nil() -> Result<(), Error> {
         let mut vm = VM::test_vm(0);
@@ -1957,14 +2664,14 @@ mod cpu_tests {
         vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(0));
         vm.execute_instruction(create_instruction(InstructionType::SetGlobal(0)))?;
         assert_eq!(vm.sp, 1);
-        assert_eq!(vm.globals[&0], CompoundValue::SimpleValue(Value::Integer(0)));
+        assert_eq!(vm.global(0), Some(&CompoundValue::SimpleValue(Value::Integer(0))));
         Ok(())
     }
 
     #[test]
     fn test_get_global() -> Result<(), Error> {
         let mut vm = VM::test_vm(0);
-        vm.globals.insert(0, CompoundValue::SimpleValue(Value::Integer(0)));
+        vm.store_global(0, CompoundValue::SimpleValue(Value::Integer(0)));
         vm.execute_instruction(create_instruction(InstructionType::GetGlobal(0)))?;
         assert_eq!(vm.sp, 1);
         assert_eq!(vm.stack[0], CompoundValue::SimpleValue(Value::Integer(0)));
@@ -1987,6 +2694,26 @@ mod cpu_tests {
             .unwrap();
     }
 
+    #[test]
+    fn test_globals_grow_on_demand() -> Result<(), Error> {
+        let mut vm = VM::test_vm(1);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(3));
+        vm.execute_instruction(create_instruction(InstructionType::SetGlobal(5)))?;
+        assert_eq!(vm.globals.len(), 6);
+        assert_eq!(vm.global(5), Some(&CompoundValue::SimpleValue(Value::Integer(3))));
+        assert_eq!(vm.global(2), None);
+        assert!(vm.execute_instruction(create_instruction(InstructionType::GetGlobal(2))).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_globals_over_the_limit_fail() {
+        let mut vm = VM::test_vm(1);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(3));
+        assert!(vm.execute_instruction(create_instruction(InstructionType::SetGlobal(usize::MAX))).is_err());
+        assert!(vm.globals.is_empty());
+    }
+
     #[test]
     fn test_set_local() -> Result<(), Error> {
         let mut vm = VM::test_vm(1);
@@ -2025,6 +2752,7 @@ mod cpu_tests {
     #[test]
     fn test_jmp_if_false_jmping() -> Result<(), Error> {
         let mut vm = VM::test_vm(1);
+        vm.rom = noops(4);
         vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(0));
         vm.execute_instruction(create_instruction(InstructionType::JmpIfFalse(3)))?;
         assert_eq!(vm.sp, 0);
@@ -2045,6 +2773,7 @@ mod cpu_tests {
     #[test]
     fn test_jmp() -> Result<(), Error> {
         let mut vm = VM::test_vm(0);
+        vm.rom = noops(4);
         vm.execute_instruction(create_instruction(InstructionType::Jmp(3)))?;
         assert_eq!(vm.sp, 0);
         assert_eq!(vm.ip(), 4);
@@ -2054,6 +2783,7 @@ mod cpu_tests {
     #[test]
     fn test_loop() -> Result<(), Error> {
         let mut vm = VM::test_vm(0);
+        vm.rom = noops(4);
         vm.frames[0].ip = 4;
         vm.execute_instruction(create_instruction(InstructionType::Loop(3)))?;
         assert_eq!(vm.sp, 0);
@@ -2064,7 +2794,8 @@ mod cpu_tests {
     #[test]
     fn test_call() -> Result<(), Error> {
         let mut vm = VM::test_vm(2);
-        vm.stack[1] = CompoundValue::SimpleValue(Value::Function { ip: 20, arity: 1, uplifts: None });
+        vm.rom = noops(20);
+        vm.stack[1] = CompoundValue::SimpleValue(Value::Function { ip: 20, arity: 1, uplifts: None, defaults: None });
         vm.execute_instruction(create_instruction(InstructionType::Call))?;
         assert_eq!(vm.frames.last().unwrap().stack_offset, 0);
         assert_eq!(vm.frames.len(), 2);
@@ -2072,6 +2803,35 @@ mod cpu_tests {
         Ok(())
     }
 
+    #[test]
+    #[should_panic(
+        expected = "called `Result::unwrap()` on an `Err` value: VMError { error_type: InvalidJump { target: -999 }, file: \"hola\", line: 0 }"
+    )]
+    fn test_loop_before_the_start() {
+        let mut vm = VM::test_vm(0);
+        vm.execute_instruction(create_instruction(InstructionType::Loop(1000)))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_jmp_past_the_end() {
+        let mut vm = VM::test_vm(1);
+        assert!(vm.execute_instruction(create_instruction(InstructionType::Jmp(1))).is_err());
+        assert!(vm.execute_instruction(create_instruction(InstructionType::JmpIfFalse(usize::MAX))).is_err());
+        assert_eq!(vm.ip(), 1);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "called `Result::unwrap()` on an `Err` value: VMError { error_type: InvalidJump { target: 20 }, file: \"hola\", line: 0 }"
+    )]
+    fn test_call_past_the_end() {
+        let mut vm = VM::test_vm(2);
+        vm.stack[1] = CompoundValue::SimpleValue(Value::Function { ip: 20, arity: 1, uplifts: None, defaults: None });
+        vm.execute_instruction(create_instruction(InstructionType::Call))
+            .unwrap();
+    }
+
     #[test]
     #[should_panic(
         expected = "called `Result::unwrap()` on an `Err` value: VMError { error_type: ExpectedFunction(SimpleValue(Integer(0))), file: \"hola\", line: 0 }"
@@ -2088,11 +2848,269 @@ mod cpu_tests {
     )]
     fn test_call_without_enough_arguments() {
         let mut vm = VM::test_vm(2);
-        vm.stack[1] = CompoundValue::SimpleValue(Value::Function { ip: 20, arity: 2, uplifts: None, });
+        vm.stack[1] = CompoundValue::SimpleValue(Value::Function { ip: 20, arity: 2, uplifts: None, defaults: None });
         vm.execute_instruction(create_instruction(InstructionType::Call))
             .unwrap();
     }
 
+    // Calls a function that stores its three arguments in the globals with CALL_N. The last two
+    // default to the string "b" and to an array. When there are bound arguments, it's called as a
+    // partial function that already has them
+    fn call_with_defaults(arguments: usize) -> Result<(VM, Value), Error> {
+        call_partial_with_defaults(&[], arguments)
+    }
+
+    fn call_partial_with_defaults(bound: &[Value], arguments: usize) -> Result<(VM, Value), Error> {
+        let mut allocator = Allocator::new(256);
+        let memory = Memory::new(256);
+        let string = allocator.malloc(1, std::iter::empty())?;
+        memory.copy_string("b", string);
+        let mut rom: Vec<InstructionType> = (0..arguments).map(InstructionType::Constant).collect();
+        rom.extend(vec![
+            InstructionType::Constant(3),
+            InstructionType::CallN(arguments),
+            InstructionType::Return,
+        ]);
+        let ip = rom.len();
+        for local in 0..3 {
+            rom.extend(vec![
+                InstructionType::GetLocal(local),
+                InstructionType::SetGlobal(local),
+                InstructionType::Pop,
+            ]);
+        }
+        rom.extend(vec![InstructionType::Nil, InstructionType::Return]);
+        let constants = (1..=3)
+            .map(|i| CompoundValue::SimpleValue(Value::Integer(i)))
+            .collect();
+        let rom = rom.into_iter().map(create_instruction).collect();
+        let mut vm = VM::new(allocator, constants, vec![], memory, rom);
+        let array = vm.build_array(&[CompoundValue::SimpleValue(Value::Integer(4))])?;
+        let defaults = match vm.build_array(&[
+            CompoundValue::SimpleValue(Value::String(string)),
+            CompoundValue::SimpleValue(array),
+        ])? {
+            Value::Array { address, .. } => address,
+            v => panic!("Invalid value {:?}", v),
+        };
+        let function = Value::Function { ip, arity: 3, uplifts: None, defaults: Some(defaults) };
+        vm.constants.push(if bound.is_empty() {
+            CompoundValue::SimpleValue(function)
+        } else {
+            CompoundValue::PartialFunction { function, arguments: bound.to_vec() }
+        });
+        // Only the function keeps the defaults alive
+        vm.collect_garbage()?;
+        vm.start();
+        vm.run()?;
+        Ok((vm, array))
+    }
+
+    fn globals(vm: &VM) -> Vec<CompoundValue> {
+        (0..3).map(|global| vm.global(global).cloned().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_call_n_fills_missing_arguments_with_defaults() -> Result<(), Error> {
+        let (vm, array) = call_with_defaults(1)?;
+        assert_eq!(globals(&vm), vec![
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(Value::String(0)),
+            CompoundValue::SimpleValue(array),
+        ]);
+        assert_eq!(vm.address_to_string(0)?, "b");
+        let (vm, array) = call_with_defaults(2)?;
+        assert_eq!(globals(&vm), vec![
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(Value::Integer(2)),
+            CompoundValue::SimpleValue(array),
+        ]);
+        let (vm, _) = call_with_defaults(3)?;
+        assert_eq!(globals(&vm), vec![
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(Value::Integer(2)),
+            CompoundValue::SimpleValue(Value::Integer(3)),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_call_n_counts_the_bound_arguments_of_a_partial_function() -> Result<(), Error> {
+        let bound = [Value::Integer(7)];
+        let (vm, array) = call_partial_with_defaults(&bound, 0)?;
+        assert_eq!(globals(&vm), vec![
+            CompoundValue::SimpleValue(Value::Integer(7)),
+            CompoundValue::SimpleValue(Value::String(0)),
+            CompoundValue::SimpleValue(array),
+        ]);
+        let (vm, array) = call_partial_with_defaults(&bound, 1)?;
+        assert_eq!(globals(&vm), vec![
+            CompoundValue::SimpleValue(Value::Integer(7)),
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(array),
+        ]);
+        let error = call_partial_with_defaults(&bound, 3).err().unwrap().downcast::<VMError>().unwrap();
+        assert_eq!(error.error_type, VMErrorType::TooManyArgumentsForFunction);
+        Ok(())
+    }
+
+    #[test]
+    fn test_call_n_without_a_default_for_a_missing_argument() {
+        let error = call_with_defaults(0).err().unwrap().downcast::<VMError>().unwrap();
+        assert_eq!(error.error_type, VMErrorType::NotEnoughArgumentsForFunction);
+    }
+
+    fn create_program(rom: Vec<Instruction>) -> VM {
+        let constants = vec![CompoundValue::SimpleValue(Value::Integer(42))];
+        let mut vm = VM::new(Allocator::new(10), constants, vec![], Memory::new(10), rom);
+        vm.new_frame(0, 0);
+        vm
+    }
+
+    #[test]
+    fn test_top_level_return_with_value() -> Result<(), Error> {
+        let mut vm = create_program(vec![
+            create_instruction(InstructionType::Constant(0)),
+            create_instruction(InstructionType::Return),
+            create_instruction(InstructionType::Nil),
+        ]);
+        assert_eq!(vm.run()?, Some(CompoundValue::SimpleValue(Value::Integer(42))));
+        assert!(vm.is_done());
+        assert_eq!(vm.exit_value(), Some(&CompoundValue::SimpleValue(Value::Integer(42))));
+        assert_eq!(vm.sp, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_level_return_without_value() -> Result<(), Error> {
+        let mut vm = create_program(vec![
+            create_instruction(InstructionType::Return),
+            create_instruction(InstructionType::Constant(0)),
+        ]);
+        assert_eq!(vm.run()?, None);
+        assert!(vm.is_done());
+        assert_eq!(vm.exit_value(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_after_the_program_finished() -> Result<(), Error> {
+        let mut vm = create_program(vec![
+            create_instruction(InstructionType::Constant(0)),
+            create_instruction(InstructionType::Return),
+        ]);
+        vm.run()?;
+        let error = vm.execute().unwrap_err();
+        assert_eq!(error.downcast::<VMErrorType>()?, VMErrorType::ProgramFinished);
+        // Running off the end of the rom finishes the program as well
+        let mut vm = create_program(vec![create_instruction(InstructionType::Nil)]);
+        assert_eq!(vm.run()?, None);
+        assert!(vm.execute().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_for_resumes_after_a_yield() -> Result<(), Error> {
+        let mut vm = create_program(vec![
+            create_instruction(InstructionType::Constant(0)),
+            create_instruction(InstructionType::Yield),
+            create_instruction(InstructionType::Constant(0)),
+            create_instruction(InstructionType::Plus),
+            create_instruction(InstructionType::Return),
+        ]);
+        assert_eq!(vm.run_for(10)?, RunStatus::Yielded);
+        assert_eq!(vm.ip(), 2);
+        assert_eq!(vm.stack(), &[CompoundValue::SimpleValue(Value::Integer(42))]);
+        assert_eq!(
+            vm.run_for(10)?,
+            RunStatus::Finished(Some(CompoundValue::SimpleValue(Value::Integer(84))))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_for_runs_out_of_fuel() -> Result<(), Error> {
+        let mut vm = create_program(vec![
+            create_instruction(InstructionType::Constant(0)),
+            create_instruction(InstructionType::Constant(0)),
+            create_instruction(InstructionType::Plus),
+            create_instruction(InstructionType::Return),
+        ]);
+        assert_eq!(vm.run_for(2)?, RunStatus::OutOfFuel);
+        assert_eq!(vm.ip(), 2);
+        assert_eq!(vm.run_for(0)?, RunStatus::OutOfFuel);
+        assert_eq!(
+            vm.run_for(2)?,
+            RunStatus::Finished(Some(CompoundValue::SimpleValue(Value::Integer(84))))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_goes_through_yields() -> Result<(), Error> {
+        let mut vm = create_program(vec![
+            create_instruction(InstructionType::Yield),
+            create_instruction(InstructionType::Constant(0)),
+            create_instruction(InstructionType::Return),
+        ]);
+        assert_eq!(vm.run()?, Some(CompoundValue::SimpleValue(Value::Integer(42))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_and_run_as_repl() -> Result<(), Error> {
+        let mut vm = VM::new(Allocator::new(10), vec![], vec![], Memory::new(10), vec![]);
+        let result = vm.append_and_run(
+            vec![
+                create_instruction(InstructionType::Constant(0)),
+                create_instruction(InstructionType::Constant(1)),
+                create_instruction(InstructionType::Plus),
+                create_instruction(InstructionType::Return),
+            ],
+            vec![
+                CompoundValue::SimpleValue(Value::Integer(1)),
+                CompoundValue::SimpleValue(Value::Integer(2)),
+            ],
+            vec![Location { address: 0, line: 1 }],
+        )?;
+        assert_eq!(result, Some(CompoundValue::SimpleValue(Value::Integer(3))));
+        let result = vm.append_and_run(
+            vec![
+                create_instruction(InstructionType::Constant(0)),
+                create_instruction(InstructionType::SetGlobal(0)),
+                create_instruction(InstructionType::Pop),
+                create_instruction(InstructionType::Return),
+                create_instruction(InstructionType::GetLocal(0)),
+                create_instruction(InstructionType::GetLocal(0)),
+                create_instruction(InstructionType::Mult),
+                create_instruction(InstructionType::Return),
+            ],
+            vec![CompoundValue::SimpleValue(Value::Function { ip: 4, arity: 1, uplifts: None, defaults: None })],
+            vec![Location { address: 0, line: 2 }],
+        )?;
+        assert_eq!(result, None);
+        assert_eq!(
+            vm.global(0),
+            Some(&CompoundValue::SimpleValue(Value::Function { ip: 8, arity: 1, uplifts: None, defaults: None })),
+        );
+        let result = vm.append_and_run(
+            vec![
+                create_instruction(InstructionType::Constant(0)),
+                create_instruction(InstructionType::GetGlobal(0)),
+                create_instruction(InstructionType::Call),
+                create_instruction(InstructionType::Return),
+            ],
+            vec![CompoundValue::SimpleValue(Value::Integer(7))],
+            vec![Location { address: 0, line: 3 }],
+        )?;
+        assert_eq!(result, Some(CompoundValue::SimpleValue(Value::Integer(49))));
+        assert_eq!(vm.rom[12].instruction_type, InstructionType::Constant(3));
+        assert_eq!(vm.rom[12].location, 2);
+        assert_eq!(vm.sp, 0);
+        assert!(vm.frames.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_array_alloc() {
         let mut vm = VM::test_vm_with_mem(1, 100);
@@ -2109,6 +3127,61 @@ mod cpu_tests {
         }
     }
 
+    #[test]
+    fn test_array_alloc_negative_capacity() {
+        let mut vm = VM::test_vm_with_memory_and_allocator(1, Memory::new(100), Allocator::new(100));
+        vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(-1));
+        let error = vm.execute_instruction(create_instruction(InstructionType::ArrayAlloc))
+            .unwrap_err()
+            .downcast::<VMError>()
+            .unwrap();
+        assert_eq!(error.error_type, VMErrorType::NegativeCapacity(-1));
+        assert_eq!(vm.sp, 0);
+        vm.push(CompoundValue::SimpleValue(Value::Integer(1))).unwrap();
+        vm.execute_instruction(create_instruction(InstructionType::ArrayAlloc))
+            .unwrap();
+        assert_eq!(vm.sp, 1);
+    }
+
+    #[test]
+    fn test_array_alloc_too_large() {
+        let mut vm = VM::test_vm_with_memory_and_allocator(1, Memory::new(100), Allocator::new(100));
+        vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(i64::MAX));
+        let error = vm.execute_instruction(create_instruction(InstructionType::ArrayAlloc))
+            .unwrap_err()
+            .downcast::<VMError>()
+            .unwrap();
+        assert_eq!(error.error_type, VMErrorType::AllocationTooLarge {
+            requested: usize::MAX,
+            limit: DEFAULT_ALLOCATION_LIMIT,
+        });
+        vm.allocation_limit = COMPOUND_VALUE_SIZE;
+        vm.push(CompoundValue::SimpleValue(Value::Integer(2))).unwrap();
+        let error = vm.execute_instruction(create_instruction(InstructionType::ArrayAlloc))
+            .unwrap_err()
+            .downcast::<VMError>()
+            .unwrap();
+        assert_eq!(error.error_type, VMErrorType::AllocationTooLarge {
+            requested: 2 * COMPOUND_VALUE_SIZE,
+            limit: COMPOUND_VALUE_SIZE,
+        });
+        vm.push(CompoundValue::SimpleValue(Value::Integer(1))).unwrap();
+        vm.execute_instruction(create_instruction(InstructionType::ArrayAlloc))
+            .unwrap();
+        assert_eq!(vm.sp, 1);
+    }
+
+    #[test]
+    fn test_object_alloc_negative_capacity() {
+        let mut vm = VM::test_vm_with_memory_and_allocator(1, Memory::new(100), Allocator::new(100));
+        vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(-3));
+        let error = vm.execute_instruction(create_instruction(InstructionType::ObjectAlloc))
+            .unwrap_err()
+            .downcast::<VMError>()
+            .unwrap();
+        assert_eq!(error.error_type, VMErrorType::NegativeCapacity(-3));
+    }
+
     #[test]
     fn test_array_get() {
         let memory = Memory::new(110);
@@ -2117,7 +3190,7 @@ mod cpu_tests {
         let address = allocator
             .malloc(std::mem::size_of::<CompoundValue>(), std::iter::empty())
             .unwrap();
-        memory.copy_t(&value, address);
+        memory.copy_t(&value, address).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
         vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(0));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Array {
@@ -2141,7 +3214,7 @@ mod cpu_tests {
         let address = allocator
             .malloc(std::mem::size_of::<CompoundValue>(), std::iter::empty())
             .unwrap();
-        memory.copy_t(&value, address);
+        memory.copy_t(&value, address).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
         vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(1));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Array {
@@ -2160,7 +3233,7 @@ mod cpu_tests {
         let address = allocator
             .malloc(std::mem::size_of::<CompoundValue>(), std::iter::empty())
             .unwrap();
-        memory.copy_t(&value, address);
+        memory.copy_t(&value, address).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(3, memory, allocator);
         vm.stack[1] = CompoundValue::SimpleValue(Value::Integer(0));
         vm.stack[2] = CompoundValue::SimpleValue(Value::Array {
@@ -2188,7 +3261,7 @@ mod cpu_tests {
         let address = allocator
             .malloc(std::mem::size_of::<CompoundValue>(), std::iter::empty())
             .unwrap();
-        memory.copy_t(&value, address);
+        memory.copy_t(&value, address).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(3, memory, allocator);
         vm.stack[1] = CompoundValue::SimpleValue(Value::Integer(1));
         vm.stack[2] = CompoundValue::SimpleValue(Value::Array {
@@ -2201,14 +3274,14 @@ mod cpu_tests {
 
     #[test]
     fn test_multi_array_set() {
-        let memory = Memory::new(150);
-        let mut allocator = Allocator::new(150);
+        let memory = Memory::new(200);
+        let mut allocator = Allocator::new(200);
         let value = CompoundValue::SimpleValue(Value::Integer(42));
         let address = allocator
             .malloc(std::mem::size_of::<CompoundValue>() * 2, std::iter::empty())
             .unwrap();
-        memory.copy_t(&value, address);
-        memory.copy_t(&value, address + VALUE_SIZE);
+        memory.copy_t(&value, address).unwrap();
+        memory.copy_t(&value, address + VALUE_SIZE).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(3, memory, allocator);
         vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(1));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Integer(2));
@@ -2261,10 +3334,10 @@ mod cpu_tests {
         let address = allocator
             .malloc(USIZE_SIZE, std::iter::empty())
             .unwrap();
-        memory.copy_t(&obj_address, address);
-        memory.copy_t(&1usize, obj_address);
-        memory.copy_t(&string_address, obj_address + USIZE_SIZE);
-        memory.copy_t(&Value::Integer(42), obj_address + USIZE_SIZE * 2);
+        memory.copy_t(&obj_address, address).unwrap();
+        memory.copy_t(&1usize, obj_address).unwrap();
+        memory.copy_t(&string_address, obj_address + USIZE_SIZE).unwrap();
+        memory.copy_t(&Value::Integer(42), obj_address + USIZE_SIZE * 2).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
         vm.stack[0] = CompoundValue::SimpleValue(Value::String(string_address));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Object {
@@ -2294,10 +3367,10 @@ mod cpu_tests {
         let address = allocator
             .malloc(USIZE_SIZE, std::iter::empty())
             .unwrap();
-        memory.copy_t(&obj_address, address);
-        memory.copy_t(&1usize, obj_address);
-        memory.copy_t(&string_address, obj_address + USIZE_SIZE);
-        memory.copy_t(&Value::Integer(42), obj_address + USIZE_SIZE * 2);
+        memory.copy_t(&obj_address, address).unwrap();
+        memory.copy_t(&1usize, obj_address).unwrap();
+        memory.copy_t(&string_address, obj_address + USIZE_SIZE).unwrap();
+        memory.copy_t(&Value::Integer(42), obj_address + USIZE_SIZE * 2).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
         vm.stack[0] = CompoundValue::SimpleValue(Value::String(wrong_address));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Object {
@@ -2320,8 +3393,8 @@ mod cpu_tests {
         let address = allocator
             .malloc(USIZE_SIZE, std::iter::empty())
             .unwrap();
-        memory.copy_t(&obj_address, address);
-        memory.copy_t(&0usize, obj_address);
+        memory.copy_t(&obj_address, address).unwrap();
+        memory.copy_t(&0usize, obj_address).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(3, memory, allocator);
         vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(42));
         vm.stack[1] = CompoundValue::SimpleValue(Value::String(string_address));
@@ -2363,10 +3436,10 @@ mod cpu_tests {
         let address = allocator
             .malloc(USIZE_SIZE, std::iter::empty())
             .unwrap();
-        memory.copy_t(&obj_address, address);
-        memory.copy_t(&1usize, obj_address);
-        memory.copy_t(&string_address, obj_address + USIZE_SIZE);
-        memory.copy_t(&Value::Integer(41), obj_address + USIZE_SIZE * 2);
+        memory.copy_t(&obj_address, address).unwrap();
+        memory.copy_t(&1usize, obj_address).unwrap();
+        memory.copy_t(&string_address, obj_address + USIZE_SIZE).unwrap();
+        memory.copy_t(&Value::Integer(41), obj_address + USIZE_SIZE * 2).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(3, memory, allocator);
         vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(42));
         vm.stack[1] = CompoundValue::SimpleValue(Value::String(string_address));
@@ -2398,7 +3471,7 @@ mod cpu_tests {
 
     #[test]
     fn test_object_set_on_non_existing_without_space() {
-        let mut vm = VM::test_vm_with_mem(3, 200);
+        let mut vm = VM::test_vm_with_mem(3, 260);
         let address = vm
             .allocator
             .borrow_mut()
@@ -2419,11 +3492,11 @@ mod cpu_tests {
             .borrow_mut()
             .malloc(USIZE_SIZE, std::iter::empty())
             .unwrap();
-        vm.memory.copy_t(&obj_address, address);
-        vm.memory.copy_t(&1usize, obj_address);
-        vm.memory.copy_t(&address, obj_address + USIZE_SIZE);
+        vm.memory.copy_t(&obj_address, address).unwrap();
+        vm.memory.copy_t(&1usize, obj_address).unwrap();
+        vm.memory.copy_t(&address, obj_address + USIZE_SIZE).unwrap();
         vm.memory
-            .copy_t(&Value::Integer(41), obj_address + USIZE_SIZE * 2);
+            .copy_t(&Value::Integer(41), obj_address + USIZE_SIZE * 2).unwrap();
         vm.stack[0] = CompoundValue::SimpleValue(Value::Integer(42));
         vm.stack[1] = CompoundValue::SimpleValue(Value::String(address2));
         vm.stack[2] = CompoundValue::SimpleValue(Value::Object {
@@ -2466,15 +3539,16 @@ mod cpu_tests {
     #[test]
     fn test_attach_uplifts() -> Result<(), Error> {
         let mut vm = VM::test_vm(1);
-        vm.globals.insert(0, CompoundValue::SimpleValue(Value::Function {
+        vm.store_global(0, CompoundValue::SimpleValue(Value::Function {
             ip: 0,
             arity: 0,
-            uplifts: None
+            uplifts: None,
+            defaults: None,
         }));
         vm.stack[0] = CompoundValue::SimpleValue(Value::Array { address: 0, capacity: 0 });
         vm.execute_instruction(create_instruction(InstructionType::AttachArray(0)))?;
         assert_eq!(vm.sp, 0);
-        assert_eq!(vm.globals.get(&0).cloned(), Some(CompoundValue::SimpleValue(Value::Function { ip: 0, arity: 0, uplifts: Some(0), })));
+        assert_eq!(vm.global(0).cloned(), Some(CompoundValue::SimpleValue(Value::Function { ip: 0, arity: 0, uplifts: Some(0), defaults: None })));
         Ok(())
     }
 
@@ -2498,13 +3572,26 @@ mod cpu_tests {
         Ok(())
     }
 
+    fn allocate_string(vm: &VM, value: &str) -> usize {
+        let address = vm.allocator.borrow_mut().malloc(value.len(), std::iter::empty()).unwrap();
+        vm.memory.copy_string(value, address);
+        address
+    }
+
+    fn allocate_tags(vm: &VM, tags: &[usize]) -> usize {
+        vm.allocate_tags(tags).unwrap()
+    }
+
+    fn get_tags(vm: &VM, tags: usize) -> Vec<usize> {
+        vm.get_tags(tags).unwrap().to_vec()
+    }
+
     #[test]
     fn test_add_tags_on_empty_array() {
-        let memory = Memory::new(110);
-        let mut allocator = Allocator::new(110);
-        let address = allocator.malloc(0, std::iter::empty()).unwrap();
-        let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
-        vm.stack[0] = CompoundValue::SimpleValue(Value::String(142));
+        let mut vm = VM::test_vm_with_memory_and_allocator(2, Memory::new(110), Allocator::new(110));
+        let tag = allocate_string(&vm, "a");
+        let address = allocate_tags(&vm, &[]);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::String(tag));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Object {
             address: 0,
             tags: address,
@@ -2516,8 +3603,7 @@ mod cpu_tests {
         if let CompoundValue::SimpleValue(Value::Object {
             tags, address: 0
         }) = vm.stack[0] {
-            let string_address = *vm.memory.get_t::<usize>(tags).unwrap();
-            assert_eq!(string_address, 142);
+            assert_eq!(get_tags(&vm, tags), vec![tag]);
         } else {
             panic!("Invalid value {:?}", vm.stack[0]);
         }
@@ -2525,12 +3611,12 @@ mod cpu_tests {
 
     #[test]
     fn test_add_tags_on_non_empty_array() {
-        let memory = Memory::new(110);
-        let mut allocator = Allocator::new(110);
-        let address = allocator.malloc(USIZE_SIZE * 2, std::iter::empty()).unwrap();
-        memory.copy_t_slice(&[142usize, 144], address);
-        let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
-        vm.stack[0] = CompoundValue::SimpleValue(Value::String(143));
+        let mut vm = VM::test_vm_with_memory_and_allocator(2, Memory::new(110), Allocator::new(110));
+        let c = allocate_string(&vm, "c");
+        let b = allocate_string(&vm, "b");
+        let a = allocate_string(&vm, "a");
+        let address = allocate_tags(&vm, &[a, c]);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::String(b));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Object {
             address: 0,
             tags: address,
@@ -2542,16 +3628,7 @@ mod cpu_tests {
         if let CompoundValue::SimpleValue(Value::Object {
             tags, address: 0
         }) = vm.stack[0] {
-            assert_eq!(
-                Some(3 * USIZE_SIZE),
-                vm.allocator.borrow().get_allocated_space(tags)
-            );
-            let string_address = *vm.memory.get_t::<usize>(tags).unwrap();
-            assert_eq!(string_address, 142);
-            let string_address = *vm.memory.get_t::<usize>(tags + USIZE_SIZE).unwrap();
-            assert_eq!(string_address, 143);
-            let string_address = *vm.memory.get_t::<usize>(tags + USIZE_SIZE * 2).unwrap();
-            assert_eq!(string_address, 144);
+            assert_eq!(get_tags(&vm, tags), vec![a, b, c]);
         } else {
             panic!("Invalid value {:?}", vm.stack[0]);
         }
@@ -2559,12 +3636,12 @@ mod cpu_tests {
 
     #[test]
     fn test_add_tags_on_array_with_duplicated() {
-        let memory = Memory::new(110);
-        let mut allocator = Allocator::new(110);
-        let address = allocator.malloc(USIZE_SIZE * 2, std::iter::empty()).unwrap();
-        memory.copy_t_slice(&[142usize, 143], address);
-        let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
-        vm.stack[0] = CompoundValue::SimpleValue(Value::String(142));
+        let mut vm = VM::test_vm_with_memory_and_allocator(2, Memory::new(110), Allocator::new(110));
+        let a = allocate_string(&vm, "a");
+        let b = allocate_string(&vm, "b");
+        let other_a = allocate_string(&vm, "a");
+        let address = allocate_tags(&vm, &[a, b]);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::String(other_a));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Object {
             address: 0,
             tags: address,
@@ -2576,26 +3653,45 @@ mod cpu_tests {
         if let CompoundValue::SimpleValue(Value::Object {
             tags, address: 0
         }) = vm.stack[0] {
-            assert_eq!(
-                Some(2 * USIZE_SIZE),
-                vm.allocator.borrow().get_allocated_space(tags)
-            );
-            let string_address = *vm.memory.get_t::<usize>(tags).unwrap();
-            assert_eq!(string_address, 142);
-            let string_address = *vm.memory.get_t::<usize>(tags + USIZE_SIZE).unwrap();
-            assert_eq!(string_address, 143);
+            assert_eq!(tags, address);
+            assert_eq!(get_tags(&vm, tags), vec![a, b]);
         } else {
             panic!("Invalid value {:?}", vm.stack[0]);
         }
     }
 
+    #[test]
+    fn test_check_tag_compares_contents() {
+        let mut vm = VM::test_vm_with_memory_and_allocator(2, Memory::new(110), Allocator::new(110));
+        let tag = allocate_string(&vm, "enemy");
+        let same_tag = allocate_string(&vm, "enemy");
+        let other_tag = allocate_string(&vm, "ally");
+        let address = allocate_tags(&vm, &[tag]);
+        let object = CompoundValue::SimpleValue(Value::Object {
+            address: 0,
+            tags: address,
+        });
+        vm.stack[0] = CompoundValue::SimpleValue(Value::String(same_tag));
+        vm.stack[1] = object.clone();
+        vm.execute_instruction(create_instruction(InstructionType::CheckTag))
+            .unwrap();
+        assert_eq!(vm.sp, 1);
+        assert_eq!(vm.stack[0], CompoundValue::SimpleValue(Value::Bool(true)));
+
+        vm.stack[0] = CompoundValue::SimpleValue(Value::String(other_tag));
+        vm.stack[1] = object;
+        vm.sp = 2;
+        vm.execute_instruction(create_instruction(InstructionType::CheckTag))
+            .unwrap();
+        assert_eq!(vm.stack[0], CompoundValue::SimpleValue(Value::Bool(false)));
+    }
+
     #[test]
     fn test_remove_tags_on_empty_array() {
-        let memory = Memory::new(110);
-        let allocator = Allocator::new(110);
-        let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
-        let address = vm.allocator.borrow_mut().malloc(0, std::iter::empty()).unwrap();
-        vm.stack[0] = CompoundValue::SimpleValue(Value::String(142));
+        let mut vm = VM::test_vm_with_memory_and_allocator(2, Memory::new(110), Allocator::new(110));
+        let tag = allocate_string(&vm, "a");
+        let address = allocate_tags(&vm, &[]);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::String(tag));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Object {
             address: 0,
             tags: address,
@@ -2608,7 +3704,8 @@ mod cpu_tests {
                                               tags, address: 0
                                           }) = vm.stack[0] {
             let size = vm.allocator.borrow_mut().get_allocated_space(tags).unwrap();
-            assert_eq!(size, 0);
+            assert_eq!(size, USIZE_SIZE);
+            assert!(get_tags(&vm, tags).is_empty());
         } else {
             panic!("Invalid value {:?}", vm.stack[0]);
         }
@@ -2616,12 +3713,13 @@ mod cpu_tests {
 
     #[test]
     fn test_remove_tags_on_non_empty_array() {
-        let memory = Memory::new(110);
-        let allocator = Allocator::new(110);
-        let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
-        let address = vm.allocator.borrow_mut().malloc(USIZE_SIZE * 3, std::iter::empty()).unwrap();
-        vm.memory.copy_t_slice(&[142usize, 143, 144], address);
-        vm.stack[0] = CompoundValue::SimpleValue(Value::String(143));
+        let mut vm = VM::test_vm_with_memory_and_allocator(2, Memory::new(110), Allocator::new(110));
+        let a = allocate_string(&vm, "a");
+        let b = allocate_string(&vm, "b");
+        let c = allocate_string(&vm, "c");
+        let other_b = allocate_string(&vm, "b");
+        let address = allocate_tags(&vm, &[a, b, c]);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::String(other_b));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Object {
             address: 0,
             tags: address,
@@ -2633,14 +3731,7 @@ mod cpu_tests {
         if let CompoundValue::SimpleValue(Value::Object {
                                               tags, address: 0
                                           }) = vm.stack[0] {
-            assert_eq!(
-                Some(2 * USIZE_SIZE),
-                vm.allocator.borrow().get_allocated_space(tags)
-            );
-            let string_address = *vm.memory.get_t::<usize>(tags).unwrap();
-            assert_eq!(string_address, 142);
-            let string_address = *vm.memory.get_t::<usize>(tags + USIZE_SIZE * 1).unwrap();
-            assert_eq!(string_address, 144);
+            assert_eq!(get_tags(&vm, tags), vec![a, c]);
         } else {
             panic!("Invalid value {:?}", vm.stack[0]);
         }
@@ -2648,12 +3739,12 @@ mod cpu_tests {
 
     #[test]
     fn test_remove_tag_with_tag_not_there() {
-        let memory = Memory::new(110);
-        let allocator = Allocator::new(110);
-        let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
-        let address = vm.allocator.borrow_mut().malloc(USIZE_SIZE * 2, std::iter::empty()).unwrap();
-        vm.memory.copy_t_slice(&[142usize, 144], address);
-        vm.stack[0] = CompoundValue::SimpleValue(Value::String(143));
+        let mut vm = VM::test_vm_with_memory_and_allocator(2, Memory::new(110), Allocator::new(110));
+        let a = allocate_string(&vm, "a");
+        let b = allocate_string(&vm, "b");
+        let c = allocate_string(&vm, "c");
+        let address = allocate_tags(&vm, &[a, c]);
+        vm.stack[0] = CompoundValue::SimpleValue(Value::String(b));
         vm.stack[1] = CompoundValue::SimpleValue(Value::Object {
             address: 0,
             tags: address,
@@ -2666,7 +3757,7 @@ mod cpu_tests {
                                               tags, address: 0
                                           }) = vm.stack[0] {
             assert_eq!(
-                Some(2 * USIZE_SIZE),
+                Some(3 * USIZE_SIZE),
                 vm.allocator.borrow().get_allocated_space(tags)
             );
             assert_eq!(tags, address);
@@ -2677,21 +3768,23 @@ mod cpu_tests {
 
     #[test]
     fn test_object_merge_merges_tags() {
-        let memory = Memory::new(220);
-        let mut allocator = Allocator::new(220);
+        let mut vm = VM::test_vm_with_memory_and_allocator(2, Memory::new(220), Allocator::new(220));
+        let a = allocate_string(&vm, "a");
+        let b = allocate_string(&vm, "b");
+        let c = allocate_string(&vm, "c");
+        let other_c = allocate_string(&vm, "c");
+        let mut allocator = vm.allocator.borrow_mut();
         let address = allocator.malloc(USIZE_SIZE, std::iter::empty()).unwrap();
         let obj_address = allocator.malloc(USIZE_SIZE, std::iter::empty()).unwrap();
-        let tags_address = allocator.malloc(USIZE_SIZE * 2, std::iter::empty()).unwrap();
         let address2 = allocator.malloc(USIZE_SIZE, std::iter::empty()).unwrap();
         let obj_address2 = allocator.malloc(USIZE_SIZE, std::iter::empty()).unwrap();
-        let tags_address2 = allocator.malloc(USIZE_SIZE * 2, std::iter::empty()).unwrap();
-        memory.copy_t(&obj_address, address);
-        memory.copy_t(&0usize, obj_address);
-        memory.copy_t_slice(&[142usize, 144], tags_address);
-        memory.copy_t(&obj_address2, address2);
-        memory.copy_t(&0usize, obj_address2);
-        memory.copy_t_slice(&[143usize, 144], tags_address2);
-        let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
+        drop(allocator);
+        let tags_address = allocate_tags(&vm, &[a, c]);
+        let tags_address2 = allocate_tags(&vm, &[b, other_c]);
+        vm.memory.copy_t(&obj_address, address).unwrap();
+        vm.memory.copy_t(&0usize, obj_address).unwrap();
+        vm.memory.copy_t(&obj_address2, address2).unwrap();
+        vm.memory.copy_t(&0usize, obj_address2).unwrap();
         vm.stack[0] = CompoundValue::SimpleValue(Value::Object {
             address,
             tags: tags_address,
@@ -2707,22 +3800,13 @@ mod cpu_tests {
                                               tags, address
                                           }) = vm.stack[0] {
             let address = *vm.memory.get_t::<usize>(address).unwrap();
-            assert_eq!(
-                Some(3 * USIZE_SIZE),
-                vm.allocator.borrow().get_allocated_space(tags)
-            );
             assert_eq!(
                 Some(USIZE_SIZE),
                 vm.allocator.borrow().get_allocated_space(address)
             );
             let object_length = *vm.memory.get_t::<usize>(address).unwrap();
             assert_eq!(object_length, 0);
-            let string_address = *vm.memory.get_t::<usize>(tags).unwrap();
-            assert_eq!(string_address, 142);
-            let string_address = *vm.memory.get_t::<usize>(tags + USIZE_SIZE).unwrap();
-            assert_eq!(string_address, 143);
-            let string_address = *vm.memory.get_t::<usize>(tags + USIZE_SIZE * 2).unwrap();
-            assert_eq!(string_address, 144);
+            assert_eq!(get_tags(&vm, tags), vec![a, b, other_c]);
         } else {
             panic!("Invalid value {:?}", vm.stack[0]);
         }
@@ -2730,8 +3814,8 @@ mod cpu_tests {
 
     #[test]
     fn test_object_merge_merges_properties() {
-        let memory = Memory::new(440);
-        let mut allocator = Allocator::new(440);
+        let memory = Memory::new(560);
+        let mut allocator = Allocator::new(560);
         let prop_address = allocator.malloc(1, std::iter::empty()).unwrap();
         let prop1_address = allocator.malloc(1, std::iter::empty()).unwrap();
         let prop2_address = allocator.malloc(1, std::iter::empty()).unwrap();
@@ -2740,17 +3824,18 @@ mod cpu_tests {
         let props_address2 = allocator.malloc(USIZE_SIZE + 2 * (USIZE_SIZE + VALUE_SIZE), std::iter::empty()).unwrap();
         let address = allocator.malloc(USIZE_SIZE, std::iter::empty()).unwrap();
         let address2 = allocator.malloc(USIZE_SIZE, std::iter::empty()).unwrap();
-        let tags_address = allocator.malloc(0, std::iter::empty()).unwrap();
+        let tags_address = allocator.malloc(USIZE_SIZE, std::iter::empty()).unwrap();
+        memory.copy_t(&0usize, tags_address).unwrap();
         memory.copy_string("A", prop_address);
         memory.copy_string("B", prop1_address);
         memory.copy_string("B", prop2_address);
         memory.copy_string("C", prop3_address);
-        memory.copy_t(&props_address, address);
-        memory.copy_t(&props_address2, address2);
-        memory.copy_t(&2usize, props_address);
-        memory.copy_t_slice(&[(prop_address, Value::Nil), (prop1_address, Value::Nil)], props_address + USIZE_SIZE);
-        memory.copy_t(&2usize, props_address2);
-        memory.copy_t_slice(&[(prop2_address, Value::Nil), (prop3_address, Value::Nil)], props_address2 + USIZE_SIZE);
+        memory.copy_t(&props_address, address).unwrap();
+        memory.copy_t(&props_address2, address2).unwrap();
+        memory.copy_t(&2usize, props_address).unwrap();
+        memory.copy_t_slice(&[(prop_address, Value::Nil), (prop1_address, Value::Nil)], props_address + USIZE_SIZE).unwrap();
+        memory.copy_t(&2usize, props_address2).unwrap();
+        memory.copy_t_slice(&[(prop2_address, Value::Nil), (prop3_address, Value::Nil)], props_address2 + USIZE_SIZE).unwrap();
         let mut vm = VM::test_vm_with_memory_and_allocator(2, memory, allocator);
         vm.stack[0] = CompoundValue::SimpleValue(Value::Object {
             address,
@@ -2769,9 +3854,10 @@ mod cpu_tests {
                                           }) = vm.stack[0] {
             let address = *vm.memory.get_t::<usize>(address).unwrap();
             assert_eq!(
-                Some(0),
+                Some(USIZE_SIZE),
                 vm.allocator.borrow().get_allocated_space(tags)
             );
+            assert!(get_tags(&vm, tags).is_empty());
             assert_eq!(
                 Some(USIZE_SIZE + 3 * (USIZE_SIZE + VALUE_SIZE)),
                 vm.allocator.borrow().get_allocated_space(address)
@@ -2788,4 +3874,107 @@ mod cpu_tests {
             panic!("Invalid value {:?}", vm.stack[0]);
         }
     }
+
+    #[test]
+    fn test_roots_trace_constant_arrays() {
+        let bytes = crate::serde::to_bytes(
+            &[
+                ConstantDeclaration::Value(Value::String(0)),
+                ConstantDeclaration::Array(vec![0]),
+                ConstantDeclaration::Array(vec![1]),
+            ],
+            &[],
+            b"ab",
+            &[create_instruction(InstructionType::Noop)],
+        );
+        let vm = crate::serde::from_bytes(&bytes, None).unwrap();
+        let inner = match vm.constants[1] {
+            CompoundValue::SimpleValue(Value::Array { address, .. }) => address,
+            ref v => panic!("Invalid value {:?}", v),
+        };
+        let outer = match vm.constants[2] {
+            CompoundValue::SimpleValue(Value::Array { address, .. }) => address,
+            ref v => panic!("Invalid value {:?}", v),
+        };
+        assert_eq!(
+            vm.get_addresses_from_value(&Value::Array { address: outer, capacity: 1 }),
+            vec![outer, inner, 0]
+        );
+        let roots: Vec<usize> = vm.get_roots().collect();
+        assert!(roots.contains(&inner));
+        assert!(roots.contains(&outer));
+    }
+
+    #[test]
+    fn test_roots_trace_uplifted_values() -> Result<(), Error> {
+        let mut vm = VM::new(Allocator::new(256), vec![], vec![], Memory::new(256), vec![]);
+        let garbage = vm.malloc(2)?;
+        let string = vm.malloc(2)?;
+        vm.memory.copy_u8_vector(b"hi", string)?;
+        let cell = vm.malloc(COMPOUND_VALUE_SIZE)?;
+        vm.memory.copy_t(&CompoundValue::SimpleValue(Value::String(string)), cell)?;
+        let uplifts = match vm.build_array(&[CompoundValue::SimpleValue(Value::Pointer(cell))])? {
+            Value::Array { address, .. } => address,
+            v => panic!("Invalid value {:?}", v),
+        };
+        vm.store_global(0, CompoundValue::SimpleValue(Value::Function {
+            ip: 0,
+            arity: 0,
+            uplifts: Some(uplifts),
+            defaults: None,
+        }));
+        let roots: Vec<usize> = vm.get_roots().collect();
+        assert!(roots.contains(&uplifts));
+        assert!(roots.contains(&cell));
+        assert!(roots.contains(&string));
+        assert!(!roots.contains(&garbage));
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_concat_collects_garbage() -> Result<(), Error> {
+        // Every iteration allocates four bytes, way more than the memory has in total
+        let rom: Vec<Instruction> = vec![
+            InstructionType::Constant(1),
+            InstructionType::SetGlobal(0),
+            InstructionType::Pop,
+            InstructionType::GetGlobal(0),
+            InstructionType::Constant(3),
+            InstructionType::Less,
+            InstructionType::JmpIfFalse(10),
+            InstructionType::Constant(0),
+            InstructionType::Constant(0),
+            InstructionType::StringConcat,
+            InstructionType::Pop,
+            InstructionType::GetGlobal(0),
+            InstructionType::Constant(2),
+            InstructionType::Plus,
+            InstructionType::SetGlobal(0),
+            InstructionType::Pop,
+            InstructionType::Loop(14),
+            InstructionType::Constant(0),
+            InstructionType::Constant(0),
+            InstructionType::StringConcat,
+            InstructionType::Return,
+        ]
+        .into_iter()
+        .map(create_instruction)
+        .collect();
+        let bytes = crate::serde::to_bytes(
+            &[Value::String(0), Value::Integer(0), Value::Integer(1), Value::Integer(1000)],
+            &[],
+            b"ab",
+            &rom,
+        );
+        let mut vm = crate::serde::from_bytes(&bytes, Some(64))?;
+        match vm.run()? {
+            Some(CompoundValue::SimpleValue(Value::String(address))) => {
+                assert_eq!(vm.address_to_string(address)?, "abab");
+            }
+            v => panic!("Invalid value {:?}", v),
+        }
+        assert_eq!(vm.global(0), Some(&CompoundValue::SimpleValue(Value::Integer(1000))));
+        Ok(())
+    }
 }
+
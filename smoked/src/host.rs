@@ -0,0 +1,139 @@
+use crate::cpu::VM;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// Time that every instruction takes when the clock is deterministic
+const SECONDS_PER_INSTRUCTION: f64 = 0.000_001;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Host {
+    start: Instant,
+    deterministic_clock: bool,
+    instructions: u64,
+    random_state: u64,
+}
+
+impl Host {
+    pub(crate) fn new() -> Host {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        Host {
+            start: Instant::now(),
+            deterministic_clock: false,
+            instructions: 0,
+            random_state: seed,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn count_instruction(&mut self) {
+        self.instructions += 1;
+    }
+
+    pub(crate) fn seconds(&self) -> f64 {
+        if self.deterministic_clock {
+            self.instructions as f64 * SECONDS_PER_INSTRUCTION
+        } else {
+            self.start.elapsed().as_secs_f64()
+        }
+    }
+
+    // splitmix64, so a seed gives the same sequence on every platform
+    pub(crate) fn next_random(&mut self) -> f32 {
+        self.random_state = self.random_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.random_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+impl VM {
+    pub fn seed_random(&mut self, seed: u64) {
+        self.host.random_state = seed;
+    }
+
+    // Makes Clock derive the time from the number of instructions executed, for reproducible runs
+    pub fn set_deterministic_clock(&mut self, deterministic: bool) {
+        self.host.deterministic_clock = deterministic;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu::{CompoundValue, Value, VM};
+    use crate::instruction::{Instruction, InstructionType};
+
+    fn create_instruction(instruction_type: InstructionType) -> Instruction {
+        Instruction {
+            instruction_type,
+            location: 0,
+        }
+    }
+
+    fn create_vm(instruction_type: InstructionType, times: usize) -> VM {
+        let rom = (0..times)
+            .flat_map(|_| {
+                vec![
+                    create_instruction(instruction_type.clone()),
+                    create_instruction(InstructionType::SetGlobal(0)),
+                    create_instruction(InstructionType::Pop),
+                ]
+            })
+            .collect();
+        let mut vm = VM::new(
+            crate::allocator::Allocator::new(0),
+            vec![],
+            vec![],
+            crate::memory::Memory::new(0),
+            rom,
+        );
+        vm.new_frame(0, 0);
+        vm
+    }
+
+    fn trace(mut vm: VM) -> Vec<f32> {
+        let mut result = vec![];
+        while !vm.is_done() {
+            for _ in 0..3 {
+                vm.execute().unwrap();
+            }
+            match vm.global(0) {
+                Some(CompoundValue::SimpleValue(Value::Float(f))) => result.push(*f),
+                v => panic!("Invalid value {:?}", v),
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn test_seeded_random_is_reproducible() {
+        let mut first = create_vm(InstructionType::Random, 10);
+        first.seed_random(42);
+        let mut second = create_vm(InstructionType::Random, 10);
+        second.seed_random(42);
+        let first = trace(first);
+        assert_eq!(first, trace(second));
+        assert!(first.iter().all(|f| *f >= 0.0 && *f < 1.0));
+        assert!(first.windows(2).any(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn test_clock_is_non_decreasing() {
+        let values = trace(create_vm(InstructionType::Clock, 100));
+        assert!(values.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_deterministic_clock_is_reproducible() {
+        let mut first = create_vm(InstructionType::Clock, 10);
+        first.set_deterministic_clock(true);
+        let mut second = create_vm(InstructionType::Clock, 10);
+        second.set_deterministic_clock(true);
+        let first = trace(first);
+        assert_eq!(first, trace(second));
+        assert!(first.windows(2).all(|w| w[0] < w[1]));
+    }
+}
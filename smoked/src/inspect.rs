@@ -0,0 +1,390 @@
+use crate::cpu::{CompoundValue, VMErrorType, Value, COMPOUND_VALUE_SIZE, VM};
+use failure::Error;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+// A read only look at a value, with what it points to in the heap already resolved
+pub enum ValueView<'a> {
+    Nil,
+    Integer(i64),
+    Float(f32),
+    Bool(bool),
+    String(&'a str),
+    Function {
+        ip: usize,
+        arity: usize,
+    },
+    PartialFunction {
+        function: Value,
+        arguments: &'a [Value],
+    },
+    Array(ArrayView<'a>),
+    Object(ObjectView<'a>),
+}
+
+pub struct ArrayView<'a> {
+    vm: &'a VM,
+    address: usize,
+    len: usize,
+}
+
+impl<'a> ArrayView<'a> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<CompoundValue> {
+        if index >= self.len {
+            return None;
+        }
+        self.vm
+            .memory
+            .get_t::<CompoundValue>(self.address + index * COMPOUND_VALUE_SIZE)
+            .ok()
+            .cloned()
+    }
+}
+
+pub struct ObjectView<'a> {
+    vm: &'a VM,
+    address: usize,
+    tags: usize,
+}
+
+impl<'a> ObjectView<'a> {
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    // Sorted by name, like the VM keeps them
+    pub fn properties(&self) -> Result<Vec<(String, CompoundValue)>, Error> {
+        let mut properties = vec![];
+        for (name, value) in self.vm.get_properties(self.address)? {
+            properties.push((
+                self.vm.address_to_string(*name)?.to_owned(),
+                CompoundValue::SimpleValue(*value),
+            ));
+        }
+        Ok(properties)
+    }
+
+    pub fn tags(&self) -> Result<Vec<String>, Error> {
+        let mut tags = vec![];
+        for tag in self.vm.get_tags(self.tags)? {
+            tags.push(self.vm.address_to_string(*tag)?.to_owned());
+        }
+        Ok(tags)
+    }
+}
+
+// What ToStr produces
+impl<'a> Display for ValueView<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueView::Nil => f.write_str("nil"),
+            ValueView::Integer(i) => write!(f, "{}", i),
+            ValueView::Float(n) => write!(f, "{}", n),
+            ValueView::Bool(b) => write!(f, "{}", b),
+            ValueView::String(s) => f.write_str(s),
+            ValueView::Function { .. } => f.write_str("[function]"),
+            ValueView::PartialFunction { .. } => f.write_str("[partial function]"),
+            ValueView::Array(_) => f.write_str("[array]"),
+            ValueView::Object(object) => write!(f, "[object {}]", object.address),
+        }
+    }
+}
+
+impl VM {
+    // Lets hosts, like debuggers and serializers, walk values without knowing the heap layout
+    pub fn inspect<'a>(&'a self, value: &'a CompoundValue) -> Result<ValueView<'a>, Error> {
+        let mut value = value;
+        let mut pointers = HashSet::new();
+        let value = loop {
+            value = match value {
+                CompoundValue::SimpleValue(Value::Pointer(address)) => {
+                    if !pointers.insert(*address) {
+                        Err(VMErrorType::PointerCycle(*address))?;
+                    }
+                    self.memory.get_t::<CompoundValue>(*address)?
+                }
+                CompoundValue::SimpleValue(value) => break value,
+                CompoundValue::PartialFunction {
+                    function,
+                    arguments,
+                } => {
+                    return Ok(ValueView::PartialFunction {
+                        function: *function,
+                        arguments,
+                    })
+                }
+            };
+        };
+        Ok(match *value {
+            Value::Nil => ValueView::Nil,
+            Value::Integer(i) => ValueView::Integer(i),
+            Value::Float(f) => ValueView::Float(f),
+            Value::Bool(b) => ValueView::Bool(b),
+            Value::String(address) => ValueView::String(self.address_to_string(address)?),
+            Value::Pointer(_) => unreachable!("Pointers are followed above"),
+            Value::Function { ip, arity, .. } => ValueView::Function { ip, arity },
+            Value::Array { capacity, address } => ValueView::Array(ArrayView {
+                vm: self,
+                address,
+                len: capacity,
+            }),
+            Value::Object { address, tags } => ValueView::Object(ObjectView {
+                vm: self,
+                address,
+                tags,
+            }),
+        })
+    }
+
+    // Compares the contents of strings, arrays and objects instead of their addresses
+    pub fn deep_equal(&self, first: &CompoundValue, second: &CompoundValue) -> Result<bool, Error> {
+        self.deep_equal_seen(first, second, &mut HashSet::new())
+    }
+
+    // Pairs of arrays or objects already being compared count as equal, so cycles end
+    fn deep_equal_seen(
+        &self,
+        first: &CompoundValue,
+        second: &CompoundValue,
+        seen: &mut HashSet<(usize, usize)>,
+    ) -> Result<bool, Error> {
+        Ok(match (self.inspect(first)?, self.inspect(second)?) {
+            (ValueView::Nil, ValueView::Nil) => true,
+            (ValueView::Integer(a), ValueView::Integer(b)) => a == b,
+            (ValueView::Float(a), ValueView::Float(b)) => a == b,
+            (ValueView::Bool(a), ValueView::Bool(b)) => a == b,
+            (ValueView::String(a), ValueView::String(b)) => a == b,
+            (
+                ValueView::Function { ip, arity },
+                ValueView::Function {
+                    ip: other_ip,
+                    arity: other_arity,
+                },
+            ) => ip == other_ip && arity == other_arity,
+            (
+                ValueView::PartialFunction {
+                    function,
+                    arguments,
+                },
+                ValueView::PartialFunction {
+                    function: other_function,
+                    arguments: other_arguments,
+                },
+            ) => function == other_function && arguments == other_arguments,
+            (ValueView::Array(a), ValueView::Array(b)) => {
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                if !seen.insert((a.address, b.address)) {
+                    return Ok(true);
+                }
+                for index in 0..a.len() {
+                    let (value, other_value) = (a.get(index).unwrap(), b.get(index).unwrap());
+                    if !self.deep_equal_seen(&value, &other_value, seen)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            (ValueView::Object(a), ValueView::Object(b)) => {
+                let (properties, other_properties) = (a.properties()?, b.properties()?);
+                if a.tags()? != b.tags()? || properties.len() != other_properties.len() {
+                    return Ok(false);
+                }
+                if !seen.insert((a.address, b.address)) {
+                    return Ok(true);
+                }
+                for ((name, value), (other_name, other_value)) in
+                    properties.iter().zip(other_properties.iter())
+                {
+                    if name != other_name || !self.deep_equal_seen(value, other_value, seen)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            _ => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::Allocator;
+    use crate::cpu::{CompoundValue, Value, COMPOUND_VALUE_SIZE, VM};
+    use crate::inspect::ValueView;
+    use crate::instruction::{Instruction, InstructionType};
+    use crate::memory::Memory;
+
+    fn create_instruction(instruction_type: InstructionType) -> Instruction {
+        Instruction {
+            instruction_type,
+            location: 0,
+        }
+    }
+
+    fn allocate_string(allocator: &mut Allocator, memory: &Memory, value: &str) -> CompoundValue {
+        let address = allocator.malloc(value.len(), std::iter::empty()).unwrap();
+        memory.copy_string(value, address);
+        CompoundValue::SimpleValue(Value::String(address))
+    }
+
+    // Leaves {name: ["hi", 1.5]} tagged as "tag" in global 1, and the array in global 0
+    fn create_vm() -> VM {
+        let mut allocator = Allocator::new(1000);
+        let memory = Memory::new(1000);
+        let constants = vec![
+            CompoundValue::SimpleValue(Value::Integer(2)),
+            allocate_string(&mut allocator, &memory, "name"),
+            allocate_string(&mut allocator, &memory, "tag"),
+            allocate_string(&mut allocator, &memory, "hi"),
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(Value::Integer(0)),
+            CompoundValue::SimpleValue(Value::Float(1.5)),
+        ];
+        let rom = vec![
+            InstructionType::Constant(0),
+            InstructionType::ArrayAlloc,
+            InstructionType::SetGlobal(0),
+            InstructionType::Pop,
+            InstructionType::Constant(3),
+            InstructionType::Constant(5),
+            InstructionType::GetGlobal(0),
+            InstructionType::ArraySet,
+            InstructionType::Pop,
+            InstructionType::Constant(6),
+            InstructionType::Constant(4),
+            InstructionType::GetGlobal(0),
+            InstructionType::ArraySet,
+            InstructionType::Pop,
+            InstructionType::Constant(4),
+            InstructionType::ObjectAlloc,
+            InstructionType::SetGlobal(1),
+            InstructionType::Pop,
+            InstructionType::GetGlobal(0),
+            InstructionType::Constant(1),
+            InstructionType::GetGlobal(1),
+            InstructionType::ObjectSet,
+            InstructionType::Pop,
+            InstructionType::Pop,
+            InstructionType::Constant(2),
+            InstructionType::GetGlobal(1),
+            InstructionType::AddTag,
+            InstructionType::SetGlobal(1),
+            InstructionType::Pop,
+        ];
+        let rom = rom.into_iter().map(create_instruction).collect();
+        let mut vm = VM::new(allocator, constants, vec![], memory, rom);
+        vm.new_frame(0, 0);
+        while !vm.is_done() {
+            vm.execute().unwrap();
+        }
+        vm
+    }
+
+    #[test]
+    fn test_inspect_nested_values() {
+        let vm = create_vm();
+        let object = vm.global(1).unwrap().clone();
+        let object = match vm.inspect(&object).unwrap() {
+            ValueView::Object(object) => object,
+            _ => panic!("Expected an object"),
+        };
+        assert_eq!(object.tags().unwrap(), vec!["tag".to_owned()]);
+        let properties = object.properties().unwrap();
+        assert_eq!(properties.len(), 1);
+        assert_eq!(properties[0].0, "name");
+        let array = match vm.inspect(&properties[0].1).unwrap() {
+            ValueView::Array(array) => array,
+            _ => panic!("Expected an array"),
+        };
+        assert_eq!(array.len(), 2);
+        assert!(array.get(2).is_none());
+        let elements: Vec<String> = (0..array.len())
+            .map(|i| vm.inspect(&array.get(i).unwrap()).unwrap().to_string())
+            .collect();
+        assert_eq!(elements, vec!["hi", "1.5"]);
+    }
+
+    #[test]
+    fn test_inspect_follows_pointers() {
+        let vm = create_vm();
+        let address = vm
+            .allocator
+            .borrow_mut()
+            .malloc(COMPOUND_VALUE_SIZE, std::iter::empty())
+            .unwrap();
+        vm.memory.copy_t(vm.global(0).unwrap(), address).unwrap();
+        let pointer = CompoundValue::SimpleValue(Value::Pointer(address));
+        match vm.inspect(&pointer).unwrap() {
+            ValueView::Array(array) => assert_eq!(array.len(), 2),
+            _ => panic!("Expected an array"),
+        }
+    }
+
+    #[test]
+    fn test_deep_equal_compares_contents() {
+        let vm = create_vm();
+        let array = vm.global(0).unwrap().clone();
+        let hi = vm
+            .allocator
+            .borrow_mut()
+            .malloc(2, std::iter::empty())
+            .unwrap();
+        vm.memory.copy_string("hi", hi);
+        let elements = vec![
+            CompoundValue::SimpleValue(Value::String(hi)),
+            CompoundValue::SimpleValue(Value::Float(1.5)),
+        ];
+        let copy = CompoundValue::SimpleValue(vm.build_array(&elements).unwrap());
+        assert_ne!(array, copy);
+        assert!(vm.deep_equal(&array, &copy).unwrap());
+        let different = CompoundValue::SimpleValue(vm.build_array(&elements[..1]).unwrap());
+        assert!(!vm.deep_equal(&array, &different).unwrap());
+        let object = vm.global(1).unwrap().clone();
+        assert!(vm.deep_equal(&object, &object).unwrap());
+        assert!(!vm.deep_equal(&object, &array).unwrap());
+    }
+
+    #[test]
+    fn test_deep_equal_survives_cycles() {
+        let vm = create_vm();
+        let nil = [CompoundValue::SimpleValue(Value::Nil)];
+        let (first, second) = (vm.build_array(&nil).unwrap(), vm.build_array(&nil).unwrap());
+        for array in [first, second].iter() {
+            if let Value::Array { address, .. } = array {
+                let itself = CompoundValue::SimpleValue(*array);
+                vm.memory.copy_t(&itself, *address).unwrap();
+            }
+        }
+        let (first, second) = (
+            CompoundValue::SimpleValue(first),
+            CompoundValue::SimpleValue(second),
+        );
+        assert!(vm.deep_equal(&first, &second).unwrap());
+        let object = vm.global(1).unwrap().clone();
+        let different = CompoundValue::SimpleValue(vm.build_array(&[object]).unwrap());
+        assert!(!vm.deep_equal(&first, &different).unwrap());
+    }
+
+    #[test]
+    fn test_inspect_rejects_pointer_cycles() {
+        let vm = create_vm();
+        let address = vm
+            .allocator
+            .borrow_mut()
+            .malloc(COMPOUND_VALUE_SIZE, std::iter::empty())
+            .unwrap();
+        let pointer = CompoundValue::SimpleValue(Value::Pointer(address));
+        vm.memory.copy_t(&pointer, address).unwrap();
+        assert!(vm.inspect(&pointer).is_err());
+    }
+}
@@ -1,4 +1,10 @@
-use log::warn;
+use crate::serde::{deserialize_usize, SerdeError, SERIALIZED_USIZE_SIZE};
+use std::convert::TryFrom;
+use std::fmt;
+
+// The highest opcode of the binary format. Every opcode up to it decodes, and so does 255 (Noop)
+pub const LAST_OPCODE: u8 = 63;
+pub const NOOP_OPCODE: u8 = 255;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum InstructionType {
@@ -54,6 +60,20 @@ pub enum InstructionType {
     ObjectMerge,
     RemoveTag,
     Duplicate,
+    Clock,
+    Random,
+    CheckedPlus,
+    CheckedMinus,
+    CheckedMult,
+    Yield,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    // Calls with the number of arguments pushed, so the function can fill in the rest
+    CallN(usize),
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -64,262 +84,294 @@ pub struct Instruction {
 
 impl Instruction {
     pub fn size(&self) -> usize {
-        match &self.instruction_type {
-            InstructionType::Constant(_) | InstructionType::SetGlobal(_) | InstructionType::GetGlobal(_) |
-            InstructionType::SetLocal(_) | InstructionType::GetLocal(_) | InstructionType::Jmp(_) |
-            InstructionType::JmpIfFalse(_) | InstructionType::Loop(_) | InstructionType::Uplift(_) |
-            InstructionType::AttachArray(_) | InstructionType::CheckType(_) => 17,
-            _ => 9,
+        match self.instruction_type.operand() {
+            Some(_) => 17,
+            None => 9,
         }
     }
 }
 
-#[inline]
-fn create_instruction(instruction_type: InstructionType, bytes: &[u8]) -> Instruction {
-    Instruction {
-        instruction_type,
-        location: usize::from_le_bytes(
-            [bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7]],
-        ),
-    }
-}
-
 impl Into<Vec<u8>> for Instruction {
     fn into(self) -> Vec<u8> {
-        let mut bytes = vec![];
-        match self.instruction_type {
-            InstructionType::Return => bytes.push(0),
-            InstructionType::Constant(b) => {
-                bytes.push(1);
-                bytes.extend_from_slice(&b.to_le_bytes());
-            },
-            InstructionType::Plus => bytes.push(2),
-            InstructionType::Minus => bytes.push(3),
-            InstructionType::Mult => bytes.push(4),
-            InstructionType::Div => bytes.push(5),
-            InstructionType::Noop => bytes.push(255),
-            InstructionType::Nil => bytes.push(6),
-            InstructionType::True => bytes.push(7),
-            InstructionType::False => bytes.push(8),
-            InstructionType::Not => bytes.push(9),
-            InstructionType::Equal => bytes.push(10),
-            InstructionType::NotEqual => bytes.push(11),
-            InstructionType::Less => bytes.push(14),
-            InstructionType::LessEqual => bytes.push(15),
-            InstructionType::Greater => bytes.push(12),
-            InstructionType::GreaterEqual => bytes.push(13),
-            InstructionType::StringConcat => bytes.push(16),
-            InstructionType::Syscall => bytes.push(17),
-            InstructionType::GetGlobal(g) => {
-                bytes.push(18);
-                bytes.extend_from_slice(&g.to_le_bytes());
-            },
-            InstructionType::SetGlobal(g) => {
-                bytes.push(19);
-                bytes.extend_from_slice(&g.to_le_bytes());
-            },
-            InstructionType::GetLocal(g) => {
-                bytes.push(20);
-                bytes.extend_from_slice(&g.to_le_bytes());
-            },
-            InstructionType::SetLocal(g) => {
-                bytes.push(21);
-                bytes.extend_from_slice(&g.to_le_bytes());
-            },
-            InstructionType::JmpIfFalse(offset) => {
-                bytes.push(22);
-                bytes.extend_from_slice(&offset.to_le_bytes());
-            },
-            InstructionType::Jmp(offset) => {
-                bytes.push(23);
-                bytes.extend_from_slice(&offset.to_le_bytes());
-            },
-            InstructionType::Loop(offset) => {
-                bytes.push(24);
-                bytes.extend_from_slice(&offset.to_le_bytes());
-            },
-            InstructionType::Call => bytes.push(25),
-            InstructionType::ArrayAlloc => bytes.push(26),
-            InstructionType::ArrayGet => bytes.push(27),
-            InstructionType::ArraySet => bytes.push(29),
-            InstructionType::ObjectAlloc => bytes.push(29),
-            InstructionType::ObjectGet => bytes.push(30),
-            InstructionType::ObjectSet => bytes.push(31),
-            InstructionType::And => bytes.push(32),
-            InstructionType::Or => bytes.push(33),
-            InstructionType::Abs => bytes.push(34),
-            InstructionType::MultiArraySet => bytes.push(35),
-            InstructionType::Push => bytes.push(36),
-            InstructionType::Pop => bytes.push(37),
-            InstructionType::RepeatedArraySet => bytes.push(38),
-            InstructionType::Strlen => bytes.push(39),
-            InstructionType::Swap => bytes.push(40),
-            InstructionType::ToStr => bytes.push(41),
-            InstructionType::Uplift(g) => {
-                bytes.push(42);
-                bytes.extend_from_slice(&g.to_le_bytes());
-            },
-            InstructionType::AttachArray(f) => {
-                bytes.push(43);
-                bytes.extend_from_slice(&f.to_le_bytes());
-            },
-            InstructionType::CheckType(t) => {
-                bytes.push(44);
-                bytes.extend_from_slice(&t.to_le_bytes());
-            },
-            InstructionType::AddTag => bytes.push(45),
-            InstructionType::CheckTag => bytes.push(46),
-            InstructionType::ObjectHas => bytes.push(47),
-            InstructionType::ObjectMerge => bytes.push(48),
-            InstructionType::RemoveTag => bytes.push(49),
-            InstructionType::Duplicate => bytes.push(50),
+        let mut bytes = vec![self.instruction_type.opcode()];
+        if let Some(operand) = self.instruction_type.operand() {
+            bytes.extend_from_slice(&(operand as u64).to_le_bytes());
         }
-        bytes.extend_from_slice(&self.location.to_le_bytes());
+        bytes.extend_from_slice(&(self.location as u64).to_le_bytes());
         bytes
     }
 }
 
-impl From<&[u8]> for Instruction {
+impl TryFrom<&[u8]> for Instruction {
+    type Error = SerdeError;
+
     #[inline]
-    fn from(bytes: &[u8]) -> Instruction {
-        match bytes[0] {
-            0 => create_instruction(InstructionType::Return, &bytes[1..]),
-            1 => create_instruction(InstructionType::Constant(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            2 => create_instruction(InstructionType::Plus, &bytes[1..]),
-            3 => create_instruction(InstructionType::Minus, &bytes[1..]),
-            4 => create_instruction(InstructionType::Mult, &bytes[1..]),
-            5 => create_instruction(InstructionType::Div, &bytes[1..]),
-            6 => create_instruction(InstructionType::Nil, &bytes[1..]),
-            7 => create_instruction(InstructionType::True, &bytes[1..]),
-            8 => create_instruction(InstructionType::False, &bytes[1..]),
-            9 => create_instruction(InstructionType::Not, &bytes[1..]),
-            10 => create_instruction(InstructionType::Equal, &bytes[1..]),
-            11 => create_instruction(InstructionType::NotEqual, &bytes[1..]),
-            12 => create_instruction(InstructionType::Greater, &bytes[1..]),
-            13 => create_instruction(InstructionType::GreaterEqual, &bytes[1..]),
-            14 => create_instruction(InstructionType::Less, &bytes[1..]),
-            15 => create_instruction(InstructionType::LessEqual, &bytes[1..]),
-            16 => create_instruction(InstructionType::StringConcat, &bytes[1..]),
-            17 => create_instruction(InstructionType::Syscall, &bytes[1..]),
-            18 => create_instruction(InstructionType::GetGlobal(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            19 => create_instruction(InstructionType::SetGlobal(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            20 => create_instruction(InstructionType::GetLocal(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            21 => create_instruction(InstructionType::SetLocal(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            22 => create_instruction(InstructionType::JmpIfFalse(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            23 => create_instruction(InstructionType::Jmp(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            24 => create_instruction(InstructionType::Loop(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            25 => create_instruction(InstructionType::Call, &bytes[1..]),
-            26 => create_instruction(InstructionType::ArrayAlloc, &bytes[1..]),
-            27 => create_instruction(InstructionType::ArrayGet, &bytes[1..]),
-            28 => create_instruction(InstructionType::ArraySet, &bytes[1..]),
-            29 => create_instruction(InstructionType::ObjectAlloc, &bytes[1..]),
-            30 => create_instruction(InstructionType::ObjectGet, &bytes[1..]),
-            31 => create_instruction(InstructionType::ObjectSet, &bytes[1..]),
-            32 => create_instruction(InstructionType::And, &bytes[1..]),
-            33 => create_instruction(InstructionType::Or, &bytes[1..]),
-            34 => create_instruction(InstructionType::Abs, &bytes[1..]),
-            35 => create_instruction(InstructionType::MultiArraySet, &bytes[1..]),
-            36 => create_instruction(InstructionType::Push, &bytes[1..]),
-            37 => create_instruction(InstructionType::Pop, &bytes[1..]),
-            38 => create_instruction(InstructionType::RepeatedArraySet, &bytes[1..]),
-            39 => create_instruction(InstructionType::Strlen, &bytes[1..]),
-            40 => create_instruction(InstructionType::Swap, &bytes[1..]),
-            41 => create_instruction(InstructionType::ToStr, &bytes[1..]),
-            42 => create_instruction(InstructionType::Uplift(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            43 => create_instruction(InstructionType::AttachArray(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            44 => create_instruction(InstructionType::CheckType(usize::from_le_bytes(
-                [bytes[1], bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8]],
-            )), &bytes[9..]),
-            45 => create_instruction(InstructionType::AddTag, &bytes[1..]),
-            46 => create_instruction(InstructionType::CheckTag, &bytes[1..]),
-            47 => create_instruction(InstructionType::ObjectHas, &bytes[1..]),
-            48 => create_instruction(InstructionType::ObjectMerge, &bytes[1..]),
-            49 => create_instruction(InstructionType::RemoveTag, &bytes[1..]),
-            50 => create_instruction(InstructionType::Duplicate,  &bytes[1..]),
-            255 => create_instruction(InstructionType::Noop, &bytes[1..]),
-            _ => {
-                warn!("Invalid instruction");
-                create_instruction(InstructionType::Noop, &bytes[1..])
-            },
+    fn try_from(bytes: &[u8]) -> Result<Instruction, SerdeError> {
+        let operand = || deserialize_usize(bytes, 1);
+        let instruction_type = match *bytes.first().ok_or(SerdeError::UnexpectedEnd)? {
+            0 => InstructionType::Return,
+            1 => InstructionType::Constant(operand()?),
+            2 => InstructionType::Plus,
+            3 => InstructionType::Minus,
+            4 => InstructionType::Mult,
+            5 => InstructionType::Div,
+            6 => InstructionType::Nil,
+            7 => InstructionType::True,
+            8 => InstructionType::False,
+            9 => InstructionType::Not,
+            10 => InstructionType::Equal,
+            11 => InstructionType::NotEqual,
+            12 => InstructionType::Greater,
+            13 => InstructionType::GreaterEqual,
+            14 => InstructionType::Less,
+            15 => InstructionType::LessEqual,
+            16 => InstructionType::StringConcat,
+            17 => InstructionType::Syscall,
+            18 => InstructionType::GetGlobal(operand()?),
+            19 => InstructionType::SetGlobal(operand()?),
+            20 => InstructionType::GetLocal(operand()?),
+            21 => InstructionType::SetLocal(operand()?),
+            22 => InstructionType::JmpIfFalse(operand()?),
+            23 => InstructionType::Jmp(operand()?),
+            24 => InstructionType::Loop(operand()?),
+            25 => InstructionType::Call,
+            26 => InstructionType::ArrayAlloc,
+            27 => InstructionType::ArrayGet,
+            28 => InstructionType::ArraySet,
+            29 => InstructionType::ObjectAlloc,
+            30 => InstructionType::ObjectGet,
+            31 => InstructionType::ObjectSet,
+            32 => InstructionType::And,
+            33 => InstructionType::Or,
+            34 => InstructionType::Abs,
+            35 => InstructionType::MultiArraySet,
+            36 => InstructionType::Push,
+            37 => InstructionType::Pop,
+            38 => InstructionType::RepeatedArraySet,
+            39 => InstructionType::Strlen,
+            40 => InstructionType::Swap,
+            41 => InstructionType::ToStr,
+            42 => InstructionType::Uplift(operand()?),
+            43 => InstructionType::AttachArray(operand()?),
+            44 => InstructionType::CheckType(operand()?),
+            45 => InstructionType::AddTag,
+            46 => InstructionType::CheckTag,
+            47 => InstructionType::ObjectHas,
+            48 => InstructionType::ObjectMerge,
+            49 => InstructionType::RemoveTag,
+            50 => InstructionType::Duplicate,
+            51 => InstructionType::Clock,
+            52 => InstructionType::Random,
+            53 => InstructionType::CheckedPlus,
+            54 => InstructionType::CheckedMinus,
+            55 => InstructionType::CheckedMult,
+            56 => InstructionType::Yield,
+            57 => InstructionType::Mod,
+            58 => InstructionType::BitAnd,
+            59 => InstructionType::BitOr,
+            60 => InstructionType::BitXor,
+            61 => InstructionType::Shl,
+            62 => InstructionType::Shr,
+            63 => InstructionType::CallN(operand()?),
+            NOOP_OPCODE => InstructionType::Noop,
+            tag => return Err(SerdeError::UnknownInstructionTag { tag }),
+        };
+        let mut instruction = Instruction { instruction_type, location: 0 };
+        instruction.location = deserialize_usize(bytes, instruction.size() - SERIALIZED_USIZE_SIZE)?;
+        Ok(instruction)
+    }
+}
+
+impl InstructionType {
+    // The byte every instruction type is encoded as, the one place opcodes are numbered
+    pub fn opcode(&self) -> u8 {
+        match self {
+            InstructionType::Return => 0,
+            InstructionType::Constant(_) => 1,
+            InstructionType::Plus => 2,
+            InstructionType::Minus => 3,
+            InstructionType::Mult => 4,
+            InstructionType::Div => 5,
+            InstructionType::Noop => NOOP_OPCODE,
+            InstructionType::Nil => 6,
+            InstructionType::True => 7,
+            InstructionType::False => 8,
+            InstructionType::Not => 9,
+            InstructionType::Equal => 10,
+            InstructionType::NotEqual => 11,
+            InstructionType::Less => 14,
+            InstructionType::LessEqual => 15,
+            InstructionType::Greater => 12,
+            InstructionType::GreaterEqual => 13,
+            InstructionType::StringConcat => 16,
+            InstructionType::Syscall => 17,
+            InstructionType::GetGlobal(_) => 18,
+            InstructionType::SetGlobal(_) => 19,
+            InstructionType::GetLocal(_) => 20,
+            InstructionType::SetLocal(_) => 21,
+            InstructionType::JmpIfFalse(_) => 22,
+            InstructionType::Jmp(_) => 23,
+            InstructionType::Loop(_) => 24,
+            InstructionType::Call => 25,
+            InstructionType::ArrayAlloc => 26,
+            InstructionType::ArrayGet => 27,
+            InstructionType::ArraySet => 28,
+            InstructionType::ObjectAlloc => 29,
+            InstructionType::ObjectGet => 30,
+            InstructionType::ObjectSet => 31,
+            InstructionType::And => 32,
+            InstructionType::Or => 33,
+            InstructionType::Abs => 34,
+            InstructionType::MultiArraySet => 35,
+            InstructionType::Push => 36,
+            InstructionType::Pop => 37,
+            InstructionType::RepeatedArraySet => 38,
+            InstructionType::Strlen => 39,
+            InstructionType::Swap => 40,
+            InstructionType::ToStr => 41,
+            InstructionType::Uplift(_) => 42,
+            InstructionType::AttachArray(_) => 43,
+            InstructionType::CheckType(_) => 44,
+            InstructionType::AddTag => 45,
+            InstructionType::CheckTag => 46,
+            InstructionType::ObjectHas => 47,
+            InstructionType::ObjectMerge => 48,
+            InstructionType::RemoveTag => 49,
+            InstructionType::Duplicate => 50,
+            InstructionType::Clock => 51,
+            InstructionType::Random => 52,
+            InstructionType::CheckedPlus => 53,
+            InstructionType::CheckedMinus => 54,
+            InstructionType::CheckedMult => 55,
+            InstructionType::Yield => 56,
+            InstructionType::Mod => 57,
+            InstructionType::BitAnd => 58,
+            InstructionType::BitOr => 59,
+            InstructionType::BitXor => 60,
+            InstructionType::Shl => 61,
+            InstructionType::Shr => 62,
+            InstructionType::CallN(_) => 63,
+        }
+    }
+
+    pub fn operand(&self) -> Option<usize> {
+        match self {
+            InstructionType::Constant(operand) | InstructionType::GetGlobal(operand) | InstructionType::SetGlobal(operand) |
+            InstructionType::GetLocal(operand) | InstructionType::SetLocal(operand) | InstructionType::JmpIfFalse(operand) |
+            InstructionType::Jmp(operand) | InstructionType::Loop(operand) | InstructionType::Uplift(operand) |
+            InstructionType::AttachArray(operand) | InstructionType::CheckType(operand) | InstructionType::CallN(operand) => {
+                Some(*operand)
+            }
+            _ => None,
+        }
+    }
+
+    // The mnemonic, without the operand. Listings and the interpreter profile both use it
+    pub fn name(&self) -> &'static str {
+        match self {
+            InstructionType::Return => "RETURN",
+            InstructionType::Constant(_) => "CONSTANT",
+            InstructionType::Plus => "PLUS",
+            InstructionType::Minus => "MINUS",
+            InstructionType::Mult => "MULT",
+            InstructionType::Div => "DIV",
+            InstructionType::Noop => "NOOP",
+            InstructionType::Nil => "NIL",
+            InstructionType::True => "TRUE",
+            InstructionType::False => "FALSE",
+            InstructionType::Not => "NOT",
+            InstructionType::Equal => "EQUAL",
+            InstructionType::NotEqual => "NOT_EQUAL",
+            InstructionType::Less => "LESS",
+            InstructionType::LessEqual => "LESS_EQUAL",
+            InstructionType::Greater => "GREATER",
+            InstructionType::GreaterEqual => "GREATER_EQUAL",
+            InstructionType::StringConcat => "STRING_CONCAT",
+            InstructionType::Syscall => "SYSCALL",
+            InstructionType::GetGlobal(_) => "GET_GLOBAL",
+            InstructionType::SetGlobal(_) => "SET_GLOBAL",
+            InstructionType::GetLocal(_) => "GET_LOCAL",
+            InstructionType::SetLocal(_) => "SET_LOCAL",
+            InstructionType::JmpIfFalse(_) => "JMP_IF_FALSE",
+            InstructionType::Jmp(_) => "JMP",
+            InstructionType::Loop(_) => "LOOP",
+            InstructionType::Call => "CALL",
+            InstructionType::ArrayAlloc => "ARRAY_ALLOC",
+            InstructionType::ArrayGet => "ARRAY_GET",
+            InstructionType::ArraySet => "ARRAY_SET",
+            InstructionType::MultiArraySet => "MULTI_ARRAY_SET",
+            InstructionType::ObjectAlloc => "OBJECT_ALLOC",
+            InstructionType::ObjectGet => "OBJECT_GET",
+            InstructionType::ObjectSet => "OBJECT_SET",
+            InstructionType::ObjectHas => "OBJECT_HAS",
+            InstructionType::And => "AND",
+            InstructionType::Or => "OR",
+            InstructionType::Abs => "ABS",
+            InstructionType::Push => "PUSH",
+            InstructionType::Pop => "POP",
+            InstructionType::RepeatedArraySet => "REPEATED_ARRAY_SET",
+            InstructionType::Strlen => "STRLEN",
+            InstructionType::Swap => "SWAP",
+            InstructionType::ToStr => "TO_STR",
+            InstructionType::Uplift(_) => "UPLIFT",
+            InstructionType::AttachArray(_) => "ATTACH_ARRAY",
+            InstructionType::CheckType(_) => "CHECK_TYPE",
+            InstructionType::AddTag => "ADD_TAG",
+            InstructionType::CheckTag => "CHECK_TAG",
+            InstructionType::ObjectMerge => "OBJECT_MERGE",
+            InstructionType::RemoveTag => "REMOVE_TAG",
+            InstructionType::Duplicate => "DUPLICATE",
+            InstructionType::Clock => "CLOCK",
+            InstructionType::Random => "RANDOM",
+            InstructionType::CheckedPlus => "CHECKED_PLUS",
+            InstructionType::CheckedMinus => "CHECKED_MINUS",
+            InstructionType::CheckedMult => "CHECKED_MULT",
+            InstructionType::Yield => "YIELD",
+            InstructionType::Mod => "MOD",
+            InstructionType::BitAnd => "BIT_AND",
+            InstructionType::BitOr => "BIT_OR",
+            InstructionType::BitXor => "BIT_XOR",
+            InstructionType::Shl => "SHL",
+            InstructionType::Shr => "SHR",
+            InstructionType::CallN(_) => "CALL_N",
         }
     }
 }
 
-impl ToString for Instruction {
-    fn to_string(&self) -> String {
-        match &self.instruction_type {
-            InstructionType::Return => "RETURN".to_owned(),
-            InstructionType::Constant(b) => format!("CONSTANT {}", b),
-            InstructionType::Plus => "PLUS".to_owned(),
-            InstructionType::Minus => "MINUS".to_owned(),
-            InstructionType::Mult => "MULT".to_owned(),
-            InstructionType::Div => "DIV".to_owned(),
-            InstructionType::Noop => "NOOP".to_owned(),
-            InstructionType::Nil => "NIL".to_owned(),
-            InstructionType::True => "TRUE".to_owned(),
-            InstructionType::False => "FALSE".to_owned(),
-            InstructionType::Not => "NOT".to_owned(),
-            InstructionType::Equal => "EQUAL".to_owned(),
-            InstructionType::NotEqual => "NOT_EQUAL".to_owned(),
-            InstructionType::Less => "LESS".to_owned(),
-            InstructionType::LessEqual => "LESS_EQUAL".to_owned(),
-            InstructionType::Greater => "GREATER".to_owned(),
-            InstructionType::GreaterEqual => "GREATER_EQUAL".to_owned(),
-            InstructionType::StringConcat => "STRING_CONCAT".to_owned(),
-            InstructionType::Syscall => "SYSCALL".to_owned(),
-            InstructionType::GetGlobal(g) => format!("GET_GLOBAL {}", g),
-            InstructionType::SetGlobal(g) => format!("SET_GLOBAL {}", g),
-            InstructionType::GetLocal(g) => format!("GET_LOCAL {}", g),
-            InstructionType::SetLocal(g) => format!("SET_LOCAL {}", g),
-            InstructionType::JmpIfFalse(offset) => format!("JMP_IF_FALSE {}", offset),
-            InstructionType::Jmp(offset) => format!("JMP {}", offset),
-            InstructionType::Loop(offset) => format!("LOOP {}", offset),
-            InstructionType::Call => "CALL".to_owned(),
-            InstructionType::ArrayAlloc => "ARRAY_ALLOC".to_owned(),
-            InstructionType::ArrayGet => "ARRAY_GET".to_owned(),
-            InstructionType::ArraySet => "ARRAY_SET".to_owned(),
-            InstructionType::MultiArraySet => "MULTI_ARRAY_SET".to_owned(),
-            InstructionType::ObjectAlloc => "OBJECT_ALLOC".to_owned(),
-            InstructionType::ObjectGet => "OBJECT_GET".to_owned(),
-            InstructionType::ObjectSet => "OBJECT_SET".to_owned(),
-            InstructionType::ObjectHas => "OBJECT_HAS".to_owned(),
-            InstructionType::And => "AND".to_owned(),
-            InstructionType::Or => "OR".to_owned(),
-            InstructionType::Abs => "ABS".to_owned(),
-            InstructionType::Push => "PUSH".to_owned(),
-            InstructionType::Pop => "POP".to_owned(),
-            InstructionType::RepeatedArraySet => "REPEATED_ARRAY_SET".to_owned(),
-            InstructionType::Strlen => "STRLEN".to_owned(),
-            InstructionType::Swap => "SWAP".to_owned(),
-            InstructionType::ToStr => "TO_STR".to_owned(),
-            InstructionType::Uplift(local) => format!("UPLIFT {}", local),
-            InstructionType::AttachArray(function) => format!("ATTACH_ARRAY {}", function),
-            InstructionType::CheckType(type_index) => format!("CHECK_TYPE {}", type_index),
-            InstructionType::AddTag => "ADD_TAG".to_owned(),
-            InstructionType::CheckTag => "CHECK_TAG".to_owned(),
-            InstructionType::ObjectMerge => "OBJECT_MERGE".to_owned(),
-            InstructionType::RemoveTag => "REMOVE_TAG".to_owned(),
-            InstructionType::Duplicate => "DUPLICATE".to_owned(),
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.instruction_type.name();
+        match self.instruction_type.operand() {
+            Some(operand) => write!(f, "{} {}", name, operand),
+            None => write!(f, "{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_encode_every_opcode_back_to_itself() {
+        for opcode in (0..=LAST_OPCODE).chain(std::iter::once(NOOP_OPCODE)) {
+            let mut bytes = vec![opcode];
+            bytes.extend_from_slice(&1u64.to_le_bytes());
+            bytes.extend_from_slice(&7u64.to_le_bytes());
+            let instruction = Instruction::try_from(&bytes[..]).unwrap();
+            let size = instruction.size();
+            let encoded: Vec<u8> = instruction.into();
+            assert_eq!(encoded[..], bytes[..size], "opcode {}", opcode);
+        }
+    }
+
+    #[test]
+    fn it_should_stop_decoding_after_the_last_opcode() {
+        let mut bytes = vec![LAST_OPCODE + 1];
+        bytes.extend_from_slice(&[0; 16]);
+        match Instruction::try_from(&bytes[..]) {
+            Err(SerdeError::UnknownInstructionTag { tag }) => assert_eq!(tag, LAST_OPCODE + 1),
+            result => panic!("Unexpected {:?}", result),
         }
     }
 }
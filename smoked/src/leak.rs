@@ -0,0 +1,186 @@
+use crate::cpu::VM;
+use failure::Error;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// Allocations made from one source line that the program couldn't reach anymore when asked
+#[derive(Debug, PartialEq)]
+pub struct Leak {
+    pub file: String,
+    pub line: usize,
+    pub count: usize,
+    pub bytes: usize,
+}
+
+impl VM {
+    // From now on every allocation remembers the location of the instruction that made it
+    pub fn enable_leak_tracking(&mut self) {
+        if self.allocation_sites.is_none() {
+            self.allocation_sites = Some(RefCell::new(HashMap::new()));
+        }
+    }
+
+    // Only allocations the collector would free are leaks, and with it on it frees them sooner
+    // or later. So leaks are only reported with it off
+    pub fn disable_garbage_collection(&mut self) {
+        self.garbage_collection = false;
+    }
+
+    // What is still allocated but out of reach of the roots of the collector, grouped by the
+    // location that allocated it. Meant for when the program finished, only allocations made
+    // after enabling leak tracking and disabling garbage collection show up
+    pub fn leak_report(&self) -> Result<Vec<Leak>, Error> {
+        let sites = match self.allocation_sites {
+            Some(ref sites) if !self.garbage_collection => sites.borrow(),
+            _ => return Ok(vec![]),
+        };
+        let reachable: HashSet<usize> = self.get_roots().collect();
+        let mut leaks: BTreeMap<Option<usize>, (usize, usize)> = BTreeMap::new();
+        for (address, size) in self.allocator.borrow().allocations() {
+            if reachable.contains(&address) {
+                continue;
+            }
+            if let Some(location) = sites.get(&address) {
+                let (count, bytes) = leaks.entry(*location).or_insert((0, 0));
+                *count += 1;
+                *bytes += size;
+            }
+        }
+        let mut report = Vec::with_capacity(leaks.len());
+        for (location, (count, bytes)) in leaks {
+            let (file, line) = match location.and_then(|index| self.locations.get(index)) {
+                Some(location) => (self.location_file(location)?, location.line),
+                None => (String::new(), 0),
+            };
+            report.push(Leak {
+                file,
+                line,
+                count,
+                bytes,
+            });
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Leak;
+    use crate::allocator::Allocator;
+    use crate::cpu::{CompoundValue, Location, Value, VM};
+    use crate::instruction::{Instruction, InstructionType};
+    use crate::memory::Memory;
+    use failure::Error;
+    use std::collections::HashSet;
+
+    const FILE: &str = "leaky.smk";
+
+    // Concatenates "a" with itself three times on line 3 and drops the result every time
+    fn leaky_vm() -> VM {
+        let mut allocator = Allocator::new(64);
+        let memory = Memory::new(64);
+        let file = allocator.malloc(FILE.len(), std::iter::empty()).unwrap();
+        memory.copy_string(FILE, file);
+        let a = allocator.malloc(1, std::iter::empty()).unwrap();
+        memory.copy_string("a", a);
+        let constants = vec![
+            CompoundValue::SimpleValue(Value::String(file)),
+            CompoundValue::SimpleValue(Value::Integer(0)),
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(Value::Integer(3)),
+            CompoundValue::SimpleValue(Value::String(a)),
+        ];
+        let locations = (1..=3)
+            .map(|line| Location {
+                address: file,
+                line,
+            })
+            .collect();
+        let rom = vec![
+            (InstructionType::Constant(1), 0),
+            (InstructionType::SetGlobal(0), 0),
+            (InstructionType::Pop, 0),
+            (InstructionType::GetGlobal(0), 1),
+            (InstructionType::Constant(3), 1),
+            (InstructionType::Less, 1),
+            (InstructionType::JmpIfFalse(10), 1),
+            (InstructionType::Constant(4), 2),
+            (InstructionType::Constant(4), 2),
+            (InstructionType::StringConcat, 2),
+            (InstructionType::Pop, 2),
+            (InstructionType::GetGlobal(0), 1),
+            (InstructionType::Constant(2), 1),
+            (InstructionType::Plus, 1),
+            (InstructionType::SetGlobal(0), 1),
+            (InstructionType::Pop, 1),
+            (InstructionType::Loop(14), 1),
+            (InstructionType::GetGlobal(0), 0),
+            (InstructionType::Return, 0),
+        ]
+        .into_iter()
+        .map(|(instruction_type, location)| Instruction {
+            instruction_type,
+            location,
+        })
+        .collect();
+        let mut vm = VM::new(allocator, constants, locations, memory, rom);
+        vm.enable_leak_tracking();
+        vm.start();
+        vm
+    }
+
+    #[test]
+    fn test_leak_report_groups_unreachable_allocations_by_line() -> Result<(), Error> {
+        let mut vm = leaky_vm();
+        vm.disable_garbage_collection();
+        assert_eq!(
+            vm.run()?,
+            Some(CompoundValue::SimpleValue(Value::Integer(3)))
+        );
+        assert_eq!(
+            vm.leak_report()?,
+            vec![Leak {
+                file: FILE.to_owned(),
+                line: 3,
+                count: 3,
+                bytes: 6,
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_leak_report_is_empty_with_garbage_collection() -> Result<(), Error> {
+        let mut vm = leaky_vm();
+        vm.run()?;
+        let roots: HashSet<usize> = vm.get_roots().collect();
+        // The dropped strings are still there, the collector didn't need the room yet
+        assert!(vm
+            .allocator
+            .borrow()
+            .allocations()
+            .any(|(address, _)| !roots.contains(&address)));
+        assert_eq!(vm.leak_report()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_leak_report_is_empty_after_collecting_garbage() -> Result<(), Error> {
+        let mut vm = leaky_vm();
+        vm.disable_garbage_collection();
+        vm.run()?;
+        vm.collect_garbage()?;
+        assert_eq!(vm.leak_report()?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_leak_report_is_empty_without_tracking() -> Result<(), Error> {
+        let mut vm = leaky_vm();
+        vm.disable_garbage_collection();
+        vm.allocation_sites = None;
+        vm.run()?;
+        assert_eq!(vm.leak_report()?, vec![]);
+        Ok(())
+    }
+}
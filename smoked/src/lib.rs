@@ -1,6 +1,14 @@
 #[macro_use] extern crate failure;
 pub mod allocator;
 pub mod cpu;
+mod host;
+pub mod inspect;
 pub mod instruction;
+pub mod leak;
 pub mod memory;
+#[cfg(feature = "profile-interp")]
+mod profile;
+pub mod scheduler;
 pub mod serde;
+pub mod snapshot;
+pub mod validator;
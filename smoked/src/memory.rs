@@ -7,6 +7,8 @@ pub enum MemoryError {
     WrongMemoryAddress { address: usize },
     #[fail(display = "Error fetching type from address")]
     ErrorFetchingFunctionFromMemory,
+    #[fail(display = "The bytes at address {} aren't valid UTF-8", address)]
+    InvalidString { address: usize },
 }
 
 #[derive(Clone)]
@@ -31,19 +33,28 @@ impl Memory {
         Ok(res)
     }
 
-    pub fn copy_t<T>(&self, value: &T, address: usize) {
+    pub fn copy_t<T>(&self, value: &T, address: usize) -> Result<(), MemoryError> {
         let v: *const T = value;
         let p: &[u8] = unsafe { std::slice::from_raw_parts(v as *const u8, size_of::<T>()) };
-        self.copy_u8_vector(p, address);
+        self.copy_u8_vector(p, address)
     }
 
-    pub fn copy_t_slice<T>(&self, values: &[T], address: usize) {
+    pub fn copy_t_slice<T>(&self, values: &[T], address: usize) -> Result<(), MemoryError> {
         let len = size_of::<T>() * values.len();
         let p: &[u8] = unsafe { std::slice::from_raw_parts(values.as_ptr() as *const u8, len) };
-        self.copy_u8_vector(p, address);
+        self.copy_u8_vector(p, address)
+    }
+
+    // The raw slices below trust their length, so every access is checked here first
+    fn check_bounds(&self, address: usize, size: usize) -> Result<(), MemoryError> {
+        match address.checked_add(size) {
+            Some(end) if end <= self.0.borrow().len() => Ok(()),
+            _ => Err(MemoryError::WrongMemoryAddress { address }),
+        }
     }
 
     pub fn get_u8_vector(&self, address: usize, size: usize) -> Result<&[u8], MemoryError> {
+        self.check_bounds(address, size)?;
         let memory: &[u8] = unsafe {
             std::slice::from_raw_parts(self.0.borrow()[address..].as_ptr(), size)
         };
@@ -59,7 +70,8 @@ impl Memory {
         Ok(array)
     }
 
-    pub fn copy_u8_vector(&self, vector: &[u8], address: usize) {
+    pub fn copy_u8_vector(&self, vector: &[u8], address: usize) -> Result<(), MemoryError> {
+        self.check_bounds(address, vector.len())?;
         let memory: &mut [u8] = unsafe {
             std::slice::from_raw_parts_mut(
                 self.0.borrow_mut()[address..].as_mut_ptr(),
@@ -67,11 +79,22 @@ impl Memory {
             )
         };
         memory.copy_from_slice(vector);
+        Ok(())
+    }
+
+    pub(crate) fn dump(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    pub(crate) fn load(&self, bytes: &[u8]) {
+        let mut memory = self.0.borrow_mut();
+        memory.resize(bytes.len(), 0);
+        memory.copy_from_slice(bytes);
     }
 
     pub(crate) fn get_string(&self, address: usize, size: usize) -> Result<&str, MemoryError> {
         let bytes = self.get_u8_vector(address, size)?;
-        Ok(std::str::from_utf8(bytes).unwrap())
+        std::str::from_utf8(bytes).map_err(|_| MemoryError::InvalidString { address })
     }
 }
 
@@ -79,7 +102,7 @@ impl Memory {
 impl Memory {
     pub(crate) fn copy_string(&self, value: &str, address: usize) {
         let bs = value.as_bytes();
-        self.copy_u8_vector(bs, address)
+        self.copy_u8_vector(bs, address).unwrap()
     }
     pub(crate) fn get_capacity(&self) -> usize {
         self.0.borrow().len()
@@ -94,7 +117,7 @@ mod tests {
     fn it_should_copy_a_u8_aray() {
         let data = &[1u8, 1, 1, 1, 1, 1, 1, 1];
         let memory = Memory::new(12);
-        memory.copy_u8_vector(data, 1);
+        memory.copy_u8_vector(data, 1).unwrap();
         assert_eq!(memory.0.borrow()[0], 0);
         assert_eq!(&memory.0.borrow()[1..9], &[1u8, 1, 1, 1, 1, 1, 1, 1]);
         assert_eq!(memory.0.borrow()[10], 0);
@@ -103,7 +126,7 @@ mod tests {
     #[test]
     fn it_should_copy_a_type() {
         let memory = Memory::new(3);
-        memory.copy_t(&true, 1);
+        memory.copy_t(&true, 1).unwrap();
         assert_eq!(memory.0.borrow()[0], 0);
         assert_eq!(memory.0.borrow()[1], 1);
         assert_eq!(memory.0.borrow()[2], 0);
@@ -120,6 +143,15 @@ mod tests {
         assert_eq!(memory.0.borrow()[2], 0);
     }
 
+    #[test]
+    fn it_should_refuse_accesses_past_the_end() {
+        let memory = Memory::new(8);
+        assert!(memory.copy_t(&1u64, 1).is_err());
+        assert!(memory.get_u8_vector(4, 5).is_err());
+        assert!(memory.get_t::<u8>(usize::MAX).is_err());
+        assert!(memory.copy_t(&1u64, 0).is_ok());
+    }
+
     #[test]
     fn it_should_be_able_to_store_a_string() {
         let s = String::from("42");
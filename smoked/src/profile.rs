@@ -0,0 +1,149 @@
+use crate::cpu::VM;
+use crate::instruction::{InstructionType, LAST_OPCODE, NOOP_OPCODE};
+use std::time::Instant;
+
+// Every variant has a dense id, so per instruction type data fits in an array. One per opcode,
+// plus Noop
+const INSTRUCTION_TYPES: usize = LAST_OPCODE as usize + 2;
+
+impl InstructionType {
+    // The opcode, with Noop right after the last one
+    #[inline]
+    fn id(&self) -> usize {
+        match self.opcode() {
+            NOOP_OPCODE => INSTRUCTION_TYPES - 1,
+            opcode => usize::from(opcode),
+        }
+    }
+}
+
+pub(crate) struct InterpProfile {
+    names: [&'static str; INSTRUCTION_TYPES],
+    counts: [u64; INSTRUCTION_TYPES],
+    total_ns: [u64; INSTRUCTION_TYPES],
+}
+
+pub(crate) struct InstructionTimer {
+    id: usize,
+    name: &'static str,
+    start: Instant,
+}
+
+impl InstructionTimer {
+    #[inline]
+    pub(crate) fn start(instruction_type: &InstructionType) -> InstructionTimer {
+        InstructionTimer {
+            id: instruction_type.id(),
+            name: instruction_type.name(),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl InterpProfile {
+    pub(crate) fn new() -> InterpProfile {
+        InterpProfile {
+            names: [""; INSTRUCTION_TYPES],
+            counts: [0; INSTRUCTION_TYPES],
+            total_ns: [0; INSTRUCTION_TYPES],
+        }
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, timer: InstructionTimer) {
+        self.names[timer.id] = timer.name;
+        self.counts[timer.id] += 1;
+        self.total_ns[timer.id] += timer.start.elapsed().as_nanos() as u64;
+    }
+}
+
+impl VM {
+    // Name, count, total and average nanoseconds of every instruction type that ran, slowest first
+    pub fn interp_profile(&self) -> Vec<(&'static str, u64, u64, u64)> {
+        let profile = &self.profile;
+        let mut result: Vec<(&'static str, u64, u64, u64)> = (0..INSTRUCTION_TYPES)
+            .filter(|id| profile.counts[*id] > 0)
+            .map(|id| {
+                let (count, total_ns) = (profile.counts[id], profile.total_ns[id]);
+                (profile.names[id], count, total_ns, total_ns / count)
+            })
+            .collect();
+        result.sort_by_key(|p| std::cmp::Reverse(p.2));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::Allocator;
+    use crate::cpu::{CompoundValue, Value, VM};
+    use crate::instruction::{Instruction, InstructionType, LAST_OPCODE, NOOP_OPCODE};
+    use crate::memory::Memory;
+    use crate::profile::INSTRUCTION_TYPES;
+    use std::collections::HashSet;
+    use std::convert::TryFrom;
+
+    fn create_instruction(instruction_type: InstructionType) -> Instruction {
+        Instruction {
+            instruction_type,
+            location: 0,
+        }
+    }
+
+    fn create_workload(times: usize) -> VM {
+        let mut rom: Vec<Instruction> = (0..times)
+            .flat_map(|_| {
+                vec![
+                    create_instruction(InstructionType::Constant(0)),
+                    create_instruction(InstructionType::Constant(0)),
+                    create_instruction(InstructionType::Plus),
+                    create_instruction(InstructionType::SetGlobal(0)),
+                    create_instruction(InstructionType::Pop),
+                    create_instruction(InstructionType::Nil),
+                    create_instruction(InstructionType::Not),
+                    create_instruction(InstructionType::Pop),
+                    create_instruction(InstructionType::Noop),
+                ]
+            })
+            .collect();
+        rom.push(create_instruction(InstructionType::Nil));
+        rom.push(create_instruction(InstructionType::Return));
+        let constants = vec![CompoundValue::SimpleValue(Value::Integer(42))];
+        let mut vm = VM::new(Allocator::new(10), constants, vec![], Memory::new(10), rom);
+        vm.new_frame(0, 0);
+        vm
+    }
+
+    #[test]
+    fn test_interp_profile_counts_every_instruction() {
+        let mut vm = create_workload(1000);
+        let mut executed = 0;
+        while !vm.is_done() {
+            vm.execute().unwrap();
+            executed += 1;
+        }
+        let profile = vm.interp_profile();
+        assert_eq!(profile.iter().map(|(_, count, _, _)| count).sum::<u64>(), executed);
+        let count = |name: &str| profile.iter().find(|p| p.0 == name).unwrap().1;
+        assert_eq!(count("CONSTANT"), 2000);
+        assert_eq!(count("PLUS"), 1000);
+        assert_eq!(count("POP"), 2000);
+        assert_eq!(count("NIL"), 1001);
+        assert_eq!(count("RETURN"), 1);
+        assert!(profile.iter().all(|p| p.3 == p.2 / p.1));
+        assert!(profile.windows(2).all(|w| w[0].2 >= w[1].2));
+    }
+
+    #[test]
+    fn test_every_opcode_has_its_own_id() {
+        let ids: HashSet<usize> = (0..=LAST_OPCODE)
+            .chain(std::iter::once(NOOP_OPCODE))
+            .map(|opcode| {
+                let mut bytes = vec![opcode];
+                bytes.extend_from_slice(&[0; 16]);
+                Instruction::try_from(&bytes[..]).unwrap().instruction_type.id()
+            })
+            .collect();
+        assert_eq!(ids, (0..INSTRUCTION_TYPES).collect());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::cpu::VM;
+use failure::Error;
+
+// Enough to get real work done in a turn without starving the other programs
+const DEFAULT_FUEL: usize = 1000;
+
+// Takes turns between several programs on the host thread. A program's turn ends when it yields,
+// finishes or runs out of fuel, whatever happens first
+pub struct Scheduler {
+    vms: Vec<VM>,
+    fuel: usize,
+}
+
+impl Scheduler {
+    pub fn new(vms: Vec<VM>) -> Scheduler {
+        Scheduler {
+            vms,
+            fuel: DEFAULT_FUEL,
+        }
+    }
+
+    // How many instructions a program runs at most before losing its turn
+    pub fn with_fuel(mut self, fuel: usize) -> Scheduler {
+        self.fuel = fuel;
+        self
+    }
+
+    // Gives every unfinished program a turn, in order. False once there's nothing left to run
+    pub fn round(&mut self) -> Result<bool, Error> {
+        let mut ran = false;
+        for vm in self.vms.iter_mut().filter(|vm| !vm.is_done()) {
+            ran = true;
+            vm.run_for(self.fuel)?;
+        }
+        Ok(ran)
+    }
+
+    // Takes turns until every program finished
+    pub fn run(&mut self) -> Result<(), Error> {
+        while self.round()? {}
+        Ok(())
+    }
+
+    pub fn vms(&self) -> &[VM] {
+        &self.vms
+    }
+
+    pub fn into_vms(self) -> Vec<VM> {
+        self.vms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scheduler;
+    use crate::allocator::Allocator;
+    use crate::cpu::{CompoundValue, Value, VM};
+    use crate::instruction::{Instruction, InstructionType};
+    use crate::memory::Memory;
+    use failure::Error;
+
+    fn create_instruction(instruction_type: InstructionType) -> Instruction {
+        Instruction {
+            instruction_type,
+            location: 0,
+        }
+    }
+
+    // Counts up to limit on global 0, yielding after every step, and returns the count
+    fn counter(limit: i64) -> VM {
+        let constants = vec![
+            CompoundValue::SimpleValue(Value::Integer(0)),
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(Value::Integer(limit)),
+        ];
+        let rom = vec![
+            InstructionType::Constant(0),
+            InstructionType::SetGlobal(0),
+            InstructionType::Pop,
+            InstructionType::GetGlobal(0),
+            InstructionType::Constant(2),
+            InstructionType::Less,
+            InstructionType::JmpIfFalse(7),
+            InstructionType::GetGlobal(0),
+            InstructionType::Constant(1),
+            InstructionType::Plus,
+            InstructionType::SetGlobal(0),
+            InstructionType::Pop,
+            InstructionType::Yield,
+            InstructionType::Loop(11),
+            InstructionType::GetGlobal(0),
+            InstructionType::Return,
+        ]
+        .into_iter()
+        .map(create_instruction)
+        .collect();
+        let mut vm = VM::new(Allocator::new(10), constants, vec![], Memory::new(10), rom);
+        vm.start();
+        vm
+    }
+
+    fn count(vm: &VM) -> Option<&CompoundValue> {
+        vm.global(0)
+    }
+
+    #[test]
+    fn test_round_interleaves_yielding_programs() -> Result<(), Error> {
+        let mut scheduler = Scheduler::new(vec![counter(2), counter(3)]);
+        assert!(scheduler.round()?);
+        assert_eq!(
+            count(&scheduler.vms()[0]),
+            Some(&CompoundValue::SimpleValue(Value::Integer(1)))
+        );
+        assert_eq!(
+            count(&scheduler.vms()[1]),
+            Some(&CompoundValue::SimpleValue(Value::Integer(1)))
+        );
+        assert!(scheduler.round()?);
+        assert_eq!(
+            count(&scheduler.vms()[0]),
+            Some(&CompoundValue::SimpleValue(Value::Integer(2)))
+        );
+        assert_eq!(
+            count(&scheduler.vms()[1]),
+            Some(&CompoundValue::SimpleValue(Value::Integer(2)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_finishes_every_program() -> Result<(), Error> {
+        let mut scheduler = Scheduler::new(vec![counter(2), counter(5)]);
+        scheduler.run()?;
+        assert!(!scheduler.round()?);
+        let vms = scheduler.into_vms();
+        assert!(vms.iter().all(VM::is_done));
+        assert_eq!(
+            vms[0].exit_value(),
+            Some(&CompoundValue::SimpleValue(Value::Integer(2)))
+        );
+        assert_eq!(
+            vms[1].exit_value(),
+            Some(&CompoundValue::SimpleValue(Value::Integer(5)))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fuel_preempts_programs_that_dont_yield() -> Result<(), Error> {
+        let mut scheduler = Scheduler::new(vec![counter(1)]).with_fuel(2);
+        assert!(scheduler.round()?);
+        // Only got to set the counter up
+        assert_eq!(
+            count(&scheduler.vms()[0]),
+            Some(&CompoundValue::SimpleValue(Value::Integer(0)))
+        );
+        assert!(!scheduler.vms()[0].is_done());
+        scheduler.run()?;
+        assert_eq!(
+            scheduler.vms()[0].exit_value(),
+            Some(&CompoundValue::SimpleValue(Value::Integer(1)))
+        );
+        Ok(())
+    }
+}
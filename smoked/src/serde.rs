@@ -1,58 +1,338 @@
 use crate::allocator::Allocator;
-use crate::cpu::{Location, NULL_VALUE, Value, STACK_MAX, VM, CompoundValue};
+use crate::host::Host;
+use crate::cpu::{Location, NULL_VALUE, Value, STACK_MAX, VM, CompoundValue, DEFAULT_ALLOCATION_LIMIT, COMPOUND_VALUE_SIZE, USIZE_SIZE, VALUE_SIZE};
 use crate::instruction::Instruction;
 use crate::memory::Memory;
+use failure::Error;
 use std::cell::RefCell;
 use std::cmp::min;
-use std::mem::size_of;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
 
-const USIZE_SIZE: usize = std::mem::size_of::<usize>();
+const ARRAY_CONSTRUCTOR: u8 = 9;
+const OBJECT_CONSTRUCTOR: u8 = 10;
+const MAGIC: &[u8; 4] = b"SMKD";
+// Bumped whenever the layout of program files changes
+pub const FORMAT_VERSION: u8 = 3;
+const OLDEST_FORMAT_VERSION: u8 = 1;
+// Functions in version 1 files have no default arguments
+pub(crate) const DEFAULTS_VERSION: u8 = 2;
+// Before version 3 files didn't store the length of the ROM, it's the rest of the file
+const ROM_LENGTH_VERSION: u8 = 3;
+// Counts, addresses and ips are stored as u64, whatever the width of usize in the host
+pub const SERIALIZED_USIZE_SIZE: usize = 8;
+// The lengths of the constants, the memory, the locations and the ROM follow the version
+const LENGTHS_OFFSET: usize = MAGIC.len() + 1;
+const HEADER_SIZE: usize = LENGTHS_OFFSET + SERIALIZED_USIZE_SIZE * 4;
+const SHORT_HEADER_SIZE: usize = LENGTHS_OFFSET + SERIALIZED_USIZE_SIZE * 3;
+const LOCATION_SIZE: usize = SERIALIZED_USIZE_SIZE * 2;
 
-fn extract_usize(bytes: &[u8]) -> usize {
-    *unsafe { (bytes.as_ptr() as *const usize).as_ref() }.unwrap()
+#[derive(Debug, Fail)]
+pub enum SerdeError {
+    #[fail(display = "Constant {} references constant {}, which isn't declared before it", constant, reference)]
+    InvalidConstantReference { constant: usize, reference: usize },
+    #[fail(display = "Constant {} uses constant {} as a property name, but it isn't a string", constant, key)]
+    ExpectedStringProperty { constant: usize, key: usize },
+    #[fail(display = "Constant {} takes its default arguments from constant {}, but it isn't an array", constant, defaults)]
+    ExpectedDefaultsArray { constant: usize, defaults: usize },
+    #[fail(display = "Not a smoked program, it doesn't start with {:?}", magic)]
+    InvalidMagic { magic: &'static [u8; 4] },
+    #[fail(display = "The program uses format version {}, but only versions {} to {} are supported", version, oldest, newest)]
+    UnsupportedVersion { version: u8, oldest: u8, newest: u8 },
+    #[fail(display = "The program has {} bytes, but it needs at least {}", size, expected)]
+    Truncated { size: usize, expected: usize },
+    #[fail(display = "The program has {} bytes, but its header says it ends at {}", size, expected)]
+    TrailingBytes { size: usize, expected: usize },
+    #[fail(display = "The program has the number {}, which doesn't fit in this platform", value)]
+    NumberTooLarge { value: u64 },
+    #[fail(display = "The program ends in the middle of a value")]
+    UnexpectedEnd,
+    #[fail(display = "Unknown value tag {}", tag)]
+    UnknownValueTag { tag: u8 },
+    #[fail(display = "Unknown instruction tag {}", tag)]
+    UnknownInstructionTag { tag: u8 },
+    #[fail(display = "A constant points to address {}, but the memory has {} bytes", address, size)]
+    AddressOutOfMemory { address: usize, size: usize },
+    #[fail(display = "Constant {} points to an array, object or pointer in the memory, which only holds strings", constant)]
+    HeapConstant { constant: usize },
 }
 
-fn extract_constants<I: Iterator<Item=u8>>(bytes: &mut I, memory: &[u8]) -> (Vec<usize>, Vec<Value>) {
+#[inline]
+pub fn serialize_usize(bytes: &mut Vec<u8>, value: usize) {
+    bytes.extend_from_slice(&(value as u64).to_le_bytes());
+}
+
+// Reads the u64 at offset, failing if it's cut short or doesn't fit in a usize
+#[inline]
+pub(crate) fn deserialize_usize(bytes: &[u8], offset: usize) -> Result<usize, SerdeError> {
+    let end = offset + SERIALIZED_USIZE_SIZE;
+    let mut value = [0u8; SERIALIZED_USIZE_SIZE];
+    value.copy_from_slice(bytes.get(offset..end).ok_or(SerdeError::Truncated {
+        size: bytes.len(),
+        expected: end,
+    })?);
+    let value = u64::from_le_bytes(value);
+    usize::try_from(value).map_err(|_| SerdeError::NumberTooLarge { value })
+}
+
+pub fn header(
+    constant_length: usize,
+    memory_length: usize,
+    location_length: usize,
+    rom_length: usize,
+) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(FORMAT_VERSION);
+    serialize_usize(&mut bytes, constant_length);
+    serialize_usize(&mut bytes, memory_length);
+    serialize_usize(&mut bytes, location_length);
+    serialize_usize(&mut bytes, rom_length);
+    bytes
+}
+
+// Where each part of a program file is, after checking the file has all of them
+struct Sections {
+    version: u8,
+    constants: Range<usize>,
+    memory: Range<usize>,
+    locations: Range<usize>,
+    rom: Range<usize>,
+}
+
+impl Sections {
+    fn new(bytes: &[u8]) -> Result<Sections, SerdeError> {
+        if bytes.len() < LENGTHS_OFFSET {
+            return Err(SerdeError::Truncated { size: bytes.len(), expected: HEADER_SIZE });
+        }
+        if bytes[..MAGIC.len()] != MAGIC[..] {
+            return Err(SerdeError::InvalidMagic { magic: MAGIC });
+        }
+        let version = bytes[MAGIC.len()];
+        if !(OLDEST_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+            return Err(SerdeError::UnsupportedVersion {
+                version,
+                oldest: OLDEST_FORMAT_VERSION,
+                newest: FORMAT_VERSION,
+            });
+        }
+        let header_size = if version < ROM_LENGTH_VERSION { SHORT_HEADER_SIZE } else { HEADER_SIZE };
+        if bytes.len() < header_size {
+            return Err(SerdeError::Truncated { size: bytes.len(), expected: header_size });
+        }
+        let length =
+            |index| deserialize_usize(bytes, LENGTHS_OFFSET + SERIALIZED_USIZE_SIZE * index);
+        let constant_length = length(0)?;
+        let memory_length = length(1)?;
+        let location_length = length(2)?;
+        let rom_start = header_size
+            .checked_add(constant_length)
+            .and_then(|size| size.checked_add(memory_length))
+            .and_then(|size| size.checked_add(location_length.checked_mul(LOCATION_SIZE)?))
+            .unwrap_or(usize::MAX);
+        let end = if version < ROM_LENGTH_VERSION {
+            rom_start.max(bytes.len())
+        } else {
+            rom_start.saturating_add(length(3)?)
+        };
+        if bytes.len() < end {
+            return Err(SerdeError::Truncated { size: bytes.len(), expected: end });
+        }
+        if bytes.len() > end {
+            return Err(SerdeError::TrailingBytes { size: bytes.len(), expected: end });
+        }
+        let memory_start = header_size + constant_length;
+        let locations_start = memory_start + memory_length;
+        Ok(Sections {
+            version,
+            constants: header_size..memory_start,
+            memory: memory_start..locations_start,
+            locations: locations_start..rom_start,
+            rom: rom_start..end,
+        })
+    }
+}
+
+// Arrays and objects are built at load time out of the constants declared before them
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstantDeclaration {
+    Value(Value),
+    Array(Vec<usize>),
+    Object(Vec<(usize, usize)>),
+}
+
+impl ConstantDeclaration {
+    fn allocation_size(&self) -> usize {
+        match self {
+            ConstantDeclaration::Value(_) => 0,
+            ConstantDeclaration::Array(elements) => elements.len() * COMPOUND_VALUE_SIZE,
+            ConstantDeclaration::Object(properties) => {
+                USIZE_SIZE * 3 + properties.len() * (VALUE_SIZE + USIZE_SIZE)
+            }
+        }
+    }
+}
+
+impl From<Value> for ConstantDeclaration {
+    fn from(value: Value) -> ConstantDeclaration {
+        ConstantDeclaration::Value(value)
+    }
+}
+
+impl From<ConstantDeclaration> for Vec<u8> {
+    fn from(declaration: ConstantDeclaration) -> Vec<u8> {
+        match declaration {
+            ConstantDeclaration::Value(value) => value.into(),
+            ConstantDeclaration::Array(elements) => {
+                let mut bytes = vec![ARRAY_CONSTRUCTOR];
+                serialize_usize(&mut bytes, elements.len());
+                for element in elements {
+                    serialize_usize(&mut bytes, element);
+                }
+                bytes
+            }
+            ConstantDeclaration::Object(properties) => {
+                let mut bytes = vec![OBJECT_CONSTRUCTOR];
+                serialize_usize(&mut bytes, properties.len());
+                for (key, value) in properties {
+                    serialize_usize(&mut bytes, key);
+                    serialize_usize(&mut bytes, value);
+                }
+                bytes
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConstantDeclaration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConstantDeclaration::Value(value) => write!(f, "{:?}", value),
+            ConstantDeclaration::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(|e| format!("#{}", e)).collect();
+                write!(f, "Array [{}]", elements.join(", "))
+            }
+            ConstantDeclaration::Object(properties) => {
+                let properties: Vec<String> = properties
+                    .iter()
+                    .map(|(key, value)| format!("#{}: #{}", key, value))
+                    .collect();
+                write!(f, "Object {{{}}}", properties.join(", "))
+            }
+        }
+    }
+}
+
+fn next_usize<I: Iterator<Item=u8>>(bytes: &mut I) -> Result<usize, SerdeError> {
+    let mut result = [0u8; SERIALIZED_USIZE_SIZE];
+    for byte in result.iter_mut() {
+        *byte = bytes.next().ok_or(SerdeError::UnexpectedEnd)?;
+    }
+    deserialize_usize(&result, 0)
+}
+
+fn extract_constants<I: Iterator<Item=u8>>(
+    bytes: &mut I,
+    version: u8,
+) -> Result<(Vec<usize>, Vec<ConstantDeclaration>), SerdeError> {
     let mut constants = vec![];
     let mut sizes = vec![];
     let mut peakable = bytes.peekable();
-    while peakable.peek().is_some() {
-        let value = Value::from(&mut peakable);
-        constants.push(value);
-        match value {
-            Value::String(address) | Value::Array { address, .. } |
-                Value::Pointer(address) => {
-                sizes.push(address);
+    while let Some(tag) = peakable.peek().cloned() {
+        if tag == ARRAY_CONSTRUCTOR {
+            peakable.next();
+            let length = next_usize(&mut peakable)?;
+            let elements = (0..length)
+                .map(|_| next_usize(&mut peakable))
+                .collect::<Result<Vec<usize>, SerdeError>>()?;
+            constants.push(ConstantDeclaration::Array(elements));
+            continue;
+        }
+        if tag == OBJECT_CONSTRUCTOR {
+            peakable.next();
+            let length = next_usize(&mut peakable)?;
+            let mut properties = Vec::with_capacity(length);
+            for _ in 0..length {
+                properties.push((next_usize(&mut peakable)?, next_usize(&mut peakable)?));
             }
-            Value::Object { address, tags, ..} => {
-                sizes.push(address);
-                let props_address = extract_usize(&memory[address..address + USIZE_SIZE]);
-                sizes.push(props_address);
-                sizes.push(tags);
+            constants.push(ConstantDeclaration::Object(properties));
+            continue;
+        }
+        let value = Value::deserialize(&mut peakable, version)?;
+        match value {
+            Value::String(address) => sizes.push(address),
+            // Their contents would be in the layout of the host, they are declared with the
+            // constructors above instead
+            Value::Array { .. } | Value::Object { .. } | Value::Pointer(_) => {
+                return Err(SerdeError::HeapConstant { constant: constants.len() });
             }
             _ => {}
         }
+        constants.push(ConstantDeclaration::Value(value));
     }
-    (sizes, constants)
+    Ok((sizes, constants))
 }
 
-#[macro_export]
-macro_rules! serialize_type {
-    ($bytes: ident, $value: expr, $type: ident) => {
-        let p: &[u8] = unsafe {
-            std::slice::from_raw_parts(&$value as *const $type as *const u8, size_of::<$type>())
+fn resolve_reference(constants: &[CompoundValue], constant: usize, reference: usize) -> Result<Value, SerdeError> {
+    match constants.get(reference) {
+        Some(CompoundValue::SimpleValue(value)) if reference < constant => Ok(*value),
+        _ => Err(SerdeError::InvalidConstantReference { constant, reference }),
+    }
+}
+
+fn build_constants(vm: &mut VM, declarations: &[ConstantDeclaration]) -> Result<(), Error> {
+    for (index, declaration) in declarations.iter().enumerate() {
+        let value = match declaration {
+            // In program files the defaults of a function are an array constant declared before it
+            ConstantDeclaration::Value(Value::Function { ip, arity, uplifts, defaults: Some(defaults) }) => {
+                let address = match resolve_reference(&vm.constants, index, *defaults)? {
+                    Value::Array { address, .. } => address,
+                    _ => Err(SerdeError::ExpectedDefaultsArray { constant: index, defaults: *defaults })?,
+                };
+                Value::Function { ip: *ip, arity: *arity, uplifts: *uplifts, defaults: Some(address) }
+            }
+            ConstantDeclaration::Value(value) => *value,
+            ConstantDeclaration::Array(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|e| resolve_reference(&vm.constants, index, *e).map(CompoundValue::SimpleValue))
+                    .collect::<Result<Vec<CompoundValue>, SerdeError>>()?;
+                vm.build_array(&elements)?
+            }
+            ConstantDeclaration::Object(properties) => {
+                let mut resolved = Vec::with_capacity(properties.len());
+                for (key, value) in properties {
+                    let key = match resolve_reference(&vm.constants, index, *key)? {
+                        Value::String(address) => address,
+                        _ => Err(SerdeError::ExpectedStringProperty { constant: index, key: *key })?,
+                    };
+                    resolved.push((key, resolve_reference(&vm.constants, index, *value)?));
+                }
+                vm.build_object(&resolved)?
+            }
         };
-        $bytes.extend_from_slice(p);
-    };
+        vm.constants.push(CompoundValue::SimpleValue(value));
+    }
+    Ok(())
+}
+
+pub fn constant_table(bytes: &[u8]) -> Result<String, Error> {
+    let sections = Sections::new(bytes)?;
+    let (_, constants) = extract_constants(&mut bytes[sections.constants].iter().cloned(), sections.version)?;
+    Ok(constants
+        .iter()
+        .enumerate()
+        .map(|(index, constant)| format!("#{} {}\n", index, constant))
+        .collect())
 }
 
-pub fn to_bytes(
-    constants: &[Value],
+// The memory only holds the bytes of strings, so unlike the rest of the file it has no byte order
+pub fn to_bytes<C: Clone + Into<Vec<u8>>>(
+    constants: &[C],
     locations: &[Location],
     memory: &[u8],
     instructions: &[Instruction],
 ) -> Vec<u8> {
-    let mut output = vec![];
     let mut upcodes = vec![];
     let mut constant_bytes = vec![];
     for i in instructions {
@@ -60,97 +340,90 @@ pub fn to_bytes(
         upcodes.extend_from_slice(&bs);
     }
     for c in constants {
-        let bs: Vec<u8> = (*c).into();
+        let bs: Vec<u8> = c.clone().into();
         constant_bytes.extend_from_slice(&bs);
     }
-    serialize_type!(output, constant_bytes.len(), usize);
-    serialize_type!(output, memory.len(), usize);
-    serialize_type!(output, locations.len(), usize);
+    let mut output = header(constant_bytes.len(), memory.len(), locations.len(), upcodes.len());
     output.extend_from_slice(&constant_bytes);
     output.extend_from_slice(&memory);
-    for _l in locations {
-        serialize_type!(output, _l.address, usize);
-        serialize_type!(output, _l.line, usize);
+    for location in locations {
+        serialize_usize(&mut output, location.address);
+        serialize_usize(&mut output, location.line);
     }
     output.extend_from_slice(&upcodes);
     output
 }
 
-pub fn from_bytes(bytes: &[u8], stack_size: Option<usize>) -> VM {
-    let constant_length = extract_usize(&bytes[0..USIZE_SIZE]);
-    let memory_length = extract_usize(&bytes[USIZE_SIZE..USIZE_SIZE * 2]);
-    let location_length = extract_usize(&bytes[USIZE_SIZE * 2..USIZE_SIZE * 3]);
-    let memory_bytes = &bytes[USIZE_SIZE * 3 + constant_length
-        ..USIZE_SIZE * 3 + constant_length + memory_length];
-    let (addresses, constants) = extract_constants(
-        &mut bytes[USIZE_SIZE * 3..USIZE_SIZE * 3 + constant_length].iter().cloned(), memory_bytes
-    );
-    let constants = constants.into_iter().map(|v| {
-        CompoundValue::SimpleValue(v)
-    }).collect();
+pub fn from_bytes(bytes: &[u8], stack_size: Option<usize>) -> Result<VM, Error> {
+    let sections = Sections::new(bytes)?;
+    let memory_bytes = &bytes[sections.memory];
+    let memory_length = memory_bytes.len();
+    let (addresses, constants) = extract_constants(&mut bytes[sections.constants].iter().cloned(), sections.version)?;
+    let constructed_size: usize = constants.iter().map(|c| c.allocation_size()).sum();
     let mut sizes = vec![];
     let mut diffs = addresses;
     diffs.sort();
+    if let Some(&address) = diffs.last().filter(|address| **address > memory_length) {
+        Err(SerdeError::AddressOutOfMemory { address, size: memory_length })?;
+    }
     diffs.push(memory_length);
     for (i, s) in diffs[1..].iter().enumerate() {
         sizes.push(s - diffs[i]);
     }
-    let stack_size = stack_size.unwrap_or(memory_length);
+    let stack_size = stack_size.unwrap_or(memory_length + constructed_size);
     let memory = Memory::new(stack_size);
-    memory.copy_u8_vector(memory_bytes, 0);
+    memory.copy_u8_vector(memory_bytes, 0)?;
     let mut locations = vec![];
-    for i in 0..location_length {
+    for location in bytes[sections.locations].chunks(LOCATION_SIZE) {
         locations.push(Location {
-            address: extract_usize(
-                &bytes[USIZE_SIZE * 3 + constant_length + memory_length + i * 2 * USIZE_SIZE
-                    ..USIZE_SIZE * 3
-                    + constant_length
-                    + memory_length
-                    + (i * 2 + 1) * USIZE_SIZE],
-            ),
-            line: extract_usize(
-                &bytes[USIZE_SIZE * 3
-                    + constant_length
-                    + memory_length
-                    + (i * 2 + 1) * USIZE_SIZE
-                    ..USIZE_SIZE * 3
-                    + constant_length
-                    + memory_length
-                    + (i * 2 + 2) * USIZE_SIZE],
-            ),
+            address: deserialize_usize(location, 0)?,
+            line: deserialize_usize(location, SERIALIZED_USIZE_SIZE)?,
         });
     }
-    let bytes = &bytes
-        [USIZE_SIZE * 3 + constant_length + memory_length + location_length * 2 * USIZE_SIZE..];
+    let bytes = &bytes[sections.rom];
     let mut rom = vec![];
     let mut index = 0;
     while index < bytes.len() {
         let to = min(index + 17, bytes.len());
-        let instruction = Instruction::from(&bytes[index..to]);
+        let instruction = Instruction::try_from(&bytes[index..to])?;
         index += instruction.size() as usize;
         rom.push(instruction);
     }
     let mut vm = VM {
-        allocator: RefCell::new(Allocator::new_with_addresses(stack_size, &sizes).unwrap()),
+        allocator: RefCell::new(Allocator::new_with_addresses(stack_size, &sizes)?),
         debug: false,
+        allocation_limit: DEFAULT_ALLOCATION_LIMIT,
         frames: vec![],
         globals: Default::default(),
         sp: 0,
         stack: [NULL_VALUE; STACK_MAX],
-        constants,
+        host: Host::new(),
+        exit_value: None,
+        allocation_sites: None,
+        garbage_collection: true,
+        #[cfg(feature = "profile-interp")]
+        profile: crate::profile::InterpProfile::new(),
+        constants: Vec::with_capacity(constants.len()),
         locations,
         memory,
         rom,
     };
+    build_constants(&mut vm, &constants)?;
     vm.new_frame(0, 0);
-    vm
+    Ok(vm)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::cpu::{Location, Value, CompoundValue};
     use crate::instruction::{Instruction, InstructionType};
-    use crate::serde::{from_bytes, to_bytes};
+    use crate::serde::{
+        constant_table, from_bytes, header, to_bytes, ConstantDeclaration, SerdeError, Sections,
+        HEADER_SIZE, LENGTHS_OFFSET,
+    };
+    use std::env;
+    use std::fs;
+    use std::path::Path;
 
     fn create_instruction(instruction_type: InstructionType) -> Instruction {
         Instruction {
@@ -162,17 +435,19 @@ mod tests {
     #[test]
     fn it_should_serialize_a_vm() {
         let bytes = [
-            78u8, 0, 0, 0, 0, 0, 0, 0, // Constant length
+            b'S', b'M', b'K', b'D', 3, // Magic and version
+            79u8, 0, 0, 0, 0, 0, 0, 0, // Constant length
             8, 0, 0, 0, 0, 0, 0, 0, // Memory length
             1, 0, 0, 0, 0, 0, 0, 0, // Locations length
+            53, 0, 0, 0, 0, 0, 0, 0, // ROM length
             0, // Nil value - 1
             1, 42, 0, 0, 0, 0, 0, 0, 0, // Integer value - 10
             2, 42, 42, 42, 42, // Float value - 15
             3, 1, // Bool value - 17
             4, 4, 0, 0, 0, 0, 0, 0, 0, // String value - 26
-            5, 42, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, // Function value - 43
-            6, 2, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, // Array value - 52
-            7, 6, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, // Object value - 61
+            5, 42, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, 0, // Function value - 44
+            6, 2, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, // Array value - 53
+            7, 6, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, // Object value - 62
             0, 1, 2, 3, 4, 5, 6, 7, // Memory
             1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // Locations
             0, 0, 0, 0, 0, 0, 0, 0, 0, // ROM
@@ -181,7 +456,7 @@ mod tests {
         ];
         let got = to_bytes(&[
             Value::Nil, Value::Integer(42), Value::Float(0.00000000000015113662f32), Value::Bool(true),
-            Value::String(4), Value::Function { arity: 42, ip: 42, uplifts: None, }, Value::Array { capacity: 2, address: 4},
+            Value::String(4), Value::Function { arity: 42, ip: 42, uplifts: None, defaults: None }, Value::Array { capacity: 2, address: 4},
             Value::Object { address: 6, tags: 6 },
         ],&[Location { address: 1, line: 1, }], &[0u8, 1, 2, 3, 4, 5, 6, 7],
             &[
@@ -198,44 +473,33 @@ mod tests {
     #[test]
     fn it_should_deserialize_into_a_vm() {
         let bytes = [
-            78u8, 0, 0, 0, 0, 0, 0, 0, // Constant length
-            14, 0, 0, 0, 0, 0, 0, 0, // Memory length
+            b'S', b'M', b'K', b'D', 3, // Magic and version
+            45u8, 0, 0, 0, 0, 0, 0, 0, // Constant length
+            6, 0, 0, 0, 0, 0, 0, 0, // Memory length
             1, 0, 0, 0, 0, 0, 0, 0, // Locations length
+            53, 0, 0, 0, 0, 0, 0, 0, // ROM length
             0, // Nil value - 1
             1, 42, 0, 0, 0, 0, 0, 0, 0, // Integer value - 10
             2, 42, 42, 42, 42, // Float value - 15
             3, 1, // Bool value - 17
             4, 4, 0, 0, 0, 0, 0, 0, 0, // String value - 26
-            5, 42, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, // Function value - 43
-            6, 2, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, // Array value - 52
-            7, 6, 0, 0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0,// Object value - 69
-            0, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0, // Memory
+            5, 42, 0, 0, 0, 0, 0, 0, 0, 42, 0, 0, 0, 0, 0, 0, 0, 0, 0, // Function value - 45
+            b'f', b'i', b'l', b'e', b'h', b'i', // Memory
             1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, // Locations
             0, 0, 0, 0, 0, 0, 0, 0, 0, // ROM
             1, 42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0, 0,
             0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0,
         ];
-        let vm = from_bytes(bytes.as_ref(), None);
-        assert_eq!(vm.constants.len(), 8);
+        let vm = from_bytes(bytes.as_ref(), None).unwrap();
+        assert_eq!(vm.constants.len(), 6);
         assert_eq!(&vm.constants[0], &CompoundValue::SimpleValue(Value::Nil));
         assert_eq!(&vm.constants[1], &CompoundValue::SimpleValue(Value::Integer(42)));
         assert_eq!(&vm.constants[2], &CompoundValue::SimpleValue(Value::Float(0.00000000000015113662f32)));
         assert_eq!(&vm.constants[3], &CompoundValue::SimpleValue(Value::Bool(true)));
         assert_eq!(&vm.constants[4], &CompoundValue::SimpleValue(Value::String(4)));
-        assert_eq!(&vm.constants[5], &CompoundValue::SimpleValue(Value::Function { arity: 42, ip: 42, uplifts: None }));
-        assert_eq!(
-            &vm.constants[6],
-            &CompoundValue::SimpleValue(Value::Array {
-                capacity: 2,
-                address: 4
-            })
-        );
-        assert_eq!(&vm.constants[7], &CompoundValue::SimpleValue(Value::Object { address: 6, tags: 6 }));
-        assert_eq!(vm.memory.get_capacity(), 14);
-        assert_eq!(
-            vm.memory.get_u8_vector(0, 14).unwrap(),
-            &[0u8, 0, 0, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0]
-        );
+        assert_eq!(&vm.constants[5], &CompoundValue::SimpleValue(Value::Function { arity: 42, ip: 42, uplifts: None, defaults: None }));
+        assert_eq!(vm.memory.get_capacity(), 6);
+        assert_eq!(vm.memory.get_u8_vector(0, 6).unwrap(), b"filehi");
         assert_eq!(
             &vm.locations,
             &[Location {
@@ -254,4 +518,332 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn it_should_keep_checked_arithmetic_when_deserializing() {
+        let rom = [
+            create_instruction(InstructionType::CheckedPlus),
+            create_instruction(InstructionType::CheckedMinus),
+            create_instruction(InstructionType::CheckedMult),
+        ];
+        let vm = from_bytes(&to_bytes::<Value>(&[], &[], &[], &rom), None).unwrap();
+        assert_eq!(&vm.rom, &rom);
+    }
+
+    fn constant_array_program() -> Vec<u8> {
+        to_bytes(
+            &[
+                ConstantDeclaration::Value(Value::Integer(1)),
+                ConstantDeclaration::Value(Value::Integer(42)),
+                ConstantDeclaration::Array(vec![0, 1]),
+            ],
+            &[],
+            &[],
+            &[
+                create_instruction(InstructionType::Constant(0)),
+                create_instruction(InstructionType::Constant(2)),
+                create_instruction(InstructionType::ArrayGet),
+            ],
+        )
+    }
+
+    #[test]
+    fn it_should_load_constant_arrays() {
+        let mut vm = from_bytes(&constant_array_program(), None).unwrap();
+        while !vm.is_done() {
+            vm.execute().unwrap();
+        }
+        assert_eq!(vm.stack(), &[CompoundValue::SimpleValue(Value::Integer(42))]);
+    }
+
+    #[test]
+    fn it_should_load_constant_objects() {
+        let bytes = to_bytes(
+            &[
+                ConstantDeclaration::Value(Value::String(0)),
+                ConstantDeclaration::Value(Value::String(1)),
+                ConstantDeclaration::Value(Value::Integer(42)),
+                ConstantDeclaration::Object(vec![(0, 2), (1, 0)]),
+            ],
+            &[],
+            b"ba",
+            &[
+                create_instruction(InstructionType::Constant(0)),
+                create_instruction(InstructionType::Constant(3)),
+                create_instruction(InstructionType::ObjectGet),
+            ],
+        );
+        let mut vm = from_bytes(&bytes, None).unwrap();
+        while !vm.is_done() {
+            vm.execute().unwrap();
+        }
+        assert_eq!(vm.stack(), &[CompoundValue::SimpleValue(Value::Integer(42))]);
+    }
+
+    #[test]
+    fn it_should_reject_constants_referencing_later_ones() {
+        let bytes = to_bytes(
+            &[
+                ConstantDeclaration::Array(vec![1]),
+                ConstantDeclaration::Value(Value::Integer(42)),
+            ],
+            &[],
+            &[],
+            &[create_instruction(InstructionType::Constant(0))],
+        );
+        let error = from_bytes(&bytes, None).err().unwrap().downcast::<SerdeError>().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Constant 0 references constant 1, which isn't declared before it"
+        );
+    }
+
+    #[test]
+    fn it_should_render_the_constant_table() {
+        assert_eq!(
+            constant_table(&constant_array_program()).unwrap(),
+            "#0 Integer(1)\n#1 Integer(42)\n#2 Array [#0, #1]\n"
+        );
+    }
+
+    fn portable_program() -> Vec<u8> {
+        to_bytes(
+            &[
+                ConstantDeclaration::Value(Value::Integer(-2)),
+                ConstantDeclaration::Value(Value::Float(1.5)),
+                ConstantDeclaration::Value(Value::String(0)),
+                ConstantDeclaration::Value(Value::Function { ip: 5, arity: 1, uplifts: Some(3), defaults: None }),
+                ConstantDeclaration::Array(vec![0, 1]),
+            ],
+            &[Location { address: 0, line: 1 }, Location { address: 2, line: 3 }],
+            b"hi",
+            &[
+                create_instruction(InstructionType::Constant(4)),
+                create_instruction(InstructionType::GetGlobal(7)),
+                create_instruction(InstructionType::Plus),
+                create_instruction(InstructionType::Jmp(300)),
+            ],
+        )
+    }
+
+    fn load_error(bytes: &[u8]) -> SerdeError {
+        from_bytes(bytes, None).err().unwrap().downcast::<SerdeError>().unwrap()
+    }
+
+    #[test]
+    fn it_should_round_trip_a_program() {
+        let vm = from_bytes(&portable_program(), None).unwrap();
+        assert_eq!(&vm.constants[..4], &[
+            CompoundValue::SimpleValue(Value::Integer(-2)),
+            CompoundValue::SimpleValue(Value::Float(1.5)),
+            CompoundValue::SimpleValue(Value::String(0)),
+            CompoundValue::SimpleValue(Value::Function { ip: 5, arity: 1, uplifts: Some(3), defaults: None }),
+        ]);
+        assert_eq!(&vm.locations, &[Location { address: 0, line: 1 }, Location { address: 2, line: 3 }]);
+        assert_eq!(vm.rom.len(), 4);
+        assert_eq!(vm.rom[3], create_instruction(InstructionType::Jmp(300)));
+    }
+
+    // The fixture was written once by this test, set UPDATE_SNAPSHOTS to rewrite it after an
+    // intended change to the format, along with FORMAT_VERSION
+    #[test]
+    fn it_should_load_the_checked_in_program_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/program_v3.smkd");
+        if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            fs::write(&path, portable_program()).unwrap();
+        }
+        let fixture = fs::read(&path)
+            .unwrap_or_else(|_| panic!("{} is missing, run with UPDATE_SNAPSHOTS=1", path.display()));
+        assert_eq!(fixture, portable_program());
+        let vm = from_bytes(&fixture, None).unwrap();
+        assert_eq!(&vm.constants[1], &CompoundValue::SimpleValue(Value::Float(1.5)));
+        assert_eq!(vm.rom[0], create_instruction(InstructionType::Constant(4)));
+    }
+
+    // Written by version 2, before the header had the length of the ROM
+    #[test]
+    fn it_should_load_version_2_program_files() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/program_v2.smkd");
+        let vm = from_bytes(&fs::read(&path).unwrap(), None).unwrap();
+        let current = from_bytes(&portable_program(), None).unwrap();
+        assert_eq!(&vm.constants[..4], &current.constants[..4]);
+        assert_eq!(&vm.locations, &current.locations);
+        assert_eq!(&vm.rom, &current.rom);
+    }
+
+    // Written by version 1, before functions had default arguments
+    #[test]
+    fn it_should_load_version_1_program_files() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/program_v1.smkd");
+        let vm = from_bytes(&fs::read(&path).unwrap(), None).unwrap();
+        let current = from_bytes(&portable_program(), None).unwrap();
+        assert_eq!(&vm.constants[..4], &current.constants[..4]);
+        assert_eq!(&vm.locations, &current.locations);
+        assert_eq!(&vm.rom, &current.rom);
+    }
+
+    #[test]
+    fn it_should_reject_files_from_other_formats() {
+        let mut bytes = portable_program();
+        bytes[4] = 0;
+        assert_eq!(
+            load_error(&bytes).to_string(),
+            "The program uses format version 0, but only versions 1 to 3 are supported"
+        );
+        bytes[0] = b'X';
+        match load_error(&bytes) {
+            SerdeError::InvalidMagic { .. } => {}
+            error => panic!("Unexpected {:?}", error),
+        }
+    }
+
+    #[test]
+    fn it_should_reject_truncated_files() {
+        let bytes = portable_program();
+        match load_error(&bytes[..10]) {
+            SerdeError::Truncated { size: 10, expected: HEADER_SIZE } => {}
+            error => panic!("Unexpected {:?}", error),
+        }
+        // Cut inside the locations
+        let locations = Sections::new(&bytes).unwrap().locations;
+        match load_error(&bytes[..locations.end - 10]) {
+            SerdeError::Truncated { .. } => {}
+            error => panic!("Unexpected {:?}", error),
+        }
+        let mut huge = bytes.clone();
+        huge[LENGTHS_OFFSET..LENGTHS_OFFSET + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        match load_error(&huge) {
+            SerdeError::Truncated { .. } | SerdeError::NumberTooLarge { .. } => {}
+            error => panic!("Unexpected {:?}", error),
+        }
+    }
+
+    fn portable_constants() -> Vec<ConstantDeclaration> {
+        vec![
+            ConstantDeclaration::Value(Value::Integer(-2)),
+            ConstantDeclaration::Value(Value::Float(1.5)),
+            ConstantDeclaration::Value(Value::String(0)),
+            ConstantDeclaration::Value(Value::Function { ip: 5, arity: 1, uplifts: Some(3), defaults: None }),
+            ConstantDeclaration::Array(vec![0, 1]),
+            ConstantDeclaration::Object(vec![(2, 0)]),
+        ]
+    }
+
+    // The header has the length of the ROM, so even a file cut between two instructions is
+    // rejected
+    #[test]
+    fn it_should_reject_files_cut_anywhere() {
+        let bytes = portable_program();
+        for length in 0..bytes.len() {
+            match from_bytes(&bytes[..length], None) {
+                Ok(_) => panic!("Loaded a file cut at {}", length),
+                Err(error) => assert!(error.downcast::<SerdeError>().is_ok()),
+            }
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        match load_error(&longer) {
+            SerdeError::TrailingBytes { size, expected } => {
+                assert_eq!((size, expected), (bytes.len() + 1, bytes.len()))
+            }
+            error => panic!("Unexpected {:?}", error),
+        }
+    }
+
+    #[test]
+    fn it_should_reject_constants_cut_anywhere() {
+        let mut constants = vec![];
+        let mut boundaries = vec![0];
+        for declaration in portable_constants() {
+            let declaration: Vec<u8> = declaration.into();
+            constants.extend_from_slice(&declaration);
+            boundaries.push(constants.len());
+        }
+        let memory = [0u8; 16];
+        for length in 0..=constants.len() {
+            let mut bytes = header(length, memory.len(), 0, 0);
+            bytes.extend_from_slice(&constants[..length]);
+            bytes.extend_from_slice(&memory);
+            match from_bytes(&bytes, None) {
+                Ok(vm) => assert_eq!(
+                    boundaries.iter().position(|b| *b == length),
+                    Some(vm.constants.len())
+                ),
+                Err(error) => match error.downcast::<SerdeError>().unwrap() {
+                    SerdeError::UnexpectedEnd => assert!(!boundaries.contains(&length)),
+                    error => panic!("Unexpected {:?} at {}", error, length),
+                },
+            }
+        }
+    }
+
+    fn defaults_program(defaults: usize) -> Vec<u8> {
+        to_bytes(
+            &[
+                ConstantDeclaration::Value(Value::String(0)),
+                ConstantDeclaration::Array(vec![0]),
+                ConstantDeclaration::Value(Value::Function { ip: 0, arity: 2, uplifts: None, defaults: Some(defaults) }),
+            ],
+            &[],
+            b"hi",
+            &[create_instruction(InstructionType::Return)],
+        )
+    }
+
+    #[test]
+    fn it_should_load_function_defaults_from_an_array_constant() {
+        let vm = from_bytes(&defaults_program(1), None).unwrap();
+        let address = match vm.constants[1] {
+            CompoundValue::SimpleValue(Value::Array { address, .. }) => address,
+            ref constant => panic!("Unexpected {:?}", constant),
+        };
+        assert_eq!(
+            vm.constants[2],
+            CompoundValue::SimpleValue(Value::Function { ip: 0, arity: 2, uplifts: None, defaults: Some(address) })
+        );
+        assert_eq!(
+            load_error(&defaults_program(0)).to_string(),
+            "Constant 2 takes its default arguments from constant 0, but it isn't an array"
+        );
+    }
+
+    #[test]
+    fn it_should_reject_unknown_tags() {
+        let mut bytes = portable_program();
+        bytes[HEADER_SIZE] = 42;
+        match load_error(&bytes) {
+            SerdeError::UnknownValueTag { tag: 42 } => {}
+            error => panic!("Unexpected {:?}", error),
+        }
+        let mut bytes = portable_program();
+        let rom_start = Sections::new(&bytes).unwrap().rom.start;
+        bytes[rom_start] = 200;
+        assert_eq!(load_error(&bytes).to_string(), "Unknown instruction tag 200");
+    }
+
+    #[test]
+    fn it_should_reject_addresses_outside_of_the_memory() {
+        let noop = [create_instruction(InstructionType::Noop)];
+        let bytes = to_bytes(&[Value::String(100)], &[], b"hi", &noop);
+        assert_eq!(
+            load_error(&bytes).to_string(),
+            "A constant points to address 100, but the memory has 2 bytes"
+        );
+    }
+
+    #[test]
+    fn it_should_reject_arrays_objects_and_pointers_in_the_memory() {
+        let noop = [create_instruction(InstructionType::Noop)];
+        for value in [
+            Value::Array { capacity: 1, address: 0 },
+            Value::Object { address: 0, tags: 0 },
+            Value::Pointer(0),
+        ].iter() {
+            let bytes = to_bytes(&[Value::String(0), *value], &[], &[0u8; 32], &noop);
+            match load_error(&bytes) {
+                SerdeError::HeapConstant { constant: 1 } => {}
+                error => panic!("Unexpected {:?}", error),
+            }
+        }
+    }
 }
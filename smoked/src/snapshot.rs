@@ -0,0 +1,110 @@
+use crate::allocator::Allocator;
+use crate::cpu::{CompoundValue, Frame, VM};
+use crate::host::Host;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VmSnapshot {
+    allocator: Allocator,
+    memory: Vec<u8>,
+    frames: Vec<Frame>,
+    globals: Vec<Option<CompoundValue>>,
+    host: Host,
+    exit_value: Option<CompoundValue>,
+    sp: usize,
+    stack: Vec<CompoundValue>,
+    constants_len: usize,
+    locations_len: usize,
+    rom_len: usize,
+}
+
+impl VM {
+    pub fn snapshot(&self) -> VmSnapshot {
+        VmSnapshot {
+            allocator: self.allocator.borrow().clone(),
+            memory: self.memory.dump(),
+            frames: self.frames.clone(),
+            globals: self.globals.clone(),
+            host: self.host.clone(),
+            exit_value: self.exit_value.clone(),
+            sp: self.sp,
+            stack: self.stack.to_vec(),
+            constants_len: self.constants.len(),
+            locations_len: self.locations.len(),
+            rom_len: self.rom.len(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &VmSnapshot) {
+        self.allocator.borrow_mut().clone_from(&snapshot.allocator);
+        self.memory.load(&snapshot.memory);
+        self.frames.clone_from(&snapshot.frames);
+        self.globals.clone_from(&snapshot.globals);
+        self.host.clone_from(&snapshot.host);
+        self.exit_value.clone_from(&snapshot.exit_value);
+        self.sp = snapshot.sp;
+        self.stack.clone_from_slice(&snapshot.stack);
+        self.constants.truncate(snapshot.constants_len);
+        self.locations.truncate(snapshot.locations_len);
+        self.rom.truncate(snapshot.rom_len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::Allocator;
+    use crate::cpu::{CompoundValue, Value, VM};
+    use crate::instruction::{Instruction, InstructionType};
+    use crate::memory::Memory;
+
+    fn create_instruction(instruction_type: InstructionType) -> Instruction {
+        Instruction {
+            instruction_type,
+            location: 0,
+        }
+    }
+
+    fn create_loop_vm() -> VM {
+        let rom = vec![
+            create_instruction(InstructionType::GetGlobal(0)),
+            create_instruction(InstructionType::Constant(0)),
+            create_instruction(InstructionType::Plus),
+            create_instruction(InstructionType::SetGlobal(0)),
+            create_instruction(InstructionType::Pop),
+            create_instruction(InstructionType::Constant(1)),
+            create_instruction(InstructionType::ArrayAlloc),
+            create_instruction(InstructionType::SetGlobal(1)),
+            create_instruction(InstructionType::Pop),
+            create_instruction(InstructionType::Loop(10)),
+        ];
+        let constants = vec![
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(Value::Integer(2)),
+        ];
+        let mut vm = VM::new(Allocator::new(8192), constants, vec![], Memory::new(8192), rom);
+        vm.store_global(0, CompoundValue::SimpleValue(Value::Integer(0)));
+        vm.new_frame(0, 0);
+        vm
+    }
+
+    fn run(vm: &mut VM, instructions: usize) {
+        for _ in 0..instructions {
+            vm.execute().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_restore_replays_deterministically() {
+        let mut vm = create_loop_vm();
+        run(&mut vm, 57);
+        let snapshot = vm.snapshot();
+        run(&mut vm, 100);
+        let first = vm.snapshot();
+        vm.restore(&snapshot);
+        assert_eq!(vm.snapshot(), snapshot);
+        run(&mut vm, 100);
+        let second = vm.snapshot();
+        assert_eq!(first, second);
+        assert_ne!(first, snapshot);
+        assert_eq!(vm.global(0), Some(&CompoundValue::SimpleValue(Value::Integer(16))));
+    }
+}
@@ -0,0 +1,636 @@
+use crate::cpu::{CompoundValue, Value, STACK_MAX, VM};
+use crate::instruction::{Instruction, InstructionType};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AbstractType {
+    Nil,
+    Bool,
+    Num,
+    Str,
+    Arr,
+    Obj,
+    Fun,
+    Any,
+}
+
+impl AbstractType {
+    fn merge(self, other: AbstractType) -> AbstractType {
+        if self == other {
+            self
+        } else {
+            AbstractType::Any
+        }
+    }
+
+    // Any always passes, since the validator only reports instructions guaranteed to fail
+    fn is_not(self, expected: AbstractType) -> bool {
+        self != AbstractType::Any && self != expected
+    }
+}
+
+impl From<&CompoundValue> for AbstractType {
+    fn from(value: &CompoundValue) -> AbstractType {
+        match value {
+            CompoundValue::SimpleValue(Value::Nil) => AbstractType::Nil,
+            CompoundValue::SimpleValue(Value::Bool(_)) => AbstractType::Bool,
+            CompoundValue::SimpleValue(Value::Integer(_)) |
+            CompoundValue::SimpleValue(Value::Float(_)) => AbstractType::Num,
+            CompoundValue::SimpleValue(Value::String(_)) => AbstractType::Str,
+            CompoundValue::SimpleValue(Value::Array { .. }) => AbstractType::Arr,
+            CompoundValue::SimpleValue(Value::Object { .. }) => AbstractType::Obj,
+            CompoundValue::SimpleValue(Value::Function { .. }) |
+            CompoundValue::PartialFunction { .. } => AbstractType::Fun,
+            CompoundValue::SimpleValue(Value::Pointer(_)) => AbstractType::Any,
+        }
+    }
+}
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum TypeWarningKind {
+    #[fail(display = "Expected two numbers. Got {:?} and {:?}", 0, 1)]
+    ExpectedNumbers(AbstractType, AbstractType),
+    #[fail(display = "Expected a number. Got {:?}", 0)]
+    ExpectedNumber(AbstractType),
+    #[fail(display = "Expected two Strings. Got {:?} and {:?}", 0, 1)]
+    ExpectedStrings(AbstractType, AbstractType),
+    #[fail(display = "Expected a String. Got {:?}", 0)]
+    ExpectedString(AbstractType),
+    #[fail(display = "Expected an object. Got {:?}", 0)]
+    ExpectedObject(AbstractType),
+    #[fail(display = "Expected an array. Got {:?}", 0)]
+    ExpectedArray(AbstractType),
+    #[fail(display = "Expected a function. Got {:?}", 0)]
+    ExpectedFunction(AbstractType),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TypeWarning {
+    pub ip: usize,
+    pub line: Option<usize>,
+    pub kind: TypeWarningKind,
+}
+
+impl fmt::Display for TypeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "[line {}, instruction {}] {}", line, self.ip, self.kind),
+            None => write!(f, "[instruction {}] {}", self.ip, self.kind),
+        }
+    }
+}
+
+// The types known at the top of the operand stack. When `exact` is set, `types` is the whole frame,
+// so locals can be tracked too. Otherwise anything below it is unknown.
+#[derive(Clone, Debug, PartialEq)]
+struct StackState {
+    types: Vec<AbstractType>,
+    exact: bool,
+}
+
+impl StackState {
+    fn new(depth: usize) -> StackState {
+        StackState {
+            types: vec![AbstractType::Any; depth],
+            exact: true,
+        }
+    }
+
+    fn unknown() -> StackState {
+        StackState {
+            types: vec![],
+            exact: false,
+        }
+    }
+
+    fn pop(&mut self) -> AbstractType {
+        self.types.pop().unwrap_or(AbstractType::Any)
+    }
+
+    fn peek(&self) -> AbstractType {
+        self.types.last().cloned().unwrap_or(AbstractType::Any)
+    }
+
+    fn push(&mut self, t: AbstractType) {
+        if self.types.len() == STACK_MAX {
+            self.types.remove(0);
+            self.exact = false;
+        }
+        self.types.push(t);
+    }
+
+    fn get_local(&self, local: usize) -> AbstractType {
+        if self.exact {
+            self.types.get(local).cloned().unwrap_or(AbstractType::Any)
+        } else {
+            AbstractType::Any
+        }
+    }
+
+    // Mirrors VM::set_local, which grows the frame when the local is past the top
+    fn set_local(&mut self, local: usize) {
+        let t = self.pop();
+        if !self.exact || local >= STACK_MAX {
+            *self = StackState::unknown();
+            self.push(t);
+            return;
+        }
+        if local >= self.types.len() {
+            self.types.resize(local + 1, AbstractType::Any);
+        }
+        // The local may hold a pointer, in which case the value is written through it
+        let stored = if self.types[local] == AbstractType::Any { AbstractType::Any } else { t };
+        self.types[local] = stored;
+        self.push(stored);
+    }
+
+    fn merge(&self, other: &StackState) -> StackState {
+        if self.exact && other.exact && self.types.len() == other.types.len() {
+            return StackState {
+                types: self.types.iter().zip(other.types.iter()).map(|(a, b)| a.merge(*b)).collect(),
+                exact: true,
+            };
+        }
+        let depth = self.types.len().min(other.types.len());
+        let mut types: Vec<AbstractType> = self.types.iter().rev()
+            .zip(other.types.iter().rev())
+            .take(depth)
+            .map(|(a, b)| a.merge(*b))
+            .collect();
+        types.reverse();
+        StackState {
+            types,
+            exact: false,
+        }
+    }
+}
+
+struct Validator<'a> {
+    rom: &'a [Instruction],
+    constants: &'a [CompoundValue],
+    warnings: Vec<TypeWarning>,
+}
+
+impl<'a> Validator<'a> {
+    fn warn(&mut self, ip: usize, kind: TypeWarningKind) {
+        self.warnings.push(TypeWarning {
+            ip,
+            line: None,
+            kind,
+        });
+    }
+
+    fn expect(&mut self, ip: usize, t: AbstractType, expected: AbstractType) {
+        if t.is_not(expected) {
+            let kind = match expected {
+                AbstractType::Num => TypeWarningKind::ExpectedNumber(t),
+                AbstractType::Str => TypeWarningKind::ExpectedString(t),
+                AbstractType::Obj => TypeWarningKind::ExpectedObject(t),
+                AbstractType::Arr => TypeWarningKind::ExpectedArray(t),
+                _ => TypeWarningKind::ExpectedFunction(t),
+            };
+            self.warn(ip, kind);
+        }
+    }
+
+    fn expect_array_and_index(&mut self, ip: usize, state: &mut StackState) {
+        let array = state.pop();
+        let index = state.pop();
+        self.expect(ip, array, AbstractType::Arr);
+        if array == AbstractType::Arr {
+            self.expect(ip, index, AbstractType::Num);
+        }
+    }
+
+    fn expect_object_and_string(&mut self, ip: usize, state: &mut StackState) {
+        let object = state.pop();
+        let key = state.pop();
+        self.expect(ip, object, AbstractType::Obj);
+        self.expect(ip, key, AbstractType::Str);
+    }
+
+    fn jump_target(&self, ip: usize) -> Option<usize> {
+        match self.rom[ip].instruction_type {
+            InstructionType::Jmp(offset) | InstructionType::JmpIfFalse(offset) => Some(ip + 1 + offset),
+            InstructionType::Loop(offset) => (ip + 1).checked_sub(offset),
+            _ => None,
+        }.filter(|target| *target < self.rom.len())
+    }
+
+    fn falls_through(&self, ip: usize) -> bool {
+        match self.rom[ip].instruction_type {
+            InstructionType::Return | InstructionType::Jmp(_) | InstructionType::Loop(_) => false,
+            _ => ip + 1 < self.rom.len(),
+        }
+    }
+
+    // Main program at the start of the ROM, plus the body of every function constant
+    fn entry_points(&self) -> Vec<(usize, usize)> {
+        let mut entries = vec![(0, 0)];
+        for constant in self.constants {
+            match constant {
+                CompoundValue::SimpleValue(Value::Function { ip, arity, .. }) |
+                CompoundValue::PartialFunction { function: Value::Function { ip, arity, .. }, .. }
+                    if *ip < self.rom.len() => entries.push((*ip, *arity)),
+                _ => {}
+            }
+        }
+        entries
+    }
+
+    fn basic_blocks(&self, entries: &[(usize, usize)]) -> BTreeSet<usize> {
+        let mut leaders: BTreeSet<usize> = entries.iter().map(|(ip, _)| *ip).collect();
+        for ip in 0..self.rom.len() {
+            if let Some(target) = self.jump_target(ip) {
+                leaders.insert(target);
+            }
+            match self.rom[ip].instruction_type {
+                InstructionType::Return | InstructionType::Jmp(_) |
+                InstructionType::JmpIfFalse(_) | InstructionType::Loop(_) if ip + 1 < self.rom.len() => {
+                    leaders.insert(ip + 1);
+                }
+                _ => {}
+            }
+        }
+        leaders
+    }
+
+    fn step(&mut self, ip: usize, state: &mut StackState) {
+        match &self.rom[ip].instruction_type {
+            InstructionType::Noop | InstructionType::Return | InstructionType::Jmp(_) |
+            InstructionType::Loop(_) | InstructionType::Yield => {}
+            InstructionType::Constant(index) => {
+                let t = self.constants.get(*index).map_or(AbstractType::Any, AbstractType::from);
+                state.push(t);
+            }
+            InstructionType::Nil => state.push(AbstractType::Nil),
+            InstructionType::True | InstructionType::False => state.push(AbstractType::Bool),
+            InstructionType::Plus | InstructionType::Minus | InstructionType::Mult |
+            InstructionType::Div | InstructionType::CheckedPlus | InstructionType::CheckedMinus |
+            InstructionType::CheckedMult | InstructionType::Mod | InstructionType::BitAnd |
+            InstructionType::BitOr | InstructionType::BitXor | InstructionType::Shl |
+            InstructionType::Shr => {
+                let a = state.pop();
+                let b = state.pop();
+                if a.is_not(AbstractType::Num) || b.is_not(AbstractType::Num) {
+                    self.warn(ip, TypeWarningKind::ExpectedNumbers(a, b));
+                }
+                state.push(AbstractType::Num);
+            }
+            InstructionType::Not | InstructionType::CheckType(_) => {
+                state.pop();
+                state.push(AbstractType::Bool);
+            }
+            InstructionType::Equal | InstructionType::NotEqual | InstructionType::Greater |
+            InstructionType::GreaterEqual | InstructionType::Less | InstructionType::LessEqual |
+            InstructionType::And | InstructionType::Or => {
+                state.pop();
+                state.pop();
+                state.push(AbstractType::Bool);
+            }
+            InstructionType::Abs => {
+                let t = state.pop();
+                self.expect(ip, t, AbstractType::Num);
+                state.push(AbstractType::Num);
+            }
+            InstructionType::StringConcat => {
+                let a = state.pop();
+                let b = state.pop();
+                if a.is_not(AbstractType::Str) || b.is_not(AbstractType::Str) {
+                    self.warn(ip, TypeWarningKind::ExpectedStrings(a, b));
+                }
+                state.push(AbstractType::Str);
+            }
+            InstructionType::Syscall => {
+                *state = StackState::unknown();
+                state.push(AbstractType::Num);
+            }
+            InstructionType::GetGlobal(_) => state.push(AbstractType::Any),
+            InstructionType::Clock | InstructionType::Random => state.push(AbstractType::Num),
+            InstructionType::SetGlobal(_) => {
+                state.pop();
+                state.push(AbstractType::Any);
+            }
+            InstructionType::GetLocal(local) => {
+                let t = state.get_local(*local);
+                state.push(t);
+            }
+            InstructionType::SetLocal(local) => state.set_local(*local),
+            InstructionType::Uplift(local) => {
+                if state.exact && *local < state.types.len() {
+                    state.types[*local] = AbstractType::Any;
+                }
+                state.push(AbstractType::Any);
+            }
+            InstructionType::JmpIfFalse(_) => {
+                state.pop();
+            }
+            InstructionType::Call | InstructionType::CallN(_) => {
+                let t = state.pop();
+                if t.is_not(AbstractType::Fun) && t.is_not(AbstractType::Obj) {
+                    self.warn(ip, TypeWarningKind::ExpectedFunction(t));
+                }
+                *state = StackState::unknown();
+            }
+            InstructionType::ArrayAlloc => {
+                let t = state.pop();
+                self.expect(ip, t, AbstractType::Num);
+                state.push(AbstractType::Arr);
+            }
+            InstructionType::ObjectAlloc => {
+                let t = state.pop();
+                self.expect(ip, t, AbstractType::Num);
+                state.push(AbstractType::Obj);
+            }
+            InstructionType::ArrayGet => {
+                self.expect_array_and_index(ip, state);
+                state.push(AbstractType::Any);
+            }
+            // The value stays on the stack
+            InstructionType::ArraySet => self.expect_array_and_index(ip, state),
+            InstructionType::MultiArraySet => {
+                let t = state.pop();
+                self.expect(ip, t, AbstractType::Arr);
+                *state = StackState::unknown();
+                state.push(AbstractType::Arr);
+            }
+            InstructionType::RepeatedArraySet => {
+                let t = state.pop();
+                self.expect(ip, t, AbstractType::Arr);
+                state.pop();
+                state.push(AbstractType::Arr);
+            }
+            InstructionType::ObjectGet => {
+                self.expect_object_and_string(ip, state);
+                state.push(AbstractType::Any);
+            }
+            InstructionType::ObjectSet => {
+                self.expect_object_and_string(ip, state);
+                let value = state.pop();
+                state.push(value);
+                state.push(AbstractType::Obj);
+            }
+            InstructionType::ObjectHas => {
+                self.expect_object_and_string(ip, state);
+                state.push(AbstractType::Obj);
+                state.push(AbstractType::Bool);
+            }
+            InstructionType::AddTag | InstructionType::RemoveTag => {
+                self.expect_object_and_string(ip, state);
+                state.push(AbstractType::Obj);
+            }
+            InstructionType::CheckTag => {
+                self.expect_object_and_string(ip, state);
+                state.push(AbstractType::Bool);
+            }
+            InstructionType::ObjectMerge => {
+                let second = state.pop();
+                let first = state.pop();
+                self.expect(ip, second, AbstractType::Obj);
+                self.expect(ip, first, AbstractType::Obj);
+                state.push(AbstractType::Obj);
+            }
+            InstructionType::Pop => {
+                state.pop();
+            }
+            InstructionType::Push | InstructionType::Duplicate => {
+                let t = state.peek();
+                state.push(t);
+            }
+            InstructionType::Strlen => {
+                let t = state.pop();
+                self.expect(ip, t, AbstractType::Str);
+                state.push(AbstractType::Num);
+            }
+            InstructionType::Swap => {
+                let top = state.pop();
+                let bottom = state.pop();
+                state.push(top);
+                state.push(bottom);
+            }
+            InstructionType::ToStr => {
+                state.pop();
+                state.push(AbstractType::Str);
+            }
+            InstructionType::AttachArray(_) => {
+                let t = state.pop();
+                self.expect(ip, t, AbstractType::Arr);
+                state.push(AbstractType::Fun);
+            }
+        }
+    }
+
+    fn run_block(&mut self, start: usize, leaders: &BTreeSet<usize>, state: &mut StackState) -> usize {
+        let mut ip = start;
+        loop {
+            self.step(ip, state);
+            if !self.falls_through(ip) || leaders.contains(&(ip + 1)) {
+                return ip;
+            }
+            ip += 1;
+        }
+    }
+
+    fn successors(&self, last: usize) -> Vec<usize> {
+        let mut successors: Vec<usize> = self.jump_target(last).into_iter().collect();
+        if self.falls_through(last) {
+            successors.push(last + 1);
+        }
+        successors
+    }
+
+    fn validate(mut self) -> Vec<TypeWarning> {
+        if self.rom.is_empty() {
+            return vec![];
+        }
+        let entries = self.entry_points();
+        let leaders = self.basic_blocks(&entries);
+        let mut states: BTreeMap<usize, StackState> = BTreeMap::new();
+        let mut worklist = VecDeque::new();
+        for (ip, arity) in entries {
+            states.insert(ip, StackState::new(arity));
+            worklist.push_back(ip);
+        }
+        while let Some(start) = worklist.pop_front() {
+            let mut state = states[&start].clone();
+            let last = self.run_block(start, &leaders, &mut state);
+            for successor in self.successors(last) {
+                let merged = match states.get(&successor) {
+                    Some(existing) => existing.merge(&state),
+                    None => state.clone(),
+                };
+                if states.get(&successor) != Some(&merged) {
+                    states.insert(successor, merged);
+                    worklist.push_back(successor);
+                }
+            }
+        }
+        // Warnings are only collected once the states are stable, to avoid reporting stale types
+        self.warnings.clear();
+        for (start, state) in states.iter() {
+            self.run_block(*start, &leaders, &mut state.clone());
+        }
+        self.warnings.sort_by_key(|w| w.ip);
+        self.warnings
+    }
+}
+
+// Reports instructions that will fail whenever they run. Warnings don't know their source line.
+pub fn validate_types(rom: &[Instruction], constants: &[CompoundValue]) -> Vec<TypeWarning> {
+    Validator {
+        rom,
+        constants,
+        warnings: vec![],
+    }.validate()
+}
+
+impl VM {
+    pub fn type_warnings(&self) -> Vec<TypeWarning> {
+        validate_types(&self.rom, &self.constants)
+            .into_iter()
+            .map(|warning| TypeWarning {
+                line: self.locations.get(self.rom[warning.ip].location).map(|l| l.line),
+                ..warning
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::allocator::Allocator;
+    use crate::cpu::{CompoundValue, Location, Value, VM};
+    use crate::instruction::{Instruction, InstructionType};
+    use crate::memory::Memory;
+    use crate::validator::{validate_types, AbstractType, TypeWarningKind};
+
+    fn create_rom(instructions: Vec<InstructionType>) -> Vec<Instruction> {
+        instructions
+            .into_iter()
+            .enumerate()
+            .map(|(location, instruction_type)| Instruction {
+                instruction_type,
+                location,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_flags_guaranteed_type_errors() {
+        let constants = vec![
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(Value::String(0)),
+        ];
+        let rom = create_rom(vec![
+            InstructionType::Constant(0),
+            InstructionType::Constant(0),
+            InstructionType::Plus,
+            InstructionType::Constant(1),
+            InstructionType::Plus,
+            InstructionType::Pop,
+            InstructionType::Constant(0),
+            InstructionType::Call,
+        ]);
+        let locations = (0..rom.len())
+            .map(|i| Location {
+                address: 0,
+                line: i + 10,
+            })
+            .collect();
+        let vm = VM::new(Allocator::new(0), constants, locations, Memory::new(0), rom);
+        let warnings = vm.type_warnings();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].ip, 4);
+        assert_eq!(warnings[0].line, Some(14));
+        assert_eq!(
+            warnings[0].kind,
+            TypeWarningKind::ExpectedNumbers(AbstractType::Str, AbstractType::Num)
+        );
+        assert_eq!(warnings[1].ip, 7);
+        assert_eq!(
+            warnings[1].kind,
+            TypeWarningKind::ExpectedFunction(AbstractType::Num)
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "[line 14, instruction 4] Expected two numbers. Got Str and Num"
+        );
+    }
+
+    #[test]
+    fn test_accepts_polymorphic_programs() {
+        let constants = vec![
+            CompoundValue::SimpleValue(Value::Integer(1)),
+            CompoundValue::SimpleValue(Value::Float(1.5)),
+            CompoundValue::SimpleValue(Value::String(0)),
+        ];
+        let rom = create_rom(vec![
+            InstructionType::GetGlobal(0),
+            InstructionType::JmpIfFalse(2),
+            InstructionType::Constant(0),
+            InstructionType::Jmp(1),
+            InstructionType::Constant(1),
+            // Integer or Float, both numbers
+            InstructionType::Constant(0),
+            InstructionType::Plus,
+            InstructionType::GetGlobal(1),
+            InstructionType::JmpIfFalse(2),
+            InstructionType::Constant(2),
+            InstructionType::Jmp(1),
+            InstructionType::Constant(0),
+            // Str or Num is only known as Any
+            InstructionType::Constant(0),
+            InstructionType::Plus,
+            InstructionType::GetLocal(0),
+            InstructionType::Constant(0),
+            InstructionType::Plus,
+        ]);
+        assert!(validate_types(&rom, &constants).is_empty());
+    }
+
+    #[test]
+    fn test_tracks_locals_and_function_bodies() {
+        let constants = vec![
+            CompoundValue::SimpleValue(Value::String(0)),
+            CompoundValue::SimpleValue(Value::Function {
+                ip: 5,
+                arity: 1,
+                uplifts: None,
+                defaults: None,
+            }),
+        ];
+        let rom = create_rom(vec![
+            InstructionType::Constant(0),
+            InstructionType::GetLocal(0),
+            InstructionType::Abs,
+            InstructionType::Return,
+            InstructionType::Noop,
+            // Function body: the argument could be anything
+            InstructionType::GetLocal(0),
+            InstructionType::Abs,
+            InstructionType::Return,
+        ]);
+        let warnings = validate_types(&rom, &constants);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].ip, 2);
+        assert_eq!(
+            warnings[0].kind,
+            TypeWarningKind::ExpectedNumber(AbstractType::Str)
+        );
+    }
+
+    #[test]
+    fn test_converges_on_loops() {
+        let constants = vec![CompoundValue::SimpleValue(Value::Integer(1))];
+        let rom = create_rom(vec![
+            InstructionType::Constant(0),
+            InstructionType::GetGlobal(0),
+            InstructionType::JmpIfFalse(1),
+            InstructionType::Loop(4),
+            InstructionType::Constant(0),
+            InstructionType::Plus,
+        ]);
+        assert!(validate_types(&rom, &constants).is_empty());
+    }
+}
@@ -0,0 +1,132 @@
+use smoked::allocator::Allocator;
+use smoked::cpu::{CompoundValue, Location, Value, VM};
+use smoked::instruction::{Instruction, InstructionType, LAST_OPCODE};
+use smoked::memory::Memory;
+use std::convert::TryFrom;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+const ROMS: usize = 2000;
+const ROM_LENGTH: usize = 48;
+const STEPS: usize = 500;
+const MEMORY_SIZE: usize = 4096;
+// Syscall would run on the host
+const SYSCALL: u8 = 17;
+const NOOP: u8 = 255;
+
+// xorshift64, so every run sees the same roms
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, limit: u64) -> u64 {
+        self.next() % limit
+    }
+
+    // Mostly small numbers, which are the ones that hit something, with the edges thrown in
+    fn operand(&mut self) -> u64 {
+        match self.below(8) {
+            0 => u64::MAX - self.below(4),
+            1 => self.next(),
+            2 => (usize::MAX as u64) / 2 + self.below(4),
+            _ => self.below(16),
+        }
+    }
+}
+
+fn random_instruction(random: &mut Random) -> Instruction {
+    let opcode = match random.below(u64::from(LAST_OPCODE) + 2) as u8 {
+        SYSCALL => NOOP,
+        opcode if opcode > LAST_OPCODE => NOOP,
+        opcode => opcode,
+    };
+    let mut bytes = vec![opcode];
+    bytes.extend_from_slice(&random.operand().to_le_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    match Instruction::try_from(&bytes[..]) {
+        Ok(instruction) => instruction,
+        // An operand that doesn't fit in this platform, the loader would refuse it
+        Err(_) => Instruction {
+            instruction_type: InstructionType::Noop,
+            location: 0,
+        },
+    }
+}
+
+// Well formed values and forged heap references, like a corrupted file could have
+fn random_constant(random: &mut Random, string: usize) -> CompoundValue {
+    let value = match random.below(12) {
+        0 => Value::Nil,
+        1 => Value::Integer(0),
+        2 => Value::Integer(i64::MIN),
+        3 => Value::Integer(random.next() as i64),
+        4 => Value::Float(random.next() as f32),
+        5 => Value::Bool(random.below(2) == 0),
+        6 => Value::String(string),
+        7 => Value::String(random.operand() as usize),
+        8 => Value::Function {
+            ip: random.operand() as usize,
+            arity: random.operand() as usize,
+            uplifts: None,
+            defaults: None,
+        },
+        9 => Value::Array {
+            capacity: random.operand() as usize,
+            address: random.operand() as usize,
+        },
+        10 => Value::Object {
+            address: random.operand() as usize,
+            tags: random.operand() as usize,
+        },
+        _ => Value::Pointer(random.operand() as usize),
+    };
+    CompoundValue::SimpleValue(value)
+}
+
+fn random_vm(random: &mut Random) -> VM {
+    let mut allocator = Allocator::new(MEMORY_SIZE);
+    let memory = Memory::new(MEMORY_SIZE);
+    let file = allocator.malloc(4, std::iter::empty()).unwrap();
+    memory.copy_u8_vector(b"fuzz", file).unwrap();
+    let string = allocator.malloc(5, std::iter::empty()).unwrap();
+    memory.copy_u8_vector(b"hello", string).unwrap();
+    let constants = (0..8).map(|_| random_constant(random, string)).collect();
+    let rom = (0..ROM_LENGTH)
+        .map(|_| random_instruction(random))
+        .collect();
+    let locations = vec![Location {
+        address: file,
+        line: 1,
+    }];
+    let mut vm = VM::new(allocator, constants, locations, memory, rom);
+    vm.allocation_limit = MEMORY_SIZE;
+    vm.start();
+    vm
+}
+
+#[test]
+fn test_adversarial_roms_dont_panic() {
+    let mut random = Random(0x5eed_cafe_f00d_d00d);
+    for rom in 0..ROMS {
+        let mut vm = random_vm(&mut random);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            for _ in 0..STEPS {
+                if vm.is_done() || vm.execute().is_err() {
+                    break;
+                }
+            }
+        }));
+        if result.is_err() {
+            let instructions: Vec<String> = vm.rom.iter().map(|i| i.to_string()).collect();
+            panic!(
+                "Rom {} panicked: {:?} with constants {:?}",
+                rom, instructions, vm.constants
+            );
+        }
+    }
+}
@@ -0,0 +1,51 @@
+extern crate emulator_space_invaders;
+extern crate failure;
+
+use emulator_space_invaders::{Buttons, ConsoleOptions, KeypadController, Machine, ROM_MEMORY_LIMIT};
+use failure::Error;
+use std::env::var;
+use std::fs::File;
+use std::io::Read;
+
+const FRAMES: usize = 300;
+const ROM_VARIABLE: &str = "SPACE_INVADERS_ROM";
+
+// Buttons held from the first frame until, but not including, the second one
+const SCRIPT: &[(usize, usize, Buttons)] = &[
+    (20, 25, Buttons::COIN),
+    (60, 65, Buttons::START),
+    (150, 200, Buttons::RIGHT),
+    (210, 215, Buttons::FIRE),
+    (250, 255, Buttons::FIRE),
+];
+
+fn buttons_at(frame: usize) -> Buttons {
+    SCRIPT
+        .iter()
+        .filter(|(from, to, _)| *from <= frame && frame < *to)
+        .fold(Buttons::NONE, |pressed, (_, _, buttons)| pressed | *buttons)
+}
+
+fn read_rom(file_name: &str) -> std::io::Result<[u8; ROM_MEMORY_LIMIT]> {
+    let mut rom = [0; ROM_MEMORY_LIMIT];
+    File::open(file_name)?.read_exact(&mut rom)?;
+    Ok(rom)
+}
+
+fn main() -> Result<(), Error> {
+    let rom_location = var(ROM_VARIABLE)
+        .map_err(|_| failure::err_msg(format!("{} should point to the game rom", ROM_VARIABLE)))?;
+    let options = ConsoleOptions::new(read_rom(&rom_location)?, "")
+        .with_audio(false);
+    let mut keypad_controller = KeypadController::new();
+    let mut machine = Machine::new(&keypad_controller, &options)?;
+    let mut pressed = Buttons::NONE;
+    for frame in 0..FRAMES {
+        keypad_controller.release(pressed);
+        pressed = buttons_at(frame);
+        keypad_controller.press(pressed);
+        machine.run_frame()?;
+    }
+    println!("Score: {}", machine.player_one_score());
+    Ok(())
+}
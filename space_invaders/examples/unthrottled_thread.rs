@@ -0,0 +1,54 @@
+extern crate emulator_space_invaders;
+extern crate failure;
+
+use emulator_space_invaders::{ConsoleOptions, KeypadController, Machine, ROM_MEMORY_LIMIT};
+use failure::Error;
+use std::env::var;
+use std::fs::File;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const SECONDS: u64 = 10;
+const ROM_VARIABLE: &str = "SPACE_INVADERS_ROM";
+// About a frame of the original hardware, so the counter stays fresh
+const CYCLES_PER_RUN: u64 = 33_333;
+
+fn read_rom(file_name: &str) -> std::io::Result<[u8; ROM_MEMORY_LIMIT]> {
+    let mut rom = [0; ROM_MEMORY_LIMIT];
+    File::open(file_name)?.read_exact(&mut rom)?;
+    Ok(rom)
+}
+
+// Runs the game as fast as the host allows on its own thread, while this one looks at it once a
+// second
+fn main() -> Result<(), Error> {
+    let rom_location = var(ROM_VARIABLE)
+        .map_err(|_| failure::err_msg(format!("{} should point to the game rom", ROM_VARIABLE)))?;
+    let options = ConsoleOptions::new(read_rom(&rom_location)?, "").with_audio(false);
+    let keypad_controller = KeypadController::new();
+    let mut machine = Machine::new(&keypad_controller, &options)?;
+    let frames = Arc::new(AtomicU64::new(0));
+    let running = Arc::new(AtomicBool::new(true));
+    let runner = {
+        let frames = frames.clone();
+        let running = running.clone();
+        thread::spawn(move || -> Result<(), Error> {
+            while running.load(Ordering::Relaxed) {
+                machine.run_cycles(CYCLES_PER_RUN)?;
+                frames.store(machine.frames_elapsed(), Ordering::Relaxed);
+            }
+            Ok(())
+        })
+    };
+    for second in 1..=SECONDS {
+        thread::sleep(Duration::from_secs(1));
+        println!("{}s: {} frames", second, frames.load(Ordering::Relaxed));
+    }
+    running.store(false, Ordering::Relaxed);
+    runner
+        .join()
+        .map_err(|_| failure::err_msg("The machine thread panicked"))?
+}
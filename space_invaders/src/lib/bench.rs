@@ -0,0 +1,147 @@
+extern crate intel8080cpu;
+
+use self::intel8080cpu::{HERTZ, ROM_MEMORY_LIMIT};
+use super::console::ConsoleOptions;
+use super::failure::Error;
+use super::io_devices::KeypadController;
+use super::machine::{Machine, CYCLES_PER_FRAME};
+use super::ConsoleError;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Reading the clock after every instruction would cost more than some of them
+const FRAMES_PER_CLOCK_CHECK: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BenchReport {
+    pub instructions: u64,
+    pub cycles: u64,
+    pub frames: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchReport {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn cycles_per_second(&self) -> f64 {
+        self.cycles as f64 / self.elapsed.as_secs_f64()
+    }
+
+    // Seconds of the real machine emulated in every second of the host
+    pub fn speed_ratio(&self) -> f64 {
+        self.cycles_per_second() / HERTZ as f64
+    }
+
+    // The speed ratio as a percentage, 100 is as fast as the arcade board
+    pub fn score(&self) -> u64 {
+        (self.speed_ratio() * 100.0).round() as u64
+    }
+}
+
+// One "name: value" per line, so scripts comparing commits can pick what they need
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "seconds: {:.3}", self.elapsed.as_secs_f64())?;
+        writeln!(f, "instructions: {}", self.instructions)?;
+        writeln!(f, "cycles: {}", self.cycles)?;
+        writeln!(f, "frames: {}", self.frames)?;
+        writeln!(
+            f,
+            "instructions_per_second: {:.0}",
+            self.instructions_per_second()
+        )?;
+        writeln!(f, "cycles_per_second: {:.0}", self.cycles_per_second())?;
+        writeln!(f, "speed_ratio: {:.2}", self.speed_ratio())?;
+        write!(f, "score: {}", self.score())
+    }
+}
+
+// Runs the rom as fast as possible for about the duration, interruptions included but with no
+// window and no audio
+pub fn run_benchmark(
+    rom: [u8; ROM_MEMORY_LIMIT],
+    duration: Duration,
+) -> Result<BenchReport, Error> {
+    let keypad_controller = KeypadController::new();
+    let options = ConsoleOptions::new(rom, "").with_audio(false);
+    let mut machine = Machine::new(&keypad_controller, &options)?;
+    let cycles_per_check = (CYCLES_PER_FRAME as u64) * FRAMES_PER_CLOCK_CHECK;
+    let mut report = BenchReport {
+        instructions: 0,
+        cycles: 0,
+        frames: 0,
+        elapsed: Duration::from_secs(0),
+    };
+    let start = Instant::now();
+    while report.elapsed < duration {
+        let outcome = machine.run_cycles(cycles_per_check)?;
+        report.instructions += outcome.instructions;
+        report.cycles += outcome.cycles;
+        report.frames += outcome.frames;
+        if outcome.halted {
+            return Err(Error::from(ConsoleError::CpuHalted {
+                cycles: report.cycles,
+            }));
+        }
+        report.elapsed = start.elapsed();
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::intel8080cpu::ROM_MEMORY_LIMIT;
+    use super::run_benchmark;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    // Increments a counter in RAM forever, with both interruptions enabled
+    fn create_rom() -> [u8; ROM_MEMORY_LIMIT] {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[0x00..0x03].copy_from_slice(&[0xc3, 0x40, 0x00]);
+        rom[0x08..0x0a].copy_from_slice(&[0xfb, 0xc9]);
+        rom[0x10..0x12].copy_from_slice(&[0xfb, 0xc9]);
+        rom[0x40..0x4b].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP, 2400H
+            0xfb, // EI
+            0x21, 0x00, 0x20, // LXI H, 2000H
+            0x34, // INR M
+            0xc3, 0x47, 0x00, // JMP 0047H
+        ]);
+        rom
+    }
+
+    #[test]
+    fn it_should_report_parseable_numbers() {
+        let report = run_benchmark(create_rom(), Duration::from_millis(200)).unwrap();
+        let values: HashMap<String, f64> = report
+            .to_string()
+            .lines()
+            .map(|line| {
+                let mut parts = line.split(": ");
+                let name = parts.next().unwrap().to_owned();
+                (name, parts.next().unwrap().parse().unwrap())
+            })
+            .collect();
+        assert!(values["seconds"] >= 0.2);
+        assert!(values["instructions"] > 0.0);
+        // INR M takes 10 cycles and JMP 10, nothing in the loop is faster than 4
+        assert!(values["cycles"] >= values["instructions"] * 4.0);
+        assert!(values["frames"] > 0.0);
+        assert!(values["instructions_per_second"] > 0.0);
+        assert_eq!(values["score"], report.score() as f64);
+    }
+
+    #[test]
+    fn it_should_refuse_to_measure_a_halted_cpu() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..2].copy_from_slice(&[
+            0xf3, // DI
+            0x76, // HLT
+        ]);
+        let error = run_benchmark(rom, Duration::from_secs(1)).unwrap_err();
+        assert!(error.to_string().contains("halted"));
+    }
+}
@@ -10,29 +10,52 @@ use self::piston::input::MouseButton;
 use self::piston_window::*;
 use super::failure::Error;
 use super::io_devices::*;
+use super::machine::{Machine, RamInit};
 use super::screen::{GameScreen, Screen};
 use super::timer::Timer;
 use super::view::{View, WINDOW_HEIGHT, WINDOW_WIDTH};
 use super::ConsoleError;
 use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-const FPS: f64 = 60.0;
-const SCREEN_INTERRUPTIONS_INTERVAL: f64 = (1.0 / FPS * 1000.0) / 2.0;
+pub use self::intel8080cpu::ROM_MEMORY_LIMIT;
+
+const FPS: u64 = 60;
+// The window asks for updates this often, then the timer alone paces the frames. Unthrottled,
+// frames run as often as it asks
+const UPDATES_PER_SECOND: u64 = 1000;
 pub(crate) const FRAME_BUFFER_ADDRESS: usize = 0x2400;
 pub(crate) const FRAME_BUFFER_SIZE: usize = 0x1C00;
 
 pub struct ConsoleOptions<'a> {
-    has_audio: bool,
-    folder: &'a str,
-    memory: [u8; ROM_MEMORY_LIMIT],
+    pub(crate) background_run: bool,
+    pub(crate) dip_switches: DipSwitches,
+    pub(crate) has_audio: bool,
+    pub(crate) folder: &'a str,
+    pub(crate) high_score_file: Option<&'a str>,
+    pub(crate) key_bindings: KeyBindings,
+    pub(crate) memory: [u8; ROM_MEMORY_LIMIT],
+    pub(crate) ram_init: RamInit,
+    pub(crate) require_known_rom: bool,
+    pub(crate) synthetic_audio: bool,
+    pub(crate) throttled: bool,
 }
 
 impl<'a> ConsoleOptions<'a> {
     pub fn new(memory: [u8; ROM_MEMORY_LIMIT], folder: &'a str) -> ConsoleOptions<'a> {
         ConsoleOptions {
+            background_run: false,
+            dip_switches: DipSwitches::default(),
             folder,
+            high_score_file: None,
+            key_bindings: KeyBindings::default(),
             memory,
             has_audio: true,
+            ram_init: RamInit::Zeroed,
+            require_known_rom: false,
+            synthetic_audio: false,
+            throttled: true,
         }
     }
 
@@ -40,15 +63,75 @@ impl<'a> ConsoleOptions<'a> {
         self.has_audio = has_audio;
         self
     }
+
+    // Synthesizes the sounds instead of loading them from the wav files in the folder
+    pub fn with_synthetic_audio(mut self, synthetic_audio: bool) -> ConsoleOptions<'a> {
+        self.synthetic_audio = synthetic_audio;
+        self
+    }
+
+    pub fn with_initial_ram(mut self, ram_init: RamInit) -> ConsoleOptions<'a> {
+        self.ram_init = ram_init;
+        self
+    }
+
+    // Keeps the high score between runs. Missing or corrupted files start from zero
+    pub fn with_high_score_file(mut self, path: &'a str) -> ConsoleOptions<'a> {
+        self.high_score_file = Some(path);
+        self
+    }
+
+    pub fn with_key_bindings(mut self, key_bindings: KeyBindings) -> ConsoleOptions<'a> {
+        self.key_bindings = key_bindings;
+        self
+    }
+
+    // Unknown ROMs only get a warning, since homebrew should run too. Cabinet accurate setups
+    // can refuse them instead
+    pub fn with_required_known_rom(mut self, require_known_rom: bool) -> ConsoleOptions<'a> {
+        self.require_known_rom = require_known_rom;
+        self
+    }
+
+    // Without throttling the game runs as fast as it can, which is handy for benchmarking
+    pub fn with_throttling(mut self, throttled: bool) -> ConsoleOptions<'a> {
+        self.throttled = throttled;
+        self
+    }
+
+    // By default the game pauses while the window doesn't have the focus. Recordings and netplay
+    // need it to keep going.
+    pub fn with_background_run(mut self, background_run: bool) -> ConsoleOptions<'a> {
+        self.background_run = background_run;
+        self
+    }
+
+    pub fn with_lives(mut self, lives: Lives) -> ConsoleOptions<'a> {
+        self.dip_switches = self.dip_switches.with_lives(lives);
+        self
+    }
+
+    // Otherwise the extra life comes at 1500 points
+    pub fn with_bonus_at_1000(mut self, bonus_at_1000: bool) -> ConsoleOptions<'a> {
+        self.dip_switches = self.dip_switches.with_bonus_at_1000(bonus_at_1000);
+        self
+    }
+
+    pub fn with_coin_info(mut self, coin_info: bool) -> ConsoleOptions<'a> {
+        self.dip_switches = self.dip_switches.with_coin_info(coin_info);
+        self
+    }
 }
 
 pub struct Console<'a> {
-    cpu: Intel8080Cpu<'a>,
-    cycles_left: i64,
+    background_run: bool,
+    focused: bool,
+    high_score_file: Option<PathBuf>,
     instructions_history: VecDeque<Intel8080Instruction>,
     keypad_controller: KeypadController,
-    prev_interruption: u8,
+    machine: Machine<'a>,
     screen: Box<dyn Screen>,
+    throttled: bool,
     timer: Timer,
     view: View,
     window: PistonWindow,
@@ -60,50 +143,32 @@ impl<'a> Console<'a> {
         view: View,
         window: PistonWindow,
     ) -> Result<Console, Error> {
-        let timer = Timer::new(SCREEN_INTERRUPTIONS_INTERVAL);
-        let keypad_controller = KeypadController::new();
-        let cpu = Console::create_cpu(&keypad_controller, options)?;
+        let timer = Timer::new(Duration::from_nanos(1_000_000_000 / FPS));
+        let keypad_controller = KeypadController::with_bindings(options.key_bindings.clone());
+        let mut machine = Machine::new(&keypad_controller, &options)?;
+        if let Some(path) = options.high_score_file {
+            machine.load_high_score(Path::new(path));
+        }
+        if !machine.rom_verification().is_known() {
+            eprintln!("Warning: the rom is {}", machine.rom_verification());
+        }
         let screen = Box::new(GameScreen::new());
 
         Ok(Console {
-            cpu,
-            cycles_left: 0,
+            background_run: options.background_run,
+            focused: true,
+            high_score_file: options.high_score_file.map(PathBuf::from),
             keypad_controller,
             instructions_history: VecDeque::with_capacity(10),
-            prev_interruption: 2,
+            machine,
             screen,
+            throttled: options.throttled,
             timer,
             view,
             window,
         })
     }
 
-    fn create_cpu<'b>(
-        keypad_controller: &KeypadController,
-        options: ConsoleOptions,
-    ) -> Result<Intel8080Cpu<'b>, Error> {
-        let mut cpu = Intel8080Cpu::new(options.memory);
-        let shift_writer = ExternalShiftWriter::new();
-        let offset_writer = ExternalShiftOffsetWriter::new();
-        let shift_reader = ExternalShiftReader::new(&shift_writer, &offset_writer);
-
-        cpu.add_input_device(0, Box::new(DummyInputDevice { value: 1 }));
-        cpu.add_input_device(1, Box::new(KeypadInput::new(keypad_controller)));
-        cpu.add_input_device(2, Box::new(DummyInputDevice { value: 1 }));
-        cpu.add_input_device(3, Box::new(shift_reader));
-        cpu.add_output_device(2, Box::new(offset_writer));
-        cpu.add_output_device(4, Box::new(shift_writer));
-        cpu.add_output_device(6, Box::new(DummyOutputDevice {}));
-        if options.has_audio {
-            cpu.add_output_device(3, Box::new(SoundPort1::new(options.folder)?));
-            cpu.add_output_device(5, Box::new(SoundPort2::new(options.folder)?));
-        } else {
-            cpu.add_output_device(3, Box::new(DummyOutputDevice {}));
-            cpu.add_output_device(5, Box::new(DummyOutputDevice {}));
-        }
-        Ok(cpu)
-    }
-
     pub fn create_window(debug: bool) -> Result<PistonWindow, Error> {
         let margin = if debug { 600 } else { 0 };
         WindowSettings::new(
@@ -119,34 +184,36 @@ impl<'a> Console<'a> {
 
     pub fn start(&mut self) -> Result<(), Error> {
         self.timer.reset();
-        Events::new(EventSettings::new().ups(1000).max_fps(60));
+        self.window.set_ups(UPDATES_PER_SECOND);
+        self.window.set_max_fps(FPS);
         let mut cursor = [0.0, 0.0];
         while let Some(e) = self.window.next() {
-            if self.cpu.is_done() {
+            if self.machine.cpu.is_done() {
                 break;
             }
 
+            if let Some(focused) = e.focus_args() {
+                self.set_focused(focused);
+            }
             e.mouse_cursor(|pos| {
                 cursor = pos;
             });
             if let Some(Button::Mouse(MouseButton::Left)) = e.release_args() {
                 if self.view.is_in_pause_button(cursor) {
-                    self.cpu.toggle_hard_stop();
-                    self.timer.reset_preserving_intervals()
+                    self.machine.cpu.toggle_hard_stop();
+                    self.timer.reset();
                 }
-                if self.cpu.is_hard_stopped() && self.view.is_in_next_button(cursor) {
-                    self.cpu.toggle_hard_stop();
-                    let cycles = self.execute_single_instruction()?;
-                    self.cpu.toggle_hard_stop();
-                    let ms_past = ((cycles as f64 / HERTZ as f64) * 1000f64) as usize;
-                    self.timer.reset_preserving_intervals_with_offset(ms_past);
+                if self.machine.cpu.is_hard_stopped() && self.view.is_in_next_button(cursor) {
+                    self.machine.cpu.toggle_hard_stop();
+                    self.execute_single_instruction()?;
+                    self.machine.cpu.toggle_hard_stop();
                 }
             }
 
 
-            if !self.cpu.is_hard_stopped() {
-                if let Some(u) = e.update_args() {
-                    self.update(u)?;
+            if !self.machine.cpu.is_hard_stopped() {
+                if e.update_args().is_some() && !self.is_paused() {
+                    self.update()?;
                 }
 
                 if let Some(Button::Keyboard(key)) = e.press_args() {
@@ -160,45 +227,77 @@ impl<'a> Console<'a> {
 
             if let Some(r) = e.render_args() {
                 self.view
-                    .render(&e, &r, &mut self.window, self.instructions_history.iter(), Some(self.cpu.get_debug_string().as_str()));
+                    .render(&e, &r, &mut self.window, self.instructions_history.iter(), Some(self.debug_string().as_str()));
             }
         }
+        if let Some(ref path) = self.high_score_file {
+            self.machine.save_high_score(path)?;
+        }
         Ok(())
     }
 
-    fn update(&mut self, args: UpdateArgs) -> Result<(), Error> {
-        self.timer.update_last_check();
-        if self.timer.should_trigger() && self.cpu.interruptions_enabled {
-            self.prev_interruption = if self.prev_interruption == 1 {
-                let frame_buffer = &self.cpu.memory
-                    [FRAME_BUFFER_ADDRESS..(FRAME_BUFFER_ADDRESS + FRAME_BUFFER_SIZE)];
-                self.screen.on_full_screen(frame_buffer);
-                2
-            } else {
-                let frame_buffer = &self.cpu.memory
-                    [FRAME_BUFFER_ADDRESS..(FRAME_BUFFER_ADDRESS + FRAME_BUFFER_SIZE)];
-                self.screen.on_mid_screen(frame_buffer);
-                1
-            };
-            self.view.update_image(self.screen.get_pixels());
-            self.cpu.execute_instruction(&Intel8080Instruction::Rst {
-                byte: self.prev_interruption,
-            })?;
+    fn debug_string(&self) -> String {
+        let (port1, port2) = self.machine.input_ports();
+        format!(
+            "{}\nROM: {}\nHigh score: {:04}\nPort 1: {:08b} Port 2: {:08b}",
+            self.machine.cpu.get_debug_string(),
+            self.machine.rom_verification(),
+            self.machine.high_score(),
+            port1,
+            port2
+        )
+    }
+
+    fn is_paused(&self) -> bool {
+        !self.focused && !self.background_run
+    }
+
+    // Frames start again a whole frame after the focus comes back, not to catch up with the pause
+    fn set_focused(&mut self, focused: bool) {
+        let was_paused = self.is_paused();
+        self.focused = focused;
+        if self.is_paused() == was_paused {
+            return;
         }
-        let mut cycles_to_run = (args.dt * (HERTZ as f64)) as i64 + self.cycles_left;
-        while cycles_to_run > 0 {
-            cycles_to_run -= self.execute_single_instruction()?;
+        if self.is_paused() {
+            self.machine.pause_audio();
+        } else {
+            self.machine.resume_audio();
+            self.timer.reset();
+        }
+        self.view.set_paused(self.is_paused());
+    }
+
+    // Runs a frame worth of cycles, then sleeps what's left of its 1/60th of a second
+    fn update(&mut self) -> Result<(), Error> {
+        self.machine.begin_frame();
+        while !self.machine.is_frame_done() {
+            self.execute_single_instruction()?;
+        }
+        if self.throttled {
+            self.timer.wait_for_trigger();
         }
-        self.cycles_left = cycles_to_run;
         Ok(())
     }
 
-    fn execute_single_instruction(&mut self) -> Result<i64, Error> {
-        let instruction = Intel8080Instruction::from(self.cpu.get_next_instruction_bytes());
+    fn update_screen(&mut self, interruption: Option<u8>) {
+        match interruption {
+            Some(2) => self.screen.on_full_screen(self.machine.frame_buffer()),
+            Some(_) => self.screen.on_mid_screen(self.machine.frame_buffer()),
+            None => return,
+        }
+        self.view.update_image(self.screen.get_pixels());
+    }
+
+    fn execute_single_instruction(&mut self) -> Result<(), Error> {
+        let bytes = self.machine.cpu.get_next_instruction_bytes();
+        let instruction = Intel8080Instruction::from(&bytes[..]);
         if self.instructions_history.len() >= 10 {
             self.instructions_history.pop_front();
         }
         self.instructions_history.push_back(instruction);
-        Ok(i64::from(self.cpu.execute()?))
+        let (_, interruption) = self.machine.step()?;
+        self.update_screen(interruption);
+        Ok(())
     }
 }
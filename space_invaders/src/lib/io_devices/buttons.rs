@@ -2,83 +2,128 @@ extern crate piston;
 
 use self::piston::input::Key;
 use super::intel8080cpu::InputDevice;
-use std::cell::RefCell;
-use std::rc::Rc;
-
-enum GameButton {
-    Down,
-    Coin,
-    Fire,
-    Left,
-    Right,
-    Start,
-    Up,
+use super::key_bindings::KeyBindings;
+use std::ops::BitOr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+// Bitmask of the buttons as seen by the game on input port 1
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Buttons(u8);
+
+impl Buttons {
+    pub const NONE: Buttons = Buttons(0x00);
+    pub const COIN: Buttons = Buttons(0x01);
+    pub const START_TWO: Buttons = Buttons(0x02);
+    pub const START: Buttons = Buttons(0x04);
+    pub const UP: Buttons = Buttons(0x08);
+    pub const FIRE: Buttons = Buttons(0x10);
+    pub const LEFT: Buttons = Buttons(0x20);
+    pub const RIGHT: Buttons = Buttons(0x40);
+    pub const DOWN: Buttons = Buttons(0x80);
+
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, other: Buttons) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl BitOr for Buttons {
+    type Output = Buttons;
+
+    fn bitor(self, other: Buttons) -> Buttons {
+        Buttons(self.0 | other.0)
+    }
 }
 
+// Player two's buttons use the same bits as player one's, but on input port 2. The machine reads
+// them through atomics, so it can run on another thread than the one pressing them
 pub struct KeypadController {
-    buttons_pressed: Rc<RefCell<u8>>,
+    bindings: KeyBindings,
+    buttons_pressed: Arc<AtomicU8>,
+    keys_pressed: Vec<Key>,
+    player_two_pressed: Arc<AtomicU8>,
 }
 
 impl KeypadController {
     pub fn new() -> KeypadController {
+        KeypadController::with_bindings(KeyBindings::default())
+    }
+
+    pub fn with_bindings(bindings: KeyBindings) -> KeypadController {
         KeypadController {
-            buttons_pressed: Rc::new(RefCell::new(0x08)),
+            bindings,
+            buttons_pressed: Arc::new(AtomicU8::new(0x08)),
+            keys_pressed: Vec::new(),
+            player_two_pressed: Arc::new(AtomicU8::new(0x00)),
         }
     }
 
-    pub fn buttons_pressed(&self) -> Rc<RefCell<u8>> {
+    pub fn buttons_pressed(&self) -> Arc<AtomicU8> {
         self.buttons_pressed.clone()
     }
 
+    pub fn player_two_pressed(&self) -> Arc<AtomicU8> {
+        self.player_two_pressed.clone()
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        Buttons(self.buttons_pressed.load(Ordering::Relaxed))
+    }
+
+    pub fn player_two_buttons(&self) -> Buttons {
+        Buttons(self.player_two_pressed.load(Ordering::Relaxed))
+    }
+
+    pub fn press(&mut self, buttons: Buttons) {
+        self.buttons_pressed.fetch_or(buttons.0, Ordering::Relaxed);
+    }
+
+    pub fn release(&mut self, buttons: Buttons) {
+        self.buttons_pressed
+            .fetch_and(!buttons.0, Ordering::Relaxed);
+    }
+
+    pub fn press_player_two(&mut self, buttons: Buttons) {
+        self.player_two_pressed
+            .fetch_or(buttons.0, Ordering::Relaxed);
+    }
+
+    pub fn release_player_two(&mut self, buttons: Buttons) {
+        self.player_two_pressed
+            .fetch_and(!buttons.0, Ordering::Relaxed);
+    }
+
     pub fn key_pressed(&mut self, key: Key) {
-        let button = self.game_button_from_key(key);
-        let mut result = *self.buttons_pressed.borrow();
-        match button {
-            Some(GameButton::Coin) => result |= 0x01,
-            Some(GameButton::Start) => result |= 0x04,
-            Some(GameButton::Up) => result |= 0x08,
-            Some(GameButton::Fire) => result |= 0x10,
-            Some(GameButton::Left) => result |= 0x20,
-            Some(GameButton::Right) => result |= 0x40,
-            Some(GameButton::Down) => result |= 0x80,
-            _ => {}
-        };
-        *(self.buttons_pressed.borrow_mut()) = result;
+        let before = self.bindings.buttons(&self.keys_pressed);
+        if !self.keys_pressed.contains(&key) {
+            self.keys_pressed.push(key);
+        }
+        self.update_keys(before);
     }
 
     pub fn key_released(&mut self, key: Key) {
-        let button = self.game_button_from_key(key);
-        let mut result = *(self.buttons_pressed.borrow());
-        match button {
-            Some(GameButton::Coin) => result &= !0x01,
-            Some(GameButton::Start) => result &= !0x04,
-            Some(GameButton::Up) => result &= !0x08,
-            Some(GameButton::Fire) => result &= !0x10,
-            Some(GameButton::Left) => result &= !0x20,
-            Some(GameButton::Right) => result &= !0x40,
-            Some(GameButton::Down) => result &= !0x80,
-            _ => {}
-        };
-        *(self.buttons_pressed.borrow_mut()) = result;
-    }
-
-    #[inline]
-    fn game_button_from_key(&self, key: Key) -> Option<GameButton> {
-        match key {
-            Key::C => Some(GameButton::Coin),
-            Key::Down => Some(GameButton::Down),
-            Key::F => Some(GameButton::Fire),
-            Key::Left => Some(GameButton::Left),
-            Key::Right => Some(GameButton::Right),
-            Key::Space => Some(GameButton::Start),
-            Key::Up => Some(GameButton::Up),
-            _ => None,
-        }
+        let before = self.bindings.buttons(&self.keys_pressed);
+        self.keys_pressed.retain(|pressed| *pressed != key);
+        self.update_keys(before);
+    }
+
+    // A button is released when none of the keys bound to it are still pressed
+    fn update_keys(&mut self, before: (Buttons, Buttons)) {
+        let after = self.bindings.buttons(&self.keys_pressed);
+        self.release(before.0);
+        self.release_player_two(before.1);
+        self.press(after.0);
+        self.press_player_two(after.1);
     }
 }
 
+#[derive(Clone)]
 pub struct KeypadInput {
-    buttons_pressed: Rc<RefCell<u8>>,
+    buttons_pressed: Arc<AtomicU8>,
 }
 
 impl KeypadInput {
@@ -87,10 +132,148 @@ impl KeypadInput {
             buttons_pressed: controller.buttons_pressed(),
         }
     }
+
+    pub(crate) fn value(&self) -> u8 {
+        self.buttons_pressed.load(Ordering::Relaxed)
+    }
 }
 
 impl InputDevice for KeypadInput {
     fn read(&mut self) -> u8 {
-        *(self.buttons_pressed).borrow()
+        self.value()
+    }
+}
+
+// The lives a game starts with, the two switches can only set these
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Lives {
+    Three,
+    Four,
+    Five,
+    Six,
+}
+
+// The switches under the cabinet, read by the game on input port 2
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct DipSwitches {
+    lives: Lives,
+    bonus_at_1000: bool,
+    coin_info: bool,
+}
+
+impl DipSwitches {
+    const BONUS_AT_1000: u8 = 0x08;
+    const COIN_INFO_OFF: u8 = 0x80;
+
+    pub fn with_lives(mut self, lives: Lives) -> DipSwitches {
+        self.lives = lives;
+        self
+    }
+
+    // Otherwise the extra life comes at 1500 points
+    pub fn with_bonus_at_1000(mut self, bonus_at_1000: bool) -> DipSwitches {
+        self.bonus_at_1000 = bonus_at_1000;
+        self
+    }
+
+    // Whether the demo shows how many coins a game costs
+    pub fn with_coin_info(mut self, coin_info: bool) -> DipSwitches {
+        self.coin_info = coin_info;
+        self
+    }
+
+    // Lives on bits 0 and 1, bonus on bit 3 and coin info on bit 7, which is set to hide it
+    pub fn bits(self) -> u8 {
+        let mut bits = self.lives as u8;
+        if self.bonus_at_1000 {
+            bits |= DipSwitches::BONUS_AT_1000;
+        }
+        if !self.coin_info {
+            bits |= DipSwitches::COIN_INFO_OFF;
+        }
+        bits
+    }
+}
+
+// Four lives, the extra one at 1500 points and the coin info shown
+impl Default for DipSwitches {
+    fn default() -> DipSwitches {
+        DipSwitches {
+            lives: Lives::Four,
+            bonus_at_1000: false,
+            coin_info: true,
+        }
+    }
+}
+
+// The DIP switches along with player two's fire, left and right
+#[derive(Clone)]
+pub struct Port2Device {
+    player_two_pressed: Arc<AtomicU8>,
+    dip_switches: DipSwitches,
+}
+
+impl Port2Device {
+    const PLAYER_TWO_BUTTONS: u8 = 0x70;
+
+    pub fn new(controller: &KeypadController, dip_switches: DipSwitches) -> Port2Device {
+        Port2Device {
+            player_two_pressed: controller.player_two_pressed(),
+            dip_switches,
+        }
+    }
+
+    pub(crate) fn value(&self) -> u8 {
+        (self.player_two_pressed.load(Ordering::Relaxed) & Port2Device::PLAYER_TWO_BUTTONS)
+            | self.dip_switches.bits()
+    }
+}
+
+impl InputDevice for Port2Device {
+    fn read(&mut self) -> u8 {
+        self.value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::intel8080cpu::InputDevice;
+    use super::{Buttons, DipSwitches, KeypadController, Lives, Port2Device};
+
+    fn read(switches: DipSwitches, buttons: Buttons) -> u8 {
+        let mut controller = KeypadController::new();
+        controller.press_player_two(buttons);
+        Port2Device::new(&controller, switches).read()
+    }
+
+    #[test]
+    fn it_should_read_the_dip_switches() {
+        let switches = DipSwitches::default();
+        assert_eq!(read(switches, Buttons::NONE), 0x01);
+        assert_eq!(read(switches.with_lives(Lives::Three), Buttons::NONE), 0x00);
+        assert_eq!(read(switches.with_lives(Lives::Five), Buttons::NONE), 0x02);
+        assert_eq!(read(switches.with_lives(Lives::Six), Buttons::NONE), 0x03);
+        assert_eq!(read(switches.with_bonus_at_1000(true), Buttons::NONE), 0x09);
+        assert_eq!(read(switches.with_coin_info(false), Buttons::NONE), 0x81);
+        let all = switches
+            .with_lives(Lives::Six)
+            .with_bonus_at_1000(true)
+            .with_coin_info(false);
+        assert_eq!(read(all, Buttons::NONE), 0x8b);
+    }
+
+    #[test]
+    fn it_should_combine_player_two_buttons_with_the_dip_switches() {
+        let switches = DipSwitches::default().with_lives(Lives::Three);
+        assert_eq!(read(switches, Buttons::FIRE), 0x10);
+        assert_eq!(read(switches, Buttons::LEFT), 0x20);
+        assert_eq!(read(switches, Buttons::RIGHT), 0x40);
+        assert_eq!(read(switches, Buttons::FIRE | Buttons::RIGHT), 0x50);
+        // Only fire, left and right are player two's, the rest of the bits are switches
+        let all = switches.with_lives(Lives::Six).with_bonus_at_1000(true);
+        assert_eq!(
+            read(all, Buttons::FIRE | Buttons::LEFT | Buttons::DOWN),
+            0x3b
+        );
     }
 }
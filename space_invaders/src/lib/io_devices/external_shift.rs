@@ -1,68 +1,71 @@
 use super::intel8080cpu::{InputDevice, OutputDevice};
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
 
+// The three devices share the registers of the shifter. They only ever run on the thread of their
+// machine, so relaxed ordering is enough
 pub struct ExternalShiftOffsetWriter {
-    shift_offset: Rc<RefCell<u8>>,
+    shift_offset: Arc<AtomicU8>,
 }
 
 impl ExternalShiftOffsetWriter {
     pub fn new() -> ExternalShiftOffsetWriter {
         ExternalShiftOffsetWriter {
-            shift_offset: Rc::new(RefCell::new(0)),
+            shift_offset: Arc::new(AtomicU8::new(0)),
         }
     }
 
-    pub fn get_shift_offset(&self) -> Rc<RefCell<u8>> {
+    pub fn get_shift_offset(&self) -> Arc<AtomicU8> {
         self.shift_offset.clone()
     }
 }
 
 impl OutputDevice for ExternalShiftOffsetWriter {
     fn write(&mut self, value: u8) {
-        *(self.shift_offset.borrow_mut()) = value & 0x07;
+        self.shift_offset.store(value & 0x07, Ordering::Relaxed);
     }
 }
 
 pub struct ExternalShiftWriter {
-    shift0: Rc<RefCell<u8>>,
-    shift1: Rc<RefCell<u8>>,
+    shift0: Arc<AtomicU8>,
+    shift1: Arc<AtomicU8>,
 }
 
 impl OutputDevice for ExternalShiftWriter {
     fn write(&mut self, value: u8) {
-        *(self.shift1.borrow_mut()) = *(self.shift0.borrow());
-        *(self.shift0.borrow_mut()) = value;
+        let previous = self.shift0.swap(value, Ordering::Relaxed);
+        self.shift1.store(previous, Ordering::Relaxed);
     }
 }
 
 impl ExternalShiftWriter {
     pub fn new() -> ExternalShiftWriter {
         ExternalShiftWriter {
-            shift0: Rc::new(RefCell::new(0)),
-            shift1: Rc::new(RefCell::new(0)),
+            shift0: Arc::new(AtomicU8::new(0)),
+            shift1: Arc::new(AtomicU8::new(0)),
         }
     }
 
-    pub fn get_shift0(&self) -> Rc<RefCell<u8>> {
+    pub fn get_shift0(&self) -> Arc<AtomicU8> {
         self.shift0.clone()
     }
 
-    pub fn get_shift1(&self) -> Rc<RefCell<u8>> {
+    pub fn get_shift1(&self) -> Arc<AtomicU8> {
         self.shift1.clone()
     }
 }
 
 pub struct ExternalShiftReader {
-    shift_offset: Rc<RefCell<u8>>,
-    shift0: Rc<RefCell<u8>>,
-    shift1: Rc<RefCell<u8>>,
+    shift_offset: Arc<AtomicU8>,
+    shift0: Arc<AtomicU8>,
+    shift1: Arc<AtomicU8>,
 }
 
 impl InputDevice for ExternalShiftReader {
     fn read(&mut self) -> u8 {
-        let v = (u16::from(*self.shift0.borrow()) << 8) | u16::from(*self.shift1.borrow());
-        (v >> (8 - *self.shift_offset.borrow())) as u8
+        let v = (u16::from(self.shift0.load(Ordering::Relaxed)) << 8)
+            | u16::from(self.shift1.load(Ordering::Relaxed));
+        (v >> (8 - self.shift_offset.load(Ordering::Relaxed))) as u8
     }
 }
 
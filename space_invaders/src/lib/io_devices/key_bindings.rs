@@ -0,0 +1,299 @@
+extern crate piston;
+
+use self::piston::input::Key;
+use super::super::failure::Error;
+use super::buttons::Buttons;
+use std::fs::read_to_string;
+use std::path::Path;
+
+// The names a bindings file can use, like the variants of piston's Key
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("A", Key::A),
+    ("B", Key::B),
+    ("C", Key::C),
+    ("D", Key::D),
+    ("E", Key::E),
+    ("F", Key::F),
+    ("G", Key::G),
+    ("H", Key::H),
+    ("I", Key::I),
+    ("J", Key::J),
+    ("K", Key::K),
+    ("L", Key::L),
+    ("M", Key::M),
+    ("N", Key::N),
+    ("O", Key::O),
+    ("P", Key::P),
+    ("Q", Key::Q),
+    ("R", Key::R),
+    ("S", Key::S),
+    ("T", Key::T),
+    ("U", Key::U),
+    ("V", Key::V),
+    ("W", Key::W),
+    ("X", Key::X),
+    ("Y", Key::Y),
+    ("Z", Key::Z),
+    ("D0", Key::D0),
+    ("D1", Key::D1),
+    ("D2", Key::D2),
+    ("D3", Key::D3),
+    ("D4", Key::D4),
+    ("D5", Key::D5),
+    ("D6", Key::D6),
+    ("D7", Key::D7),
+    ("D8", Key::D8),
+    ("D9", Key::D9),
+    ("NumPad0", Key::NumPad0),
+    ("NumPad1", Key::NumPad1),
+    ("NumPad2", Key::NumPad2),
+    ("NumPad3", Key::NumPad3),
+    ("NumPad4", Key::NumPad4),
+    ("NumPad5", Key::NumPad5),
+    ("NumPad6", Key::NumPad6),
+    ("NumPad7", Key::NumPad7),
+    ("NumPad8", Key::NumPad8),
+    ("NumPad9", Key::NumPad9),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Space", Key::Space),
+    ("Return", Key::Return),
+    ("Tab", Key::Tab),
+    ("Backspace", Key::Backspace),
+    ("LShift", Key::LShift),
+    ("RShift", Key::RShift),
+    ("LCtrl", Key::LCtrl),
+    ("RCtrl", Key::RCtrl),
+    ("LAlt", Key::LAlt),
+    ("RAlt", Key::RAlt),
+    ("Comma", Key::Comma),
+    ("Period", Key::Period),
+    ("Slash", Key::Slash),
+    ("Semicolon", Key::Semicolon),
+    ("Minus", Key::Minus),
+    ("Equals", Key::Equals),
+];
+
+#[derive(Debug, Fail)]
+pub enum KeyBindingsError {
+    #[fail(display = "line {}: expected action=key, found \"{}\"", line, text)]
+    InvalidLine { line: usize, text: String },
+    #[fail(display = "line {}: unknown action \"{}\"", line, action)]
+    UnknownAction { line: usize, action: String },
+    #[fail(
+        display = "line {}: unknown key \"{}\", keys are named like A, D1, NumPad4, Left or Space",
+        line, key
+    )]
+    UnknownKey { line: usize, key: String },
+}
+
+fn key_from_name(name: &str) -> Option<Key> {
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|(_, key)| *key)
+}
+
+// Every action can have more than one key, pressing any of them presses its button
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyBindings {
+    pub p1_left: Vec<Key>,
+    pub p1_right: Vec<Key>,
+    pub p1_fire: Vec<Key>,
+    pub p2_left: Vec<Key>,
+    pub p2_right: Vec<Key>,
+    pub p2_fire: Vec<Key>,
+    pub coin: Vec<Key>,
+    pub start1: Vec<Key>,
+    pub start2: Vec<Key>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings {
+            p1_left: vec![Key::Left],
+            p1_right: vec![Key::Right],
+            p1_fire: vec![Key::F],
+            p2_left: vec![Key::A],
+            p2_right: vec![Key::D],
+            p2_fire: vec![Key::W],
+            coin: vec![Key::C],
+            start1: vec![Key::Space],
+            start2: vec![Key::D2],
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn load(path: &Path) -> Result<KeyBindings, Error> {
+        Ok(KeyBindings::parse(&read_to_string(path)?)?)
+    }
+
+    // Lines like p1_fire=F,Space. Actions missing from the text keep their default keys, and
+    // # starts a comment
+    pub fn parse(text: &str) -> Result<KeyBindings, KeyBindingsError> {
+        let mut bindings = KeyBindings::default();
+        let mut bound: Vec<String> = Vec::new();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (action, keys) = match line.find('=') {
+                Some(position) => (line[..position].trim(), &line[position + 1..]),
+                None => {
+                    return Err(KeyBindingsError::InvalidLine {
+                        line: line_number,
+                        text: line.to_owned(),
+                    })
+                }
+            };
+            let mut parsed = Vec::new();
+            for name in keys.split(',').map(str::trim) {
+                match key_from_name(name) {
+                    Some(key) => parsed.push(key),
+                    None => {
+                        return Err(KeyBindingsError::UnknownKey {
+                            line: line_number,
+                            key: name.to_owned(),
+                        })
+                    }
+                }
+            }
+            let action_keys = match bindings.keys_mut(action) {
+                Some(action_keys) => action_keys,
+                None => {
+                    return Err(KeyBindingsError::UnknownAction {
+                        line: line_number,
+                        action: action.to_owned(),
+                    })
+                }
+            };
+            // The first line of an action replaces its defaults, the next ones add to it
+            if !bound.iter().any(|name| name == action) {
+                action_keys.clear();
+                bound.push(action.to_owned());
+            }
+            action_keys.extend(parsed);
+        }
+        Ok(bindings)
+    }
+
+    // The buttons the keys press on input ports 1 and 2, in that order
+    pub fn buttons(&self, keys: &[Key]) -> (Buttons, Buttons) {
+        let mut port1 = Buttons::NONE;
+        let mut port2 = Buttons::NONE;
+        for key in keys {
+            let key = *key;
+            for (action_keys, buttons) in [
+                (&self.coin, Buttons::COIN),
+                (&self.start1, Buttons::START),
+                (&self.start2, Buttons::START_TWO),
+                (&self.p1_fire, Buttons::FIRE),
+                (&self.p1_left, Buttons::LEFT),
+                (&self.p1_right, Buttons::RIGHT),
+            ]
+            .iter()
+            {
+                if action_keys.contains(&key) {
+                    port1 = port1 | *buttons;
+                }
+            }
+            for (action_keys, buttons) in [
+                (&self.p2_fire, Buttons::FIRE),
+                (&self.p2_left, Buttons::LEFT),
+                (&self.p2_right, Buttons::RIGHT),
+            ]
+            .iter()
+            {
+                if action_keys.contains(&key) {
+                    port2 = port2 | *buttons;
+                }
+            }
+        }
+        (port1, port2)
+    }
+
+    fn keys_mut(&mut self, action: &str) -> Option<&mut Vec<Key>> {
+        match action {
+            "p1_left" => Some(&mut self.p1_left),
+            "p1_right" => Some(&mut self.p1_right),
+            "p1_fire" => Some(&mut self.p1_fire),
+            "p2_left" => Some(&mut self.p2_left),
+            "p2_right" => Some(&mut self.p2_right),
+            "p2_fire" => Some(&mut self.p2_fire),
+            "coin" => Some(&mut self.coin),
+            "start1" => Some(&mut self.start1),
+            "start2" => Some(&mut self.start2),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Buttons, KeypadController};
+    use super::piston::input::Key;
+    use super::{KeyBindings, KeyBindingsError};
+
+    #[test]
+    fn it_should_parse_bindings_over_the_defaults() {
+        let bindings = KeyBindings::parse(
+            "# Arrows for one, WASD for two\n\
+             p1_fire = Up, space\n\
+             p2_left=A\n\
+             p2_left=NumPad4 # Either works\n\
+             \n\
+             start2=D2",
+        )
+        .unwrap();
+        assert_eq!(bindings.p1_fire, vec![Key::Up, Key::Space]);
+        assert_eq!(bindings.p2_left, vec![Key::A, Key::NumPad4]);
+        assert_eq!(bindings.p1_left, KeyBindings::default().p1_left);
+    }
+
+    #[test]
+    fn it_should_describe_what_it_cant_parse() {
+        match KeyBindings::parse("coin=C\np1_fire=Fire") {
+            Err(KeyBindingsError::UnknownKey { line: 2, ref key }) if key == "Fire" => {}
+            other => panic!("Unexpected {:?}", other),
+        }
+        let error = KeyBindings::parse("p3_fire=F").unwrap_err();
+        assert_eq!(error.to_string(), "line 1: unknown action \"p3_fire\"");
+        let error = KeyBindings::parse("\ncoin").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "line 2: expected action=key, found \"coin\""
+        );
+    }
+
+    #[test]
+    fn it_should_map_keys_to_the_bits_of_their_port() {
+        let bindings = KeyBindings::parse("p1_fire=F,Space\np2_fire=Space\nstart1=Return").unwrap();
+        assert_eq!(
+            bindings.buttons(&[Key::Space, Key::Left, Key::D2]),
+            (
+                Buttons::FIRE | Buttons::LEFT | Buttons::START_TWO,
+                Buttons::FIRE
+            )
+        );
+        let mut controller = KeypadController::with_bindings(bindings);
+        controller.key_pressed(Key::F);
+        controller.key_pressed(Key::Space);
+        controller.key_pressed(Key::D);
+        assert_eq!(controller.buttons(), Buttons::UP | Buttons::FIRE);
+        assert_eq!(
+            controller.player_two_buttons(),
+            Buttons::FIRE | Buttons::RIGHT
+        );
+        // The fire button stays down while another of its keys is pressed
+        controller.key_released(Key::F);
+        assert!(controller.buttons().contains(Buttons::FIRE));
+        controller.key_released(Key::Space);
+        assert_eq!(controller.buttons(), Buttons::UP);
+        assert_eq!(controller.player_two_buttons(), Buttons::RIGHT);
+    }
+}
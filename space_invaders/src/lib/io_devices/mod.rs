@@ -3,6 +3,8 @@ use self::intel8080cpu::{InputDevice, OutputDevice};
 
 mod buttons;
 mod external_shift;
+mod key_bindings;
+mod sound_bank;
 mod sounds;
 
 pub struct DummyOutputDevice {}
@@ -23,4 +25,6 @@ impl InputDevice for DummyInputDevice {
 
 pub use self::buttons::*;
 pub use self::external_shift::*;
+pub use self::key_bindings::*;
+pub use self::sound_bank::*;
 pub use self::sounds::*;
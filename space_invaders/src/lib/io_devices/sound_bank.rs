@@ -0,0 +1,171 @@
+pub const SAMPLE_RATE: u32 = 22_050;
+pub const SOUNDS: usize = 10;
+const AMPLITUDE: f32 = 0.3;
+
+pub struct Sound {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub samples: Vec<f32>,
+}
+
+// Shared by the sound ports, which go wherever the machine goes
+pub trait SoundBank: Send + Sync {
+    fn sound(&self, id: usize) -> Option<&Sound>;
+}
+
+// Approximations of the original sounds, for when the wav files aren't available
+pub struct SyntheticSoundBank {
+    sounds: Vec<Sound>,
+}
+
+impl SyntheticSoundBank {
+    pub fn new() -> SyntheticSoundBank {
+        SyntheticSoundBank {
+            sounds: (0..SOUNDS)
+                .map(|id| Sound {
+                    channels: 1,
+                    sample_rate: SAMPLE_RATE,
+                    samples: synthesize(id),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl SoundBank for SyntheticSoundBank {
+    fn sound(&self, id: usize) -> Option<&Sound> {
+        self.sounds.get(id)
+    }
+}
+
+fn samples_in(seconds: f32) -> usize {
+    (seconds * SAMPLE_RATE as f32) as usize
+}
+
+// Square wave sliding linearly from one frequency to the other
+pub fn square_sweep(start: f32, end: f32, seconds: f32) -> Vec<f32> {
+    let length = samples_in(seconds);
+    let mut phase = 0.0;
+    (0..length)
+        .map(|i| {
+            let frequency = start + (end - start) * i as f32 / length as f32;
+            phase = (phase + frequency / SAMPLE_RATE as f32).fract();
+            if phase < 0.5 {
+                AMPLITUDE
+            } else {
+                -AMPLITUDE
+            }
+        })
+        .collect()
+}
+
+// xorshift32, so the noise is the same on every run
+pub fn noise(seconds: f32, seed: u32) -> Vec<f32> {
+    let mut state = seed.max(1);
+    (0..samples_in(seconds))
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state as f32 / u32::MAX as f32 * 2.0 - 1.0) * AMPLITUDE
+        })
+        .collect()
+}
+
+// Short linear attack followed by a linear decay to silence
+pub fn with_decay(mut samples: Vec<f32>) -> Vec<f32> {
+    let length = samples.len();
+    let attack = (length / 50).max(1);
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let volume = if i < attack {
+            i as f32 / attack as f32
+        } else {
+            (length - i) as f32 / (length - attack) as f32
+        };
+        *sample *= volume;
+    }
+    samples
+}
+
+pub fn synthesize(id: usize) -> Vec<f32> {
+    match id {
+        // Flying saucer, looped while it's on screen
+        0 => {
+            let mut samples = square_sweep(500.0, 700.0, 0.05);
+            samples.extend(square_sweep(700.0, 500.0, 0.05));
+            samples
+        }
+        // Shot
+        1 => with_decay(square_sweep(1200.0, 200.0, 0.3)),
+        // Player explosion
+        2 => with_decay(noise(1.0, 2)),
+        // Invader killed
+        3 => with_decay(noise(0.3, 3)),
+        // The four steps of the fleet march
+        4 => square_sweep(110.0, 110.0, 0.1),
+        5 => square_sweep(98.0, 98.0, 0.1),
+        6 => square_sweep(87.0, 87.0, 0.1),
+        7 => square_sweep(82.0, 82.0, 0.1),
+        // Flying saucer hit
+        8 => with_decay(square_sweep(1000.0, 100.0, 1.0)),
+        // Extra life
+        _ => with_decay(square_sweep(880.0, 880.0, 0.5)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{synthesize, SoundBank, SyntheticSoundBank, SAMPLE_RATE, SOUNDS};
+
+    fn zero_crossings(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|w| (w[0] < 0.0) != (w[1] < 0.0))
+            .count()
+    }
+
+    fn seconds(samples: &[f32]) -> f32 {
+        samples.len() as f32 / SAMPLE_RATE as f32
+    }
+
+    #[test]
+    fn it_should_synthesize_every_sound() {
+        let bank = SyntheticSoundBank::new();
+        for id in 0..SOUNDS {
+            let sound = bank.sound(id).unwrap();
+            assert_eq!(sound.channels, 1);
+            assert!(!sound.samples.is_empty());
+            assert!(sound.samples.iter().any(|s| s.abs() > 0.1));
+            assert!(sound.samples.iter().all(|s| s.abs() <= 1.0));
+        }
+        assert!(bank.sound(SOUNDS).is_none());
+    }
+
+    #[test]
+    fn it_should_synthesize_sounds_of_the_right_length() {
+        assert!((seconds(&synthesize(1)) - 0.3).abs() < 0.01);
+        assert!((seconds(&synthesize(2)) - 1.0).abs() < 0.01);
+        assert!((seconds(&synthesize(4)) - 0.1).abs() < 0.01);
+        assert!((seconds(&synthesize(8)) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn it_should_place_the_march_in_the_low_frequencies() {
+        for id in 4..8 {
+            // Around 100 Hz, that's about 20 crossings in a tenth of a second
+            let crossings = zero_crossings(&synthesize(id));
+            assert!((14..=24).contains(&crossings), "{} crossings", crossings);
+        }
+        assert!(zero_crossings(&synthesize(4)) > zero_crossings(&synthesize(7)));
+    }
+
+    #[test]
+    fn it_should_make_descending_and_noisy_sounds() {
+        let shot = synthesize(1);
+        let (start, end) = shot.split_at(shot.len() / 2);
+        assert!(zero_crossings(start) > zero_crossings(end) * 2);
+        let explosion = synthesize(2);
+        // Noise crosses zero far more often than any of the tones
+        assert!(zero_crossings(&explosion) as f32 / seconds(&explosion) > 5000.0);
+    }
+}
@@ -1,85 +1,146 @@
 extern crate rodio;
 
-use std::io::BufReader;
 use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, Mutex};
+use self::rodio::buffer::SamplesBuffer;
 use self::rodio::{Sink, Source, Decoder, Device};
 use super::super::failure::Error;
 use super::super::ConsoleError;
 use super::intel8080cpu::OutputDevice;
+use super::sound_bank::{Sound, SoundBank, SyntheticSoundBank};
+
+// The ids go from 0.wav to 8.wav
+const WAV_SOUNDS: usize = 9;
+
+pub struct WavSoundBank {
+    sounds: Vec<Sound>,
+}
+
+impl WavSoundBank {
+    pub fn load(folder: &str) -> Result<WavSoundBank, Error> {
+        let mut sounds = Vec::with_capacity(WAV_SOUNDS);
+        for id in 0..WAV_SOUNDS {
+            let file = File::open(format!("{}/{}.wav", folder, id))?;
+            let decoder = Decoder::new(BufReader::new(file))
+                .map_err(|e| Error::from(ConsoleError::CantCreateSound { msg: e.to_string() }))?;
+            let channels = decoder.channels();
+            let sample_rate = decoder.sample_rate();
+            sounds.push(Sound {
+                channels,
+                sample_rate,
+                samples: decoder.convert_samples::<f32>().collect(),
+            });
+        }
+        Ok(WavSoundBank { sounds })
+    }
+}
+
+impl SoundBank for WavSoundBank {
+    fn sound(&self, id: usize) -> Option<&Sound> {
+        self.sounds.get(id)
+    }
+}
+
+pub fn load_sound_bank(folder: &str, synthetic: bool) -> Arc<dyn SoundBank> {
+    if synthetic {
+        return Arc::new(SyntheticSoundBank::new());
+    }
+    match WavSoundBank::load(folder) {
+        Ok(bank) => Arc::new(bank),
+        Err(e) => {
+            eprintln!("Can't load the sounds from {} ({}), using synthetic sounds", folder, e);
+            Arc::new(SyntheticSoundBank::new())
+        }
+    }
+}
+
+fn create_source(sounds: &dyn SoundBank, id: usize) -> Option<SamplesBuffer<f32>> {
+    sounds
+        .sound(id)
+        .map(|s| SamplesBuffer::new(s.channels, s.sample_rate, s.samples.clone()))
+}
+
+// Every sink the sound ports play on, so they can be paused together
+#[derive(Clone, Default)]
+pub struct SoundSinks {
+    sinks: Arc<Mutex<Vec<Arc<Sink>>>>,
+}
+
+impl SoundSinks {
+    fn create(&self, device: &Device) -> Arc<Sink> {
+        let sink = Arc::new(Sink::new(device));
+        self.sinks.lock().unwrap().push(sink.clone());
+        sink
+    }
+
+    pub fn pause(&self) {
+        for sink in self.sinks.lock().unwrap().iter() {
+            sink.pause();
+        }
+    }
+
+    pub fn resume(&self) {
+        for sink in self.sinks.lock().unwrap().iter() {
+            sink.play();
+        }
+    }
+}
 
 pub struct SoundPort1 {
     last_value: u8,
     device: Device,
-    background: Sink,
-    instant_sound_1: String,
-    instant_sound_2: String,
-    instant_sound_3: String,
-    sound_sink: Sink,
+    background: Arc<Sink>,
+    sounds: Arc<dyn SoundBank>,
+    sound_sink: Arc<Sink>,
 }
 
 pub struct SoundPort2 {
     last_value: u8,
     device: Device,
-    instant_sound_4: String,
-    instant_sound_5: String,
-    instant_sound_6: String,
-    instant_sound_7: String,
-    instant_sound_8: String,
-    sound_sink: Sink,
-}
-
-fn create_sound(path: &str) -> std::io::Result<File> {
-    File::open(path)
+    sounds: Arc<dyn SoundBank>,
+    sound_sink: Arc<Sink>,
 }
 
 impl SoundPort1 {
-    pub fn new(folder: &str) -> Result<SoundPort1, Error> {
+    pub fn new(sounds: Arc<dyn SoundBank>, sinks: &SoundSinks) -> Result<SoundPort1, Error> {
         let device = rodio::default_output_device().unwrap();
         Ok(SoundPort1 {
             last_value: 0,
             background: {
-                let sink = Sink::new(&device);
-                let sound = create_sound(&format!("{}/0.wav", folder))?;
-                let sound = Decoder::new(BufReader::new(sound))
-                    .map_err(|e| Error::from(ConsoleError::CantCreateSound { msg: e.to_string() }))?;
-                sink.append(sound.repeat_infinite());
+                let sink = sinks.create(&device);
+                if let Some(sound) = create_source(&*sounds, 0) {
+                    sink.append(sound.repeat_infinite());
+                }
                 sink.stop();
                 sink
             },
-            instant_sound_1: format!("{}/1.wav", folder),
-            instant_sound_2: format!("{}/2.wav", folder),
-            instant_sound_3: format!("{}/3.wav", folder),
-            sound_sink: Sink::new(&device),
+            sounds,
+            sound_sink: sinks.create(&device),
             device,
         })
     }
 }
 
 impl SoundPort2 {
-    pub fn new(folder: &str) -> Result<SoundPort2, Error> {
+    pub fn new(sounds: Arc<dyn SoundBank>, sinks: &SoundSinks) -> Result<SoundPort2, Error> {
         let device = rodio::default_output_device().unwrap();
         Ok(SoundPort2 {
             last_value: 0,
-            instant_sound_4: format!("{}/4.wav", folder),
-            instant_sound_5: format!("{}/5.wav", folder),
-            instant_sound_6: format!("{}/6.wav", folder),
-            instant_sound_7: format!("{}/7.wav", folder),
-            instant_sound_8: format!("{}/8.wav", folder),
-            sound_sink: Sink::new(&device),
+            sounds,
+            sound_sink: sinks.create(&device),
             device,
         })
     }
 }
 
 macro_rules! maybe_play_instant_sound {
-    ($position:expr, $byte:ident, $this:ident, $sound:ident) => {
+    ($position:expr, $byte:ident, $this:ident, $sound:expr) => {
         if ($byte & $position) ^ ($byte & $this.last_value) > 0 && $this.sound_sink.empty() {
-            let file = create_sound(&$this.$sound).unwrap();
-            let sound = Decoder::new(BufReader::new(file))
-                .map_err(|e| Error::from(ConsoleError::CantCreateSound { msg: e.to_string() }))
-                .unwrap();
-            $this.sound_sink.append(sound);
-            $this.sound_sink.play();
+            if let Some(sound) = create_source(&*$this.sounds, $sound) {
+                $this.sound_sink.append(sound);
+                $this.sound_sink.play();
+            }
         }
     };
 }
@@ -92,18 +153,18 @@ impl OutputDevice for SoundPort1 {
                 self.background.play();
             }
         }
-        maybe_play_instant_sound!(0x02, byte, self, instant_sound_1);
-        maybe_play_instant_sound!(0x04, byte, self, instant_sound_2);
-        maybe_play_instant_sound!(0x08, byte, self, instant_sound_3);
+        maybe_play_instant_sound!(0x02, byte, self, 1);
+        maybe_play_instant_sound!(0x04, byte, self, 2);
+        maybe_play_instant_sound!(0x08, byte, self, 3);
     }
 }
 
 impl OutputDevice for SoundPort2 {
     fn write(&mut self, byte: u8) {
-        maybe_play_instant_sound!(0x01, byte, self, instant_sound_4);
-        maybe_play_instant_sound!(0x02, byte, self, instant_sound_5);
-        maybe_play_instant_sound!(0x04, byte, self, instant_sound_6);
-        maybe_play_instant_sound!(0x08, byte, self, instant_sound_7);
-        maybe_play_instant_sound!(0x10, byte, self, instant_sound_8);
+        maybe_play_instant_sound!(0x01, byte, self, 4);
+        maybe_play_instant_sound!(0x02, byte, self, 5);
+        maybe_play_instant_sound!(0x04, byte, self, 6);
+        maybe_play_instant_sound!(0x08, byte, self, 7);
+        maybe_play_instant_sound!(0x10, byte, self, 8);
     }
 }
@@ -0,0 +1,719 @@
+extern crate intel8080cpu;
+
+use self::intel8080cpu::*;
+use super::console::{ConsoleOptions, FRAME_BUFFER_ADDRESS, FRAME_BUFFER_SIZE};
+use super::failure::Error;
+use super::io_devices::*;
+use super::memory::{ConsoleMemory, RAM_ADDRESS};
+use super::rom_check::{verify_rom, RomVerification};
+use super::ConsoleError;
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+pub(crate) const CYCLES_PER_INTERRUPTION: i64 = HERTZ / 120;
+// The mid-screen and the vblank interruptions split every frame in two halves
+pub(crate) const CYCLES_PER_FRAME: i64 = CYCLES_PER_INTERRUPTION * 2;
+// Player one's score, as two BCD bytes with the least significant first
+const PLAYER_ONE_SCORE_ADDRESS: u16 = 0x20f8;
+// Same layout as the scores, the game only compares and redraws it
+const HIGH_SCORE_ADDRESS: u16 = 0x20f4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RamInit {
+    Zeroed,
+    Ones,
+    Pattern(u8),
+    Seeded(u64),
+}
+
+impl RamInit {
+    pub(crate) fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamInit::Zeroed => fill_with(ram, 0),
+            RamInit::Ones => fill_with(ram, 0xff),
+            RamInit::Pattern(byte) => fill_with(ram, byte),
+            RamInit::Seeded(seed) => fill_with_seed(ram, seed),
+        }
+    }
+}
+
+fn fill_with(ram: &mut [u8], byte: u8) {
+    for b in ram.iter_mut() {
+        *b = byte;
+    }
+}
+
+// splitmix64, so the same seed gives the same RAM on every platform
+fn fill_with_seed(ram: &mut [u8], seed: u64) {
+    let mut state = seed;
+    for chunk in ram.chunks_mut(8) {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        let len = chunk.len();
+        chunk.copy_from_slice(&z.to_le_bytes()[..len]);
+    }
+}
+
+fn bcd_to_u32(byte: u8) -> u32 {
+    u32::from(byte >> 4) * 10 + u32::from(byte & 0x0f)
+}
+
+fn is_bcd(byte: u8) -> bool {
+    byte >> 4 < 10 && byte & 0x0f < 10
+}
+
+type WriteWatcher = (Range<u16>, Box<dyn FnMut(u16, u8) + Send>);
+// Cycles since the last vblank, address and value
+pub type FrameWrite = (i64, u16, u8);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RunOutcome {
+    pub instructions: u64,
+    // Can go past the budget by the rest of the last instruction and its interruption
+    pub cycles: u64,
+    pub frames: u64,
+    // The cpu stopped, and only an interruption that never comes could wake it up
+    pub halted: bool,
+}
+
+// The next interruption the video hardware raises, and in how many cycles
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PendingInterrupt {
+    pub interruption: u8,
+    pub cycles: i64,
+}
+
+struct FrameWriteLog {
+    limit: usize,
+    current: Vec<FrameWrite>,
+    finished: Vec<FrameWrite>,
+}
+
+impl FrameWriteLog {
+    fn record(&mut self, write: FrameWrite) {
+        if self.current.len() < self.limit {
+            self.current.push(write);
+        }
+    }
+
+    fn finish_frame(&mut self) {
+        self.finished = std::mem::take(&mut self.current);
+    }
+}
+
+pub struct Machine<'a> {
+    // The machine runs on its own thread, so any listener has to go along with it
+    pub(crate) cpu: Intel8080Cpu<'a, ConsoleMemory, dyn EmulationListener + Send + 'a>,
+    cycles_until_interruption: i64,
+    frame_cycle: i64,
+    frame_cycles_left: i64,
+    frame_write_log: Option<FrameWriteLog>,
+    frames: u64,
+    port1: KeypadInput,
+    port2: Port2Device,
+    prev_interruption: u8,
+    rom_verification: RomVerification,
+    sound_sinks: SoundSinks,
+    watchers: Vec<WriteWatcher>,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new<'b>(
+        keypad_controller: &KeypadController,
+        options: &ConsoleOptions,
+    ) -> Result<Machine<'b>, Error> {
+        let rom_verification = verify_rom(&options.memory);
+        if options.require_known_rom && !rom_verification.is_known() {
+            return Err(Error::from(ConsoleError::UnknownRom {
+                verification: rom_verification,
+            }));
+        }
+        let mut cpu = Intel8080Cpu::with_memory(ConsoleMemory::new(options.memory));
+        cpu.set_termination_condition(TerminationCondition::Never);
+        options.ram_init.fill(cpu.memory.ram_mut());
+        let shift_writer = ExternalShiftWriter::new();
+        let offset_writer = ExternalShiftOffsetWriter::new();
+        let shift_reader = ExternalShiftReader::new(&shift_writer, &offset_writer);
+
+        cpu.add_input_device(0, Box::new(DummyInputDevice { value: 1 }));
+        let port1 = KeypadInput::new(keypad_controller);
+        let port2 = Port2Device::new(keypad_controller, options.dip_switches);
+        cpu.add_input_device(1, Box::new(port1.clone()));
+        cpu.add_input_device(2, Box::new(port2.clone()));
+        cpu.add_input_device(3, Box::new(shift_reader));
+        cpu.add_output_device(2, Box::new(offset_writer));
+        cpu.add_output_device(4, Box::new(shift_writer));
+        cpu.add_output_device(6, Box::new(DummyOutputDevice {}));
+        let sound_sinks = SoundSinks::default();
+        if options.has_audio {
+            let sounds = load_sound_bank(options.folder, options.synthetic_audio);
+            cpu.add_output_device(3, Box::new(SoundPort1::new(sounds.clone(), &sound_sinks)?));
+            cpu.add_output_device(5, Box::new(SoundPort2::new(sounds, &sound_sinks)?));
+        } else {
+            cpu.add_output_device(3, Box::new(DummyOutputDevice {}));
+            cpu.add_output_device(5, Box::new(DummyOutputDevice {}));
+        }
+        Ok(Machine {
+            cpu,
+            cycles_until_interruption: CYCLES_PER_INTERRUPTION,
+            frame_cycle: 0,
+            frame_cycles_left: 0,
+            frame_write_log: None,
+            frames: 0,
+            port1,
+            port2,
+            prev_interruption: 2,
+            rom_verification,
+            sound_sinks,
+            watchers: Vec::new(),
+        })
+    }
+
+    // Only the sounds stop, the cycles don't move until frames run again
+    pub fn pause_audio(&self) {
+        self.sound_sinks.pause();
+    }
+
+    pub fn resume_audio(&self) {
+        self.sound_sinks.resume();
+    }
+
+    // What the game would read from input ports 1 and 2 right now
+    pub fn input_ports(&self) -> (u8, u8) {
+        (self.port1.value(), self.port2.value())
+    }
+
+    pub fn rom_verification(&self) -> &RomVerification {
+        &self.rom_verification
+    }
+
+    pub fn frame_buffer(&self) -> &[u8] {
+        let start = FRAME_BUFFER_ADDRESS - RAM_ADDRESS;
+        &self.cpu.memory.ram()[start..start + FRAME_BUFFER_SIZE]
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        self.cpu.memory.ram()
+    }
+
+    pub fn player_one_score(&self) -> u32 {
+        let low = self.cpu.read_memory(PLAYER_ONE_SCORE_ADDRESS);
+        let high = self.cpu.read_memory(PLAYER_ONE_SCORE_ADDRESS + 1);
+        bcd_to_u32(high) * 100 + bcd_to_u32(low)
+    }
+
+    pub fn high_score(&self) -> u32 {
+        let low = self.cpu.read_memory(HIGH_SCORE_ADDRESS);
+        let high = self.cpu.read_memory(HIGH_SCORE_ADDRESS + 1);
+        bcd_to_u32(high) * 100 + bcd_to_u32(low)
+    }
+
+    // The file keeps the two bytes as they are in RAM. Anything that doesn't look like them is
+    // ignored, so a bad file only costs the high score
+    pub fn load_high_score(&mut self, path: &Path) {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(_) => return,
+        };
+        if bytes.len() != 2 || !bytes.iter().all(|byte| is_bcd(*byte)) {
+            return;
+        }
+        self.cpu.write_memory(HIGH_SCORE_ADDRESS, bytes[0]);
+        self.cpu.write_memory(HIGH_SCORE_ADDRESS + 1, bytes[1]);
+    }
+
+    pub fn save_high_score(&self, path: &Path) -> Result<(), Error> {
+        let bytes = [
+            self.cpu.read_memory(HIGH_SCORE_ADDRESS),
+            self.cpu.read_memory(HIGH_SCORE_ADDRESS + 1),
+        ];
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    // Runs the cycles of a whole frame, ending right after its vblank interruption
+    pub fn run_frame(&mut self) -> Result<i64, Error> {
+        let mut cycles = 0;
+        self.begin_frame();
+        while !self.is_frame_done() {
+            cycles += i64::from(self.step()?.0);
+        }
+        Ok(cycles)
+    }
+
+    // Runs at least the cycles, however long they take on the host. For running as fast as
+    // possible, in steps between which other threads can look at the machine
+    pub fn run_cycles(&mut self, budget: u64) -> Result<RunOutcome, Error> {
+        let frames = self.frames;
+        let mut instructions = 0;
+        let mut cycles = 0;
+        let mut halted = false;
+        while cycles < budget && !halted {
+            let spent = self.step()?.0;
+            if spent > 0 {
+                instructions += 1;
+            }
+            cycles += u64::from(spent);
+            halted = spent == 0 || self.cpu.is_done();
+        }
+        Ok(RunOutcome {
+            instructions,
+            cycles,
+            frames: self.frames - frames,
+            halted,
+        })
+    }
+
+    // Frames of video time since power on, whether the game took their interruptions or not
+    pub fn frames_elapsed(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn pending_interrupt(&self) -> PendingInterrupt {
+        PendingInterrupt {
+            interruption: if self.prev_interruption == 1 { 2 } else { 1 },
+            cycles: self.cycles_until_interruption,
+        }
+    }
+
+    // What a frame overran is taken from the next one, so frames stay in step with the interruptions
+    pub(crate) fn begin_frame(&mut self) {
+        self.frame_cycles_left += CYCLES_PER_FRAME;
+    }
+
+    pub(crate) fn is_frame_done(&self) -> bool {
+        self.frame_cycles_left <= 0 || self.cpu.is_done()
+    }
+
+    // Calls back with every write into the range, after the instruction that made it finishes
+    pub fn watch_range(&mut self, range: Range<u16>, callback: Box<dyn FnMut(u16, u8) + Send>) {
+        self.cpu.log_writes(true);
+        self.watchers.push((range, callback));
+    }
+
+    // Keeps up to limit VRAM writes per frame, each with the cycle it finished in
+    pub fn record_frame_writes(&mut self, limit: usize) {
+        self.cpu.log_writes(true);
+        self.frame_write_log = Some(FrameWriteLog {
+            limit,
+            current: Vec::with_capacity(limit),
+            finished: Vec::new(),
+        });
+    }
+
+    // The VRAM writes of the last frame that reached its vblank
+    pub fn take_frame_write_log(&mut self) -> Vec<FrameWrite> {
+        match self.frame_write_log {
+            Some(ref mut log) => std::mem::take(&mut log.finished),
+            None => Vec::new(),
+        }
+    }
+
+    fn notify_watchers(&mut self) {
+        if self.watchers.is_empty() && self.frame_write_log.is_none() {
+            return;
+        }
+        let watchers = &mut self.watchers;
+        let frame_write_log = &mut self.frame_write_log;
+        let frame_cycle = self.frame_cycle;
+        let vram =
+            (FRAME_BUFFER_ADDRESS as u16)..((FRAME_BUFFER_ADDRESS + FRAME_BUFFER_SIZE) as u16);
+        self.cpu.drain_writes(|address, value| {
+            for (range, callback) in watchers.iter_mut() {
+                if range.contains(&address) {
+                    callback(address, value);
+                }
+            }
+            if let Some(ref mut log) = frame_write_log {
+                if vram.contains(&address) {
+                    log.record((frame_cycle, address, value));
+                }
+            }
+        });
+    }
+
+    // Returns the cycles taken and the interruption fired after them, if it was time for one
+    pub fn step(&mut self) -> Result<(u8, Option<u8>), Error> {
+        let cycles = self.cpu.execute()?;
+        self.frame_cycle += i64::from(cycles);
+        self.notify_watchers();
+        if self.frame_cycle >= CYCLES_PER_FRAME {
+            self.frame_cycle -= CYCLES_PER_FRAME;
+            self.frames += 1;
+            if let Some(ref mut log) = self.frame_write_log {
+                log.finish_frame();
+            }
+        }
+        self.frame_cycles_left -= i64::from(cycles);
+        self.cycles_until_interruption -= i64::from(cycles);
+        if self.cycles_until_interruption > 0 {
+            return Ok((cycles, None));
+        }
+        self.cycles_until_interruption += CYCLES_PER_INTERRUPTION;
+        let interruption = self.interrupt()?;
+        Ok((cycles, interruption))
+    }
+
+    pub fn interrupt(&mut self) -> Result<Option<u8>, Error> {
+        let interruption = if self.prev_interruption == 1 { 2 } else { 1 };
+        if !self.cpu.interrupt(interruption)? {
+            return Ok(None);
+        }
+        self.prev_interruption = interruption;
+        self.notify_watchers();
+        Ok(Some(self.prev_interruption))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::console::ConsoleOptions;
+    use super::super::io_devices::Buttons;
+    use super::super::io_devices::KeypadController;
+    use super::super::io_devices::Lives;
+    use super::intel8080cpu::ROM_MEMORY_LIMIT;
+    use super::{Machine, PendingInterrupt, RamInit, CYCLES_PER_FRAME, CYCLES_PER_INTERRUPTION};
+    use std::collections::hash_map::DefaultHasher;
+    use std::fs;
+    use std::hash::{Hash, Hasher};
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    // Adds every RAM byte into VRAM forever, so VRAM depends on the initial RAM
+    fn create_rom() -> [u8; ROM_MEMORY_LIMIT] {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[0x00..0x03].copy_from_slice(&[0xc3, 0x40, 0x00]);
+        rom[0x08..0x0a].copy_from_slice(&[0xfb, 0xc9]);
+        rom[0x10..0x12].copy_from_slice(&[0xfb, 0xc9]);
+        rom[0x40..0x5c].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP, 2400H
+            0xfb, // EI
+            0x21, 0x00, 0x20, // LXI H, 2000H
+            0x11, 0x00, 0x24, // LXI D, 2400H
+            0x1a, // LDAX D
+            0x86, // ADD M
+            0x12, // STAX D
+            0x23, // INX H
+            0x13, // INX D
+            0x7a, // MOV A, D
+            0xfe, 0x40, // CPI 40H
+            0xc2, 0x4a, 0x00, // JNZ 004AH
+            0xc3, 0x44, 0x00, // JMP 0044H
+            0x00, 0x00, 0x00, 0x00,
+        ]);
+        rom
+    }
+
+    fn frame_hashes(ram_init: RamInit, frames: usize) -> Vec<u64> {
+        let keypad_controller = KeypadController::new();
+        let options = ConsoleOptions::new(create_rom(), "")
+            .with_audio(false)
+            .with_initial_ram(ram_init);
+        let mut machine = Machine::new(&keypad_controller, &options).unwrap();
+        let mut hashes = Vec::with_capacity(frames);
+        while hashes.len() < frames {
+            if let (_, Some(2)) = machine.step().unwrap() {
+                let mut hasher = DefaultHasher::new();
+                machine.frame_buffer().hash(&mut hasher);
+                hashes.push(hasher.finish());
+            }
+        }
+        hashes
+    }
+
+    #[test]
+    fn it_should_refuse_unknown_roms_only_when_asked_to() {
+        let keypad_controller = KeypadController::new();
+        let options = ConsoleOptions::new(create_rom(), "").with_audio(false);
+        let machine = Machine::new(&keypad_controller, &options).unwrap();
+        assert!(!machine.rom_verification().is_known());
+        let options = options.with_required_known_rom(true);
+        match Machine::new(&keypad_controller, &options) {
+            Err(error) => assert!(error.to_string().contains("invaders.h")),
+            Ok(_) => panic!("The rom shouldn't be accepted"),
+        }
+    }
+
+    #[test]
+    fn it_should_produce_identical_frames_with_the_same_seed() {
+        assert_eq!(
+            frame_hashes(RamInit::Seeded(42), 10),
+            frame_hashes(RamInit::Seeded(42), 10)
+        );
+    }
+
+    #[test]
+    fn it_should_produce_different_frames_with_different_seeds() {
+        assert_ne!(
+            frame_hashes(RamInit::Seeded(42), 10),
+            frame_hashes(RamInit::Seeded(24), 10)
+        );
+    }
+
+    // Counts the mid-screen interruptions at 2000H and the vblank ones at 2001H
+    fn create_counting_rom() -> [u8; ROM_MEMORY_LIMIT] {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[0x00..0x03].copy_from_slice(&[0xc3, 0x40, 0x00]);
+        rom[0x08..0x0b].copy_from_slice(&[0xc3, 0x20, 0x00]);
+        rom[0x10..0x13].copy_from_slice(&[0xc3, 0x30, 0x00]);
+        for (handler, counter) in [(0x20, 0x00), (0x30, 0x01)].iter() {
+            rom[*handler..(*handler + 11)].copy_from_slice(&[
+                0xf5, // PUSH PSW
+                0x3a, *counter, 0x20, // LDA counter
+                0x3c, // INR A
+                0x32, *counter, 0x20, // STA counter
+                0xf1, // POP PSW
+                0xfb, // EI
+                0xc9, // RET
+            ]);
+        }
+        rom[0x40..0x48].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP, 2400H
+            0xfb, // EI
+            0x00, // NOP
+            0xc3, 0x44, 0x00, // JMP 0044H
+        ]);
+        rom
+    }
+
+    #[test]
+    fn it_should_run_the_cycles_of_a_frame_between_vblanks() {
+        let keypad_controller = KeypadController::new();
+        let options = ConsoleOptions::new(create_counting_rom(), "").with_audio(false);
+        let mut machine = Machine::new(&keypad_controller, &options).unwrap();
+        let mut total = 0;
+        for frame in 1..=120 {
+            let cycles = machine.run_frame().unwrap();
+            // No instruction, interruption included, takes more than 17 cycles
+            assert!(((CYCLES_PER_FRAME - 17)..(CYCLES_PER_FRAME + 18)).contains(&cycles));
+            total += cycles;
+            assert_eq!(machine.ram()[0x00], frame);
+            // The vblank interruption ends the frame, its handler runs in the next one
+            assert_eq!(machine.ram()[0x01], frame - 1);
+        }
+        // Overruns are paid back in the next frame, so frames don't drift
+        let overrun = total - 120 * CYCLES_PER_FRAME;
+        assert!((0..18).contains(&overrun), "{} cycles of overrun", overrun);
+    }
+
+    fn create_machine<'a>(rom: [u8; ROM_MEMORY_LIMIT]) -> Machine<'a> {
+        let keypad_controller = KeypadController::new();
+        let options = ConsoleOptions::new(rom, "").with_audio(false);
+        Machine::new(&keypad_controller, &options).unwrap()
+    }
+
+    #[test]
+    fn it_should_count_frames_while_running_cycles() {
+        let mut machine = create_machine(create_counting_rom());
+        assert_eq!(
+            machine.pending_interrupt(),
+            PendingInterrupt {
+                interruption: 1,
+                cycles: CYCLES_PER_INTERRUPTION
+            }
+        );
+        let budget = 10 * CYCLES_PER_FRAME as u64;
+        let outcome = machine.run_cycles(budget).unwrap();
+        assert!((budget..(budget + 18)).contains(&outcome.cycles));
+        assert_eq!((outcome.frames, outcome.halted), (10, false));
+        assert_eq!(machine.frames_elapsed(), 10);
+        // The budget ran out with the vblank, so the mid-screen one comes next
+        let pending = machine.pending_interrupt();
+        assert_eq!(pending.interruption, 1);
+        assert!(
+            ((CYCLES_PER_INTERRUPTION - 18)..=CYCLES_PER_INTERRUPTION).contains(&pending.cycles)
+        );
+        let outcome = machine.run_cycles(CYCLES_PER_INTERRUPTION as u64).unwrap();
+        assert_eq!(outcome.frames, 0);
+        assert_eq!(machine.pending_interrupt().interruption, 2);
+    }
+
+    #[test]
+    fn it_should_stop_running_cycles_when_the_cpu_halts() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..2].copy_from_slice(&[
+            0xf3, // DI, 4 cycles
+            0x76, // HLT, 7 cycles
+        ]);
+        let mut machine = create_machine(rom);
+        let outcome = machine.run_cycles(1_000_000).unwrap();
+        assert_eq!(
+            (outcome.cycles, outcome.frames, outcome.halted),
+            (11, 0, true)
+        );
+        assert_eq!(outcome.instructions, 2);
+    }
+
+    fn assert_send<T: Send>() {}
+
+    #[test]
+    fn it_should_run_on_another_thread_than_its_controller() {
+        assert_send::<Machine<'static>>();
+        let expected = frame_hashes(RamInit::Seeded(7), 30);
+        let mut keypad_controller = KeypadController::new();
+        let options = ConsoleOptions::new(create_rom(), "")
+            .with_audio(false)
+            .with_initial_ram(RamInit::Seeded(7));
+        let mut machine = Machine::new(&keypad_controller, &options).unwrap();
+        let (sender, receiver) = channel();
+        let runner = thread::spawn(move || {
+            let mut frames = 0;
+            while frames < 30 {
+                if let (_, Some(2)) = machine.step().unwrap() {
+                    let mut hasher = DefaultHasher::new();
+                    machine.frame_buffer().hash(&mut hasher);
+                    sender.send(hasher.finish()).unwrap();
+                    frames += 1;
+                }
+            }
+            machine.frames_elapsed()
+        });
+        // The rom never reads the buttons, so pressing them can't change the frames
+        let mut hashes = Vec::new();
+        while hashes.len() < 30 {
+            keypad_controller.press(Buttons::FIRE);
+            keypad_controller.release(Buttons::FIRE);
+            hashes.extend(receiver.try_iter());
+        }
+        assert_eq!(runner.join().unwrap(), 30);
+        assert_eq!(hashes, expected);
+        assert_eq!(keypad_controller.buttons(), Buttons::UP);
+    }
+
+    #[test]
+    fn it_should_read_the_dip_switches_and_player_two_on_port_2() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..0x06].copy_from_slice(&[
+            0xf3, // DI
+            0xdb, 0x02, // IN 02H
+            0x32, 0x00, 0x20, // STA 2000H
+        ]);
+        let mut keypad_controller = KeypadController::new();
+        let options = ConsoleOptions::new(rom, "")
+            .with_audio(false)
+            .with_lives(Lives::Six)
+            .with_bonus_at_1000(true)
+            .with_coin_info(false);
+        let mut machine = Machine::new(&keypad_controller, &options).unwrap();
+        keypad_controller.press_player_two(Buttons::LEFT);
+        for _ in 0..3 {
+            machine.step().unwrap();
+        }
+        assert_eq!(machine.ram()[0], 0xab);
+        assert_eq!(machine.input_ports(), (0x08, 0xab));
+    }
+
+    #[test]
+    fn it_should_log_the_frame_cycle_of_every_vram_write() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..0x16].copy_from_slice(&[
+            0xf3, // DI, 4 cycles
+            0x31, 0x00, 0x24, // LXI SP, 2400H, 10 cycles
+            0x3e, 0x11, // MVI A, 11H, 7 cycles
+            0x32, 0x00, 0x20, // STA 2000H, 13 cycles
+            0x32, 0x00, 0x24, // STA 2400H, 13 cycles
+            0x21, 0x01, 0x24, // LXI H, 2401H, 10 cycles
+            0x36, 0x22, // MVI M, 22H, 10 cycles
+            0x00, // NOP, 4 cycles
+            0x77, // MOV M, A, 7 cycles
+            0xc3, 0x13, 0x00, // JMP 0013H
+        ]);
+        let mut machine = create_machine(rom);
+        machine.record_frame_writes(16);
+        machine.run_frame().unwrap();
+        assert_eq!(
+            machine.take_frame_write_log(),
+            vec![(47, 0x2400, 0x11), (67, 0x2401, 0x22), (78, 0x2401, 0x11)]
+        );
+        assert!(machine.take_frame_write_log().is_empty());
+        machine.run_frame().unwrap();
+        assert!(machine.take_frame_write_log().is_empty());
+    }
+
+    #[test]
+    fn it_should_cap_and_restart_the_frame_write_log() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..0x0a].copy_from_slice(&[
+            0xf3, // DI
+            0x21, 0x00, 0x24, // LXI H, 2400H
+            0x77, // MOV M, A, 7 cycles
+            0xc3, 0x04, 0x00, // JMP 0004H, 10 cycles
+            0x00, 0x00,
+        ]);
+        let mut machine = create_machine(rom);
+        machine.record_frame_writes(100);
+        for _ in 0..3 {
+            machine.run_frame().unwrap();
+            let log = machine.take_frame_write_log();
+            assert_eq!(log.len(), 100);
+            // Stamps count from the vblank, not from the start of the machine
+            assert!(log[0].0 <= 17 + 17);
+            assert!(log.windows(2).all(|w| w[1].0 - w[0].0 == 17));
+        }
+    }
+
+    #[test]
+    fn it_should_fill_ram_with_a_pattern() {
+        let mut ram = [0; 16];
+        RamInit::Pattern(0xaa).fill(&mut ram);
+        assert!(ram.iter().all(|b| *b == 0xaa));
+        RamInit::Ones.fill(&mut ram);
+        assert!(ram.iter().all(|b| *b == 0xff));
+    }
+
+    #[test]
+    fn it_should_notify_vram_writes_in_order() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[..17].copy_from_slice(&[
+            0x31, 0x00, 0x24, // LXI SP, 2400H
+            0x3e, 0x11, // MVI A, 11H
+            0x32, 0x10, 0x24, // STA 2410H
+            0xc5, // PUSH B
+            0x21, 0xff, 0x3f, // LXI H, 3FFFH
+            0x36, 0x22, // MVI M, 22H
+            0x32, 0x00, 0x24, // STA 2400H
+        ]);
+        let keypad_controller = KeypadController::new();
+        let options = ConsoleOptions::new(rom, "").with_audio(false);
+        let mut machine = Machine::new(&keypad_controller, &options).unwrap();
+        let (sender, receiver) = channel();
+        machine.watch_range(
+            0x2400..0x4000,
+            Box::new(move |address, value| sender.send((address, value)).unwrap()),
+        );
+        for _ in 0..7 {
+            machine.step().unwrap();
+        }
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![(0x2410, 0x11), (0x3fff, 0x22), (0x2400, 0x11)]
+        );
+    }
+
+    #[test]
+    fn it_should_keep_the_high_score_in_a_file() {
+        let path = std::env::temp_dir().join(format!("si-high-score-{}", std::process::id()));
+        let mut machine = create_machine([0; ROM_MEMORY_LIMIT]);
+        machine.cpu.write_memory(0x20f4, 0x50);
+        machine.cpu.write_memory(0x20f5, 0x12);
+        assert_eq!(machine.high_score(), 1250);
+        machine.save_high_score(&path).unwrap();
+        let mut machine = create_machine([0; ROM_MEMORY_LIMIT]);
+        machine.load_high_score(&path);
+        assert_eq!(machine.high_score(), 1250);
+        fs::write(&path, [0x5a, 0x12]).unwrap();
+        let mut machine = create_machine([0; ROM_MEMORY_LIMIT]);
+        machine.load_high_score(&path);
+        assert_eq!(machine.high_score(), 0);
+        fs::write(&path, [0x50, 0x12, 0x00]).unwrap();
+        machine.load_high_score(&path);
+        assert_eq!(machine.high_score(), 0);
+        fs::remove_file(&path).unwrap();
+        machine.load_high_score(&path);
+        assert_eq!(machine.high_score(), 0);
+    }
+}
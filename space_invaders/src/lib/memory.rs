@@ -0,0 +1,83 @@
+extern crate intel8080cpu;
+
+use self::intel8080cpu::{Memory, ROM_MEMORY_LIMIT};
+
+pub(crate) const RAM_ADDRESS: usize = 0x2000;
+const RAM_SIZE: usize = 0x2000;
+// The board only decodes 14 address lines, the ROM and the RAM repeat every 16KB
+const ADDRESS_MASK: u16 = 0x3fff;
+
+// The ROM can't be written. Above the RAM there's nothing but copies of both
+pub(crate) struct ConsoleMemory {
+    rom: [u8; ROM_MEMORY_LIMIT],
+    ram: [u8; RAM_SIZE],
+}
+
+impl ConsoleMemory {
+    pub(crate) fn new(rom: [u8; ROM_MEMORY_LIMIT]) -> ConsoleMemory {
+        ConsoleMemory {
+            rom,
+            ram: [0; RAM_SIZE],
+        }
+    }
+
+    pub(crate) fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub(crate) fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl Memory for ConsoleMemory {
+    fn set(&mut self, index: u16, new_value: u8) {
+        let index = (index & ADDRESS_MASK) as usize;
+        if index >= RAM_ADDRESS {
+            self.ram[index - RAM_ADDRESS] = new_value;
+        }
+    }
+
+    fn get(&self, index: u16) -> u8 {
+        let index = (index & ADDRESS_MASK) as usize;
+        if index < RAM_ADDRESS {
+            self.rom[index]
+        } else {
+            self.ram[index - RAM_ADDRESS]
+        }
+    }
+
+    fn len(&self) -> usize {
+        ROM_MEMORY_LIMIT + RAM_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::intel8080cpu::{Memory, ROM_MEMORY_LIMIT};
+    use super::ConsoleMemory;
+
+    #[test]
+    fn it_should_mirror_the_ram_above_it() {
+        let mut memory = ConsoleMemory::new([0; ROM_MEMORY_LIMIT]);
+        memory.set(0x6400, 0x42);
+        assert_eq!(memory.get(0x2400), 0x42);
+        assert_eq!(memory.get(0xe400), 0x42);
+        assert_eq!(memory.ram()[0x400], 0x42);
+        memory.set_word(0x3fff, 0x1234);
+        assert_eq!(memory.get(0x3fff), 0x34);
+        // The high byte wraps to the ROM, which ignores it
+        assert_eq!(memory.get(0x0000), 0);
+    }
+
+    #[test]
+    fn it_should_ignore_writes_to_the_rom() {
+        let mut rom = [0; ROM_MEMORY_LIMIT];
+        rom[0x100] = 0xc3;
+        let mut memory = ConsoleMemory::new(rom);
+        memory.set(0x100, 0x00);
+        memory.set(0x4100, 0x00);
+        assert_eq!(memory.get(0x100), 0xc3);
+        assert_eq!(memory.get(0x4100), 0xc3);
+    }
+}
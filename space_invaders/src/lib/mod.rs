@@ -9,10 +9,26 @@ pub enum ConsoleError {
     CantCreateWindow { msg: String },
     #[fail(display = "couldn't create sound: {}", msg)]
     CantCreateSound { msg: String },
+    #[fail(display = "the rom isn't a known Space Invaders set: {}", verification)]
+    UnknownRom {
+        verification: rom_check::RomVerification,
+    },
+    #[fail(display = "the cpu halted after {} cycles", cycles)]
+    CpuHalted { cycles: u64 },
 }
 
+mod bench;
 pub mod console;
 mod io_devices;
+pub mod machine;
+mod memory;
+mod rom_check;
 mod screen;
 mod timer;
 pub mod view;
+
+pub use bench::{run_benchmark, BenchReport};
+pub use console::{ConsoleOptions, ROM_MEMORY_LIMIT};
+pub use io_devices::{Buttons, KeyBindings, KeyBindingsError, KeypadController, Lives};
+pub use machine::{FrameWrite, Machine, PendingInterrupt, RamInit, RunOutcome};
+pub use rom_check::{verify_rom, KnownRomSet, RomVerification, KNOWN_ROM_SETS};
@@ -0,0 +1,163 @@
+use std::fmt;
+
+// The ROM is four 2KB chips, each dumped to its own file
+const PIECE_SIZE: usize = 0x800;
+
+pub struct KnownRomSet {
+    pub name: &'static str,
+    // File name and CRC32 of each chip, in the order they're mapped from 0x0000
+    pub pieces: [(&'static str, u32); 4],
+}
+
+// Checksums from the MAME rom sets
+pub const KNOWN_ROM_SETS: &[KnownRomSet] = &[KnownRomSet {
+    name: "Space Invaders (Midway)",
+    pieces: [
+        ("invaders.h", 0x734f_5ad8),
+        ("invaders.g", 0x6bfa_ca4a),
+        ("invaders.f", 0x0cce_ad96),
+        ("invaders.e", 0x14e5_38b0),
+    ],
+}];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RomVerification {
+    Known {
+        set: &'static str,
+    },
+    Unknown {
+        closest: &'static str,
+        differing: Vec<&'static str>,
+    },
+}
+
+impl RomVerification {
+    pub fn is_known(&self) -> bool {
+        matches!(self, RomVerification::Known { .. })
+    }
+}
+
+impl fmt::Display for RomVerification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomVerification::Known { set } => write!(f, "{}", set),
+            RomVerification::Unknown { closest, differing } => write!(
+                f,
+                "unknown, the closest set is {}, with a different {}",
+                closest,
+                differing.join(", ")
+            ),
+        }
+    }
+}
+
+// CRC-32 as used by zip and MAME, bit by bit since it only runs once per load
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 > 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+pub fn verify_rom(rom: &[u8]) -> RomVerification {
+    verify_rom_against(rom, KNOWN_ROM_SETS)
+}
+
+pub(crate) fn verify_rom_against(rom: &[u8], sets: &[KnownRomSet]) -> RomVerification {
+    let checksums: Vec<u32> = rom.chunks(PIECE_SIZE).map(crc32).collect();
+    let mut closest: Option<(&KnownRomSet, Vec<&'static str>)> = None;
+    for set in sets {
+        let differing: Vec<&'static str> = set
+            .pieces
+            .iter()
+            .enumerate()
+            .filter(|(i, (_, crc))| checksums.get(*i) != Some(crc))
+            .map(|(_, (name, _))| *name)
+            .collect();
+        if differing.is_empty() {
+            return RomVerification::Known { set: set.name };
+        }
+        if closest
+            .as_ref()
+            .is_none_or(|(_, d)| differing.len() < d.len())
+        {
+            closest = Some((set, differing));
+        }
+    }
+    match closest {
+        Some((set, differing)) => RomVerification::Unknown {
+            closest: set.name,
+            differing,
+        },
+        None => RomVerification::Unknown {
+            closest: "none",
+            differing: vec![],
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, verify_rom_against, KnownRomSet, RomVerification, PIECE_SIZE};
+
+    fn create_rom() -> Vec<u8> {
+        (0..PIECE_SIZE * 4).map(|i| (i / 7) as u8).collect()
+    }
+
+    fn create_set(name: &'static str, rom: &[u8]) -> KnownRomSet {
+        let crc = |i: usize| crc32(&rom[i * PIECE_SIZE..(i + 1) * PIECE_SIZE]);
+        KnownRomSet {
+            name,
+            pieces: [("h", crc(0)), ("g", crc(1)), ("f", crc(2)), ("e", crc(3))],
+        }
+    }
+
+    #[test]
+    fn it_should_compute_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn it_should_verify_a_known_rom() {
+        let rom = create_rom();
+        let mut other = rom.clone();
+        other[0] ^= 0xff;
+        let sets = [create_set("other", &other), create_set("classic", &rom)];
+        let verification = verify_rom_against(&rom, &sets);
+        assert_eq!(verification, RomVerification::Known { set: "classic" });
+        assert!(verification.is_known());
+    }
+
+    #[test]
+    fn it_should_report_the_closest_set_and_the_differing_pieces() {
+        let rom = create_rom();
+        let mut far = rom.clone();
+        far[0] ^= 0xff;
+        far[PIECE_SIZE] ^= 0xff;
+        let mut close = rom.clone();
+        close[PIECE_SIZE * 3] ^= 0xff;
+        let sets = [create_set("far", &far), create_set("close", &close)];
+        let verification = verify_rom_against(&rom, &sets);
+        assert_eq!(
+            verification,
+            RomVerification::Unknown {
+                closest: "close",
+                differing: vec!["e"],
+            }
+        );
+        assert!(!verification.is_known());
+        assert_eq!(
+            verification.to_string(),
+            "unknown, the closest set is close, with a different e"
+        );
+    }
+}
@@ -1,61 +1,143 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-pub struct Timer {
-    last_trigger: usize,
-    last_check: usize,
-    interval: f64,
+pub trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        sleep(duration);
+    }
+}
+
+pub struct Timer<C: Clock = SystemClock> {
+    clock: C,
+    interval: Duration,
+    next_trigger: Instant,
 }
 
 impl Timer {
-    pub(crate) fn new(interval: f64) -> Timer {
-        let ms = Timer::get_millis();
+    pub(crate) fn new(interval: Duration) -> Timer {
+        Timer::with_clock(interval, SystemClock)
+    }
+}
+
+impl<C: Clock> Timer<C> {
+    pub(crate) fn with_clock(interval: Duration, clock: C) -> Timer<C> {
         Timer {
-            last_check: ms,
-            last_trigger: ms,
+            next_trigger: clock.now() + interval,
+            clock,
             interval,
         }
     }
 
+    // Also how to come back from a pause, the next trigger is an interval away from now
     pub(crate) fn reset(&mut self) {
-        let ms = Timer::get_millis();
-        self.last_check = ms;
-        self.last_trigger = ms;
+        self.next_trigger = self.clock.now() + self.interval;
+    }
+
+    // Sleeps until the next trigger. Falling more than an interval behind starts over instead of
+    // rushing through the missed triggers.
+    pub(crate) fn wait_for_trigger(&mut self) {
+        let now = self.clock.now();
+        if self.next_trigger > now {
+            self.clock.sleep(self.next_trigger - now);
+        } else if now - self.next_trigger > self.interval {
+            self.next_trigger = now;
+        }
+        self.next_trigger += self.interval;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, Timer};
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
+
+    // Time only moves when someone sleeps or the test says so
+    #[derive(Clone)]
+    struct MockClock {
+        now: Rc<Cell<Instant>>,
+        sleeps: Rc<RefCell<Vec<Duration>>>,
     }
 
-    pub fn update_last_check(&mut self) -> usize {
-        let new_time = Timer::get_millis();
-        let elapsed = new_time - self.last_check;
-        self.last_check = new_time;
-        elapsed
+    impl MockClock {
+        fn new() -> MockClock {
+            MockClock {
+                now: Rc::new(Cell::new(Instant::now())),
+                sleeps: Rc::new(RefCell::new(Vec::new())),
+            }
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.now.set(self.now.get() + duration);
+        }
     }
 
-    pub(crate) fn should_trigger(&mut self) -> bool {
-        let ms = Timer::get_millis();
-        let should = (ms as f64 - self.last_trigger as f64) > self.interval;
-        if should {
-            self.last_trigger = ms;
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.sleeps.borrow_mut().push(duration);
+            self.advance(duration);
         }
-        should
     }
 
-    pub fn reset_preserving_intervals_with_offset(&mut self, offset: usize) {
-        let new_time = Timer::get_millis();
-        self.last_trigger = new_time - (self.last_check - self.last_trigger) + offset;
-        self.last_check = new_time;
+    #[test]
+    fn it_should_sleep_until_every_trigger() {
+        let frame = Duration::from_millis(5);
+        let clock = MockClock::new();
+        let mut timer = Timer::with_clock(frame, clock.clone());
+        clock.advance(Duration::from_millis(2));
+        for _ in 0..4 {
+            timer.wait_for_trigger();
+        }
+        assert_eq!(
+            *clock.sleeps.borrow(),
+            vec![Duration::from_millis(3), frame, frame, frame]
+        );
     }
 
-    pub fn reset_preserving_intervals(&mut self) {
-        let new_time = Timer::get_millis();
-        self.last_trigger = new_time - (self.last_check - self.last_trigger);
-        self.last_check = new_time;
+    #[test]
+    fn it_should_start_over_when_falling_behind() {
+        let frame = Duration::from_millis(5);
+        let clock = MockClock::new();
+        let mut timer = Timer::with_clock(frame, clock.clone());
+        clock.advance(Duration::from_millis(30));
+        timer.wait_for_trigger();
+        timer.wait_for_trigger();
+        // Only a frame from where it fell behind, not the five frames it missed
+        assert_eq!(*clock.sleeps.borrow(), vec![frame]);
     }
 
-    fn get_millis() -> usize {
-        let start = SystemTime::now();
-        let since_the_epoch = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        (since_the_epoch.as_secs() * 1000) as usize
-            + since_the_epoch.subsec_nanos() as usize / 1_000_000
+    #[test]
+    fn it_should_resume_a_frame_away_after_a_pause() {
+        let frame = Duration::from_millis(16);
+        let clock = MockClock::new();
+        let mut timer = Timer::with_clock(frame, clock.clone());
+        clock.advance(Duration::from_millis(6));
+        timer.wait_for_trigger();
+        // Paused for ten seconds, six hundred frames that shouldn't be caught up with
+        clock.advance(Duration::from_secs(10));
+        timer.reset();
+        clock.advance(Duration::from_millis(4));
+        timer.wait_for_trigger();
+        timer.wait_for_trigger();
+        assert_eq!(
+            *clock.sleeps.borrow(),
+            vec![Duration::from_millis(10), Duration::from_millis(12), frame]
+        );
     }
 }
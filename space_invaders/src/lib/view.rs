@@ -18,6 +18,8 @@ pub(crate) const WINDOW_HEIGHT: u32 = SCREEN_HEIGHT as u32;
 pub(crate) const WINDOW_WIDTH: u32 = SCREEN_WIDTH as u32;
 const BUTTON_HEIGHT: usize = 50usize;
 const BUTTON_WIDTH: usize = 50usize;
+const PAUSED_TEXT: &str = "PAUSED";
+const PAUSED_FONT_SIZE: u32 = 32;
 const NEXT_BUTTON: [[bool; BUTTON_WIDTH]; BUTTON_HEIGHT] = [
     [true; 50],
     [
@@ -603,9 +605,9 @@ const PAUSE_BUTTON: [[bool; BUTTON_WIDTH]; BUTTON_HEIGHT] = [
     [true; 50],
 ];
 
-fn update_image(pixels: &[&[bool]], image: &mut RgbaImage, texture: &mut Texture) {
+fn update_image<R: AsRef<[bool]>>(pixels: &[R], image: &mut RgbaImage, texture: &mut Texture) {
     for (line, row) in pixels.iter().enumerate() {
-        for (column, drawn_pixel) in row.iter().enumerate() {
+        for (column, drawn_pixel) in row.as_ref().iter().enumerate() {
             let pixel = if *drawn_pixel {
                 [255; 4]
             } else {
@@ -623,6 +625,7 @@ pub struct View {
     left_menu_visible: bool,
     next_texture: G2dTexture,
     next_position: [f64; 2],
+    paused: bool,
     pause_texture: G2dTexture,
     pause_position: [f64; 2],
     texture: Texture,
@@ -639,16 +642,8 @@ impl View {
         let mut pause_texture =
             Texture::from_image(&pause_image.convert(), &TextureSettings::new());
         let left_menu_visible = debug;
-        let p = &PAUSE_BUTTON
-            .iter()
-            .map(|a| a.as_ref())
-            .collect::<Vec<&[bool]>>();
-        let n = &NEXT_BUTTON
-            .iter()
-            .map(|a| a.as_ref())
-            .collect::<Vec<&[bool]>>();
-        update_image(p.as_ref(), &mut pause_image, &mut pause_texture);
-        update_image(n.as_ref(), &mut next_image, &mut next_texture);
+        update_image(&PAUSE_BUTTON, &mut pause_image, &mut pause_texture);
+        update_image(&NEXT_BUTTON, &mut next_image, &mut next_texture);
         let pause_img =
             GfxTexture::from_image(&mut texture_context, &pause_image, &TextureSettings::new())
                 .unwrap();
@@ -661,6 +656,7 @@ impl View {
             left_menu_visible,
             next_texture: next_img,
             next_position: [0f64; 2],
+            paused: false,
             pause_texture: pause_img,
             pause_position: [0f64; 2],
             texture,
@@ -696,6 +692,22 @@ impl View {
             )
             .unwrap();
             image(&img, transform, gl);
+            if self.paused {
+                let (width, height) = (f64::from(WINDOW_WIDTH), f64::from(WINDOW_HEIGHT));
+                rectangle([0.0, 0.0, 0.0, 0.6], [0.0, 0.0, width, height], transform, gl);
+                // Glyphs are around 0.6 of the font size wide, close enough to center it
+                let text_width = (PAUSED_TEXT.len() as u32 * PAUSED_FONT_SIZE) as f64 * 0.6;
+                let text_transform = transform.trans((width - text_width) / 2.0, height / 2.0);
+                text::Text::new_color([1.0, 1.0, 1.0, 1.0], PAUSED_FONT_SIZE)
+                    .draw(
+                        PAUSED_TEXT,
+                        &mut self.glyphs,
+                        &c.draw_state,
+                        text_transform,
+                        gl,
+                    )
+                    .unwrap();
+            }
             if self.left_menu_visible {
                 let (x, y) = (SCREEN_WIDTH as f64, 0f64);
                 let menu_transform = transform.trans(x, y);
@@ -727,15 +739,21 @@ impl View {
                         )
                         .unwrap();
                 }
-                // Update glyphs before rendering.
+            }
+            // Update glyphs before rendering.
+            if self.left_menu_visible || self.paused {
                 self.glyphs.factory.encoder.flush(device);
             }
         });
     }
 
+    // While paused, the last frame is dimmed under a paused sign
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
     pub fn update_image(&mut self, pixels: &ScreenLayout) {
-        let p = &pixels.iter().map(|a| a.as_ref()).collect::<Vec<&[bool]>>();
-        update_image(p.as_ref(), &mut self.image, &mut self.texture)
+        update_image(pixels, &mut self.image, &mut self.texture)
     }
 
     pub fn is_in_pause_button(&self, position: [f64; 2]) -> bool {
@@ -1,4 +1,5 @@
 extern crate emulator_space_invaders;
+#[macro_use]
 extern crate failure;
 extern crate find_folder;
 extern crate intel8080cpu;
@@ -6,26 +7,67 @@ extern crate piston_window;
 
 use emulator_space_invaders::console::{Console, ConsoleOptions};
 use emulator_space_invaders::view::View;
+use emulator_space_invaders::{run_benchmark, KeyBindings};
 use failure::Error;
 use intel8080cpu::*;
 use std::env::args;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::time::Duration;
 
-const USAGE: &str = "Usage: space-invaders [game|test] [file] [--no-audio]
+const USAGE: &str = "Usage: space-invaders [game|test|bench] [file] [--no-audio] [--synth-audio] [--unthrottled] [--background-run] [--require-known-rom]
 
-If running either test, [file] should be a hex file with Intel 8080 instructions.
+If running either test, [file] should be a hex file with Intel 8080 instructions. A number after it
+caps the cycles the program can run, so a misbehaving one fails instead of looping forever.
 
 When selecting the mode game, [file] should be a folder that contains the following content:
 
 ./rom # The rom of the game
-./0.wav ... 9.wav # The audio files of the game";
+./0.wav ... 8.wav # The audio files of the game, synthesized if missing or with --synth-audio
+./highscore # Where the high score is kept between runs, created on exit
+./keys # Optional key bindings, lines like p1_fire=F,Space for p1_left, p1_right, p1_fire, p2_left,
+       # p2_right, p2_fire, coin, start1 and start2
 
-struct PrintScreen;
+With --unthrottled the game runs as fast as it can instead of at 60 frames per second.
 
-impl Printer for PrintScreen {
-    fn print(&mut self, bytes: &[u8]) {
-        println!("{}", String::from_utf8_lossy(bytes));
+The game pauses while its window doesn't have the focus, unless --background-run is set.
+
+Roms that aren't a known Space Invaders set only get a warning, unless --require-known-rom is set.
+
+With bench, [file] should be a rom. It runs without window or audio as fast as it can, for five
+seconds or the ones after --seconds, and prints how fast it went.";
+const DEFAULT_BENCH_SECONDS: f64 = 5.0;
+
+#[derive(Debug, Fail)]
+enum TestError {
+    #[fail(display = "The diagnostic program reported a failure.")]
+    DiagnosticFailed,
+    #[fail(display = "The diagnostic program finished without reporting success.")]
+    NoSuccessReported,
+    #[fail(display = "The diagnostic program didn't finish in {} cycles.", cycles)]
+    CycleLimitReached { cycles: u64 },
+}
+
+#[derive(Default)]
+struct TestListener {
+    failed: bool,
+    succeeded: bool,
+}
+
+impl EmulationListener for TestListener {
+    fn on_output(&mut self, bytes: &[u8]) {
+        let output = String::from_utf8_lossy(bytes);
+        println!("{}", output);
+        if output.contains("FAILED") || output.contains("ERROR") {
+            self.failed = true;
+        } else if output.contains("OPERATIONAL") {
+            self.succeeded = true;
+        }
+    }
+
+    fn on_illegal_opcode(&mut self, pc: u16, opcode: u8) {
+        eprintln!("Illegal opcode {:#04x} at {:#06x}", opcode, pc);
     }
 }
 
@@ -38,10 +80,32 @@ fn read_file(file_name: &str) -> std::io::Result<[u8; ROM_MEMORY_LIMIT]> {
     Ok(memory)
 }
 
-fn start_game(folder: &str, has_audio: bool, debug: bool) -> Result<(), Error> {
+fn start_game(
+    folder: &str,
+    has_audio: bool,
+    synthetic_audio: bool,
+    throttled: bool,
+    background_run: bool,
+    require_known_rom: bool,
+    debug: bool,
+) -> Result<(), Error> {
     let rom_location = format!("{}/rom", folder);
+    let high_score_location = format!("{}/highscore", folder);
+    let keys_location = format!("{}/keys", folder);
     let memory = read_file(&rom_location)?;
-    let options = ConsoleOptions::new(memory, folder).with_audio(has_audio);
+    let key_bindings = if Path::new(&keys_location).exists() {
+        KeyBindings::load(Path::new(&keys_location))?
+    } else {
+        KeyBindings::default()
+    };
+    let options = ConsoleOptions::new(memory, folder)
+        .with_high_score_file(&high_score_location)
+        .with_key_bindings(key_bindings)
+        .with_audio(has_audio)
+        .with_synthetic_audio(synthetic_audio)
+        .with_throttling(throttled)
+        .with_background_run(background_run)
+        .with_required_known_rom(require_known_rom);
     let assets = find_folder::Search::ParentsThenKids(3, 3)
         .for_folder("assets")
         .unwrap();
@@ -53,29 +117,81 @@ fn start_game(folder: &str, has_audio: bool, debug: bool) -> Result<(), Error> {
     console.start().map_err(Error::from)
 }
 
-fn test(memory: [u8; ROM_MEMORY_LIMIT]) -> Result<(), Error> {
-    let screen = &mut (PrintScreen {});
-    let mut cpu = Intel8080Cpu::new_cp_m_compatible(memory, screen);
-
-    while !cpu.is_done() {
-        cpu.execute()?;
+fn test(memory: [u8; ROM_MEMORY_LIMIT], cycle_limit: Option<u64>) -> Result<(), Error> {
+    let listener = &mut TestListener::default();
+    {
+        let mut cpu = Intel8080Cpu::new_cp_m_compatible(memory, listener);
+        cpu.set_termination_condition(TerminationCondition::Any(vec![
+            TerminationCondition::PcPasses(ROM_MEMORY_LIMIT as u16),
+            TerminationCondition::OnHlt,
+            TerminationCondition::JumpToZero,
+        ]));
+        match cycle_limit {
+            Some(limit) => {
+                cpu.run_until_cycles(limit)?;
+                if !cpu.is_done() {
+                    return Err(Error::from(TestError::CycleLimitReached { cycles: limit }));
+                }
+            }
+            None => {
+                while !cpu.is_done() {
+                    cpu.execute()?;
+                }
+            }
+        }
+    }
+    if listener.failed {
+        Err(Error::from(TestError::DiagnosticFailed))
+    } else if !listener.succeeded {
+        Err(Error::from(TestError::NoSuccessReported))
+    } else {
+        Ok(())
     }
+}
+
+fn bench(memory: [u8; ROM_MEMORY_LIMIT], seconds: f64) -> Result<(), Error> {
+    let report = run_benchmark(memory, Duration::from_secs_f64(seconds))?;
+    println!("{}", report);
     Ok(())
 }
 
 fn main() {
     let args: Vec<String> = args().collect();
-    if args.len() < 3 || args.len() > 5 {
+    if args.len() < 3 || args.len() > 9 {
         panic!(USAGE);
     }
 
     if args[1] == "game" {
         let has_audio = !args.iter().find(|a| a.as_str() == "--no-audio").is_some();
+        let synthetic_audio = args.iter().any(|a| a.as_str() == "--synth-audio");
+        let throttled = !args.iter().any(|a| a.as_str() == "--unthrottled");
+        let background_run = args.iter().any(|a| a.as_str() == "--background-run");
+        let require_known_rom = args.iter().any(|a| a.as_str() == "--require-known-rom");
         let debug = args.iter().find(|a| a.as_str() == "--debug").is_some();
-        start_game(&args[2], has_audio, debug).unwrap();
+        start_game(
+            &args[2],
+            has_audio,
+            synthetic_audio,
+            throttled,
+            background_run,
+            require_known_rom,
+            debug,
+        )
+        .unwrap();
     } else if args[1] == "test" {
         let memory = read_file(&args[2]).unwrap();
-        test(memory).unwrap();
+        let cycle_limit = args.get(3).map(|limit| limit.parse::<u64>().expect(USAGE));
+        test(memory, cycle_limit).unwrap();
+    } else if args[1] == "bench" {
+        let memory = read_file(&args[2]).unwrap();
+        let seconds = match args.iter().position(|a| a.as_str() == "--seconds") {
+            Some(index) => args
+                .get(index + 1)
+                .and_then(|seconds| seconds.parse::<f64>().ok())
+                .expect(USAGE),
+            None => DEFAULT_BENCH_SECONDS,
+        };
+        bench(memory, seconds).unwrap();
     } else {
         panic!(USAGE);
     }
@@ -0,0 +1,53 @@
+extern crate emulator_space_invaders;
+
+use emulator_space_invaders::{Buttons, ConsoleOptions, KeypadController, Machine, ROM_MEMORY_LIMIT};
+
+// Counts fire presses into player one's score
+fn create_rom() -> [u8; ROM_MEMORY_LIMIT] {
+    let mut rom = [0; ROM_MEMORY_LIMIT];
+    rom[0x00..0x03].copy_from_slice(&[0xc3, 0x40, 0x00]);
+    rom[0x08..0x0a].copy_from_slice(&[0xfb, 0xc9]);
+    rom[0x10..0x12].copy_from_slice(&[0xfb, 0xc9]);
+    rom[0x40..0x5a].copy_from_slice(&[
+        0x31, 0x00, 0x24, // LXI SP, 2400H
+        0x21, 0xf8, 0x20, // LXI H, 20F8H
+        0xfb, // EI
+        0xdb, 0x01, // IN 1
+        0xe6, 0x10, // ANI 10H
+        0xca, 0x47, 0x00, // JZ 0047H
+        0x34, // INR M
+        0xdb, 0x01, // IN 1
+        0xe6, 0x10, // ANI 10H
+        0xc2, 0x4f, 0x00, // JNZ 004FH
+        0xc3, 0x47, 0x00, // JMP 0047H
+        0x00,
+    ]);
+    rom
+}
+
+#[test]
+fn it_should_play_an_input_script_headlessly() {
+    let script = [
+        (5, 10, Buttons::COIN),
+        (20, 22, Buttons::FIRE),
+        (30, 32, Buttons::FIRE | Buttons::RIGHT),
+        (40, 42, Buttons::FIRE),
+    ];
+    let options = ConsoleOptions::new(create_rom(), "")
+        .with_audio(false);
+    let mut keypad_controller = KeypadController::new();
+    let mut machine = Machine::new(&keypad_controller, &options).unwrap();
+    let mut pressed = Buttons::NONE;
+    for frame in 0..60 {
+        keypad_controller.release(pressed);
+        pressed = script
+            .iter()
+            .filter(|(from, to, _)| *from <= frame && frame < *to)
+            .fold(Buttons::NONE, |pressed, (_, _, buttons)| pressed | *buttons);
+        keypad_controller.press(pressed);
+        machine.run_frame().unwrap();
+    }
+    assert!(keypad_controller.buttons().contains(Buttons::UP));
+    assert_eq!(machine.player_one_score(), 3);
+    assert_eq!(machine.ram()[0xf8], 0x03);
+}